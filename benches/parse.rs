@@ -0,0 +1,69 @@
+// cargo bench --bench parse
+//
+// what DnsMessageRef saves over DnsMessage::from_bytes on a typical answer: a CNAME, a handful of
+// addresses and an OPT record. A server that only needs the question (to look it up in a cache,
+// say) gets away with DnsMessageRef::parse and nothing else; reading every record through it
+// still allocates nothing. No criterion here, just a timer and enough rounds to even things out
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+use implementation::{DnsMessage, DnsMessageRef, Edns, RData, ResourceRecord};
+
+const ROUNDS: u32 = 200_000;
+
+fn packet() -> Vec<u8> {
+    let query = DnsMessage::query("www.example.com").build();
+    let mut res = DnsMessage::response_to(&query);
+    res.add_answer(ResourceRecord::new(
+        "www.example.com",
+        300,
+        RData::CNAME("edge.example.net".to_string()),
+    ));
+    for last in 1..=6 {
+        res.add_answer(ResourceRecord::new(
+            "edge.example.net",
+            60,
+            RData::A([192, 0, 2, last].into()),
+        ));
+    }
+    res.edns = Some(Edns::new(1232));
+    res.to_bytes().unwrap()
+}
+
+fn time(name: &str, mut f: impl FnMut()) -> Duration {
+    // a few rounds to warm up
+    for _ in 0..ROUNDS / 10 {
+        f();
+    }
+    let start = Instant::now();
+    for _ in 0..ROUNDS {
+        f();
+    }
+    let each = start.elapsed() / ROUNDS;
+    println!("{:<40} {:>8?}", name, each);
+    each
+}
+
+fn main() {
+    let packet = packet();
+    let owned = time("DnsMessage::from_bytes", || {
+        black_box(DnsMessage::from_bytes(black_box(&packet)).unwrap());
+    });
+    let borrowed = time("DnsMessageRef::parse", || {
+        black_box(DnsMessageRef::parse(black_box(&packet)).unwrap());
+    });
+    time("DnsMessageRef::parse + every record", || {
+        let msg = DnsMessageRef::parse(black_box(&packet)).unwrap();
+        for rr in msg.answers().chain(msg.additional()) {
+            black_box((rr.name.labels().count(), rr.rr_type, rr.rdata));
+        }
+    });
+    time("DnsMessageRef::parse + to_message", || {
+        let msg = DnsMessageRef::parse(black_box(&packet)).unwrap();
+        black_box(msg.to_message().unwrap());
+    });
+    println!(
+        "parsing in place is {:.1}x faster",
+        owned.as_secs_f64() / borrowed.as_secs_f64()
+    );
+}
@@ -0,0 +1,15 @@
+// cargo +nightly fuzz run parse fuzz/corpus/parse
+//
+// from_bytes has to cope with anything at all: no panics, no hangs, no huge allocations. And
+// whatever it does accept has to go back through to_bytes without a panic (it may still be
+// refused there: with the compression undone, a big message can come out over 64K)
+#![no_main]
+
+use implementation::DnsMessage;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(msg) = DnsMessage::from_bytes(data) {
+        let _ = msg.to_bytes();
+    }
+});
@@ -0,0 +1,221 @@
+// destination address ordering (RFC 6724 section 6): given every address a name resolved to, put
+// the ones most likely to work first, the way getaddrinfo does. Mostly that means not trying
+// IPv6 first on a host with no IPv6 route, and not trying a global address before a link-local
+// one on the same link.
+//
+// The rules compare each destination with the source address the kernel would pick for it. We
+// find that out the same way glibc does, by connecting a UDP socket (which sends nothing) and
+// asking for its local address. Rules 3, 4 and 7 need to know about deprecated, home and
+// tunnelled addresses, which we can't see from here, so they're left out
+use std::cmp::Ordering;
+use std::net::{IpAddr, Ipv6Addr, SocketAddr, UdpSocket};
+
+use crate::resolver::unspecified_addr;
+
+// scopes from RFC 4291, the bigger the wider
+const SCOPE_LINK_LOCAL: u8 = 0x2;
+const SCOPE_SITE_LOCAL: u8 = 0x5;
+const SCOPE_GLOBAL: u8 = 0xE;
+
+// the default policy table: prefix, prefix length, precedence, label. First match wins, so the
+// longer prefixes come first
+const POLICY: [(Ipv6Addr, u8, u8, u8); 9] = [
+    (Ipv6Addr::LOCALHOST, 128, 50, 0),
+    (Ipv6Addr::new(0, 0, 0, 0, 0, 0xFFFF, 0, 0), 96, 35, 4),
+    (Ipv6Addr::new(0, 0, 0, 0, 0, 0, 0, 0), 96, 1, 3),
+    (Ipv6Addr::new(0x2001, 0, 0, 0, 0, 0, 0, 0), 32, 5, 5),
+    (Ipv6Addr::new(0x2002, 0, 0, 0, 0, 0, 0, 0), 16, 30, 2),
+    (Ipv6Addr::new(0x3FFE, 0, 0, 0, 0, 0, 0, 0), 16, 1, 12),
+    (Ipv6Addr::new(0xFEC0, 0, 0, 0, 0, 0, 0, 0), 10, 1, 11),
+    (Ipv6Addr::new(0xFC00, 0, 0, 0, 0, 0, 0, 0), 7, 3, 13),
+    (Ipv6Addr::UNSPECIFIED, 0, 40, 1),
+];
+
+// sorts `addrs` in place, best first. Addresses that compare equal keep their order, so the
+// server's round robin still spreads the load
+pub fn sort_addrs(addrs: &mut [IpAddr]) {
+    sort_with(addrs, source_for);
+}
+
+// the work of sort_addrs, with the source address lookup passed in so tests don't depend on how
+// the machine running them is connected
+fn sort_with(addrs: &mut [IpAddr], source_for: impl Fn(IpAddr) -> Option<IpAddr>) {
+    let mut keyed: Vec<(IpAddr, Option<IpAddr>)> =
+        addrs.iter().map(|&addr| (addr, source_for(addr))).collect();
+    keyed.sort_by(|a, b| compare(*a, *b));
+    for (slot, (addr, _)) in addrs.iter_mut().zip(keyed) {
+        *slot = addr;
+    }
+}
+
+// None when there's no route to `dest` at all
+fn source_for(dest: IpAddr) -> Option<IpAddr> {
+    let dest = SocketAddr::new(dest, 53);
+    let socket = UdpSocket::bind(unspecified_addr(&dest)).ok()?;
+    socket.connect(dest).ok()?;
+    socket.local_addr().ok().map(|local| local.ip())
+}
+
+// Less when (da, sa) should be tried before (db, sb)
+fn compare((da, sa): (IpAddr, Option<IpAddr>), (db, sb): (IpAddr, Option<IpAddr>)) -> Ordering {
+    let (sa, sb) = match (sa, sb) {
+        (Some(sa), Some(sb)) => (sa, sb),
+        // rule 1: avoid unusable destinations
+        (Some(_), None) => return Ordering::Less,
+        (None, Some(_)) => return Ordering::Greater,
+        (None, None) => return Ordering::Equal,
+    };
+    let (da6, db6) = (mapped(da), mapped(db));
+    let (sa6, sb6) = (mapped(sa), mapped(sb));
+
+    // rule 2: prefer matching scope
+    let matching = |d, s| scope(d) == scope(s);
+    let by_scope = matching(&da6, &sa6).cmp(&matching(&db6, &sb6)).reverse();
+    // rule 5: prefer matching label
+    let matching = |d, s| policy(d).1 == policy(s).1;
+    let by_label = matching(&da6, &sa6).cmp(&matching(&db6, &sb6)).reverse();
+    // rule 6: prefer higher precedence
+    let by_precedence = policy(&da6).0.cmp(&policy(&db6).0).reverse();
+    // rule 8: prefer smaller scope
+    let by_smaller_scope = scope(&da6).cmp(&scope(&db6));
+    // rule 9: prefer the longest prefix shared with the source. glibc only does this for IPv6,
+    // and so do we: for IPv4 it mostly undoes the server's round robin for no good reason
+    let by_prefix = if da.is_ipv6() && db.is_ipv6() {
+        common_prefix(&da6, &sa6)
+            .cmp(&common_prefix(&db6, &sb6))
+            .reverse()
+    } else {
+        Ordering::Equal
+    };
+
+    by_scope
+        .then(by_label)
+        .then(by_precedence)
+        .then(by_smaller_scope)
+        .then(by_prefix)
+}
+
+// IPv4 goes through the table as ::ffff:a.b.c.d
+fn mapped(addr: IpAddr) -> Ipv6Addr {
+    match addr {
+        IpAddr::V4(v4) => v4.to_ipv6_mapped(),
+        IpAddr::V6(v6) => v6,
+    }
+}
+
+// (precedence, label)
+fn policy(addr: &Ipv6Addr) -> (u8, u8) {
+    POLICY
+        .iter()
+        .find(|(prefix, len, _, _)| common_prefix(addr, prefix) >= *len as u32)
+        .map(|&(_, _, precedence, label)| (precedence, label))
+        .unwrap_or((40, 1))
+}
+
+fn scope(addr: &Ipv6Addr) -> u8 {
+    let segments = addr.segments();
+    if let Some(v4) = addr.to_ipv4_mapped() {
+        // 127/8 and 169.254/16 count as link-local, everything else as global (section 3.2)
+        return if v4.is_loopback() || v4.is_link_local() {
+            SCOPE_LINK_LOCAL
+        } else {
+            SCOPE_GLOBAL
+        };
+    }
+    if segments[0] >> 8 == 0xFF {
+        // multicast carries its scope in the address
+        return (segments[0] & 0xF) as u8;
+    }
+    if addr.is_loopback() || segments[0] & 0xFFC0 == 0xFE80 {
+        SCOPE_LINK_LOCAL
+    } else if segments[0] & 0xFFC0 == 0xFEC0 {
+        SCOPE_SITE_LOCAL
+    } else {
+        SCOPE_GLOBAL
+    }
+}
+
+fn common_prefix(a: &Ipv6Addr, b: &Ipv6Addr) -> u32 {
+    (u128::from(*a) ^ u128::from(*b)).leading_zeros()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn test_rfc6724_examples() {
+        // the examples from section 10.2, each with the sources of the host it describes
+        let sorted = |addrs: &[&str], sources: &[(&str, &str)]| {
+            let mut addrs: Vec<IpAddr> = addrs.iter().map(|s| ip(s)).collect();
+            let sources: Vec<(IpAddr, IpAddr)> =
+                sources.iter().map(|(d, s)| (ip(d), ip(s))).collect();
+            sort_with(&mut addrs, |dest| {
+                sources.iter().find(|(d, _)| *d == dest).map(|(_, s)| *s)
+            });
+            addrs
+        };
+
+        // prefer matching scope
+        assert_eq!(
+            sorted(
+                &["2001:db8:1::1", "198.51.100.121"],
+                &[
+                    ("2001:db8:1::1", "fe80::1"),
+                    ("198.51.100.121", "198.51.100.117")
+                ]
+            ),
+            [ip("198.51.100.121"), ip("2001:db8:1::1")]
+        );
+        // prefer higher precedence: native IPv6 over IPv4
+        assert_eq!(
+            sorted(
+                &["198.51.100.121", "2001:db8:1::1"],
+                &[
+                    ("2001:db8:1::1", "2001:db8:1::2"),
+                    ("198.51.100.121", "198.51.100.117")
+                ]
+            ),
+            [ip("2001:db8:1::1"), ip("198.51.100.121")]
+        );
+        // prefer smaller scope
+        assert_eq!(
+            sorted(
+                &["2001:db8:1::1", "fe80::1"],
+                &[("2001:db8:1::1", "2001:db8:1::2"), ("fe80::1", "fe80::2")]
+            ),
+            [ip("fe80::1"), ip("2001:db8:1::1")]
+        );
+        // prefer matching label: 6to4 with a 6to4 source
+        assert_eq!(
+            sorted(
+                &["2001:db8:1::1", "2002:c633:6401::1"],
+                &[
+                    ("2001:db8:1::1", "2002:c633:6401::2"),
+                    ("2002:c633:6401::1", "2002:c633:6401::2")
+                ]
+            ),
+            [ip("2002:c633:6401::1"), ip("2001:db8:1::1")]
+        );
+        // avoid unusable destinations: no IPv6 route, IPv4 goes first
+        assert_eq!(
+            sorted(
+                &["2001:db8:1::1", "198.51.100.121"],
+                &[("198.51.100.121", "198.51.100.117")]
+            ),
+            [ip("198.51.100.121"), ip("2001:db8:1::1")]
+        );
+    }
+
+    #[test]
+    fn test_ties_keep_their_order() {
+        let mut addrs = vec![ip("192.0.2.9"), ip("192.0.2.1"), ip("192.0.2.5")];
+        let expected = addrs.clone();
+        sort_with(&mut addrs, |_| Some(ip("192.0.2.200")));
+        assert_eq!(addrs, expected);
+    }
+}
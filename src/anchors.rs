@@ -0,0 +1,557 @@
+// trust anchors kept in a file, and kept up to date the RFC 5011 way: the zone announces a new
+// key by publishing it in its DNSKEY set (signed with the keys we already trust) for the
+// hold-down time before we trust it too, and retires an old one by setting its REVOKE flag. So
+// a resolver that checks in every now and then follows a root KSK rollover by itself.
+//
+// The file is in root.key format, the zone file lines unbound-anchor and friends write: DS or
+// DNSKEY records for one zone, ; for comments. We write DNSKEY lines back, with what we know
+// about each key in ;; comments after it
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::dnssec::{self, matches_ds, question, verify};
+use crate::encoding::{from_base64, to_base64};
+use crate::rdata::{TYPE_DNSKEY, TYPE_RRSIG};
+use crate::{DnsError, Dnskey, Ds, Name, RData, Resolver, ResourceRecord, TrustAnchor};
+
+// how long a new key has to be in the DNSKEY set before we trust it, and how long a revoked one
+// stays on file (RFC 5011 2.4.1 and 4, for a zone whose TTLs don't push them up)
+pub const HOLD_DOWN: u64 = 30 * 86400;
+
+// where a key stands with us (RFC 5011 4)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyState {
+    // new, waiting out the hold-down
+    AddPend,
+    // trusted
+    Valid,
+    // trusted, but gone from the DNSKEY set without being revoked
+    Missing,
+    // revoked by the zone, never to be trusted again
+    Revoked,
+}
+
+impl KeyState {
+    fn as_str(self) -> &'static str {
+        match self {
+            KeyState::AddPend => "addpend",
+            KeyState::Valid => "valid",
+            KeyState::Missing => "missing",
+            KeyState::Revoked => "revoked",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TrackedKey {
+    pub key: Dnskey,
+    pub state: KeyState,
+    // when it got into that state, in seconds since the epoch
+    pub since: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AnchorStore {
+    // where save writes to, None for a store that didn't come from a file
+    path: Option<PathBuf>,
+    zone: Name,
+    // DS anchors from the file, until the keys they are for turn up
+    ds: Vec<Ds>,
+    keys: Vec<TrackedKey>,
+}
+
+impl AnchorStore {
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        let mut store = Self::parse(&fs::read_to_string(&path)?)?;
+        store.path = Some(path.as_ref().to_path_buf());
+        Ok(store)
+    }
+
+    // the DS or DNSKEY lines of a root.key file. Records of other types are skipped, records
+    // for more than one zone are an error
+    pub fn parse(text: &str) -> io::Result<Self> {
+        let mut zone: Option<Name> = None;
+        let mut ds = Vec::new();
+        let mut keys = Vec::new();
+        for line in records(text) {
+            let (record, state) = match line.split_once(";;") {
+                Some((record, state)) => (record, Some(state)),
+                None => (line.as_str(), None),
+            };
+            let mut words = record.split_whitespace().peekable();
+            let Some(owner) = words.next() else {
+                continue;
+            };
+            // the TTL and class are optional and we have no use for them
+            words.next_if(|word| word.parse::<u32>().is_ok());
+            words.next_if(|word| word.eq_ignore_ascii_case("IN"));
+            let rr_type = words.next().unwrap_or_default().to_ascii_uppercase();
+            let fields: Vec<&str> = words.collect();
+            let data = match rr_type.as_str() {
+                "DS" => Record::Ds(parse_ds(&fields).ok_or_else(|| invalid(&line))?),
+                "DNSKEY" => Record::Dnskey(parse_dnskey(&fields).ok_or_else(|| invalid(&line))?),
+                _ => continue,
+            };
+            let owner = Name::from(owner);
+            if zone.get_or_insert_with(|| owner.clone()) != &owner {
+                return Err(invalid("trust anchors for more than one zone"));
+            }
+            match data {
+                Record::Ds(record) => ds.push(record),
+                Record::Dnskey(key) => {
+                    let (state, since) = parse_state(state.unwrap_or_default());
+                    keys.push(TrackedKey { key, state, since });
+                }
+            }
+        }
+        let zone = zone.ok_or_else(|| invalid("no trust anchors"))?;
+        Ok(AnchorStore {
+            path: None,
+            zone,
+            ds,
+            keys,
+        })
+    }
+
+    pub fn zone(&self) -> &Name {
+        &self.zone
+    }
+
+    pub fn keys(&self) -> &[TrackedKey] {
+        &self.keys
+    }
+
+    // what to hand to Resolver::set_trust_anchors: the DS anchors we still have, and every key
+    // we trust
+    pub fn anchors(&self) -> Vec<TrustAnchor> {
+        let ds = self.ds.iter().map(|ds| TrustAnchor {
+            zone: self.zone.clone(),
+            ds: ds.clone(),
+        });
+        let keys = self
+            .keys
+            .iter()
+            .filter(|tracked| matches!(tracked.state, KeyState::Valid | KeyState::Missing))
+            .map(|tracked| TrustAnchor::from_dnskey(self.zone.clone(), &tracked.key));
+        ds.chain(keys).collect()
+    }
+
+    // asks for the zone's DNSKEY set and goes on from there, see update. RFC 5011 wants this
+    // done at least every 15 days, and no more than once an hour
+    pub fn refresh(&mut self, resolver: &Resolver) -> Result<bool, DnsError> {
+        let res = resolver.query(&question(self.zone.clone(), TYPE_DNSKEY))?;
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        self.update(&res.answers, now)
+    }
+
+    // moves each key along with what the zone's DNSKEY set (`records`, with its signatures)
+    // says, as of `now` (seconds since the epoch, for the hold-down). The set only counts when
+    // a key we trust already signed it. True when anything changed, and the store wants saving
+    pub fn update(&mut self, records: &[ResourceRecord], now: u64) -> Result<bool, DnsError> {
+        let rrset: Vec<&ResourceRecord> = records
+            .iter()
+            .filter(|rr| rr.rr_type == TYPE_DNSKEY && rr.name == self.zone)
+            .collect();
+        let sigs: Vec<_> = records
+            .iter()
+            .filter(|rr| rr.rr_type == TYPE_RRSIG && rr.name == self.zone)
+            .filter_map(|rr| match &rr.data {
+                RData::RRSIG(sig) if sig.type_covered == TYPE_DNSKEY => Some(sig),
+                _ => None,
+            })
+            .collect();
+        let published: Vec<&Dnskey> = rrset
+            .iter()
+            .filter_map(|rr| match &rr.data {
+                RData::DNSKEY(key) => Some(key),
+                _ => None,
+            })
+            .collect();
+        let signs = |key: &Dnskey| {
+            sigs.iter()
+                .any(|sig| verify(&rrset, sig, key, dnssec::now()).is_ok())
+        };
+
+        let trusted = published.iter().any(|key| {
+            let tracked = self.keys.iter().any(|tracked| {
+                matches!(tracked.state, KeyState::Valid | KeyState::Missing) && tracked.key == **key
+            });
+            let anchored = self.ds.iter().any(|ds| matches_ds(&self.zone, key, ds));
+            (tracked || anchored) && signs(key)
+        });
+        if !trusted {
+            return Err(DnsError::Bogus("DNSKEY set isn't signed by a key we trust"));
+        }
+
+        let (ds_before, keys_before) = (self.ds.len(), self.keys.clone());
+        // the keys our DS anchors are for, trusted straight away: the file vouches for them
+        for key in &published {
+            if self.ds.iter().any(|ds| matches_ds(&self.zone, key, ds))
+                && !self.keys.iter().any(|tracked| tracked.key.same_key(key))
+            {
+                self.keys.push(TrackedKey {
+                    key: (*key).clone(),
+                    state: KeyState::Valid,
+                    since: now,
+                });
+            }
+        }
+        // and the DS anchors go once their keys are tracked. One for a key that isn't out yet
+        // (a successor configured ahead of the rollover) stays until it is
+        let zone = &self.zone;
+        self.ds
+            .retain(|ds| !published.iter().any(|key| matches_ds(zone, key, ds)));
+
+        for key in published.iter().filter(|key| key.is_sep()) {
+            let tracked = self.keys.iter_mut().find(|t| t.key.same_key(key));
+            // a revocation only counts with the revoked key's own signature on it
+            if key.is_revoked() {
+                if let Some(tracked) = tracked {
+                    if tracked.state != KeyState::Revoked && signs(key) {
+                        *tracked = TrackedKey {
+                            key: (*key).clone(),
+                            state: KeyState::Revoked,
+                            since: now,
+                        };
+                    }
+                }
+                continue;
+            }
+            match tracked {
+                None => self.keys.push(TrackedKey {
+                    key: (*key).clone(),
+                    state: KeyState::AddPend,
+                    since: now,
+                }),
+                Some(tracked) => match tracked.state {
+                    KeyState::AddPend if now.saturating_sub(tracked.since) >= HOLD_DOWN => {
+                        tracked.state = KeyState::Valid;
+                        tracked.since = now;
+                    }
+                    KeyState::Missing => {
+                        tracked.state = KeyState::Valid;
+                        tracked.since = now;
+                    }
+                    _ => {}
+                },
+            }
+        }
+
+        // the ones that are gone: a trusted key stays trusted (until it's revoked), a pending one
+        // starts all over if it turns up again, a revoked one is forgotten after the hold-down
+        self.keys.retain_mut(|tracked| {
+            if published.iter().any(|key| tracked.key.same_key(key)) {
+                return true;
+            }
+            match tracked.state {
+                KeyState::Valid => {
+                    tracked.state = KeyState::Missing;
+                    tracked.since = now;
+                    true
+                }
+                KeyState::AddPend => false,
+                KeyState::Revoked => now.saturating_sub(tracked.since) < HOLD_DOWN,
+                KeyState::Missing => true,
+            }
+        });
+        Ok(self.ds.len() != ds_before || self.keys != keys_before)
+    }
+
+    // the store as a root.key file, which open reads back
+    pub fn to_text(&self) -> String {
+        let zone = if self.zone.is_root() {
+            ".".to_string()
+        } else {
+            format!("{}.", self.zone)
+        };
+        let mut text = format!(
+            "; trust anchors for {}, kept up to date by RFC 5011\n",
+            zone
+        );
+        for ds in &self.ds {
+            text += &format!(
+                "{} IN DS {} {} {} {}\n",
+                zone,
+                ds.key_tag,
+                ds.algorithm,
+                ds.digest_type,
+                ds.digest
+                    .iter()
+                    .map(|b| format!("{:02X}", b))
+                    .collect::<String>()
+            );
+        }
+        for tracked in &self.keys {
+            let key = &tracked.key;
+            text += &format!(
+                "{} IN DNSKEY {} {} {} {} ;{{id = {}}} ;;state={} ;;since={}\n",
+                zone,
+                key.flags,
+                key.protocol,
+                key.algorithm,
+                to_base64(&key.public_key),
+                key.key_tag(),
+                tracked.state.as_str(),
+                tracked.since
+            );
+        }
+        text
+    }
+
+    // writes the store back to the file it came from, through a temporary file so a crash
+    // halfway leaves the old one
+    pub fn save(&self) -> io::Result<()> {
+        let Some(path) = &self.path else {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                "trust anchors that didn't come from a file",
+            ));
+        };
+        let tmp = path.with_extension("tmp");
+        fs::write(&tmp, self.to_text())?;
+        fs::rename(&tmp, path)
+    }
+}
+
+enum Record {
+    Ds(Ds),
+    Dnskey(Dnskey),
+}
+
+fn invalid(what: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("bad trust anchor file: {}", what),
+    )
+}
+
+// the records of a zone file, one line each: comments (but not our ;; state) dropped, and the
+// lines of a record in ( ) put together
+fn records(text: &str) -> Vec<String> {
+    let mut records = Vec::new();
+    let mut current = String::new();
+    let mut open = false;
+    for line in text.lines() {
+        let (data, state) = match line.split_once(";;") {
+            Some((data, state)) => (data, Some(state)),
+            None => (line, None),
+        };
+        let data = data.split(';').next().unwrap_or_default();
+        for c in data.chars() {
+            match c {
+                '(' => open = true,
+                ')' => open = false,
+                c => current.push(c),
+            }
+        }
+        current.push(' ');
+        if let Some(state) = state {
+            current += ";;";
+            current += state;
+        }
+        if !open {
+            if !current.trim().is_empty() {
+                records.push(current.trim().to_string());
+            }
+            current.clear();
+        }
+    }
+    records
+}
+
+fn parse_ds(fields: &[&str]) -> Option<Ds> {
+    let [key_tag, algorithm, digest_type, digest @ ..] = fields else {
+        return None;
+    };
+    let digest: String = digest.concat();
+    if !digest.len().is_multiple_of(2) || digest.is_empty() {
+        return None;
+    }
+    Some(Ds {
+        key_tag: key_tag.parse().ok()?,
+        algorithm: algorithm.parse().ok()?,
+        digest_type: digest_type.parse().ok()?,
+        digest: (0..digest.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(digest.get(i..i + 2)?, 16).ok())
+            .collect::<Option<_>>()?,
+    })
+}
+
+fn parse_dnskey(fields: &[&str]) -> Option<Dnskey> {
+    let [flags, protocol, algorithm, key @ ..] = fields else {
+        return None;
+    };
+    Some(Dnskey {
+        flags: flags.parse().ok()?,
+        protocol: protocol.parse().ok()?,
+        algorithm: algorithm.parse().ok()?,
+        public_key: from_base64(&key.concat())?,
+    })
+}
+
+// the ;;state= and ;;since= comments after a DNSKEY. One without them is a key the user put
+// there, trusted as of now
+fn parse_state(comments: &str) -> (KeyState, u64) {
+    let mut state = KeyState::Valid;
+    let mut since = 0;
+    for comment in comments.split(";;") {
+        match comment.trim().split_once('=') {
+            Some(("state", value)) => {
+                state = match value.trim() {
+                    "addpend" => KeyState::AddPend,
+                    "missing" => KeyState::Missing,
+                    "revoked" => KeyState::Revoked,
+                    _ => KeyState::Valid,
+                }
+            }
+            Some(("since", value)) => since = value.trim().parse().unwrap_or(0),
+            _ => {}
+        }
+    }
+    (state, since)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dnssec::tests::ZoneKey;
+
+    const DAY: u64 = 86400;
+
+    // the root's DNSKEY set with `keys` in it, signed by `signers`
+    fn dnskey_set(keys: &[&ZoneKey], signers: &[&ZoneKey]) -> Vec<ResourceRecord> {
+        let rrset: Vec<ResourceRecord> = keys
+            .iter()
+            .map(|key| ResourceRecord::new("", 172800, RData::DNSKEY(key.dnskey.clone())))
+            .collect();
+        let mut records: Vec<ResourceRecord> = signers.iter().map(|key| key.sign(&rrset)).collect();
+        records.extend(rrset);
+        records
+    }
+
+    #[test]
+    fn test_parse_root_key() {
+        let store = AnchorStore::parse(
+            "; the root's KSK-2017, as IANA publishes it\n\
+             . 172800 IN DS 20326 8 2 E06D44B80B8F1D39A95C0B0D7C65D08458E880409BBC683457104237C7F8EC8D\n\
+             . IN DNSKEY 257 3 15 ( l02Woi0iS8Aa25FQkUd9RMzZHJpBoRQwAQEX1SxZJA4= ) ;;state=addpend ;;since=1700000000\n",
+        )
+        .unwrap();
+        assert!(store.zone().is_root());
+        assert_eq!(store.anchors(), TrustAnchor::root()[..1]);
+        let tracked = &store.keys()[0];
+        assert_eq!(tracked.state, KeyState::AddPend);
+        assert_eq!(tracked.since, 1700000000);
+        assert_eq!(tracked.key.public_key.len(), 32);
+        assert_eq!(
+            to_base64(&tracked.key.public_key),
+            "l02Woi0iS8Aa25FQkUd9RMzZHJpBoRQwAQEX1SxZJA4="
+        );
+
+        assert!(AnchorStore::parse("; nothing\n").is_err());
+        assert!(AnchorStore::parse(". IN DS 20326 8 2 E06D4\n").is_err());
+        assert!(AnchorStore::parse(". IN DS 1 8 2 AA\nexample. IN DS 1 8 2 AA\n").is_err());
+    }
+
+    #[test]
+    fn test_rollover() {
+        let old = ZoneKey::new("");
+        let new = ZoneKey::new("");
+        let mut store = AnchorStore::parse(&format!(
+            ". IN DS {} 15 2 {}",
+            old.ds().key_tag,
+            old.ds()
+                .digest
+                .iter()
+                .map(|b| format!("{:02x}", b))
+                .collect::<String>()
+        ))
+        .unwrap();
+        let t0 = 1_700_000_000;
+
+        // the key the DS is for becomes a tracked one
+        assert!(store.update(&dnskey_set(&[&old], &[&old]), t0).unwrap());
+        assert_eq!(store.keys()[0].state, KeyState::Valid);
+
+        // a set nobody we trust signed changes nothing
+        let err = store
+            .update(&dnskey_set(&[&old, &new], &[&new]), t0)
+            .unwrap_err();
+        assert!(matches!(err, DnsError::Bogus(_)));
+
+        // the new key shows up, and has to wait out the hold-down
+        let both = dnskey_set(&[&old, &new], &[&old]);
+        assert!(store.update(&both, t0).unwrap());
+        assert_eq!(store.keys()[1].state, KeyState::AddPend);
+        assert!(!store.update(&both, t0 + 10 * DAY).unwrap());
+        assert_eq!(store.anchors().len(), 1);
+        assert!(store.update(&both, t0 + 31 * DAY).unwrap());
+        assert_eq!(store.keys()[1].state, KeyState::Valid);
+        assert_eq!(store.anchors().len(), 2);
+
+        // a trusted key dropped without being revoked stays trusted, but missing
+        let only_new = dnskey_set(&[&new], &[&new]);
+        assert!(store.update(&only_new, t0 + 32 * DAY).unwrap());
+        assert_eq!(store.keys()[0].state, KeyState::Missing);
+        assert_eq!(store.anchors().len(), 2);
+        assert!(store.update(&both, t0 + 33 * DAY).unwrap());
+        assert_eq!(store.keys()[0].state, KeyState::Valid);
+
+        // the old one is revoked, signed by itself, and the new one takes over
+        let mut revoked = old;
+        revoked.dnskey.flags |= 0x0080;
+        let set = dnskey_set(&[&revoked, &new], &[&revoked, &new]);
+        assert!(store.update(&set, t0 + 40 * DAY).unwrap());
+        assert_eq!(store.keys()[0].state, KeyState::Revoked);
+        assert_eq!(
+            store.anchors(),
+            [TrustAnchor::from_dnskey(Name::root(), &new.dnskey)]
+        );
+
+        // it's forgotten once it's been gone for the hold-down
+        store.update(&only_new, t0 + 50 * DAY).unwrap();
+        assert_eq!(store.keys().len(), 2);
+        store.update(&only_new, t0 + 71 * DAY).unwrap();
+        assert_eq!(store.keys().len(), 1);
+
+        // and what's in the file is what we had
+        let reread = AnchorStore::parse(&store.to_text()).unwrap();
+        assert_eq!(reread.keys(), store.keys());
+    }
+
+    fn ds_line(key: &ZoneKey) -> String {
+        let ds = key.ds();
+        let digest: String = ds.digest.iter().map(|b| format!("{:02x}", b)).collect();
+        format!(". IN DS {} 15 2 {}\n", ds.key_tag, digest)
+    }
+
+    #[test]
+    fn test_ds_for_a_key_not_out_yet_is_kept() {
+        let current = ZoneKey::new("");
+        let successor = ZoneKey::new("");
+        let mut store = AnchorStore::parse(&(ds_line(&current) + &ds_line(&successor))).unwrap();
+        let t0 = 1_700_000_000;
+
+        // only the current key is published: its DS is done with, the successor's isn't
+        assert!(store
+            .update(&dnskey_set(&[&current], &[&current]), t0)
+            .unwrap());
+        assert_eq!(store.keys().len(), 1);
+        assert_eq!(store.anchors().len(), 2);
+        let reread = AnchorStore::parse(&store.to_text()).unwrap();
+        assert_eq!(reread, store);
+        assert_eq!(reread.ds, [successor.ds()]);
+
+        // and when the successor does turn up, the file has already vouched for it
+        let both = dnskey_set(&[&current, &successor], &[&current]);
+        assert!(store.update(&both, t0 + DAY).unwrap());
+        assert!(store.ds.is_empty());
+        assert_eq!(store.keys()[1].state, KeyState::Valid);
+    }
+}
@@ -0,0 +1,264 @@
+// the Resolver for async code, on tokio: the same exchange (UDP, TCP when the answer doesn't fit,
+// the next upstream when one fails) without tying up a thread per query, so a service can have
+// hundreds of lookups in flight at once. Each query gets a socket of its own rather than sharing
+// one behind a lock like the blocking Resolver does, which keeps concurrent queries out of each
+// other's way (and gives each one a different source port)
+use std::io;
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use bytes::BytesMut;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpStream, UdpSocket};
+use tokio::time::timeout;
+use tokio_util::codec::{Decoder, Encoder};
+
+use crate::options::QueryOptions;
+use crate::resolver::{
+    answers_question, case_restored, echoes_case, unspecified_addr, with_edns, with_mixed_case,
+    DEFAULT_TIMEOUT,
+};
+use crate::resolver::{DEFAULT_SERVER, RCODE_REFUSED, RCODE_SERVFAIL};
+use crate::{DnsCodec, DnsError, DnsMessage, Name, RCODE_FORMERR};
+
+pub struct AsyncResolver {
+    servers: Vec<SocketAddr>,
+    // for each upstream as a whole: UDP, the TCP retry and all the waiting in between
+    timeout: Duration,
+    options: QueryOptions,
+}
+
+impl Default for AsyncResolver {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl AsyncResolver {
+    pub fn new() -> Self {
+        Self::with_servers([DEFAULT_SERVER.parse().unwrap()])
+    }
+
+    pub fn with_server(server: SocketAddr) -> Self {
+        Self::with_servers([server])
+    }
+
+    // tried in the order given, the next one only when the one before fails
+    pub fn with_servers(servers: impl IntoIterator<Item = SocketAddr>) -> Self {
+        AsyncResolver {
+            servers: servers.into_iter().collect(),
+            timeout: DEFAULT_TIMEOUT,
+            options: QueryOptions::default(),
+        }
+    }
+
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = timeout;
+    }
+
+    pub fn set_options(&mut self, options: QueryOptions) {
+        self.options = options;
+    }
+
+    // a recursive query for `name` of the given type
+    pub async fn lookup(
+        &self,
+        name: impl Into<Name>,
+        qtype: impl Into<u16>,
+    ) -> Result<DnsMessage, DnsError> {
+        self.query(&DnsMessage::query(name).qtype(qtype).build())
+            .await
+    }
+
+    // like Resolver::query: the upstreams in turn until one answers, with SERVFAIL/REFUSED,
+    // errors and timeouts handing over to the next one
+    pub async fn query(&self, msg: &DnsMessage) -> Result<DnsMessage, DnsError> {
+        match with_mixed_case(msg, &self.options) {
+            Some(mixed) => case_restored(msg, &mixed, self.query_as_is(&mixed).await?),
+            None => self.query_as_is(msg).await,
+        }
+    }
+
+    async fn query_as_is(&self, msg: &DnsMessage) -> Result<DnsMessage, DnsError> {
+        let with_opt = with_edns(msg, &self.options);
+        let mut last =
+            Err(io::Error::new(io::ErrorKind::InvalidInput, "no upstream servers").into());
+        for &server in &self.servers {
+            let exchange = self.query_server(server, msg, &with_opt);
+            let result = match timeout(self.timeout, exchange).await {
+                Ok(result) => result,
+                Err(_) => Err(DnsError::Timeout),
+            };
+            match result {
+                Ok(res) if matches!(res.rcode(), RCODE_SERVFAIL | RCODE_REFUSED) => last = Ok(res),
+                Ok(res) => return Ok(res),
+                Err(e) => last = Err(e),
+            }
+        }
+        last
+    }
+
+    async fn query_server(
+        &self,
+        server: SocketAddr,
+        msg: &DnsMessage,
+        with_opt: &DnsMessage,
+    ) -> Result<DnsMessage, DnsError> {
+        let res = self.exchange(server, with_opt).await?;
+        // same as the blocking Resolver: a server choking on our OPT record gets asked again
+        // without it
+        if res.rcode() == RCODE_FORMERR && !std::ptr::eq(with_opt, msg) {
+            return self.exchange(server, msg).await;
+        }
+        Ok(res)
+    }
+
+    async fn exchange(&self, server: SocketAddr, msg: &DnsMessage) -> Result<DnsMessage, DnsError> {
+        let query = msg.to_bytes()?;
+        if !self.options.force_tcp {
+            match self.query_udp(server, msg, &query).await? {
+                Some(res) if !res.truncated() => return Ok(res),
+                _ => {}
+            }
+        }
+        query_tcp(server, msg, &query).await
+    }
+
+    // None when the answer didn't fit: truncated, or bigger than we said we'd take
+    async fn query_udp(
+        &self,
+        server: SocketAddr,
+        msg: &DnsMessage,
+        query: &[u8],
+    ) -> Result<Option<DnsMessage>, DnsError> {
+        let socket = UdpSocket::bind(unspecified_addr(&server)).await?;
+        socket.connect(server).await?;
+        socket.send(query).await?;
+
+        // a connected socket only hands us datagrams from the server, but those can still be
+        // late answers or forgeries: keep reading until one matches (or the timeout hits)
+        let payload = self.options.udp_payload();
+        let mut buf = vec![0; payload + 1];
+        loop {
+            let size = socket.recv(&mut buf).await?;
+            if size < 2 || buf[0..2] != msg.header.identification.to_be_bytes() {
+                continue;
+            }
+            if size > payload {
+                return Ok(None);
+            }
+            let res = DnsMessage::from_bytes(&buf[..size])?;
+            if answers_question(msg, &res)
+                && (!self.options.randomize_case || echoes_case(msg, &res))
+            {
+                return Ok(Some(res));
+            }
+        }
+    }
+}
+
+async fn query_tcp(
+    server: SocketAddr,
+    msg: &DnsMessage,
+    query: &[u8],
+) -> Result<DnsMessage, DnsError> {
+    let mut stream = TcpStream::connect(server).await?;
+    stream.set_nodelay(true)?;
+    let mut codec = DnsCodec;
+    let mut buf = BytesMut::new();
+    codec.encode(query, &mut buf)?;
+    stream.write_all(&buf).await?;
+
+    buf.clear();
+    let packet = loop {
+        if let Some(msg) = codec.decode(&mut buf)? {
+            break msg;
+        }
+        if stream.read_buf(&mut buf).await? == 0 {
+            return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+        }
+    };
+    let res = DnsMessage::from_bytes(&packet)?;
+    if res.header.identification != msg.header.identification || !answers_question(msg, &res) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "TCP response doesn't match the query",
+        )
+        .into());
+    }
+    Ok(res)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{
+        mock_tcp_server_at, mock_udp_server, mock_udp_server_replies, response_with_rdata,
+    };
+    use crate::RecordType;
+    use std::net::Ipv4Addr;
+
+    #[tokio::test]
+    async fn test_concurrent_lookups() {
+        // the letter after "host" ends up as the address, so every answer is told apart
+        let server = mock_udp_server(20, |query| {
+            let n = DnsMessage::from_bytes(query)
+                .unwrap()
+                .question()
+                .qname
+                .as_str()
+                .as_bytes()[4];
+            response_with_rdata(query, 1, &[192, 0, 2, n])
+        });
+        let resolver = std::sync::Arc::new(AsyncResolver::with_server(server));
+
+        let mut tasks = tokio::task::JoinSet::new();
+        for n in 0..20u8 {
+            let resolver = resolver.clone();
+            tasks.spawn(async move {
+                let name = format!("host{}.example.com", (b'a' + n) as char);
+                let res = resolver.lookup(name.as_str(), RecordType::A).await.unwrap();
+                (n, res.ipv4_addrs())
+            });
+        }
+        while let Some(done) = tasks.join_next().await {
+            let (n, addrs) = done.unwrap();
+            assert_eq!(addrs, vec![Ipv4Addr::new(192, 0, 2, b'a' + n)]);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_timeout_then_next_server() {
+        let silent = mock_udp_server_replies(1, |_| vec![]);
+        let answering = mock_udp_server(1, |query| response_with_rdata(query, 1, &[192, 0, 2, 1]));
+        let mut resolver = AsyncResolver::with_servers([silent]);
+        resolver.set_timeout(Duration::from_millis(200));
+        let err = resolver
+            .lookup("example.com", RecordType::A)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, DnsError::Timeout));
+
+        let mut resolver = AsyncResolver::with_servers([silent, answering]);
+        resolver.set_timeout(Duration::from_millis(200));
+        let res = resolver.lookup("example.com", RecordType::A).await.unwrap();
+        assert_eq!(res.ipv4_addrs(), vec![Ipv4Addr::new(192, 0, 2, 1)]);
+    }
+
+    #[tokio::test]
+    async fn test_truncated_answer_goes_over_tcp() {
+        let server = mock_udp_server(1, |query| {
+            let mut res = response_with_rdata(query, 1, &[0, 0, 0, 0]);
+            res[2] |= 0x02; // TC
+            res
+        });
+        mock_tcp_server_at(server, 1, |query| {
+            response_with_rdata(query, 1, &[192, 0, 2, 7])
+        });
+        let res = AsyncResolver::with_server(server)
+            .lookup("example.com", RecordType::A)
+            .await
+            .unwrap();
+        assert_eq!(res.ipv4_addrs(), vec![Ipv4Addr::new(192, 0, 2, 7)]);
+    }
+}
@@ -0,0 +1,524 @@
+// a zone we're authoritative for, loaded from a zone file (see zone.rs), and the answers an
+// authoritative server gives out of it (RFC 1034 section 4.3.2):
+// - a name with records of the type asked for gets them, with AA set
+// - a name that exists without that type is NODATA: no answers, NOERROR and the SOA in the
+//   authority section so the resolver can cache the nothing (RFC 2308). A name with nothing of
+//   its own but names below it (an empty non-terminal) exists all the same
+// - a name that doesn't exist is NXDOMAIN with the SOA, unless a wildcard covers it (RFC 4592):
+//   "*.example.com" answers for the names under example.com that aren't there, but only below
+//   the closest name that is
+// - a name at or below a delegation (NS records anywhere but the apex) isn't ours, and gets a
+//   referral: no AA, the NS records in the authority section and the glue addresses we have for
+//   them. DS is the exception, it lives on our side of the cut
+// - a CNAME answers for every type, and when its target is in the zone too we go on to answer
+//   for that, the way the resolver would have to otherwise
+//
+// MX, NS and SRV answers come with the addresses of their targets when we have them
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crate::rdata::{
+    TYPE_A, TYPE_AAAA, TYPE_CNAME, TYPE_DS, TYPE_NS, TYPE_NSEC, TYPE_RRSIG, TYPE_SOA,
+};
+use crate::zone::load_zone;
+use crate::RCODE_NXDOMAIN;
+use crate::{DnsMessage, Name, RData, RecordType, ResourceRecord, Soa, ZoneDelta, ZoneError};
+
+// how many CNAMEs inside the zone we follow for one answer
+const MAX_CNAME_CHAIN: usize = 8;
+
+#[derive(Debug, Clone)]
+pub struct Zone {
+    origin: Name,
+    soa: ResourceRecord,
+    records: HashMap<Name, Vec<ResourceRecord>>,
+    // every name that exists in the zone: the owners and every name between them and the apex,
+    // so the empty non-terminals are in it too
+    names: HashSet<Name>,
+}
+
+impl Zone {
+    // the zone at `origin` made of `records`. It needs its SOA at the apex, nothing outside of
+    // it, and no name with a CNAME and other data (RFC 1034 section 3.6.2)
+    pub fn new(origin: impl Into<Name>, records: Vec<ResourceRecord>) -> Result<Zone, ZoneError> {
+        let origin = origin.into();
+        let error = |message| ZoneError {
+            file: None,
+            line: 0,
+            message,
+        };
+        let mut soa = None;
+        let mut by_name: HashMap<Name, Vec<ResourceRecord>> = HashMap::new();
+        for rr in records {
+            if !rr.name.is_subdomain_of(&origin) {
+                return Err(error(format!("{} is outside zone {}", rr.name, origin)));
+            }
+            if rr.rr_type == TYPE_SOA {
+                if rr.name != origin || soa.is_some() {
+                    return Err(error(format!("SOA at {} in zone {}", rr.name, origin)));
+                }
+                soa = Some(rr.clone());
+            }
+            by_name.entry(rr.name.clone()).or_default().push(rr);
+        }
+        let soa = soa.ok_or_else(|| error(format!("zone {} has no SOA", origin)))?;
+        for (name, records) in &by_name {
+            let cname = records.iter().any(|rr| rr.rr_type == TYPE_CNAME);
+            // the DNSSEC records that go with the CNAME are the only other data it can have
+            let other = records
+                .iter()
+                .any(|rr| ![TYPE_CNAME, TYPE_RRSIG, TYPE_NSEC].contains(&rr.rr_type));
+            if cname && other {
+                return Err(error(format!("CNAME and other data at {}", name)));
+            }
+        }
+        let mut names = HashSet::new();
+        for owner in by_name.keys() {
+            let mut name = owner.clone();
+            while names.insert(name.clone()) && name != origin {
+                let Some(parent) = name.parent() else {
+                    break;
+                };
+                name = parent;
+            }
+        }
+        Ok(Zone {
+            origin,
+            soa,
+            records: by_name,
+            names,
+        })
+    }
+
+    // the zone in the zone file at `path`
+    pub fn load(path: impl AsRef<Path>, origin: impl Into<Name>) -> Result<Zone, ZoneError> {
+        let origin = origin.into();
+        let records = load_zone(path.as_ref(), origin.clone())?;
+        Zone::new(origin, records).map_err(|e| ZoneError {
+            file: Some(path.as_ref().to_path_buf()),
+            ..e
+        })
+    }
+
+    // writes the zone out as a zone file that `load` reads back, every name in full. It goes to
+    // a file next to `path` first and is then renamed over it, so a crash halfway through leaves
+    // the old file as it was rather than half a zone
+    pub fn save(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let path = path.as_ref();
+        let mut text = format!("$ORIGIN {}\n", absolute(&self.origin));
+        for rr in self.records() {
+            text.push_str(&format!(
+                "{} {} {} {} {}\n",
+                absolute(&rr.name),
+                rr.ttl,
+                rr.class(),
+                rr.record_type(),
+                rr.data
+            ));
+        }
+        let mut temporary = path.as_os_str().to_owned();
+        temporary.push(".tmp");
+        fs::write(&temporary, text)?;
+        fs::rename(&temporary, path)
+    }
+
+    pub fn origin(&self) -> &Name {
+        &self.origin
+    }
+
+    // the class of the zone, its SOA's
+    pub fn class(&self) -> u16 {
+        self.soa.class
+    }
+
+    pub fn soa(&self) -> &Soa {
+        match &self.soa.data {
+            RData::SOA(soa) => soa,
+            _ => unreachable!("Zone::new only takes an SOA record as the SOA"),
+        }
+    }
+
+    // every record in the zone, the SOA first
+    pub fn records(&self) -> impl Iterator<Item = &ResourceRecord> {
+        let rest = self
+            .records
+            .values()
+            .flatten()
+            .filter(|rr| rr.rr_type != TYPE_SOA);
+        std::iter::once(&self.soa).chain(rest)
+    }
+
+    // the zone as it is after `delta` (see ixfr): the records it removes gone, TTLs aside, and
+    // the ones it adds in. It has to start from the version we have, and only remove what's
+    // there; otherwise the zone is left as it was
+    pub fn apply(&mut self, delta: &ZoneDelta) -> Result<(), ZoneError> {
+        let error = |message: &str| ZoneError {
+            file: None,
+            line: 0,
+            message: message.to_string(),
+        };
+        match delta.removed.first().map(|rr| &rr.data) {
+            Some(RData::SOA(soa)) if soa.serial == self.soa().serial => {}
+            _ => return Err(error("delta isn't from the zone's version")),
+        }
+        let mut records: Vec<ResourceRecord> = self.records().cloned().collect();
+        for gone in &delta.removed {
+            let same = |rr: &ResourceRecord| {
+                rr.name == gone.name
+                    && rr.rr_type == gone.rr_type
+                    && rr.class == gone.class
+                    && rr.data == gone.data
+            };
+            let Some(at) = records.iter().position(same) else {
+                return Err(error("delta removes a record the zone doesn't have"));
+            };
+            records.remove(at);
+        }
+        records.extend(delta.added.iter().cloned());
+        *self = Zone::new(self.origin.clone(), records)?;
+        Ok(())
+    }
+
+    pub fn contains(&self, name: &Name) -> bool {
+        name.is_subdomain_of(&self.origin)
+    }
+
+    // the response to `query`, whose name has to be in the zone. Only the answer part: the
+    // server adds RA, EDNS and whatever else goes with it
+    pub fn answer(&self, query: &DnsMessage) -> DnsMessage {
+        let qtype = query.question().qtype;
+        let mut res = DnsMessage::response_to(query);
+        let mut flags = res.flags();
+        flags.aa = true;
+
+        let mut qname = query.question().qname.clone();
+        let mut followed = Vec::new();
+        loop {
+            if let Some(cut) = self.delegation(&qname, qtype) {
+                // AA goes with the first name of the answer, which a CNAME may have been
+                flags.aa = !res.answers.is_empty();
+                self.refer(&cut, &mut res);
+                break;
+            }
+            let Some(records) = self.lookup(&qname) else {
+                flags.rcode = RCODE_NXDOMAIN as u8;
+                res.add_authority(self.negative_soa());
+                break;
+            };
+
+            let cname = records.iter().find(|rr| rr.rr_type == TYPE_CNAME);
+            let any = RecordType::from(qtype) == RecordType::Any;
+            if let Some(cname) = cname.filter(|_| qtype != TYPE_CNAME && !any) {
+                res.add_answer(cname.clone());
+                let RData::CNAME(target) = &cname.data else {
+                    break;
+                };
+                let target = Name::from(target.as_str());
+                followed.push(qname);
+                if !self.contains(&target)
+                    || followed.contains(&target)
+                    || followed.len() > MAX_CNAME_CHAIN
+                {
+                    break;
+                }
+                qname = target;
+                continue;
+            }
+
+            let answers: Vec<_> = records
+                .into_iter()
+                .filter(|rr| any || rr.rr_type == qtype)
+                .collect();
+            if answers.is_empty() {
+                res.add_authority(self.negative_soa());
+            }
+            for rr in answers {
+                self.add_addresses_for(&rr.data, &mut res);
+                res.add_answer(rr);
+            }
+            break;
+        }
+        res.set_flags(flags);
+        res
+    }
+
+    // the records at `name`, renamed from the wildcard when one covers it. Empty for a name
+    // that only exists because of names below it, None for one that doesn't exist at all
+    fn lookup(&self, name: &Name) -> Option<Vec<ResourceRecord>> {
+        if let Some(records) = self.records.get(name) {
+            return Some(records.clone());
+        }
+        if self.names.contains(name) {
+            return Some(Vec::new());
+        }
+        // the wildcard is the one at the closest encloser, the nearest name above that exists
+        let mut encloser = name.parent()?;
+        while !self.names.contains(&encloser) {
+            encloser = encloser.parent()?;
+        }
+        let star = Name::from(if encloser.is_root() {
+            "*".to_string()
+        } else {
+            format!("*.{}", encloser)
+        });
+        let records = self.records.get(&star)?;
+        let renamed = records
+            .iter()
+            .map(|rr| ResourceRecord {
+                name: name.clone(),
+                ..rr.clone()
+            })
+            .collect();
+        Some(renamed)
+    }
+
+    // the delegation `name` is at or below, the one closest to the apex if there are several.
+    // The DS records of a child zone are ours to answer for, so DS at the cut itself isn't one
+    fn delegation(&self, name: &Name, qtype: u16) -> Option<Name> {
+        let mut between = Vec::new();
+        let mut current = name.clone();
+        while current != self.origin {
+            let parent = current.parent();
+            between.push(current);
+            current = parent?;
+        }
+        between.into_iter().rev().find(|cut| {
+            let ns = self
+                .records
+                .get(cut)
+                .is_some_and(|records| records.iter().any(|rr| rr.rr_type == TYPE_NS));
+            ns && !(cut == name && qtype == TYPE_DS)
+        })
+    }
+
+    // the NS records at `cut` (and its DS, if it's signed) and the glue for them
+    fn refer(&self, cut: &Name, res: &mut DnsMessage) {
+        let records = self.records.get(cut).cloned().unwrap_or_default();
+        for rr in records {
+            if rr.rr_type == TYPE_NS {
+                self.add_addresses_for(&rr.data, res);
+                res.add_authority(rr);
+            } else if rr.rr_type == TYPE_DS {
+                res.add_authority(rr);
+            }
+        }
+    }
+
+    // the A and AAAA records we have for the name an NS, MX or SRV record points at
+    fn add_addresses_for(&self, data: &RData, res: &mut DnsMessage) {
+        let target = match data {
+            RData::NS(target) => target,
+            RData::MX { exchange, .. } => exchange,
+            RData::SRV(srv) => &srv.target,
+            _ => return,
+        };
+        let Some(records) = self.records.get(&Name::from(target.as_str())) else {
+            return;
+        };
+        for rr in records {
+            if (rr.rr_type == TYPE_A || rr.rr_type == TYPE_AAAA) && !res.additional.contains(rr) {
+                res.add_additional(rr.clone());
+            }
+        }
+    }
+
+    // the SOA for a negative answer, with the TTL negative caching goes by (RFC 2308 section 3)
+    fn negative_soa(&self) -> ResourceRecord {
+        ResourceRecord {
+            ttl: self.soa.ttl.min(self.soa().minimum),
+            ..self.soa.clone()
+        }
+    }
+}
+
+// `name` with the trailing dot that makes it absolute in a zone file
+fn absolute(name: &Name) -> String {
+    if name.is_root() {
+        ".".to_string()
+    } else {
+        format!("{}.", name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse_zone;
+    use std::net::Ipv4Addr;
+
+    const ZONE: &str = "\
+$TTL 3600
+@       SOA     ns1 hostmaster 1 7200 900 1209600 300
+        NS      ns1
+        MX      10 mail
+ns1     A       192.0.2.1
+mail    A       192.0.2.2
+        AAAA    2001:db8::2
+www     CNAME   web.lab
+web.lab A       192.0.2.3
+alias   CNAME   elsewhere.example.net.
+*.apps  A       192.0.2.4
+known.apps TXT  \"here\"
+child   NS      ns.child
+        DS      12345 13 2 ABCDEF
+ns.child A      192.0.2.5
+";
+
+    fn zone() -> Zone {
+        Zone::new("example.com", parse_zone(ZONE, "example.com").unwrap()).unwrap()
+    }
+
+    fn ask(zone: &Zone, name: &str, qtype: RecordType) -> DnsMessage {
+        zone.answer(&DnsMessage::query(name).qtype(qtype).build())
+    }
+
+    #[test]
+    fn test_answers_and_negatives() {
+        let zone = zone();
+        assert_eq!(zone.soa().serial, 1);
+        assert_eq!(zone.records().next().unwrap().rr_type, TYPE_SOA);
+
+        let res = ask(&zone, "ns1.example.com", RecordType::A);
+        assert!(res.flags().aa);
+        assert_eq!(res.ipv4_addrs(), [Ipv4Addr::new(192, 0, 2, 1)]);
+        assert!(res.authority.is_empty());
+
+        // MX with the exchange's addresses
+        let res = ask(&zone, "example.com", RecordType::MX);
+        assert_eq!(res.answers.len(), 1);
+        assert_eq!(res.additional.len(), 2);
+
+        // NODATA, with the SOA at the negative TTL
+        let res = ask(&zone, "mail.example.com", RecordType::TXT);
+        assert_eq!(res.rcode(), 0);
+        assert!(res.answers.is_empty());
+        assert_eq!(res.authority[0].rr_type, TYPE_SOA);
+        assert_eq!(res.authority[0].ttl, 300);
+        // lab has nothing but web.lab below it
+        let res = ask(&zone, "lab.example.com", RecordType::A);
+        assert_eq!(res.rcode(), 0);
+        assert_eq!(res.authority[0].rr_type, TYPE_SOA);
+
+        let res = ask(&zone, "nope.example.com", RecordType::A);
+        assert!(res.flags().aa);
+        assert_eq!(res.rcode(), RCODE_NXDOMAIN);
+        assert_eq!(res.authority[0].rr_type, TYPE_SOA);
+
+        // a CNAME is followed inside the zone, and left for the resolver outside it
+        let res = ask(&zone, "www.example.com", RecordType::A);
+        assert_eq!(res.answers[0].rr_type, TYPE_CNAME);
+        assert_eq!(res.ipv4_addrs(), [Ipv4Addr::new(192, 0, 2, 3)]);
+        let res = ask(&zone, "alias.example.com", RecordType::A);
+        assert_eq!(res.answers.len(), 1);
+        assert_eq!(res.rcode(), 0);
+        let res = ask(&zone, "www.example.com", RecordType::CNAME);
+        assert_eq!(res.answers.len(), 1);
+    }
+
+    #[test]
+    fn test_wildcards() {
+        let zone = zone();
+        let res = ask(&zone, "a.b.apps.example.com", RecordType::A);
+        assert_eq!(res.answers[0].name, Name::from("a.b.apps.example.com"));
+        assert_eq!(res.ipv4_addrs(), [Ipv4Addr::new(192, 0, 2, 4)]);
+        // the wildcard has no TXT
+        let res = ask(&zone, "x.apps.example.com", RecordType::TXT);
+        assert_eq!(res.rcode(), 0);
+        assert!(res.answers.is_empty());
+        // a name that's there isn't covered, nor anything below it
+        let res = ask(&zone, "known.apps.example.com", RecordType::A);
+        assert_eq!(res.rcode(), 0);
+        assert!(res.answers.is_empty());
+        let res = ask(&zone, "x.known.apps.example.com", RecordType::A);
+        assert_eq!(res.rcode(), RCODE_NXDOMAIN);
+    }
+
+    #[test]
+    fn test_empty_non_terminals_follow_the_zone() {
+        let mut zone = zone();
+        let records = |text: &str| parse_zone(text, "example.com").unwrap();
+        let web = records("$TTL 3600\nweb.lab A 192.0.2.3\n");
+        let mut soa = records("$TTL 3600\n@ SOA ns1 hostmaster 1 7200 900 1209600 300\n");
+        soa.extend(web);
+        let mut added = records("$TTL 3600\n@ SOA ns1 hostmaster 2 7200 900 1209600 300\n");
+        added.extend(records("$TTL 3600\nx.y.z A 192.0.2.6\n"));
+        zone.apply(&ZoneDelta {
+            removed: soa,
+            added,
+        })
+        .unwrap();
+        // lab went with web.lab, and y.z came in with x.y.z
+        let res = ask(&zone, "lab.example.com", RecordType::A);
+        assert_eq!(res.rcode(), RCODE_NXDOMAIN);
+        for name in ["y.z.example.com", "z.example.com"] {
+            let res = ask(&zone, name, RecordType::A);
+            assert_eq!(res.rcode(), 0);
+            assert!(res.answers.is_empty());
+        }
+    }
+
+    #[test]
+    fn test_referrals() {
+        let zone = zone();
+        for name in ["child.example.com", "www.child.example.com"] {
+            let res = ask(&zone, name, RecordType::A);
+            assert!(!res.flags().aa);
+            assert_eq!(res.rcode(), 0);
+            assert!(res.answers.is_empty());
+            assert_eq!(res.authority[0].rr_type, TYPE_NS);
+            assert_eq!(res.authority[1].rr_type, TYPE_DS);
+            assert_eq!(
+                res.additional[0].data,
+                RData::A(Ipv4Addr::new(192, 0, 2, 5))
+            );
+        }
+        // the DS is ours
+        let res = ask(&zone, "child.example.com", RecordType::DS);
+        assert!(res.flags().aa);
+        assert_eq!(res.answers[0].rr_type, TYPE_DS);
+        // and the apex NS aren't a delegation
+        let res = ask(&zone, "example.com", RecordType::NS);
+        assert!(res.flags().aa);
+        assert_eq!(res.answers.len(), 1);
+    }
+
+    #[test]
+    fn test_zone_checks() {
+        let records = |text: &str| parse_zone(text, "example.com").unwrap();
+        let error = Zone::new("example.com", records("$TTL 60\nwww A 192.0.2.1\n")).unwrap_err();
+        assert_eq!(error.message, "zone example.com has no SOA");
+        let error = Zone::new(
+            "example.com",
+            records("$TTL 60\n@ SOA ns hm 1 2 3 4 5\nwww.example.net. A 192.0.2.1\n"),
+        )
+        .unwrap_err();
+        assert_eq!(error.message, "www.example.net is outside zone example.com");
+        let error = Zone::new(
+            "example.com",
+            records("$TTL 60\n@ SOA ns hm 1 2 3 4 5\nwww CNAME @\n A 192.0.2.1\n"),
+        )
+        .unwrap_err();
+        assert_eq!(error.message, "CNAME and other data at www.example.com");
+    }
+
+    #[test]
+    fn test_save_and_load() {
+        let zone = zone();
+        let path = std::env::temp_dir().join(format!("zone-{}.zone", std::process::id()));
+        zone.save(&path).unwrap();
+        let loaded = Zone::load(&path, "example.com").unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let sorted = |zone: &Zone| {
+            let mut records: Vec<(String, u16, u32, RData)> = zone
+                .records()
+                .map(|rr| (rr.name.to_string(), rr.rr_type, rr.ttl, rr.data.clone()))
+                .collect();
+            records.sort_by(|a, b| (&a.0, a.1).cmp(&(&b.0, b.1)));
+            records
+        };
+        assert_eq!(sorted(&loaded), sorted(&zone));
+    }
+}
@@ -0,0 +1,600 @@
+// zone transfers (AXFR, RFC 5936): ask a zone's server for everything in it. Always over TCP,
+// and the answer is a stream of messages rather than one: the zone's SOA, every other record in
+// the zone, and the SOA again to say that was all of it. Records are spread over the messages
+// however the server likes, so all we can do is keep reading until the second SOA shows up.
+//
+// IXFR (RFC 1995) asks for only what changed since the version we have, and comes back the same
+// way but with deltas between the SOAs. We ask it over TCP too
+use std::net::SocketAddr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::rdata::TYPE_SOA;
+use crate::tcp::{self, DnsFramed};
+use crate::ResponseCode;
+use crate::{DnsError, DnsMessage, Name, RData, RecordType, ResourceRecord, Soa, TsigError};
+use crate::{TsigKey, Zone};
+
+#[derive(Debug, Clone)]
+pub struct TransferOptions {
+    // for connecting, and then for each read. A big zone takes a while as a whole, but the
+    // server shouldn't go quiet between two messages
+    pub timeout: Duration,
+    // signs the query, and then the answer has to be signed with the same key (see tsig.rs)
+    pub tsig: Option<TsigKey>,
+}
+
+impl Default for TransferOptions {
+    fn default() -> Self {
+        TransferOptions {
+            timeout: Duration::from_secs(10),
+            tsig: None,
+        }
+    }
+}
+
+// a signed transfer can leave out the TSIG on up to 99 messages in a row (RFC 8945 5.3.1)
+const MAX_UNSIGNED_IN_A_ROW: usize = 99;
+
+// `axfr` with the default options
+pub fn transfer_zone(zone: &str, server: SocketAddr) -> Result<Vec<ResourceRecord>, DnsError> {
+    axfr(zone, server, &TransferOptions::default())
+}
+
+// every record in `zone`, in the order the server sent them. The SOA comes first and appears
+// only once, the copy closing the transfer is left off
+pub fn axfr(
+    zone: &str,
+    server: SocketAddr,
+    opts: &TransferOptions,
+) -> Result<Vec<ResourceRecord>, DnsError> {
+    let zone = Name::from(zone);
+    let query = DnsMessage::query(zone.clone())
+        .qtype(RecordType::AXFR)
+        .recursion_desired(false)
+        .build();
+
+    let mut records: Vec<ResourceRecord> = Vec::new();
+    receive(&query, server, opts, |rr, _| {
+        let is_soa = rr.rr_type == TYPE_SOA && rr.name == zone;
+        if records.is_empty() {
+            if !is_soa {
+                return Err(DnsError::Malformed(
+                    "zone transfer doesn't start with the zone's SOA",
+                ));
+            }
+        } else if is_soa {
+            return Ok(true);
+        }
+        records.push(rr);
+        Ok(false)
+    })?;
+    Ok(records)
+}
+
+// what an IXFR brought back
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ZoneTransfer {
+    // the serial we have is still the zone's
+    UpToDate,
+    // what changed since, one delta per version of the zone in between, oldest first
+    Incremental(Vec<ZoneDelta>),
+    // the whole zone, SOA first, as AXFR has it: the server didn't keep enough history for a
+    // delta (or doesn't do IXFR at all)
+    Full(Vec<ResourceRecord>),
+}
+
+// one step from a version of a zone to the next. The SOA is part of both: the old one is the
+// first record removed and the new one the first added (RFC 1995 section 4)
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ZoneDelta {
+    pub removed: Vec<ResourceRecord>,
+    pub added: Vec<ResourceRecord>,
+}
+
+// incremental zone transfer (IXFR, RFC 1995): what changed in `zone` since version `serial`.
+// A server that doesn't do IXFR answers NOTIMP or FORMERR, and then we ask it with AXFR instead
+pub fn ixfr(
+    zone: &str,
+    serial: u32,
+    server: SocketAddr,
+    opts: &TransferOptions,
+) -> Result<ZoneTransfer, DnsError> {
+    let name = Name::from(zone);
+    let mut query = DnsMessage::query(name.clone())
+        .qtype(RecordType::IXFR)
+        .recursion_desired(false)
+        .build();
+    // the version we have goes in the authority section, only its serial matters
+    query.add_authority(ResourceRecord::new(
+        name.clone(),
+        0,
+        RData::SOA(Soa {
+            mname: String::new(),
+            rname: String::new(),
+            serial,
+            refresh: 0,
+            retry: 0,
+            expire: 0,
+            minimum: 0,
+        }),
+    ));
+
+    let mut reader = IxfrReader::new(name, serial);
+    match receive(&query, server, opts, |rr, last| reader.take(rr, last)) {
+        Ok(()) => Ok(reader.finish()),
+        Err(DnsError::Rcode(rcode) | DnsError::Extended { rcode, .. })
+            if matches!(
+                ResponseCode::from(rcode),
+                ResponseCode::FormErr | ResponseCode::NotImp
+            ) =>
+        {
+            Ok(ZoneTransfer::Full(axfr(zone, server, opts)?))
+        }
+        Err(e) => Err(e),
+    }
+}
+
+// brings `zone` up to date with the copy at `server`: the deltas since its serial applied one
+// by one, or the whole zone again when that's what the server sends (or when a delta doesn't
+// fit the zone we have, which means our copy isn't what the server thinks it is). Whether
+// anything changed
+pub fn update_zone(
+    zone: &mut Zone,
+    server: SocketAddr,
+    opts: &TransferOptions,
+) -> Result<bool, DnsError> {
+    let origin = zone.origin().to_string();
+    let records = match ixfr(&origin, zone.soa().serial, server, opts)? {
+        ZoneTransfer::UpToDate => return Ok(false),
+        ZoneTransfer::Incremental(deltas) => {
+            let mut updated = zone.clone();
+            if deltas.iter().all(|delta| updated.apply(delta).is_ok()) {
+                *zone = updated;
+                return Ok(true);
+            }
+            axfr(&origin, server, opts)?
+        }
+        ZoneTransfer::Full(records) => records,
+    };
+    *zone = Zone::new(zone.origin().clone(), records)
+        .map_err(|_| DnsError::Malformed("transferred zone doesn't hold together"))?;
+    Ok(true)
+}
+
+// where we are in an IXFR response: after the opening SOA comes either the rest of the zone
+// (AXFR style) or deltas, each the old SOA, what's gone, the new SOA and what's new
+enum IxfrState {
+    Start,
+    // the opening SOA, with nothing after it yet
+    Opened,
+    Full,
+    Removing,
+    Adding,
+}
+
+struct IxfrReader {
+    zone: Name,
+    ours: u32,
+    // the zone's current serial, from the opening SOA
+    current: Option<ResourceRecord>,
+    state: IxfrState,
+    records: Vec<ResourceRecord>,
+    deltas: Vec<ZoneDelta>,
+    up_to_date: bool,
+}
+
+impl IxfrReader {
+    fn new(zone: Name, ours: u32) -> Self {
+        IxfrReader {
+            zone,
+            ours,
+            current: None,
+            state: IxfrState::Start,
+            records: Vec::new(),
+            deltas: Vec::new(),
+            up_to_date: false,
+        }
+    }
+
+    // takes one record, `last` in its message. Whether that was the end of the transfer
+    fn take(&mut self, rr: ResourceRecord, last: bool) -> Result<bool, DnsError> {
+        let serial = match &rr.data {
+            RData::SOA(soa) if rr.name == self.zone => Some(soa.serial),
+            _ => None,
+        };
+        let current = self
+            .current
+            .as_ref()
+            .and_then(|current| match &current.data {
+                RData::SOA(soa) => Some(soa.serial),
+                _ => None,
+            });
+        match self.state {
+            IxfrState::Start => {
+                let Some(serial) = serial else {
+                    return Err(DnsError::Malformed(
+                        "zone transfer doesn't start with the zone's SOA",
+                    ));
+                };
+                self.current = Some(rr);
+                self.state = IxfrState::Opened;
+                // nothing but the SOA, and no newer than ours (in RFC 1982 serial arithmetic):
+                // there's nothing to send us
+                if last && (serial.wrapping_sub(self.ours) as i32) <= 0 {
+                    self.up_to_date = true;
+                    return Ok(true);
+                }
+            }
+            IxfrState::Opened => match serial {
+                // the SOA of the version we have, starting the first delta
+                Some(_) => {
+                    self.deltas.push(ZoneDelta {
+                        removed: vec![rr],
+                        added: Vec::new(),
+                    });
+                    self.state = IxfrState::Removing;
+                }
+                None => {
+                    self.records.extend(self.current.clone());
+                    self.records.push(rr);
+                    self.state = IxfrState::Full;
+                }
+            },
+            IxfrState::Full => {
+                if serial.is_some() {
+                    return Ok(true);
+                }
+                self.records.push(rr);
+            }
+            IxfrState::Removing => {
+                let delta = self.deltas.last_mut().unwrap();
+                if serial.is_some() {
+                    self.state = IxfrState::Adding;
+                    delta.added.push(rr);
+                } else {
+                    delta.removed.push(rr);
+                }
+            }
+            IxfrState::Adding => {
+                // the current version's SOA began the last delta's additions, seeing it again
+                // closes the transfer
+                if serial.is_some() && serial == current {
+                    return Ok(true);
+                }
+                if serial.is_some() {
+                    self.deltas.push(ZoneDelta {
+                        removed: vec![rr],
+                        added: Vec::new(),
+                    });
+                    self.state = IxfrState::Removing;
+                } else {
+                    self.deltas.last_mut().unwrap().added.push(rr);
+                }
+            }
+        }
+        Ok(false)
+    }
+
+    fn finish(self) -> ZoneTransfer {
+        if self.up_to_date {
+            ZoneTransfer::UpToDate
+        } else if matches!(self.state, IxfrState::Full) {
+            ZoneTransfer::Full(self.records)
+        } else {
+            ZoneTransfer::Incremental(self.deltas)
+        }
+    }
+}
+
+// sends `query` to `server` over TCP and hands every record of the answers streaming back to
+// `take`, along with whether it's the last of its message, until `take` says that was all. With
+// a TSIG key the query goes out signed, and the first and last messages back have to be signed
+// too, with none more than 99 messages after the one before
+fn receive(
+    query: &DnsMessage,
+    server: SocketAddr,
+    opts: &TransferOptions,
+    mut take: impl FnMut(ResourceRecord, bool) -> Result<bool, DnsError>,
+) -> Result<(), DnsError> {
+    let mut query = query.clone();
+    let mut mac = match &opts.tsig {
+        Some(key) => Some(key.sign(&mut query, None, now())?),
+        None => None,
+    };
+    let mut stream = DnsFramed::new(tcp::connect(server, opts.timeout)?);
+    stream.write_message(&query.to_bytes()?)?;
+
+    let mut first = true;
+    // the messages since the last signed one
+    let mut unsigned = Vec::new();
+    let mut unsigned_count = 0;
+    loop {
+        let packet = stream
+            .read_message()
+            .and_then(|packet| packet.ok_or(std::io::ErrorKind::UnexpectedEof.into()))
+            .map_err(|e| {
+                if e.kind() == std::io::ErrorKind::UnexpectedEof {
+                    DnsError::Malformed("zone transfer ended before the closing SOA")
+                } else {
+                    e.into()
+                }
+            })?;
+        if let (Some(key), Some(mac)) = (&opts.tsig, mac.as_mut()) {
+            if first {
+                *mac = key.verify(packet, Some(mac), now())?;
+            } else if let Some(next) = key.verify_next(packet, mac, &unsigned, now())? {
+                *mac = next;
+                unsigned.clear();
+                unsigned_count = 0;
+            } else if unsigned_count == MAX_UNSIGNED_IN_A_ROW {
+                return Err(TsigError::Unsigned.into());
+            } else {
+                unsigned.extend_from_slice(packet);
+                unsigned_count += 1;
+            }
+        }
+        let msg = DnsMessage::from_bytes(packet)?;
+        // only the first message has to repeat the question, the ID is on every one of them
+        if msg.header.identification != query.header.identification
+            || (first && !msg.questions.is_empty() && msg.questions != query.questions)
+        {
+            return Err(DnsError::Malformed(
+                "zone transfer message doesn't match the query",
+            ));
+        }
+        // REFUSED (or NOTAUTH) is what servers say when we aren't allowed to transfer the zone
+        if msg.rcode() != 0 {
+            return Err(DnsError::from_response(&msg));
+        }
+        first = false;
+
+        let count = msg.answers.len();
+        for (i, rr) in msg.answers.into_iter().enumerate() {
+            if take(rr, i + 1 == count)? {
+                // the end of it has to be signed, or anything could have been left off
+                if unsigned_count > 0 {
+                    return Err(TsigError::Unsigned.into());
+                }
+                return Ok(());
+            }
+        }
+    }
+}
+
+pub(crate) fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.as_secs())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{mock_tcp_server, mock_tcp_server_for};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    fn soa() -> ResourceRecord {
+        soa_at(2024010101)
+    }
+
+    fn soa_at(serial: u32) -> ResourceRecord {
+        ResourceRecord::new(
+            "example.com",
+            3600,
+            RData::SOA(Soa {
+                mname: "ns1.example.com".to_string(),
+                rname: "hostmaster.example.com".to_string(),
+                serial,
+                refresh: 7200,
+                retry: 900,
+                expire: 1209600,
+                minimum: 300,
+            }),
+        )
+    }
+
+    // the zone in two messages, split in the middle of the records
+    fn stream_zone(query: &[u8]) -> Vec<Vec<u8>> {
+        let query = DnsMessage::from_bytes(query).unwrap();
+        let mut first = DnsMessage::response_to(&query);
+        first.add_answer(soa());
+        first.add_answer(ResourceRecord::new(
+            "example.com",
+            3600,
+            RData::NS("ns1.example.com".to_string()),
+        ));
+        first.add_answer(ResourceRecord::new(
+            "www.example.com",
+            300,
+            RData::A([192, 0, 2, 1].into()),
+        ));
+
+        let mut second = DnsMessage::response_to(&query);
+        second.questions.clear();
+        second.add_answer(ResourceRecord::new(
+            "mail.example.com",
+            300,
+            RData::A([192, 0, 2, 2].into()),
+        ));
+        second.add_answer(soa());
+        vec![first.to_bytes().unwrap(), second.to_bytes().unwrap()]
+    }
+
+    #[test]
+    fn test_axfr_collects_records_across_messages() {
+        let server = mock_tcp_server(|query| {
+            let parsed = DnsMessage::from_bytes(query).unwrap();
+            assert_eq!(parsed.question().qtype, 252);
+            assert!(!parsed.recursion_desired());
+            stream_zone(query)
+        });
+
+        let records = axfr("example.com", server, &TransferOptions::default()).unwrap();
+        let names: Vec<&str> = records.iter().map(|rr| rr.name.as_str()).collect();
+        assert_eq!(
+            names,
+            [
+                "example.com",
+                "example.com",
+                "www.example.com",
+                "mail.example.com"
+            ]
+        );
+        assert_eq!(records[0].data, soa().data);
+        assert_eq!(records[3].data, RData::A([192, 0, 2, 2].into()));
+    }
+
+    #[test]
+    fn test_axfr_without_closing_soa_is_an_error() {
+        let server = mock_tcp_server(|query| {
+            let mut zone = stream_zone(query);
+            zone.pop();
+            zone
+        });
+        let err = axfr("example.com", server, &TransferOptions::default()).unwrap_err();
+        assert!(matches!(err, DnsError::Malformed(_)));
+    }
+
+    #[test]
+    fn test_axfr_refused() {
+        let server = mock_tcp_server(|query| {
+            let query = DnsMessage::from_bytes(query).unwrap();
+            let mut res = DnsMessage::response_to(&query);
+            res.header.flags |= 5; // REFUSED
+            vec![res.to_bytes().unwrap()]
+        });
+        let err = transfer_zone("example.com", server).unwrap_err();
+        assert!(matches!(err, DnsError::Rcode(5)));
+    }
+
+    fn a(name: &str, last: u8) -> ResourceRecord {
+        ResourceRecord::new(name, 300, RData::A([192, 0, 2, last].into()))
+    }
+
+    fn reply(query: &[u8], records: Vec<ResourceRecord>) -> Vec<u8> {
+        let mut res = DnsMessage::response_to(&DnsMessage::from_bytes(query).unwrap());
+        for rr in records {
+            res.add_answer(rr);
+        }
+        res.to_bytes().unwrap()
+    }
+
+    #[test]
+    fn test_ixfr_applies_deltas() {
+        // version 1 to 3 in two steps, over two messages
+        let server = mock_tcp_server(|query| {
+            let parsed = DnsMessage::from_bytes(query).unwrap();
+            assert_eq!(parsed.question().qtype, 251);
+            assert!(matches!(&parsed.authority[0].data, RData::SOA(soa) if soa.serial == 1));
+            vec![
+                reply(
+                    query,
+                    vec![soa_at(3), soa_at(1), a("www.example.com", 1), soa_at(2)],
+                ),
+                reply(
+                    query,
+                    vec![
+                        a("www.example.com", 3),
+                        soa_at(2),
+                        soa_at(3),
+                        a("mail.example.com", 4),
+                        soa_at(3),
+                    ],
+                ),
+            ]
+        });
+        let mut zone = Zone::new(
+            "example.com",
+            vec![soa_at(1), a("www.example.com", 1), a("ftp.example.com", 9)],
+        )
+        .unwrap();
+
+        assert!(update_zone(&mut zone, server, &TransferOptions::default()).unwrap());
+        assert_eq!(zone.soa().serial, 3);
+        let mut records: Vec<String> = zone
+            .records()
+            .map(|rr| format!("{} {}", rr.name, rr.data))
+            .collect();
+        records.sort();
+        assert_eq!(records.len(), 4);
+        assert!(records.contains(&"www.example.com 192.0.2.3".to_string()));
+        assert!(records.contains(&"mail.example.com 192.0.2.4".to_string()));
+        assert!(records.contains(&"ftp.example.com 192.0.2.9".to_string()));
+    }
+
+    #[test]
+    fn test_ixfr_up_to_date_full_and_fallback() {
+        // up to date, then a full zone in the IXFR answer, then NOTIMP and the AXFR after it
+        let connection = AtomicUsize::new(0);
+        let server = mock_tcp_server_for(4, move |query| {
+            let qtype = DnsMessage::from_bytes(query).unwrap().question().qtype;
+            match connection.fetch_add(1, Ordering::SeqCst) {
+                0 => vec![reply(query, vec![soa_at(5)])],
+                1 => vec![reply(
+                    query,
+                    vec![soa_at(6), a("www.example.com", 6), soa_at(6)],
+                )],
+                2 => {
+                    assert_eq!(qtype, 251);
+                    let mut res = DnsMessage::from_bytes(&reply(query, Vec::new())).unwrap();
+                    res.header.flags |= 4; // NOTIMP
+                    vec![res.to_bytes().unwrap()]
+                }
+                _ => {
+                    assert_eq!(qtype, 252);
+                    vec![reply(
+                        query,
+                        vec![soa_at(7), a("www.example.com", 7), soa_at(7)],
+                    )]
+                }
+            }
+        });
+        let opts = TransferOptions::default();
+
+        assert_eq!(
+            ixfr("example.com", 5, server, &opts).unwrap(),
+            ZoneTransfer::UpToDate
+        );
+        let ZoneTransfer::Full(records) = ixfr("example.com", 5, server, &opts).unwrap() else {
+            panic!("not the full zone");
+        };
+        let data: Vec<RData> = records.into_iter().map(|rr| rr.data).collect();
+        assert_eq!(data, [soa_at(6).data, a("www.example.com", 6).data]);
+        let mut zone = Zone::new("example.com", vec![soa_at(5)]).unwrap();
+        assert!(update_zone(&mut zone, server, &opts).unwrap());
+        assert_eq!(zone.soa().serial, 7);
+        assert_eq!(zone.records().count(), 2);
+    }
+
+    #[test]
+    fn test_signed_transfer() {
+        let key = TsigKey::new("transfer-key", &b"0123456789abcdef0123456789abcdef"[..]);
+        // the first connection gets the whole zone signed, the second has its end unsigned
+        let connection = AtomicUsize::new(0);
+        let server_key = key.clone();
+        let server = mock_tcp_server_for(2, move |query| {
+            let request_mac = server_key.verify(query, None, now()).unwrap();
+            let mut messages: Vec<DnsMessage> = stream_zone(query)
+                .iter()
+                .map(|bytes| DnsMessage::from_bytes(bytes).unwrap())
+                .collect();
+            let mac = server_key
+                .sign(&mut messages[0], Some(&request_mac), now())
+                .unwrap();
+            if connection.fetch_add(1, Ordering::SeqCst) == 0 {
+                server_key
+                    .sign_next(&mut messages[1], &mac, &[], now())
+                    .unwrap();
+            }
+            messages.iter().map(|msg| msg.to_bytes().unwrap()).collect()
+        });
+        let opts = TransferOptions {
+            tsig: Some(key),
+            ..TransferOptions::default()
+        };
+
+        assert_eq!(axfr("example.com", server, &opts).unwrap().len(), 4);
+        let err = axfr("example.com", server, &opts).unwrap_err();
+        assert!(matches!(err, DnsError::Tsig(TsigError::Unsigned)));
+    }
+}
@@ -0,0 +1,232 @@
+// Pi-hole style blocking: names from ad and tracker lists get a made-up answer instead of the
+// real one, so nothing on the network ever reaches them. A blocked name takes everything below
+// it along (blocking doubleclick.net blocks ad.doubleclick.net too).
+//
+// Lists come in two shapes and we take both, line by line: hosts files pointing the names at
+// 0.0.0.0 (the address is ignored, and so are the localhost lines every such file starts
+// with), and plain lists of one domain per line. # starts a comment in either, and a leading
+// "*." is dropped since every entry covers its subdomains anyway
+use std::collections::HashSet;
+use std::fs;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::path::Path;
+
+use crate::{
+    DnsMessage, Edns, ExtendedError, Name, RData, RecordType, ResourceRecord, RCODE_NXDOMAIN,
+};
+
+// short, so a name taken off the list works again soon for clients that cached the block
+pub const BLOCKED_TTL: u32 = 10;
+
+// the Extended DNS Error (RFC 8914) a blocked answer carries for clients with EDNS
+const EDE_BLOCKED: u16 = 15;
+
+// the names hosts-format lists have for the machine itself, which aren't meant as blocks
+const HOSTS_BOILERPLATE: &[&str] = &[
+    "localhost",
+    "localhost.localdomain",
+    "local",
+    "broadcasthost",
+    "ip6-localhost",
+    "ip6-loopback",
+    "ip6-localnet",
+    "ip6-mcastprefix",
+    "ip6-allnodes",
+    "ip6-allrouters",
+    "ip6-allhosts",
+    "0.0.0.0",
+];
+
+// what a blocked name gets
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum BlockAction {
+    // "no such name"
+    #[default]
+    NxDomain,
+    // these addresses for A and AAAA (and nothing for anything else): a box that shows a
+    // "blocked" page, or 0.0.0.0 and :: so the client fails fast without retrying elsewhere
+    Sinkhole {
+        v4: Ipv4Addr,
+        v6: Ipv6Addr,
+    },
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Blocklist {
+    domains: HashSet<Name>,
+    action: BlockAction,
+}
+
+impl Blocklist {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn set_action(&mut self, action: BlockAction) {
+        self.action = action;
+    }
+
+    pub fn action(&self) -> BlockAction {
+        self.action
+    }
+
+    // blocks `name` and everything below it
+    pub fn add(&mut self, name: impl Into<Name>) {
+        self.domains.insert(name.into());
+    }
+
+    // the names in a list, in either format. How many there were
+    pub fn add_list(&mut self, text: &str) -> usize {
+        let before = self.domains.len();
+        for line in text.lines() {
+            let line = line.split('#').next().unwrap_or("");
+            let mut words = line.split_whitespace();
+            let Some(first) = words.next() else {
+                continue;
+            };
+            let names: Vec<&str> = if first.parse::<IpAddr>().is_ok() {
+                words
+                    .filter(|name| !HOSTS_BOILERPLATE.contains(&name.to_ascii_lowercase().as_str()))
+                    .collect()
+            } else {
+                vec![first]
+            };
+            for name in names {
+                let name = name.strip_prefix("*.").unwrap_or(name);
+                if !name.is_empty() {
+                    self.add(name);
+                }
+            }
+        }
+        self.domains.len() - before
+    }
+
+    // the names in the list at `path`
+    pub fn load(&mut self, path: impl AsRef<Path>) -> io::Result<usize> {
+        Ok(self.add_list(&fs::read_to_string(path)?))
+    }
+
+    pub fn len(&self) -> usize {
+        self.domains.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.domains.is_empty()
+    }
+
+    // whether `name`, or a name it's under, is on the list
+    pub fn is_blocked(&self, name: &Name) -> bool {
+        let mut name = name.clone();
+        loop {
+            if self.domains.contains(&name) {
+                return true;
+            }
+            match name.parent() {
+                Some(parent) if !parent.is_root() => name = parent,
+                _ => return false,
+            }
+        }
+    }
+
+    // the made-up answer to `query` when its name is blocked, None when it isn't
+    pub fn answer(&self, query: &DnsMessage) -> Option<DnsMessage> {
+        let qname = &query.question().qname;
+        if !self.is_blocked(qname) {
+            return None;
+        }
+        let mut res = DnsMessage::response_to(query);
+        let mut flags = res.flags();
+        flags.ra = true;
+        match self.action {
+            BlockAction::NxDomain => flags.rcode = RCODE_NXDOMAIN as u8,
+            BlockAction::Sinkhole { v4, v6 } => {
+                let qtype = RecordType::from(query.question().qtype);
+                if matches!(qtype, RecordType::A | RecordType::Any) {
+                    res.add_answer(ResourceRecord::new(
+                        qname.clone(),
+                        BLOCKED_TTL,
+                        RData::A(v4),
+                    ));
+                }
+                if matches!(qtype, RecordType::AAAA | RecordType::Any) {
+                    res.add_answer(ResourceRecord::new(
+                        qname.clone(),
+                        BLOCKED_TTL,
+                        RData::AAAA(v6),
+                    ));
+                }
+            }
+        }
+        res.set_flags(flags);
+        if let Some(client) = &query.edns {
+            let mut edns = Edns::new(client.udp_payload);
+            edns.options
+                .push(ExtendedError::new(EDE_BLOCKED, "").to_option());
+            res.edns = Some(edns);
+        }
+        Some(res)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LISTS: &str = "\
+# hosts format
+127.0.0.1 localhost
+0.0.0.0 0.0.0.0
+0.0.0.0 ads.example.com tracker.example.net  # two on one line
+::1 ip6-localhost
+# one per line
+doubleclick.net
+*.metrics.example
+";
+
+    #[test]
+    fn test_lists_and_suffixes() {
+        let mut blocklist = Blocklist::new();
+        assert_eq!(blocklist.add_list(LISTS), 4);
+        for blocked in [
+            "ads.example.com",
+            "Tracker.Example.Net.",
+            "doubleclick.net",
+            "ad.g.doubleclick.net",
+            "metrics.example",
+            "eu.metrics.example",
+        ] {
+            assert!(blocklist.is_blocked(&Name::from(blocked)), "{}", blocked);
+        }
+        for allowed in ["example.com", "localhost", "notdoubleclick.net", "net"] {
+            assert!(!blocklist.is_blocked(&Name::from(allowed)), "{}", allowed);
+        }
+    }
+
+    #[test]
+    fn test_answers() {
+        let mut blocklist = Blocklist::new();
+        blocklist.add("ads.example.com");
+        let query = DnsMessage::query("x.ads.example.com")
+            .edns(Edns::new(1232))
+            .build();
+        let res = blocklist.answer(&query).unwrap();
+        assert_eq!(res.rcode(), RCODE_NXDOMAIN);
+        assert_eq!(res.extended_errors(), [ExtendedError::new(15, "")]);
+        assert!(blocklist
+            .answer(&DnsMessage::query("example.com").build())
+            .is_none());
+
+        blocklist.set_action(BlockAction::Sinkhole {
+            v4: Ipv4Addr::UNSPECIFIED,
+            v6: Ipv6Addr::UNSPECIFIED,
+        });
+        let res = blocklist.answer(&query).unwrap();
+        assert_eq!(res.rcode(), 0);
+        assert_eq!(res.ipv4_addrs(), [Ipv4Addr::UNSPECIFIED]);
+        let mx = DnsMessage::query("ads.example.com")
+            .qtype(RecordType::MX)
+            .build();
+        assert!(blocklist.answer(&mx).unwrap().answers.is_empty());
+    }
+}
@@ -0,0 +1,706 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::{
+    ClientSubnet, DnsMessage, DnsQuestion, Edns, Name, RData, RecordType, ResourceRecord,
+    RCODE_NXDOMAIN,
+};
+
+// answers we already have, keyed by the question they answer and kept until the smallest TTL
+// among them runs out. "No such name" and "no such record" answers are kept too (RFC 2308), for
+// as long as the zone's SOA says. A Resolver given one (see Resolver::set_cache) checks it before
+// asking upstream and fills it with what comes back. Everything goes through a Mutex, so one
+// cache can be shared between threads (and resolvers) through a plain &DnsCache.
+//
+// An answer that came with an EDNS Client Subnet scope (RFC 7871 7.3) is only for clients in
+// that network: it's kept under the scope, and only handed out to queries for a subnet inside
+// it. The most specific answer wins, and the ones with no scope at all are for everybody.
+//
+// An answer fetched with DO is kept apart from one fetched without: the one without has no
+// RRSIGs, and a client asking with DO (or a validator) needs them. An answer to a query with CD
+// set isn't kept at all, it's whatever the upstream had, checked or not, and the next client
+// may well want it checked. The AD bit the upstream set is kept with the answer.
+//
+// It holds max_entries at most (DEFAULT_CACHE_ENTRIES unless given): a server caching whatever
+// its clients ask about would otherwise keep every name it was ever asked for. When it's full
+// the entries that expired go first, then the ones closest to expiring
+#[derive(Debug)]
+pub struct DnsCache {
+    entries: Mutex<HashMap<CacheKey, CacheEntry>>,
+    max_entries: usize,
+}
+
+pub const DEFAULT_CACHE_ENTRIES: usize = 10_000;
+
+// name, qtype, qclass, DO and the client subnet scope, if any. Name compares case-insensitively,
+// so Example.COM hits example.com
+type CacheKey = (Name, u16, u16, bool, Option<ClientSubnet>);
+
+#[derive(Debug, Clone)]
+struct CacheEntry {
+    // NXDOMAIN for a name that doesn't exist, 0 for everything else
+    rcode: u16,
+    // whether the upstream vouched for it with AD
+    authentic: bool,
+    answers: Vec<ResourceRecord>,
+    authority: Vec<ResourceRecord>,
+    additional: Vec<ResourceRecord>,
+    // wall clock rather than Instant, it has to mean the same thing after a restart
+    expires: SystemTime,
+}
+
+// what a saved cache file starts with: "DNSC" and a format version
+const FILE_MAGIC: &[u8; 5] = b"DNSC\x01";
+
+impl Default for DnsCache {
+    fn default() -> Self {
+        Self::with_max_entries(DEFAULT_CACHE_ENTRIES)
+    }
+}
+
+impl DnsCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_max_entries(max_entries: usize) -> Self {
+        DnsCache {
+            entries: Mutex::default(),
+            max_entries: max_entries.max(1),
+        }
+    }
+
+    // the records stay for as long as the shortest TTL among them says
+    pub fn insert(&self, question: &DnsQuestion, records: Vec<ResourceRecord>) {
+        let entry = CacheEntry {
+            rcode: 0,
+            authentic: false,
+            answers: records,
+            authority: Vec::new(),
+            additional: Vec::new(),
+            expires: SystemTime::now(),
+        };
+        self.insert_fresh(key(question, false, None), entry, u32::MAX);
+    }
+
+    // a whole response, all three sections of it, under the question it answers and whether
+    // `query` asked with DO. The additional section counts towards the expiry too, glue that's
+    // gone stale takes the answer with it. A negative answer (NXDOMAIN, or NODATA: no error but
+    // nothing of the type asked for) lasts as long as the SOA in its authority section allows,
+    // and isn't kept at all without one. Nor is anything `query` asked for with CD
+    pub fn insert_response(&self, query: &DnsMessage, res: &DnsMessage) {
+        if query.checking_disabled() {
+            return;
+        }
+        let mut entry = CacheEntry {
+            rcode: res.rcode(),
+            authentic: res.authentic_data(),
+            answers: res.answers.clone(),
+            authority: res.authority.clone(),
+            additional: res.additional.clone(),
+            expires: SystemTime::now(),
+        };
+        let mut limit = u32::MAX;
+        if is_negative(res) {
+            let Some(ttl) = negative_ttl(&entry.authority) else {
+                return;
+            };
+            // the SOA goes out with the negative TTL as its own, which is how whoever we hand it
+            // to knows how long to keep the answer
+            for rr in &mut entry.authority {
+                if let RData::SOA(_) = rr.data {
+                    rr.ttl = ttl;
+                }
+            }
+            limit = ttl;
+        }
+        let key = key(res.question(), dnssec_ok(query), scope_of(res));
+        self.insert_fresh(key, entry, limit);
+    }
+
+    // expiring when the shortest TTL in `entry` (or `limit`, if that's sooner) runs out
+    fn insert_fresh(&self, key: CacheKey, mut entry: CacheEntry, limit: u32) {
+        let ttl = entry.records().map(|rr| rr.ttl).min().unwrap_or(0);
+        entry.expires = SystemTime::now() + Duration::from_secs(ttl.min(limit) as u64);
+        self.insert_entry(key, entry);
+    }
+
+    // a new key in a full cache has to make room: sweeping out everything that expired (which
+    // otherwise only goes when its question comes up again) and, if that wasn't enough, dropping
+    // whatever has the least time left
+    fn insert_entry(&self, key: CacheKey, entry: CacheEntry) {
+        let mut entries = self.entries.lock().unwrap();
+        if entries.len() >= self.max_entries && !entries.contains_key(&key) {
+            let now = SystemTime::now();
+            entries.retain(|_, entry| entry.expires > now);
+            while entries.len() >= self.max_entries {
+                let Some(soonest) = entries
+                    .iter()
+                    .min_by_key(|(_, entry)| entry.expires)
+                    .map(|(key, _)| key.clone())
+                else {
+                    break;
+                };
+                entries.remove(&soonest);
+            }
+        }
+        entries.insert(key, entry);
+    }
+
+    // the cached answers with their TTLs counted down to what's left of them, or None if we
+    // have nothing (or nothing fresh) for this question. A cached NXDOMAIN gives None as well,
+    // get_response is the one that can tell those apart
+    pub fn get(&self, question: &DnsQuestion) -> Option<Vec<ResourceRecord>> {
+        self.fresh(question, false, None)
+            .filter(|entry| entry.rcode == 0)
+            .map(|entry| entry.answers)
+    }
+
+    // what we'd have got asking upstream again: a response to `query` with its ID and question,
+    // the cached rcode, AD and sections, TTLs counted down like get does. Only what was fetched
+    // with DO answers a query with DO. Answers scoped to a client subnet count when the query's
+    // own client subnet option is inside it
+    pub fn get_response(&self, query: &DnsMessage) -> Option<DnsMessage> {
+        let subnet = query.edns.as_ref().and_then(Edns::client_subnet);
+        self.get_response_for(query, subnet.as_ref())
+    }
+
+    // get_response as if `query` were for `subnet`, for a resolver that adds the option later
+    pub fn get_response_for(
+        &self,
+        query: &DnsMessage,
+        subnet: Option<&ClientSubnet>,
+    ) -> Option<DnsMessage> {
+        let entry = self.fresh(query.question(), dnssec_ok(query), subnet)?;
+        let mut res = DnsMessage::response_to(query);
+        // it came from a server that recursed for us in the first place
+        let mut flags = res.flags();
+        flags.ra = true;
+        flags.rcode = entry.rcode as u8;
+        flags.ad = entry.authentic;
+        res.set_flags(flags);
+        for rr in entry.answers {
+            res.add_answer(rr);
+        }
+        for rr in entry.authority {
+            res.add_authority(rr);
+        }
+        for rr in entry.additional {
+            res.add_additional(rr);
+        }
+        Some(res)
+    }
+
+    // a copy of the entry for `question` with the TTLs counted down, dropping any that expired.
+    // For a client subnet, the entries scoped to it are tried from the longest prefix down before
+    // the one for everybody
+    fn fresh(
+        &self,
+        question: &DnsQuestion,
+        dnssec_ok: bool,
+        subnet: Option<&ClientSubnet>,
+    ) -> Option<CacheEntry> {
+        let mut entries = self.entries.lock().unwrap();
+        let scopes = subnet.map_or(0, |subnet| subnet.source_prefix);
+        let scopes = (1..=scopes)
+            .rev()
+            .map(|prefix| Some(ClientSubnet::new(subnet.unwrap().addr, prefix)))
+            .chain([None]);
+        for scope in scopes {
+            let key = key(question, dnssec_ok, scope);
+            let Some(entry) = entries.get(&key) else {
+                continue;
+            };
+            let remaining = match entry.expires.duration_since(SystemTime::now()) {
+                Ok(left) if !left.is_zero() => left.as_secs() as u32,
+                _ => {
+                    entries.remove(&key);
+                    continue;
+                }
+            };
+            let mut entry = entry.clone();
+            for rr in entry.records_mut() {
+                rr.ttl = rr.ttl.min(remaining);
+            }
+            return Some(entry);
+        }
+        None
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    // the file is FILE_MAGIC and then one entry after the other: when it expires (seconds since
+    // the epoch, u64), how long the entry is (u32), and the entry itself as a DNS message with
+    // the question as its question and the cached sections as its own. DO goes in an OPT record,
+    // along with the scope of a scoped entry in a client subnet option, and AD in the header.
+    // Written to a temporary file first and renamed over `path`, so a crash halfway through
+    // doesn't leave a torn cache behind
+    pub fn save_to(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let mut bytes = FILE_MAGIC.to_vec();
+        for (key, entry) in self.entries.lock().unwrap().iter() {
+            let mut msg = DnsMessage::query(key.0.clone()).build();
+            msg.questions[0].qtype = key.1;
+            msg.questions[0].qclass = key.2;
+            if key.3 || key.4.is_some() {
+                let mut edns = Edns::new(0);
+                edns.dnssec_ok = key.3;
+                if let Some(scope) = &key.4 {
+                    edns.options.push(
+                        ClientSubnet {
+                            scope_prefix: scope.source_prefix,
+                            ..*scope
+                        }
+                        .to_option(),
+                    );
+                }
+                msg.edns = Some(edns);
+            }
+            let mut flags = msg.flags();
+            flags.rcode = entry.rcode as u8;
+            flags.ad = entry.authentic;
+            msg.set_flags(flags);
+            entry
+                .answers
+                .iter()
+                .for_each(|rr| msg.add_answer(rr.clone()));
+            entry
+                .authority
+                .iter()
+                .for_each(|rr| msg.add_authority(rr.clone()));
+            entry
+                .additional
+                .iter()
+                .for_each(|rr| msg.add_additional(rr.clone()));
+            let expires = entry
+                .expires
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |since| since.as_secs());
+            let msg = msg
+                .to_bytes()
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+            bytes.extend(&expires.to_be_bytes());
+            bytes.extend(&(msg.len() as u32).to_be_bytes());
+            bytes.extend(&msg);
+        }
+
+        let path = path.as_ref();
+        let mut tmp = path.as_os_str().to_owned();
+        tmp.push(".tmp");
+        fs::write(&tmp, &bytes)?;
+        fs::rename(&tmp, path)
+    }
+
+    // a cache of at most `max_entries` (see with_max_entries) with what was saved in `path`.
+    // Whatever had already expired by the time we read the file is left out
+    pub fn load_from(path: impl AsRef<Path>, max_entries: usize) -> io::Result<Self> {
+        let bytes = fs::read(path)?;
+        let corrupt = |what: &str| io::Error::new(io::ErrorKind::InvalidData, what.to_string());
+        let mut rest = bytes
+            .strip_prefix(FILE_MAGIC)
+            .ok_or_else(|| corrupt("not a DNS cache file"))?;
+
+        let cache = DnsCache::with_max_entries(max_entries);
+        let now = SystemTime::now();
+        while !rest.is_empty() {
+            if rest.len() < 12 {
+                return Err(corrupt("cache file cut off in an entry header"));
+            }
+            let expires = u64::from_be_bytes(rest[0..8].try_into().unwrap());
+            let len = u32::from_be_bytes(rest[8..12].try_into().unwrap()) as usize;
+            let msg = rest
+                .get(12..12 + len)
+                .ok_or_else(|| corrupt("cache file cut off in an entry"))?;
+            rest = &rest[12 + len..];
+
+            let expires = UNIX_EPOCH + Duration::from_secs(expires);
+            if expires <= now {
+                continue;
+            }
+            let msg = DnsMessage::from_bytes(msg)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+            let key = key(msg.question(), dnssec_ok(&msg), scope_of(&msg));
+            let entry = CacheEntry {
+                rcode: msg.rcode(),
+                authentic: msg.authentic_data(),
+                answers: msg.answers,
+                authority: msg.authority,
+                additional: msg.additional,
+                expires,
+            };
+            cache.insert_entry(key, entry);
+        }
+        Ok(cache)
+    }
+}
+
+impl CacheEntry {
+    fn records(&self) -> impl Iterator<Item = &ResourceRecord> {
+        self.answers
+            .iter()
+            .chain(&self.authority)
+            .chain(&self.additional)
+    }
+
+    fn records_mut(&mut self) -> impl Iterator<Item = &mut ResourceRecord> {
+        self.answers
+            .iter_mut()
+            .chain(&mut self.authority)
+            .chain(&mut self.additional)
+    }
+}
+
+// NXDOMAIN, or NODATA: the name exists but has nothing of the type asked for. A CNAME on its own
+// is NODATA too when it wasn't the CNAME we were after, the chain ends without an answer
+fn is_negative(res: &DnsMessage) -> bool {
+    match res.rcode() {
+        RCODE_NXDOMAIN => true,
+        0 => {
+            let qtype = res.question().qtype;
+            RecordType::from(qtype) != RecordType::Any
+                && !res.answers.iter().any(|rr| rr.rr_type == qtype)
+        }
+        _ => false,
+    }
+}
+
+// how long a negative answer may be kept: the smaller of the SOA record's TTL and its minimum
+// field (RFC 2308 section 5). None without an SOA, which means don't keep it
+fn negative_ttl(authority: &[ResourceRecord]) -> Option<u32> {
+    authority.iter().find_map(|rr| match &rr.data {
+        RData::SOA(soa) => Some(rr.ttl.min(soa.minimum)),
+        _ => None,
+    })
+}
+
+// the network an answer is for, from its client subnet option. None when it didn't come with
+// one, or the scope is /0: either way it's the same answer for everybody
+fn scope_of(res: &DnsMessage) -> Option<ClientSubnet> {
+    let scope = res.edns.as_ref()?.client_subnet()?.scope();
+    (scope.source_prefix > 0).then_some(scope)
+}
+
+fn dnssec_ok(msg: &DnsMessage) -> bool {
+    msg.edns.as_ref().is_some_and(|edns| edns.dnssec_ok)
+}
+
+fn key(question: &DnsQuestion, dnssec_ok: bool, scope: Option<ClientSubnet>) -> CacheKey {
+    (
+        question.qname.clone(),
+        question.qtype,
+        question.qclass,
+        dnssec_ok,
+        scope,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::RData;
+
+    fn question(name: &str) -> DnsQuestion {
+        DnsMessage::new(name.to_string()).question().clone()
+    }
+
+    fn a_record(name: &str, ttl: u32) -> ResourceRecord {
+        ResourceRecord::new(name, ttl, RData::A([192, 0, 2, 1].into()))
+    }
+
+    #[test]
+    fn test_get_counts_ttl_down_and_drops_expired() {
+        let cache = DnsCache::new();
+        cache.insert(&question("example.com"), vec![a_record("example.com", 300)]);
+        cache.insert(&question("gone.example"), vec![a_record("gone.example", 0)]);
+
+        let hit = cache.get(&question("EXAMPLE.com.")).unwrap();
+        assert_eq!(hit[0].data, RData::A([192, 0, 2, 1].into()));
+        assert!(hit[0].ttl <= 300 && hit[0].ttl >= 298);
+
+        assert!(cache.get(&question("gone.example")).is_none());
+        assert!(cache.get(&question("other.example")).is_none());
+        assert_eq!(cache.len(), 1);
+    }
+
+    #[test]
+    fn test_full_cache_makes_room() {
+        let cache = DnsCache::with_max_entries(3);
+        cache.insert(
+            &question("long.example"),
+            vec![a_record("long.example", 3600)],
+        );
+        cache.insert(
+            &question("short.example"),
+            vec![a_record("short.example", 60)],
+        );
+        cache.insert(&question("gone.example"), vec![a_record("gone.example", 0)]);
+        assert_eq!(cache.len(), 3);
+
+        // the expired one goes, nothing else has to
+        cache.insert(&question("new.example"), vec![a_record("new.example", 300)]);
+        assert_eq!(cache.len(), 3);
+        assert!(cache.get(&question("short.example")).is_some());
+
+        // then the one with the least time left
+        cache.insert(
+            &question("newer.example"),
+            vec![a_record("newer.example", 300)],
+        );
+        assert_eq!(cache.len(), 3);
+        assert!(cache.get(&question("short.example")).is_none());
+        for name in ["long.example", "new.example", "newer.example"] {
+            assert!(cache.get(&question(name)).is_some(), "{}", name);
+        }
+
+        // replacing an entry doesn't push anything out
+        cache.insert(
+            &question("long.example"),
+            vec![a_record("long.example", 30)],
+        );
+        assert_eq!(cache.len(), 3);
+        assert!(cache.get(&question("new.example")).is_some());
+
+        // the limit comes back with a saved cache, the file doesn't get to fill it past that
+        let path = std::env::temp_dir().join(format!("dns-cache-max-{}.bin", std::process::id()));
+        cache.save_to(&path).unwrap();
+        let loaded = DnsCache::load_from(&path, 3).unwrap();
+        let smaller = DnsCache::load_from(&path, 2).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        loaded.insert(
+            &question("last.example"),
+            vec![a_record("last.example", 300)],
+        );
+        assert_eq!(loaded.len(), 3);
+        assert_eq!(smaller.len(), 2);
+    }
+
+    // the plain query (no DO, no CD) `res` is the answer to
+    fn asked(res: &DnsMessage) -> DnsMessage {
+        let mut query = DnsMessage::query(res.question().qname.clone()).build();
+        query.questions[0] = res.question().clone();
+        query
+    }
+
+    fn negative_response(name: &str, qtype: RecordType, rcode: u8, soa: bool) -> DnsMessage {
+        let query = DnsMessage::query(name).qtype(qtype).build();
+        let mut res = DnsMessage::response_to(&query);
+        let mut flags = res.flags();
+        flags.rcode = rcode;
+        res.set_flags(flags);
+        if soa {
+            res.add_authority(ResourceRecord::new(
+                "example.com",
+                3600,
+                RData::SOA(crate::Soa {
+                    mname: "ns1.example.com".to_string(),
+                    rname: "hostmaster.example.com".to_string(),
+                    serial: 1,
+                    refresh: 7200,
+                    retry: 900,
+                    expire: 1209600,
+                    minimum: 60,
+                }),
+            ));
+        }
+        res
+    }
+
+    #[test]
+    fn test_negative_answers_kept_for_the_soa_minimum() {
+        let cache = DnsCache::new();
+        let nxdomain = negative_response("bigfoot.example.com", RecordType::A, 3, true);
+        let nodata = negative_response("example.com", RecordType::AAAA, 0, true);
+        cache.insert_response(&asked(&nxdomain), &nxdomain);
+        cache.insert_response(&asked(&nodata), &nodata);
+        // without an SOA there's no saying how long it holds, so it isn't kept
+        let nosoa = negative_response("nosoa.example.com", RecordType::A, 3, false);
+        cache.insert_response(&asked(&nosoa), &nosoa);
+        assert_eq!(cache.len(), 2);
+
+        let hit = cache.get_response(&nxdomain).unwrap();
+        assert_eq!(hit.rcode(), RCODE_NXDOMAIN);
+        assert!(hit.answers.is_empty());
+        // the smaller of the SOA's TTL and its minimum
+        assert!(hit.authority[0].ttl <= 60 && hit.authority[0].ttl >= 58);
+        assert!(cache.get(nxdomain.question()).is_none());
+
+        let hit = cache.get_response(&nodata).unwrap();
+        assert_eq!(hit.rcode(), 0);
+        assert!(hit.answers.is_empty());
+        assert_eq!(cache.get(nodata.question()), Some(Vec::new()));
+        // the name exists, another type of it is a different question
+        let a = DnsMessage::query("example.com").build();
+        assert!(cache.get_response(&a).is_none());
+    }
+
+    #[test]
+    fn test_save_and_load_keeps_fresh_entries_only() {
+        let path = std::env::temp_dir().join(format!("dns-cache-{}.bin", std::process::id()));
+        let cache = DnsCache::new();
+        let mut records = vec![a_record("example.com", 3600)];
+        records.push(ResourceRecord::new(
+            "example.com",
+            3600,
+            RData::Unknown(0xFF00, vec![1, 2, 3]),
+        ));
+        cache.insert(&question("example.com"), records.clone());
+        cache.insert_entry(
+            key(&question("stale.example"), false, None),
+            CacheEntry {
+                rcode: 0,
+                authentic: false,
+                answers: vec![a_record("stale.example", 60)],
+                authority: Vec::new(),
+                additional: Vec::new(),
+                expires: SystemTime::now() - Duration::from_secs(10),
+            },
+        );
+        assert_eq!(cache.len(), 2);
+
+        cache.save_to(&path).unwrap();
+        let loaded = DnsCache::load_from(&path, DEFAULT_CACHE_ENTRIES).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(loaded.len(), 1);
+        let hit = loaded.get(&question("example.com")).unwrap();
+        assert_eq!(hit.len(), 2);
+        assert_eq!(hit[0].data, records[0].data);
+        assert_eq!(hit[1].data, records[1].data);
+        assert!(hit[0].ttl > 3500);
+        assert!(loaded.get(&question("stale.example")).is_none());
+    }
+
+    #[test]
+    fn test_client_subnet_scopes() {
+        let subnet = |addr: [u8; 4], prefix| ClientSubnet::new(addr.into(), prefix);
+        let answer = |addr: [u8; 4], scope| {
+            let query = DnsMessage::query("cdn.example").build();
+            let mut res = DnsMessage::response_to(&query);
+            res.add_answer(ResourceRecord::new(
+                "cdn.example",
+                300,
+                RData::A(addr.into()),
+            ));
+            let mut edns = Edns::new(1232);
+            edns.options.push(
+                ClientSubnet {
+                    scope_prefix: scope,
+                    ..subnet(addr, 24)
+                }
+                .to_option(),
+            );
+            res.edns = Some(edns);
+            res
+        };
+        let cache = DnsCache::new();
+        let res = answer([198, 51, 100, 1], 24);
+        cache.insert_response(&asked(&res), &res);
+        let res = answer([198, 51, 0, 0], 16);
+        cache.insert_response(&asked(&res), &res);
+        let res = answer([203, 0, 113, 0], 0);
+        cache.insert_response(&asked(&res), &res);
+        assert_eq!(cache.len(), 3);
+
+        let query = DnsMessage::query("cdn.example").build();
+        let addr_for = |subnet: Option<ClientSubnet>| {
+            cache
+                .get_response_for(&query, subnet.as_ref())
+                .unwrap()
+                .answers[0]
+                .data
+                .clone()
+        };
+        // the most specific scope the client is in, then the one for everybody
+        assert_eq!(
+            addr_for(Some(subnet([198, 51, 100, 9], 24))),
+            RData::A([198, 51, 100, 1].into())
+        );
+        assert_eq!(
+            addr_for(Some(subnet([198, 51, 7, 9], 24))),
+            RData::A([198, 51, 0, 0].into())
+        );
+        assert_eq!(
+            addr_for(Some(subnet([192, 0, 2, 1], 24))),
+            RData::A([203, 0, 113, 0].into())
+        );
+        // a /16 query can't take an answer meant for one /24 inside it, and no subnet at all
+        // (or the privacy one) only gets what's for everybody
+        assert_eq!(
+            addr_for(Some(subnet([198, 51, 100, 9], 16))),
+            RData::A([198, 51, 0, 0].into())
+        );
+        assert_eq!(addr_for(None), RData::A([203, 0, 113, 0].into()));
+        assert_eq!(
+            addr_for(Some(ClientSubnet::privacy())),
+            RData::A([203, 0, 113, 0].into())
+        );
+
+        // and the scopes survive a save
+        let path = std::env::temp_dir().join(format!("dns-cache-ecs-{}.bin", std::process::id()));
+        cache.save_to(&path).unwrap();
+        let loaded = DnsCache::load_from(&path, DEFAULT_CACHE_ENTRIES).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let hit = loaded
+            .get_response_for(&query, Some(&subnet([198, 51, 100, 9], 24)))
+            .unwrap();
+        assert_eq!(hit.answers[0].data, RData::A([198, 51, 100, 1].into()));
+    }
+
+    #[test]
+    fn test_dnssec_bits() {
+        let plain = DnsMessage::query("example.com").build();
+        let with_do = DnsMessage::query("example.com").dnssec_ok(true).build();
+        let with_cd = DnsMessage::query("example.com")
+            .dnssec_ok(true)
+            .checking_disabled(true)
+            .build();
+        let answer = |ad| {
+            let mut res = DnsMessage::response_to(&plain);
+            res.add_answer(a_record("example.com", 300));
+            let mut flags = res.flags();
+            flags.ad = ad;
+            res.set_flags(flags);
+            res
+        };
+
+        // unchecked, so not kept for anybody
+        let cache = DnsCache::new();
+        cache.insert_response(&with_cd, &answer(false));
+        assert!(cache.is_empty());
+
+        // without DO it's no answer to a query with DO, and AD comes back as it was
+        cache.insert_response(&plain, &answer(false));
+        assert!(cache.get_response(&with_do).is_none());
+        cache.insert_response(&with_do, &answer(true));
+        assert_eq!(cache.len(), 2);
+        assert!(!cache.get_response(&plain).unwrap().authentic_data());
+        assert!(cache.get_response(&with_do).unwrap().authentic_data());
+
+        // all of which survives a save
+        let path = std::env::temp_dir().join(format!("dns-cache-do-{}.bin", std::process::id()));
+        cache.save_to(&path).unwrap();
+        let loaded = DnsCache::load_from(&path, DEFAULT_CACHE_ENTRIES).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(loaded.len(), 2);
+        assert!(!loaded.get_response(&plain).unwrap().authentic_data());
+        assert!(loaded.get_response(&with_do).unwrap().authentic_data());
+    }
+
+    #[test]
+    fn test_load_rejects_garbage() {
+        let path = std::env::temp_dir().join(format!("dns-cache-bad-{}.bin", std::process::id()));
+        std::fs::write(&path, b"DNSC\x01\x00\x00").unwrap();
+        let err = DnsCache::load_from(&path, DEFAULT_CACHE_ENTRIES).unwrap_err();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+}
@@ -0,0 +1,331 @@
+// the command line, dig style: the name, type and class in any order, the server after an @,
+// and +options to change how the query goes out and what gets printed
+//
+//     implementation example.com AAAA @9.9.9.9 +tcp +dnssec +short
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, ToSocketAddrs};
+use std::time::Duration;
+
+use implementation::{DnsClass, DnsMessage, Name, RecordType};
+
+// who gets asked without an @, the same server send_message uses
+const DEFAULT_SERVER: Ipv4Addr = Ipv4Addr::new(8, 8, 8, 8);
+
+pub const USAGE: &str = "\
+usage: implementation [@server] [name] [type] [class] [-p port] [-x addr] [+option...]
+
+  @server        who to ask: an address (9.9.9.9, 2620:fe::fe, [2620:fe::fe]:5353) or a host
+                 name, 8.8.8.8 unless given
+  name           what to ask about, the root unless given
+  type, class    A and IN unless given. -t and -c work too
+  -p port        the server's port, 53 (853 with +tls, 443 with +https) unless given
+  -x addr        a reverse lookup: PTR for the address's name under in-addr.arpa or ip6.arpa
+
+  +tcp           over TCP instead of UDP
+  +tls           DNS over TLS
+  +https[=path]  DNS over HTTPS, at /dns-query unless given
+  +[no]dnssec    ask for the DNSSEC records (the DO bit)
+  +[no]rec       ask the server to recurse (on by default)
+  +[no]cd        checking disabled: send the answer even if it fails validation
+  +[no]short     print the answers' data and nothing else
+  +[no]json      print the response as JSON (--json works too)
+  +[no]trace     resolve the name ourselves from the root (or from the @server), showing every
+                 server asked on the way down
+  +timeout=secs  how long each try waits, 5 unless given
+  +tries=n       how many tries, 1 unless given
+";
+
+// how the query goes out
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Transport {
+    Udp,
+    Tcp,
+    Tls,
+    // the endpoint's path
+    Https(String),
+}
+
+impl Transport {
+    fn default_port(&self) -> u16 {
+        match self {
+            Transport::Udp | Transport::Tcp => 53,
+            Transport::Tls => 853,
+            Transport::Https(_) => 443,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Args {
+    pub name: Name,
+    pub qtype: RecordType,
+    pub qclass: DnsClass,
+    // as given after the @, None for the default server
+    pub server: Option<String>,
+    // None for the transport's usual one
+    pub port: Option<u16>,
+    pub transport: Transport,
+    pub dnssec: bool,
+    pub recurse: bool,
+    pub checking_disabled: bool,
+    pub short: bool,
+    pub json: bool,
+    pub trace: bool,
+    pub timeout: Duration,
+    pub tries: usize,
+    pub help: bool,
+}
+
+impl Default for Args {
+    fn default() -> Self {
+        Args {
+            name: Name::root(),
+            qtype: RecordType::A,
+            qclass: DnsClass::IN,
+            server: None,
+            port: None,
+            transport: Transport::Udp,
+            dnssec: false,
+            recurse: true,
+            checking_disabled: false,
+            short: false,
+            json: false,
+            trace: false,
+            timeout: Duration::from_secs(5),
+            tries: 1,
+            help: false,
+        }
+    }
+}
+
+impl Args {
+    // the arguments after the program's name. Like dig, a word that's a type or class name is
+    // taken as one, anything else is the name to look up
+    pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Self, String> {
+        let mut parsed = Args::default();
+        let mut name = None;
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            let mut value =
+                |flag: &str| args.next().ok_or_else(|| format!("{} needs a value", flag));
+            match arg.as_str() {
+                "-h" | "--help" => parsed.help = true,
+                "--json" => parsed.json = true,
+                "-p" => {
+                    let port = value("-p")?;
+                    parsed.port = Some(port.parse().map_err(|_| bad("port", &port))?);
+                }
+                "-t" => parsed.qtype = value("-t")?.parse().map_err(|e| format!("{}", e))?,
+                "-c" => parsed.qclass = value("-c")?.parse().map_err(|e| format!("{}", e))?,
+                "-q" => name = Some(value("-q")?),
+                "-x" => {
+                    let addr = value("-x")?;
+                    let addr: IpAddr = addr.parse().map_err(|_| bad("address", &addr))?;
+                    name = Some(Name::reverse(addr).to_string());
+                    parsed.qtype = RecordType::PTR;
+                }
+                _ if arg.starts_with('@') => parsed.server = Some(arg[1..].to_string()),
+                _ if arg.starts_with('+') => parsed.option(&arg[1..])?,
+                _ if arg.starts_with('-') && arg.len() > 1 => {
+                    return Err(format!("unknown flag {}", arg))
+                }
+                _ => {
+                    if let Ok(qtype) = arg.parse() {
+                        parsed.qtype = qtype;
+                    } else if let Ok(qclass) = arg.parse() {
+                        parsed.qclass = qclass;
+                    } else if let Some(first) = &name {
+                        return Err(format!("more than one name: {} and {}", first, arg));
+                    } else {
+                        name = Some(arg);
+                    }
+                }
+            }
+        }
+        if let Some(name) = name {
+            parsed.name = name.parse().map_err(|e| format!("{}", e))?;
+        }
+        Ok(parsed)
+    }
+
+    // one +option, without the +. Every switch has a no form, dig's longer spellings work too
+    fn option(&mut self, option: &str) -> Result<(), String> {
+        let (key, value) = match option.split_once('=') {
+            Some((key, value)) => (key, Some(value)),
+            None => (option, None),
+        };
+        let (on, switch) = match key.strip_prefix("no") {
+            Some(switch) => (false, switch),
+            None => (true, key),
+        };
+        match (switch, value) {
+            ("tcp" | "vc", None) => {
+                self.transport = if on { Transport::Tcp } else { Transport::Udp };
+            }
+            ("tls", None) => {
+                self.transport = if on { Transport::Tls } else { Transport::Udp };
+            }
+            ("https", path) if on || path.is_none() => {
+                self.transport = match on {
+                    true => Transport::Https(path.unwrap_or("/dns-query").to_string()),
+                    false => Transport::Udp,
+                };
+            }
+            ("dnssec", None) => self.dnssec = on,
+            ("rec" | "recurse", None) => self.recurse = on,
+            ("cd" | "cdflag", None) => self.checking_disabled = on,
+            ("short", None) => self.short = on,
+            ("json", None) => self.json = on,
+            ("trace", None) => self.trace = on,
+            ("timeout", Some(secs)) if on => {
+                let secs: u64 = secs.parse().map_err(|_| bad("timeout", secs))?;
+                self.timeout = Duration::from_secs(secs.max(1));
+            }
+            ("tries", Some(tries)) if on => {
+                self.tries = tries.parse().map_err(|_| bad("number of tries", tries))?;
+                self.tries = self.tries.max(1);
+            }
+            _ => return Err(format!("unknown option +{}", option)),
+        }
+        Ok(())
+    }
+
+    pub fn query(&self) -> DnsMessage {
+        DnsMessage::query(self.name.clone())
+            .qtype(self.qtype)
+            .qclass(self.qclass)
+            .recursion_desired(self.recurse)
+            .checking_disabled(self.checking_disabled)
+            .dnssec_ok(self.dnssec)
+            .build()
+    }
+
+    // where the server is: the address after the @ or the host name looked up with the system
+    // resolver, on -p's port or the transport's own
+    pub fn server_addr(&self) -> io::Result<SocketAddr> {
+        let port = self.port.unwrap_or_else(|| self.transport.default_port());
+        let Some(server) = &self.server else {
+            return Ok(SocketAddr::new(DEFAULT_SERVER.into(), port));
+        };
+        if let Ok(addr) = server.parse() {
+            return Ok(addr);
+        }
+        // an IPv6 address can come in brackets without a port as well
+        let bare = server
+            .strip_prefix('[')
+            .and_then(|rest| rest.strip_suffix(']'));
+        if let Ok(ip) = bare.unwrap_or(server).parse::<IpAddr>() {
+            return Ok(SocketAddr::new(ip, port));
+        }
+        (server.as_str(), port)
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("no address for {}", server),
+                )
+            })
+    }
+
+    // the name the server's certificate has to be for, with +tls and +https: the host name
+    // after the @, or its address when that's all we were given
+    #[cfg_attr(not(feature = "tls"), allow(dead_code))]
+    pub fn server_name(&self) -> io::Result<String> {
+        match &self.server {
+            Some(server) if server.parse::<SocketAddr>().is_err() && !server.starts_with('[') => {
+                Ok(server.clone())
+            }
+            _ => Ok(self.server_addr()?.ip().to_string()),
+        }
+    }
+}
+
+fn bad(what: &str, value: &str) -> String {
+    format!("bad {}: {}", what, value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &str) -> Result<Args, String> {
+        Args::parse(args.split_whitespace().map(String::from))
+    }
+
+    #[test]
+    fn test_parse() {
+        let args = parse("example.com AAAA @9.9.9.9 +tcp +dnssec +short").unwrap();
+        assert_eq!(args.name, "example.com");
+        assert_eq!(args.qtype, RecordType::AAAA);
+        assert_eq!(args.transport, Transport::Tcp);
+        assert!(args.dnssec && args.short && args.recurse);
+        assert_eq!(args.server_addr().unwrap(), "9.9.9.9:53".parse().unwrap());
+
+        // any order, dig's other spellings, and the defaults for what isn't there
+        let args = parse("+norec ch txt version.bind -p 5353 @127.0.0.1 +tries=3").unwrap();
+        assert_eq!(args.name, "version.bind");
+        assert_eq!((args.qtype, args.qclass), (RecordType::TXT, DnsClass::CH));
+        assert!(!args.recurse);
+        assert_eq!(args.tries, 3);
+        assert_eq!(
+            args.server_addr().unwrap(),
+            "127.0.0.1:5353".parse().unwrap()
+        );
+
+        let args = parse("--json example.com +nojson +json").unwrap();
+        assert!(args.json && !args.trace);
+        let args = parse("+trace example.com @127.0.0.1").unwrap();
+        assert!(args.trace);
+
+        let args = parse("").unwrap();
+        assert_eq!(args, Args::default());
+        assert!(args.name.is_root());
+        assert_eq!(args.server_addr().unwrap(), "8.8.8.8:53".parse().unwrap());
+    }
+
+    #[test]
+    fn test_transports_and_servers() {
+        let args = parse("-x 192.0.2.1 @[2620:fe::fe]:5353 +tls").unwrap();
+        assert_eq!(args.name, "1.2.0.192.in-addr.arpa");
+        assert_eq!(args.qtype, RecordType::PTR);
+        assert_eq!(args.transport, Transport::Tls);
+        assert_eq!(
+            args.server_addr().unwrap(),
+            "[2620:fe::fe]:5353".parse().unwrap()
+        );
+        assert_eq!(args.server_name().unwrap(), "2620:fe::fe");
+        let args = parse("@[2620:fe::fe] +tls").unwrap();
+        assert_eq!(
+            args.server_addr().unwrap(),
+            "[2620:fe::fe]:853".parse().unwrap()
+        );
+        assert_eq!(args.server_name().unwrap(), "2620:fe::fe");
+
+        let args = parse("@1.1.1.1 +https").unwrap();
+        assert_eq!(args.transport, Transport::Https("/dns-query".into()));
+        assert_eq!(args.server_addr().unwrap(), "1.1.1.1:443".parse().unwrap());
+        let args = parse("+https=/resolve +nohttps +tls").unwrap();
+        assert_eq!(args.transport, Transport::Tls);
+        let args = parse("@localhost +https=/resolve").unwrap();
+        assert_eq!(args.transport, Transport::Https("/resolve".into()));
+        assert_eq!(args.server_name().unwrap(), "localhost");
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        for args in [
+            "+bogus",
+            "-z",
+            "-p",
+            "-p http",
+            "-x example.com",
+            "+timeout=soon",
+            "+dnssec=yes",
+            "one.example two.example",
+            "bad..name",
+        ] {
+            assert!(parse(args).is_err(), "{}", args);
+        }
+    }
+}
@@ -0,0 +1,350 @@
+// DNS cookies (RFC 7873): a lightweight answer to off-path spoofing for plain UDP. The client
+// puts 8 random bytes of its own in an EDNS COOKIE option and only believes answers that echo
+// them; the server hands back a cookie of its own, made from the client's cookie and address,
+// which the client sends with every later query. A server that sees its own cookie come back
+// knows the client's address isn't forged, so it can't be used to bounce big answers at a victim.
+//
+// Server cookies are made the RFC 9018 way: a version, a timestamp and 8 bytes of hash over the
+// client cookie, those two and the client's address. The hash is SipHash keyed with a random
+// secret (a RandomState of our own), so nobody else can make one, but it also means the cookies
+// of two servers never work for each other even when they share an address
+use std::borrow::Cow;
+use std::collections::hash_map::RandomState;
+use std::collections::HashMap;
+use std::hash::{BuildHasher, Hasher};
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+use crate::random::random_u64;
+use crate::rdata::TYPE_TSIG;
+use crate::{DnsMessage, EdnsOption};
+
+pub const EDNS_COOKIE: u16 = 10;
+
+// the version byte of an RFC 9018 server cookie
+const COOKIE_VERSION: u8 = 1;
+// a server cookie is good for an hour, and we start handing out a fresh one after half of that
+// (RFC 9018 4.3). A clock a little ahead of ours doesn't make it bad either
+const COOKIE_LIFETIME: u32 = 3600;
+const COOKIE_REFRESH: u32 = 1800;
+const COOKIE_CLOCK_SKEW: u32 = 300;
+
+// the cookies a resolver uses with its upstreams: a client cookie for each server address, and
+// whatever server cookie that server last gave us
+#[derive(Debug, Default)]
+pub(crate) struct ClientCookies {
+    jar: Mutex<HashMap<IpAddr, Cookie>>,
+}
+
+#[derive(Debug, Clone)]
+struct Cookie {
+    client: [u8; 8],
+    server: Vec<u8>,
+}
+
+impl ClientCookies {
+    // `msg` with our cookie for `server` in its OPT record. A query without an OPT record goes
+    // as it is, and so does a TSIG-signed one
+    pub fn attach<'a>(&self, msg: &'a DnsMessage, server: IpAddr) -> Cow<'a, DnsMessage> {
+        if msg.edns.is_none() || msg.additional.iter().any(|rr| rr.rr_type == TYPE_TSIG) {
+            return Cow::Borrowed(msg);
+        }
+        let cookie = {
+            let mut jar = self.jar.lock().unwrap();
+            jar.entry(server)
+                .or_insert_with(|| Cookie {
+                    client: random_u64().to_be_bytes(),
+                    server: Vec::new(),
+                })
+                .clone()
+        };
+        let mut msg = msg.clone();
+        let edns = msg.edns.as_mut().unwrap();
+        edns.options.retain(|option| option.code != EDNS_COOKIE);
+        let mut data = cookie.client.to_vec();
+        data.extend(cookie.server);
+        edns.options.push(EdnsOption {
+            code: EDNS_COOKIE,
+            data,
+        });
+        Cow::Owned(msg)
+    }
+
+    // keeps the server cookie `res` came with, if the client half is the one we sent `server`
+    pub fn store(&self, server: IpAddr, res: &DnsMessage) {
+        let Some((client, Some(cookie))) = cookie_of(res) else {
+            return;
+        };
+        let mut jar = self.jar.lock().unwrap();
+        if let Some(ours) = jar.get_mut(&server) {
+            if ours.client == client {
+                ours.server = cookie.to_vec();
+            }
+        }
+    }
+}
+
+// whether `res` can be the answer to `query` as far as cookies go: when both carry one, the
+// client half has to be ours. A server that doesn't do cookies just leaves the option out
+pub(crate) fn echoes_cookie(query: &DnsMessage, res: &DnsMessage) -> bool {
+    let theirs = res.edns.as_ref().and_then(|edns| edns.option(EDNS_COOKIE));
+    match (cookie_of(query), theirs) {
+        (Some((ours, _)), Some(theirs)) => theirs.get(..8) == Some(&ours[..]),
+        _ => true,
+    }
+}
+
+// the client cookie and the server cookie (if there is one) from a message's COOKIE option.
+// None when there's no option, or it isn't a well-formed one: 8 bytes of client cookie and
+// then nothing, or 8 to 32 bytes of server cookie (RFC 7873 4)
+pub(crate) fn cookie_of(msg: &DnsMessage) -> Option<([u8; 8], Option<&[u8]>)> {
+    let data = msg.edns.as_ref()?.option(EDNS_COOKIE)?;
+    let client = data.get(..8)?.try_into().unwrap();
+    match data.len() {
+        8 => Some((client, None)),
+        16..=40 => Some((client, Some(&data[8..]))),
+        _ => None,
+    }
+}
+
+// what the server makes of the cookie a query came with
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum CookieCheck {
+    // no COOKIE option at all
+    Missing,
+    // one that isn't 8 bytes, or 16 to 40: FORMERR
+    Malformed,
+    // a client cookie and no server cookie, or one that isn't ours (any more)
+    ClientOnly([u8; 8]),
+    // a server cookie we made for this client, not too old
+    Valid([u8; 8]),
+}
+
+// the server's half: makes server cookies and checks the ones clients send back
+pub(crate) struct ServerCookies {
+    secret: RandomState,
+}
+
+impl ServerCookies {
+    pub fn new() -> Self {
+        ServerCookies {
+            secret: RandomState::new(),
+        }
+    }
+
+    pub fn check(&self, query: &DnsMessage, client: IpAddr, now: u32) -> CookieCheck {
+        if query
+            .edns
+            .as_ref()
+            .and_then(|edns| edns.option(EDNS_COOKIE))
+            .is_none()
+        {
+            return CookieCheck::Missing;
+        }
+        let Some((cookie, server)) = cookie_of(query) else {
+            return CookieCheck::Malformed;
+        };
+        match server {
+            Some(server) if self.is_ours(&cookie, server, client, now) => {
+                CookieCheck::Valid(cookie)
+            }
+            _ => CookieCheck::ClientOnly(cookie),
+        }
+    }
+
+    // the COOKIE option for the response to a query that came with `check`: the client's cookie
+    // back with a server cookie for it. The one the client sent if it's still fresh, a new one
+    // otherwise. None for a query without a (well-formed) cookie
+    pub fn option(
+        &self,
+        query: &DnsMessage,
+        check: CookieCheck,
+        client: IpAddr,
+        now: u32,
+    ) -> Option<EdnsOption> {
+        let cookie = match check {
+            CookieCheck::Missing | CookieCheck::Malformed => return None,
+            CookieCheck::ClientOnly(cookie) | CookieCheck::Valid(cookie) => cookie,
+        };
+        let mut data = cookie.to_vec();
+        match cookie_of(query) {
+            Some((_, Some(server)))
+                if check == CookieCheck::Valid(cookie)
+                    && now.wrapping_sub(timestamp(server)) < COOKIE_REFRESH =>
+            {
+                data.extend(server)
+            }
+            _ => data.extend(self.make(&cookie, client, now)),
+        }
+        Some(EdnsOption {
+            code: EDNS_COOKIE,
+            data,
+        })
+    }
+
+    // version, 3 reserved bytes, the timestamp and the hash over all of it
+    fn make(&self, cookie: &[u8; 8], client: IpAddr, now: u32) -> [u8; 16] {
+        let mut server = [0u8; 16];
+        server[0] = COOKIE_VERSION;
+        server[4..8].copy_from_slice(&now.to_be_bytes());
+        let hash = self.hash(cookie, &server[..8], client);
+        server[8..].copy_from_slice(&hash.to_be_bytes());
+        server
+    }
+
+    fn is_ours(&self, cookie: &[u8; 8], server: &[u8], client: IpAddr, now: u32) -> bool {
+        if server.len() != 16 || server[0] != COOKIE_VERSION {
+            return false;
+        }
+        // the age counts in serial number arithmetic, so a timestamp from the future comes out
+        // huge unless it's only a little ahead
+        let age = now.wrapping_sub(timestamp(server));
+        if age > COOKIE_LIFETIME && age.wrapping_neg() > COOKIE_CLOCK_SKEW {
+            return false;
+        }
+        self.hash(cookie, &server[..8], client).to_be_bytes() == server[8..]
+    }
+
+    fn hash(&self, cookie: &[u8; 8], head: &[u8], client: IpAddr) -> u64 {
+        let mut hasher = self.secret.build_hasher();
+        hasher.write(cookie);
+        hasher.write(head);
+        match client {
+            IpAddr::V4(addr) => hasher.write(&addr.octets()),
+            IpAddr::V6(addr) => hasher.write(&addr.octets()),
+        }
+        hasher.finish()
+    }
+}
+
+fn timestamp(server: &[u8]) -> u32 {
+    u32::from_be_bytes(server[4..8].try_into().unwrap())
+}
+
+// seconds since the epoch, as the 32 bits a server cookie has room for
+pub(crate) fn now() -> u32 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map_or(0, |since| since.as_secs() as u32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Edns;
+    use std::net::Ipv4Addr;
+
+    fn with_cookie(data: &[u8]) -> DnsMessage {
+        let mut query = DnsMessage::query("example.com").build();
+        let mut edns = Edns::new(1232);
+        edns.options.push(EdnsOption {
+            code: EDNS_COOKIE,
+            data: data.to_vec(),
+        });
+        query.edns = Some(edns);
+        query
+    }
+
+    #[test]
+    fn test_server_cookies() {
+        let cookies = ServerCookies::new();
+        let client = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1));
+        let now = 1_700_000_000;
+
+        assert_eq!(
+            cookies.check(&DnsMessage::query("example.com").build(), client, now),
+            CookieCheck::Missing
+        );
+        assert_eq!(
+            cookies.check(&with_cookie(&[1; 5]), client, now),
+            CookieCheck::Malformed
+        );
+        let first = with_cookie(&[1; 8]);
+        assert_eq!(
+            cookies.check(&first, client, now),
+            CookieCheck::ClientOnly([1; 8])
+        );
+
+        // the cookie we hand out comes back good from the same client, and is handed back
+        // unchanged while it's fresh
+        let check = CookieCheck::ClientOnly([1; 8]);
+        let issued = cookies.option(&first, check, client, now).unwrap().data;
+        assert_eq!(issued.len(), 24);
+        let second = with_cookie(&issued);
+        assert_eq!(
+            cookies.check(&second, client, now + 60),
+            CookieCheck::Valid([1; 8])
+        );
+        let again = cookies.option(&second, CookieCheck::Valid([1; 8]), client, now + 60);
+        assert_eq!(again.unwrap().data, issued);
+        let later = COOKIE_REFRESH + 1;
+        let refreshed = cookies.option(&second, CookieCheck::Valid([1; 8]), client, now + later);
+        assert_ne!(refreshed.unwrap().data, issued);
+
+        // but not from anyone else, not with another client cookie and not once it's too old
+        let other = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 2));
+        assert_eq!(
+            cookies.check(&second, other, now),
+            CookieCheck::ClientOnly([1; 8])
+        );
+        let mut swapped = issued.clone();
+        swapped[..8].copy_from_slice(&[2; 8]);
+        assert_eq!(
+            cookies.check(&with_cookie(&swapped), client, now),
+            CookieCheck::ClientOnly([2; 8])
+        );
+        assert_eq!(
+            cookies.check(&second, client, now + COOKIE_LIFETIME + 1),
+            CookieCheck::ClientOnly([1; 8])
+        );
+        // a clock a little ahead is fine
+        assert_eq!(
+            cookies.check(&second, client, now - 100),
+            CookieCheck::Valid([1; 8])
+        );
+        // and somebody else's server can't vouch for it
+        assert_eq!(
+            ServerCookies::new().check(&second, client, now),
+            CookieCheck::ClientOnly([1; 8])
+        );
+    }
+
+    #[test]
+    fn test_client_cookies() {
+        let cookies = ClientCookies::default();
+        let server = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 53));
+        let mut query = DnsMessage::query("example.com").build();
+        // nothing to carry it without EDNS
+        assert!(matches!(cookies.attach(&query, server), Cow::Borrowed(_)));
+
+        query.edns = Some(Edns::new(1232));
+        let first = cookies.attach(&query, server).into_owned();
+        let (client, none) = cookie_of(&first).unwrap();
+        assert!(none.is_none());
+
+        // an answer with somebody else's client cookie is no answer to ours, and its server
+        // cookie isn't kept
+        let mut stray = [&[0; 8][..], &[7; 16]].concat();
+        stray[0] = client[0].wrapping_add(1);
+        let stray = with_cookie(&stray);
+        assert!(!echoes_cookie(&first, &stray));
+        cookies.store(server, &stray);
+        assert_eq!(cookie_of(&cookies.attach(&query, server)).unwrap().1, None);
+
+        let answer = with_cookie(&[&client[..], &[7; 16]].concat());
+        assert!(echoes_cookie(&first, &answer));
+        assert!(echoes_cookie(
+            &first,
+            &DnsMessage::query("example.com").build()
+        ));
+        cookies.store(server, &answer);
+        let second = cookies.attach(&query, server).into_owned();
+        assert_eq!(cookie_of(&second), Some((client, Some(&[7; 16][..]))));
+
+        // every server gets a client cookie of its own
+        let other = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 54));
+        let (theirs, _) = cookie_of(&cookies.attach(&query, other)).unwrap();
+        assert_ne!(theirs, client);
+    }
+}
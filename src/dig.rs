@@ -0,0 +1,231 @@
+// messages printed the way dig prints them: the header with its flags and counts, the OPT
+// pseudosection, then each section that has anything in it, one record per line in
+// presentation format. `{}` on a DnsMessage gives the lot, on a record or a question its line:
+//
+//     ;; ->>HEADER<<- opcode: QUERY, status: NOERROR, id: 4242
+//     ;; flags: qr rd ra; QUERY: 1, ANSWER: 1, AUTHORITY: 0, ADDITIONAL: 1
+//
+//     ;; OPT PSEUDOSECTION:
+//     ; EDNS: version: 0, flags:; udp: 1232
+//     ;; QUESTION SECTION:
+//     ;example.com.                   IN      A
+//
+//     ;; ANSWER SECTION:
+//     example.com.            300     IN      A       192.0.2.1
+use core::fmt;
+
+use crate::edns::EDNS_EXTENDED_ERROR;
+use crate::encoding::to_hex;
+use crate::{DnsClass, DnsMessage, DnsQuestion, Edns, Name, RecordType, ResourceRecord};
+
+// the column a record's TTL starts at, the owner name is padded out to it with tabs
+const OWNER_WIDTH: usize = 24;
+const EDNS_COOKIE: u16 = 10;
+
+impl fmt::Display for DnsMessage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let flags = self.flags();
+        writeln!(
+            f,
+            ";; ->>HEADER<<- opcode: {}, status: {}, id: {}",
+            self.opcode(),
+            self.response_code(),
+            self.header.identification
+        )?;
+        f.write_str(";; flags:")?;
+        for (on, name) in [
+            (flags.qr, "qr"),
+            (flags.aa, "aa"),
+            (flags.tc, "tc"),
+            (flags.rd, "rd"),
+            (flags.ra, "ra"),
+            (flags.ad, "ad"),
+            (flags.cd, "cd"),
+        ] {
+            if on {
+                write!(f, " {}", name)?;
+            }
+        }
+        // the OPT record is one of the additional records on the wire, and dig counts it
+        writeln!(
+            f,
+            "; QUERY: {}, ANSWER: {}, AUTHORITY: {}, ADDITIONAL: {}",
+            self.questions.len(),
+            self.answers.len(),
+            self.authority.len(),
+            self.additional.len() + self.edns.is_some() as usize
+        )?;
+        f.write_str("\n")?;
+
+        if let Some(edns) = &self.edns {
+            write_edns(f, edns)?;
+        }
+        f.write_str(";; QUESTION SECTION:\n")?;
+        for question in &self.questions {
+            writeln!(f, "{}", question)?;
+        }
+        for (title, records) in [
+            ("ANSWER", &self.answers),
+            ("AUTHORITY", &self.authority),
+            ("ADDITIONAL", &self.additional),
+        ] {
+            if records.is_empty() {
+                continue;
+            }
+            write!(f, "\n;; {} SECTION:\n", title)?;
+            for rr in records {
+                writeln!(f, "{}", rr)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+// ";example.com.  IN  A", with an empty column where a record has its TTL
+impl fmt::Display for DnsQuestion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let owner = alloc::format!(";{}", fqdn(&self.qname));
+        write_owner(f, &owner)?;
+        write!(
+            f,
+            "\t{}\t{}",
+            DnsClass::from(self.qclass),
+            RecordType::from(self.qtype)
+        )
+    }
+}
+
+// "example.com.  300  IN  A  192.0.2.1", the same as a zone file line
+impl fmt::Display for ResourceRecord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write_owner(f, &fqdn(&self.name))?;
+        write!(
+            f,
+            "{}\t{}\t{}\t{}",
+            self.ttl,
+            DnsClass::from(self.class),
+            RecordType::from(self.rr_type),
+            self.data
+        )
+    }
+}
+
+fn write_edns(f: &mut fmt::Formatter<'_>, edns: &Edns) -> fmt::Result {
+    f.write_str(";; OPT PSEUDOSECTION:\n")?;
+    let flags = if edns.dnssec_ok { " do" } else { "" };
+    writeln!(
+        f,
+        "; EDNS: version: {}, flags:{}; udp: {}",
+        edns.version, flags, edns.udp_payload
+    )?;
+    for error in edns.extended_errors() {
+        writeln!(f, "; EDE: {}", error)?;
+    }
+    for option in &edns.options {
+        match option.code {
+            // the ones that parsed are above, the rest aren't worth showing
+            EDNS_EXTENDED_ERROR => {}
+            EDNS_COOKIE => writeln!(f, "; COOKIE: {}", to_hex(&option.data))?,
+            code => writeln!(f, "; OPT={}: {}", code, to_hex(&option.data))?,
+        }
+    }
+    Ok(())
+}
+
+// the owner name, then tabs up to OWNER_WIDTH (and always at least one)
+fn write_owner(f: &mut fmt::Formatter<'_>, owner: &str) -> fmt::Result {
+    f.write_str(owner)?;
+    let tabs = OWNER_WIDTH.saturating_sub(owner.len()).div_ceil(8);
+    for _ in 0..tabs.max(1) {
+        f.write_str("\t")?;
+    }
+    Ok(())
+}
+
+pub(crate) fn fqdn(name: &Name) -> alloc::string::String {
+    if name.is_root() {
+        ".".into()
+    } else {
+        alloc::format!("{}.", name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{EdnsOption, ExtendedError, RData};
+    use alloc::string::ToString;
+    use alloc::vec;
+    use core::net::Ipv4Addr;
+
+    #[test]
+    fn test_dig_output() {
+        let query = DnsMessage::query("example.com").id(4242).build();
+        let mut res = DnsMessage::response_to(&query);
+        let mut flags = res.flags();
+        flags.ra = true;
+        res.set_flags(flags);
+        res.add_answer(ResourceRecord::new(
+            "example.com",
+            300,
+            RData::A(Ipv4Addr::new(192, 0, 2, 1)),
+        ));
+        res.add_authority(ResourceRecord::new(
+            "example.com",
+            86400,
+            RData::NS("a.iana-servers.net".into()),
+        ));
+        let mut edns = Edns::new(1232);
+        edns.dnssec_ok = true;
+        edns.options = vec![
+            EdnsOption {
+                code: EDNS_COOKIE,
+                data: vec![1, 2, 3, 4, 5, 6, 7, 8],
+            },
+            ExtendedError::new(18, "").to_option(),
+        ];
+        res.edns = Some(edns);
+
+        let expected = "\
+;; ->>HEADER<<- opcode: QUERY, status: NOERROR, id: 4242
+;; flags: qr rd ra; QUERY: 1, ANSWER: 1, AUTHORITY: 1, ADDITIONAL: 1
+
+;; OPT PSEUDOSECTION:
+; EDNS: version: 0, flags: do; udp: 1232
+; EDE: Prohibited (18)
+; COOKIE: 0102030405060708
+;; QUESTION SECTION:
+;example.com.\t\t\tIN\tA
+
+;; ANSWER SECTION:
+example.com.\t\t300\tIN\tA\t192.0.2.1
+
+;; AUTHORITY SECTION:
+example.com.\t\t86400\tIN\tNS\ta.iana-servers.net.
+";
+        assert_eq!(res.to_string(), expected);
+    }
+
+    #[test]
+    fn test_record_lines() {
+        let long = ResourceRecord::new(
+            "a-rather-long-name.example.com",
+            60,
+            RData::TXT(vec![b"hello world".to_vec()]),
+        );
+        assert_eq!(
+            long.to_string(),
+            "a-rather-long-name.example.com.\t60\tIN\tTXT\t\"hello world\""
+        );
+        let root = ResourceRecord::new(Name::root(), 0, RData::Unknown(99, vec![0xab]));
+        assert_eq!(root.to_string(), ".\t\t\t0\tIN\tTYPE99\t\\# 1 AB");
+
+        let mut nxdomain = DnsMessage::query("nope.example").id(1).build();
+        let mut flags = nxdomain.flags();
+        (flags.qr, flags.rcode) = (true, 3);
+        nxdomain.set_flags(flags);
+        let text = nxdomain.to_string();
+        assert!(text.starts_with(";; ->>HEADER<<- opcode: QUERY, status: NXDOMAIN, id: 1\n"));
+        assert!(text.ends_with(";; QUESTION SECTION:\n;nope.example.\t\t\tIN\tA\n"));
+    }
+}
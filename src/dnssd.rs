@@ -0,0 +1,234 @@
+// DNS-based service discovery (RFC 6763): a PTR query for a service type (_http._tcp.local, or
+// _http._tcp.example.com in unicast DNS) lists its instances by name, and each instance has an
+// SRV record saying where it runs and a TXT record with whatever else it wants to tell. Over mDNS
+// the responders send an instance's SRV, TXT and addresses along with the PTR in the additional
+// section; we only ask about the instances they left out.
+//
+// The TXT record is a list of key=value strings (RFC 6763 section 6). Keys are case-insensitive
+// and only the first of each counts, a key without "=" is a flag that's just there, and an
+// empty string is how a record with nothing to say keeps from being empty
+use crate::rdata::{TYPE_SRV, TYPE_TXT};
+use crate::{DnsError, DnsMessage, Name, RData, RecordType, Resolver, ResourceRecord};
+use crate::{SrvRecord, RCODE_NXDOMAIN};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ServiceInstance {
+    // the instance's full name, "Office Printer._ipp._tcp.local"
+    pub name: Name,
+    // where it runs, from its SRV record
+    pub host: Name,
+    pub port: u16,
+    // its TXT record's pairs, in order, with None for a key that has no "="
+    pub txt: Vec<(String, Option<String>)>,
+}
+
+impl ServiceInstance {
+    // the value of `key` in the TXT record. A key that's there without a value gives ""
+    pub fn value(&self, key: &str) -> Option<&str> {
+        self.txt
+            .iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case(key))
+            .map(|(_, value)| value.as_deref().unwrap_or(""))
+    }
+}
+
+// see Resolver::discover_services
+pub(crate) fn discover(
+    resolver: &Resolver,
+    service: &str,
+) -> Result<Vec<ServiceInstance>, DnsError> {
+    let res = resolver.query(&DnsMessage::query(service).qtype(RecordType::PTR).build())?;
+    match res.rcode() {
+        0 => {}
+        RCODE_NXDOMAIN => return Ok(Vec::new()),
+        _ => return Err(DnsError::from_response(&res)),
+    }
+    let service = Name::from(service);
+    let mut names: Vec<Name> = Vec::new();
+    for rr in res.answers.iter().filter(|rr| rr.name == service) {
+        if let RData::PTR(instance) = &rr.data {
+            let instance = Name::from(instance.as_str());
+            if !names.contains(&instance) {
+                names.push(instance);
+            }
+        }
+    }
+
+    let mut instances = Vec::new();
+    for name in names {
+        let srv = match srv_of(&res.additional, &name) {
+            Some(srv) => Some(srv),
+            None => srv_of(&ask(resolver, &name, TYPE_SRV)?, &name),
+        };
+        // an instance with no SRV record has gone away since the PTR was cached
+        let Some(srv) = srv else {
+            continue;
+        };
+        let txt = match txt_of(&res.additional, &name) {
+            Some(txt) => txt,
+            None => txt_of(&ask(resolver, &name, TYPE_TXT)?, &name).unwrap_or_default(),
+        };
+        instances.push(ServiceInstance {
+            name,
+            host: Name::from(srv.target.as_str()),
+            port: srv.port,
+            txt,
+        });
+    }
+    Ok(instances)
+}
+
+// the answers to one follow-up query. A name that isn't there is no records, not an error
+fn ask(resolver: &Resolver, name: &Name, qtype: u16) -> Result<Vec<ResourceRecord>, DnsError> {
+    let res = resolver.query(&DnsMessage::query(name.clone()).qtype(qtype).build())?;
+    match res.rcode() {
+        0 | RCODE_NXDOMAIN => Ok(res.answers),
+        _ => Err(DnsError::from_response(&res)),
+    }
+}
+
+// `name`'s SRV record among `records`, the lowest priority one if there are several
+fn srv_of(records: &[ResourceRecord], name: &Name) -> Option<SrvRecord> {
+    records
+        .iter()
+        .filter(|rr| rr.name == *name)
+        .filter_map(|rr| match &rr.data {
+            RData::SRV(srv) => Some(srv.clone()),
+            _ => None,
+        })
+        .min_by_key(|srv| srv.priority)
+}
+
+fn txt_of(records: &[ResourceRecord], name: &Name) -> Option<Vec<(String, Option<String>)>> {
+    records
+        .iter()
+        .filter(|rr| rr.name == *name)
+        .find_map(|rr| match &rr.data {
+            RData::TXT(strings) => Some(pairs(strings)),
+            _ => None,
+        })
+}
+
+// keys are printable ASCII, values can be anything: this is where they become text
+fn pairs(strings: &[Vec<u8>]) -> Vec<(String, Option<String>)> {
+    let mut pairs: Vec<(String, Option<String>)> = Vec::new();
+    for string in strings {
+        let string = String::from_utf8_lossy(string);
+        let (key, value) = match string.split_once('=') {
+            Some((key, value)) => (key, Some(value.to_string())),
+            None => (&*string, None),
+        };
+        // "=value" has no key, and is to be ignored
+        if key.is_empty() || pairs.iter().any(|(k, _)| k.eq_ignore_ascii_case(key)) {
+            continue;
+        }
+        pairs.push((key.to_string(), value));
+    }
+    pairs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::mock_udp_server;
+
+    fn srv(port: u16, target: &str) -> RData {
+        RData::SRV(SrvRecord {
+            priority: 0,
+            weight: 0,
+            port,
+            target: target.to_string(),
+        })
+    }
+
+    fn txt(strings: &[&str]) -> RData {
+        RData::TXT(strings.iter().map(|s| s.as_bytes().to_vec()).collect())
+    }
+
+    #[test]
+    fn test_txt_pairs() {
+        let instance = ServiceInstance {
+            name: Name::from("x._http._tcp.local"),
+            host: Name::from("x.local"),
+            port: 80,
+            txt: pairs(&[
+                b"path=/admin".to_vec(),
+                b"secure".to_vec(),
+                b"PATH=/other".to_vec(),
+                b"=junk".to_vec(),
+                b"note=a=b".to_vec(),
+            ]),
+        };
+        assert_eq!(instance.txt.len(), 3);
+        assert_eq!(instance.value("Path"), Some("/admin"));
+        assert_eq!(instance.value("secure"), Some(""));
+        assert_eq!(instance.value("note"), Some("a=b"));
+        assert_eq!(instance.value("missing"), None);
+        assert!(pairs(&[Vec::new()]).is_empty());
+    }
+
+    #[test]
+    fn test_discover_services() {
+        // two instances: the first comes with its SRV and TXT in the additional section, the
+        // second costs an SRV and a TXT query of its own. A third has no SRV (one more query)
+        // and is skipped
+        let server = mock_udp_server(4, |query| {
+            let query = DnsMessage::from_bytes(query).unwrap();
+            let mut res = DnsMessage::response_to(&query);
+            let qname = query.question().qname.clone();
+            match (qname.as_str(), query.question().qtype) {
+                ("_http._tcp.example.com", _) => {
+                    for instance in ["Office Printer", "nas", "gone"] {
+                        res.add_answer(ResourceRecord::new(
+                            qname.clone(),
+                            300,
+                            RData::PTR(format!("{}._http._tcp.example.com", instance)),
+                        ));
+                    }
+                    let printer = "Office Printer._http._tcp.example.com";
+                    res.add_additional(ResourceRecord::new(
+                        printer,
+                        300,
+                        srv(631, "printer.example.com"),
+                    ));
+                    res.add_additional(ResourceRecord::new(printer, 300, txt(&["rp=ipp/print"])));
+                }
+                ("nas._http._tcp.example.com", TYPE_SRV) => {
+                    res.add_answer(ResourceRecord::new(
+                        qname,
+                        300,
+                        srv(8080, "nas.example.com"),
+                    ));
+                }
+                ("nas._http._tcp.example.com", TYPE_TXT) => {
+                    res.add_answer(ResourceRecord::new(qname, 300, txt(&[""])));
+                }
+                _ => {}
+            }
+            res.to_bytes().unwrap()
+        });
+        let resolver = Resolver::with_server(server);
+
+        let instances = resolver
+            .discover_services("_http._tcp.example.com")
+            .unwrap();
+        assert_eq!(
+            instances,
+            vec![
+                ServiceInstance {
+                    name: Name::from("Office Printer._http._tcp.example.com"),
+                    host: Name::from("printer.example.com"),
+                    port: 631,
+                    txt: vec![("rp".to_string(), Some("ipp/print".to_string()))],
+                },
+                ServiceInstance {
+                    name: Name::from("nas._http._tcp.example.com"),
+                    host: Name::from("nas.example.com"),
+                    port: 8080,
+                    txt: Vec::new(),
+                },
+            ]
+        );
+        assert_eq!(resolver.stats().queries_sent, 4);
+    }
+}
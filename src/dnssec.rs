@@ -0,0 +1,1411 @@
+// DNSSEC validation (RFC 4033-4035): checking that the records we got are the ones the zone
+// signed, with a chain of keys going all the way up to one we trust from the start, the root's.
+// Each zone's keys (DNSKEY) are vouched for by a hash of one of them in its parent (DS), which is
+// signed with the parent's keys, and so on up.
+//
+// We validate as a stub: the upstream does the resolving, we ask it with DO (send the
+// signatures) and CD (hand everything over, even what you think is bogus) and go fetch the
+// DNSKEY and DS records ourselves to check the chain. ring does the cryptography.
+//
+// An answer saying there is nothing (NXDOMAIN, or no records of the type asked for) has to
+// prove it too, with signed NSEC or NSEC3 records spanning the gap in the zone where the name
+// would be (RFC 4035 5.4, RFC 5155 8)
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use ring::digest;
+use ring::signature::{self, RsaPublicKeyComponents, UnparsedPublicKey};
+
+use crate::encoding::from_base32hex;
+use crate::rdata::{
+    TYPE_CNAME, TYPE_DNAME, TYPE_DNSKEY, TYPE_DS, TYPE_NSEC, TYPE_NSEC3, TYPE_RRSIG, TYPE_SOA,
+};
+use crate::resolver::Security;
+use crate::{
+    names_equal, DnsError, DnsMessage, Dnskey, Ds, Name, Nsec, Nsec3, RData, RecordType, Resolver,
+    ResourceRecord, Rrsig, RCODE_NXDOMAIN,
+};
+
+// the DNSKEY protocol field, which has only ever had the one value
+const PROTOCOL: u8 = 3;
+// the longest we keep a zone's validated keys, whatever their TTL says
+const MAX_KEYS_TTL: u32 = 86400;
+// how long we remember that a zone is unsigned
+const INSECURE_TTL: Duration = Duration::from_secs(300);
+// NSEC3 with more hash iterations than this proves nothing to us, the answer counts as unsigned
+// (RFC 9276 3.2). Hashing that many times for every lookup is a denial of service waiting to
+// happen
+const MAX_NSEC3_ITERATIONS: u16 = 150;
+
+// a key we trust without anyone vouching for it, given as the DS record of it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TrustAnchor {
+    pub zone: Name,
+    pub ds: Ds,
+}
+
+impl TrustAnchor {
+    // `key` as the key of `zone`, by the SHA-256 DS of it
+    pub fn from_dnskey(zone: Name, key: &Dnskey) -> TrustAnchor {
+        let ds = Ds {
+            key_tag: key.key_tag(),
+            algorithm: key.algorithm,
+            digest_type: 2,
+            digest: ds_digest(&zone, Some(key), 2).expect("SHA-256 is a digest type we know"),
+        };
+        TrustAnchor { zone, ds }
+    }
+
+    // the root zone's key-signing keys, KSK-2017 and KSK-2024, as IANA publishes them
+    // (https://data.iana.org/root-anchors/root-anchors.xml)
+    pub fn root() -> Vec<TrustAnchor> {
+        [
+            (
+                20326,
+                "E06D44B80B8F1D39A95C0B0D7C65D08458E880409BBC683457104237C7F8EC8D",
+            ),
+            (
+                38696,
+                "683D2D0ACB8C9B712A1948B27F741219298D0A450D612C483AF444A4C0FB2B16",
+            ),
+        ]
+        .into_iter()
+        .map(|(key_tag, digest)| TrustAnchor {
+            zone: Name::root(),
+            ds: Ds {
+                key_tag,
+                algorithm: 8,
+                digest_type: 2,
+                digest: hex(digest),
+            },
+        })
+        .collect()
+    }
+}
+
+fn hex(text: &str) -> Vec<u8> {
+    (0..text.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&text[i..i + 2], 16).unwrap())
+        .collect()
+}
+
+// what we found out about a zone's keys
+#[derive(Debug, Clone)]
+enum ZoneKeys {
+    // validated all the way up, these are the zone's signing keys
+    Secure(Vec<Dnskey>),
+    // the parent says the zone isn't signed (no DS), or signs it with nothing we can check
+    Insecure,
+}
+
+pub(crate) struct Validator {
+    anchors: Vec<TrustAnchor>,
+    // what we already know about each zone, and until when
+    zones: Mutex<HashMap<Name, (ZoneKeys, Instant)>>,
+}
+
+impl Validator {
+    pub(crate) fn new(anchors: Vec<TrustAnchor>) -> Self {
+        Validator {
+            anchors,
+            zones: Mutex::new(HashMap::new()),
+        }
+    }
+
+    // how far `res` can be trusted. Every RRset in the answer section has to check out, and
+    // when the answer is that there is nothing (or it came from a wildcard), the NSEC or NSEC3
+    // records in the authority section have to prove the name isn't there. Secure when it all
+    // does, Insecure when some of it lives in a zone the chain of trust proves unsigned.
+    // Anything else is a DnsError::Bogus. `resolver` is how we get hold of the keys
+    pub(crate) fn validate(
+        &self,
+        resolver: &Resolver,
+        res: &DnsMessage,
+    ) -> Result<Security, DnsError> {
+        let qname = &res.question().qname;
+        let qtype = res.question().qtype;
+        let nxdomain = res.rcode() == RCODE_NXDOMAIN;
+        if !matches!(res.rcode(), 0 | RCODE_NXDOMAIN)
+            || !self.anchors.iter().any(|a| qname.is_subdomain_of(&a.zone))
+        {
+            return Ok(Security::Indeterminate);
+        }
+        let mut security = self.check_rrsets(resolver, &res.answers)?;
+
+        // the end of the CNAME chain, where the records (or the lack of them) are
+        let name = res.canonical_name();
+        let denied = nxdomain
+            || (qtype != u16::from(RecordType::Any)
+                && !res.answers.iter().any(|rr| rr.rr_type == qtype));
+        let expanded = expanded_from_wildcard(&res.answers);
+        if !denied && expanded.is_empty() {
+            return Ok(security);
+        }
+        if rrsets(&res.authority).is_empty() {
+            return self
+                .unsigned(resolver, &name)
+                .map(|status| weakest(security, status));
+        }
+        let status = self.check_rrsets(resolver, &res.authority)?;
+        if status == Security::Secure {
+            let mut proven = Some(Security::Secure);
+            if denied {
+                proven = proven
+                    .zip(proves_denial(&res.authority, &name, qtype, nxdomain))
+                    .map(|(a, b)| weakest(a, b));
+            }
+            for (owner, labels) in &expanded {
+                proven = proven
+                    .zip(proves_expansion(&res.authority, owner, *labels))
+                    .map(|(a, b)| weakest(a, b));
+            }
+            match proven {
+                Some(proven) => security = weakest(security, proven),
+                None => return Err(DnsError::Bogus("no proof the name doesn't exist")),
+            }
+        }
+        Ok(weakest(security, status))
+    }
+
+    // every RRset of `section` against its signatures, see validate
+    fn check_rrsets(
+        &self,
+        resolver: &Resolver,
+        section: &[ResourceRecord],
+    ) -> Result<Security, DnsError> {
+        let mut security = Security::Secure;
+        for (owner, rr_type, records) in rrsets(section) {
+            let sigs = sigs_over(section, &owner, rr_type);
+            // a server answering from a DNAME makes the CNAME up on the spot, only the DNAME
+            // is signed
+            let synthesized = rr_type == TYPE_CNAME
+                && section.iter().any(|rr| {
+                    rr.rr_type == TYPE_DNAME && owner != rr.name && owner.is_subdomain_of(&rr.name)
+                });
+            if sigs.is_empty() && synthesized {
+                continue;
+            }
+            let status = if sigs.is_empty() {
+                self.unsigned(resolver, &owner)?
+            } else {
+                self.check(resolver, &owner, &records, &sigs)?
+            };
+            security = weakest(security, status);
+        }
+        Ok(security)
+    }
+
+    // Secure if one of `sigs` over the RRset verifies with its zone's keys, Insecure if that
+    // zone turns out to be unsigned
+    fn check(
+        &self,
+        resolver: &Resolver,
+        owner: &Name,
+        records: &[&ResourceRecord],
+        sigs: &[&Rrsig],
+    ) -> Result<Security, DnsError> {
+        let mut reason = "no signature verifies";
+        for sig in sigs {
+            let signer = Name::from(sig.signer.as_str());
+            if !owner.is_subdomain_of(&signer) {
+                reason = "signed by a zone the name isn't in";
+                continue;
+            }
+            let keys = match self.zone_keys(resolver, &signer)? {
+                ZoneKeys::Insecure => return Ok(Security::Insecure),
+                ZoneKeys::Secure(keys) => keys,
+            };
+            if keys
+                .iter()
+                .any(|key| verify(records, sig, key, now()).is_ok())
+            {
+                return Ok(Security::Secure);
+            }
+        }
+        Err(DnsError::Bogus(reason))
+    }
+
+    // `zone`'s keys, checked against the DS records its parent has for it (or against our
+    // trust anchors, for the zone they're for)
+    fn zone_keys(&self, resolver: &Resolver, zone: &Name) -> Result<ZoneKeys, DnsError> {
+        if let Some(keys) = self.known(zone) {
+            return Ok(keys);
+        }
+        let anchored: Vec<Ds> = self
+            .anchors
+            .iter()
+            .filter(|anchor| anchor.zone == *zone)
+            .map(|anchor| anchor.ds.clone())
+            .collect();
+        let ds = match anchored.is_empty() {
+            false => anchored,
+            true => match self.ds_of(resolver, zone)? {
+                Some(ds) => ds,
+                None => return Ok(self.remember(zone, ZoneKeys::Insecure, INSECURE_TTL)),
+            },
+        };
+        // a zone whose DS records are all for algorithms we don't know counts as unsigned
+        // (RFC 4035 5.2), we have no way of telling good signatures from bad ones
+        let ds: Vec<Ds> = ds
+            .into_iter()
+            .filter(|ds| supported(ds.algorithm) && ds_digest(zone, None, ds.digest_type).is_some())
+            .collect();
+        if ds.is_empty() {
+            return Ok(self.remember(zone, ZoneKeys::Insecure, INSECURE_TTL));
+        }
+
+        let res = resolver.query(&question(zone.clone(), TYPE_DNSKEY))?;
+        let records: Vec<&ResourceRecord> = res
+            .answers
+            .iter()
+            .filter(|rr| rr.rr_type == TYPE_DNSKEY && rr.name == *zone)
+            .collect();
+        let keys: Vec<Dnskey> = records
+            .iter()
+            .filter_map(|rr| match &rr.data {
+                RData::DNSKEY(key) => Some(key.clone()),
+                _ => None,
+            })
+            .collect();
+        let entry: Vec<&Dnskey> = keys
+            .iter()
+            .filter(|key| ds.iter().any(|ds| matches_ds(zone, key, ds)))
+            .collect();
+        if entry.is_empty() {
+            return Err(DnsError::Bogus("no DNSKEY matches the DS"));
+        }
+        let signed = res.answers.iter().any(|rr| match &rr.data {
+            RData::RRSIG(sig) if sig.type_covered == TYPE_DNSKEY && rr.name == *zone => entry
+                .iter()
+                .any(|key| verify(&records, sig, key, now()).is_ok()),
+            _ => false,
+        });
+        if !signed {
+            return Err(DnsError::Bogus(
+                "DNSKEY RRset isn't signed by a key the DS vouches for",
+            ));
+        }
+
+        let ttl = records.iter().map(|rr| rr.ttl).min().unwrap_or(0);
+        let keys = keys.into_iter().filter(Dnskey::is_zone_key).collect();
+        Ok(self.remember(
+            zone,
+            ZoneKeys::Secure(keys),
+            Duration::from_secs(ttl.min(MAX_KEYS_TTL) as u64),
+        ))
+    }
+
+    // the DS records for `zone`, validated with its parent's keys. None when there are none,
+    // or the parent is unsigned itself: either way the zone is insecure
+    fn ds_of(&self, resolver: &Resolver, zone: &Name) -> Result<Option<Vec<Ds>>, DnsError> {
+        let res = resolver.query(&question(zone.clone(), TYPE_DS))?;
+        let records: Vec<&ResourceRecord> = res
+            .answers
+            .iter()
+            .filter(|rr| rr.rr_type == TYPE_DS && rr.name == *zone)
+            .collect();
+        // the DS lives in the parent, a zone can't vouch for its own keys. Nor can it say it
+        // has none: that's for the parent to prove, unless it's unsigned itself
+        let parent = zone
+            .parent()
+            .ok_or(DnsError::Bogus("a DS for the root zone"))?;
+        let from_parent = |rr: &&ResourceRecord| match &rr.data {
+            RData::RRSIG(sig) => !names_equal(&sig.signer, zone.as_str()),
+            _ => true,
+        };
+        if records.is_empty() {
+            let proof: Vec<ResourceRecord> = res
+                .authority
+                .iter()
+                .filter(|rr| matches!(rr.rr_type, TYPE_NSEC | TYPE_NSEC3 | TYPE_RRSIG))
+                .filter(from_parent)
+                .cloned()
+                .collect();
+            let mut security = Security::Secure;
+            for (owner, rr_type, records) in rrsets(&proof) {
+                let sigs = sigs_over(&proof, &owner, rr_type);
+                let status = match sigs.is_empty() {
+                    true => self.unsigned(resolver, &parent)?,
+                    false => self.check(resolver, &owner, &records, &sigs)?,
+                };
+                security = weakest(security, status);
+            }
+            if rrsets(&proof).is_empty() {
+                self.unsigned(resolver, &parent)?;
+            } else if security == Security::Secure
+                && proves_denial(&proof, zone, TYPE_DS, false).is_none()
+            {
+                return Err(DnsError::Bogus("no proof the DS doesn't exist"));
+            }
+            return Ok(None);
+        }
+        let answers: Vec<ResourceRecord> =
+            res.answers.iter().filter(from_parent).cloned().collect();
+        let sigs = sigs_over(&answers, zone, TYPE_DS);
+        if sigs.is_empty() {
+            return self.unsigned(resolver, &parent).map(|_| None);
+        }
+        match self.check(resolver, zone, &records, &sigs)? {
+            Security::Secure => Ok(Some(
+                records
+                    .iter()
+                    .filter_map(|rr| match &rr.data {
+                        RData::DS(ds) => Some(ds.clone()),
+                        _ => None,
+                    })
+                    .collect(),
+            )),
+            _ => Ok(None),
+        }
+    }
+
+    // records for `name` came without signatures: fine if they're in a zone below an insecure
+    // delegation, bogus if every zone from the trust anchor down to them is signed. We go down
+    // from the anchor a label at a time; a name with DS records is a signed zone, one with an
+    // SOA but no DS an unsigned one, anything else no zone of its own
+    fn unsigned(&self, resolver: &Resolver, name: &Name) -> Result<Security, DnsError> {
+        let Some(anchor) = self
+            .anchors
+            .iter()
+            .filter(|anchor| name.is_subdomain_of(&anchor.zone))
+            .max_by_key(|anchor| anchor.zone.labels().len())
+        else {
+            return Ok(Security::Indeterminate);
+        };
+        let mut below = Vec::new();
+        let mut current = name.clone();
+        while current != anchor.zone {
+            below.push(current.clone());
+            match current.parent() {
+                Some(parent) => current = parent,
+                None => break,
+            }
+        }
+        for zone in below.into_iter().rev() {
+            if let ZoneKeys::Insecure = self.zone_keys_if_cut(resolver, &zone)? {
+                return Ok(Security::Insecure);
+            }
+        }
+        Err(DnsError::Bogus("unsigned records in a signed zone"))
+    }
+
+    // zone_keys for a name that may or may not be where a zone starts. One that isn't counts as
+    // secure, it's part of the signed zone above it
+    fn zone_keys_if_cut(&self, resolver: &Resolver, name: &Name) -> Result<ZoneKeys, DnsError> {
+        if let Some(keys) = self.known(name) {
+            return Ok(keys);
+        }
+        if self.ds_of(resolver, name)?.is_some() {
+            return self.zone_keys(resolver, name);
+        }
+        let res = resolver.query(&question(name.clone(), TYPE_SOA))?;
+        let apex = res
+            .answers
+            .iter()
+            .any(|rr| rr.rr_type == TYPE_SOA && rr.name == *name);
+        if apex {
+            return Ok(self.remember(name, ZoneKeys::Insecure, INSECURE_TTL));
+        }
+        Ok(ZoneKeys::Secure(Vec::new()))
+    }
+
+    fn known(&self, zone: &Name) -> Option<ZoneKeys> {
+        let mut zones = self.zones.lock().unwrap();
+        match zones.get(zone) {
+            Some((keys, expires)) if *expires > Instant::now() => Some(keys.clone()),
+            Some(_) => {
+                zones.remove(zone);
+                None
+            }
+            None => None,
+        }
+    }
+
+    fn remember(&self, zone: &Name, keys: ZoneKeys, ttl: Duration) -> ZoneKeys {
+        let expires = Instant::now() + ttl;
+        self.zones
+            .lock()
+            .unwrap()
+            .insert(zone.clone(), (keys.clone(), expires));
+        keys
+    }
+}
+
+// the queries the validator makes for keys: signatures wanted, and everything handed over
+pub(crate) fn question(name: Name, qtype: u16) -> DnsMessage {
+    DnsMessage::query(name)
+        .qtype(qtype)
+        .dnssec_ok(true)
+        .checking_disabled(true)
+        .build()
+}
+
+// the records of a section grouped into RRsets (same name, same type), in order of first
+// appearance. The signatures aren't an RRset of their own for this
+fn rrsets(section: &[ResourceRecord]) -> Vec<(Name, u16, Vec<&ResourceRecord>)> {
+    let mut sets: Vec<(Name, u16, Vec<&ResourceRecord>)> = Vec::new();
+    for rr in section.iter().filter(|rr| rr.rr_type != TYPE_RRSIG) {
+        match sets
+            .iter_mut()
+            .find(|(name, rr_type, _)| *name == rr.name && *rr_type == rr.rr_type)
+        {
+            Some((_, _, records)) => records.push(rr),
+            None => sets.push((rr.name.clone(), rr.rr_type, vec![rr])),
+        }
+    }
+    sets
+}
+
+// the signatures in `section` over the RRset of `rr_type` at `owner`
+fn sigs_over<'a>(section: &'a [ResourceRecord], owner: &Name, rr_type: u16) -> Vec<&'a Rrsig> {
+    section
+        .iter()
+        .filter(|rr| rr.name == *owner)
+        .filter_map(|rr| match &rr.data {
+            RData::RRSIG(sig) if sig.type_covered == rr_type => Some(sig),
+            _ => None,
+        })
+        .collect()
+}
+
+// the less trusted of the two
+fn weakest(a: Security, b: Security) -> Security {
+    match (a, b) {
+        (Security::Secure, other) | (other, Security::Secure) => other,
+        (Security::Insecure, _) | (_, Security::Insecure) => Security::Insecure,
+        _ => Security::Indeterminate,
+    }
+}
+
+// the answers that were made up from a wildcard, with how many labels the wildcard's parent has
+fn expanded_from_wildcard(answers: &[ResourceRecord]) -> Vec<(Name, u8)> {
+    let mut expanded: Vec<(Name, u8)> = Vec::new();
+    for rr in answers {
+        let RData::RRSIG(sig) = &rr.data else {
+            continue;
+        };
+        let labels = rr.name.labels();
+        let wildcard = labels.first().is_some_and(|label| label == b"*");
+        if (sig.labels as usize) < labels.len() - wildcard as usize
+            && !expanded.iter().any(|(name, _)| *name == rr.name)
+        {
+            expanded.push((rr.name.clone(), sig.labels));
+        }
+    }
+    expanded
+}
+
+// ordering of names as DNSSEC sorts them (RFC 4034 6.1): label by label from the right, in
+// lowercase, a name before the ones below it
+pub(crate) fn canonical_cmp(a: &Name, b: &Name) -> Ordering {
+    let labels = |name: &Name| -> Vec<Vec<u8>> {
+        let mut labels = name.labels();
+        labels.reverse();
+        labels
+            .iter_mut()
+            .for_each(|label| label.make_ascii_lowercase());
+        labels
+    };
+    labels(a).cmp(&labels(b))
+}
+
+// whether `name` falls in the gap between `owner` and `next`. The last NSEC of a zone points
+// back at the apex, its gap is everything after it
+fn covers(owner: &Name, next: &Name, name: &Name) -> bool {
+    let after = canonical_cmp(owner, name) == Ordering::Less;
+    let before = canonical_cmp(name, next) == Ordering::Less;
+    match canonical_cmp(owner, next) {
+        Ordering::Less => after && before,
+        _ => after || before,
+    }
+}
+
+// the deepest name above both
+fn common_ancestor(a: &Name, b: &Name) -> Name {
+    let mut ancestor = a.clone();
+    while !b.is_subdomain_of(&ancestor) {
+        ancestor = ancestor.parent().unwrap_or_else(Name::root);
+    }
+    ancestor
+}
+
+fn wildcard_at(name: &Name) -> Name {
+    match name.is_root() {
+        true => Name::from("*"),
+        false => Name::from(format!("*.{}", name)),
+    }
+}
+
+// whether the NSEC or NSEC3 records of `section` prove that `name` has no `qtype` records
+// (NODATA), or with `nxdomain` that it doesn't exist at all, and no wildcard stands in for it.
+// Secure when they do, Insecure when they could only say so much with NSEC3 opt-out (or
+// parameters we won't hash with), None when they don't prove it. The signatures over them are
+// for the caller to check
+pub(crate) fn proves_denial(
+    section: &[ResourceRecord],
+    name: &Name,
+    qtype: u16,
+    nxdomain: bool,
+) -> Option<Security> {
+    let nsecs: Vec<(Name, &Nsec)> = section
+        .iter()
+        .filter_map(|rr| match &rr.data {
+            RData::NSEC(nsec) => Some((rr.name.clone(), nsec)),
+            _ => None,
+        })
+        .collect();
+    if !nsecs.is_empty() {
+        return nsec_denial(&nsecs, name, qtype, nxdomain).then_some(Security::Secure);
+    }
+    let nsec3s: Vec<(Name, &Nsec3)> = section
+        .iter()
+        .filter_map(|rr| match &rr.data {
+            RData::NSEC3(nsec3) => Some((rr.name.clone(), nsec3)),
+            _ => None,
+        })
+        .collect();
+    Nsec3Proof::new(&nsec3s)?.denial(name, qtype, nxdomain)
+}
+
+// whether the records at `name` with `types` say nothing of `qtype`. A CNAME there would have
+// been the answer instead, and an NSEC at a zone's apex is the child's, which knows nothing of
+// the DS records in the parent
+fn lacks(types: &[u16], qtype: u16, name: &Name) -> bool {
+    !types.contains(&qtype)
+        && !types.contains(&TYPE_CNAME)
+        && (qtype != TYPE_DS || name.is_root() || !types.contains(&TYPE_SOA))
+}
+
+fn nsec_denial(nsecs: &[(Name, &Nsec)], name: &Name, qtype: u16, nxdomain: bool) -> bool {
+    let next = |nsec: &Nsec| Name::from(nsec.next.as_str());
+    if !nxdomain {
+        let nodata = nsecs.iter().any(|(owner, nsec)| {
+            // the name itself, or an empty non-terminal: nothing of its own but names below it
+            (owner == name && lacks(&nsec.types, qtype, name))
+                || (covers(owner, &next(nsec), name) && next(nsec).is_subdomain_of(name))
+        });
+        if nodata {
+            return true;
+        }
+    }
+    let Some((owner, nsec)) = nsecs.iter().find(|(owner, nsec)| {
+        covers(owner, &next(nsec), name) && !next(nsec).is_subdomain_of(name)
+    }) else {
+        return false;
+    };
+    // no name, and the wildcard that would have matched it isn't there either (NXDOMAIN), or
+    // has no `qtype` (NODATA)
+    let closest = common_ancestor(name, owner);
+    let closest = match common_ancestor(name, &next(nsec)) {
+        other if other.labels().len() > closest.labels().len() => other,
+        _ => closest,
+    };
+    let wildcard = wildcard_at(&closest);
+    nsecs.iter().any(|(owner, nsec)| match nxdomain {
+        true => covers(owner, &next(nsec), &wildcard),
+        false => *owner == wildcard && lacks(&nsec.types, qtype, &wildcard),
+    })
+}
+
+// a record at `owner` was made up from a wildcard with `labels` labels above it: the name the
+// query was for must not exist itself, or it would have been the answer
+fn proves_expansion(section: &[ResourceRecord], owner: &Name, labels: u8) -> Option<Security> {
+    let mut closest = owner.clone();
+    let mut next_closer = owner.clone();
+    while closest.labels().len() > labels as usize {
+        next_closer = closest.clone();
+        closest = closest.parent()?;
+    }
+    for rr in section {
+        match &rr.data {
+            RData::NSEC(nsec) if covers(&rr.name, &Name::from(nsec.next.as_str()), owner) => {
+                return Some(Security::Secure)
+            }
+            _ => {}
+        }
+    }
+    let nsec3s: Vec<(Name, &Nsec3)> = section
+        .iter()
+        .filter_map(|rr| match &rr.data {
+            RData::NSEC3(nsec3) => Some((rr.name.clone(), nsec3)),
+            _ => None,
+        })
+        .collect();
+    let proof = Nsec3Proof::new(&nsec3s)?;
+    if proof.unusable() {
+        return Some(Security::Insecure);
+    }
+    let cover = proof.covering(&next_closer)?;
+    Some(match cover.opt_out() {
+        true => Security::Insecure,
+        false => Security::Secure,
+    })
+}
+
+// the NSEC3 records of one answer, all from the one zone with the same hash parameters
+struct Nsec3Proof<'a> {
+    zone: Name,
+    params: &'a Nsec3,
+    // the owner hashes, decoded from the first label
+    records: Vec<(Vec<u8>, &'a Nsec3)>,
+}
+
+impl<'a> Nsec3Proof<'a> {
+    fn new(nsec3s: &[(Name, &'a Nsec3)]) -> Option<Self> {
+        let (first, params) = nsec3s.first()?;
+        let zone = first.parent()?;
+        let records = nsec3s
+            .iter()
+            .filter(|(owner, nsec3)| {
+                owner.parent().as_ref() == Some(&zone)
+                    && nsec3.hash_algorithm == params.hash_algorithm
+                    && nsec3.iterations == params.iterations
+                    && nsec3.salt == params.salt
+            })
+            .filter_map(|(owner, nsec3)| Some((from_base32hex(&owner.labels()[0])?, *nsec3)))
+            .collect();
+        Some(Nsec3Proof {
+            zone,
+            params,
+            records,
+        })
+    }
+
+    // SHA-1 is the only hash there is; past the iteration limit we don't try
+    fn unusable(&self) -> bool {
+        self.params.hash_algorithm != 1 || self.params.iterations > MAX_NSEC3_ITERATIONS
+    }
+
+    fn hash(&self, name: &Name) -> Vec<u8> {
+        nsec3_hash(name, &self.params.salt, self.params.iterations)
+    }
+
+    fn matching(&self, name: &Name) -> Option<&'a Nsec3> {
+        let hash = self.hash(name);
+        self.records
+            .iter()
+            .find(|(owner, _)| *owner == hash)
+            .map(|(_, nsec3)| *nsec3)
+    }
+
+    fn covering(&self, name: &Name) -> Option<&'a Nsec3> {
+        let hash = self.hash(name);
+        self.records
+            .iter()
+            .find(|(owner, nsec3)| {
+                let next = &nsec3.next_hashed;
+                match owner < next {
+                    true => *owner < hash && hash < *next,
+                    false => *owner < hash || hash < *next,
+                }
+            })
+            .map(|(_, nsec3)| *nsec3)
+    }
+
+    // see proves_denial. No name, unless its hash is there, so we look for the closest encloser
+    // (RFC 5155 7.2.1): the deepest name above it whose hash is there, with the hash of the
+    // name one label below that (the next closer) and of the wildcard at it both covered
+    fn denial(&self, name: &Name, qtype: u16, nxdomain: bool) -> Option<Security> {
+        if self.unusable() {
+            return Some(Security::Insecure);
+        }
+        if !nxdomain {
+            if let Some(nsec3) = self.matching(name) {
+                return lacks(&nsec3.types, qtype, name).then_some(Security::Secure);
+            }
+        }
+        let mut closest = name.clone();
+        let mut next_closer = None;
+        while self.matching(&closest).is_none() {
+            if closest == self.zone {
+                return None;
+            }
+            next_closer = Some(closest.clone());
+            closest = closest.parent()?;
+        }
+        let cover = self.covering(&next_closer?)?;
+        // opt-out: the name could be an unsigned delegation the zone left out
+        let security = match cover.opt_out() {
+            true => Security::Insecure,
+            false => Security::Secure,
+        };
+        let wildcard = wildcard_at(&closest);
+        if nxdomain {
+            return self.covering(&wildcard).map(|_| security);
+        }
+        if qtype == TYPE_DS && cover.opt_out() {
+            return Some(Security::Insecure);
+        }
+        let nsec3 = self.matching(&wildcard)?;
+        lacks(&nsec3.types, qtype, &wildcard).then_some(security)
+    }
+}
+
+// the NSEC3 hash of `name` (RFC 5155 5): SHA-1 over the name in lowercase wire form and the
+// salt, then `iterations` more times over the hash and the salt
+pub fn nsec3_hash(name: &Name, salt: &[u8], iterations: u16) -> Vec<u8> {
+    let mut data = name.to_wire().unwrap_or_default().to_ascii_lowercase();
+    data.extend(salt);
+    let mut hash = digest::digest(&digest::SHA1_FOR_LEGACY_USE_ONLY, &data);
+    for _ in 0..iterations {
+        let mut data = hash.as_ref().to_vec();
+        data.extend(salt);
+        hash = digest::digest(&digest::SHA1_FOR_LEGACY_USE_ONLY, &data);
+    }
+    hash.as_ref().to_vec()
+}
+
+// seconds since the epoch, in the 32 bits RRSIGs keep them in
+pub(crate) fn now() -> u32 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs() as u32
+}
+
+// the algorithms we can check signatures for
+fn supported(algorithm: u8) -> bool {
+    matches!(algorithm, 5 | 7 | 8 | 10 | 13 | 14 | 15)
+}
+
+// checks `sig` over the RRset `records` with `key`: the key has to be the one the signature
+// names, `now` inside its validity period, and the signature has to match
+pub fn verify(
+    records: &[&ResourceRecord],
+    sig: &Rrsig,
+    key: &Dnskey,
+    now: u32,
+) -> Result<(), DnsError> {
+    if key.algorithm != sig.algorithm
+        || key.key_tag() != sig.key_tag
+        || key.protocol != PROTOCOL
+        || !key.is_zone_key()
+    {
+        return Err(DnsError::Bogus("signed with a different key"));
+    }
+    // serial number arithmetic (RFC 1982), the times wrap around in 2106
+    if (now.wrapping_sub(sig.inception) as i32) < 0 || (sig.expiration.wrapping_sub(now) as i32) < 0
+    {
+        return Err(DnsError::Bogus("signature expired or not valid yet"));
+    }
+    let data = signed_data(records, sig)?;
+    if verify_signature(key, &data, &sig.signature) {
+        Ok(())
+    } else {
+        Err(DnsError::Bogus("signature doesn't match"))
+    }
+}
+
+// what the signature is over (RFC 4034 3.1.8.1): the RRSIG's own fields minus the signature,
+// then every record in canonical form and order
+pub(crate) fn signed_data(records: &[&ResourceRecord], sig: &Rrsig) -> Result<Vec<u8>, DnsError> {
+    let first = records
+        .first()
+        .ok_or(DnsError::Bogus("signature over no records"))?;
+    let labels = first.name.labels().len();
+    if sig.labels as usize > labels {
+        return Err(DnsError::Bogus("signature has more labels than its owner"));
+    }
+    // a record made up from a wildcard was signed as the wildcard
+    let mut owner = first.name.clone();
+    for _ in sig.labels as usize..labels {
+        owner = owner.parent().unwrap();
+    }
+    if (sig.labels as usize) < labels {
+        owner = Name::from(format!("*.{}", owner));
+    }
+    let owner = owner.to_wire()?.to_ascii_lowercase();
+
+    let mut head = sig.clone();
+    head.signature.clear();
+    head.signer = head.signer.to_ascii_lowercase();
+    let mut data = Vec::new();
+    RData::RRSIG(head).encode(&mut data)?;
+
+    let mut rdatas = records
+        .iter()
+        .map(|rr| canonical_rdata(rr))
+        .collect::<Result<Vec<_>, _>>()?;
+    rdatas.sort();
+    rdatas.dedup();
+    for rdata in rdatas {
+        data.extend(&owner);
+        data.extend(first.rr_type.to_be_bytes());
+        data.extend(first.class.to_be_bytes());
+        data.extend(sig.original_ttl.to_be_bytes());
+        data.extend((rdata.len() as u16).to_be_bytes());
+        data.extend(rdata);
+    }
+    Ok(data)
+}
+
+// the rdata as it came, what the zone signed. Except for the types RFC 4034 6.2 (as cut down by
+// RFC 6840 5.1) says have their names written out in full and in lowercase: those are all names
+// and numbers, so they can be rebuilt from `data` without losing anything, and have to be, as
+// the names in the wire rdata can be compressed
+fn canonical_rdata(rr: &ResourceRecord) -> Result<Vec<u8>, DnsError> {
+    let lower = |name: &String| name.to_ascii_lowercase();
+    let canonical = match &rr.data {
+        RData::NS(name) => RData::NS(lower(name)),
+        RData::CNAME(name) => RData::CNAME(lower(name)),
+        RData::PTR(name) => RData::PTR(lower(name)),
+        RData::DNAME(name) => RData::DNAME(lower(name)),
+        RData::MX {
+            preference,
+            exchange,
+        } => RData::MX {
+            preference: *preference,
+            exchange: lower(exchange),
+        },
+        RData::SOA(soa) => {
+            let mut soa = soa.clone();
+            soa.mname = lower(&soa.mname);
+            soa.rname = lower(&soa.rname);
+            RData::SOA(soa)
+        }
+        RData::SRV(srv) => {
+            let mut srv = srv.clone();
+            srv.target = lower(&srv.target);
+            RData::SRV(srv)
+        }
+        RData::RRSIG(sig) => {
+            let mut sig = sig.clone();
+            sig.signer = lower(&sig.signer);
+            RData::RRSIG(sig)
+        }
+        _ => return Ok(rr.rdata.clone()),
+    };
+    let mut rdata = Vec::new();
+    canonical.encode(&mut rdata)?;
+    Ok(rdata)
+}
+
+// whether `key` is the one `ds` is the hash of
+pub(crate) fn matches_ds(zone: &Name, key: &Dnskey, ds: &Ds) -> bool {
+    key.algorithm == ds.algorithm
+        && key.key_tag() == ds.key_tag
+        && ds_digest(zone, Some(key), ds.digest_type).is_some_and(|digest| digest == ds.digest)
+}
+
+// the DS digest of `key` as the key of `zone`: the hash of the owner name and the DNSKEY rdata.
+// None for a digest type we don't know. With no key, just whether we know the type
+pub(crate) fn ds_digest(zone: &Name, key: Option<&Dnskey>, digest_type: u8) -> Option<Vec<u8>> {
+    let algorithm = match digest_type {
+        1 => &digest::SHA1_FOR_LEGACY_USE_ONLY,
+        2 => &digest::SHA256,
+        4 => &digest::SHA384,
+        _ => return None,
+    };
+    let Some(key) = key else {
+        return Some(Vec::new());
+    };
+    let mut data = zone.to_wire().ok()?.to_ascii_lowercase();
+    RData::DNSKEY(key.clone()).encode(&mut data).ok()?;
+    Some(digest::digest(algorithm, &data).as_ref().to_vec())
+}
+
+fn verify_signature(key: &Dnskey, data: &[u8], sig: &[u8]) -> bool {
+    let rsa = |params: &'static signature::RsaParameters| {
+        let Some((e, n)) = rsa_key(&key.public_key) else {
+            return false;
+        };
+        RsaPublicKeyComponents { n, e }
+            .verify(params, data, sig)
+            .is_ok()
+    };
+    // ring wants the uncompressed point with its 0x04 in front, DNSKEY has just x and y
+    let ecdsa = |alg: &'static signature::EcdsaVerificationAlgorithm| {
+        let mut point = vec![4];
+        point.extend(&key.public_key);
+        UnparsedPublicKey::new(alg, point).verify(data, sig).is_ok()
+    };
+    match key.algorithm {
+        // RSASHA1 and RSASHA1-NSEC3-SHA1
+        5 | 7 => rsa(&signature::RSA_PKCS1_1024_8192_SHA1_FOR_LEGACY_USE_ONLY),
+        // 1024 bit RSA keys are still out there, ring only calls them legacy
+        8 => rsa(&signature::RSA_PKCS1_1024_8192_SHA256_FOR_LEGACY_USE_ONLY),
+        10 => rsa(&signature::RSA_PKCS1_1024_8192_SHA512_FOR_LEGACY_USE_ONLY),
+        13 => ecdsa(&signature::ECDSA_P256_SHA256_FIXED),
+        14 => ecdsa(&signature::ECDSA_P384_SHA384_FIXED),
+        15 => UnparsedPublicKey::new(&signature::ED25519, &key.public_key)
+            .verify(data, sig)
+            .is_ok(),
+        _ => false,
+    }
+}
+
+// an RSA DNSKEY (RFC 3110): the exponent's length in one byte, or a zero and then two bytes
+// for a long one, the exponent, and the rest is the modulus
+fn rsa_key(key: &[u8]) -> Option<(&[u8], &[u8])> {
+    let (len, rest) = match key {
+        [0, hi, lo, rest @ ..] => (u16::from_be_bytes([*hi, *lo]) as usize, rest),
+        [len, rest @ ..] => (*len as usize, rest),
+        [] => return None,
+    };
+    if len == 0 || rest.len() <= len {
+        return None;
+    }
+    let (e, n) = rest.split_at(len);
+    let skip = n.iter().take_while(|b| **b == 0).count();
+    Some((e, &n[skip..]))
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::encoding::to_base32hex;
+    use crate::test_util::{mock_udp_server, with_id};
+    use ring::rand::SystemRandom;
+    use ring::signature::{Ed25519KeyPair, KeyPair};
+
+    // a zone's key pair, Ed25519 because it's the quickest to make
+    pub(crate) struct ZoneKey {
+        pub zone: Name,
+        pub dnskey: Dnskey,
+        pair: Ed25519KeyPair,
+    }
+
+    impl ZoneKey {
+        pub fn new(zone: &str) -> Self {
+            let pkcs8 = Ed25519KeyPair::generate_pkcs8(&SystemRandom::new()).unwrap();
+            let pair = Ed25519KeyPair::from_pkcs8(pkcs8.as_ref()).unwrap();
+            ZoneKey {
+                zone: Name::from(zone),
+                dnskey: Dnskey {
+                    flags: 0x0101,
+                    protocol: PROTOCOL,
+                    algorithm: 15,
+                    public_key: pair.public_key().as_ref().to_vec(),
+                },
+                pair,
+            }
+        }
+
+        pub fn ds(&self) -> Ds {
+            Ds {
+                key_tag: self.dnskey.key_tag(),
+                algorithm: 15,
+                digest_type: 2,
+                digest: ds_digest(&self.zone, Some(&self.dnskey), 2).unwrap(),
+            }
+        }
+
+        pub fn anchor(&self) -> TrustAnchor {
+            TrustAnchor {
+                zone: self.zone.clone(),
+                ds: self.ds(),
+            }
+        }
+
+        // the RRSIG record over `records` (one RRset), valid from an hour ago for a day
+        pub fn sign(&self, records: &[ResourceRecord]) -> ResourceRecord {
+            let first = &records[0];
+            // a wildcard's "*" doesn't count
+            let labels = first.name.labels();
+            let wildcard = labels.first().is_some_and(|label| label == b"*");
+            let mut sig = Rrsig {
+                type_covered: first.rr_type,
+                algorithm: 15,
+                labels: (labels.len() - wildcard as usize) as u8,
+                original_ttl: first.ttl,
+                expiration: now() + 86400,
+                inception: now() - 3600,
+                key_tag: self.dnskey.key_tag(),
+                signer: self.zone.to_string(),
+                signature: Vec::new(),
+            };
+            let refs: Vec<&ResourceRecord> = records.iter().collect();
+            let data = signed_data(&refs, &sig).unwrap();
+            sig.signature = self.pair.sign(&data).as_ref().to_vec();
+            ResourceRecord::new(first.name.clone(), first.ttl, RData::RRSIG(sig))
+        }
+    }
+
+    fn a(name: &str, ip: [u8; 4]) -> ResourceRecord {
+        ResourceRecord::new(name, 300, RData::A(ip.into()))
+    }
+
+    #[test]
+    fn test_verify() {
+        let key = ZoneKey::new("example.com");
+        let records = [
+            a("www.example.com", [192, 0, 2, 1]),
+            a("www.example.com", [192, 0, 2, 2]),
+        ];
+        let RData::RRSIG(sig) = key.sign(&records).data else {
+            unreachable!()
+        };
+        let refs: Vec<&ResourceRecord> = records.iter().collect();
+        assert!(verify(&refs, &sig, &key.dnskey, now()).is_ok());
+
+        // the order of the records and the case of the owner don't matter
+        let shuffled = [
+            a("WWW.Example.COM", [192, 0, 2, 2]),
+            a("www.example.com", [192, 0, 2, 1]),
+        ];
+        let refs: Vec<&ResourceRecord> = shuffled.iter().collect();
+        assert!(verify(&refs, &sig, &key.dnskey, now()).is_ok());
+
+        // a changed address, a signature past its expiry and someone else's key don't pass
+        let forged = [
+            a("www.example.com", [192, 0, 2, 1]),
+            a("www.example.com", [192, 0, 2, 66]),
+        ];
+        let refs: Vec<&ResourceRecord> = forged.iter().collect();
+        assert!(verify(&refs, &sig, &key.dnskey, now()).is_err());
+        let refs: Vec<&ResourceRecord> = records.iter().collect();
+        assert!(verify(&refs, &sig, &key.dnskey, sig.expiration + 1).is_err());
+        let other = ZoneKey::new("example.com");
+        assert!(verify(&refs, &sig, &other.dnskey, now()).is_err());
+    }
+
+    #[test]
+    fn test_signed_rdata_is_the_wire_rdata() {
+        // an HTTPS record with an ALPN id that isn't UTF-8, which the typed form can't hold as
+        // it is: what was signed is what was sent
+        let wire = b"\x00\x01\x00\x00\x01\x00\x02\x01\xff";
+        let data = RData::decode(crate::rdata::TYPE_HTTPS, wire, 0, wire.len() as u16).unwrap();
+        let mut rr = ResourceRecord::new("example.com", 300, data.clone());
+        rr.rdata = wire.to_vec();
+        rr.rdlength = wire.len() as u16;
+
+        let key = ZoneKey::new("example.com");
+        let RData::RRSIG(sig) = key.sign(&[rr.clone()]).data else {
+            unreachable!()
+        };
+        assert!(signed_data(&[&rr], &sig).unwrap().ends_with(wire));
+        assert!(verify(&[&rr], &sig, &key.dnskey, now()).is_ok());
+        let rebuilt = ResourceRecord::new("example.com", 300, data);
+        assert_ne!(rebuilt.rdata, wire);
+        assert!(verify(&[&rebuilt], &sig, &key.dnskey, now()).is_err());
+    }
+
+    #[test]
+    fn test_wildcard_answers_verify_as_the_wildcard() {
+        let key = ZoneKey::new("example.com");
+        let RData::RRSIG(sig) = key.sign(&[a("*.example.com", [192, 0, 2, 9])]).data else {
+            unreachable!()
+        };
+        let expanded = a("anything.example.com", [192, 0, 2, 9]);
+        assert!(verify(&[&expanded], &sig, &key.dnskey, now()).is_ok());
+    }
+
+    #[test]
+    fn test_root_anchors() {
+        let anchors = TrustAnchor::root();
+        assert_eq!(anchors.len(), 2);
+        assert!(anchors.iter().all(|anchor| anchor.zone.is_root()));
+        assert_eq!(anchors[0].ds.key_tag, 20326);
+        assert_eq!(anchors[0].ds.digest.len(), 32);
+    }
+
+    // a mock upstream serving every zone of `zones` (each with its RRSIGs, and NSEC records
+    // for the signed ones): a query goes to the deepest zone the name is in (the parent for a
+    // DS), and gets every record there with the name and type asked for, plus the RRSIGs over
+    // them. When there are none, the zone's NSEC records go in the authority section, with
+    // NXDOMAIN if there is nothing at or below the name
+    pub(crate) fn signed_server(zones: Vec<(Name, Vec<ResourceRecord>)>) -> std::net::SocketAddr {
+        mock_udp_server(64, move |query| {
+            let msg = DnsMessage::from_bytes(query).unwrap();
+            let q = msg.question();
+            let (_, records) = zones
+                .iter()
+                .filter(|(apex, _)| {
+                    q.qname.is_subdomain_of(apex) && !(q.qtype == TYPE_DS && q.qname == *apex)
+                })
+                .max_by_key(|(apex, _)| apex.labels().len())
+                .unwrap();
+            let covered = |rr: &ResourceRecord| match &rr.data {
+                RData::RRSIG(sig) => sig.type_covered,
+                _ => rr.rr_type,
+            };
+            let mut res = DnsMessage::response_to(&msg);
+            for rr in records {
+                if rr.name == q.qname && covered(rr) == q.qtype {
+                    res.add_answer(rr.clone());
+                }
+            }
+            if res.answers.is_empty() {
+                if !records.iter().any(|rr| rr.name.is_subdomain_of(&q.qname)) {
+                    let mut flags = res.flags();
+                    flags.rcode = RCODE_NXDOMAIN as u8;
+                    res.set_flags(flags);
+                }
+                for rr in records.iter().filter(|rr| covered(rr) == TYPE_NSEC) {
+                    res.add_authority(rr.clone());
+                }
+            }
+            let id = u16::from_be_bytes([query[0], query[1]]);
+            with_id(res.to_bytes().unwrap(), id)
+        })
+    }
+
+    // NSEC records for `names` (in canonical order, with the types at each), signed with `key`
+    fn nsec_chain(key: &ZoneKey, names: &[(&str, &[u16])]) -> Vec<ResourceRecord> {
+        let mut records = Vec::new();
+        for (i, (owner, types)) in names.iter().enumerate() {
+            let next = names[(i + 1) % names.len()].0;
+            let nsec = [ResourceRecord::new(
+                *owner,
+                3600,
+                RData::NSEC(Nsec {
+                    next: next.to_string(),
+                    types: types.to_vec(),
+                }),
+            )];
+            records.push(key.sign(&nsec));
+            records.extend(nsec);
+        }
+        records
+    }
+
+    // a signed root with a signed example. under it and an unsigned insecure. next to it.
+    // `tamper` changes the address of www.example after it was signed
+    fn signed_tree(tamper: bool) -> (ZoneKey, Vec<(Name, Vec<ResourceRecord>)>) {
+        let root = ZoneKey::new("");
+        let example = ZoneKey::new("example");
+        let mut zones = Vec::new();
+        for key in [&root, &example] {
+            let dnskey = [ResourceRecord::new(
+                key.zone.clone(),
+                3600,
+                RData::DNSKEY(key.dnskey.clone()),
+            )];
+            zones.push((key.zone.clone(), vec![key.sign(&dnskey), dnskey[0].clone()]));
+        }
+
+        let apex: &[u16] = &[2, TYPE_SOA, TYPE_RRSIG, TYPE_NSEC, TYPE_DNSKEY];
+        let ds = [ResourceRecord::new(
+            "example",
+            3600,
+            RData::DS(example.ds()),
+        )];
+        zones[0].1.push(root.sign(&ds));
+        zones[0].1.extend(ds);
+        zones[0].1.extend(nsec_chain(
+            &root,
+            &[
+                ("", apex),
+                ("example", &[2, TYPE_DS, TYPE_RRSIG, TYPE_NSEC]),
+                ("insecure", &[2, TYPE_RRSIG, TYPE_NSEC]),
+            ],
+        ));
+
+        let www = [a("www.example", [192, 0, 2, 80])];
+        zones[1].1.push(example.sign(&www));
+        let mut www = www[0].clone();
+        if tamper {
+            www = a("www.example", [203, 0, 113, 66]);
+        }
+        zones[1].1.push(www);
+        zones[1].1.extend(nsec_chain(
+            &example,
+            &[
+                ("example", apex),
+                ("www.example", &[1, TYPE_RRSIG, TYPE_NSEC]),
+            ],
+        ));
+
+        // an unsigned zone, with no DS for it in the root
+        let soa = ResourceRecord::new(
+            "insecure",
+            3600,
+            RData::SOA(crate::Soa {
+                mname: "ns.insecure".to_string(),
+                rname: "admin.insecure".to_string(),
+                serial: 1,
+                refresh: 3600,
+                retry: 600,
+                expire: 86400,
+                minimum: 300,
+            }),
+        );
+        zones.push((
+            Name::from("insecure"),
+            vec![soa, a("www.insecure", [192, 0, 2, 81])],
+        ));
+        (root, zones)
+    }
+
+    #[test]
+    fn test_resolver_validates_up_to_the_anchor() {
+        let (root, zones) = signed_tree(false);
+        let mut resolver = Resolver::with_server(signed_server(zones));
+        resolver.set_trust_anchors(vec![root.anchor()]);
+
+        let lookup = resolver
+            .lookup("www.example.", crate::RecordType::A)
+            .unwrap();
+        assert_eq!(lookup.security, Security::Secure);
+        assert!(lookup.response.authentic_data());
+        assert_eq!(
+            lookup.response.ipv4_addrs(),
+            [std::net::Ipv4Addr::new(192, 0, 2, 80)]
+        );
+
+        let lookup = resolver
+            .lookup("www.insecure.", crate::RecordType::A)
+            .unwrap();
+        assert_eq!(lookup.security, Security::Insecure);
+        assert!(!lookup.response.authentic_data());
+    }
+
+    #[test]
+    fn test_negative_answers_are_proven() {
+        let (root, zones) = signed_tree(false);
+        let mut resolver = Resolver::with_server(signed_server(zones));
+        resolver.set_trust_anchors(vec![root.anchor()]);
+
+        let lookup = resolver
+            .lookup("www.example.", crate::RecordType::AAAA)
+            .unwrap();
+        assert_eq!(lookup.security, Security::Secure);
+        assert!(lookup.response.answers.is_empty());
+        let lookup = resolver
+            .lookup("nowhere.example.", crate::RecordType::A)
+            .unwrap();
+        assert_eq!(lookup.response.rcode(), RCODE_NXDOMAIN);
+        assert_eq!(lookup.security, Security::Secure);
+
+        // the same answers without the NSEC records to back them up
+        let (root, mut zones) = signed_tree(false);
+        for (_, records) in &mut zones {
+            records.retain(|rr| rr.rr_type != TYPE_NSEC);
+        }
+        let mut resolver = Resolver::with_server(signed_server(zones));
+        resolver.set_trust_anchors(vec![root.anchor()]);
+        for (name, qtype) in [
+            ("www.example.", crate::RecordType::AAAA),
+            ("nowhere.example.", crate::RecordType::A),
+        ] {
+            let err = resolver.lookup(name, qtype).unwrap_err();
+            assert!(matches!(err, DnsError::Bogus(_)), "{}: {:?}", name, err);
+        }
+    }
+
+    #[test]
+    fn test_resolver_rejects_bogus_answers() {
+        let (root, zones) = signed_tree(true);
+        let mut resolver = Resolver::with_server(signed_server(zones));
+        resolver.set_trust_anchors(vec![root.anchor()]);
+
+        let err = resolver
+            .lookup("www.example.", crate::RecordType::A)
+            .unwrap_err();
+        assert!(matches!(err, DnsError::Bogus(_)));
+        assert!(matches!(
+            resolver.resolve("www.example."),
+            Err(DnsError::Bogus(_))
+        ));
+
+        // and with a trust anchor that isn't the root's key, nothing checks out
+        let mut resolver = Resolver::with_server(signed_server(signed_tree(false).1));
+        resolver.set_trust_anchors(vec![root.anchor()]);
+        let err = resolver
+            .lookup("www.example.", crate::RecordType::A)
+            .unwrap_err();
+        assert!(matches!(err, DnsError::Bogus(_)));
+    }
+
+    #[test]
+    fn test_canonical_order() {
+        // RFC 4034 6.1
+        let names = [
+            "example",
+            "a.example",
+            "yljkjljk.a.example",
+            "Z.a.example",
+            "zABC.a.EXAMPLE",
+            "z.example",
+            "\\001.z.example",
+            "*.z.example",
+            "\\200.z.example",
+        ]
+        .map(Name::from);
+        for pair in names.windows(2) {
+            assert_eq!(
+                canonical_cmp(&pair[0], &pair[1]),
+                Ordering::Less,
+                "{:?}",
+                pair
+            );
+        }
+        let next = Name::from("z.example");
+        assert!(covers(&names[1], &next, &Name::from("b.example")));
+        assert!(!covers(&names[1], &next, &Name::from("zz.example")));
+        // the last one in the zone covers everything after it
+        assert!(covers(&next, &names[0], &Name::from("zz.example")));
+    }
+
+    fn base32hex(bytes: &[u8]) -> String {
+        to_base32hex(bytes).to_ascii_lowercase()
+    }
+
+    // the NSEC3 records of example. for `names`, with the RFC 5155 appendix A parameters
+    fn nsec3_zone(names: &[(&str, &[u16])], flags: u8) -> Vec<ResourceRecord> {
+        let salt = hex("AABBCCDD");
+        let mut hashed: Vec<(Vec<u8>, &[u16])> = names
+            .iter()
+            .map(|(name, types)| (nsec3_hash(&Name::from(*name), &salt, 12), *types))
+            .collect();
+        hashed.sort();
+        (0..hashed.len())
+            .map(|i| {
+                let (hash, types) = &hashed[i];
+                let nsec3 = Nsec3 {
+                    hash_algorithm: 1,
+                    flags,
+                    iterations: 12,
+                    salt: salt.clone(),
+                    next_hashed: hashed[(i + 1) % hashed.len()].0.clone(),
+                    types: types.to_vec(),
+                };
+                let owner = format!("{}.example", base32hex(hash));
+                ResourceRecord::new(owner.as_str(), 3600, RData::NSEC3(nsec3))
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_nsec3_denial() {
+        let hash = nsec3_hash(&Name::from("example"), &hex("AABBCCDD"), 12);
+        assert_eq!(base32hex(&hash), "0p9mhaveqvm6t7vbl5lop2u3t2rp3tom");
+        assert_eq!(from_base32hex(base32hex(&hash).as_bytes()), Some(hash));
+
+        let name = Name::from;
+        let zone = nsec3_zone(
+            &[
+                ("example", &[2, TYPE_SOA, TYPE_RRSIG, TYPE_DNSKEY]),
+                ("www.example", &[1, TYPE_RRSIG]),
+            ],
+            0,
+        );
+        let nowhere = name("nowhere.example");
+        let www = name("www.example");
+        assert_eq!(
+            proves_denial(&zone, &nowhere, 1, true),
+            Some(Security::Secure)
+        );
+        assert_eq!(
+            proves_denial(&zone, &www, 28, false),
+            Some(Security::Secure)
+        );
+        // www has an A, and it's there so no NXDOMAIN for it
+        assert_eq!(proves_denial(&zone, &www, 1, false), None);
+        assert_eq!(proves_denial(&zone, &www, 1, true), None);
+        // without the apex there's no closest encloser
+        assert_eq!(proves_denial(&zone[1..], &nowhere, 1, true), None);
+
+        // an unsigned delegation hiding behind opt-out
+        let sub = name("sub.example");
+        assert_eq!(proves_denial(&zone, &sub, TYPE_DS, false), None);
+        let zone = nsec3_zone(&[("example", &[2, TYPE_SOA, TYPE_RRSIG])], 1);
+        assert_eq!(
+            proves_denial(&zone, &sub, TYPE_DS, false),
+            Some(Security::Insecure)
+        );
+    }
+}
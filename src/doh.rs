@@ -0,0 +1,381 @@
+// DNS over HTTPS (RFC 8484): the wire-format query as the body of a POST, content type
+// application/dns-message, and the response's body is the DNS response. To the network it looks
+// like any other HTTPS traffic.
+//
+// We speak HTTP/1.1 over our own TLS connection and keep it open between queries (keep-alive),
+// which is the part of HTTP/2 connection reuse that matters to a client sending one query at a
+// time; HTTP/2's multiplexing would only pay off with many queries in flight on one transport
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, ToSocketAddrs};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use rustls::pki_types::ServerName;
+use rustls::{ClientConfig, RootCertStore};
+
+use crate::resolver::{answers_question, DEFAULT_TIMEOUT};
+use crate::tls::{client_config, connect, default_roots, server_name, TlsStream};
+use crate::transport::{timeout_error, DnsTransport};
+use crate::{DnsError, DnsMessage};
+
+const CONTENT_TYPE: &str = "application/dns-message";
+// a header line or status line longer than this isn't from a DoH server we want to talk to
+const MAX_LINE: usize = 8192;
+
+pub struct DohTransport {
+    host: String,
+    path: String,
+    // where the host lives. Looked up with the system resolver when connecting unless given
+    // with set_server_addr, which saves depending on another resolver to bootstrap this one
+    port: u16,
+    addr: Option<SocketAddr>,
+    server_name: ServerName<'static>,
+    config: Arc<ClientConfig>,
+    timeout: Duration,
+    conn: Mutex<Option<BufReader<TlsStream>>>,
+}
+
+impl DohTransport {
+    // `url` is the server's DoH endpoint, like "https://cloudflare-dns.com/dns-query"
+    pub fn new(url: &str) -> Result<Self, DnsError> {
+        Self::with_roots(url, default_roots())
+    }
+
+    // the same, trusting `roots` instead of the public CAs
+    pub fn with_roots(url: &str, roots: RootCertStore) -> Result<Self, DnsError> {
+        let (host, port, path) = parse_url(url)?;
+        Ok(DohTransport {
+            server_name: server_name(&host)?,
+            host,
+            path,
+            port,
+            addr: None,
+            config: client_config(roots, vec![b"http/1.1".to_vec()])?,
+            timeout: DEFAULT_TIMEOUT,
+            conn: Mutex::new(None),
+        })
+    }
+
+    // connect here instead of looking the URL's host up, e.g. 1.1.1.1:443 for cloudflare-dns.com.
+    // The certificate still has to be for the host in the URL
+    pub fn set_server_addr(&mut self, addr: SocketAddr) {
+        self.addr = Some(addr);
+    }
+
+    // for connecting, and then again for each read and write
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = timeout;
+    }
+
+    fn connect(&self, timeout: Duration) -> Result<BufReader<TlsStream>, DnsError> {
+        let addr = match self.addr {
+            Some(addr) => addr,
+            None => (self.host.as_str(), self.port)
+                .to_socket_addrs()?
+                .next()
+                .ok_or_else(|| DnsError::InvalidName(self.host.clone()))?,
+        };
+        let stream = connect(addr, &self.server_name, &self.config, timeout)?;
+        Ok(BufReader::new(stream))
+    }
+
+    // one POST and its response on `conn`, and whether the server wants to keep the connection
+    fn post(
+        &self,
+        conn: &mut BufReader<TlsStream>,
+        query: &[u8],
+        timeout: Duration,
+    ) -> Result<Response, DnsError> {
+        let mut request = format!(
+            "POST {} HTTP/1.1\r\nHost: {}\r\nContent-Type: {}\r\nAccept: {}\r\nContent-Length: {}\r\n\r\n",
+            self.path,
+            self.host,
+            CONTENT_TYPE,
+            CONTENT_TYPE,
+            query.len()
+        )
+        .into_bytes();
+        request.extend(query);
+        let stream = conn.get_mut();
+        // a kept connection still has the timeouts of the query that opened it
+        stream.sock.set_read_timeout(Some(timeout))?;
+        stream.sock.set_write_timeout(Some(timeout))?;
+        stream.write_all(&request).map_err(timeout_error)?;
+        stream.flush().map_err(timeout_error)?;
+        read_response(conn).map_err(timeout_error)
+    }
+}
+
+impl DnsTransport for DohTransport {
+    fn exchange(&self, msg: &DnsMessage) -> Result<DnsMessage, DnsError> {
+        self.exchange_timeout(msg, self.timeout)
+    }
+
+    fn exchange_timeout(
+        &self,
+        msg: &DnsMessage,
+        timeout: Duration,
+    ) -> Result<DnsMessage, DnsError> {
+        let query = msg.to_bytes()?;
+        let mut conn = self.conn.lock().unwrap();
+
+        // like TlsTransport: a kept connection the server has since closed gets one retry on a
+        // fresh one
+        let reused = conn.is_some();
+        let mut stream = match conn.take() {
+            Some(stream) => stream,
+            None => self.connect(timeout)?,
+        };
+        let res = match self.post(&mut stream, &query, timeout) {
+            Ok(res) => res,
+            Err(DnsError::Timeout) => return Err(DnsError::Timeout),
+            Err(_) if reused => {
+                stream = self.connect(timeout)?;
+                self.post(&mut stream, &query, timeout)?
+            }
+            Err(e) => return Err(e),
+        };
+        if res.keep_alive {
+            *conn = Some(stream);
+        }
+
+        if res.status != 200 {
+            return Err(
+                io::Error::other(format!("DoH server answered HTTP {}", res.status)).into(),
+            );
+        }
+        if !res.content_type.eq_ignore_ascii_case(CONTENT_TYPE) {
+            return Err(DnsError::Malformed(
+                "DoH response isn't application/dns-message",
+            ));
+        }
+        let res = DnsMessage::from_bytes(&res.body)?;
+        if res.header.identification != msg.header.identification || !answers_question(msg, &res) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "DoH response doesn't match the query",
+            )
+            .into());
+        }
+        Ok(res)
+    }
+}
+
+// https://host[:port][/path]. Anything but https makes no sense for DoH
+fn parse_url(url: &str) -> Result<(String, u16, String), DnsError> {
+    let invalid = || DnsError::InvalidName(url.to_string());
+    let rest = url.strip_prefix("https://").ok_or_else(invalid)?;
+    let (authority, path) = match rest.find('/') {
+        Some(slash) => rest.split_at(slash),
+        None => (rest, "/"),
+    };
+    let (host, port) = match authority.rsplit_once(':') {
+        Some((host, port)) => (host, port.parse().map_err(|_| invalid())?),
+        None => (authority, 443),
+    };
+    if host.is_empty() {
+        return Err(invalid());
+    }
+    Ok((host.to_string(), port, path.to_string()))
+}
+
+struct Response {
+    status: u16,
+    content_type: String,
+    keep_alive: bool,
+    body: Vec<u8>,
+}
+
+// status line, headers, and the body by Content-Length or chunked
+fn read_response(conn: &mut impl BufRead) -> io::Result<Response> {
+    let bad = |what: &str| io::Error::new(io::ErrorKind::InvalidData, what.to_string());
+    let status_line = read_line(conn)?;
+    let mut parts = status_line.split(' ');
+    let version = parts.next().unwrap_or("");
+    let status = parts
+        .next()
+        .and_then(|code| code.parse().ok())
+        .filter(|_| version.starts_with("HTTP/1."))
+        .ok_or_else(|| bad("not an HTTP/1.x response"))?;
+
+    let mut res = Response {
+        status,
+        content_type: String::new(),
+        keep_alive: version == "HTTP/1.1",
+        body: Vec::new(),
+    };
+    let mut length = None;
+    let mut chunked = false;
+    loop {
+        let line = read_line(conn)?;
+        if line.is_empty() {
+            break;
+        }
+        let Some((name, value)) = line.split_once(':') else {
+            return Err(bad("malformed HTTP header"));
+        };
+        let value = value.trim();
+        match name.trim().to_ascii_lowercase().as_str() {
+            "content-length" => {
+                length = Some(value.parse().map_err(|_| bad("bad Content-Length"))?)
+            }
+            "content-type" => res.content_type = value.to_string(),
+            "transfer-encoding" => chunked = value.eq_ignore_ascii_case("chunked"),
+            "connection" => res.keep_alive = !value.eq_ignore_ascii_case("close"),
+            _ => {}
+        }
+    }
+
+    if chunked {
+        loop {
+            let size = read_line(conn)?;
+            let size = size.split(';').next().unwrap_or("");
+            let size = usize::from_str_radix(size.trim(), 16).map_err(|_| bad("bad chunk size"))?;
+            if size == 0 {
+                // trailers, if any, up to the empty line
+                while !read_line(conn)?.is_empty() {}
+                break;
+            }
+            read_body(conn, &mut res.body, size)?;
+            if !read_line(conn)?.is_empty() {
+                return Err(bad("chunk longer than its size"));
+            }
+        }
+    } else {
+        // a DNS message is never over 64K, no need to take more than that on faith
+        match length {
+            Some(len) if len <= 65535 => read_body(conn, &mut res.body, len)?,
+            Some(_) => return Err(bad("DoH response body over 64K")),
+            None => return Err(bad("DoH response without a length")),
+        }
+    }
+    Ok(res)
+}
+
+fn read_body(conn: &mut impl Read, body: &mut Vec<u8>, len: usize) -> io::Result<()> {
+    if body.len() + len > 65535 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "DoH response body over 64K",
+        ));
+    }
+    let start = body.len();
+    body.resize(start + len, 0);
+    conn.read_exact(&mut body[start..])
+}
+
+// one CRLF-terminated line, without the CRLF
+fn read_line(conn: &mut impl BufRead) -> io::Result<String> {
+    let mut line = Vec::new();
+    conn.take(MAX_LINE as u64).read_until(b'\n', &mut line)?;
+    if line.last() != Some(&b'\n') {
+        return Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            "HTTP line cut off or too long",
+        ));
+    }
+    line.pop();
+    if line.last() == Some(&b'\r') {
+        line.pop();
+    }
+    String::from_utf8(line)
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "HTTP line isn't UTF-8"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{response_with_rdata, test_roots, test_tls_config};
+    use rustls::{ServerConnection, StreamOwned};
+    use std::net::{Ipv4Addr, TcpListener};
+    use std::thread;
+
+    // an HTTPS server taking one connection and `count` POSTs on it. Each answer is an A record
+    // (sent chunked, every other one), the last one says Connection: close
+    fn mock_doh_server(count: usize) -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            let (tcp, _) = listener.accept().unwrap();
+            let conn = ServerConnection::new(test_tls_config()).unwrap();
+            let mut stream = BufReader::new(StreamOwned::new(conn, tcp));
+            for i in 0..count {
+                let request_line = read_line(&mut stream).unwrap();
+                assert_eq!(request_line, "POST /dns-query HTTP/1.1");
+                let mut length = 0;
+                loop {
+                    let line = read_line(&mut stream).unwrap();
+                    if line.is_empty() {
+                        break;
+                    }
+                    if let Some(len) = line.strip_prefix("Content-Length: ") {
+                        length = len.parse().unwrap();
+                    }
+                }
+                let mut query = vec![0; length];
+                stream.read_exact(&mut query).unwrap();
+
+                let body = response_with_rdata(&query, 1, &[192, 0, 2, i as u8]);
+                let close = if i + 1 == count {
+                    "Connection: close\r\n"
+                } else {
+                    ""
+                };
+                let mut res = format!(
+                    "HTTP/1.1 200 OK\r\nContent-Type: {}\r\n{}",
+                    CONTENT_TYPE, close
+                )
+                .into_bytes();
+                if i % 2 == 0 {
+                    res.extend(format!("Content-Length: {}\r\n\r\n", body.len()).as_bytes());
+                    res.extend(&body);
+                } else {
+                    res.extend(b"Transfer-Encoding: chunked\r\n\r\n");
+                    let (first, second) = body.split_at(5);
+                    for chunk in [first, second] {
+                        res.extend(format!("{:x}\r\n", chunk.len()).as_bytes());
+                        res.extend(chunk);
+                        res.extend(b"\r\n");
+                    }
+                    res.extend(b"0\r\n\r\n");
+                }
+                stream.get_mut().write_all(&res).unwrap();
+            }
+        });
+        addr
+    }
+
+    #[test]
+    fn test_doh_reuses_the_connection() {
+        let server = mock_doh_server(3);
+        let mut transport =
+            DohTransport::with_roots("https://dns.test/dns-query", test_roots()).unwrap();
+        transport.set_server_addr(server);
+        for i in 0..3 {
+            let res = transport
+                .exchange(&DnsMessage::new("example.com".to_string()))
+                .unwrap();
+            assert_eq!(res.ipv4_addrs(), vec![Ipv4Addr::new(192, 0, 2, i)]);
+        }
+        // the server said close the last time
+        assert!(transport.conn.lock().unwrap().is_none());
+    }
+
+    #[test]
+    fn test_parse_url() {
+        assert_eq!(
+            parse_url("https://cloudflare-dns.com/dns-query").unwrap(),
+            (
+                "cloudflare-dns.com".to_string(),
+                443,
+                "/dns-query".to_string()
+            )
+        );
+        assert_eq!(
+            parse_url("https://dns.example:8443").unwrap(),
+            ("dns.example".to_string(), 8443, "/".to_string())
+        );
+        assert!(parse_url("http://dns.example/dns-query").is_err());
+        assert!(parse_url("https://:443/").is_err());
+    }
+}
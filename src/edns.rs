@@ -0,0 +1,535 @@
+// EDNS0 (RFC 6891): the extensions classic DNS didn't have room for, carried in an OPT
+// pseudo-record at the end of the additional section. Nothing about it is a real record: the
+// owner is always the root, the class field is the sender's UDP payload size and the TTL field
+// holds the upper bits of the rcode, the EDNS version and flags. We take it out of the
+// additional section when parsing and put it back when encoding, so the rest of the crate only
+// ever sees it as DnsMessage::edns
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt;
+use core::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use core::time::Duration;
+
+use crate::rdata::TYPE_OPT;
+use crate::{DnsError, ResourceRecord};
+
+// the one flag defined so far: "DNSSEC OK", send us the signatures too
+const FLAG_DO: u32 = 0x8000;
+
+pub const EDNS_CLIENT_SUBNET: u16 = 8;
+pub const EDNS_TCP_KEEPALIVE: u16 = 11;
+pub const EDNS_EXTENDED_ERROR: u16 = 15;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Edns {
+    // the biggest UDP response the sender can take
+    pub udp_payload: u16,
+    // the upper 8 bits of the 12-bit rcode, the lower 4 are in the header as always
+    pub extended_rcode: u8,
+    pub version: u8,
+    pub dnssec_ok: bool,
+    pub options: Vec<EdnsOption>,
+}
+
+// one option from the OPT record's rdata: cookies, padding, client subnet and so on
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct EdnsOption {
+    pub code: u16,
+    pub data: Vec<u8>,
+}
+
+impl Edns {
+    // version 0, no flags, no options
+    pub fn new(udp_payload: u16) -> Self {
+        Edns {
+            udp_payload,
+            extended_rcode: 0,
+            version: 0,
+            dnssec_ok: false,
+            options: Vec::new(),
+        }
+    }
+
+    // the data of the first option with this code
+    pub fn option(&self, code: u16) -> Option<&[u8]> {
+        self.options
+            .iter()
+            .find(|option| option.code == code)
+            .map(|option| option.data.as_slice())
+    }
+
+    // the client subnet option, if there's one and it makes sense
+    pub fn client_subnet(&self) -> Option<ClientSubnet> {
+        ClientSubnet::from_option(self.option(EDNS_CLIENT_SUBNET)?).ok()
+    }
+
+    // the idle timeout from an edns-tcp-keepalive option (RFC 7828): how long the server will
+    // keep a TCP connection open with nothing going over it, zero when it wants it closed. None
+    // without the option, and for the empty one a client sends to ask
+    pub fn tcp_keepalive(&self) -> Option<Duration> {
+        let &[t0, t1] = self.option(EDNS_TCP_KEEPALIVE)? else {
+            return None;
+        };
+        Some(Duration::from_millis(
+            u16::from_be_bytes([t0, t1]) as u64 * 100,
+        ))
+    }
+
+    // every Extended DNS Error in it, there can be more than one. Malformed ones are left out
+    pub fn extended_errors(&self) -> Vec<ExtendedError> {
+        self.options
+            .iter()
+            .filter(|option| option.code == EDNS_EXTENDED_ERROR)
+            .filter_map(|option| ExtendedError::from_option(&option.data).ok())
+            .collect()
+    }
+
+    pub(crate) fn from_record(rr: &ResourceRecord) -> Result<Self, DnsError> {
+        let mut options = Vec::new();
+        let mut rest = rr.rdata.as_slice();
+        while !rest.is_empty() {
+            let (code, len) = match rest {
+                [c0, c1, l0, l1, ..] => (
+                    u16::from_be_bytes([*c0, *c1]),
+                    u16::from_be_bytes([*l0, *l1]) as usize,
+                ),
+                _ => return Err(DnsError::Malformed("EDNS option cut off in its header")),
+            };
+            let data = rest
+                .get(4..4 + len)
+                .ok_or(DnsError::Malformed("EDNS option runs past the OPT record"))?;
+            options.push(EdnsOption {
+                code,
+                data: data.to_vec(),
+            });
+            rest = &rest[4 + len..];
+        }
+        Ok(Edns {
+            udp_payload: rr.class,
+            extended_rcode: (rr.ttl >> 24) as u8,
+            version: (rr.ttl >> 16) as u8,
+            dnssec_ok: rr.ttl & FLAG_DO != 0,
+            options,
+        })
+    }
+
+    // root name, type OPT, then class/TTL/rdata standing in for everything above
+    pub(crate) fn write(&self, bytes: &mut Vec<u8>) -> Result<(), DnsError> {
+        let mut ttl = (self.extended_rcode as u32) << 24 | (self.version as u32) << 16;
+        if self.dnssec_ok {
+            ttl |= FLAG_DO;
+        }
+        let mut rdata = Vec::new();
+        for option in &self.options {
+            let len = u16::try_from(option.data.len())
+                .map_err(|_| DnsError::Malformed("EDNS option longer than 65535 bytes"))?;
+            rdata.extend(option.code.to_be_bytes());
+            rdata.extend(len.to_be_bytes());
+            rdata.extend(&option.data);
+        }
+        let rdlength = u16::try_from(rdata.len())
+            .map_err(|_| DnsError::Malformed("OPT record longer than 65535 bytes"))?;
+
+        bytes.push(0);
+        bytes.extend(TYPE_OPT.to_be_bytes());
+        bytes.extend(self.udp_payload.to_be_bytes());
+        bytes.extend(ttl.to_be_bytes());
+        bytes.extend(rdlength.to_be_bytes());
+        bytes.extend(rdata);
+        Ok(())
+    }
+}
+
+impl EdnsOption {
+    // an edns-tcp-keepalive option: empty in a query, asking for the server's idle timeout, and
+    // with the timeout in an answer. It goes on the wire in units of 100ms
+    pub fn tcp_keepalive(idle: Option<Duration>) -> Self {
+        let data = match idle {
+            Some(idle) => u16::try_from(idle.as_millis() / 100)
+                .unwrap_or(u16::MAX)
+                .to_be_bytes()
+                .to_vec(),
+            None => Vec::new(),
+        };
+        EdnsOption {
+            code: EDNS_TCP_KEEPALIVE,
+            data,
+        }
+    }
+}
+
+// EDNS Client Subnet (RFC 7871): the network a query is really for, passed on by a recursive
+// resolver so a CDN can answer with servers close to the client rather than close to the
+// resolver. In the answer the server says, as the scope prefix, how much of the address it
+// looked at, which is how far the answer can be shared: /24 means everyone in that /24 gets the
+// same, /0 means everybody does
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ClientSubnet {
+    // with every bit past source_prefix zero
+    pub addr: IpAddr,
+    pub source_prefix: u8,
+    // 0 in a query, the server fills it in
+    pub scope_prefix: u8,
+}
+
+impl ClientSubnet {
+    // the first `prefix` bits of `addr` (all of them, if it's longer than the address). 24 for
+    // IPv4 and 56 for IPv6 is what RFC 7871 11.1 suggests to keep clients private
+    pub fn new(addr: IpAddr, prefix: u8) -> Self {
+        let prefix = prefix.min(max_prefix(addr));
+        ClientSubnet {
+            addr: masked(addr, prefix),
+            source_prefix: prefix,
+            scope_prefix: 0,
+        }
+    }
+
+    // 0.0.0.0/0: "don't use my address for this one" (RFC 7871 7.1.2). The server answers as
+    // if for anybody, and a resolver in front of it won't add a subnet of its own either
+    pub fn privacy() -> Self {
+        Self::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), 0)
+    }
+
+    // the network the answer it came with is good for: the address cut to the scope prefix,
+    // or to the source prefix if the server claims to have looked at more than we sent
+    pub fn scope(&self) -> ClientSubnet {
+        Self::new(self.addr, self.scope_prefix.min(self.source_prefix))
+    }
+
+    // family (1 for IPv4, 2 for IPv6), the two prefixes, then only as many bytes of the address
+    // as the source prefix covers
+    pub fn from_option(data: &[u8]) -> Result<Self, DnsError> {
+        let [f0, f1, source_prefix, scope_prefix, bytes @ ..] = data else {
+            return Err(DnsError::Malformed("client subnet option too short"));
+        };
+        let (source_prefix, scope_prefix) = (*source_prefix, *scope_prefix);
+        let addr = match u16::from_be_bytes([*f0, *f1]) {
+            1 => {
+                let mut octets = [0u8; 4];
+                octets
+                    .get_mut(..bytes.len())
+                    .ok_or(DnsError::Malformed("client subnet address too long"))?
+                    .copy_from_slice(bytes);
+                IpAddr::V4(Ipv4Addr::from(octets))
+            }
+            2 => {
+                let mut octets = [0u8; 16];
+                octets
+                    .get_mut(..bytes.len())
+                    .ok_or(DnsError::Malformed("client subnet address too long"))?
+                    .copy_from_slice(bytes);
+                IpAddr::V6(Ipv6Addr::from(octets))
+            }
+            _ => return Err(DnsError::Malformed("unknown client subnet address family")),
+        };
+        let max = max_prefix(addr);
+        if source_prefix > max
+            || scope_prefix > max
+            || bytes.len() != (source_prefix as usize).div_ceil(8)
+            || masked(addr, source_prefix) != addr
+        {
+            return Err(DnsError::Malformed(
+                "client subnet address doesn't match its prefix",
+            ));
+        }
+        Ok(ClientSubnet {
+            addr,
+            source_prefix,
+            scope_prefix,
+        })
+    }
+
+    pub fn to_option(&self) -> EdnsOption {
+        let (family, octets) = match self.addr {
+            IpAddr::V4(addr) => (1u16, addr.octets().to_vec()),
+            IpAddr::V6(addr) => (2u16, addr.octets().to_vec()),
+        };
+        let mut data = family.to_be_bytes().to_vec();
+        data.push(self.source_prefix);
+        data.push(self.scope_prefix);
+        data.extend(&octets[..(self.source_prefix as usize).div_ceil(8)]);
+        EdnsOption {
+            code: EDNS_CLIENT_SUBNET,
+            data,
+        }
+    }
+}
+
+// an Extended DNS Error (RFC 8914): why a server answered the way it did, mostly why it failed.
+// A SERVFAIL could be anything, with one of these it's "DNSSEC Bogus" or "No Reachable
+// Authority". Some come with plain answers too, a stale one from the cache or a blocked name
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExtendedError {
+    pub info_code: u16,
+    // for people, not for programs to parse. Often empty
+    pub extra_text: String,
+}
+
+impl ExtendedError {
+    pub fn new(info_code: u16, extra_text: impl Into<String>) -> Self {
+        ExtendedError {
+            info_code,
+            extra_text: extra_text.into(),
+        }
+    }
+
+    // the info code, then the text as UTF-8. Servers aren't meant to NUL-terminate it, some do
+    // anyway
+    pub fn from_option(data: &[u8]) -> Result<Self, DnsError> {
+        let [c0, c1, text @ ..] = data else {
+            return Err(DnsError::Malformed("extended DNS error too short"));
+        };
+        let text = text.strip_suffix(&[0]).unwrap_or(text);
+        Ok(ExtendedError {
+            info_code: u16::from_be_bytes([*c0, *c1]),
+            extra_text: String::from_utf8_lossy(text).into_owned(),
+        })
+    }
+
+    pub fn to_option(&self) -> EdnsOption {
+        let mut data = self.info_code.to_be_bytes().to_vec();
+        data.extend(self.extra_text.as_bytes());
+        EdnsOption {
+            code: EDNS_EXTENDED_ERROR,
+            data,
+        }
+    }
+
+    // what the IANA registry calls the info code, None for one it doesn't have yet
+    pub fn name(&self) -> Option<&'static str> {
+        Some(match self.info_code {
+            0 => "Other Error",
+            1 => "Unsupported DNSKEY Algorithm",
+            2 => "Unsupported DS Digest Type",
+            3 => "Stale Answer",
+            4 => "Forged Answer",
+            5 => "DNSSEC Indeterminate",
+            6 => "DNSSEC Bogus",
+            7 => "Signature Expired",
+            8 => "Signature Not Yet Valid",
+            9 => "DNSKEY Missing",
+            10 => "RRSIGs Missing",
+            11 => "No Zone Key Bit Set",
+            12 => "NSEC Missing",
+            13 => "Cached Error",
+            14 => "Not Ready",
+            15 => "Blocked",
+            16 => "Censored",
+            17 => "Filtered",
+            18 => "Prohibited",
+            19 => "Stale NXDomain Answer",
+            20 => "Not Authoritative",
+            21 => "Not Supported",
+            22 => "No Reachable Authority",
+            23 => "Network Error",
+            24 => "Invalid Data",
+            25 => "Signature Expired before Valid",
+            26 => "Too Early",
+            27 => "Unsupported NSEC3 Iterations Value",
+            28 => "Unable to conform to policy",
+            29 => "Synthesized",
+            30 => "Invalid Query Type",
+            _ => return None,
+        })
+    }
+}
+
+// "DNSSEC Bogus (6): no valid signature for example.com A", the way dig prints them
+impl fmt::Display for ExtendedError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.name() {
+            Some(name) => write!(f, "{} ({})", name, self.info_code)?,
+            None => write!(f, "extended error {}", self.info_code)?,
+        }
+        if !self.extra_text.is_empty() {
+            write!(f, ": {}", self.extra_text)?;
+        }
+        Ok(())
+    }
+}
+
+fn max_prefix(addr: IpAddr) -> u8 {
+    match addr {
+        IpAddr::V4(_) => 32,
+        IpAddr::V6(_) => 128,
+    }
+}
+
+// `addr` with everything past the first `prefix` bits zeroed
+fn masked(addr: IpAddr, prefix: u8) -> IpAddr {
+    match addr {
+        IpAddr::V4(addr) => {
+            let mask = u32::MAX.checked_shl(32 - prefix as u32).unwrap_or(0);
+            IpAddr::V4(Ipv4Addr::from(u32::from(addr) & mask))
+        }
+        IpAddr::V6(addr) => {
+            let mask = u128::MAX.checked_shl(128 - prefix as u32).unwrap_or(0);
+            IpAddr::V6(Ipv6Addr::from(u128::from(addr) & mask))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::DnsMessage;
+    use alloc::vec;
+
+    #[test]
+    fn test_opt_round_trip() {
+        let mut query = DnsMessage::query("example.com").build();
+        query.edns = Some(Edns {
+            udp_payload: 4096,
+            extended_rcode: 0,
+            version: 0,
+            dnssec_ok: true,
+            options: vec![
+                EdnsOption {
+                    code: 10,
+                    data: vec![1, 2, 3, 4, 5, 6, 7, 8],
+                },
+                EdnsOption {
+                    code: 12,
+                    data: Vec::new(),
+                },
+            ],
+        });
+        let bytes = query.to_bytes().unwrap();
+        // one additional record on the wire, and it's the last 11 + 12 + 4 bytes
+        assert_eq!(&bytes[10..12], &[0, 1]);
+        assert_eq!(&bytes[bytes.len() - 27..bytes.len() - 24], &[0, 0, 41]);
+
+        let parsed = DnsMessage::from_bytes(&bytes).unwrap();
+        assert!(parsed.additional.is_empty());
+        assert_eq!(parsed.edns, query.edns);
+        assert_eq!(
+            parsed.edns.unwrap().option(10),
+            Some(&[1, 2, 3, 4, 5, 6, 7, 8][..])
+        );
+    }
+
+    #[test]
+    fn test_extended_rcode() {
+        // BADVERS (16): 1 in the OPT record's upper bits, 0 in the header
+        let mut res = DnsMessage::query("example.com").build();
+        let mut edns = Edns::new(1232);
+        edns.extended_rcode = 1;
+        res.edns = Some(edns);
+        let parsed = DnsMessage::from_bytes(&res.to_bytes().unwrap()).unwrap();
+        assert_eq!(parsed.rcode(), 16);
+        assert_eq!(parsed.response_code(), crate::ResponseCode::BadVers);
+    }
+
+    #[test]
+    fn test_bad_opt_records() {
+        let mut query = DnsMessage::query("example.com").build();
+        query.edns = Some(Edns::new(1232));
+        let mut bytes = query.to_bytes().unwrap();
+
+        // an option header promising more than the record holds
+        let mut cut = bytes.clone();
+        let end = cut.len();
+        cut[end - 2..].copy_from_slice(&[0, 4]);
+        cut.extend([0, 12, 0, 9]);
+        assert!(matches!(
+            DnsMessage::from_bytes(&cut),
+            Err(DnsError::Malformed(_))
+        ));
+
+        // two OPT records
+        let opt = bytes[bytes.len() - 11..].to_vec();
+        bytes.extend(opt);
+        bytes[11] = 2;
+        assert!(matches!(
+            DnsMessage::from_bytes(&bytes),
+            Err(DnsError::Malformed(_))
+        ));
+    }
+
+    #[test]
+    fn test_extended_errors() {
+        let mut edns = Edns::new(1232);
+        edns.options
+            .push(ExtendedError::new(6, "no signature for example.com").to_option());
+        edns.options.push(EdnsOption {
+            code: EDNS_EXTENDED_ERROR,
+            data: vec![0, 22, 0],
+        });
+        // too short to be one
+        edns.options.push(EdnsOption {
+            code: EDNS_EXTENDED_ERROR,
+            data: vec![9],
+        });
+        let mut res = DnsMessage::query("example.com").build();
+        res.edns = Some(edns);
+        let parsed = DnsMessage::from_bytes(&res.to_bytes().unwrap()).unwrap();
+
+        let errors = parsed.edns.unwrap().extended_errors();
+        assert_eq!(
+            errors,
+            [
+                ExtendedError::new(6, "no signature for example.com"),
+                ExtendedError::new(22, ""),
+            ]
+        );
+        assert_eq!(
+            alloc::format!("{}", errors[0]),
+            "DNSSEC Bogus (6): no signature for example.com"
+        );
+        assert_eq!(
+            alloc::format!("{}", errors[1]),
+            "No Reachable Authority (22)"
+        );
+        assert_eq!(
+            alloc::format!("{}", ExtendedError::new(4000, "x")),
+            "extended error 4000: x"
+        );
+    }
+
+    #[test]
+    fn test_client_subnet() {
+        let subnet = ClientSubnet::new(IpAddr::V4(Ipv4Addr::new(198, 51, 100, 77)), 24);
+        assert_eq!(subnet.addr, IpAddr::V4(Ipv4Addr::new(198, 51, 100, 0)));
+        let option = subnet.to_option();
+        assert_eq!(option.data, [0, 1, 24, 0, 198, 51, 100]);
+        assert_eq!(ClientSubnet::from_option(&option.data).unwrap(), subnet);
+
+        let mut edns = Edns::new(1232);
+        edns.options.push(ClientSubnet::privacy().to_option());
+        assert_eq!(edns.option(EDNS_CLIENT_SUBNET), Some(&[0, 1, 0, 0][..]));
+        assert_eq!(edns.client_subnet(), Some(ClientSubnet::privacy()));
+
+        // a /20 takes three bytes, and what's past the prefix has to be zero
+        let v6 = ClientSubnet::new("2001:db8:abcd::1".parse().unwrap(), 20);
+        assert_eq!(v6.to_option().data, [0, 2, 20, 0, 0x20, 0x01, 0x00]);
+        assert!(ClientSubnet::from_option(&[0, 2, 20, 0, 0x20, 0x01, 0x01]).is_err());
+        assert!(ClientSubnet::from_option(&[0, 1, 24, 0, 198, 51]).is_err());
+        assert!(ClientSubnet::from_option(&[0, 1, 33, 0, 1, 2, 3, 4, 5]).is_err());
+        assert!(ClientSubnet::from_option(&[0, 3, 0, 0]).is_err());
+
+        // the server's scope can't be wider than what we sent
+        let answered = ClientSubnet::from_option(&[0, 1, 24, 16, 198, 51, 100]).unwrap();
+        assert_eq!(
+            answered.scope(),
+            ClientSubnet::new(IpAddr::V4(Ipv4Addr::new(198, 51, 0, 0)), 16)
+        );
+        let greedy = ClientSubnet::from_option(&[0, 1, 24, 28, 198, 51, 100]).unwrap();
+        assert_eq!(greedy.scope(), subnet);
+    }
+
+    #[test]
+    fn test_tcp_keepalive() {
+        let mut edns = Edns::new(1232);
+        edns.options.push(EdnsOption::tcp_keepalive(None));
+        assert_eq!(edns.option(EDNS_TCP_KEEPALIVE), Some(&[][..]));
+        assert_eq!(edns.tcp_keepalive(), None);
+
+        edns.options = vec![EdnsOption::tcp_keepalive(Some(Duration::from_secs(30)))];
+        assert_eq!(edns.options[0].data, [1, 44]);
+        assert_eq!(edns.tcp_keepalive(), Some(Duration::from_secs(30)));
+        edns.options = vec![EdnsOption::tcp_keepalive(Some(Duration::from_secs(86400)))];
+        assert_eq!(edns.options[0].data, [0xFF, 0xFF]);
+    }
+}
@@ -0,0 +1,138 @@
+// the binary-to-text encodings of DNS presentation format: hex (DS digests, TLSA data, RFC 3597
+// generic rdata), base64 (keys and signatures) and base32hex (NSEC3 hashes)
+use alloc::string::String;
+use alloc::vec::Vec;
+
+const BASE64: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+const BASE32HEX: &[u8; 32] = b"0123456789ABCDEFGHIJKLMNOPQRSTUV";
+
+// upper case, the way zone files usually have it
+pub(crate) fn to_hex(bytes: &[u8]) -> String {
+    const DIGITS: &[u8; 16] = b"0123456789ABCDEF";
+    let mut out = String::with_capacity(bytes.len() * 2);
+    for byte in bytes {
+        out.push(DIGITS[(byte >> 4) as usize] as char);
+        out.push(DIGITS[(byte & 15) as usize] as char);
+    }
+    out
+}
+
+// either case. None for an odd number of digits or anything that isn't one
+pub(crate) fn from_hex(text: &str) -> Option<Vec<u8>> {
+    let text = text.as_bytes();
+    if !text.len().is_multiple_of(2) {
+        return None;
+    }
+    text.chunks(2)
+        .map(|pair| {
+            let digit = |c: u8| (c as char).to_digit(16);
+            Some((digit(pair[0])? << 4 | digit(pair[1])?) as u8)
+        })
+        .collect()
+}
+
+pub(crate) fn to_base64(bytes: &[u8]) -> String {
+    let mut out = String::new();
+    for chunk in bytes.chunks(3) {
+        let n = chunk
+            .iter()
+            .enumerate()
+            .fold(0u32, |n, (i, b)| n | (*b as u32) << (16 - 8 * i));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(BASE64[(n >> (18 - 6 * i)) as usize & 63] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+#[cfg_attr(not(feature = "std"), allow(dead_code))]
+pub(crate) fn from_base64(text: &str) -> Option<Vec<u8>> {
+    let mut out = Vec::new();
+    let (mut bits, mut value) = (0, 0u32);
+    for c in text.bytes().filter(|c| *c != b'=') {
+        let digit = BASE64.iter().position(|d| *d == c)? as u32;
+        value = value << 6 | digit;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            out.push((value >> bits) as u8);
+            value &= (1 << bits) - 1;
+        }
+    }
+    Some(out)
+}
+
+// base32 with the "extended hex" alphabet (RFC 4648 7), without padding, which is what NSEC3
+// owner labels and next hashes are written in
+pub(crate) fn to_base32hex(bytes: &[u8]) -> String {
+    let mut out = String::new();
+    let (mut bits, mut value) = (0, 0u32);
+    for byte in bytes {
+        value = value << 8 | *byte as u32;
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            out.push(BASE32HEX[(value >> bits) as usize & 31] as char);
+        }
+        value &= (1 << bits) - 1;
+    }
+    if bits > 0 {
+        out.push(BASE32HEX[(value << (5 - bits)) as usize & 31] as char);
+    }
+    out
+}
+
+#[cfg_attr(not(feature = "std"), allow(dead_code))]
+pub(crate) fn from_base32hex(text: &[u8]) -> Option<Vec<u8>> {
+    let mut out = Vec::new();
+    let (mut bits, mut value) = (0, 0u32);
+    for c in text {
+        let digit = match c.to_ascii_lowercase() {
+            c @ b'0'..=b'9' => c - b'0',
+            c @ b'a'..=b'v' => c - b'a' + 10,
+            _ => return None,
+        };
+        value = value << 5 | digit as u32;
+        bits += 5;
+        if bits >= 8 {
+            bits -= 8;
+            out.push((value >> bits) as u8);
+            value &= (1 << bits) - 1;
+        }
+    }
+    Some(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trips() {
+        // RFC 4648 section 10
+        for (plain, base64, base32hex) in [
+            ("", "", ""),
+            ("f", "Zg==", "CO"),
+            ("fo", "Zm8=", "CPNG"),
+            ("foo", "Zm9v", "CPNMU"),
+            ("foobar", "Zm9vYmFy", "CPNMUOJ1E8"),
+        ] {
+            assert_eq!(to_base64(plain.as_bytes()), base64);
+            assert_eq!(from_base64(base64).unwrap(), plain.as_bytes());
+            assert_eq!(to_base32hex(plain.as_bytes()), base32hex);
+            assert_eq!(
+                from_base32hex(base32hex.as_bytes()).unwrap(),
+                plain.as_bytes()
+            );
+        }
+        assert_eq!(to_hex(b"\x00\xab\x10"), "00AB10");
+        assert_eq!(from_hex("00ab10").unwrap(), b"\x00\xab\x10");
+        assert!(from_hex("abc").is_none());
+        assert!(from_hex("zz").is_none());
+        assert!(from_base64("Zm9v!").is_none());
+    }
+}
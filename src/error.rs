@@ -0,0 +1,115 @@
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt;
+
+use crate::{DnsMessage, ExtendedError, TsigError};
+#[cfg(feature = "std")]
+use std::io;
+
+// everything that can go wrong while talking DNS
+#[derive(Debug)]
+pub enum DnsError {
+    // the socket itself failed (bind, send, recv...)
+    #[cfg(feature = "std")]
+    Io(io::Error),
+    // no answer from the server in time
+    Timeout,
+    // decoding a record's rdata needed more bytes than its rdlength said it had
+    RdataOverrun {
+        rr_type: u16,
+    },
+    // a name that can't go on the wire: an empty label in the middle, a label over 63 bytes or
+    // the whole thing over 255
+    InvalidName(String),
+    // a TSIG-signed exchange that didn't check out
+    Tsig(TsigError),
+    // the server sent something that doesn't make sense as DNS (or as an answer to what we
+    // asked), or a message of ours is too big to be DNS
+    Malformed(&'static str),
+    // the server answered, but with an error rcode (REFUSED, SERVFAIL...) where we needed data
+    Rcode(u16),
+    // the same, with the Extended DNS Errors (RFC 8914) the server gave as the reason
+    Extended {
+        rcode: u16,
+        errors: Vec<ExtendedError>,
+    },
+    // DNSSEC says the answer was tampered with, or the keys that should vouch for it don't
+    Bogus(&'static str),
+}
+
+impl fmt::Display for DnsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            #[cfg(feature = "std")]
+            DnsError::Io(e) => write!(f, "I/O error: {}", e),
+            DnsError::Timeout => write!(f, "timed out waiting for the server"),
+            DnsError::RdataOverrun { rr_type } => {
+                write!(
+                    f,
+                    "rdata of a type {} record runs past its rdlength",
+                    rr_type
+                )
+            }
+            DnsError::InvalidName(name) => write!(f, "invalid domain name: {:?}", name),
+            DnsError::Tsig(e) => write!(f, "TSIG: {}", e),
+            DnsError::Malformed(what) => write!(f, "malformed message: {}", what),
+            DnsError::Rcode(rcode) => write!(f, "server answered with rcode {}", rcode),
+            DnsError::Extended { rcode, errors } => {
+                write!(f, "server answered with rcode {}", rcode)?;
+                for (i, error) in errors.iter().enumerate() {
+                    f.write_str(if i == 0 { " (" } else { ", " })?;
+                    write!(f, "{}", error)?;
+                }
+                f.write_str(")")
+            }
+            DnsError::Bogus(why) => write!(f, "DNSSEC validation failed: {}", why),
+        }
+    }
+}
+
+impl DnsError {
+    // the error for a response whose rcode we can't do anything with, with its Extended DNS
+    // Errors if it has any
+    pub fn from_response(res: &DnsMessage) -> Self {
+        let errors = res.extended_errors();
+        if errors.is_empty() {
+            DnsError::Rcode(res.rcode())
+        } else {
+            DnsError::Extended {
+                rcode: res.rcode(),
+                errors,
+            }
+        }
+    }
+
+    // the server's rcode, for the errors that are one
+    pub fn rcode(&self) -> Option<u16> {
+        match self {
+            DnsError::Rcode(rcode) | DnsError::Extended { rcode, .. } => Some(*rcode),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for DnsError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            DnsError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl From<io::Error> for DnsError {
+    fn from(e: io::Error) -> Self {
+        DnsError::Io(e)
+    }
+}
+
+impl From<TsigError> for DnsError {
+    fn from(e: TsigError) -> Self {
+        DnsError::Tsig(e)
+    }
+}
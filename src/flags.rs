@@ -0,0 +1,76 @@
+// the 16 bit flags word of the header, one field per bit (or group of bits):
+//
+//   15  14-11   10  9   8   7   6   5   4   3-0
+//   QR  OPCODE  AA  TC  RD  RA  Z   AD  CD  RCODE
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DnsFlags {
+    pub qr: bool,   // 0 for a query, 1 for a response
+    pub opcode: u8, // 0 is a standard query
+    pub aa: bool,   // the answer comes from a server authoritative for the name
+    pub tc: bool,   // truncated, the full answer only fits over TCP
+    pub rd: bool,   // recursion desired: please chase the answer for me
+    pub ra: bool,   // recursion available on this server
+    pub z: bool,    // reserved, always 0
+    pub ad: bool,   // authentic data: the server validated the answer with DNSSEC
+    pub cd: bool,   // checking disabled: don't validate, hand me the data as is
+    pub rcode: u8,  // response code, 0 is no error and 3 is NXDOMAIN
+}
+
+const QR: u16 = 1 << 15;
+const AA: u16 = 1 << 10;
+const TC: u16 = 1 << 9;
+const RD: u16 = 1 << 8;
+const RA: u16 = 1 << 7;
+const Z: u16 = 1 << 6;
+const AD: u16 = 1 << 5;
+const CD: u16 = 1 << 4;
+
+impl DnsFlags {
+    pub fn from_u16(flags: u16) -> Self {
+        DnsFlags {
+            qr: flags & QR != 0,
+            opcode: ((flags >> 11) & 0xF) as u8,
+            aa: flags & AA != 0,
+            tc: flags & TC != 0,
+            rd: flags & RD != 0,
+            ra: flags & RA != 0,
+            z: flags & Z != 0,
+            ad: flags & AD != 0,
+            cd: flags & CD != 0,
+            rcode: (flags & 0xF) as u8,
+        }
+    }
+
+    pub fn to_u16(self) -> u16 {
+        let bit = |on: bool, mask: u16| if on { mask } else { 0 };
+        bit(self.qr, QR)
+            | ((self.opcode as u16 & 0xF) << 11)
+            | bit(self.aa, AA)
+            | bit(self.tc, TC)
+            | bit(self.rd, RD)
+            | bit(self.ra, RA)
+            | bit(self.z, Z)
+            | bit(self.ad, AD)
+            | bit(self.cd, CD)
+            | (self.rcode as u16 & 0xF)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_flags_round_trip() {
+        for flags in [
+            0x0000, 0x0100, 0x8180, 0x8183, 0x81A0, 0x0110, 0xFFFF, 0x2800,
+        ] {
+            assert_eq!(DnsFlags::from_u16(flags).to_u16(), flags);
+        }
+
+        let flags = DnsFlags::from_u16(0x81B0);
+        assert!(flags.qr && flags.rd && flags.ra && flags.ad && flags.cd);
+        assert!(!flags.aa && !flags.tc);
+        assert_eq!(flags.rcode, 0);
+    }
+}
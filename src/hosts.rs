@@ -0,0 +1,280 @@
+// the hosts file: names with addresses that are settled locally, looked at before any DNS the way
+// the C library does with "files dns" in nsswitch.conf. Containers live on it, their runtime
+// writes the names of linked containers there and nowhere else.
+//
+// One line per address: the address, its canonical name, then any aliases, with # starting a
+// comment. A name found there is ours, and we answer for it alone: its addresses for A and
+// AAAA, nothing at all for any other type (rather than asking DNS about it), and every name an
+// address has for the PTR query of its reverse name. Lines that don't make sense are skipped.
+//
+// HostsFile re-reads the file when it changes on disk, checking its modification time at most
+// once a second, so an entry added while we run is there a moment later
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::{DnsMessage, Name, RData, RecordType, ResourceRecord};
+
+#[cfg(not(windows))]
+pub const HOSTS_FILE: &str = "/etc/hosts";
+#[cfg(windows)]
+pub const HOSTS_FILE: &str = r"C:\Windows\System32\drivers\etc\hosts";
+
+// how long we go without looking at the file's modification time
+const RELOAD_CHECK: Duration = Duration::from_secs(1);
+// the records we make up don't last: the file can change any time, and whoever we hand them to
+// (a client of the server, say) should come back and ask
+const HOSTS_TTL: u32 = 0;
+
+// the parsed contents of one hosts file
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct HostsTable {
+    by_name: HashMap<Name, Vec<IpAddr>>,
+    // keyed by the reverse name (1.0.0.127.in-addr.arpa), which is what a PTR query asks about
+    by_reverse: HashMap<Name, Vec<Name>>,
+}
+
+impl HostsTable {
+    pub fn parse(text: &str) -> Self {
+        let mut table = HostsTable::default();
+        for line in text.lines() {
+            let line = line.split('#').next().unwrap_or("");
+            let mut words = line.split_whitespace();
+            // a scope on a link-local address (fe80::1%eth0) means nothing to DNS
+            let Some(addr) = words
+                .next()
+                .and_then(|addr| addr.split('%').next()?.parse::<IpAddr>().ok())
+            else {
+                continue;
+            };
+            for name in words.map(Name::from) {
+                let addrs = table.by_name.entry(name.clone()).or_default();
+                if !addrs.contains(&addr) {
+                    addrs.push(addr);
+                }
+                let names = table.by_reverse.entry(Name::reverse(addr)).or_default();
+                if !names.contains(&name) {
+                    names.push(name);
+                }
+            }
+        }
+        table
+    }
+
+    // every address the file gives `name`, in the order of the lines
+    pub fn addrs(&self, name: &Name) -> Option<&[IpAddr]> {
+        self.by_name.get(name).map(Vec::as_slice)
+    }
+
+    // every name the file gives `addr`, the canonical one of its first line first
+    pub fn names(&self, addr: IpAddr) -> Option<&[Name]> {
+        self.by_reverse.get(&Name::reverse(addr)).map(Vec::as_slice)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.by_name.is_empty()
+    }
+
+    // the authoritative answer to `query`, if it's about a name (or a reverse name) in the file.
+    // None for everything else, and for classes other than IN
+    pub fn answer(&self, query: &DnsMessage) -> Option<DnsMessage> {
+        let question = query.question();
+        if !matches!(question.qclass, 1 | 255) {
+            return None;
+        }
+        let qtype = RecordType::from(question.qtype);
+        let owner = question.qname.clone();
+        let records: Vec<RData> = if let Some(addrs) = self.by_name.get(&owner) {
+            addrs
+                .iter()
+                .filter_map(|addr| match (addr, qtype) {
+                    (IpAddr::V4(addr), RecordType::A | RecordType::Any) => Some(RData::A(*addr)),
+                    (IpAddr::V6(addr), RecordType::AAAA | RecordType::Any) => {
+                        Some(RData::AAAA(*addr))
+                    }
+                    _ => None,
+                })
+                .collect()
+        } else {
+            let names = self.by_reverse.get(&owner)?;
+            match qtype {
+                RecordType::PTR | RecordType::Any => names
+                    .iter()
+                    .map(|name| RData::PTR(name.to_string()))
+                    .collect(),
+                _ => Vec::new(),
+            }
+        };
+
+        let mut res = DnsMessage::response_to(query);
+        let mut flags = res.flags();
+        flags.aa = true;
+        flags.ra = true;
+        res.set_flags(flags);
+        for data in records {
+            res.add_answer(ResourceRecord::new(owner.clone(), HOSTS_TTL, data));
+        }
+        Some(res)
+    }
+}
+
+// a hosts file on disk, re-read whenever it changes
+#[derive(Debug)]
+pub struct HostsFile {
+    path: PathBuf,
+    loaded: Mutex<Loaded>,
+}
+
+#[derive(Debug)]
+struct Loaded {
+    table: Arc<HostsTable>,
+    // what the file looked like when we read it: modification time and length, the length
+    // catching a rewrite within the same tick of a coarse clock
+    version: Option<(SystemTime, u64)>,
+    // when we last looked, None to look on the next lookup
+    checked: Option<Instant>,
+}
+
+impl HostsFile {
+    // fails if the file can't be read now. Once it's open a file that goes away is an empty one,
+    // until it's back
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let version = version(&path)?;
+        let table = HostsTable::parse(&fs::read_to_string(&path)?);
+        Ok(HostsFile {
+            path,
+            loaded: Mutex::new(Loaded {
+                table: Arc::new(table),
+                version: Some(version),
+                checked: Some(Instant::now()),
+            }),
+        })
+    }
+
+    // the machine's hosts file, HOSTS_FILE
+    pub fn system() -> io::Result<Self> {
+        Self::open(HOSTS_FILE)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    // the table as the file is now, reading it again first if it changed
+    pub fn table(&self) -> Arc<HostsTable> {
+        let mut loaded = self.loaded.lock().unwrap();
+        if loaded
+            .checked
+            .is_some_and(|checked| checked.elapsed() < RELOAD_CHECK)
+        {
+            return loaded.table.clone();
+        }
+        loaded.checked = Some(Instant::now());
+        let now = version(&self.path).ok();
+        if now != loaded.version {
+            let text = now.and_then(|_| fs::read_to_string(&self.path).ok());
+            loaded.table = Arc::new(HostsTable::parse(text.as_deref().unwrap_or("")));
+            loaded.version = now;
+        }
+        loaded.table.clone()
+    }
+
+    pub fn answer(&self, query: &DnsMessage) -> Option<DnsMessage> {
+        self.table().answer(query)
+    }
+}
+
+fn version(path: &Path) -> io::Result<(SystemTime, u64)> {
+    let meta = fs::metadata(path)?;
+    Ok((meta.modified()?, meta.len()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::{Ipv4Addr, Ipv6Addr};
+
+    const HOSTS: &str = "\
+# the usual
+127.0.0.1\tlocalhost
+::1 localhost ip6-localhost
+192.168.1.50 printer.home printer # the one upstairs
+fe80::1%eth0 router.home
+not-an-address bogus.home
+10.0.0.1
+";
+
+    #[test]
+    fn test_parse() {
+        let table = HostsTable::parse(HOSTS);
+        assert_eq!(
+            table.addrs(&Name::from("LOCALHOST.")),
+            Some(
+                &[
+                    IpAddr::from(Ipv4Addr::LOCALHOST),
+                    IpAddr::from(Ipv6Addr::LOCALHOST)
+                ][..]
+            )
+        );
+        assert_eq!(
+            table.addrs(&Name::from("printer")),
+            Some(&[IpAddr::from([192, 168, 1, 50])][..])
+        );
+        assert!(table.addrs(&Name::from("router.home")).is_some());
+        assert!(table.addrs(&Name::from("bogus.home")).is_none());
+        assert_eq!(
+            table.names(IpAddr::from([192, 168, 1, 50])),
+            Some(&[Name::from("printer.home"), Name::from("printer")][..])
+        );
+    }
+
+    #[test]
+    fn test_answers() {
+        let table = HostsTable::parse(HOSTS);
+        let ask = |name: &str, qtype: RecordType| {
+            table.answer(&DnsMessage::query(name).qtype(qtype).id(9).build())
+        };
+
+        let res = ask("printer.home", RecordType::A).unwrap();
+        assert_eq!(res.header.identification, 9);
+        assert!(res.flags().aa);
+        assert_eq!(res.ipv4_addrs(), [Ipv4Addr::new(192, 168, 1, 50)]);
+        // the name is ours, so no AAAA means none at all rather than asking DNS
+        let res = ask("printer.home", RecordType::AAAA).unwrap();
+        assert_eq!(res.rcode(), 0);
+        assert!(res.answers.is_empty());
+        assert_eq!(
+            ask("localhost", RecordType::AAAA).unwrap().ipv6_addrs(),
+            [Ipv6Addr::LOCALHOST]
+        );
+        assert_eq!(ask("localhost", RecordType::Any).unwrap().answers.len(), 2);
+        assert!(ask("example.com", RecordType::A).is_none());
+
+        let res = ask("50.1.168.192.in-addr.arpa", RecordType::PTR).unwrap();
+        assert_eq!(res.answers[0].data, RData::PTR("printer.home".to_string()));
+    }
+
+    #[test]
+    fn test_reload() {
+        let path = std::env::temp_dir().join(format!("hosts-{}", std::process::id()));
+        fs::write(&path, "192.0.2.1 one.test\n").unwrap();
+        let hosts = HostsFile::open(&path).unwrap();
+        assert!(hosts.table().addrs(&Name::from("one.test")).is_some());
+
+        fs::write(&path, "192.0.2.2 two.test three.test\n").unwrap();
+        hosts.loaded.lock().unwrap().checked = None;
+        let table = hosts.table();
+        assert!(table.addrs(&Name::from("one.test")).is_none());
+        assert!(table.addrs(&Name::from("three.test")).is_some());
+
+        // gone is empty, not an error
+        fs::remove_file(&path).unwrap();
+        hosts.loaded.lock().unwrap().checked = None;
+        assert!(hosts.table().is_empty());
+    }
+}
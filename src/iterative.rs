@@ -0,0 +1,1290 @@
+// iterative resolution: instead of handing the whole job to a recursive resolver like 8.8.8.8,
+// we walk the tree ourselves. Ask a root server, get referred to the TLD's servers, get referred
+// to the zone's own servers, and finally get the answer from one of them. Every query goes out
+// with RD off, each server only tells us what it knows first hand.
+//
+// The referrals we follow are remembered (the "infrastructure cache"): once we know where
+// example.com's servers are, looking up another name in it goes straight to them instead of
+// through the root and com again
+use std::collections::HashMap;
+use std::fmt;
+use std::mem;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::dig::fqdn;
+use crate::rdata::{TYPE_A, TYPE_NS};
+use crate::resolver::recursed_anyway;
+use crate::transport::DnsTransport;
+use crate::{
+    DnsError, DnsMessage, Name, RData, RecordType, Resolver, ResourceRecord, RCODE_NXDOMAIN,
+};
+
+// where iteration starts: the root servers' names and addresses. Full socket addresses rather
+// than IPs, so a lab root can listen somewhere other than port 53
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RootHints {
+    servers: Vec<(Name, SocketAddr)>,
+}
+
+// the IANA root servers (https://www.iana.org/domains/root/servers)
+const IANA_ROOTS: [(&str, Ipv4Addr, Ipv6Addr); 13] = [
+    (
+        "a.root-servers.net",
+        Ipv4Addr::new(198, 41, 0, 4),
+        Ipv6Addr::new(0x2001, 0x503, 0xba3e, 0, 0, 0, 0x2, 0x30),
+    ),
+    (
+        "b.root-servers.net",
+        Ipv4Addr::new(170, 247, 170, 2),
+        Ipv6Addr::new(0x2801, 0x1b8, 0x10, 0, 0, 0, 0, 0xb),
+    ),
+    (
+        "c.root-servers.net",
+        Ipv4Addr::new(192, 33, 4, 12),
+        Ipv6Addr::new(0x2001, 0x500, 0x2, 0, 0, 0, 0, 0xc),
+    ),
+    (
+        "d.root-servers.net",
+        Ipv4Addr::new(199, 7, 91, 13),
+        Ipv6Addr::new(0x2001, 0x500, 0x2d, 0, 0, 0, 0, 0xd),
+    ),
+    (
+        "e.root-servers.net",
+        Ipv4Addr::new(192, 203, 230, 10),
+        Ipv6Addr::new(0x2001, 0x500, 0xa8, 0, 0, 0, 0, 0xe),
+    ),
+    (
+        "f.root-servers.net",
+        Ipv4Addr::new(192, 5, 5, 241),
+        Ipv6Addr::new(0x2001, 0x500, 0x2f, 0, 0, 0, 0, 0xf),
+    ),
+    (
+        "g.root-servers.net",
+        Ipv4Addr::new(192, 112, 36, 4),
+        Ipv6Addr::new(0x2001, 0x500, 0x12, 0, 0, 0, 0, 0xd0d),
+    ),
+    (
+        "h.root-servers.net",
+        Ipv4Addr::new(198, 97, 190, 53),
+        Ipv6Addr::new(0x2001, 0x500, 0x1, 0, 0, 0, 0, 0x53),
+    ),
+    (
+        "i.root-servers.net",
+        Ipv4Addr::new(192, 36, 148, 17),
+        Ipv6Addr::new(0x2001, 0x7fe, 0, 0, 0, 0, 0, 0x53),
+    ),
+    (
+        "j.root-servers.net",
+        Ipv4Addr::new(192, 58, 128, 30),
+        Ipv6Addr::new(0x2001, 0x503, 0xc27, 0, 0, 0, 0x2, 0x30),
+    ),
+    (
+        "k.root-servers.net",
+        Ipv4Addr::new(193, 0, 14, 129),
+        Ipv6Addr::new(0x2001, 0x7fd, 0, 0, 0, 0, 0, 0x1),
+    ),
+    (
+        "l.root-servers.net",
+        Ipv4Addr::new(199, 7, 83, 42),
+        Ipv6Addr::new(0x2001, 0x500, 0x9f, 0, 0, 0, 0, 0x42),
+    ),
+    (
+        "m.root-servers.net",
+        Ipv4Addr::new(202, 12, 27, 33),
+        Ipv6Addr::new(0x2001, 0xdc3, 0, 0, 0, 0, 0, 0x35),
+    ),
+];
+
+impl Default for RootHints {
+    fn default() -> Self {
+        Self::iana()
+    }
+}
+
+impl RootHints {
+    // no servers at all, fill it in with add() for a private hierarchy
+    pub fn empty() -> Self {
+        RootHints {
+            servers: Vec::new(),
+        }
+    }
+
+    // the real root, IPv4 addresses first: plenty of hosts still can't reach anything over v6
+    pub fn iana() -> Self {
+        let mut hints = RootHints::empty();
+        for (name, v4, _) in IANA_ROOTS {
+            hints.add(name, SocketAddr::new(IpAddr::V4(v4), 53));
+        }
+        for (name, _, v6) in IANA_ROOTS {
+            hints.add(name, SocketAddr::new(IpAddr::V6(v6), 53));
+        }
+        hints
+    }
+
+    pub fn add(&mut self, name: impl Into<Name>, addr: SocketAddr) {
+        self.servers.push((name.into(), addr));
+    }
+
+    pub fn servers(&self) -> &[(Name, SocketAddr)] {
+        &self.servers
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IterativeOptions {
+    // per query; a server that doesn't answer in time is skipped for the next one
+    pub timeout: Duration,
+    // the port the servers we get referred to listen on. Always 53 out there, but a lab
+    // hierarchy running unprivileged can move it
+    pub port: u16,
+    // QNAME minimization (RFC 9156): on the way down, ask each zone's servers only about the
+    // next label (an NS query for it) instead of the whole name, so only the servers of the
+    // name's own zone ever see all of it
+    pub qname_minimization: bool,
+}
+
+impl Default for IterativeOptions {
+    fn default() -> Self {
+        IterativeOptions {
+            timeout: Duration::from_secs(2),
+            port: 53,
+            qname_minimization: true,
+        }
+    }
+}
+
+// a zone's nameservers, each with the addresses we were given for it (none if there was no glue)
+type Nameservers = Vec<(Name, Vec<IpAddr>)>;
+
+// no real name is more than a handful of delegations deep, a chain longer than this is a loop
+const MAX_REFERRALS: usize = 16;
+// RFC 9156 2.3's limits on minimization, for names with lots of labels (an ip6.arpa one has
+// 34): one label at a time for the first few queries, then bigger steps, so a name never takes
+// more than this many minimized queries
+const MAX_MINIMISE_COUNT: usize = 10;
+const MINIMISE_ONE_LAB: usize = 4;
+// how deep we go resolving the nameservers of nameservers (when a referral has no glue)
+const MAX_DEPTH: usize = 4;
+// how many times one lookup goes back to the root to chase a CNAME
+const MAX_CNAMES: usize = 8;
+// how many nameservers without glue we resolve at the same time
+const GLUE_LOOKUPS: usize = 3;
+// the longest we remember a delegation, whatever its NS records say
+const MAX_DELEGATION_TTL: u32 = 86400;
+
+pub struct IterativeResolver {
+    hints: RootHints,
+    options: IterativeOptions,
+    // each lookup starts at the next root in line, which spreads the load and means a dead root
+    // only slows down the lookups that happen to start on it
+    next_root: AtomicUsize,
+    // shared with the forks looking up nameservers, what they learn is ours too
+    delegations: Arc<Delegations>,
+    // where each server's answer is written down, for trace(). Shared with the forks as well
+    trace: Option<TraceLog>,
+}
+
+type TraceLog = Arc<Mutex<Vec<TraceStep>>>;
+
+// one query of a traced lookup: who we asked, about what, and what came of it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TraceStep {
+    // the zone whose servers we were asking, the root to start with
+    pub zone: Name,
+    pub server: SocketAddr,
+    // the name and type that went out, which with QNAME minimization isn't always the one
+    // being looked up
+    pub qname: Name,
+    pub qtype: u16,
+    pub rtt: Duration,
+    // it answered although the query went out with RD off: it looked the name up elsewhere
+    // rather than answering from its own zones, which is no use for following the delegations
+    pub recursed: bool,
+    // what went wrong, as it would print, for a server that didn't answer or answered with
+    // SERVFAIL, REFUSED and the like
+    pub outcome: Result<Referral, String>,
+}
+
+// one line per step, in the spirit of dig +trace:
+//
+//     ;; 198.41.0.4:53 for . in 23 ms: example.com. NS, referral to com. (a.gtld-servers.net. ...)
+impl fmt::Display for TraceStep {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            ";; {} for {} in {} ms: {} {}, ",
+            self.server,
+            fqdn(&self.zone),
+            self.rtt.as_millis(),
+            fqdn(&self.qname),
+            RecordType::from(self.qtype)
+        )?;
+        match &self.outcome {
+            Ok(Referral::Answer(records)) if records.is_empty() => f.write_str("no data"),
+            Ok(Referral::Answer(records)) => write!(f, "{} answers", records.len()),
+            Ok(Referral::NameError) => f.write_str("NXDOMAIN"),
+            Ok(Referral::Delegation { zone, nameservers }) => {
+                write!(f, "referral to {} (", fqdn(zone))?;
+                for (i, (ns, glue)) in nameservers.iter().enumerate() {
+                    let sep = if i == 0 { "" } else { ", " };
+                    write!(f, "{}{}", sep, fqdn(ns))?;
+                    for ip in glue {
+                        write!(f, " {}", ip)?;
+                    }
+                }
+                f.write_str(")")
+            }
+            Err(e) => write!(f, "error: {}", e),
+        }?;
+        if self.recursed {
+            f.write_str(" (recursed, although asked not to)")?;
+        }
+        Ok(())
+    }
+}
+
+// everything trace() saw on the way down: every query, in the order they were answered (the
+// lookups of nameservers without glue run alongside, their steps are mixed in with the rest),
+// and what resolve() would have returned
+#[derive(Debug)]
+pub struct ResolutionTrace {
+    pub steps: Vec<TraceStep>,
+    pub answer: Result<DnsMessage, DnsError>,
+}
+
+// the infrastructure cache: zones we were referred to, with the addresses of their servers and
+// when the NS records that told us about them run out
+#[derive(Debug, Default)]
+struct Delegations(Mutex<HashMap<Name, (Vec<SocketAddr>, Instant)>>);
+
+impl Delegations {
+    fn insert(&self, zone: Name, servers: Vec<SocketAddr>, ttl: u32) {
+        let expires = Instant::now() + Duration::from_secs(ttl.min(MAX_DELEGATION_TTL) as u64);
+        self.0.lock().unwrap().insert(zone, (servers, expires));
+    }
+
+    // the zone closest to `name` (`name` itself included, the root not) that we still know
+    // the servers of
+    fn closest(&self, name: &Name) -> Option<(Name, Vec<SocketAddr>)> {
+        let mut zones = self.0.lock().unwrap();
+        let now = Instant::now();
+        zones.retain(|_, (_, expires)| *expires > now);
+        let mut zone = name.clone();
+        while !zone.is_root() {
+            if let Some((servers, _)) = zones.get(&zone) {
+                return Some((zone, servers.clone()));
+            }
+            zone = zone.parent()?;
+        }
+        None
+    }
+
+    fn forget(&self, zone: &Name) {
+        self.0.lock().unwrap().remove(zone);
+    }
+
+    fn len(&self) -> usize {
+        self.0.lock().unwrap().len()
+    }
+}
+
+impl Default for IterativeResolver {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl IterativeResolver {
+    pub fn new() -> Self {
+        Self::with_root_hints(RootHints::iana())
+    }
+
+    pub fn with_root_hints(hints: RootHints) -> Self {
+        IterativeResolver {
+            hints,
+            options: IterativeOptions::default(),
+            next_root: AtomicUsize::new(0),
+            delegations: Arc::default(),
+            trace: None,
+        }
+    }
+
+    pub fn set_options(&mut self, options: IterativeOptions) {
+        self.options = options;
+    }
+
+    // how many zones' servers we know right now, without going through the root
+    pub fn known_zones(&self) -> usize {
+        self.delegations.len()
+    }
+
+    // the response of the server that finally had the answer (or said the name doesn't exist,
+    // or that it has no records of that type). When the name is a CNAME to somewhere the
+    // server doesn't have the records for, we go and look the target up too, from the root
+    // again: the response is then the last server's, with the question put back to `name` and
+    // the CNAMEs that led there ahead of its answers
+    pub fn resolve(&self, name: &str, qtype: u16) -> Result<DnsMessage, DnsError> {
+        let name = Name::from(name);
+        let mut res = self.lookup(&name, qtype, &[])?;
+        let mut chain = Vec::new();
+        let mut seen = vec![name.clone()];
+        while let Some(target) = redirected(&res, qtype) {
+            if seen.contains(&target) || seen.len() > MAX_CNAMES {
+                return Err(DnsError::Malformed("CNAME chain loops or runs too long"));
+            }
+            seen.push(target.clone());
+            chain.append(&mut res.answers);
+            res = self.lookup(&target, qtype, &[])?;
+        }
+
+        if !chain.is_empty() {
+            chain.append(&mut res.answers);
+            res.answers = chain;
+            res.header.no_of_answers_rr = res.answers.len() as u16;
+            if let Some(question) = res.questions.first_mut() {
+                question.qname = name;
+            }
+        }
+        Ok(res)
+    }
+
+    // resolve() that keeps track of every server asked along the way: the referral each one
+    // gave, how long it took, the ones that let us down. The delegations we already know are
+    // still used, so a trace from a fresh resolver is the one that shows the whole path
+    pub fn trace(&self, name: &str, qtype: u16) -> ResolutionTrace {
+        let mut tracer = self.fork();
+        let log = TraceLog::default();
+        tracer.trace = Some(log.clone());
+        let answer = tracer.resolve(name, qtype);
+        // the glue lookups still running hold on to the log too, what they add from now on
+        // isn't part of the trace
+        let steps = mem::take(&mut *log.lock().unwrap());
+        ResolutionTrace { steps, answer }
+    }
+
+    // `resolving` is the chain of lookups this one is part of: the names whose nameservers we
+    // are after, outermost first. Empty for a lookup of our own. Starts at the closest zone we
+    // know the servers of; if they let us down we forget them and go back to the root
+    fn lookup(&self, name: &Name, qtype: u16, resolving: &[Name]) -> Result<DnsMessage, DnsError> {
+        if resolving.len() > MAX_DEPTH {
+            return Err(DnsError::Malformed(
+                "nameservers nested too deep to resolve",
+            ));
+        }
+        if let Some((zone, servers)) = self.delegations.closest(name) {
+            match self.lookup_from(name, qtype, resolving, zone.clone(), servers) {
+                Ok(res) => return Ok(res),
+                Err(_) => self.delegations.forget(&zone),
+            }
+        }
+        self.lookup_from(name, qtype, resolving, Name::root(), self.roots())
+    }
+
+    // the rest of a lookup, from the servers of `zone` down
+    fn lookup_from(
+        &self,
+        name: &Name,
+        qtype: u16,
+        resolving: &[Name],
+        mut zone: Name,
+        mut servers: Vec<SocketAddr>,
+    ) -> Result<DnsMessage, DnsError> {
+        let mut chain = resolving.to_vec();
+        chain.push(name.clone());
+
+        // nameservers of the current zone that came without glue, resolved only if none of the
+        // ones with glue answer
+        let mut unresolved = Vec::new();
+        let query = DnsMessage::query(name.clone())
+            .qtype(qtype)
+            .recursion_desired(false)
+            .build();
+
+        let mut minimized_count = 0;
+        for _ in 0..MAX_REFERRALS {
+            let minimized = match self.options.qname_minimization {
+                true => minimized(name, &zone, minimized_count),
+                false => None,
+            };
+            minimized_count += minimized.is_some() as usize;
+            let (target, step) = match &minimized {
+                Some(next) => (
+                    next,
+                    DnsMessage::query(next.clone())
+                        .qtype(TYPE_NS)
+                        .recursion_desired(false)
+                        .build(),
+                ),
+                None => (name, query.clone()),
+            };
+            let mut res = match self.ask(&zone, &servers, &step) {
+                Ok(res) => res,
+                Err(_) if !unresolved.is_empty() => {
+                    servers = self.resolve_nameservers(&mem::take(&mut unresolved), &chain)?;
+                    self.ask(&zone, &servers, &step)?
+                }
+                Err(e) => return Err(e),
+            };
+            let mut referral = classify(&res, target);
+            // anything but a referral for the next label (usually NODATA: no zone starts there,
+            // but also servers that get NS queries for empty non-terminals wrong) and we give up
+            // on hiding the rest of the name from these servers and ask them the real question
+            if minimized.is_some() && !matches!(referral, Referral::Delegation { .. }) {
+                res = self.ask(&zone, &servers, &query)?;
+                referral = classify(&res, name);
+            }
+            let (child, nameservers) = match referral {
+                Referral::Delegation { zone, nameservers } => (zone, nameservers),
+                // an answer, NXDOMAIN, or NODATA: whatever it is, it's final
+                _ => return Ok(res),
+            };
+            // a referral has to take us further down, otherwise the servers are sending us in
+            // circles (or the zone is lame)
+            if child == zone || !child.is_subdomain_of(&zone) {
+                return Err(DnsError::Malformed(
+                    "referral doesn't lead closer to the name",
+                ));
+            }
+
+            let port = self.options.port;
+            servers = nameservers
+                .iter()
+                .flat_map(|(_, addrs)| addrs.iter().map(|ip| SocketAddr::new(*ip, port)))
+                .collect();
+            // a nameserver inside the zone can only be found through the zone's own servers,
+            // which is exactly what we don't know yet, and one we're already in the middle of
+            // resolving would send us round in a circle
+            unresolved = nameservers
+                .into_iter()
+                .filter(|(ns, addrs)| {
+                    addrs.is_empty() && !ns.is_subdomain_of(&child) && !chain.contains(ns)
+                })
+                .map(|(ns, _)| ns)
+                .collect();
+            if servers.is_empty() {
+                servers = self.resolve_nameservers(&mem::take(&mut unresolved), &chain)?;
+            }
+            let ttl = res
+                .authority
+                .iter()
+                .filter(|rr| rr.rr_type == TYPE_NS && rr.name == child)
+                .map(|rr| rr.ttl)
+                .min()
+                .unwrap_or(0);
+            self.delegations.insert(child.clone(), servers.clone(), ttl);
+            zone = child;
+        }
+        Err(DnsError::Malformed("too many referrals"))
+    }
+
+    // all the roots, starting with the next one in the rotation
+    fn roots(&self) -> Vec<SocketAddr> {
+        let roots = self.hints.servers();
+        if roots.is_empty() {
+            return Vec::new();
+        }
+        let start = self.next_root.fetch_add(1, Ordering::Relaxed) % roots.len();
+        roots
+            .iter()
+            .cycle()
+            .skip(start)
+            .take(roots.len())
+            .map(|(_, addr)| *addr)
+            .collect()
+    }
+
+    // tries the servers in order until one answers, it's enough that one of them does.
+    // `zone` is the one they're the servers of, for the trace
+    fn ask(
+        &self,
+        zone: &Name,
+        servers: &[SocketAddr],
+        query: &DnsMessage,
+    ) -> Result<DnsMessage, DnsError> {
+        let mut last_err = DnsError::Malformed("no servers to ask");
+        for &server in servers {
+            let started = Instant::now();
+            let res = ask_server(server, query, &self.options);
+            if let Some(log) = &self.trace {
+                let question = query.question();
+                let recursed = res.as_ref().is_ok_and(|res| recursed_anyway(query, res));
+                let outcome = match &res {
+                    Ok(res) => match res.rcode() {
+                        0 | RCODE_NXDOMAIN => Ok(classify(res, &question.qname)),
+                        _ => Err(DnsError::from_response(res).to_string()),
+                    },
+                    Err(e) => Err(e.to_string()),
+                };
+                log.lock().unwrap().push(TraceStep {
+                    zone: zone.clone(),
+                    server,
+                    qname: question.qname.clone(),
+                    qtype: question.qtype,
+                    rtt: started.elapsed(),
+                    recursed,
+                    outcome,
+                });
+            }
+            match res {
+                Ok(res) => return Ok(res),
+                Err(e) => last_err = e,
+            }
+        }
+        Err(last_err)
+    }
+
+    // the addresses of whichever of the nameservers resolves first. Each one is a whole lookup
+    // of its own, and a dead server somewhere along one of them costs a timeout or more, so up to
+    // GLUE_LOOKUPS of them run at once on their own threads. The ones still going when we have
+    // an address are left to finish by themselves, nobody waits for them
+    fn resolve_nameservers(
+        &self,
+        nameservers: &[Name],
+        resolving: &[Name],
+    ) -> Result<Vec<SocketAddr>, DnsError> {
+        let port = self.options.port;
+        let (tx, rx) = mpsc::channel();
+        let mut pending = nameservers.iter();
+        let mut running = 0;
+        let spawn = |ns: &Name| {
+            let (resolver, ns, resolving, tx) =
+                (self.fork(), ns.clone(), resolving.to_vec(), tx.clone());
+            thread::spawn(move || {
+                let _ = tx.send(resolver.lookup(&ns, TYPE_A, &resolving));
+            });
+        };
+        for ns in pending.by_ref().take(GLUE_LOOKUPS) {
+            spawn(ns);
+            running += 1;
+        }
+
+        let mut last_err = DnsError::Malformed("no nameservers we can resolve");
+        while running > 0 {
+            running -= 1;
+            match rx.recv() {
+                Ok(Ok(res)) if !res.ipv4_addrs().is_empty() => {
+                    return Ok(res
+                        .ipv4_addrs()
+                        .into_iter()
+                        .map(|ip| SocketAddr::new(IpAddr::V4(ip), port))
+                        .collect())
+                }
+                Ok(Ok(_)) => {}
+                Ok(Err(e)) => last_err = e,
+                Err(_) => break,
+            }
+            // one more finished without an address, its place goes to the next in line
+            if let Some(ns) = pending.next() {
+                spawn(ns);
+                running += 1;
+            }
+        }
+        Err(last_err)
+    }
+
+    // a copy to hand to another thread, picking up the root rotation where we are
+    fn fork(&self) -> IterativeResolver {
+        IterativeResolver {
+            hints: self.hints.clone(),
+            options: self.options.clone(),
+            next_root: AtomicUsize::new(self.next_root.load(Ordering::Relaxed)),
+            delegations: self.delegations.clone(),
+            trace: self.trace.clone(),
+        }
+    }
+}
+
+// resolving from the root in place of an upstream that recurses for us: as the transport of a
+// Resolver (or behind a DnsServer) this makes it a recursive resolver of its own. The answer
+// comes back as a recursive server would give it, RA set and AA not
+impl DnsTransport for IterativeResolver {
+    fn exchange(&self, msg: &DnsMessage) -> Result<DnsMessage, DnsError> {
+        let mut res = self.resolve(msg.question().qname.as_str(), msg.question().qtype)?;
+        res.header.identification = msg.header.identification;
+        let mut flags = res.flags();
+        flags.aa = false;
+        flags.ra = true;
+        flags.rd = msg.recursion_desired();
+        res.set_flags(flags);
+        Ok(res)
+    }
+}
+
+// the name to ask `zone`'s servers about instead of `name` (inside it), when `count` minimized
+// queries went out for it already: the ancestor one label below `zone`, or a few labels below
+// once we're past MINIMISE_ONE_LAB. None when that would be all of `name` anyway
+fn minimized(name: &Name, zone: &Name, count: usize) -> Option<Name> {
+    if count >= MAX_MINIMISE_COUNT || !name.is_subdomain_of(zone) {
+        return None;
+    }
+    let left = name.labels().len() - zone.labels().len();
+    let step = match count < MINIMISE_ONE_LAB {
+        true => 1,
+        false => left.div_ceil(MAX_MINIMISE_COUNT - count),
+    };
+    if step >= left {
+        return None;
+    }
+    let mut child = name.clone();
+    for _ in 0..left - step {
+        child = child.parent()?;
+    }
+    Some(child)
+}
+
+// where a final response sends us next: the end of its CNAME chain, if the chain leads off to
+// a name it has no records of the type we want for
+pub(crate) fn redirected(res: &DnsMessage, qtype: u16) -> Option<Name> {
+    let target = res.canonical_name();
+    if res.rcode() != 0
+        || target == res.question().qname
+        || matches!(RecordType::from(qtype), RecordType::CNAME | RecordType::Any)
+        || res
+            .answers
+            .iter()
+            .any(|rr| rr.name == target && rr.rr_type == qtype)
+    {
+        return None;
+    }
+    Some(target)
+}
+
+// iterative resolution from the IANA root servers with the default options, for when there's
+// just the one name to look up. The records for the name, CNAMEs included; empty when the name
+// exists but has nothing of type `qtype`, an Rcode error when it doesn't exist or the servers
+// had trouble with it
+pub fn resolve_iterative(name: &str, qtype: u16) -> Result<Vec<ResourceRecord>, DnsError> {
+    let res = IterativeResolver::new().resolve(name, qtype)?;
+    match res.rcode() {
+        0 => Ok(res.answers),
+        _ => Err(DnsError::from_response(&res)),
+    }
+}
+
+// what one server told us about a name
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Referral {
+    // the records it had for the name, CNAMEs included. Empty when the name exists but has
+    // nothing of the type we asked for (NODATA)
+    Answer(Vec<ResourceRecord>),
+    // not its zone: ask one of these, the servers for `zone`, paired with any glue addresses
+    // that came along for them
+    Delegation {
+        zone: Name,
+        nameservers: Nameservers,
+    },
+    // NXDOMAIN, the name doesn't exist at all
+    NameError,
+}
+
+// a single hop of iterative resolution: ask `server` about `name` (with RD off) and sort out
+// what kind of response came back. Errors for a server that didn't answer or answered with an
+// error like SERVFAIL or REFUSED
+pub fn resolve_step(
+    name: &str,
+    qtype: u16,
+    server: SocketAddr,
+    opts: &IterativeOptions,
+) -> Result<Referral, DnsError> {
+    let name = Name::from(name);
+    let query = DnsMessage::query(name.clone())
+        .qtype(qtype)
+        .recursion_desired(false)
+        .build();
+    let res = ask_server(server, &query, opts)?;
+    match res.rcode() {
+        0 | RCODE_NXDOMAIN => Ok(classify(&res, &name)),
+        _ => Err(DnsError::from_response(&res)),
+    }
+}
+
+fn ask_server(
+    server: SocketAddr,
+    query: &DnsMessage,
+    opts: &IterativeOptions,
+) -> Result<DnsMessage, DnsError> {
+    let mut resolver = Resolver::with_server(server);
+    resolver.set_timeout(opts.timeout);
+    resolver.query(query)
+}
+
+fn classify(res: &DnsMessage, name: &Name) -> Referral {
+    if res.rcode() == RCODE_NXDOMAIN {
+        return Referral::NameError;
+    }
+    match delegation(res, name) {
+        Some((zone, nameservers)) => Referral::Delegation { zone, nameservers },
+        None => Referral::Answer(res.answers.clone()),
+    }
+}
+
+// if `res` is a referral for `name`: the zone it hands us down to, and its nameservers with
+// whatever glue addresses the additional section had for them
+fn delegation(res: &DnsMessage, name: &Name) -> Option<(Name, Nameservers)> {
+    if res.rcode() != 0 || !res.answers.is_empty() || res.flags().aa {
+        return None;
+    }
+    let mut zone = None;
+    let mut nameservers = Vec::new();
+    for rr in &res.authority {
+        if let RData::NS(ns) = &rr.data {
+            if !name.is_subdomain_of(&rr.name) {
+                continue;
+            }
+            zone = Some(rr.name.clone());
+            let ns = Name::from(ns);
+            let glue = res
+                .additional
+                .iter()
+                .filter(|glue| glue.name == ns)
+                .filter_map(|glue| match glue.data {
+                    RData::A(ip) => Some(IpAddr::V4(ip)),
+                    RData::AAAA(ip) => Some(IpAddr::V6(ip)),
+                    _ => None,
+                })
+                .collect();
+            nameservers.push((ns, glue));
+        }
+    }
+    Some((zone?, nameservers))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{free_loopback_port, mock_udp_server, mock_udp_server_replies_at};
+    use std::sync::atomic::AtomicUsize;
+    use std::sync::Arc;
+
+    // without QNAME minimization: the mock roots answer whatever they're asked themselves,
+    // there's nothing below them to minimize for
+    fn options(port: u16) -> IterativeOptions {
+        IterativeOptions {
+            timeout: Duration::from_millis(300),
+            port,
+            qname_minimization: false,
+        }
+    }
+
+    type Asked = Arc<std::sync::Mutex<Vec<(String, u16)>>>;
+
+    // a server answering `count` queries with whatever `respond` makes of them, keeping track of
+    // the names and types it was asked
+    fn recording_server(
+        addr: SocketAddr,
+        count: usize,
+        respond: fn(&DnsMessage) -> DnsMessage,
+    ) -> Asked {
+        let asked = Asked::default();
+        let log = asked.clone();
+        mock_udp_server_replies_at(addr, count, move |query| {
+            let query = DnsMessage::from_bytes(query).unwrap();
+            let question = query.question();
+            log.lock()
+                .unwrap()
+                .push((question.qname.to_string(), question.qtype));
+            vec![respond(&query).to_bytes().unwrap()]
+        });
+        asked
+    }
+
+    fn referral(query: &DnsMessage, zone: &str, ns: &str, glue: [u8; 4]) -> DnsMessage {
+        let mut res = DnsMessage::response_to(query);
+        res.add_authority(ResourceRecord::new(zone, 60, RData::NS(ns.to_string())));
+        res.add_additional(ResourceRecord::new(ns, 60, RData::A(glue.into())));
+        res
+    }
+
+    // an authoritative answer for whatever is asked, counting the queries
+    fn answering_root(addr: SocketAddr, count: usize) -> Arc<AtomicUsize> {
+        let seen = Arc::new(AtomicUsize::new(0));
+        let counter = seen.clone();
+        mock_udp_server_replies_at(addr, count, move |query| {
+            counter.fetch_add(1, Ordering::SeqCst);
+            let query = DnsMessage::from_bytes(query).unwrap();
+            assert!(!query.recursion_desired());
+            let mut res = DnsMessage::response_to(&query);
+            let mut flags = res.flags();
+            flags.aa = true;
+            res.set_flags(flags);
+            res.add_answer(ResourceRecord::new(
+                query.question().qname.clone(),
+                60,
+                RData::A([10, 0, 0, 1].into()),
+            ));
+            vec![res.to_bytes().unwrap()]
+        });
+        seen
+    }
+
+    #[test]
+    fn test_custom_root_hints_are_where_resolution_starts() {
+        let port = free_loopback_port();
+        let root = SocketAddr::from(([127, 0, 0, 1], port));
+        let seen = answering_root(root, 1);
+
+        let mut hints = RootHints::empty();
+        hints.add("root.lab", root);
+        let mut resolver = IterativeResolver::with_root_hints(hints);
+        resolver.set_options(options(port));
+
+        let res = resolver.resolve("host.lab", TYPE_A).unwrap();
+        assert_eq!(res.ipv4_addrs(), vec![Ipv4Addr::new(10, 0, 0, 1)]);
+        assert_eq!(seen.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn test_silent_root_falls_over_to_the_next() {
+        let port = free_loopback_port();
+        let silent = SocketAddr::from(([127, 0, 0, 2], port));
+        let live = SocketAddr::from(([127, 0, 0, 3], port));
+        let ignored = Arc::new(AtomicUsize::new(0));
+        let counter = ignored.clone();
+        mock_udp_server_replies_at(silent, 1, move |_| {
+            counter.fetch_add(1, Ordering::SeqCst);
+            vec![]
+        });
+        let answered = answering_root(live, 2);
+
+        let mut hints = RootHints::empty();
+        hints.add("silent.lab", silent);
+        hints.add("live.lab", live);
+        let mut resolver = IterativeResolver::with_root_hints(hints);
+        resolver.set_options(options(port));
+
+        // the first lookup starts at the silent root and has to move on, the second one starts
+        // at the live root straight away
+        for _ in 0..2 {
+            let res = resolver.resolve("host.lab", TYPE_A).unwrap();
+            assert_eq!(res.ipv4_addrs(), vec![Ipv4Addr::new(10, 0, 0, 1)]);
+        }
+        assert_eq!(ignored.load(Ordering::SeqCst), 1);
+        assert_eq!(answered.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn test_missing_glue_resolved_in_parallel() {
+        // example.com's nameservers: ns1 with glue, but nothing listens there; ns.slow.test and
+        // ns.fast.test without glue, the root answers for the fast one and never for the slow
+        // one; ns2.example.com without glue, which nobody can resolve before reaching the zone
+        let port = free_loopback_port();
+        let root = SocketAddr::from(([127, 0, 0, 1], port));
+        let auth = SocketAddr::from(([127, 0, 0, 5], port));
+        let asked = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let log = asked.clone();
+        mock_udp_server_replies_at(root, 3, move |query| {
+            let query = DnsMessage::from_bytes(query).unwrap();
+            let qname = query.question().qname.clone();
+            log.lock().unwrap().push(qname.to_string());
+            let mut res = DnsMessage::response_to(&query);
+            if qname == "ns.slow.test" {
+                return vec![];
+            } else if qname == "ns.fast.test" {
+                let mut flags = res.flags();
+                flags.aa = true;
+                res.set_flags(flags);
+                res.add_answer(ResourceRecord::new(
+                    qname,
+                    60,
+                    RData::A([127, 0, 0, 5].into()),
+                ));
+            } else {
+                for ns in [
+                    "ns1.example.com",
+                    "ns.slow.test",
+                    "ns.fast.test",
+                    "ns2.example.com",
+                ] {
+                    res.add_authority(ResourceRecord::new(
+                        "example.com",
+                        60,
+                        RData::NS(ns.to_string()),
+                    ));
+                }
+                res.add_additional(ResourceRecord::new(
+                    "ns1.example.com",
+                    60,
+                    RData::A([127, 0, 0, 4].into()),
+                ));
+            }
+            vec![res.to_bytes().unwrap()]
+        });
+        mock_udp_server_replies_at(auth, 1, |query| {
+            let query = DnsMessage::from_bytes(query).unwrap();
+            let mut res = DnsMessage::response_to(&query);
+            let mut flags = res.flags();
+            flags.aa = true;
+            res.set_flags(flags);
+            res.add_answer(ResourceRecord::new(
+                "www.example.com",
+                60,
+                RData::A([192, 0, 2, 80].into()),
+            ));
+            vec![res.to_bytes().unwrap()]
+        });
+
+        let mut hints = RootHints::empty();
+        hints.add("root.lab", root);
+        let mut resolver = IterativeResolver::with_root_hints(hints);
+        resolver.set_options(IterativeOptions {
+            timeout: Duration::from_secs(1),
+            port,
+            qname_minimization: false,
+        });
+
+        let started = std::time::Instant::now();
+        let res = resolver.resolve("www.example.com", TYPE_A).unwrap();
+        assert_eq!(res.ipv4_addrs(), vec![Ipv4Addr::new(192, 0, 2, 80)]);
+        // one timeout for the dead ns1, none for the slow one: one after the other it would
+        // have been at least two
+        assert!(started.elapsed() < Duration::from_millis(1900));
+        assert!(!asked
+            .lock()
+            .unwrap()
+            .iter()
+            .any(|name| name == "ns2.example.com"));
+    }
+
+    #[test]
+    fn test_qname_minimization() {
+        // root -> com -> example.com, where dept.example.com is just a name inside the zone
+        let port = free_loopback_port();
+        let root = SocketAddr::from(([127, 0, 0, 1], port));
+        let root_asked = recording_server(root, 1, |query| {
+            referral(query, "com", "a.gtld.test", [127, 0, 0, 6])
+        });
+        let tld_asked = recording_server(([127, 0, 0, 6], port).into(), 1, |query| {
+            referral(query, "example.com", "ns.example.com", [127, 0, 0, 7])
+        });
+        let auth_asked = recording_server(([127, 0, 0, 7], port).into(), 2, |query| {
+            let mut res = DnsMessage::response_to(query);
+            let mut flags = res.flags();
+            flags.aa = true;
+            res.set_flags(flags);
+            // NODATA for the NS query about dept.example.com
+            if query.question().qtype == TYPE_A {
+                res.add_answer(ResourceRecord::new(
+                    query.question().qname.clone(),
+                    60,
+                    RData::A([192, 0, 2, 80].into()),
+                ));
+            }
+            res
+        });
+
+        let mut hints = RootHints::empty();
+        hints.add("root.lab", root);
+        let mut resolver = IterativeResolver::with_root_hints(hints);
+        resolver.set_options(IterativeOptions {
+            qname_minimization: true,
+            ..options(port)
+        });
+
+        let res = resolver.resolve("www.dept.example.com", TYPE_A).unwrap();
+        assert_eq!(res.ipv4_addrs(), vec![Ipv4Addr::new(192, 0, 2, 80)]);
+        let asked = |log: &Asked| log.lock().unwrap().clone();
+        assert_eq!(asked(&root_asked), vec![("com".to_string(), TYPE_NS)]);
+        assert_eq!(
+            asked(&tld_asked),
+            vec![("example.com".to_string(), TYPE_NS)]
+        );
+        assert_eq!(
+            asked(&auth_asked),
+            vec![
+                ("dept.example.com".to_string(), TYPE_NS),
+                ("www.dept.example.com".to_string(), TYPE_A)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_long_names_are_minimized_in_bigger_steps() {
+        let name = Name::reverse("2001:db8::1".parse().unwrap());
+        let (mut zone, mut count) = (Name::root(), 0);
+        let mut steps = Vec::new();
+        while let Some(next) = minimized(&name, &zone, count) {
+            steps.push(next.labels().len() - zone.labels().len());
+            zone = next;
+            count += 1;
+        }
+        assert!(count <= MAX_MINIMISE_COUNT);
+        assert_eq!(steps[..MINIMISE_ONE_LAB], [1, 1, 1, 1]);
+        assert!(steps[MINIMISE_ONE_LAB..].iter().all(|step| *step > 1));
+
+        // a short name goes a label at a time, and never as itself
+        let name = Name::from("www.dept.example.com");
+        assert_eq!(
+            minimized(&name, &Name::from("com"), 0),
+            Some(Name::from("example.com"))
+        );
+        assert_eq!(minimized(&name, &Name::from("dept.example.com"), 2), None);
+    }
+
+    #[test]
+    fn test_known_delegations_skip_the_root() {
+        // the root only answers once, the second name has to go straight to example.com's server
+        let port = free_loopback_port();
+        let root = SocketAddr::from(([127, 0, 0, 1], port));
+        let root_asked = recording_server(root, 1, |query| {
+            referral(query, "example.com", "ns.example.com", [127, 0, 0, 9])
+        });
+        let auth = SocketAddr::from(([127, 0, 0, 9], port));
+        let auth_asked = answering_root(auth, 2);
+
+        let mut hints = RootHints::empty();
+        hints.add("root.lab", root);
+        let mut resolver = IterativeResolver::with_root_hints(hints);
+        resolver.set_options(options(port));
+
+        for name in ["www.example.com", "mail.example.com"] {
+            let res = resolver.resolve(name, TYPE_A).unwrap();
+            assert_eq!(res.ipv4_addrs(), vec![Ipv4Addr::new(10, 0, 0, 1)]);
+        }
+        assert_eq!(root_asked.lock().unwrap().len(), 1);
+        assert_eq!(auth_asked.load(Ordering::SeqCst), 2);
+        assert_eq!(resolver.known_zones(), 1);
+    }
+
+    #[test]
+    fn test_trace_shows_the_path() {
+        let port = free_loopback_port();
+        let silent = SocketAddr::from(([127, 0, 0, 2], port));
+        let root = SocketAddr::from(([127, 0, 0, 1], port));
+        let auth = SocketAddr::from(([127, 0, 0, 9], port));
+        mock_udp_server_replies_at(silent, 1, |_| vec![]);
+        recording_server(root, 1, |query| {
+            referral(query, "example.com", "ns.example.com", [127, 0, 0, 9])
+        });
+        answering_root(auth, 1);
+
+        let mut hints = RootHints::empty();
+        hints.add("silent.lab", silent);
+        hints.add("root.lab", root);
+        let mut resolver = IterativeResolver::with_root_hints(hints);
+        resolver.set_options(options(port));
+
+        let trace = resolver.trace("www.example.com", TYPE_A);
+        let res = trace.answer.unwrap();
+        assert_eq!(res.ipv4_addrs(), vec![Ipv4Addr::new(10, 0, 0, 1)]);
+        let steps: Vec<_> = trace
+            .steps
+            .iter()
+            .map(|step| (step.zone.to_string(), step.server))
+            .collect();
+        assert_eq!(
+            steps,
+            [
+                (".".to_string(), silent),
+                (".".to_string(), root),
+                ("example.com".to_string(), auth)
+            ]
+        );
+        assert!(trace.steps[0].outcome.is_err());
+        assert!(trace.steps[0].rtt >= Duration::from_millis(300));
+        assert_eq!(
+            trace.steps[1].outcome,
+            Ok(Referral::Delegation {
+                zone: Name::from("example.com"),
+                nameservers: vec![(Name::from("ns.example.com"), vec![auth.ip()])],
+            })
+        );
+        assert!(trace.steps[1]
+            .to_string()
+            .ends_with("www.example.com. A, referral to example.com. (ns.example.com. 127.0.0.9)"));
+        assert!(matches!(trace.steps[2].outcome, Ok(Referral::Answer(ref rrs)) if rrs.len() == 1));
+        assert!(trace.steps.iter().all(|step| !step.recursed));
+    }
+
+    #[test]
+    fn test_trace_flags_recursion() {
+        // a "root" that's really a recursive resolver: an answer, but not an authoritative one
+        let port = free_loopback_port();
+        let root = SocketAddr::from(([127, 0, 0, 1], port));
+        recording_server(root, 1, |query| {
+            let mut res = DnsMessage::response_to(query);
+            res.add_answer(ResourceRecord::new(
+                query.question().qname.clone(),
+                60,
+                RData::A([192, 0, 2, 1].into()),
+            ));
+            res
+        });
+        let mut hints = RootHints::empty();
+        hints.add("root.lab", root);
+        let mut resolver = IterativeResolver::with_root_hints(hints);
+        resolver.set_options(options(port));
+
+        let trace = resolver.trace("www.example.com", TYPE_A);
+        assert!(trace.answer.is_ok());
+        assert_eq!(trace.steps.len(), 1);
+        assert!(trace.steps[0].recursed);
+        assert!(trace.steps[0]
+            .to_string()
+            .ends_with("1 answers (recursed, although asked not to)"));
+    }
+
+    #[test]
+    fn test_cname_to_another_zone_is_followed() {
+        // the server for www.example.com only has the CNAME, cdn.example.net is somewhere else
+        // in the tree (here the same mock, asked a second time)
+        let port = free_loopback_port();
+        let root = SocketAddr::from(([127, 0, 0, 1], port));
+        mock_udp_server_replies_at(root, 2, |query| {
+            let query = DnsMessage::from_bytes(query).unwrap();
+            let qname = query.question().qname.clone();
+            let mut res = DnsMessage::response_to(&query);
+            let mut flags = res.flags();
+            flags.aa = true;
+            res.set_flags(flags);
+            let data = match qname.as_str() {
+                "www.example.com" => RData::CNAME("cdn.example.net".to_string()),
+                _ => RData::A([192, 0, 2, 7].into()),
+            };
+            res.add_answer(ResourceRecord::new(qname, 60, data));
+            vec![res.to_bytes().unwrap()]
+        });
+
+        let mut hints = RootHints::empty();
+        hints.add("root.lab", root);
+        let mut resolver = IterativeResolver::with_root_hints(hints);
+        resolver.set_options(options(port));
+
+        let res = resolver.resolve("www.example.com", TYPE_A).unwrap();
+        assert_eq!(res.question().qname, "www.example.com");
+        assert_eq!(res.answers.len(), 2);
+        assert_eq!(
+            res.answers[0].data,
+            RData::CNAME("cdn.example.net".to_string())
+        );
+        assert_eq!(res.ipv4_addrs(), vec![Ipv4Addr::new(192, 0, 2, 7)]);
+    }
+
+    #[test]
+    fn test_cname_loop_across_lookups_is_an_error() {
+        let port = free_loopback_port();
+        let root = SocketAddr::from(([127, 0, 0, 1], port));
+        mock_udp_server_replies_at(root, 2, |query| {
+            let query = DnsMessage::from_bytes(query).unwrap();
+            let qname = query.question().qname.clone();
+            let mut res = DnsMessage::response_to(&query);
+            let target = match qname.as_str() {
+                "a.test" => "b.test",
+                _ => "a.test",
+            };
+            res.add_answer(ResourceRecord::new(
+                qname,
+                60,
+                RData::CNAME(target.to_string()),
+            ));
+            vec![res.to_bytes().unwrap()]
+        });
+
+        let mut hints = RootHints::empty();
+        hints.add("root.lab", root);
+        let mut resolver = IterativeResolver::with_root_hints(hints);
+        resolver.set_options(options(port));
+        let err = resolver.resolve("a.test", TYPE_A).unwrap_err();
+        assert!(matches!(err, DnsError::Malformed(_)));
+    }
+
+    #[test]
+    fn test_resolve_step_answer() {
+        let server = mock_udp_server(1, |query| {
+            let query = DnsMessage::from_bytes(query).unwrap();
+            let mut res = DnsMessage::response_to(&query);
+            res.add_answer(ResourceRecord::new(
+                "www.example.com",
+                60,
+                RData::CNAME("example.com".to_string()),
+            ));
+            res.add_answer(ResourceRecord::new(
+                "example.com",
+                60,
+                RData::A([192, 0, 2, 1].into()),
+            ));
+            res.to_bytes().unwrap()
+        });
+        let step = resolve_step("www.example.com", TYPE_A, server, &options(53)).unwrap();
+        match step {
+            Referral::Answer(records) => {
+                assert_eq!(records.len(), 2);
+                assert_eq!(records[1].data, RData::A([192, 0, 2, 1].into()));
+            }
+            other => panic!("expected an answer, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_resolve_step_delegation_with_glue() {
+        // the .com servers referring us to example.com: two nameservers with glue (one of them
+        // dual-stack), one out of zone without any
+        let server = mock_udp_server(1, |query| {
+            let query = DnsMessage::from_bytes(query).unwrap();
+            let mut res = DnsMessage::response_to(&query);
+            for ns in ["ns1.example.com", "ns2.example.com", "ns.other.net"] {
+                res.add_authority(ResourceRecord::new(
+                    "example.com",
+                    172800,
+                    RData::NS(ns.to_string()),
+                ));
+            }
+            res.add_additional(ResourceRecord::new(
+                "ns1.example.com",
+                172800,
+                RData::A([192, 0, 2, 53].into()),
+            ));
+            res.add_additional(ResourceRecord::new(
+                "ns1.example.com",
+                172800,
+                RData::AAAA("2001:db8::53".parse().unwrap()),
+            ));
+            res.add_additional(ResourceRecord::new(
+                "NS2.example.com",
+                172800,
+                RData::A([198, 51, 100, 53].into()),
+            ));
+            res.to_bytes().unwrap()
+        });
+
+        let step = resolve_step("www.example.com", TYPE_A, server, &options(53)).unwrap();
+        let v4 = |a, b, c, d| IpAddr::V4(Ipv4Addr::new(a, b, c, d));
+        assert_eq!(
+            step,
+            Referral::Delegation {
+                zone: Name::from("example.com"),
+                nameservers: vec![
+                    (
+                        Name::from("ns1.example.com"),
+                        vec![v4(192, 0, 2, 53), "2001:db8::53".parse().unwrap()]
+                    ),
+                    (Name::from("ns2.example.com"), vec![v4(198, 51, 100, 53)]),
+                    (Name::from("ns.other.net"), vec![]),
+                ],
+            }
+        );
+    }
+
+    #[test]
+    fn test_resolve_step_name_error() {
+        let server = mock_udp_server(1, |query| {
+            let query = DnsMessage::from_bytes(query).unwrap();
+            let mut res = DnsMessage::response_to(&query);
+            res.header.flags |= RCODE_NXDOMAIN;
+            res.to_bytes().unwrap()
+        });
+        let step = resolve_step("nope.example.com", TYPE_A, server, &options(53)).unwrap();
+        assert_eq!(step, Referral::NameError);
+    }
+
+    #[test]
+    fn test_iana_hints() {
+        let hints = RootHints::default();
+        assert_eq!(hints.servers().len(), 26);
+        assert_eq!(hints.servers()[0].0, "a.root-servers.net");
+        assert!(hints.servers().iter().all(|(_, addr)| addr.port() == 53));
+    }
+}
@@ -1,370 +1,248 @@
+// with the default `std` feature off, the crate is just the wire format (message types,
+// to_bytes/from_bytes) on top of alloc. Everything that needs sockets lives behind `std`
+#![cfg_attr(not(feature = "std"), no_std)]
+
+extern crate alloc;
+
+#[cfg(feature = "std")]
+mod address_order;
+#[cfg(feature = "dnssec")]
+mod anchors;
+#[cfg(feature = "tokio")]
+mod async_resolver;
+#[cfg(feature = "std")]
+mod authority;
+#[cfg(feature = "std")]
+mod axfr;
+#[cfg(feature = "std")]
+mod blocklist;
+#[cfg(feature = "std")]
+mod cache;
+#[cfg(feature = "std")]
+mod cookie;
+mod dig;
+#[cfg(feature = "std")]
+mod dnssd;
+#[cfg(feature = "dnssec")]
+mod dnssec;
+#[cfg(feature = "doh")]
+mod doh;
+mod edns;
+mod encoding;
+mod error;
+mod flags;
+mod hmac;
+#[cfg(feature = "std")]
+mod hosts;
+#[cfg(feature = "std")]
+mod iterative;
+#[cfg(feature = "std")]
+mod llmnr;
+#[cfg(feature = "std")]
+mod local;
+#[cfg(feature = "std")]
+mod mdns;
+mod message;
+mod message_ref;
+#[cfg(feature = "std")]
+mod mux;
+#[cfg(feature = "std")]
+mod mx;
+mod name;
+#[cfg(feature = "std")]
+mod options;
+#[cfg(feature = "std")]
+mod pcap;
+#[cfg(feature = "std")]
+mod pool;
+#[cfg(feature = "std")]
+mod ports;
+mod presentation;
+#[cfg(feature = "std")]
+mod random;
+mod rdata;
+#[cfg(feature = "std")]
+mod resolver;
+#[cfg(feature = "std")]
+mod retry;
+#[cfg(feature = "serde")]
+mod rfc8427;
+#[cfg(feature = "std")]
+mod rtt;
+#[cfg(feature = "std")]
+mod secondary;
+#[cfg(feature = "std")]
+mod server;
+#[cfg(feature = "std")]
+mod srv;
+#[cfg(feature = "std")]
+mod stats;
+#[cfg(feature = "std")]
+mod system;
+#[cfg(feature = "std")]
+mod tcp;
+#[cfg(all(test, feature = "std"))]
+mod test_util;
+#[cfg(feature = "tls")]
+mod tls;
+#[cfg(feature = "std")]
+mod transport;
+mod tsig;
+#[cfg(feature = "std")]
+mod txt;
+mod types;
+#[cfg(feature = "std")]
+mod update;
+#[cfg(feature = "std")]
+mod zone;
+
+#[cfg(feature = "std")]
+pub use address_order::sort_addrs;
+#[cfg(feature = "dnssec")]
+pub use anchors::{AnchorStore, KeyState, TrackedKey, HOLD_DOWN};
+#[cfg(feature = "tokio")]
+pub use async_resolver::AsyncResolver;
+#[cfg(feature = "std")]
+pub use authority::Zone;
+#[cfg(feature = "std")]
+pub use axfr::{axfr, ixfr, transfer_zone, update_zone, TransferOptions, ZoneDelta, ZoneTransfer};
+#[cfg(feature = "std")]
+pub use blocklist::{BlockAction, Blocklist, BLOCKED_TTL};
+#[cfg(feature = "std")]
+pub use cache::{DnsCache, DEFAULT_CACHE_ENTRIES};
+#[cfg(feature = "std")]
+pub use cookie::EDNS_COOKIE;
+#[cfg(feature = "std")]
+pub use dnssd::ServiceInstance;
+#[cfg(feature = "dnssec")]
+pub use dnssec::{nsec3_hash, verify, TrustAnchor};
+#[cfg(feature = "doh")]
+pub use doh::DohTransport;
+pub use edns::{
+    ClientSubnet, Edns, EdnsOption, ExtendedError, EDNS_CLIENT_SUBNET, EDNS_EXTENDED_ERROR,
+    EDNS_TCP_KEEPALIVE,
+};
+pub use error::DnsError;
+pub use flags::DnsFlags;
+#[cfg(feature = "std")]
+pub use hosts::{HostsFile, HostsTable, HOSTS_FILE};
+#[cfg(feature = "std")]
+pub use iterative::{
+    resolve_iterative, resolve_step, IterativeOptions, IterativeResolver, Referral,
+    ResolutionTrace, RootHints, TraceStep,
+};
+#[cfg(feature = "std")]
+pub use llmnr::send_llmnr;
+#[cfg(feature = "std")]
+pub use local::{LocalRecords, LOCAL_TTL};
+#[cfg(feature = "std")]
+pub use mdns::{is_mdns_name, send_mdns, MDNS_GROUP_V4, MDNS_GROUP_V6, MDNS_PORT};
+pub use message::{
+    DnsHeader, DnsMessage, DnsQuestion, QueryBuilder, RecordOffsets, ResourceRecord, RCODE_FORMERR,
+    RCODE_NXDOMAIN,
+};
+pub use message_ref::{DnsMessageRef, Labels, NameRef, QuestionRef, Questions, RecordRef, Records};
+#[cfg(feature = "std")]
+pub use mux::{UdpMux, DEFAULT_MUX_SOCKETS};
+#[cfg(feature = "std")]
+pub use mx::MailExchanger;
+pub use name::{dname_substitute, names_equal, unescape_name, Name};
+#[cfg(feature = "std")]
+pub use options::QueryOptions;
+#[cfg(feature = "std")]
+pub use pcap::PcapWriter;
+#[cfg(feature = "std")]
+pub use pool::{ConnectionPool, DEFAULT_IDLE_TIMEOUT};
+#[cfg(feature = "std")]
+pub use ports::SourcePorts;
+pub use rdata::{
+    Caa, Dnskey, Ds, Nsec, Nsec3, RData, Rrsig, Soa, SrvRecord, SvcParam, SvcParams, Svcb, Tlsa,
+    Tsig, SVC_ALPN, SVC_ECH, SVC_IPV4HINT, SVC_IPV6HINT, SVC_MANDATORY, SVC_NO_DEFAULT_ALPN,
+    SVC_PORT,
+};
+#[cfg(feature = "std")]
+pub use resolver::{Lookup, Resolved, Resolver, Security, Strategy};
+#[cfg(feature = "std")]
+pub use retry::RetryPolicy;
+#[cfg(feature = "std")]
+pub use rtt::{ServerHealth, DEMOTE_AFTER};
+#[cfg(feature = "std")]
+pub use secondary::SecondaryZone;
+#[cfg(feature = "std")]
+pub use server::{DnsServer, DEFAULT_LISTEN, DEFAULT_UDP_HANDLERS};
+#[cfg(feature = "std")]
+pub use srv::resolve_srv;
+#[cfg(feature = "std")]
+pub use stats::Stats;
+#[cfg(feature = "std")]
+pub use system::{SystemConfig, RESOLV_CONF};
+#[cfg(feature = "tokio")]
+pub use tcp::DnsCodec;
+#[cfg(feature = "std")]
+pub use tcp::DnsFramed;
+#[cfg(feature = "tls")]
+pub use tls::TlsTransport;
+#[cfg(feature = "std")]
+pub use transport::{DnsTransport, TcpTransport, UdpTransport};
+pub use tsig::{TsigError, TsigKey, DEFAULT_FUDGE};
+#[cfg(feature = "std")]
+pub use txt::Txt;
+pub use types::{DnsClass, Opcode, QType, RecordType, ResponseCode, UnknownMnemonic};
+#[cfg(feature = "std")]
+pub use update::UpdateBuilder;
+#[cfg(feature = "std")]
+pub use zone::{load_zone, parse_zone, ZoneError};
+
+#[cfg(feature = "std")]
 use std::io;
-use std::net::UdpSocket;
+#[cfg(feature = "std")]
+use std::net::SocketAddr;
+#[cfg(feature = "std")]
+use std::sync::OnceLock;
+#[cfg(feature = "std")]
 use std::time::Duration;
-#[derive(Debug)]
-pub struct DnsHeader {
-    // header section - 12 bytes
-    pub identification: u16,
-    pub flags: u16, // for now we are planning on hardcoding it because we are just using recursion
-    pub no_of_questions: u16,
-    pub no_of_answers_rr: u16,
-    pub no_of_authority_rr: u16,
-    pub no_of_additional_rr: u16,
-}
-#[derive(Debug)]
-pub struct DnsQuestion {
-    //Name and type feilds for a query
-    pub qname: String, // example.com
-    pub qtype: u16,    // A = 1
-    pub qclass: u16,   // IN = 1
-}
-#[derive(Debug)]
-pub struct ResourceRecord {
-    pub name: String,
-    pub rr_type: u16, // A = 1, NS = 2, etc.
-    pub class: u16,   // Usually IN (1)
-    pub ttl: u32,
-    pub rdlength: u16,
-    pub rdata: Vec<u8>, // Parsed separately depending on type
-}
-#[derive(Debug)]
-pub struct DnsMessage {
-    pub header: DnsHeader,
-    pub question: DnsQuestion,
-    pub answers: Vec<ResourceRecord>,    // RRs in response to query
-    pub authority: Vec<ResourceRecord>,  //Records for authoritative servers
-    pub additional: Vec<ResourceRecord>, //Additional helpful info
-}
-
-impl DnsMessage {
-    pub fn new(url: String) -> Self {
-        let header = DnsHeader {
-            identification: 0x1234, // random ID hardcoded for now
-            flags: 0x0100,          // we will send the recursion request
-            no_of_questions: 1,
-            no_of_answers_rr: 0,
-            no_of_authority_rr: 0,
-            no_of_additional_rr: 0,
-        };
-
-        let question = DnsQuestion {
-            qname: url,
-            qtype: 1, // A record  we are hardcoding it 1-Ipv4 , 2-NS ,5- CName,15-MX, 28-Ipv6
-            qclass: 1, // IN (Internet)
-        };
-
-        DnsMessage {
-            header,
-            question,
-            // the next section we will get a response back
-            answers: Vec::new(),
-            authority: Vec::new(),
-            additional: Vec::new(),
-        }
-    }
-
-    pub fn to_bytes(&self) -> Vec<u8> {
-        let mut bytes = Vec::new();
-
-        // HEADER SECTION
-        bytes.extend(&self.header.identification.to_be_bytes()); // 2 bytes
-        bytes.extend(&self.header.flags.to_be_bytes()); // 2 bytes
-        bytes.extend(&self.header.no_of_questions.to_be_bytes()); // 2 bytes
-        bytes.extend(&self.header.no_of_answers_rr.to_be_bytes()); // 2 bytes
-        bytes.extend(&self.header.no_of_authority_rr.to_be_bytes()); // 2 bytes
-        bytes.extend(&self.header.no_of_additional_rr.to_be_bytes()); // 2 bytes
-
-        // QUESTION SECTION
-        // QNAME — example.com becomes [7]example[3]com[0]
-        for label in self.question.qname.split('.') {
-            bytes.push(label.len() as u8); // length byte
-            bytes.extend(label.as_bytes()); // label bytes
-        }
-        bytes.push(0); // end of QNAME
-
-        // QTYPE (2 bytes)
-        bytes.extend(&self.question.qtype.to_be_bytes());
-
-        // QCLASS (2 bytes)
-        bytes.extend(&self.question.qclass.to_be_bytes());
-
-        bytes
-    }
-
-    pub fn from_bytes(buf: &[u8]) -> Self {
-        // Now we know that the header section is of 12 bytes from the start
-        // 0-11 now we get the data for the next bytes from this like how many questions[qname,qtype,qclass], [RR]answers, authority , additional info
-
-        // Parse header (first 12 bytes)
-        let header = DnsHeader {
-            identification: u16::from_be_bytes([buf[0], buf[1]]),
-            flags: u16::from_be_bytes([buf[2], buf[3]]),
-            no_of_questions: u16::from_be_bytes([buf[4], buf[5]]),
-            no_of_answers_rr: u16::from_be_bytes([buf[6], buf[7]]),
-            no_of_authority_rr: u16::from_be_bytes([buf[8], buf[9]]),
-            no_of_additional_rr: u16::from_be_bytes([buf[10], buf[11]]),
-        };
-
-        // Questions = no of questions x [qname,qtype,qclass]
-        // now qtype and q class are of fixed size 2 bytes
-        // and qname ends with a zero-length byte (0) 7example3com0 so that is how we will parse Questions
-
-        let mut pos = 12; // after header
-        let mut questions = Vec::new();
-
-        for _ in 0..header.no_of_questions {
-            let (qname, next_pos) = parse_qname(buf, pos);
-            pos = next_pos;
-            let qtype = u16::from_be_bytes([buf[pos], buf[pos + 1]]);
-            pos += 2;
-            let qclass = u16::from_be_bytes([buf[pos], buf[pos + 1]]);
-            pos += 2;
-            questions.push(DnsQuestion {
-                qname,
-                qtype,
-                qclass,
-            });
-        }
-
-        // Answers, Authority, Additional - Are all resource records x no.of items(from header)
-        // type=2 class=2 TTL=4 rd_length=2 and rd_data encompasses rd length
-        // the name hah! is saved often using pointer compression. And what is pointer compression you ask?
-
-        fn parse_rr(buf: &[u8], mut pos: usize) -> (ResourceRecord, usize) {
-            let (name, new_pos) = parse_qname(buf, pos);
-            pos = new_pos;
-
-            let rr_type = u16::from_be_bytes([buf[pos], buf[pos + 1]]);
-            pos += 2;
-
-            let class = u16::from_be_bytes([buf[pos], buf[pos + 1]]);
-            pos += 2;
-
-            let ttl = u32::from_be_bytes([buf[pos], buf[pos + 1], buf[pos + 2], buf[pos + 3]]);
-            pos += 4;
-
-            let rdlength = u16::from_be_bytes([buf[pos], buf[pos + 1]]);
-            pos += 2;
-
-            let rdata = buf[pos..pos + rdlength as usize].to_vec();
-            pos += rdlength as usize;
-
-            (
-                ResourceRecord {
-                    name,
-                    rr_type,
-                    class,
-                    ttl,
-                    rdlength,
-                    rdata,
-                },
-                pos,
-            )
-        }
-
-        let mut answers = Vec::new();
-        for _ in 0..header.no_of_answers_rr {
-            let (rr, new_pos) = parse_rr(buf, pos);
-            pos = new_pos;
-            answers.push(rr);
-        }
-
-        let mut authority = Vec::new();
-        for _ in 0..header.no_of_authority_rr {
-            let (rr, new_pos) = parse_rr(buf, pos);
-            pos = new_pos;
-            authority.push(rr);
-        }
-
-        let mut additional = Vec::new();
-        for _ in 0..header.no_of_additional_rr {
-            let (rr, new_pos) = parse_rr(buf, pos);
-            pos = new_pos;
-            additional.push(rr);
-        }
-
-        DnsMessage {
-            header,
-            question: questions.into_iter().next().unwrap_or(DnsQuestion {
-                qname: "".to_string(),
-                qtype: 0,
-                qclass: 0,
-            }),
-            answers,
-            authority,
-            additional,
-        }
-    }
-}
-
-// okay this is made to handle name parsing I. Qusetion we just see if byte is 00 for eg: 03 'w' 'w' 'w' 07 'e' 'x' 'a' 'm' 'p' 'l' 'e' 03 'c' 'o' 'm' 00
-// II. okay so pointer compression is just that we don't waste bytes we just add the pointer the names where it has appeared before in the buffer
-// The first two bits of a length byte set to 11 (binary) or 0xC0 (hex) indicate a pointer
-// The next 14 bits represent the offset in the message where the rest of the domain name can be found.
-//         Example:
-// Suppose somewhere in the DNS message, at position 20, we already had:
-
-// 07 'e' 'x' 'a' 'm' 'p' 'l' 'e' 03 'c' 'o' 'm' 00
-// Later, instead of repeating "example.com", the message can use a pointer like:
-
-// C0 14
-// C0 = 11000000 binary → pointer marker
-// 14 (hex) = 20 decimal → offset to position 20 where "example.com" starts
-fn parse_qname(buf: &[u8], mut pos: usize) -> (String, usize) {
-    let mut labels = Vec::new();
-    let mut jumped = false;
-    let mut original_pos = 0;
-
-    loop {
-        let byte = buf[pos];
-
-        // Checking if the first two bits are 1 1 (pointer)
-        if byte & 0b11000000 == 0b11000000 {
-            let second_byte = buf[pos + 1];
-            // this part is fucking hell
-
-            // “Just stick the two bytes together — that’s the pointer, right?”
-            // But what we really need is:
-
-            // “Use the last 6 bits of the first byte and all 8 bits of the second byte to build a 14-bit number.
-
-            // lets take another example: a very simple and plain analogy:
-            // If you have two digits: 4 and 2, and you want to make 42, you multiply the first by 10 and add the second.
-
-            // In binary:
-            // If you have two bytes: 0x01 and 0x0C, and want to make 0x010C, you shift the first by 8 and add the second.
-
-            // now we extract the pointer
-            // We Remove the two high bits 11000000 because they just show the that the next 14 bits is a pointer
-            let upper_pointer_bits = byte ^ 0b11000000;
-
-            //  shift left by 8 bits - well the first 6 bits of the pointer contribution
-            // keep in mind that the pointer is still 2 bytes that is why we cast it left by 8 bits
-            let upper_offset = (upper_pointer_bits as u16) << 8;
-
-            let lower_offset = second_byte as u16;
-
-            // We Add(OR) the two parts into the full 14-bit offset which is actually u16
-            let pointer_offset = upper_offset | lower_offset;
-
-            // Save current position only the first time we jump
-            if !jumped {
-                original_pos = pos + 2; // like from where do we continue after this
-            }
-
-            pos = pointer_offset as usize;
-            jumped = true;
-            continue;
-        }
-
-        // If byte is 0, end of the QNAME hex(00)
-        if byte == 0 {
-            pos += 1;
-            break;
-        }
-
-        pos += 1;
-
-        let label_length = byte as usize;
-
-        let end = pos + label_length;
-
-        let label = &buf[pos..end];
-
-        labels.push(String::from_utf8_lossy(label).to_string());
-        pos += byte as usize;
-    }
-
-    let qname = labels.join(".");
-
-    // Return the position we stopped at
-    if jumped {
-        (qname, original_pos)
-    } else {
-        (qname, pos)
-    }
-}
 
+#[cfg(feature = "std")]
 pub fn input_url() -> DnsMessage {
     let mut input = String::new();
     println!("Input the domain name you want to resolve: ");
     io::stdin().read_line(&mut input).unwrap();
     let url = input.trim();
-    let msg = DnsMessage::new(url.to_owned());
-    return msg;
+    DnsMessage::new(url.to_owned())
 }
 
-pub fn send_message(msg: DnsMessage) -> DnsMessage {
+#[cfg(feature = "std")]
+pub fn send_message(msg: DnsMessage) -> Result<DnsMessage, DnsError> {
     // 1. creating a DNS message and then turning it into bytes and then send it to the 8.8.8.8 for now we are not handling the complexities ourself
-    let server = "8.8.8.8:53"; // Google DNS
-    let socket = UdpSocket::bind("0.0.0.0:0").expect("could not bind to address");
-
-    // Optional: set a timeout
-    socket
-        .set_read_timeout(Some(Duration::from_secs(5)))
-        .unwrap();
-
-    let message_bytes = msg.to_bytes();
-
-    // Send to DNS server
-    socket
-        .send_to(&message_bytes, server)
-        .expect("failed to send DNS query");
-
-    // Receive response
-    let mut buf = [0u8; 512]; // Max size for a DNS response is 512 bytes
-    let (size, _) = socket
-        .recv_from(&mut buf)
-        .expect("did not receive a response");
-
-    // okay so now we have our bytes with us from in the buf so we try to parse it into the message again
-    let res = DnsMessage::from_bytes(&buf[..size]);
-    res
-}
-
-#[cfg(test)]
-mod tests {
-    use super::*;
-
-    #[test]
-    fn test_round_trip_serialization() {
-        let msg = DnsMessage::new("example.com".to_string());
-        let bytes = msg.to_bytes();
-        let parsed_msg = DnsMessage::from_bytes(&bytes);
-
-        assert_eq!(msg.header.identification, parsed_msg.header.identification);
-        assert_eq!(msg.header.flags, parsed_msg.header.flags);
-        assert_eq!(msg.question.qname, parsed_msg.question.qname);
-        assert_eq!(msg.question.qtype, parsed_msg.question.qtype);
-        assert_eq!(msg.question.qclass, parsed_msg.question.qclass);
-    }
-
-    #[test]
-    fn test_parse_qname_basic() {
-        // example.com encoded as [7]example[3]com[0]
-        let buf = [
-            7u8, b'e', b'x', b'a', b'm', b'p', b'l', b'e', 3, b'c', b'o', b'm', 0,
-        ];
-        let (qname, pos) = parse_qname(&buf, 0);
-        assert_eq!(qname, "example.com");
-        assert_eq!(pos, buf.len());
-    }
-
-    #[test]
-    fn test_parse_qname_with_pointer() {
-        // Buffer layout:
-        // 0..12: 7 'e' 'x' 'a' 'm' 'p' 'l' 'e' 3 'c' 'o' 'm' 0   (example.com)
-        // 12..16: some filler bytes (x, a, c, ... )
-        // 16: pointer 0xC000 (11000000 00000000) pointing to offset 0, i.e. "example.com"
-        // After pointer comes some bytes representing "reachhere" (just filler)
-        let buf = [
-            7u8, b'e', b'x', b'a', b'm', b'p', b'l', b'e', 3, b'c', b'o', b'm', 0, b'x', b'a',
-            b'c', 0xC0, 0x00, // pointer to offset 0 ("example.com")
-            b'r', b'e', b'a', b'c', b'h', b'h', b'e', b'r', b'e',
-        ];
-
-        let (qname, pos) = parse_qname(&buf, 16);
-        assert_eq!(qname, "example.com");
-        assert_eq!(pos, 18); // pointer consumes 2 bytes
+    // every call shares the sockets of one UdpMux, so any number of threads can have a query out
+    // at once without binding a socket each. Three quick tries rather than one long one, so a
+    // single lost packet doesn't cost us the answer, and TCP for an answer too big for UDP
+    static MUX: OnceLock<UdpMux> = OnceLock::new();
+    let mux = MUX.get_or_init(|| {
+        let mut mux = UdpMux::new();
+        mux.set_timeout(Duration::from_secs(2));
+        mux
+    });
+    let retry = RetryPolicy {
+        attempts: 3,
+        timeout: None,
+        backoff: Duration::from_millis(250),
+        max_backoff: Duration::from_secs(1),
+        jitter: true,
+        switch_server: false,
+        deadline: None,
+    };
+    let server: SocketAddr = resolver::DEFAULT_SERVER.parse().unwrap();
+    let mut last = Err(DnsError::Timeout);
+    for try_no in 0..retry.attempts {
+        if try_no > 0 {
+            std::thread::sleep(retry.delay(try_no));
+        }
+        match mux.exchange(server, &msg) {
+            Ok(res) if res.truncated() => return TcpTransport::new(server).exchange(&msg),
+            Ok(res) => return Ok(res),
+            Err(e) => last = Err(e),
+        }
     }
+    last
 }
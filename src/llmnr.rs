@@ -0,0 +1,123 @@
+// LLMNR (RFC 4795): how Windows machines resolve each other's single-label names on a LAN with
+// no DNS server involved. It's ordinary DNS on the wire, just multicast to everyone on the link
+// and answered by whichever host owns the name
+use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4, UdpSocket};
+use std::time::{Duration, Instant};
+
+use crate::{DnsError, DnsMessage};
+
+pub const LLMNR_GROUP: Ipv4Addr = Ipv4Addr::new(224, 0, 0, 252);
+pub const LLMNR_PORT: u16 = 5355;
+
+// LLMNR_TIMEOUT from the RFC: how long we keep listening for answers after sending the query
+const LLMNR_WINDOW: Duration = Duration::from_secs(1);
+
+// asks everyone on the local link for `name` and returns every response that came back within
+// the window. More than one host can answer (that's a name conflict, but it happens), and an
+// empty Vec just means nobody claimed the name
+pub fn send_llmnr(name: &str) -> Result<Vec<DnsMessage>, DnsError> {
+    let socket = llmnr_socket()?;
+    let group = SocketAddr::V4(SocketAddrV4::new(LLMNR_GROUP, LLMNR_PORT));
+    collect_responses(&socket, &llmnr_query(name), group, LLMNR_WINDOW)
+}
+
+// a standard query, but all of the flags stay clear: LLMNR has no recursion (there's nobody to
+// recurse to) and the bits RD/RA occupy mean something else here (C and T)
+fn llmnr_query(name: &str) -> DnsMessage {
+    DnsMessage::query(name).recursion_desired(false).build()
+}
+
+fn llmnr_socket() -> Result<UdpSocket, DnsError> {
+    let socket = UdpSocket::bind("0.0.0.0:0")?;
+    // link-local only: the query must never get routed off the local network
+    socket.set_multicast_ttl_v4(1)?;
+    socket.set_multicast_loop_v4(true)?;
+    Ok(socket)
+}
+
+fn collect_responses(
+    socket: &UdpSocket,
+    query: &DnsMessage,
+    dest: SocketAddr,
+    window: Duration,
+) -> Result<Vec<DnsMessage>, DnsError> {
+    socket.send_to(&query.to_bytes()?, dest)?;
+
+    // responders answer with unicast from their own address, so we can't filter on the source,
+    // only on the ID and the QR bit
+    let mut responses = Vec::new();
+    let mut buf = [0u8; 4096];
+    let deadline = Instant::now() + window;
+    loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            break;
+        }
+        socket.set_read_timeout(Some(remaining))?;
+        let size = match socket.recv_from(&mut buf) {
+            Ok((size, _)) => size,
+            Err(e)
+                if matches!(
+                    e.kind(),
+                    std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut
+                ) =>
+            {
+                break
+            }
+            Err(e) => return Err(e.into()),
+        };
+        if size < 12 || buf[0..2] != query.header.identification.to_be_bytes() {
+            continue;
+        }
+        // anyone on the link can send us junk, that shouldn't cost us the real answers
+        match DnsMessage::from_bytes(&buf[..size]) {
+            Ok(res) if res.is_response() => responses.push(res),
+            _ => {}
+        }
+    }
+    Ok(responses)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{mock_udp_server_replies, response_with_rdata};
+
+    #[test]
+    fn test_llmnr_multicast_setup() {
+        assert!(LLMNR_GROUP.is_multicast());
+        let socket = llmnr_socket().unwrap();
+        assert_eq!(socket.multicast_ttl_v4().unwrap(), 1);
+    }
+
+    #[test]
+    fn test_llmnr_query_packet() {
+        let bytes = llmnr_query("printer").to_bytes().unwrap();
+
+        // no flags at all, a single question and nothing else
+        assert_eq!(&bytes[2..4], &[0, 0]);
+        assert_eq!(&bytes[4..12], &[0, 1, 0, 0, 0, 0, 0, 0]);
+        // single-label name: [7]printer[0], then type A, class IN
+        assert_eq!(&bytes[12..21], b"\x07printer\x00");
+        assert_eq!(&bytes[21..], &[0, 1, 0, 1]);
+    }
+
+    #[test]
+    fn test_llmnr_collects_every_response() {
+        // two hosts both claim the name, plus a packet that isn't a response at all
+        let responder = mock_udp_server_replies(1, |query| {
+            let a = response_with_rdata(query, 1, &[192, 168, 1, 10]);
+            let b = response_with_rdata(query, 1, &[192, 168, 1, 11]);
+            vec![a, query.to_vec(), b]
+        });
+
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let query = llmnr_query("printer");
+        let responses =
+            collect_responses(&socket, &query, responder, Duration::from_millis(300)).unwrap();
+
+        assert_eq!(responses.len(), 2);
+        assert_eq!(responses[0].answers[0].rdata, vec![192, 168, 1, 10]);
+        assert_eq!(responses[1].answers[0].rdata, vec![192, 168, 1, 11]);
+    }
+}
@@ -0,0 +1,231 @@
+// records we answer for ourselves, from memory, before the cache or any upstream: the printer on
+// the LAN, a name pointed at a test box, everything under .test going to localhost. Like the
+// hosts file, but any type of record, wildcards (RFC 4592, "*.test" stands in for every name
+// below test that has nothing of its own) and whole zones.
+//
+// A name with records is ours; so is every name inside a zone added with add_zone, and one of
+// those with nothing at all is NXDOMAIN rather than a question for the upstream. Everything
+// else isn't ours and goes out as usual. A CNAME answers for every type, the resolver then goes
+// after where it points like it would with an upstream's
+use std::collections::{HashMap, HashSet};
+use std::net::IpAddr;
+
+use crate::rdata::{TYPE_CNAME, TYPE_SOA};
+use crate::{DnsMessage, Name, RData, RecordType, ResourceRecord, RCODE_NXDOMAIN};
+
+// the TTL add_addr gives its records: short, so a change shows up soon for whoever we hand
+// them to
+pub const LOCAL_TTL: u32 = 60;
+
+#[derive(Debug, Clone, Default)]
+pub struct LocalRecords {
+    records: HashMap<Name, Vec<ResourceRecord>>,
+    zones: HashSet<Name>,
+}
+
+impl LocalRecords {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // one more record. An owner of "*.something" is a wildcard
+    pub fn add(&mut self, rr: ResourceRecord) {
+        self.records.entry(rr.name.clone()).or_default().push(rr);
+    }
+
+    // an A or AAAA record for `name`, with LOCAL_TTL
+    pub fn add_addr(&mut self, name: impl Into<Name>, addr: IpAddr) {
+        let data = match addr {
+            IpAddr::V4(addr) => RData::A(addr),
+            IpAddr::V6(addr) => RData::AAAA(addr),
+        };
+        self.add(ResourceRecord::new(name, LOCAL_TTL, data));
+    }
+
+    // every name at or below `zone` is ours, and the ones without records don't exist. An SOA
+    // added at the zone's apex goes out with the negative answers, so they can be cached
+    pub fn add_zone(&mut self, zone: impl Into<Name>) {
+        self.zones.insert(zone.into());
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.records.is_empty() && self.zones.is_empty()
+    }
+
+    // the authoritative answer to `query`, or None when the name isn't ours
+    pub fn answer(&self, query: &DnsMessage) -> Option<DnsMessage> {
+        let qname = &query.question().qname;
+        let qtype = query.question().qtype;
+        let found = match self.records.get(qname) {
+            Some(records) => Some(records.clone()),
+            None => self.wildcard(qname),
+        };
+        let zone = self.zone_of(qname);
+        if found.is_none() && zone.is_none() {
+            return None;
+        }
+
+        let mut res = DnsMessage::response_to(query);
+        let mut flags = res.flags();
+        flags.aa = true;
+        flags.ra = true;
+        // a name with nothing of its own but names below it (an empty non-terminal) exists all
+        // the same
+        let records = found.unwrap_or_default();
+        let below = || {
+            self.records
+                .keys()
+                .any(|owner| owner.is_subdomain_of(qname))
+        };
+        if records.is_empty() && !below() {
+            flags.rcode = RCODE_NXDOMAIN as u8;
+        }
+        res.set_flags(flags);
+
+        let cname = records
+            .iter()
+            .any(|rr| rr.rr_type == TYPE_CNAME && qtype != TYPE_CNAME);
+        let answers: Vec<_> = records
+            .into_iter()
+            .filter(|rr| {
+                if cname {
+                    rr.rr_type == TYPE_CNAME
+                } else {
+                    rr.rr_type == qtype || RecordType::from(qtype) == RecordType::Any
+                }
+            })
+            .collect();
+        if answers.is_empty() {
+            // NODATA or NXDOMAIN, with the zone's SOA when there's one
+            let soa = zone
+                .and_then(|zone| self.records.get(zone))
+                .and_then(|apex| apex.iter().find(|rr| rr.rr_type == TYPE_SOA).cloned());
+            if let Some(soa) = soa {
+                res.add_authority(soa);
+            }
+        }
+        for rr in answers {
+            res.add_answer(rr);
+        }
+        Some(res)
+    }
+
+    // the records of the wildcard that covers `name`, renamed to it. Only the closest encloser's
+    // wildcard counts: once an ancestor of `name` has records of its own, no wildcard further up
+    // reaches past it
+    fn wildcard(&self, name: &Name) -> Option<Vec<ResourceRecord>> {
+        let mut ancestor = name.parent()?;
+        loop {
+            let star = Name::from(if ancestor.is_root() {
+                "*".to_string()
+            } else {
+                format!("*.{}", ancestor)
+            });
+            if let Some(records) = self.records.get(&star) {
+                let renamed = records
+                    .iter()
+                    .map(|rr| ResourceRecord {
+                        name: name.clone(),
+                        ..rr.clone()
+                    })
+                    .collect();
+                return Some(renamed);
+            }
+            if self.records.contains_key(&ancestor) {
+                return None;
+            }
+            ancestor = ancestor.parent()?;
+        }
+    }
+
+    // the closest zone of ours `name` is in
+    fn zone_of(&self, name: &Name) -> Option<&Name> {
+        self.zones
+            .iter()
+            .filter(|zone| name.is_subdomain_of(zone))
+            .max_by_key(|zone| zone.labels().len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Soa;
+    use std::net::Ipv4Addr;
+
+    fn ask(local: &LocalRecords, name: &str, qtype: RecordType) -> Option<DnsMessage> {
+        local.answer(&DnsMessage::query(name).qtype(qtype).build())
+    }
+
+    #[test]
+    fn test_names_and_wildcards() {
+        let mut local = LocalRecords::new();
+        local.add_addr("printer.home", IpAddr::from([192, 168, 1, 50]));
+        local.add_addr("*.test", IpAddr::from([127, 0, 0, 1]));
+        local.add_addr("real.test", IpAddr::from([192, 0, 2, 9]));
+        local.add(ResourceRecord::new(
+            "www.home",
+            300,
+            RData::CNAME("printer.home".to_string()),
+        ));
+
+        let res = ask(&local, "Printer.Home.", RecordType::A).unwrap();
+        assert!(res.flags().aa);
+        assert_eq!(res.ipv4_addrs(), [Ipv4Addr::new(192, 168, 1, 50)]);
+        assert_eq!(res.answers[0].ttl, LOCAL_TTL);
+        // ours, but not of that type
+        let res = ask(&local, "printer.home", RecordType::AAAA).unwrap();
+        assert_eq!(res.rcode(), 0);
+        assert!(res.answers.is_empty());
+
+        // the wildcard answers for the name asked about, any depth below it
+        let res = ask(&local, "app.dev.test", RecordType::A).unwrap();
+        assert_eq!(res.answers[0].name, Name::from("app.dev.test"));
+        assert_eq!(res.ipv4_addrs(), [Ipv4Addr::LOCALHOST]);
+        assert_eq!(
+            ask(&local, "real.test", RecordType::A)
+                .unwrap()
+                .ipv4_addrs(),
+            [Ipv4Addr::new(192, 0, 2, 9)]
+        );
+        // but not past a name with records of its own, nor for test itself
+        assert!(ask(&local, "x.real.test", RecordType::A).is_none());
+        assert!(ask(&local, "test", RecordType::A).is_none());
+
+        // a CNAME whatever the type, for the resolver to follow
+        let res = ask(&local, "www.home", RecordType::A).unwrap();
+        assert_eq!(res.answers.len(), 1);
+        assert_eq!(res.answers[0].rr_type, TYPE_CNAME);
+
+        assert!(ask(&local, "example.com", RecordType::A).is_none());
+    }
+
+    #[test]
+    fn test_zones() {
+        let mut local = LocalRecords::new();
+        local.add_zone("home");
+        local.add(ResourceRecord::new(
+            "home",
+            3600,
+            RData::SOA(Soa {
+                mname: "router.home".to_string(),
+                rname: "admin.home".to_string(),
+                serial: 1,
+                refresh: 3600,
+                retry: 600,
+                expire: 86400,
+                minimum: 60,
+            }),
+        ));
+        local.add_addr("printer.home", IpAddr::from([192, 168, 1, 50]));
+
+        let res = ask(&local, "nas.home", RecordType::A).unwrap();
+        assert_eq!(res.rcode(), RCODE_NXDOMAIN);
+        assert_eq!(res.authority[0].rr_type, TYPE_SOA);
+        assert_eq!(ask(&local, "home", RecordType::A).unwrap().rcode(), 0);
+        let res = ask(&local, "printer.home", RecordType::TXT).unwrap();
+        assert_eq!(res.rcode(), 0);
+        assert_eq!(res.authority.len(), 1);
+        assert!(ask(&local, "home.example", RecordType::A).is_none());
+    }
+}
@@ -1,13 +1,174 @@
-use implementation;
+// a small dig: one query from the command line (see cli.rs), and what came back
+mod cli;
+
+use std::io;
+use std::process;
+use std::sync::Arc;
+use std::time::Instant;
+
+use cli::{Args, Transport};
+use implementation::{
+    DnsError, DnsMessage, DnsTransport, IterativeOptions, IterativeResolver, Name, QueryOptions,
+    Resolver, RetryPolicy, RootHints,
+};
 
 fn main() {
-    println!(
-        "DNS Resolver client side working model from scratch:
-    - For now I have used to delegate the task of resolving to google dns
-    - But in future I plan to add my own custom handling of resolving domains\n"
-    );
-    let msg = implementation::input_url();
-    // println!("{:#?}", msg);
-    let res = implementation::send_message(msg);
-    println!("{:#?}", res);
+    let args = match Args::parse(std::env::args().skip(1)) {
+        Ok(args) => args,
+        Err(e) => {
+            eprintln!("error: {}\n\n{}", e, cli::USAGE);
+            process::exit(2);
+        }
+    };
+    if args.help {
+        print!("{}", cli::USAGE);
+        return;
+    }
+    let started = Instant::now();
+    match run(&args) {
+        Ok(res) if args.json => match json(&res) {
+            Ok(json) => println!("{}", json),
+            Err(e) => {
+                eprintln!("error: {}", e);
+                process::exit(1);
+            }
+        },
+        Ok(res) if args.short => {
+            for rr in &res.answers {
+                println!("{}", rr.data);
+            }
+        }
+        Ok(res) => {
+            println!("{}", res);
+            println!(";; Query time: {} msec", started.elapsed().as_millis());
+            // a trace asked lots of servers, its steps have them all
+            if let Some(server) = args.server_addr().ok().filter(|_| !args.trace) {
+                println!(";; SERVER: {}", server);
+            }
+        }
+        Err(e) => {
+            eprintln!("error: {}", e);
+            process::exit(1);
+        }
+    }
+}
+
+fn run(args: &Args) -> Result<DnsMessage, DnsError> {
+    if args.trace {
+        return trace(args);
+    }
+    let resolver = resolver(args)?;
+    let query = args.query();
+    let mut last = Err(DnsError::Timeout);
+    for _ in 0..args.tries {
+        match resolver.query(&query) {
+            Err(DnsError::Timeout) => last = Err(DnsError::Timeout),
+            res => return res,
+        }
+    }
+    last
+}
+
+// +trace: iterative resolution starting at the root servers, or at the @server as the only
+// root, with a line for each server asked before the answer gets printed
+fn trace(args: &Args) -> Result<DnsMessage, DnsError> {
+    let hints = match &args.server {
+        Some(_) => {
+            let mut hints = RootHints::empty();
+            hints.add(Name::root(), args.server_addr()?);
+            hints
+        }
+        None => RootHints::iana(),
+    };
+    let mut resolver = IterativeResolver::with_root_hints(hints);
+    resolver.set_options(IterativeOptions {
+        timeout: args.timeout,
+        port: args.port.unwrap_or(53),
+        ..IterativeOptions::default()
+    });
+    let trace = resolver.trace(args.name.as_str(), args.qtype.into());
+    if !args.short && !args.json {
+        for step in &trace.steps {
+            println!("{}", step);
+        }
+        println!();
+    }
+    trace.answer
+}
+
+// a Resolver that asks the one server, over the transport asked for. It sends the query as it
+// is, the flags and DO bit included
+fn resolver(args: &Args) -> Result<Resolver, DnsError> {
+    let server = args.server_addr()?;
+    let transport = match &args.transport {
+        Transport::Udp | Transport::Tcp => {
+            let mut resolver = Resolver::with_server(server);
+            resolver.set_timeout(args.timeout);
+            resolver.set_retry_policy(RetryPolicy::default());
+            resolver.set_options(QueryOptions {
+                force_tcp: args.transport == Transport::Tcp,
+                ..QueryOptions::default()
+            });
+            return Ok(resolver);
+        }
+        Transport::Tls => tls(args, server)?,
+        Transport::Https(path) => https(args, server, path)?,
+    };
+    Ok(Resolver::with_transport(transport))
+}
+
+#[cfg(feature = "tls")]
+fn tls(args: &Args, server: std::net::SocketAddr) -> Result<Arc<dyn DnsTransport>, DnsError> {
+    let mut transport = implementation::TlsTransport::new(server, &args.server_name()?)?;
+    transport.set_timeout(args.timeout);
+    Ok(Arc::new(transport))
+}
+
+#[cfg(not(feature = "tls"))]
+fn tls(_: &Args, _: std::net::SocketAddr) -> Result<Arc<dyn DnsTransport>, DnsError> {
+    Err(not_built("+tls", "tls"))
+}
+
+// the URL is for the host name after the @ when there is one, but we connect to the address we
+// already looked up
+#[cfg(feature = "doh")]
+fn https(
+    args: &Args,
+    server: std::net::SocketAddr,
+    path: &str,
+) -> Result<Arc<dyn DnsTransport>, DnsError> {
+    let host = match args.server_name()? {
+        name if name.contains(':') => format!("[{}]", name),
+        name => name,
+    };
+    let url = format!("https://{}:{}{}", host, server.port(), path);
+    let mut transport = implementation::DohTransport::new(&url)?;
+    transport.set_server_addr(server);
+    transport.set_timeout(args.timeout);
+    Ok(Arc::new(transport))
+}
+
+#[cfg(not(feature = "doh"))]
+fn https(_: &Args, _: std::net::SocketAddr, _: &str) -> Result<Arc<dyn DnsTransport>, DnsError> {
+    Err(not_built("+https", "doh"))
+}
+
+#[cfg(feature = "serde")]
+fn json(res: &DnsMessage) -> Result<String, DnsError> {
+    serde_json::to_string_pretty(res)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e).into())
+}
+
+#[cfg(not(feature = "serde"))]
+fn json(_: &DnsMessage) -> Result<String, DnsError> {
+    Err(not_built("+json", "serde"))
+}
+
+#[cfg(not(all(feature = "tls", feature = "doh", feature = "serde")))]
+fn not_built(option: &str, feature: &str) -> DnsError {
+    io::Error::new(
+        io::ErrorKind::Unsupported,
+        format!("{} needs a build with the {} feature", option, feature),
+    )
+    .into()
 }
@@ -0,0 +1,268 @@
+// multicast DNS (RFC 6762): names under .local belong to whichever machine on the link claims
+// them, and no unicast server knows about them. We ask the way a plain resolver is allowed to,
+// with one-shot queries (section 5.1): sent to the group from an ephemeral port, answered by
+// unicast with our ID and question echoed, like any DNS server would.
+//
+// Nobody tells us when everyone has answered, so after the first response we keep listening for
+// a short while (responders delay shared answers by up to 120ms, RFC 6762 section 6) and put
+// every record that came in into one answer. Nothing at all within the timeout is NXDOMAIN: mDNS
+// has no negative answers to wait for
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4, SocketAddrV6, UdpSocket};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::{DnsError, DnsMessage, Name, ResourceRecord, RCODE_NXDOMAIN};
+
+pub const MDNS_GROUP_V4: Ipv4Addr = Ipv4Addr::new(224, 0, 0, 251);
+pub const MDNS_GROUP_V6: Ipv6Addr = Ipv6Addr::new(0xff02, 0, 0, 0, 0, 0, 0, 0xfb);
+pub const MDNS_PORT: u16 = 5353;
+
+// how long we wait for anyone to answer at all
+const MDNS_TIMEOUT: Duration = Duration::from_secs(1);
+// and how long we keep listening for the others once somebody has
+const MDNS_AGGREGATION: Duration = Duration::from_millis(200);
+
+// the top bit of a record's class in mDNS is the cache-flush bit (RFC 6762 section 10.2), not
+// part of the class
+const CACHE_FLUSH: u16 = 0x8000;
+
+// the names that are looked up with mDNS: .local, and the reverse names of link-local
+// addresses (169.254/16 and fe80::/10), RFC 6762 section 4
+const MDNS_DOMAINS: &[&str] = &[
+    "local",
+    "254.169.in-addr.arpa",
+    "8.e.f.ip6.arpa",
+    "9.e.f.ip6.arpa",
+    "a.e.f.ip6.arpa",
+    "b.e.f.ip6.arpa",
+];
+
+pub fn is_mdns_name(name: &Name) -> bool {
+    MDNS_DOMAINS
+        .iter()
+        .any(|domain| name.is_subdomain_of(&Name::from(*domain)))
+}
+
+// both groups on the mDNS port, where Resolver sends .local names unless told otherwise
+pub(crate) fn mdns_groups() -> Vec<SocketAddr> {
+    vec![
+        SocketAddr::V4(SocketAddrV4::new(MDNS_GROUP_V4, MDNS_PORT)),
+        SocketAddr::V6(SocketAddrV6::new(MDNS_GROUP_V6, MDNS_PORT, 0, 0)),
+    ]
+}
+
+// asks the link about `query`'s question over both IPv4 and IPv6, and answers it with what
+// everyone sent back
+pub fn send_mdns(query: &DnsMessage) -> Result<DnsMessage, DnsError> {
+    query_groups(query, &mdns_groups())
+}
+
+// the same, to these addresses. A family that can't send at all (no IPv6 on the machine, say)
+// is left out; it's only an error if none of them can
+pub(crate) fn query_groups(
+    query: &DnsMessage,
+    groups: &[SocketAddr],
+) -> Result<DnsMessage, DnsError> {
+    let one_shot = one_shot_query(query);
+    let results: Vec<_> = thread::scope(|scope| {
+        let asking: Vec<_> = groups
+            .iter()
+            .map(|group| {
+                let one_shot = &one_shot;
+                scope.spawn(move || {
+                    let socket = mdns_socket(*group)?;
+                    collect_responses(&socket, one_shot, *group, MDNS_TIMEOUT, MDNS_AGGREGATION)
+                })
+            })
+            .collect();
+        asking
+            .into_iter()
+            .map(|thread| thread.join().unwrap())
+            .collect()
+    });
+
+    let mut responses = Vec::new();
+    let mut last = None;
+    let mut sent = false;
+    for result in results {
+        match result {
+            Ok(found) => {
+                sent = true;
+                responses.extend(found);
+            }
+            Err(e) => last = Some(e),
+        }
+    }
+    match last {
+        Some(e) if !sent => Err(e),
+        _ => Ok(merged(query, &responses)),
+    }
+}
+
+// RD clear (there's no recursion on the link) and a fresh ID of our own. The question is the
+// caller's, class and all
+fn one_shot_query(query: &DnsMessage) -> DnsMessage {
+    let mut one_shot = DnsMessage::query(query.question().qname.clone())
+        .recursion_desired(false)
+        .build();
+    one_shot.questions = query.questions.clone();
+    one_shot
+}
+
+fn mdns_socket(group: SocketAddr) -> Result<UdpSocket, DnsError> {
+    // what RFC 6762 section 11 says to send with, although on the local link it hardly matters
+    let socket = match group {
+        SocketAddr::V4(_) => {
+            let socket = UdpSocket::bind("0.0.0.0:0")?;
+            socket.set_multicast_ttl_v4(255)?;
+            socket.set_multicast_loop_v4(true)?;
+            socket
+        }
+        SocketAddr::V6(_) => {
+            let socket = UdpSocket::bind("[::]:0")?;
+            socket.set_multicast_loop_v6(true)?;
+            socket
+        }
+    };
+    Ok(socket)
+}
+
+fn collect_responses(
+    socket: &UdpSocket,
+    query: &DnsMessage,
+    dest: SocketAddr,
+    timeout: Duration,
+    aggregation: Duration,
+) -> Result<Vec<DnsMessage>, DnsError> {
+    socket.send_to(&query.to_bytes()?, dest)?;
+
+    // like LLMNR, the answers come from the responders' own addresses, so the ID and QR bit are
+    // all we can go on
+    let mut responses = Vec::new();
+    let mut buf = [0u8; 9000];
+    let mut deadline = Instant::now() + timeout;
+    loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            break;
+        }
+        socket.set_read_timeout(Some(remaining))?;
+        let size = match socket.recv_from(&mut buf) {
+            Ok((size, _)) => size,
+            Err(e)
+                if matches!(
+                    e.kind(),
+                    std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut
+                ) =>
+            {
+                break
+            }
+            Err(e) => return Err(e.into()),
+        };
+        if size < 12 || buf[0..2] != query.header.identification.to_be_bytes() {
+            continue;
+        }
+        match DnsMessage::from_bytes(&buf[..size]) {
+            Ok(res) if res.is_response() => {
+                if responses.is_empty() {
+                    deadline = deadline.min(Instant::now() + aggregation);
+                }
+                responses.push(res);
+            }
+            _ => {}
+        }
+    }
+    Ok(responses)
+}
+
+// one answer to `query` out of every response: each record once, cache-flush bit cleared
+fn merged(query: &DnsMessage, responses: &[DnsMessage]) -> DnsMessage {
+    let mut res = DnsMessage::response_to(query);
+    let mut flags = res.flags();
+    flags.aa = true;
+    flags.ra = true;
+    if responses.is_empty() {
+        flags.rcode = RCODE_NXDOMAIN as u8;
+    }
+    res.set_flags(flags);
+
+    let plain = |rr: &ResourceRecord| ResourceRecord {
+        class: rr.class & !CACHE_FLUSH,
+        ..rr.clone()
+    };
+    for response in responses {
+        for rr in response.answers.iter().map(plain) {
+            if !res.answers.contains(&rr) {
+                res.add_answer(rr);
+            }
+        }
+        for rr in response.additional.iter().map(plain) {
+            if !res.additional.contains(&rr) && !res.answers.contains(&rr) {
+                res.add_additional(rr);
+            }
+        }
+    }
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{mock_udp_server_replies, response_with_rdata};
+    use crate::RecordType;
+
+    #[test]
+    fn test_mdns_names() {
+        assert!(MDNS_GROUP_V4.is_multicast());
+        assert!(MDNS_GROUP_V6.is_multicast());
+        for name in [
+            "printer.local",
+            "Printer.Local.",
+            "7.1.254.169.in-addr.arpa",
+        ] {
+            assert!(is_mdns_name(&Name::from(name)), "{}", name);
+        }
+        for name in ["example.com", "local.example.com", "notlocal"] {
+            assert!(!is_mdns_name(&Name::from(name)), "{}", name);
+        }
+
+        let query = DnsMessage::query("printer.local")
+            .qtype(RecordType::AAAA)
+            .build();
+        let one_shot = one_shot_query(&query);
+        assert!(!one_shot.recursion_desired());
+        assert_eq!(one_shot.questions, query.questions);
+    }
+
+    #[test]
+    fn test_mdns_aggregates_responses() {
+        // one host answers twice (the second time with the cache-flush bit set), another once,
+        // and a query nobody asked for goes by in between
+        let responder = mock_udp_server_replies(1, |query| {
+            let a = response_with_rdata(query, 1, &[192, 168, 1, 10]);
+            let mut flushed = a.clone();
+            let class = flushed.len() - 4 - 2 - 4 - 2;
+            flushed[class] |= 0x80;
+            let b = response_with_rdata(query, 1, &[192, 168, 1, 11]);
+            vec![a, query.to_vec(), flushed, b]
+        });
+
+        let query = DnsMessage::query("printer.local").id(7).build();
+        let res = query_groups(&query, &[responder]).unwrap();
+        assert_eq!(res.header.identification, 7);
+        assert!(res.flags().aa);
+        assert_eq!(
+            res.ipv4_addrs(),
+            [
+                Ipv4Addr::new(192, 168, 1, 10),
+                Ipv4Addr::new(192, 168, 1, 11)
+            ]
+        );
+        assert!(res.answers.iter().all(|rr| rr.class == 1));
+
+        // nobody home
+        let silent = mock_udp_server_replies(1, |_| Vec::new());
+        let res = query_groups(&query, &[silent]).unwrap();
+        assert_eq!(res.rcode(), RCODE_NXDOMAIN);
+    }
+}
@@ -0,0 +1,1394 @@
+// the wire format: message types and how they turn into bytes and back. Nothing in here touches
+// the network (or std), only alloc, so it can be used on its own in no_std/embedded builds
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::net::{Ipv4Addr, Ipv6Addr};
+use core::ops::Range;
+
+#[cfg(feature = "std")]
+use std::collections::HashMap;
+
+use crate::name::{dname_substitute, escape_label, fits_on_wire, unescape_name};
+use crate::rdata::TYPE_TSIG;
+use crate::{
+    DnsClass, DnsError, DnsFlags, DnsMessageRef, Edns, ExtendedError, Name, Opcode, RData,
+    RecordType, ResponseCode,
+};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DnsHeader {
+    // header section - 12 bytes
+    pub identification: u16,
+    pub flags: u16, // for now we are planning on hardcoding it because we are just using recursion
+    pub no_of_questions: u16,
+    pub no_of_answers_rr: u16,
+    pub no_of_authority_rr: u16,
+    pub no_of_additional_rr: u16,
+}
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DnsQuestion {
+    //Name and type feilds for a query
+    pub qname: Name, // example.com
+    pub qtype: u16,  // A = 1
+    pub qclass: u16, // IN = 1
+}
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ResourceRecord {
+    pub name: Name,
+    pub rr_type: u16, // A = 1, NS = 2, etc.
+    pub class: u16,   // Usually IN (1)
+    pub ttl: u32,
+    pub rdlength: u16,
+    pub rdata: Vec<u8>, // the raw bytes, exactly as they came off the wire
+    pub data: RData,    // and decoded depending on rr_type
+}
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DnsMessage {
+    pub header: DnsHeader,
+    // nearly always exactly one, but a message can have none (some error responses, the later
+    // messages of a zone transfer) or, in theory, several
+    pub questions: Vec<DnsQuestion>,
+    pub answers: Vec<ResourceRecord>,    // RRs in response to query
+    pub authority: Vec<ResourceRecord>,  //Records for authoritative servers
+    pub additional: Vec<ResourceRecord>, //Additional helpful info
+    // the EDNS OPT pseudo-record, which is never among the additional records themselves
+    pub edns: Option<Edns>,
+}
+
+// where each question and record was found in the packet: start..end byte ranges, one per
+// entry and in the same order as the message's sections. A record's range covers everything from
+// its name (or the pointer standing in for it) to the end of its rdata
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RecordOffsets {
+    pub questions: Vec<Range<usize>>,
+    pub answers: Vec<Range<usize>>,
+    pub authority: Vec<Range<usize>>,
+    pub additional: Vec<Range<usize>>,
+    pub edns: Option<Range<usize>>,
+}
+
+// response codes live in the low 4 bits of the flags word. 1 is the server refusing to parse
+// what we sent, 3 means the name doesn't exist
+pub const RCODE_FORMERR: u16 = 1;
+pub const RCODE_NXDOMAIN: u16 = 3;
+
+pub(crate) const MAX_MESSAGE_LEN: usize = 65535;
+
+// what DnsMessage::question gives for a message without one
+static NO_QUESTION: DnsQuestion = DnsQuestion {
+    qname: Name::ROOT,
+    qtype: 0,
+    qclass: 0,
+};
+// the longest a name can be on the wire, length bytes included
+const MAX_NAME_LEN: usize = 255;
+// the most labels a name that short can have, and so the most pointers a name needs
+const MAX_POINTERS: usize = 127;
+
+// a fresh random ID for every query: the ID (with the source port) is all that stops an off-path
+// attacker from slipping us a forged answer, so it mustn't be guessable
+#[cfg(feature = "std")]
+fn new_id() -> u16 {
+    crate::random::random_u16()
+}
+
+#[cfg(not(feature = "std"))]
+fn new_id() -> u16 {
+    0
+}
+
+// small builder so callers can pick the options for a query instead of poking at struct fields
+pub struct QueryBuilder {
+    qname: Name,
+    qtype: u16,
+    qclass: u16,
+    recursion_desired: bool,
+    checking_disabled: bool,
+    authentic_data: bool,
+    dnssec_ok: bool,
+    edns: Option<Edns>,
+    id: Option<u16>,
+}
+
+// the UDP payload we advertise when the builder has to make up an OPT record for DO, the same
+// DNS flag day 2020 number QueryOptions defaults to
+const BUILDER_UDP_PAYLOAD: u16 = 1232;
+
+impl QueryBuilder {
+    // takes a RecordType/QType or the raw number
+    pub fn qtype(mut self, qtype: impl Into<u16>) -> Self {
+        self.qtype = qtype.into();
+        self
+    }
+
+    // IN unless asked otherwise. CH is the other one still in use, for asking a server
+    // things like version.bind. A DnsClass or the raw number
+    pub fn qclass(mut self, qclass: impl Into<u16>) -> Self {
+        self.qclass = qclass.into();
+        self
+    }
+
+    pub fn recursion_desired(mut self, on: bool) -> Self {
+        self.recursion_desired = on;
+        self
+    }
+
+    // CD: for DNSSEC validation on our end. The server hands over what it has even if it thinks
+    // the signatures are bogus, so we can judge for ourselves
+    pub fn checking_disabled(mut self, on: bool) -> Self {
+        self.checking_disabled = on;
+        self
+    }
+
+    // AD in a query (RFC 6840): we'd like to know whether the server validated the answer,
+    // without asking for the signatures themselves
+    pub fn authentic_data(mut self, on: bool) -> Self {
+        self.authentic_data = on;
+        self
+    }
+
+    // DO: send the DNSSEC records (RRSIG and friends) along. It lives in the OPT record, so this
+    // adds one if the query doesn't have it yet
+    pub fn dnssec_ok(mut self, on: bool) -> Self {
+        self.dnssec_ok = on;
+        self
+    }
+
+    // an OPT record to go with the query (by default the Resolver adds one of its own)
+    pub fn edns(mut self, edns: Edns) -> Self {
+        self.edns = Some(edns);
+        self
+    }
+
+    // a transaction ID of our choosing instead of a random one, for tests and for no_std builds
+    // (which have no randomness to draw on and would otherwise send 0)
+    pub fn id(mut self, id: u16) -> Self {
+        self.id = Some(id);
+        self
+    }
+
+    pub fn build(self) -> DnsMessage {
+        let mut msg = DnsMessage::blank(self.qname);
+        msg.header.identification = self.id.unwrap_or_else(new_id);
+        msg.questions[0].qtype = self.qtype;
+        msg.questions[0].qclass = self.qclass;
+        msg.edns = self.edns;
+        if self.dnssec_ok {
+            msg.edns
+                .get_or_insert_with(|| Edns::new(BUILDER_UDP_PAYLOAD))
+                .dnssec_ok = true;
+        }
+        msg.update_flags(|flags| {
+            flags.rd = self.recursion_desired;
+            flags.cd = self.checking_disabled;
+            flags.ad = self.authentic_data;
+        });
+        msg
+    }
+}
+
+impl DnsMessage {
+    // the convenience path: recursive query to a resolver that does the heavy lifting
+    pub fn new(url: String) -> Self {
+        DnsMessage::query(url).build()
+    }
+
+    // RD stays on by default, turn it off with .recursion_desired(false) for iterative lookups
+    pub fn query(url: impl Into<Name>) -> QueryBuilder {
+        QueryBuilder {
+            qname: url.into(),
+            qtype: 1, // A unless asked otherwise
+            qclass: 1,
+            recursion_desired: true,
+            checking_disabled: false,
+            authentic_data: false,
+            dnssec_ok: false,
+            edns: None,
+            id: None,
+        }
+    }
+
+    // an empty response to `query`: same ID and question, QR set and RD copied over like a
+    // server is supposed to. Fill in the sections with add_answer & co. This is what a mock
+    // server (or any other code answering queries) starts from
+    pub fn response_to(query: &DnsMessage) -> Self {
+        let mut res = DnsMessage::blank(query.question().qname.clone());
+        res.header.identification = query.header.identification;
+        res.update_flags(|flags| {
+            flags.qr = true;
+            flags.rd = query.recursion_desired();
+        });
+        res.header.no_of_questions = query.questions.len() as u16;
+        res.questions = query.questions.clone();
+        res
+    }
+
+    // the question, the first one if there are several. A message without any gets one for the
+    // root with type and class 0, which matches nothing anybody asks
+    pub fn question(&self) -> &DnsQuestion {
+        self.questions.first().unwrap_or(&NO_QUESTION)
+    }
+
+    pub fn flags(&self) -> DnsFlags {
+        DnsFlags::from_u16(self.header.flags)
+    }
+
+    pub fn set_flags(&mut self, flags: DnsFlags) {
+        self.header.flags = flags.to_u16();
+    }
+
+    fn update_flags(&mut self, change: impl FnOnce(&mut DnsFlags)) {
+        let mut flags = self.flags();
+        change(&mut flags);
+        self.set_flags(flags);
+    }
+
+    pub fn is_response(&self) -> bool {
+        self.flags().qr
+    }
+
+    pub fn set_response(&mut self, on: bool) {
+        self.update_flags(|flags| flags.qr = on);
+    }
+
+    // these keep the header counts in step with the sections
+    pub fn add_question(&mut self, question: DnsQuestion) {
+        self.questions.push(question);
+        self.header.no_of_questions += 1;
+    }
+
+    pub fn add_answer(&mut self, rr: ResourceRecord) {
+        self.answers.push(rr);
+        self.header.no_of_answers_rr += 1;
+    }
+
+    pub fn add_authority(&mut self, rr: ResourceRecord) {
+        self.authority.push(rr);
+        self.header.no_of_authority_rr += 1;
+    }
+
+    pub fn add_additional(&mut self, rr: ResourceRecord) {
+        self.additional.push(rr);
+        self.header.no_of_additional_rr += 1;
+    }
+
+    pub fn truncated(&self) -> bool {
+        self.flags().tc
+    }
+
+    pub fn recursion_desired(&self) -> bool {
+        self.flags().rd
+    }
+
+    pub fn set_recursion_desired(&mut self, on: bool) {
+        self.update_flags(|flags| flags.rd = on);
+    }
+
+    pub fn checking_disabled(&self) -> bool {
+        self.flags().cd
+    }
+
+    pub fn set_checking_disabled(&mut self, on: bool) {
+        self.update_flags(|flags| flags.cd = on);
+    }
+
+    // AD in a response: the server says it validated the data with DNSSEC. Only worth anything
+    // if we trust that server and the path to it
+    pub fn authentic_data(&self) -> bool {
+        self.flags().ad
+    }
+
+    // the name the answer is really about once the CNAMEs and DNAMEs in the answer section are
+    // followed from the question: www.example.com CNAME example.net means the addresses we want
+    // are the ones for example.net. A DNAME rewrites the name instead of naming it outright. Stops
+    // at the first name we have already been through, so a looping chain can't hang us
+    pub fn canonical_name(&self) -> Name {
+        let mut chain = self.cname_chain();
+        chain.pop().unwrap()
+    }
+
+    // every name canonical_name goes through, the question's first and its result last. When
+    // the chain loops, the name that closes the loop is in there twice
+    pub fn cname_chain(&self) -> Vec<Name> {
+        let mut chain = alloc::vec![self.question().qname.clone()];
+        loop {
+            let name = chain.last().unwrap();
+            let next = self.answers.iter().find_map(|rr| match &rr.data {
+                RData::CNAME(target) if rr.name == *name => Some(Name::from(target)),
+                RData::DNAME(target) => {
+                    dname_substitute(name.as_str(), rr.name.as_str(), target).map(Name::from)
+                }
+                _ => None,
+            });
+            let Some(next) = next else {
+                return chain;
+            };
+            let looped = chain.contains(&next);
+            chain.push(next);
+            if looped {
+                return chain;
+            }
+        }
+    }
+
+    // the addresses the question resolves to. Only A records owned by the end of the CNAME
+    // chain count: a resolver can leave addresses for the intermediate names (or for something
+    // else entirely) in the answer section, and those aren't what we asked for
+    pub fn ipv4_addrs(&self) -> Vec<Ipv4Addr> {
+        let target = self.canonical_name();
+        self.answers
+            .iter()
+            .filter_map(|rr| match rr.data {
+                RData::A(addr) if rr.name == target => Some(addr),
+                _ => None,
+            })
+            .collect()
+    }
+
+    // same as ipv4_addrs, for AAAA
+    pub fn ipv6_addrs(&self) -> Vec<Ipv6Addr> {
+        let target = self.canonical_name();
+        self.answers
+            .iter()
+            .filter_map(|rr| match rr.data {
+                RData::AAAA(addr) if rr.name == target => Some(addr),
+                _ => None,
+            })
+            .collect()
+    }
+
+    // with EDNS the rcode is 12 bits, the upper 8 of them in the OPT record
+    pub fn rcode(&self) -> u16 {
+        let extended = self
+            .edns
+            .as_ref()
+            .map_or(0, |edns| edns.extended_rcode as u16);
+        extended << 4 | self.flags().rcode as u16
+    }
+
+    // why the server answered the way it did, when it says (RFC 8914)
+    pub fn extended_errors(&self) -> Vec<ExtendedError> {
+        self.edns
+            .as_ref()
+            .map_or_else(Vec::new, |edns| edns.extended_errors())
+    }
+
+    // rcode() and the header's opcode with names on them
+    pub fn response_code(&self) -> ResponseCode {
+        ResponseCode::from(self.rcode())
+    }
+
+    pub fn opcode(&self) -> Opcode {
+        Opcode::from(self.flags().opcode)
+    }
+
+    // an ANY query gets back a mix of types, this sorts the answers into a pile per rr_type
+    #[cfg(feature = "std")]
+    pub fn answers_by_type(&self) -> HashMap<u16, Vec<&ResourceRecord>> {
+        let mut by_type: HashMap<u16, Vec<&ResourceRecord>> = HashMap::new();
+        for rr in &self.answers {
+            by_type.entry(rr.rr_type).or_default().push(rr);
+        }
+        by_type
+    }
+
+    fn blank(url: Name) -> Self {
+        let header = DnsHeader {
+            identification: 0, // the builder (or response_to) fills it in
+            flags: 0,          // the builder decides on the RD bit
+            no_of_questions: 1,
+            no_of_answers_rr: 0,
+            no_of_authority_rr: 0,
+            no_of_additional_rr: 0,
+        };
+
+        let question = DnsQuestion {
+            qname: url,
+            qtype: 1, // A record by default, the builder can change it 1-Ipv4 , 2-NS ,5- CName,15-MX, 28-Ipv6
+            qclass: 1, // IN (Internet)
+        };
+
+        DnsMessage {
+            header,
+            questions: alloc::vec![question],
+            // the next section we will get a response back
+            answers: Vec::new(),
+            authority: Vec::new(),
+            additional: Vec::new(),
+            edns: None,
+        }
+    }
+
+    // fails for a message that can't be put on the wire: a name with a label over 63 bytes or
+    // over 255 in total, or the whole thing over 64K
+    pub fn to_bytes(&self) -> Result<Vec<u8>, DnsError> {
+        let mut bytes = Vec::new();
+
+        // HEADER SECTION
+        // the counts come from the sections themselves so they can't disagree with what we
+        // actually write
+        let no_of_questions = u16::try_from(self.questions.len())
+            .map_err(|_| DnsError::Malformed("more than 65535 questions"))?;
+        bytes.extend(&self.header.identification.to_be_bytes()); // 2 bytes
+        bytes.extend(&self.header.flags.to_be_bytes()); // 2 bytes
+        bytes.extend(&no_of_questions.to_be_bytes()); // 2 bytes
+        bytes.extend(&(self.answers.len() as u16).to_be_bytes()); // 2 bytes
+        bytes.extend(&(self.authority.len() as u16).to_be_bytes()); // 2 bytes
+        let no_of_additional = self.additional.len() + self.edns.is_some() as usize;
+        bytes.extend(&(no_of_additional as u16).to_be_bytes()); // 2 bytes
+
+        // names repeat a lot (every answer's owner is usually the question's name), so from
+        // here on a name, or the tail end of one, that's already been written becomes a pointer
+        // back to it
+        let mut names = NameCompressor::default();
+
+        // QUESTION SECTION
+        for question in &self.questions {
+            // QNAME — example.com becomes [7]example[3]com[0]
+            write_name(&mut bytes, question.qname.as_str(), &mut names)?;
+
+            // QTYPE (2 bytes)
+            bytes.extend(&question.qtype.to_be_bytes());
+
+            // QCLASS (2 bytes)
+            bytes.extend(&question.qclass.to_be_bytes());
+        }
+
+        // ANSWER, AUTHORITY, ADDITIONAL - only a response has anything here. The OPT record
+        // goes at the end, unless the message is signed: the TSIG record has to be the very last
+        let (additional, tsig) = match self.additional.split_last() {
+            Some((last, rest)) if last.rr_type == TYPE_TSIG => (rest, Some(last)),
+            _ => (self.additional.as_slice(), None),
+        };
+        for rr in self.answers.iter().chain(&self.authority).chain(additional) {
+            rr.write(&mut bytes, Some(&mut names))?;
+        }
+        if let Some(edns) = &self.edns {
+            edns.write(&mut bytes)?;
+        }
+        // RFC 8945 wants the TSIG record's names written out in full
+        if let Some(tsig) = tsig {
+            tsig.write(&mut bytes, None)?;
+        }
+
+        if bytes.len() > MAX_MESSAGE_LEN {
+            return Err(DnsError::Malformed("message longer than 65535 bytes"));
+        }
+        Ok(bytes)
+    }
+
+    pub fn from_bytes(buf: &[u8]) -> Result<Self, DnsError> {
+        Self::from_bytes_with_offsets(buf).map(|(msg, _)| msg)
+    }
+
+    // same as from_bytes, plus where in `buf` every question and record sits. Meant for tooling
+    // (hexdumps, showing off compression...), the message itself is exactly what from_bytes gives
+    //
+    // Whatever `buf` holds, this returns an error rather than panicking, looping or allocating
+    // more than the input justifies, so it's safe to point at packets from anyone (and at a
+    // fuzzer, see fuzz/)
+    pub fn from_bytes_with_offsets(buf: &[u8]) -> Result<(Self, RecordOffsets), DnsError> {
+        // the checking and the walking are DnsMessageRef's, all that's left here is the copying
+        DnsMessageRef::parse(buf)?.to_message_with_offsets()
+    }
+}
+
+impl DnsQuestion {
+    pub fn record_type(&self) -> RecordType {
+        RecordType::from(self.qtype)
+    }
+
+    pub fn class(&self) -> DnsClass {
+        DnsClass::from(self.qclass)
+    }
+}
+
+impl ResourceRecord {
+    pub fn record_type(&self) -> RecordType {
+        RecordType::from(self.rr_type)
+    }
+
+    pub fn class(&self) -> DnsClass {
+        DnsClass::from(self.class)
+    }
+
+    pub fn as_dname(&self) -> Option<&str> {
+        match &self.data {
+            RData::DNAME(target) => Some(target),
+            _ => None,
+        }
+    }
+
+    // a class IN record carrying `data`, with the type and raw rdata filled in from it
+    pub fn new(name: impl Into<Name>, ttl: u32, data: RData) -> Self {
+        let mut rdata = Vec::new();
+        // rdata that can't be encoded (a name in it too long) is refused when the record is
+        // written out, which is where we can report it
+        let _ = data.encode(&mut rdata);
+        ResourceRecord {
+            name: name.into(),
+            rr_type: data.rr_type(),
+            class: 1,
+            ttl,
+            rdlength: rdata.len() as u16,
+            rdata,
+            data,
+        }
+    }
+
+    // the rdata is re-encoded from the typed `data` rather than copied from `rdata`: a record
+    // parsed out of a packet can have compression pointers in its raw rdata, and those point
+    // into a packet that isn't the one we are writing
+    // `names` is None to write every name in full
+    fn write(
+        &self,
+        bytes: &mut Vec<u8>,
+        mut names: Option<&mut NameCompressor>,
+    ) -> Result<(), DnsError> {
+        match names.as_deref_mut() {
+            Some(names) => write_name(bytes, self.name.as_str(), names)?,
+            None => write_qname(bytes, self.name.as_str())?,
+        }
+        bytes.extend(&self.rr_type.to_be_bytes());
+        bytes.extend(&self.class.to_be_bytes());
+        bytes.extend(&self.ttl.to_be_bytes());
+
+        let rdlength_at = bytes.len();
+        bytes.extend(&[0, 0]); // filled in once we know how long the rdata came out
+        self.data.encode_compressed(bytes, names)?;
+        let rdlength = u16::try_from(bytes.len() - rdlength_at - 2)
+            .map_err(|_| DnsError::Malformed("record data longer than 65535 bytes"))?;
+        bytes[rdlength_at..rdlength_at + 2].copy_from_slice(&rdlength.to_be_bytes());
+        Ok(())
+    }
+}
+
+// example.com (or example.com.) becomes [7]example[3]com[0], and the root ("" or ".") is just [0].
+// Escapes like \. or \000 turn back into the raw bytes they stand for
+pub(crate) fn write_qname(bytes: &mut Vec<u8>, name: &str) -> Result<(), DnsError> {
+    let labels = unescape_name(name);
+    if !fits_on_wire(&labels) {
+        return Err(DnsError::InvalidName(String::from(name)));
+    }
+    for label in labels {
+        bytes.push(label.len() as u8); // length byte
+        bytes.extend(label); // label bytes
+    }
+    bytes.push(0); // end of QNAME
+    Ok(())
+}
+
+// where the names (and the tails of names) already in the message start, so the next time one
+// comes up we can write a pointer to it instead. Keyed on the lowercased labels, a name differing
+// only in case is the same name
+#[derive(Debug, Default)]
+pub(crate) struct NameCompressor {
+    suffixes: BTreeMap<Vec<Vec<u8>>, u16>,
+}
+
+// write_qname with compression: the longest tail of `name` already in `bytes` (which has to be
+// the message from its very first byte, pointers are offsets into it) is replaced by a pointer,
+// and the labels written out in front of it are remembered for the names after this one
+pub(crate) fn write_name(
+    bytes: &mut Vec<u8>,
+    name: &str,
+    names: &mut NameCompressor,
+) -> Result<(), DnsError> {
+    let labels = unescape_name(name);
+    if !fits_on_wire(&labels) {
+        return Err(DnsError::InvalidName(String::from(name)));
+    }
+    for i in 0..labels.len() {
+        let suffix: Vec<Vec<u8>> = labels[i..]
+            .iter()
+            .map(|label| label.to_ascii_lowercase())
+            .collect();
+        if let Some(&offset) = names.suffixes.get(&suffix) {
+            bytes.extend((0xC000 | offset).to_be_bytes());
+            return Ok(());
+        }
+        // a pointer only has 14 bits for the offset
+        if bytes.len() < 0x4000 {
+            names.suffixes.insert(suffix, bytes.len() as u16);
+        }
+        bytes.push(labels[i].len() as u8);
+        bytes.extend(&labels[i]);
+    }
+    bytes.push(0);
+    Ok(())
+}
+
+// okay this is made to handle name parsing I. Qusetion we just see if byte is 00 for eg: 03 'w' 'w' 'w' 07 'e' 'x' 'a' 'm' 'p' 'l' 'e' 03 'c' 'o' 'm' 00
+// II. okay so pointer compression is just that we don't waste bytes we just add the pointer the names where it has appeared before in the buffer
+// The first two bits of a length byte set to 11 (binary) or 0xC0 (hex) indicate a pointer
+// The next 14 bits represent the offset in the message where the rest of the domain name can be found.
+//         Example:
+// Suppose somewhere in the DNS message, at position 20, we already had:
+
+// 07 'e' 'x' 'a' 'm' 'p' 'l' 'e' 03 'c' 'o' 'm' 00
+// Later, instead of repeating "example.com", the message can use a pointer like:
+
+// C0 14
+// C0 = 11000000 binary → pointer marker
+// 14 (hex) = 20 decimal → offset to position 20 where "example.com" starts
+//
+// Nothing in the packet is trusted: running off the end, a pointer that doesn't go backwards
+// (which is how a loop would have to start), too many pointers or a name over 255 bytes is an
+// error. Together they put a fixed ceiling on the work one name can cost, so parsing a whole
+// message takes time in proportion to its size however it's put together
+pub(crate) fn parse_qname(buf: &[u8], pos: usize) -> Result<(String, usize), DnsError> {
+    let mut labels = Vec::new();
+    let next = walk_name(buf, pos, |label| labels.push(escape_label(label)))?;
+    Ok((labels.join("."), next))
+}
+
+// the checking and pointer chasing of parse_qname without building anything: each label goes to
+// `on_label` as it's found, and we give back where the name ends, the same as parse_qname does
+pub(crate) fn walk_name<'a>(
+    buf: &'a [u8],
+    mut pos: usize,
+    mut on_label: impl FnMut(&'a [u8]),
+) -> Result<usize, DnsError> {
+    let mut pointers = 0;
+    let mut jumped = false;
+    let mut original_pos = 0;
+    // where the labels we are reading right now started. A pointer has to go to somewhere before
+    // that: an earlier name, never this one again
+    let mut fragment_start = pos;
+    // the name's size on the wire with the pointers followed, the final 0 included
+    let mut name_len = 1;
+    let past_end = || DnsError::Malformed("name runs past the end of the message");
+
+    loop {
+        let byte = *buf.get(pos).ok_or_else(past_end)?;
+
+        // Checking if the first two bits are 1 1 (pointer)
+        if byte & 0b11000000 == 0b11000000 {
+            let second_byte = *buf.get(pos + 1).ok_or_else(past_end)?;
+            // this part is fucking hell
+
+            // “Just stick the two bytes together — that’s the pointer, right?”
+            // But what we really need is:
+
+            // “Use the last 6 bits of the first byte and all 8 bits of the second byte to build a 14-bit number.
+
+            // lets take another example: a very simple and plain analogy:
+            // If you have two digits: 4 and 2, and you want to make 42, you multiply the first by 10 and add the second.
+
+            // In binary:
+            // If you have two bytes: 0x01 and 0x0C, and want to make 0x010C, you shift the first by 8 and add the second.
+
+            // now we extract the pointer
+            // We Remove the two high bits 11000000 because they just show the that the next 14 bits is a pointer
+            let upper_pointer_bits = byte ^ 0b11000000;
+
+            //  shift left by 8 bits - well the first 6 bits of the pointer contribution
+            // keep in mind that the pointer is still 2 bytes that is why we cast it left by 8 bits
+            let upper_offset = (upper_pointer_bits as u16) << 8;
+
+            let lower_offset = second_byte as u16;
+
+            // We Add(OR) the two parts into the full 14-bit offset which is actually u16
+            let pointer_offset = upper_offset | lower_offset;
+
+            if pointer_offset as usize >= fragment_start {
+                return Err(DnsError::Malformed(
+                    "compression pointer doesn't point backwards",
+                ));
+            }
+            // pointers that only lead to more pointers add nothing to the name, but going
+            // backwards alone would still let a message chain thousands of them. No encoder
+            // needs more than one per label
+            pointers += 1;
+            if pointers > MAX_POINTERS {
+                return Err(DnsError::Malformed(
+                    "too many compression pointers in a name",
+                ));
+            }
+
+            // Save current position only the first time we jump
+            if !jumped {
+                original_pos = pos + 2; // like from where do we continue after this
+            }
+
+            pos = pointer_offset as usize;
+            fragment_start = pos;
+            jumped = true;
+            continue;
+        }
+
+        // 01 and 10 in the top bits are label types nobody ended up using
+        if byte & 0b11000000 != 0 {
+            return Err(DnsError::Malformed("unknown label type"));
+        }
+
+        // If byte is 0, end of the QNAME hex(00)
+        if byte == 0 {
+            pos += 1;
+            break;
+        }
+
+        pos += 1;
+
+        let label_length = byte as usize;
+
+        let end = pos + label_length;
+
+        let label = buf.get(pos..end).ok_or_else(past_end)?;
+
+        name_len += label_length + 1;
+        if name_len > MAX_NAME_LEN {
+            return Err(DnsError::Malformed("name longer than 255 bytes"));
+        }
+
+        on_label(label);
+        pos += byte as usize;
+    }
+
+    // Return the position we stopped at
+    if jumped {
+        Ok(original_pos)
+    } else {
+        Ok(pos)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::QType;
+    use alloc::string::ToString;
+    use alloc::vec;
+
+    #[test]
+    fn test_to_bytes_refuses_what_cant_go_on_the_wire() {
+        // the lenient From<&str> lets these through, encoding them is where they get caught
+        let long_label = "x".repeat(64);
+        let err = DnsMessage::query(long_label.as_str()).build().to_bytes();
+        assert!(matches!(err, Err(DnsError::InvalidName(_))));
+
+        let mut res = DnsMessage::query("example.com").build();
+        let long_name = ["y".repeat(63).as_str(); 4].join(".");
+        res.add_answer(ResourceRecord::new(
+            "example.com",
+            60,
+            RData::CNAME(long_name),
+        ));
+        assert!(matches!(res.to_bytes(), Err(DnsError::InvalidName(_))));
+
+        // 300 TXT records of 255 bytes each is well over 64K
+        let mut big = DnsMessage::query("example.com").build();
+        for _ in 0..300 {
+            big.add_answer(ResourceRecord::new(
+                "example.com",
+                60,
+                RData::TXT(vec![vec![b'z'; 255]]),
+            ));
+        }
+        assert!(matches!(big.to_bytes(), Err(DnsError::Malformed(_))));
+    }
+
+    #[test]
+    fn test_round_trip_serialization() {
+        let msg = DnsMessage::new("example.com".to_string());
+        let bytes = msg.to_bytes().unwrap();
+        let parsed_msg = DnsMessage::from_bytes(&bytes).unwrap();
+
+        assert_eq!(msg.header.identification, parsed_msg.header.identification);
+        assert_eq!(msg.header.flags, parsed_msg.header.flags);
+        assert_eq!(msg.question().qname, parsed_msg.question().qname);
+        assert_eq!(msg.question().qtype, parsed_msg.question().qtype);
+        assert_eq!(msg.question().qclass, parsed_msg.question().qclass);
+    }
+
+    #[test]
+    fn test_round_trip_with_alloc_only() {
+        // nothing here needs std: the message lives in a Vec and we parse it back. This test
+        // also runs under `cargo test --no-default-features`
+        let msg = DnsMessage::query("no-std.example").build();
+        let bytes: Vec<u8> = msg.to_bytes().unwrap();
+        let parsed = DnsMessage::from_bytes(&bytes).unwrap();
+
+        assert_eq!(parsed.question().qname, "no-std.example");
+        assert_eq!(parsed.to_bytes().unwrap(), bytes);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_json_round_trip() {
+        let query = DnsMessage::query("example.com")
+            .id(7)
+            .dnssec_ok(true)
+            .build();
+        let mut res = DnsMessage::response_to(&query);
+        res.edns = query.edns.clone();
+        res.add_answer(ResourceRecord::new(
+            "example.com",
+            300,
+            RData::MX {
+                preference: 10,
+                exchange: "mail.example.com".into(),
+            },
+        ));
+        res.add_answer(ResourceRecord::new(
+            "example.com",
+            300,
+            RData::A(Ipv4Addr::new(192, 0, 2, 1)),
+        ));
+        let res = DnsMessage::from_bytes(&res.to_bytes().unwrap()).unwrap();
+
+        let json = serde_json::to_value(&res).unwrap();
+        assert_eq!(json["questions"][0]["qname"], "example.com");
+        assert_eq!(
+            json["answers"][0]["data"]["MX"]["exchange"],
+            "mail.example.com"
+        );
+        assert_eq!(json["answers"][1]["data"]["A"], "192.0.2.1");
+        assert_eq!(json["edns"]["dnssec_ok"], true);
+        let back: DnsMessage = serde_json::from_value(json).unwrap();
+        assert_eq!(back, res);
+
+        let bad = serde_json::json!({ "qname": "a..b", "qtype": 1, "qclass": 1 });
+        assert!(serde_json::from_value::<DnsQuestion>(bad).is_err());
+    }
+
+    #[test]
+    fn test_response_round_trip() {
+        let query = DnsMessage::query("www.example.com").build();
+        let mut res = DnsMessage::response_to(&query);
+        res.add_answer(ResourceRecord::new(
+            "www.example.com",
+            300,
+            RData::CNAME("example.com".to_string()),
+        ));
+        res.add_answer(ResourceRecord::new(
+            "example.com",
+            300,
+            RData::A(core::net::Ipv4Addr::new(93, 184, 216, 34)),
+        ));
+        res.add_authority(ResourceRecord::new(
+            "example.com",
+            3600,
+            RData::SOA(crate::Soa {
+                mname: "ns.example.com".to_string(),
+                rname: "hostmaster.example.com".to_string(),
+                serial: 2024010101,
+                refresh: 7200,
+                retry: 900,
+                expire: 1209600,
+                minimum: 300,
+            }),
+        ));
+        res.add_additional(ResourceRecord::new(
+            "example.com",
+            60,
+            RData::TXT(alloc::vec![b"hello".to_vec(), b"world".to_vec()]),
+        ));
+        res.add_additional(ResourceRecord::new(
+            "example.com",
+            60,
+            RData::Unknown(0xFF00, alloc::vec![1, 2, 3]),
+        ));
+
+        let bytes = res.to_bytes().unwrap();
+        assert_eq!(bytes[2] & 0x80, 0x80); // QR
+        assert_eq!(&bytes[4..12], &[0, 1, 0, 2, 0, 1, 0, 2]);
+
+        let parsed = DnsMessage::from_bytes(&bytes).unwrap();
+        assert!(parsed.is_response());
+        assert!(parsed.recursion_desired());
+        // the raw rdata of the CNAME and SOA now has compression pointers in it, the typed data
+        // is where they have to agree
+        let data = |msg: &DnsMessage| -> Vec<RData> {
+            msg.answers
+                .iter()
+                .chain(&msg.authority)
+                .chain(&msg.additional)
+                .map(|rr| rr.data.clone())
+                .collect()
+        };
+        assert_eq!(data(&parsed), data(&res));
+        assert_eq!(parsed.question(), res.question());
+        assert_eq!(parsed.to_bytes().unwrap(), bytes);
+    }
+
+    #[test]
+    fn test_multiple_questions() {
+        let mut query = DnsMessage::query("example.com").id(9).build();
+        query.add_question(DnsQuestion {
+            qname: Name::from("www.example.com"),
+            qtype: 28,
+            qclass: 1,
+        });
+        let bytes = query.to_bytes().unwrap();
+        assert_eq!(&bytes[4..6], &[0, 2]);
+        // the second name is a pointer into the first
+        assert_eq!(bytes.len(), 12 + 13 + 4 + 6 + 4);
+        let parsed = DnsMessage::from_bytes(&bytes).unwrap();
+        assert_eq!(parsed.questions, query.questions);
+        assert_eq!(parsed.question().qname, "example.com");
+        assert_eq!(DnsMessage::response_to(&parsed).questions.len(), 2);
+
+        // none at all, and none come back out
+        query.questions.clear();
+        let bytes = query.to_bytes().unwrap();
+        assert_eq!(&bytes[4..6], &[0, 0]);
+        let parsed = DnsMessage::from_bytes(&bytes).unwrap();
+        assert!(parsed.questions.is_empty());
+        assert_eq!(parsed.question().qtype, 0);
+        assert!(parsed.question().qname.is_root());
+    }
+
+    #[test]
+    fn test_reencoding_drops_stale_compression_pointers() {
+        // parse a response whose CNAME rdata points back at the question, then move that record
+        // into a different message: it has to come out self-contained
+        let query = DnsMessage::query("www.example.com").build();
+        let mut bytes = query.to_bytes().unwrap();
+        bytes[2] |= 0x80;
+        bytes[7] = 1;
+        bytes.extend(&[0xC0, 0x0C, 0, 5, 0, 1, 0, 0, 0, 60, 0, 2, 0xC0, 0x10]);
+        let parsed = DnsMessage::from_bytes(&bytes).unwrap();
+        assert_eq!(
+            parsed.answers[0].data,
+            RData::CNAME("example.com".to_string())
+        );
+
+        let mut other = DnsMessage::response_to(&DnsMessage::query("elsewhere.test").build());
+        other.add_answer(parsed.answers[0].clone());
+        let reparsed = DnsMessage::from_bytes(&other.to_bytes().unwrap()).unwrap();
+        assert_eq!(
+            reparsed.answers[0].data,
+            RData::CNAME("example.com".to_string())
+        );
+    }
+
+    #[test]
+    fn test_dname_response_rewrites_the_query_name() {
+        let query = DnsMessage::query("www.Old.example").build();
+        let mut res = DnsMessage::response_to(&query);
+        res.add_answer(ResourceRecord::new(
+            "old.example",
+            300,
+            RData::DNAME("new.example".to_string()),
+        ));
+        let res = DnsMessage::from_bytes(&res.to_bytes().unwrap()).unwrap();
+
+        assert_eq!(res.answers[0].as_dname(), Some("new.example"));
+        assert_eq!(res.canonical_name(), "www.new.example");
+    }
+
+    #[test]
+    fn test_addrs_only_come_from_the_end_of_the_cname_chain() {
+        let query = DnsMessage::query("www.example.com").build();
+        let mut res = DnsMessage::response_to(&query);
+        res.add_answer(ResourceRecord::new(
+            "www.example.com",
+            60,
+            RData::CNAME("edge.cdn.example".to_string()),
+        ));
+        // an address for the alias itself, and one for some unrelated name
+        res.add_answer(ResourceRecord::new(
+            "www.example.com",
+            60,
+            RData::A([192, 0, 2, 1].into()),
+        ));
+        res.add_answer(ResourceRecord::new(
+            "other.example",
+            60,
+            RData::A([192, 0, 2, 2].into()),
+        ));
+        res.add_answer(ResourceRecord::new(
+            "Edge.CDN.example",
+            60,
+            RData::A([198, 51, 100, 1].into()),
+        ));
+        res.add_answer(ResourceRecord::new(
+            "edge.cdn.example",
+            60,
+            RData::A([198, 51, 100, 2].into()),
+        ));
+        res.add_answer(ResourceRecord::new(
+            "edge.cdn.example",
+            60,
+            RData::AAAA("2001:db8::1".parse().unwrap()),
+        ));
+        let res = DnsMessage::from_bytes(&res.to_bytes().unwrap()).unwrap();
+
+        assert_eq!(
+            res.ipv4_addrs(),
+            vec![
+                Ipv4Addr::new(198, 51, 100, 1),
+                Ipv4Addr::new(198, 51, 100, 2)
+            ]
+        );
+        assert_eq!(
+            res.ipv6_addrs(),
+            vec!["2001:db8::1".parse::<Ipv6Addr>().unwrap()]
+        );
+
+        // without the CNAME the question's own addresses are the answer
+        let mut direct = DnsMessage::response_to(&query);
+        direct.add_answer(ResourceRecord::new(
+            "www.example.com",
+            60,
+            RData::A([192, 0, 2, 1].into()),
+        ));
+        assert_eq!(direct.ipv4_addrs(), vec![Ipv4Addr::new(192, 0, 2, 1)]);
+    }
+
+    #[test]
+    fn test_canonical_name_follows_mixed_chains_and_stops_on_loops() {
+        let query = DnsMessage::query("a.example").build();
+        let mut res = DnsMessage::response_to(&query);
+        res.add_answer(ResourceRecord::new(
+            "a.example",
+            60,
+            RData::CNAME("www.old.example".to_string()),
+        ));
+        res.add_answer(ResourceRecord::new(
+            "old.example",
+            60,
+            RData::DNAME("new.example".to_string()),
+        ));
+        res.add_answer(ResourceRecord::new(
+            "WWW.new.example",
+            60,
+            RData::CNAME("cdn.example".to_string()),
+        ));
+        assert_eq!(res.canonical_name(), "cdn.example");
+
+        // cdn.example pointing back at a.example closes the loop
+        res.add_answer(ResourceRecord::new(
+            "cdn.example",
+            60,
+            RData::CNAME("a.example".to_string()),
+        ));
+        assert_eq!(res.canonical_name(), "a.example");
+    }
+
+    #[test]
+    fn test_query_builder_sets_everything() {
+        let query = DnsMessage::query("version.bind")
+            .qtype(crate::RecordType::TXT)
+            .qclass(crate::DnsClass::CH)
+            .recursion_desired(false)
+            .authentic_data(true)
+            .dnssec_ok(true)
+            .id(0xABCD)
+            .build();
+        let parsed = DnsMessage::from_bytes(&query.to_bytes().unwrap()).unwrap();
+        assert_eq!(parsed.header.identification, 0xABCD);
+        assert_eq!((parsed.question().qtype, parsed.question().qclass), (16, 3));
+        assert_eq!(parsed.question().record_type(), crate::RecordType::TXT);
+        assert_eq!(parsed.question().class(), crate::DnsClass::CH);
+        assert_eq!(parsed.opcode(), crate::Opcode::Query);
+        let flags = parsed.flags();
+        assert!(!flags.rd && flags.ad && !flags.cd && !flags.qr);
+        let edns = parsed.edns.unwrap();
+        assert!(edns.dnssec_ok);
+        assert_eq!(edns.udp_payload, 1232);
+
+        // DO goes into an OPT record that's already there rather than replacing it
+        let query = DnsMessage::query("example.com")
+            .edns(Edns::new(4096))
+            .dnssec_ok(true)
+            .build();
+        let edns = query.edns.unwrap();
+        assert!(edns.dnssec_ok);
+        assert_eq!(edns.udp_payload, 4096);
+    }
+
+    #[test]
+    fn test_checking_disabled_and_authentic_data() {
+        let query = DnsMessage::query("example.com")
+            .checking_disabled(true)
+            .build();
+        assert!(query.checking_disabled());
+        // CD is bit 4 of the flags word, in the second flags byte
+        assert_eq!(query.to_bytes().unwrap()[3] & 0x10, 0x10);
+        assert_eq!(
+            DnsMessage::new("example.com".to_string())
+                .to_bytes()
+                .unwrap()[3]
+                & 0x10,
+            0
+        );
+
+        // a validating resolver answers with AD (bit 5) set
+        let mut res = DnsMessage::response_to(&query);
+        res.update_flags(|flags| {
+            flags.ra = true;
+            flags.ad = true;
+        });
+        let bytes = res.to_bytes().unwrap();
+        assert_eq!(bytes[3] & 0x20, 0x20);
+
+        let parsed = DnsMessage::from_bytes(&bytes).unwrap();
+        assert!(parsed.authentic_data());
+        assert!(parsed.flags().ra);
+        assert!(!DnsMessage::from_bytes(&query.to_bytes().unwrap())
+            .unwrap()
+            .authentic_data());
+    }
+
+    #[test]
+    fn test_names_are_compressed() {
+        let query = DnsMessage::query("www.Example.com").build();
+        let mut res = DnsMessage::response_to(&query);
+        res.add_answer(ResourceRecord::new(
+            "www.example.com",
+            60,
+            RData::CNAME("example.com".to_string()),
+        ));
+        res.add_answer(ResourceRecord::new(
+            "_sip._udp.example.com",
+            60,
+            RData::SRV(crate::SrvRecord {
+                priority: 0,
+                weight: 0,
+                port: 5060,
+                target: "sip.example.com".to_string(),
+            }),
+        ));
+        let bytes = res.to_bytes().unwrap();
+        let (parsed, offsets) = DnsMessage::from_bytes_with_offsets(&bytes).unwrap();
+
+        // the CNAME's owner is the question's name whatever the case, and its target the tail
+        // of it: a pointer each
+        let cname = &bytes[offsets.answers[0].clone()];
+        assert_eq!(&cname[..2], &[0xC0, 12]);
+        assert_eq!(&cname[10..], &[0, 2, 0xC0, 16]);
+        // SRV's owner shares example.com with the question, its target stays whole (RFC 3597)
+        let srv = &bytes[offsets.answers[1].clone()];
+        assert_eq!(&srv[..12], b"\x04_sip\x04_udp\xC0\x10");
+        assert!(srv.ends_with(b"\x03sip\x07example\x03com\x00"));
+
+        // the pointer brings the question's spelling along, which is still the same name
+        assert_eq!(
+            parsed.answers[0].data,
+            RData::CNAME("Example.com".to_string())
+        );
+        assert_eq!(parsed.answers[1].data, res.answers[1].data);
+    }
+
+    #[test]
+    fn test_record_offsets() {
+        let query = DnsMessage::query("example.com").build();
+        let mut res = DnsMessage::response_to(&query);
+        res.add_answer(ResourceRecord::new(
+            "example.com",
+            60,
+            RData::A(core::net::Ipv4Addr::new(10, 0, 0, 1)),
+        ));
+        res.add_answer(ResourceRecord::new(
+            "example.com",
+            60,
+            RData::A(core::net::Ipv4Addr::new(10, 0, 0, 2)),
+        ));
+        let bytes = res.to_bytes().unwrap();
+        let (parsed, offsets) = DnsMessage::from_bytes_with_offsets(&bytes).unwrap();
+
+        // header is 12 bytes, then [7]example[3]com[0] + type + class = 17 bytes
+        assert_eq!(offsets.questions, alloc::vec![12..29]);
+        // each answer: a 2 byte pointer back to the question's name, 10 bytes of
+        // type/class/ttl/rdlength, 4 bytes of address
+        assert_eq!(offsets.answers, alloc::vec![29..45, 45..61]);
+        assert_eq!(offsets.answers[1].end, bytes.len());
+        assert!(offsets.authority.is_empty() && offsets.additional.is_empty());
+        assert_eq!(&bytes[offsets.answers[1].end - 4..], &[10, 0, 0, 2]);
+
+        assert_eq!(parsed, DnsMessage::from_bytes(&bytes).unwrap());
+    }
+
+    #[test]
+    fn test_any_query() {
+        let msg = DnsMessage::query("example.com").qtype(QType::Any).build();
+        let bytes = msg.to_bytes().unwrap();
+        assert_eq!(&bytes[bytes.len() - 4..], &[0, 255, 0, 1]);
+    }
+
+    #[test]
+    fn test_recursion_desired_flag() {
+        let rd_on = DnsMessage::query("example.com").build();
+        let rd_off = DnsMessage::query("example.com")
+            .recursion_desired(false)
+            .build();
+
+        assert!(rd_on.recursion_desired());
+        assert!(!rd_off.recursion_desired());
+
+        // RD lives in the low bit of the first flags byte (byte 2 of the header)
+        assert_eq!(rd_on.to_bytes().unwrap()[2], 0x01);
+        assert_eq!(rd_off.to_bytes().unwrap()[2], 0x00);
+
+        let mut msg = DnsMessage::new("example.com".to_string());
+        msg.set_recursion_desired(false);
+        assert_eq!(msg.header.flags, 0x0000);
+        msg.set_recursion_desired(true);
+        assert_eq!(msg.header.flags, 0x0100);
+    }
+
+    #[test]
+    fn test_parse_qname_basic() {
+        // example.com encoded as [7]example[3]com[0]
+        let buf = [
+            7u8, b'e', b'x', b'a', b'm', b'p', b'l', b'e', 3, b'c', b'o', b'm', 0,
+        ];
+        let (qname, pos) = parse_qname(&buf, 0).unwrap();
+        assert_eq!(qname, "example.com");
+        assert_eq!(pos, buf.len());
+    }
+
+    #[test]
+    fn test_binary_labels_round_trip() {
+        // a label holding a dot and a zero byte, under com
+        let wire = b"\x05a.b\x00c\x03com\x00";
+        let (qname, _) = parse_qname(wire, 0).unwrap();
+        assert_eq!(qname, "a\\.b\\000c.com");
+
+        let mut bytes = Vec::new();
+        write_qname(&mut bytes, &qname).unwrap();
+        assert_eq!(bytes, wire);
+
+        // and the same through a whole query
+        let query = DnsMessage::query(qname.clone()).build();
+        let parsed = DnsMessage::from_bytes(&query.to_bytes().unwrap()).unwrap();
+        assert_eq!(parsed.question().qname.as_str(), qname);
+    }
+
+    #[test]
+    fn test_parse_qname_with_pointer() {
+        // Buffer layout:
+        // 0..12: 7 'e' 'x' 'a' 'm' 'p' 'l' 'e' 3 'c' 'o' 'm' 0   (example.com)
+        // 12..16: some filler bytes (x, a, c, ... )
+        // 16: pointer 0xC000 (11000000 00000000) pointing to offset 0, i.e. "example.com"
+        // After pointer comes some bytes representing "reachhere" (just filler)
+        let buf = [
+            7u8, b'e', b'x', b'a', b'm', b'p', b'l', b'e', 3, b'c', b'o', b'm', 0, b'x', b'a',
+            b'c', 0xC0, 0x00, // pointer to offset 0 ("example.com")
+            b'r', b'e', b'a', b'c', b'h', b'h', b'e', b'r', b'e',
+        ];
+
+        let (qname, pos) = parse_qname(&buf, 16).unwrap();
+        assert_eq!(qname, "example.com");
+        assert_eq!(pos, 18); // pointer consumes 2 bytes
+    }
+
+    // inputs that used to panic (or spin forever) before the parser stopped trusting the packet
+    #[test]
+    fn test_malformed_input_is_an_error_not_a_panic() {
+        let header = |qd: u8, an: u8| vec![0x12, 0x34, 0x81, 0x80, 0, qd, 0, an, 0, 0, 0, 0];
+        let mut cases: Vec<Vec<u8>> = vec![
+            Vec::new(),
+            vec![0x12, 0x34, 0x81],
+            header(1, 0),                 // says it has a question, doesn't
+            header(0, 200),               // same for 200 answers
+            vec![0; MAX_MESSAGE_LEN + 1], // too big to be DNS at all
+        ];
+        // a pointer to itself, and two names pointing at each other
+        let mut self_loop = header(1, 0);
+        self_loop.extend([0xC0, 12, 0, 1, 0, 1]);
+        cases.push(self_loop);
+        let mut ping_pong = header(1, 0);
+        ping_pong.extend([1, b'a', 0xC0, 17, 0, 1, 0, 1, 1, b'b', 0xC0, 12]);
+        cases.push(ping_pong);
+        // label type 01, a label running off the end, and a name of 300 bytes
+        let mut odd_label = header(1, 0);
+        odd_label.extend([0x40, 0, 0, 1, 0, 1]);
+        cases.push(odd_label);
+        let mut cut_label = header(1, 0);
+        cut_label.extend([20, b'a', b'b']);
+        cases.push(cut_label);
+        let mut long_name = header(1, 0);
+        for _ in 0..5 {
+            long_name.push(60);
+            long_name.extend([b'x'; 60]);
+        }
+        long_name.extend([0, 0, 1, 0, 1]);
+        cases.push(long_name);
+        // a record owned by the root, with the rdata of an unknown type holding 300 pointers
+        // in a row, each to the one before it, and then a record whose name is the last one
+        let mut chained = header(0, 2);
+        chained.extend([0, 0xFF, 0, 0, 1, 0, 0, 0, 60, 0x02, 0x58]);
+        chained.extend([0xC0, 12]);
+        for i in 1..300u16 {
+            chained.extend((0xC000 | (23 + 2 * (i - 1))).to_be_bytes());
+        }
+        chained.extend((0xC000 | (23 + 2 * 299u16)).to_be_bytes());
+        chained.extend([0, 1, 0, 1, 0, 0, 0, 60, 0, 0]);
+        cases.push(chained);
+        // a record whose rdlength goes past the end
+        let mut big_rdlength = header(0, 1);
+        big_rdlength.extend([0, 0, 1, 0, 1, 0, 0, 0, 60, 0xFF, 0xFF, 1, 2, 3, 4]);
+        cases.push(big_rdlength);
+
+        for case in cases {
+            assert!(
+                matches!(DnsMessage::from_bytes(&case), Err(DnsError::Malformed(_))),
+                "{:?} was accepted",
+                case
+            );
+        }
+    }
+
+    // a poor man's fuzzer for `cargo test`: every prefix of a real response, and a few thousand
+    // random byte flips of it, have to come back as Ok or Err without panicking. What parses has
+    // to encode to something that parses again and encodes to the very same bytes (the
+    // round_trip fuzz target checks the same)
+    #[test]
+    fn test_truncated_and_mutated_packets_never_panic() {
+        let query = DnsMessage::query("www.example.com").build();
+        let mut res = DnsMessage::response_to(&query);
+        res.add_answer(ResourceRecord::new(
+            "www.example.com",
+            60,
+            RData::CNAME("example.com".to_string()),
+        ));
+        res.add_answer(ResourceRecord::new(
+            "example.com",
+            60,
+            RData::MX {
+                preference: 10,
+                exchange: "mail.example.com".to_string(),
+            },
+        ));
+        res.add_answer(ResourceRecord::new(
+            "example.com",
+            60,
+            RData::TXT(vec![b"v=spf1 -all".to_vec()]),
+        ));
+        let packet = res.to_bytes().unwrap();
+
+        for len in 0..packet.len() {
+            let _ = DnsMessage::from_bytes(&packet[..len]);
+        }
+
+        let mut seed: u32 = 0x2545_f491;
+        let mut next = || {
+            seed ^= seed << 13;
+            seed ^= seed >> 17;
+            seed ^= seed << 5;
+            seed as usize
+        };
+        for _ in 0..5000 {
+            let mut mutated = packet.clone();
+            for _ in 0..1 + next() % 4 {
+                let at = next() % mutated.len();
+                mutated[at] = next() as u8;
+            }
+            let Ok(msg) = DnsMessage::from_bytes(&mutated) else {
+                continue;
+            };
+            if let Ok(bytes) = msg.to_bytes() {
+                let again = DnsMessage::from_bytes(&bytes).unwrap();
+                assert_eq!(again.to_bytes().unwrap(), bytes, "{:?}", mutated);
+            }
+        }
+    }
+}
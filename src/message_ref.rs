@@ -0,0 +1,536 @@
+// a message read in place: DnsMessageRef borrows the packet and hands out its questions and
+// records as views into it, names still compressed and rdata as the raw bytes. Nothing is
+// copied or allocated until asked for, which is what a busy server wants when all it looks at
+// is the question and a few header bits.
+//
+// Parsing checks the whole packet up front, with the same rules as DnsMessage::from_bytes (every
+// name walked, pointers and all, and every record inside the message), so walking the sections
+// afterwards can't go wrong. Only the rdata is left undecoded, RecordRef::data does that. And
+// to_message turns the lot into a DnsMessage, which is how from_bytes itself works
+use alloc::vec::Vec;
+use core::fmt;
+
+use crate::message::{walk_name, RecordOffsets, MAX_MESSAGE_LEN};
+use crate::name::escape_label;
+use crate::rdata::TYPE_OPT;
+use crate::ResourceRecord;
+use crate::{DnsError, DnsFlags, DnsHeader, DnsMessage, DnsQuestion, Edns, Name, RData};
+
+#[derive(Debug, Clone)]
+pub struct DnsMessageRef<'a> {
+    buf: &'a [u8],
+    pub header: DnsHeader,
+    // where each section starts: questions, answers, authority, additional
+    sections: [usize; 4],
+}
+
+// a name in the packet, wherever its labels and pointers lead
+#[derive(Clone, Copy)]
+pub struct NameRef<'a> {
+    buf: &'a [u8],
+    pos: usize,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct QuestionRef<'a> {
+    pub qname: NameRef<'a>,
+    pub qtype: u16,
+    pub qclass: u16,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct RecordRef<'a> {
+    buf: &'a [u8],
+    pub name: NameRef<'a>,
+    pub rr_type: u16,
+    pub class: u16,
+    pub ttl: u32,
+    // the rdata exactly as it is in the packet, compression pointers included
+    pub rdata: &'a [u8],
+    rdata_pos: usize,
+}
+
+impl<'a> DnsMessageRef<'a> {
+    // fails for anything DnsMessage::from_bytes would refuse, save for rdata that doesn't decode
+    pub fn parse(buf: &'a [u8]) -> Result<Self, DnsError> {
+        if buf.len() < 12 {
+            return Err(DnsError::Malformed("shorter than a DNS header"));
+        }
+        // the TCP length prefix is a u16, nothing bigger can be a DNS message
+        if buf.len() > MAX_MESSAGE_LEN {
+            return Err(DnsError::Malformed("bigger than a DNS message can be"));
+        }
+        let count = |at: usize| u16::from_be_bytes([buf[at], buf[at + 1]]);
+        let header = DnsHeader {
+            identification: count(0),
+            flags: count(2),
+            no_of_questions: count(4),
+            no_of_answers_rr: count(6),
+            no_of_authority_rr: count(8),
+            no_of_additional_rr: count(10),
+        };
+
+        let mut sections = [12; 4];
+        let mut pos = walk_questions(buf, 12, header.no_of_questions)?;
+        let counts = [
+            header.no_of_answers_rr,
+            header.no_of_authority_rr,
+            header.no_of_additional_rr,
+        ];
+        for (i, count) in counts.into_iter().enumerate() {
+            sections[i + 1] = pos;
+            for _ in 0..count {
+                pos = walk_record(buf, pos)?.1;
+            }
+        }
+        Ok(DnsMessageRef {
+            buf,
+            header,
+            sections,
+        })
+    }
+
+    pub fn flags(&self) -> DnsFlags {
+        DnsFlags::from_u16(self.header.flags)
+    }
+
+    pub fn is_response(&self) -> bool {
+        self.flags().qr
+    }
+
+    pub fn questions(&self) -> Questions<'a> {
+        Questions {
+            buf: self.buf,
+            pos: self.sections[0],
+            left: self.header.no_of_questions,
+        }
+    }
+
+    // the first question, the way DnsMessage::question has it but None when there isn't one
+    pub fn question(&self) -> Option<QuestionRef<'a>> {
+        self.questions().next()
+    }
+
+    pub fn answers(&self) -> Records<'a> {
+        self.records(1, self.header.no_of_answers_rr)
+    }
+
+    pub fn authority(&self) -> Records<'a> {
+        self.records(2, self.header.no_of_authority_rr)
+    }
+
+    // every additional record, the OPT record too if there is one. DnsMessage takes that one
+    // out into `edns`, here it's just another record
+    pub fn additional(&self) -> Records<'a> {
+        self.records(3, self.header.no_of_additional_rr)
+    }
+
+    fn records(&self, section: usize, count: u16) -> Records<'a> {
+        Records {
+            buf: self.buf,
+            pos: self.sections[section],
+            left: count,
+        }
+    }
+
+    // everything copied out into an owned DnsMessage, rdata decoded. The same as
+    // DnsMessage::from_bytes on the packet
+    pub fn to_message(&self) -> Result<DnsMessage, DnsError> {
+        self.to_message_with_offsets().map(|(msg, _)| msg)
+    }
+
+    pub(crate) fn to_message_with_offsets(&self) -> Result<(DnsMessage, RecordOffsets), DnsError> {
+        let mut offsets = RecordOffsets::default();
+        let mut questions = Vec::new();
+        let mut pos = self.sections[0];
+        for question in self.questions() {
+            let end = skip_name(self.buf, pos) + 4;
+            offsets.questions.push(pos..end);
+            pos = end;
+            questions.push(question.to_question());
+        }
+
+        let mut sections: [Vec<ResourceRecord>; 3] = Default::default();
+        let mut edns = None;
+        let counts = [
+            self.header.no_of_answers_rr,
+            self.header.no_of_authority_rr,
+            self.header.no_of_additional_rr,
+        ];
+        for (i, count) in counts.into_iter().enumerate() {
+            let mut pos = self.sections[i + 1];
+            for _ in 0..count {
+                let (rr, end) = record_at(self.buf, pos);
+                let range = pos..end;
+                pos = end;
+                let rr = rr.to_record()?;
+                match i {
+                    0 => offsets.answers.push(range),
+                    1 => offsets.authority.push(range),
+                    _ if rr.rr_type == TYPE_OPT => {
+                        if edns.is_some() {
+                            return Err(DnsError::Malformed("more than one OPT record"));
+                        }
+                        edns = Some(Edns::from_record(&rr)?);
+                        offsets.edns = Some(range);
+                        continue;
+                    }
+                    _ => offsets.additional.push(range),
+                }
+                sections[i].push(rr);
+            }
+        }
+
+        let [answers, authority, additional] = sections;
+        let msg = DnsMessage {
+            header: self.header.clone(),
+            questions,
+            answers,
+            authority,
+            additional,
+            edns,
+        };
+        Ok((msg, offsets))
+    }
+}
+
+impl<'a> NameRef<'a> {
+    // the labels, leftmost first, each as the raw bytes in the packet
+    pub fn labels(&self) -> Labels<'a> {
+        Labels {
+            buf: self.buf,
+            pos: self.pos,
+        }
+    }
+
+    pub fn is_root(&self) -> bool {
+        self.labels().next().is_none()
+    }
+
+    pub fn to_name(&self) -> Name {
+        // the name was checked when the message was parsed, this can't fail
+        Name::from_wire(self.buf, self.pos).map_or_else(|_| Name::root(), |(name, _)| name)
+    }
+}
+
+// names compare without regard to ASCII case, the way Name does
+impl PartialEq<Name> for NameRef<'_> {
+    fn eq(&self, other: &Name) -> bool {
+        let theirs = other.labels();
+        self.labels().count() == theirs.len()
+            && self
+                .labels()
+                .zip(&theirs)
+                .all(|(ours, theirs)| ours.eq_ignore_ascii_case(theirs))
+    }
+}
+
+impl PartialEq for NameRef<'_> {
+    fn eq(&self, other: &NameRef<'_>) -> bool {
+        self.labels().count() == other.labels().count()
+            && self
+                .labels()
+                .zip(other.labels())
+                .all(|(ours, theirs)| ours.eq_ignore_ascii_case(theirs))
+    }
+}
+
+// like Name's: escaped labels, no trailing dot, "." for the root
+impl fmt::Display for NameRef<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_root() {
+            return f.write_str(".");
+        }
+        for (i, label) in self.labels().enumerate() {
+            if i > 0 {
+                f.write_str(".")?;
+            }
+            f.write_str(&escape_label(label))?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Debug for NameRef<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "NameRef({})", self)
+    }
+}
+
+impl QuestionRef<'_> {
+    pub fn to_question(&self) -> DnsQuestion {
+        DnsQuestion {
+            qname: self.qname.to_name(),
+            qtype: self.qtype,
+            qclass: self.qclass,
+        }
+    }
+}
+
+impl RecordRef<'_> {
+    // the rdata decoded, the way DnsMessage has it in ResourceRecord::data. Names in it can be
+    // pointers to anywhere before, which is why this needs the whole packet and not just `rdata`
+    pub fn data(&self) -> Result<RData, DnsError> {
+        RData::decode(
+            self.rr_type,
+            self.buf,
+            self.rdata_pos,
+            self.rdata.len() as u16,
+        )
+    }
+
+    pub fn to_record(&self) -> Result<ResourceRecord, DnsError> {
+        Ok(ResourceRecord {
+            name: self.name.to_name(),
+            rr_type: self.rr_type,
+            class: self.class,
+            ttl: self.ttl,
+            rdlength: self.rdata.len() as u16,
+            rdata: self.rdata.to_vec(),
+            data: self.data()?,
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Questions<'a> {
+    buf: &'a [u8],
+    pos: usize,
+    left: u16,
+}
+
+impl<'a> Iterator for Questions<'a> {
+    type Item = QuestionRef<'a>;
+
+    fn next(&mut self) -> Option<QuestionRef<'a>> {
+        if self.left == 0 {
+            return None;
+        }
+        self.left -= 1;
+        let qname = NameRef {
+            buf: self.buf,
+            pos: self.pos,
+        };
+        let pos = skip_name(self.buf, self.pos);
+        self.pos = pos + 4;
+        Some(QuestionRef {
+            qname,
+            qtype: u16_at(self.buf, pos),
+            qclass: u16_at(self.buf, pos + 2),
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Records<'a> {
+    buf: &'a [u8],
+    pos: usize,
+    left: u16,
+}
+
+impl<'a> Iterator for Records<'a> {
+    type Item = RecordRef<'a>;
+
+    fn next(&mut self) -> Option<RecordRef<'a>> {
+        if self.left == 0 {
+            return None;
+        }
+        self.left -= 1;
+        let (rr, next) = record_at(self.buf, self.pos);
+        self.pos = next;
+        Some(rr)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Labels<'a> {
+    buf: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Iterator for Labels<'a> {
+    type Item = &'a [u8];
+
+    fn next(&mut self) -> Option<&'a [u8]> {
+        loop {
+            let byte = *self.buf.get(self.pos)?;
+            if byte & 0b1100_0000 == 0b1100_0000 {
+                self.pos = (u16_at(self.buf, self.pos) & 0x3FFF) as usize;
+                continue;
+            }
+            if byte == 0 {
+                return None;
+            }
+            let label = self.buf.get(self.pos + 1..self.pos + 1 + byte as usize)?;
+            self.pos += 1 + byte as usize;
+            return Some(label);
+        }
+    }
+}
+
+// the end of `count` questions from `pos`, each checked
+fn walk_questions(buf: &[u8], mut pos: usize, count: u16) -> Result<usize, DnsError> {
+    for _ in 0..count {
+        pos = walk_name(buf, pos, |_| {})? + 4;
+        if pos > buf.len() {
+            return Err(DnsError::Malformed(
+                "message ends in the middle of a record",
+            ));
+        }
+    }
+    Ok(pos)
+}
+
+// one record at `pos`, checked, and where the next one starts
+fn walk_record(buf: &[u8], pos: usize) -> Result<(RecordRef<'_>, usize), DnsError> {
+    let fixed = walk_name(buf, pos, |_| {})?;
+    if fixed + 10 > buf.len() {
+        return Err(DnsError::Malformed(
+            "message ends in the middle of a record",
+        ));
+    }
+    let rdlength = u16_at(buf, fixed + 8) as usize;
+    if fixed + 10 + rdlength > buf.len() {
+        return Err(DnsError::Malformed(
+            "rdata runs past the end of the message",
+        ));
+    }
+    Ok(record_at(buf, pos))
+}
+
+// a record somewhere walk_record has already been
+fn record_at(buf: &[u8], pos: usize) -> (RecordRef<'_>, usize) {
+    let fixed = skip_name(buf, pos);
+    let rdata_pos = fixed + 10;
+    let rdlength = u16_at(buf, fixed + 8) as usize;
+    let end = (rdata_pos + rdlength).min(buf.len());
+    let rr = RecordRef {
+        buf,
+        name: NameRef { buf, pos },
+        rr_type: u16_at(buf, fixed),
+        class: u16_at(buf, fixed + 2),
+        ttl: (u16_at(buf, fixed + 4) as u32) << 16 | u16_at(buf, fixed + 6) as u32,
+        rdata: buf.get(rdata_pos.min(end)..end).unwrap_or_default(),
+        rdata_pos,
+    };
+    (rr, end)
+}
+
+// past the name at `pos` without following its pointer, if it ends in one
+fn skip_name(buf: &[u8], mut pos: usize) -> usize {
+    while let Some(&byte) = buf.get(pos) {
+        if byte & 0b1100_0000 == 0b1100_0000 {
+            return pos + 2;
+        }
+        pos += 1;
+        if byte == 0 {
+            break;
+        }
+        pos += byte as usize;
+    }
+    pos
+}
+
+// a big-endian u16 at `pos`, 0 past the end (where walking has already made sure we never look)
+fn u16_at(buf: &[u8], pos: usize) -> u16 {
+    match buf.get(pos..pos + 2) {
+        Some(bytes) => u16::from_be_bytes([bytes[0], bytes[1]]),
+        None => 0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rdata::{TYPE_CNAME, TYPE_MX};
+    use alloc::string::ToString;
+
+    fn response() -> Vec<u8> {
+        let query = DnsMessage::query("www.example.com").id(0x1234).build();
+        let mut res = DnsMessage::response_to(&query);
+        res.add_answer(ResourceRecord::new(
+            "www.example.com",
+            60,
+            RData::CNAME("example.com".to_string()),
+        ));
+        res.add_answer(ResourceRecord::new(
+            "example.com",
+            300,
+            RData::MX {
+                preference: 10,
+                exchange: "mail.example.com".to_string(),
+            },
+        ));
+        res.add_additional(ResourceRecord::new(
+            "mail.example.com",
+            300,
+            RData::A([192, 0, 2, 1].into()),
+        ));
+        res.edns = Some(Edns::new(1232));
+        res.to_bytes().unwrap()
+    }
+
+    #[test]
+    fn test_borrowed_view() {
+        let packet = response();
+        let msg = DnsMessageRef::parse(&packet).unwrap();
+        assert_eq!(msg.header.identification, 0x1234);
+        assert!(msg.is_response());
+
+        let question = msg.question().unwrap();
+        assert_eq!(question.qname.to_string(), "www.example.com");
+        assert!(question.qname == Name::from("WWW.example.COM"));
+        assert_eq!(question.qtype, 1);
+
+        let answers: Vec<RecordRef> = msg.answers().collect();
+        assert_eq!(answers.len(), 2);
+        // the owner is a pointer back to the question
+        assert!(answers[0].name == question.qname);
+        assert_eq!(answers[0].rr_type, TYPE_CNAME);
+        assert_eq!(answers[0].rdata, &[0xC0, 16]);
+        assert_eq!(
+            answers[0].data().unwrap(),
+            RData::CNAME("example.com".to_string())
+        );
+        assert_eq!(answers[1].rr_type, TYPE_MX);
+        assert_eq!(answers[1].ttl, 300);
+        let labels: Vec<&[u8]> = answers[1].name.labels().collect();
+        assert_eq!(labels, [&b"example"[..], &b"com"[..]]);
+        assert_eq!(msg.authority().count(), 0);
+
+        // the OPT record is still among the additional records
+        let types: Vec<u16> = msg.additional().map(|rr| rr.rr_type).collect();
+        assert_eq!(types, [1, TYPE_OPT]);
+        assert!(msg.additional().nth(1).unwrap().name.is_root());
+
+        let owned = msg.to_message().unwrap();
+        assert_eq!(owned, DnsMessage::from_bytes(&packet).unwrap());
+        assert_eq!(owned.edns.unwrap().udp_payload, 1232);
+    }
+
+    #[test]
+    fn test_checked_up_front() {
+        let packet = response();
+        for len in 0..packet.len() {
+            assert!(DnsMessageRef::parse(&packet[..len]).is_err());
+        }
+        // a pointer forwards in the second answer's name is found before anyone looks at it
+        let msg = DnsMessageRef::parse(&packet).unwrap();
+        let mut bad = packet.clone();
+        let second = msg.answers().nth(1).unwrap();
+        let at = second.name.pos;
+        bad[at..at + 2].copy_from_slice(&(0xC000u16 | (packet.len() as u16 - 2)).to_be_bytes());
+        assert!(matches!(
+            DnsMessageRef::parse(&bad),
+            Err(DnsError::Malformed(_))
+        ));
+        // rdata that doesn't decode is only found by whoever decodes it
+        let mut bad = packet.clone();
+        let cname = msg.answers().next().unwrap();
+        bad[cname.rdata_pos] = 0x40;
+        let msg = DnsMessageRef::parse(&bad).unwrap();
+        assert!(msg.answers().next().unwrap().data().is_err());
+        assert!(msg.to_message().is_err());
+        assert_eq!(msg.answers().count(), 2);
+    }
+}
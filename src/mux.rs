@@ -0,0 +1,467 @@
+// many queries in flight at once over a handful of UDP sockets, instead of a socket (and a
+// thread blocked on it) for each. Every query goes out from one of the sockets picked at random
+// (see ports.rs for why), and a thread per socket reads whatever comes back and hands each
+// answer to the query it's for: the one sent from that socket to that server, with that ID and
+// question. Anything else is a stray and gets dropped: a second copy of an answer already
+// handed over, an answer to a query that gave up waiting, or a forgery that got the question
+// wrong. None of those can end a query early, the one we're waiting for can still come in
+// after them. A query stays registered until whoever sent it takes an answer, so one it turns
+// down for reasons of its own (a cookie or the case of the name that don't match) doesn't end
+// it either
+use std::collections::HashMap;
+use std::io;
+use std::net::{SocketAddr, UdpSocket};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex, Weak};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::ports::{bind, SourcePorts};
+use crate::random::random_u64;
+use crate::resolver::{record, Capture, DEFAULT_TIMEOUT};
+use crate::stats::Counters;
+use crate::{DnsError, DnsMessage, DnsMessageRef, DnsQuestion};
+
+// sockets per address family
+pub const DEFAULT_MUX_SOCKETS: usize = 4;
+// how often a reading thread with nothing to read checks whether the mux is still there
+const POLL: Duration = Duration::from_millis(200);
+
+pub struct UdpMux {
+    ports: SourcePorts,
+    timeout: Duration,
+    // bound on first use, the IPv4 ones for IPv4 servers and the IPv6 ones for IPv6 servers
+    v4: Mutex<Vec<MuxSocket>>,
+    v6: Mutex<Vec<MuxSocket>>,
+    shared: Arc<Shared>,
+}
+
+#[derive(Clone)]
+struct MuxSocket {
+    local: SocketAddr,
+    socket: Arc<UdpSocket>,
+    since: Instant,
+    // set once it's been replaced by a socket on a new port. It takes no new queries, and its
+    // thread lets it go when the ones still out on it are done
+    retired: Arc<AtomicBool>,
+}
+
+// what the reading threads get at. They only hold on to it weakly, and stop once the mux is gone
+struct Shared {
+    // the queries waiting for an answer, by the socket they went out on, the server and the ID
+    pending: Mutex<HashMap<Key, Pending>>,
+    // strays are counted here, along with the rest of a Resolver's traffic when it's one of
+    // theirs
+    counters: Arc<Counters>,
+    // every datagram that goes out or comes in is written here as well, strays included
+    capture: Arc<Capture>,
+}
+
+type Key = (SocketAddr, SocketAddr, u16);
+
+struct Pending {
+    question: DnsQuestion,
+    tx: Sender<Vec<u8>>,
+}
+
+// a query out on one of the sockets, and what comes back for it. Dropping it ends the wait:
+// anything for it still queued up, or still to come, is a stray
+pub(crate) struct Waiting<'a> {
+    shared: &'a Shared,
+    key: Key,
+    rx: Receiver<Vec<u8>>,
+}
+
+impl Default for UdpMux {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl UdpMux {
+    pub fn new() -> Self {
+        Self::with_sockets(DEFAULT_MUX_SOCKETS)
+    }
+
+    // `sockets` of them for each address family. More sockets means more source ports for an
+    // attacker to guess, and room for more queries with the same ID to the same server
+    pub fn with_sockets(sockets: usize) -> Self {
+        Self::with_source_ports(SourcePorts {
+            sockets,
+            rebind_after: None,
+        })
+    }
+
+    // as many sockets as `ports` says, moved to new ports as often as it says
+    pub fn with_source_ports(ports: SourcePorts) -> Self {
+        Self::attached(ports, Arc::default(), Arc::default())
+    }
+
+    // a Resolver's, counting its strays and writing to its capture
+    pub(crate) fn attached(
+        ports: SourcePorts,
+        counters: Arc<Counters>,
+        capture: Arc<Capture>,
+    ) -> Self {
+        UdpMux {
+            ports,
+            timeout: DEFAULT_TIMEOUT,
+            v4: Mutex::new(Vec::new()),
+            v6: Mutex::new(Vec::new()),
+            shared: Arc::new(Shared {
+                pending: Mutex::new(HashMap::new()),
+                counters,
+                capture,
+            }),
+        }
+    }
+
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = timeout;
+    }
+
+    pub fn source_ports(&self) -> SourcePorts {
+        self.ports
+    }
+
+    // the datagrams that came in and weren't the answer to anything waiting
+    pub fn strays(&self) -> u64 {
+        self.shared.counters.stray_responses.load(Ordering::Relaxed)
+    }
+
+    // `msg` to `server`, and its answer. Like UdpTransport a truncated answer comes back as it
+    // is, TC set, and it's up to the caller to go again over TCP
+    pub fn exchange(&self, server: SocketAddr, msg: &DnsMessage) -> Result<DnsMessage, DnsError> {
+        let waiting = self.send(server, msg, &msg.to_bytes()?)?;
+        let deadline = Instant::now() + self.timeout;
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            let datagram = waiting.recv(remaining).ok_or(DnsError::Timeout)?;
+            match DnsMessage::from_bytes(&datagram) {
+                Ok(res) => return Ok(res),
+                // the right ID and question in front of junk is no answer either
+                Err(_) => waiting.stray(),
+            }
+        }
+    }
+
+    // sends `query`, which is `msg` encoded, to `server` and registers it for what comes back
+    pub(crate) fn send(
+        &self,
+        server: SocketAddr,
+        msg: &DnsMessage,
+        query: &[u8],
+    ) -> Result<Waiting<'_>, DnsError> {
+        let sockets = self.sockets(&server)?;
+        let id = msg.header.identification;
+        let (tx, rx) = mpsc::channel();
+
+        // a socket picked at random, or the next one after it that doesn't have a query with
+        // this ID out to the server already, otherwise we couldn't tell their answers apart
+        let start = (random_u64() % sockets.len() as u64) as usize;
+        let (key, socket) = {
+            let mut pending = self.shared.pending.lock().unwrap();
+            let free = (0..sockets.len())
+                .map(|i| &sockets[(start + i) % sockets.len()])
+                .map(|socket| ((socket.local, server, id), socket))
+                .find(|(key, socket)| {
+                    !socket.retired.load(Ordering::Acquire) && !pending.contains_key(key)
+                })
+                .map(|(key, socket)| (key, socket.socket.clone()));
+            let (key, socket) = match free {
+                Some(free) => free,
+                // a query of the same ID on every one of them: this one gets a socket to
+                // itself, retired from the start. Opened under the lock, so its thread can't
+                // see it idle before the query is in
+                None => {
+                    let extra = self.open(&server, true)?;
+                    ((extra.local, server, id), extra.socket)
+                }
+            };
+            let question = msg.question().clone();
+            pending.insert(key, Pending { question, tx });
+            (key, socket)
+        };
+        // from here on dropping it unregisters the query, should the send fail
+        let waiting = Waiting {
+            shared: &self.shared,
+            key,
+            rx,
+        };
+        // in the capture first: the answer can be in before send_to has even returned, and
+        // it's written from the reading thread
+        let shared = &self.shared;
+        record(&shared.capture, &shared.counters, key.0, server, query);
+        socket.send_to(query, server)?;
+        Ok(waiting)
+    }
+
+    // the sockets for a query to `server`, bound the first time they're needed and rebound
+    // once they've had their time
+    fn sockets(&self, server: &SocketAddr) -> io::Result<Vec<MuxSocket>> {
+        let mut sockets = match server {
+            SocketAddr::V4(_) => self.v4.lock().unwrap(),
+            SocketAddr::V6(_) => self.v6.lock().unwrap(),
+        };
+        while sockets.len() < self.ports.sockets.max(1) {
+            sockets.push(self.open(server, false)?);
+        }
+        if let Some(after) = self.ports.rebind_after {
+            for socket in sockets.iter_mut().filter(|s| s.since.elapsed() >= after) {
+                let fresh = self.open(server, false)?;
+                socket.retired.store(true, Ordering::Release);
+                *socket = fresh;
+            }
+        }
+        Ok(sockets.clone())
+    }
+
+    // a socket on a random port, and the thread that reads it
+    fn open(&self, server: &SocketAddr, retired: bool) -> io::Result<MuxSocket> {
+        let socket = bind(server)?;
+        socket.set_read_timeout(Some(POLL))?;
+        let socket = MuxSocket {
+            local: socket.local_addr()?,
+            socket: Arc::new(socket),
+            since: Instant::now(),
+            retired: Arc::new(AtomicBool::new(retired)),
+        };
+        let (reading, shared) = (socket.clone(), Arc::downgrade(&self.shared));
+        thread::spawn(move || read_answers(reading, shared));
+        Ok(socket)
+    }
+}
+
+impl Waiting<'_> {
+    // the next datagram from the server with our ID and question, None once `timeout` is up
+    pub(crate) fn recv(&self, timeout: Duration) -> Option<Vec<u8>> {
+        self.rx.recv_timeout(timeout).ok()
+    }
+
+    // what recv handed over wasn't the answer after all
+    pub(crate) fn stray(&self) {
+        Counters::bump(&self.shared.counters.stray_responses);
+    }
+}
+
+impl Drop for Waiting<'_> {
+    fn drop(&mut self) {
+        self.shared.pending.lock().unwrap().remove(&self.key);
+        // the copies that came in after the answer we took, while it was still registered
+        for _ in self.rx.try_iter() {
+            self.stray();
+        }
+    }
+}
+
+// the thread behind each socket
+fn read_answers(socket: MuxSocket, shared: Weak<Shared>) {
+    let mut buf = vec![0; 65535];
+    loop {
+        let received = socket.socket.recv_from(&mut buf);
+        let Some(shared) = shared.upgrade() else {
+            return;
+        };
+        // an error is the poll timeout, or an ICMP error for something we sent earlier (windows
+        // reports those on the next read). Neither is about the queries still waiting
+        if let Ok((size, peer)) = received {
+            let datagram = &buf[..size];
+            record(
+                &shared.capture,
+                &shared.counters,
+                peer,
+                socket.local,
+                datagram,
+            );
+            shared.deliver(socket.local, peer, datagram);
+        }
+        if socket.retired.load(Ordering::Acquire) && shared.done_with(socket.local) {
+            return;
+        }
+    }
+}
+
+impl Shared {
+    fn deliver(&self, local: SocketAddr, peer: SocketAddr, datagram: &[u8]) {
+        // the zero-copy view is enough to tell whether it's for anyone, a stray isn't worth
+        // parsing all of
+        let delivered = DnsMessageRef::parse(datagram).is_ok_and(|view| {
+            let key = (local, peer, view.header.identification);
+            let pending = self.pending.lock().unwrap();
+            pending.get(&key).is_some_and(|waiting| {
+                view.is_response()
+                    && view.question().is_none_or(|q| {
+                        q.qname == waiting.question.qname
+                            && q.qtype == waiting.question.qtype
+                            && q.qclass == waiting.question.qclass
+                    })
+                    // still registered, so still listening
+                    && waiting.tx.send(datagram.to_vec()).is_ok()
+            })
+        });
+        if !delivered {
+            Counters::bump(&self.counters.stray_responses);
+        }
+    }
+
+    // whether nothing is waiting on the socket any more. Checked under the same lock send
+    // registers queries under, so a retired socket can't pick up a new one after it
+    fn done_with(&self, local: SocketAddr) -> bool {
+        let pending = self.pending.lock().unwrap();
+        !pending.keys().any(|key| key.0 == local)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ports::FIRST_PORT;
+    use crate::test_util::response_with_rdata;
+    use std::collections::HashSet;
+    use std::net::Ipv4Addr;
+
+    // a UDP server that takes `count` queries before answering any, then answers them last to
+    // first: each one twice, after an answer with the right ID to a different question
+    fn mock_slow_server(count: usize) -> SocketAddr {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = socket.local_addr().unwrap();
+        thread::spawn(move || {
+            let mut queries = Vec::new();
+            let mut buf = [0u8; 512];
+            for _ in 0..count {
+                let (size, client) = socket.recv_from(&mut buf).unwrap();
+                queries.push((buf[..size].to_vec(), client));
+            }
+            for (query, client) in queries.iter().rev() {
+                let last = query[12] + 1;
+                let res = response_with_rdata(query, 1, &[192, 0, 2, last]);
+                let mut forged = DnsMessage::from_bytes(&res).unwrap();
+                forged.questions[0].qname = "forged.example".into();
+                socket.send_to(&forged.to_bytes().unwrap(), client).unwrap();
+                socket.send_to(&res, client).unwrap();
+                socket.send_to(&res, client).unwrap();
+            }
+        });
+        addr
+    }
+
+    // a UDP server that answers `count` queries, and the source port of each one
+    fn mock_port_server(count: usize) -> (SocketAddr, Receiver<u16>) {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = socket.local_addr().unwrap();
+        let (tx, rx) = mpsc::channel();
+        thread::spawn(move || {
+            let mut buf = [0u8; 512];
+            for _ in 0..count {
+                let (size, client) = socket.recv_from(&mut buf).unwrap();
+                let res = response_with_rdata(&buf[..size], 1, &[192, 0, 2, 1]);
+                socket.send_to(&res, client).unwrap();
+                tx.send(client.port()).unwrap();
+            }
+        });
+        (addr, rx)
+    }
+
+    #[test]
+    fn test_concurrent_queries() {
+        let server = mock_slow_server(6);
+        let mux = UdpMux::with_sockets(2);
+        thread::scope(|scope| {
+            for len in 1..=6 {
+                let mux = &mux;
+                scope.spawn(move || {
+                    // the first label's length tells the server which query it's answering
+                    let name = format!("{}.example", "a".repeat(len));
+                    let query = DnsMessage::query(name).build();
+                    let res = mux.exchange(server, &query).unwrap();
+                    assert_eq!(res.header.identification, query.header.identification);
+                    let last = len as u8 + 1;
+                    assert_eq!(res.ipv4_addrs(), [Ipv4Addr::new(192, 0, 2, last)]);
+                });
+            }
+        });
+        assert_eq!(mux.v4.lock().unwrap().len(), 2);
+        assert!(mux.shared.pending.lock().unwrap().is_empty());
+
+        // every forgery and every duplicate dropped, the duplicates maybe still on their way
+        thread::sleep(Duration::from_millis(100));
+        assert_eq!(mux.strays(), 12);
+    }
+
+    #[test]
+    fn test_same_id_on_every_socket() {
+        let server = mock_slow_server(2);
+        let mux = UdpMux::with_sockets(1);
+        thread::scope(|scope| {
+            for len in 1..=2 {
+                let mux = &mux;
+                scope.spawn(move || {
+                    let name = format!("{}.example", "a".repeat(len));
+                    let mut query = DnsMessage::query(name).build();
+                    query.header.identification = 0x5555;
+                    let res = mux.exchange(server, &query).unwrap();
+                    let last = len as u8 + 1;
+                    assert_eq!(res.ipv4_addrs(), [Ipv4Addr::new(192, 0, 2, last)]);
+                });
+            }
+        });
+        // the second one went out from a socket of its own, which isn't kept
+        assert_eq!(mux.v4.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_timeout() {
+        let silent = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let mut mux = UdpMux::new();
+        mux.set_timeout(Duration::from_millis(200));
+        let query = DnsMessage::query("example.com").build();
+        let server = silent.local_addr().unwrap();
+        assert!(matches!(
+            mux.exchange(server, &query),
+            Err(DnsError::Timeout)
+        ));
+        assert!(mux.shared.pending.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_rotates_over_random_ports() {
+        let (server, ports) = mock_port_server(64);
+        let mux = UdpMux::with_source_ports(SourcePorts {
+            sockets: 4,
+            rebind_after: None,
+        });
+        for _ in 0..64 {
+            let query = DnsMessage::query("example.com").build();
+            mux.exchange(server, &query).unwrap();
+        }
+        let ports: HashSet<u16> = ports.iter().take(64).collect();
+        assert!(ports.iter().all(|&port| port >= FIRST_PORT));
+        // four sockets, and 64 picks missing one of them is a 1 in 10^7 fluke
+        assert_eq!(ports.len(), 4);
+        assert!(mux.v6.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_rebinds() {
+        let (server, ports) = mock_port_server(5);
+        let mux = UdpMux::with_source_ports(SourcePorts {
+            sockets: 1,
+            rebind_after: Some(Duration::ZERO),
+        });
+        for _ in 0..5 {
+            let query = DnsMessage::query("example.com").build();
+            mux.exchange(server, &query).unwrap();
+        }
+        // five random ports out of 16K all the same would be quite something
+        assert!(ports.iter().take(5).collect::<HashSet<_>>().len() > 1);
+
+        let (server, ports) = mock_port_server(2);
+        let kept = UdpMux::with_source_ports(SourcePorts::default());
+        for _ in 0..2 {
+            let query = DnsMessage::query("example.com").build();
+            kept.exchange(server, &query).unwrap();
+        }
+        assert_eq!(ports.iter().take(2).collect::<HashSet<_>>().len(), 1);
+    }
+}
@@ -0,0 +1,150 @@
+// MX lookups (RFC 5321 section 5.1): where mail for a domain goes, as a list of mail exchangers
+// to try in order of preference, lowest first. Servers usually send the exchangers' addresses
+// along in the additional section.
+//
+// A domain without MX records gets an empty list here. Mail to it still goes to the domain's own
+// address (the "implicit MX"), but that's the mailer's call, not ours. The null MX of RFC 7505,
+// a single "." exchanger saying the domain takes no mail at all, is an empty list too
+use std::net::IpAddr;
+
+use crate::srv::{addresses, lookup_addrs};
+use crate::{DnsError, Name, RData, RecordType, Resolver, RCODE_NXDOMAIN};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MailExchanger {
+    pub preference: u16,
+    pub exchange: Name,
+    // the exchanger's addresses, empty when nobody looked (or it has none)
+    pub addrs: Vec<IpAddr>,
+}
+
+// see Resolver::lookup_mx. With `resolve`, an exchanger that came without addresses gets its A
+// and AAAA looked up
+pub(crate) fn lookup(
+    resolver: &Resolver,
+    domain: &str,
+    resolve: bool,
+) -> Result<Vec<MailExchanger>, DnsError> {
+    let res = resolver.lookup(domain, RecordType::MX)?.response;
+    match res.rcode() {
+        0 | RCODE_NXDOMAIN => {}
+        _ => return Err(DnsError::from_response(&res)),
+    }
+    let owner = res.canonical_name();
+    let mut exchangers: Vec<MailExchanger> = res
+        .answers
+        .iter()
+        .filter(|rr| rr.name == owner)
+        .filter_map(|rr| match &rr.data {
+            RData::MX {
+                preference,
+                exchange,
+            } => Some(MailExchanger {
+                preference: *preference,
+                exchange: Name::from(exchange.as_str()),
+                addrs: Vec::new(),
+            }),
+            _ => None,
+        })
+        .collect();
+    if let [only] = exchangers.as_slice() {
+        if only.exchange.is_root() {
+            return Ok(Vec::new());
+        }
+    }
+    // stable, so exchangers of the same preference stay in the order the server gave them
+    exchangers.sort_by_key(|mx| mx.preference);
+
+    for mx in &mut exchangers {
+        mx.addrs = addresses(&res.additional, &mx.exchange);
+        if mx.addrs.is_empty() && resolve {
+            mx.addrs = lookup_addrs(resolver, &mx.exchange);
+        }
+    }
+    Ok(exchangers)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rdata::{TYPE_A, TYPE_MX};
+    use crate::test_util::mock_udp_server;
+    use crate::{DnsMessage, ResourceRecord};
+
+    fn mx(preference: u16, exchange: &str) -> RData {
+        RData::MX {
+            preference,
+            exchange: exchange.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_lookup_mx() {
+        // the backup exchanger comes without glue, which costs an A and an AAAA query when we
+        // resolve
+        let server = mock_udp_server(4, |query| {
+            let query = DnsMessage::from_bytes(query).unwrap();
+            let mut res = DnsMessage::response_to(&query);
+            let qname = query.question().qname.clone();
+            match query.question().qtype {
+                TYPE_MX => {
+                    res.add_answer(ResourceRecord::new(
+                        qname.clone(),
+                        300,
+                        mx(20, "backup.example.net"),
+                    ));
+                    res.add_answer(ResourceRecord::new(qname, 300, mx(10, "mx.example.com")));
+                    res.add_additional(ResourceRecord::new(
+                        "mx.example.com",
+                        300,
+                        RData::A([192, 0, 2, 25].into()),
+                    ));
+                }
+                TYPE_A => res.add_answer(ResourceRecord::new(
+                    qname,
+                    300,
+                    RData::A([198, 51, 100, 25].into()),
+                )),
+                _ => {}
+            }
+            res.to_bytes().unwrap()
+        });
+        let resolver = Resolver::with_server(server);
+        let ip = |s: &str| s.parse::<IpAddr>().unwrap();
+
+        let exchangers = resolver.lookup_mx("example.com.").unwrap();
+        assert_eq!(
+            exchangers,
+            vec![
+                MailExchanger {
+                    preference: 10,
+                    exchange: Name::from("mx.example.com"),
+                    addrs: vec![ip("192.0.2.25")],
+                },
+                MailExchanger {
+                    preference: 20,
+                    exchange: Name::from("backup.example.net"),
+                    addrs: Vec::new(),
+                },
+            ]
+        );
+        let exchangers = resolver.resolve_mx("example.com.").unwrap();
+        assert_eq!(exchangers[1].addrs, [ip("198.51.100.25")]);
+    }
+
+    #[test]
+    fn test_null_mx() {
+        let server = mock_udp_server(1, |query| {
+            let query = DnsMessage::from_bytes(query).unwrap();
+            let mut res = DnsMessage::response_to(&query);
+            res.add_answer(ResourceRecord::new(
+                query.question().qname.clone(),
+                300,
+                mx(0, "."),
+            ));
+            res.to_bytes().unwrap()
+        });
+        let resolver = Resolver::with_server(server);
+        assert!(resolver.lookup_mx("nomail.example.").unwrap().is_empty());
+    }
+}
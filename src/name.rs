@@ -0,0 +1,480 @@
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::fmt;
+use core::hash::{Hash, Hasher};
+use core::net::IpAddr;
+use core::str::FromStr;
+
+use crate::message::{parse_qname, write_qname};
+use crate::DnsError;
+
+// helpers for domain names.
+//
+// Names are kept in presentation format, the way dig or a zone file would show them: labels
+// joined by dots, and any byte in a label that would be ambiguous or unprintable escaped with a
+// backslash. A dot inside a label is `\.`, a backslash is `\\`, and something like a 0x00 byte
+// is a decimal `\000`. That way any name that came off the wire can be written back to it
+// exactly as it was, which `from_utf8_lossy` couldn't promise
+
+// the presentation form of one label's raw bytes
+pub(crate) fn escape_label(label: &[u8]) -> String {
+    let mut out = String::with_capacity(label.len());
+    for &byte in label {
+        match byte {
+            b'.' | b'\\' | b'"' | b'(' | b')' | b';' | b'@' | b'$' => {
+                out.push('\\');
+                out.push(byte as char);
+            }
+            0x21..=0x7E => out.push(byte as char),
+            _ => out.push_str(&format!("\\{:03}", byte)),
+        }
+    }
+    out
+}
+
+// the reverse of the escaping: the raw bytes of each label, ready to be written to the wire.
+// Empty labels (the root, a trailing dot) are skipped. Escapes that don't make sense (a lone
+// backslash at the end, \DDD over 255) are taken literally rather than refused
+pub fn unescape_name(name: &str) -> Vec<Vec<u8>> {
+    let mut labels = raw_labels(name);
+    labels.retain(|label| !label.is_empty());
+    labels
+}
+
+// every label between unescaped dots, empty ones included (so `a..b` has three), after dropping
+// the one trailing dot a fully qualified name may have
+fn raw_labels(name: &str) -> Vec<Vec<u8>> {
+    let name = strip_trailing_dot(name);
+    if name.is_empty() {
+        return Vec::new();
+    }
+
+    let bytes = name.as_bytes();
+    let mut labels = Vec::new();
+    let mut label = Vec::new();
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'\\' => match decimal_escape(&bytes[i + 1..]) {
+                Some(value) => {
+                    label.push(value);
+                    i += 4;
+                }
+                None => {
+                    // \. and friends: the next byte as is
+                    label.push(*bytes.get(i + 1).unwrap_or(&b'\\'));
+                    i += 2;
+                }
+            },
+            b'.' => {
+                labels.push(core::mem::take(&mut label));
+                i += 1;
+            }
+            byte => {
+                label.push(byte);
+                i += 1;
+            }
+        }
+    }
+    labels.push(label);
+    labels
+}
+
+// drops a final dot, unless it's an escaped one (an odd run of backslashes in front of it)
+pub(crate) fn strip_trailing_dot(name: &str) -> &str {
+    match name.strip_suffix('.') {
+        Some(rest) if rest.bytes().rev().take_while(|&b| b == b'\\').count() % 2 == 0 => rest,
+        _ => name,
+    }
+}
+
+// \DDD, the three digits after the backslash
+pub(crate) fn decimal_escape(rest: &[u8]) -> Option<u8> {
+    let digits = rest.get(..3)?;
+    if !digits.iter().all(u8::is_ascii_digit) {
+        return None;
+    }
+    let value = digits
+        .iter()
+        .fold(0u16, |acc, digit| acc * 10 + (digit - b'0') as u16);
+    u8::try_from(value).ok()
+}
+
+// the labels of a presentation-format name, still escaped. Splits on dots that aren't escaped
+pub(crate) fn split_labels(name: &str) -> Vec<&str> {
+    let bytes = name.as_bytes();
+    let mut labels = Vec::new();
+    let mut start = 0;
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'\\' => i += 2,
+            b'.' => {
+                if i > start {
+                    labels.push(&name[start..i]);
+                }
+                i += 1;
+                start = i;
+            }
+            _ => i += 1,
+        }
+    }
+    if start < bytes.len() {
+        labels.push(&name[start..]);
+    }
+    labels
+}
+
+// DNS names are case-insensitive (RFC 4343) and a trailing dot just means "fully qualified", so
+// `Example.COM.` and `example.com` are the same name. Compared label by label on the raw bytes,
+// so it doesn't matter how a byte happened to be escaped either
+pub fn names_equal(a: &str, b: &str) -> bool {
+    let a = raw_labels(a);
+    let b = raw_labels(b);
+    a.len() == b.len() && a.iter().zip(&b).all(|(x, y)| x.eq_ignore_ascii_case(y))
+}
+
+// if `name` sits strictly below `zone`, the labels in front of it ("www.mail" for
+// www.mail.example.com under example.com)
+pub(crate) fn prefix_below(name: &str, zone: &str) -> Option<String> {
+    let name_labels = split_labels(name);
+    let zone_labels = split_labels(zone);
+    let split = name_labels.len().checked_sub(zone_labels.len() + 1)? + 1;
+    if !names_equal(&name_labels[split..].join("."), zone) {
+        return None;
+    }
+    Some(name_labels[..split].join("."))
+}
+
+// DNAME substitution (RFC 6672): a DNAME at `owner` pointing to `target` moves everything below
+// owner under target, so foo.old.example with old.example DNAME new.example is foo.new.example.
+// The owner name itself isn't affected, only names under it
+pub fn dname_substitute(name: &str, owner: &str, target: &str) -> Option<String> {
+    let prefix = prefix_below(name, owner)?;
+    let target = split_labels(target);
+    if target.is_empty() {
+        return Some(prefix);
+    }
+    Some(format!("{}.{}", prefix, target.join(".")))
+}
+
+// a domain name, kept in the same presentation format as everything else in here but
+// normalized: no trailing dot, no empty labels, and every label escaped the one way escape_label
+// does it. The case is left alone (servers echo it back and some randomize it on purpose), it's
+// only ignored when comparing, so two Names are equal whenever names_equal says they are
+#[derive(Debug, Clone, Default)]
+pub struct Name(String);
+
+// the most a name can take on the wire, length bytes and the final 0 included (RFC 1035 2.3.4)
+const MAX_NAME_LEN: usize = 255;
+const MAX_LABEL_LEN: usize = 63;
+
+// whether these (non-empty) labels make a name short enough to be written to the wire
+pub(crate) fn fits_on_wire(labels: &[Vec<u8>]) -> bool {
+    let wire_len = labels.iter().map(|label| label.len() + 1).sum::<usize>() + 1;
+    labels.iter().all(|label| label.len() <= MAX_LABEL_LEN) && wire_len <= MAX_NAME_LEN
+}
+
+impl Name {
+    pub fn root() -> Self {
+        Name::ROOT
+    }
+
+    pub(crate) const ROOT: Name = Name(String::new());
+
+    fn from_labels(labels: &[Vec<u8>]) -> Self {
+        let labels: Vec<String> = labels.iter().map(|label| escape_label(label)).collect();
+        Name(labels.join("."))
+    }
+
+    // without a trailing dot, and "" for the root
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    pub fn is_root(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    // the raw bytes of each label, leftmost first
+    pub fn labels(&self) -> Vec<Vec<u8>> {
+        unescape_name(&self.0)
+    }
+
+    // the name with its leftmost label taken off, None for the root
+    pub fn parent(&self) -> Option<Name> {
+        let labels = self.labels();
+        if labels.is_empty() {
+            return None;
+        }
+        Some(Name::from_labels(&labels[1..]))
+    }
+
+    // true for the name itself and everything below it, so every name is a subdomain of the root
+    pub fn is_subdomain_of(&self, other: &Name) -> bool {
+        let labels = self.labels();
+        let other = other.labels();
+        labels.len() >= other.len()
+            && labels[labels.len() - other.len()..]
+                .iter()
+                .zip(&other)
+                .all(|(x, y)| x.eq_ignore_ascii_case(y))
+    }
+
+    // the name PTR records for `addr` live under: 1.2.0.192.in-addr.arpa for 192.0.2.1, and
+    // one label per nibble, lowest first, under ip6.arpa for IPv6
+    pub fn reverse(addr: IpAddr) -> Self {
+        match addr {
+            IpAddr::V4(v4) => {
+                let [a, b, c, d] = v4.octets();
+                Name(format!("{}.{}.{}.{}.in-addr.arpa", d, c, b, a))
+            }
+            IpAddr::V6(v6) => {
+                let mut name = String::new();
+                for byte in v6.octets().iter().rev() {
+                    name.push_str(&format!("{:x}.{:x}.", byte & 0xF, byte >> 4));
+                }
+                name.push_str("ip6.arpa");
+                Name(name)
+            }
+        }
+    }
+
+    // uncompressed, [7]example[3]com[0]. Fails for a name that came in through the lenient
+    // From conversions with a label or the whole name too long
+    pub fn to_wire(&self) -> Result<Vec<u8>, DnsError> {
+        let mut bytes = Vec::new();
+        write_qname(&mut bytes, &self.0)?;
+        Ok(bytes)
+    }
+
+    // the name starting at `pos` in a whole message (pointers can point anywhere before it), and
+    // where whatever comes after the name starts
+    pub fn from_wire(buf: &[u8], pos: usize) -> Result<(Name, usize), DnsError> {
+        // parse_qname already hands back labels escaped the normalized way
+        let (name, next) = parse_qname(buf, pos)?;
+        Ok((Name(name), next))
+    }
+}
+
+// the strict way in: refuses anything that couldn't be written to the wire as is. The From
+// conversions below are the lenient one the rest of the crate has always used, they just drop
+// empty labels
+impl FromStr for Name {
+    type Err = DnsError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut labels = raw_labels(s);
+        // "" and "." are both the root, which raw_labels gives back as one empty label
+        if labels.len() == 1 && labels[0].is_empty() {
+            labels.clear();
+        }
+        if labels.iter().any(|label| label.is_empty()) || !fits_on_wire(&labels) {
+            return Err(DnsError::InvalidName(s.to_string()));
+        }
+        Ok(Name::from_labels(&labels))
+    }
+}
+
+impl From<&str> for Name {
+    fn from(s: &str) -> Self {
+        Name::from_labels(&unescape_name(s))
+    }
+}
+
+impl From<String> for Name {
+    fn from(s: String) -> Self {
+        Name::from(s.as_str())
+    }
+}
+
+impl From<&String> for Name {
+    fn from(s: &String) -> Self {
+        Name::from(s.as_str())
+    }
+}
+
+// a name is a string in JSON and the like, the way it's written in a zone file
+#[cfg(feature = "serde")]
+impl serde::Serialize for Name {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for Name {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let name = String::deserialize(deserializer)?;
+        name.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+impl fmt::Display for Name {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_root() {
+            f.write_str(".")
+        } else {
+            f.write_str(&self.0)
+        }
+    }
+}
+
+impl PartialEq for Name {
+    fn eq(&self, other: &Name) -> bool {
+        names_equal(&self.0, &other.0)
+    }
+}
+
+impl Eq for Name {}
+
+impl PartialEq<str> for Name {
+    fn eq(&self, other: &str) -> bool {
+        names_equal(&self.0, other)
+    }
+}
+
+impl PartialEq<&str> for Name {
+    fn eq(&self, other: &&str) -> bool {
+        names_equal(&self.0, other)
+    }
+}
+
+// has to agree with eq, so the case is folded here too
+impl Hash for Name {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        for label in self.labels() {
+            state.write_usize(label.len());
+            for byte in label {
+                state.write_u8(byte.to_ascii_lowercase());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec;
+
+    fn name(s: &str) -> Name {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn test_parent() {
+        assert_eq!(name("www.example.com").parent(), Some(name("example.com")));
+        assert_eq!(name("example.com.").parent(), Some(name("com")));
+        assert_eq!(name("com").parent(), Some(Name::root()));
+        assert_eq!(Name::root().parent(), None);
+        // an escaped dot doesn't count as a label boundary
+        assert_eq!(name("a\\.b.example").parent(), Some(name("example")));
+    }
+
+    #[test]
+    fn test_reverse() {
+        assert_eq!(
+            Name::reverse("192.0.2.1".parse().unwrap()),
+            "1.2.0.192.in-addr.arpa"
+        );
+        assert_eq!(
+            Name::reverse("2001:db8::567:89ab".parse().unwrap()),
+            "b.a.9.8.7.6.5.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.8.b.d.0.1.0.0.2.ip6.arpa"
+        );
+    }
+
+    #[test]
+    fn test_is_subdomain_of() {
+        let example = name("example.com");
+        assert!(name("www.example.com").is_subdomain_of(&example));
+        assert!(name("a.b.EXAMPLE.com.").is_subdomain_of(&example));
+        assert!(example.is_subdomain_of(&example));
+        assert!(example.is_subdomain_of(&Name::root()));
+        assert!(!name("badexample.com").is_subdomain_of(&example));
+        assert!(!name("com").is_subdomain_of(&example));
+        assert!(!name("example.org").is_subdomain_of(&example));
+        assert!(!Name::root().is_subdomain_of(&example));
+    }
+
+    #[test]
+    fn test_name_normalizes_and_checks_lengths() {
+        let n = name("WWW.Example.com.");
+        assert_eq!(n.as_str(), "WWW.Example.com");
+        assert_eq!(n.to_string(), "WWW.Example.com");
+        assert_eq!(n, name("www.example.com"));
+        assert_eq!(Name::root().to_string(), ".");
+        assert_eq!(name("a\\046b.c").as_str(), "a\\.b.c");
+
+        let wire = n.to_wire().unwrap();
+        assert_eq!(wire, b"\x03WWW\x07Example\x03com\x00");
+        assert_eq!(Name::from_wire(&wire, 0).unwrap(), (n, wire.len()));
+
+        assert!("a..b".parse::<Name>().is_err());
+        assert!(".example".parse::<Name>().is_err());
+        let long_label = "x".repeat(64);
+        assert!(long_label.parse::<Name>().is_err());
+        assert!(long_label[1..].parse::<Name>().is_ok());
+        // 4 labels of 63 come to 257 bytes on the wire, 2 trimmed off is exactly the limit
+        let label = &long_label[1..];
+        let too_long = format!("{0}.{0}.{0}.{0}", label);
+        assert!(too_long.parse::<Name>().is_err());
+        assert!(too_long[2..].parse::<Name>().is_ok());
+    }
+
+    #[test]
+    fn test_names_equal_ignores_case_and_trailing_dot() {
+        assert!(names_equal("Example.COM.", "example.com"));
+        assert!(names_equal("example.com", "EXAMPLE.com."));
+        assert!(names_equal(".", ""));
+        assert!(!names_equal("example.com", "example.org"));
+        assert!(!names_equal("www.example.com", "example.com"));
+        assert!(!names_equal("example.com..", "example.com"));
+    }
+
+    #[test]
+    fn test_escaping_round_trips_odd_labels() {
+        // a label with a dot in it and a zero byte, then an ordinary one
+        let label = b"a.b\x00c";
+        let escaped = escape_label(label);
+        assert_eq!(escaped, "a\\.b\\000c");
+
+        let name = format!("{}.com", escaped);
+        assert_eq!(unescape_name(&name), vec![label.to_vec(), b"com".to_vec()]);
+        assert_eq!(split_labels(&name), vec![escaped.as_str(), "com"]);
+
+        assert_eq!(escape_label(b"back\\slash here"), "back\\\\slash\\032here");
+        assert_eq!(
+            unescape_name("back\\\\slash\\032here."),
+            vec![b"back\\slash here".to_vec()]
+        );
+        assert_eq!(unescape_name(""), Vec::<Vec<u8>>::new());
+        assert_eq!(unescape_name("."), Vec::<Vec<u8>>::new());
+        assert_eq!(unescape_name("dot\\."), vec![b"dot.".to_vec()]);
+
+        // an escaped dot is part of the label, not a separator
+        assert!(names_equal("A\\.B.example", "a\\046b.EXAMPLE."));
+        assert!(!names_equal("a\\.b.example", "a.b.example"));
+    }
+
+    #[test]
+    fn test_dname_substitute() {
+        assert_eq!(
+            dname_substitute("foo.old.example", "old.example", "new.example").unwrap(),
+            "foo.new.example"
+        );
+        assert_eq!(
+            dname_substitute("a.b.OLD.example.", "old.example.", "new.test.").unwrap(),
+            "a.b.new.test"
+        );
+        // the owner itself and names outside it aren't rewritten
+        assert_eq!(
+            dname_substitute("old.example", "old.example", "new.example"),
+            None
+        );
+        assert_eq!(
+            dname_substitute("foo.bold.example", "old.example", "new.example"),
+            None
+        );
+    }
+}
@@ -0,0 +1,59 @@
+use crate::ClientSubnet;
+
+// knobs for how a query goes out on the wire
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QueryOptions {
+    // the biggest UDP response we are prepared to take. It's advertised to the server in an
+    // EDNS OPT record and sizes our receive buffer; anything bigger comes back truncated (or
+    // doesn't fit) and we go again over TCP. At 512 or below we don't send EDNS at all and stick
+    // to classic DNS. Lower it on networks that mangle big UDP packets
+    pub max_udp_payload: u16,
+    // skip UDP and always ask over TCP
+    pub force_tcp: bool,
+    // pad queries with EDNS padding (RFC 7830) so their length is a multiple of this many bytes,
+    // which hides the name from anyone looking only at sizes. Meant for encrypted transports,
+    // 128 is what RFC 8467 suggests. 0 turns it off, and it needs EDNS so it does nothing at a
+    // max_udp_payload of 512 or below
+    pub padding_block: u16,
+    // 0x20: flip the case of the letters in the query name at random, and only take an answer
+    // that repeats it exactly. Every letter is one more bit a forged answer has to guess. Off by
+    // default, now and then a server out there doesn't echo the case back
+    pub randomize_case: bool,
+    // DNS cookies (RFC 7873): send each upstream a client cookie of our own, and the server
+    // cookie it gave us last time, in the OPT record. Answers carrying somebody else's client
+    // cookie are dropped. Servers that don't know the option ignore it, so it's on by default;
+    // like padding it needs EDNS
+    pub cookies: bool,
+    // EDNS Client Subnet (RFC 7871) to send with every query, so CDNs answer for that network
+    // rather than for wherever we are. ClientSubnet::privacy() asks them not to use an address
+    // at all. None leaves the option out, and then whatever the server does is up to it
+    pub client_subnet: Option<ClientSubnet>,
+}
+
+// classic DNS limit for UDP without EDNS
+pub const CLASSIC_UDP_PAYLOAD: u16 = 512;
+
+impl Default for QueryOptions {
+    fn default() -> Self {
+        QueryOptions {
+            // the DNS flag day 2020 recommendation, small enough to avoid IP fragmentation
+            max_udp_payload: 1232,
+            force_tcp: false,
+            padding_block: 0,
+            randomize_case: false,
+            cookies: true,
+            client_subnet: None,
+        }
+    }
+}
+
+impl QueryOptions {
+    pub(crate) fn uses_edns(&self) -> bool {
+        self.max_udp_payload > CLASSIC_UDP_PAYLOAD
+    }
+
+    // how much we actually read off the socket
+    pub(crate) fn udp_payload(&self) -> usize {
+        self.max_udp_payload.max(CLASSIC_UDP_PAYLOAD) as usize
+    }
+}
@@ -0,0 +1,148 @@
+// just enough of the pcap file format to open our DNS traffic in Wireshark/tcpdump. We never see
+// the real frames (the OS does the UDP/IP part for us), so each DNS payload is wrapped in a
+// made-up IP + UDP header with the right addresses and ports, which is all a dissector needs
+use std::io::{self, Write};
+use std::net::{IpAddr, SocketAddr};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const MAGIC: u32 = 0xa1b2_c3d4;
+// LINKTYPE_RAW: packets start straight at the IP header, no ethernet framing to invent
+const LINKTYPE_RAW: u32 = 101;
+const SNAPLEN: u32 = 65535;
+const UDP: u8 = 17;
+
+pub struct PcapWriter<W: Write> {
+    out: W,
+}
+
+impl<W: Write> PcapWriter<W> {
+    // writes the global header straight away, so even a capture with no packets opens fine
+    pub fn new(mut out: W) -> io::Result<Self> {
+        out.write_all(&MAGIC.to_le_bytes())?;
+        out.write_all(&2u16.to_le_bytes())?; // version 2.4
+        out.write_all(&4u16.to_le_bytes())?;
+        out.write_all(&0i32.to_le_bytes())?; // timestamps are UTC
+        out.write_all(&0u32.to_le_bytes())?; // sigfigs, always 0
+        out.write_all(&SNAPLEN.to_le_bytes())?;
+        out.write_all(&LINKTYPE_RAW.to_le_bytes())?;
+        Ok(PcapWriter { out })
+    }
+
+    // one DNS message going from `src` to `dst`. Messages that really went over TCP are written
+    // as UDP too, Wireshark decodes them just the same
+    pub fn write_packet(
+        &mut self,
+        time: SystemTime,
+        src: SocketAddr,
+        dst: SocketAddr,
+        payload: &[u8],
+    ) -> io::Result<()> {
+        let frame = udp_frame(src, dst, payload);
+        let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+
+        self.out
+            .write_all(&(since_epoch.as_secs() as u32).to_le_bytes())?;
+        self.out
+            .write_all(&since_epoch.subsec_micros().to_le_bytes())?;
+        self.out.write_all(&(frame.len() as u32).to_le_bytes())?; // captured length
+        self.out.write_all(&(frame.len() as u32).to_le_bytes())?; // original length
+        self.out.write_all(&frame)?;
+        self.out.flush()
+    }
+}
+
+fn udp_frame(src: SocketAddr, dst: SocketAddr, payload: &[u8]) -> Vec<u8> {
+    let udp_len = (8 + payload.len()) as u16;
+    let mut udp = Vec::with_capacity(udp_len as usize);
+    udp.extend(&src.port().to_be_bytes());
+    udp.extend(&dst.port().to_be_bytes());
+    udp.extend(&udp_len.to_be_bytes());
+    udp.extend(&[0, 0]); // checksum, filled in below
+    udp.extend(payload);
+
+    let mut frame = Vec::new();
+    let mut pseudo_header = Vec::new();
+    match (src.ip(), dst.ip()) {
+        (IpAddr::V6(src_ip), IpAddr::V6(dst_ip)) => {
+            frame.extend(&[0x60, 0, 0, 0]); // version 6, no traffic class or flow label
+            frame.extend(&udp_len.to_be_bytes());
+            frame.extend(&[UDP, 64]); // next header, hop limit
+            frame.extend(&src_ip.octets());
+            frame.extend(&dst_ip.octets());
+
+            pseudo_header.extend(&src_ip.octets());
+            pseudo_header.extend(&dst_ip.octets());
+            pseudo_header.extend(&(udp_len as u32).to_be_bytes());
+            pseudo_header.extend(&[0, 0, 0, UDP]);
+        }
+        (src_ip, dst_ip) => {
+            // a v4/v6 mix can't happen on one socket, but treat anything odd as v4 anyway
+            let src_ip = to_v4_octets(src_ip);
+            let dst_ip = to_v4_octets(dst_ip);
+            frame.extend(&[0x45, 0]); // version 4, 20 byte header, no TOS
+            frame.extend(&(20 + udp_len).to_be_bytes());
+            frame.extend(&[0, 0, 0x40, 0]); // no ID, don't fragment
+            frame.extend(&[64, UDP, 0, 0]); // TTL, protocol, checksum
+            frame.extend(&src_ip);
+            frame.extend(&dst_ip);
+            let checksum = internet_checksum(&frame);
+            frame[10..12].copy_from_slice(&checksum.to_be_bytes());
+
+            pseudo_header.extend(&src_ip);
+            pseudo_header.extend(&dst_ip);
+            pseudo_header.extend(&[0, UDP]);
+            pseudo_header.extend(&udp_len.to_be_bytes());
+        }
+    }
+
+    pseudo_header.extend(&udp);
+    let checksum = match internet_checksum(&pseudo_header) {
+        0 => 0xFFFF, // 0 means "no checksum" in UDP, so a real 0 is sent as all ones
+        sum => sum,
+    };
+    udp[6..8].copy_from_slice(&checksum.to_be_bytes());
+    frame.extend(udp);
+    frame
+}
+
+fn to_v4_octets(ip: IpAddr) -> [u8; 4] {
+    match ip {
+        IpAddr::V4(ip) => ip.octets(),
+        IpAddr::V6(ip) => ip.to_ipv4_mapped().map_or([0; 4], |ip| ip.octets()),
+    }
+}
+
+// RFC 1071: one's complement of the one's complement sum of all the 16 bit words
+fn internet_checksum(data: &[u8]) -> u16 {
+    let mut sum: u32 = 0;
+    for chunk in data.chunks(2) {
+        let word = if chunk.len() == 2 {
+            u16::from_be_bytes([chunk[0], chunk[1]])
+        } else {
+            u16::from_be_bytes([chunk[0], 0])
+        };
+        sum += word as u32;
+    }
+    while sum > 0xFFFF {
+        sum = (sum & 0xFFFF) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ipv4_header_checksum_verifies() {
+        let frame = udp_frame(
+            "10.0.0.1:5000".parse().unwrap(),
+            "10.0.0.2:53".parse().unwrap(),
+            b"hello",
+        );
+        assert_eq!(frame.len(), 20 + 8 + 5);
+        // summing a header including its own checksum gives 0
+        assert_eq!(internet_checksum(&frame[..20]), 0);
+        assert_eq!(&frame[20..24], &[0x13, 0x88, 0, 53]);
+    }
+}
@@ -0,0 +1,437 @@
+// TCP (and TLS) connections to upstreams kept open between queries, so only the first query to
+// a server pays for the handshakes (RFC 7766 6.2.1). A connection is pipelined: any number of
+// queries can be out on it at once, from as many threads, and the answers come back in whatever
+// order the server sends them. A thread per connection reads them and hands each one to the
+// query with its ID.
+//
+// Queries go out with an empty edns-tcp-keepalive option (RFC 7828) when they have EDNS, and a
+// server that answers with one tells us how long it will keep an idle connection: we go by
+// that rather than our own idle timeout, and a timeout of zero means the connection is closed
+// once it is done with
+use std::collections::HashMap;
+use std::io::{self, Read, Write};
+use std::net::{Shutdown, SocketAddr, TcpStream};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+#[cfg(feature = "tls")]
+use rustls::pki_types::ServerName;
+#[cfg(feature = "tls")]
+use rustls::{ClientConfig, RootCertStore};
+
+use crate::resolver::{answers_question, DEFAULT_TIMEOUT};
+use crate::tcp::{self, DnsFramed};
+use crate::transport::timeout_error;
+use crate::{DnsError, DnsMessage, EdnsOption, EDNS_TCP_KEEPALIVE};
+
+// how long a connection we have nothing to send on is kept, when the server hasn't said
+pub const DEFAULT_IDLE_TIMEOUT: Duration = Duration::from_secs(10);
+
+// the two ends of a connection, used from different threads
+pub(crate) type Reader = Box<dyn Read + Send>;
+pub(crate) type Writer = Box<dyn Write + Send>;
+
+pub struct ConnectionPool {
+    #[cfg(feature = "tls")]
+    tls: Option<(ServerName<'static>, Arc<ClientConfig>)>,
+    timeout: Duration,
+    idle_timeout: Duration,
+    // at most one per server, everything to a server goes down the same connection
+    connections: Mutex<HashMap<SocketAddr, Arc<Connection>>>,
+}
+
+// one exchange on a pooled connection: what went over the wire both ways, and from where
+pub(crate) struct Exchange {
+    pub(crate) local: SocketAddr,
+    pub(crate) query: Vec<u8>,
+    pub(crate) response: Vec<u8>,
+    pub(crate) res: DnsMessage,
+}
+
+impl Default for ConnectionPool {
+    fn default() -> Self {
+        Self::tcp()
+    }
+}
+
+impl ConnectionPool {
+    // plain DNS over TCP
+    pub fn tcp() -> Self {
+        ConnectionPool {
+            #[cfg(feature = "tls")]
+            tls: None,
+            timeout: DEFAULT_TIMEOUT,
+            idle_timeout: DEFAULT_IDLE_TIMEOUT,
+            connections: Mutex::new(HashMap::new()),
+        }
+    }
+
+    // DNS over TLS (see tls.rs), with the server certificates checked against `hostname`. Every
+    // server in the pool is taken to have a certificate for that name, so one pool goes with
+    // one provider
+    #[cfg(feature = "tls")]
+    pub fn tls(hostname: &str) -> Result<Self, DnsError> {
+        Self::tls_with_roots(hostname, crate::tls::default_roots())
+    }
+
+    #[cfg(feature = "tls")]
+    pub fn tls_with_roots(hostname: &str, roots: RootCertStore) -> Result<Self, DnsError> {
+        let name = crate::tls::server_name(hostname)?;
+        let config = crate::tls::client_config(roots, Vec::new())?;
+        Ok(ConnectionPool {
+            tls: Some((name, config)),
+            ..Self::tcp()
+        })
+    }
+
+    // for connecting, each write, and waiting for each answer
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = timeout;
+    }
+
+    // how long a connection with nothing going over it is kept, unless the server says otherwise
+    pub fn set_idle_timeout(&mut self, idle: Duration) {
+        self.idle_timeout = idle;
+    }
+
+    // the number of connections open, idle or not
+    pub fn open_connections(&self) -> usize {
+        let now = Instant::now();
+        let connections = self.connections.lock().unwrap();
+        connections.values().filter(|conn| conn.usable(now)).count()
+    }
+
+    pub fn exchange(&self, server: SocketAddr, msg: &DnsMessage) -> Result<DnsMessage, DnsError> {
+        self.exchange_timeout(server, msg, self.timeout)
+    }
+
+    // the same, with `timeout` for this query instead of the pool's
+    pub fn exchange_timeout(
+        &self,
+        server: SocketAddr,
+        msg: &DnsMessage,
+        timeout: Duration,
+    ) -> Result<DnsMessage, DnsError> {
+        let done = self.exchange_with(server, msg, timeout)?;
+        if !answers_question(msg, &done.res) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "TCP response doesn't match the query",
+            )
+            .into());
+        }
+        Ok(done.res)
+    }
+
+    // `msg` to `server` on the open connection if there is one, a new one if not. The answer
+    // has the query's ID, checking the rest is up to the caller
+    pub(crate) fn exchange_with(
+        &self,
+        server: SocketAddr,
+        msg: &DnsMessage,
+        timeout: Duration,
+    ) -> Result<Exchange, DnsError> {
+        let mut query = msg.clone();
+        if let Some(edns) = &mut query.edns {
+            if edns.option(EDNS_TCP_KEEPALIVE).is_none() {
+                edns.options.push(EdnsOption::tcp_keepalive(None));
+            }
+        }
+        let query = query.to_bytes()?;
+        let id = msg.header.identification;
+
+        // servers close idle connections whenever they like, and we only find out when using
+        // one. A reused connection that fails gets one more try on a fresh one, short of a
+        // timeout: a server that's slow on the old connection is no quicker on a new one
+        let (conn, reused) = self.connection(server, timeout)?;
+        let (conn, response) = match conn.exchange(id, &query, timeout) {
+            Ok(response) => (conn, response),
+            Err(DnsError::Timeout) => return Err(DnsError::Timeout),
+            Err(_) if reused => {
+                self.forget(server, &conn);
+                let (conn, _) = self.connection(server, timeout)?;
+                let response = conn.exchange(id, &query, timeout)?;
+                (conn, response)
+            }
+            Err(e) => return Err(e),
+        };
+
+        let res = DnsMessage::from_bytes(&response)?;
+        if let Some(idle) = res.edns.as_ref().and_then(|edns| edns.tcp_keepalive()) {
+            conn.set_idle_timeout(idle);
+        }
+        Ok(Exchange {
+            local: conn.local,
+            query,
+            response,
+            res,
+        })
+    }
+
+    // the connection to `server`, and whether it was open already. A new one is made with the
+    // pool locked, so queries starting together all end up on the same connection
+    fn connection(
+        &self,
+        server: SocketAddr,
+        timeout: Duration,
+    ) -> Result<(Arc<Connection>, bool), DnsError> {
+        let mut connections = self.connections.lock().unwrap();
+        if let Some(conn) = connections.get(&server) {
+            if conn.usable(Instant::now()) {
+                return Ok((conn.clone(), true));
+            }
+        }
+        // replacing the old one drops our handle on it, and dropping the last one closes it
+        let conn = Arc::new(self.connect(server, timeout)?);
+        connections.insert(server, conn.clone());
+        Ok((conn, false))
+    }
+
+    fn connect(&self, server: SocketAddr, timeout: Duration) -> Result<Connection, DnsError> {
+        let sock = tcp::connect(server, timeout).map_err(timeout_error)?;
+        #[cfg(feature = "tls")]
+        if let Some((name, config)) = &self.tls {
+            let (reader, writer) = crate::tls::split(sock.try_clone()?, name, config)?;
+            return Ok(Connection::open(sock, reader, writer, self.idle_timeout)?);
+        }
+        let (reader, writer) = (sock.try_clone()?, sock.try_clone()?);
+        Ok(Connection::open(
+            sock,
+            Box::new(reader),
+            Box::new(writer),
+            self.idle_timeout,
+        )?)
+    }
+
+    // takes `conn` out of the pool, unless it's been replaced already
+    fn forget(&self, server: SocketAddr, conn: &Arc<Connection>) {
+        let mut connections = self.connections.lock().unwrap();
+        if connections
+            .get(&server)
+            .is_some_and(|open| Arc::ptr_eq(open, conn))
+        {
+            connections.remove(&server);
+        }
+    }
+}
+
+pub(crate) struct Connection {
+    local: SocketAddr,
+    // kept to shut the connection down, which is also what stops the thread reading from it
+    sock: TcpStream,
+    writer: Mutex<Writer>,
+    shared: Arc<Mutex<Shared>>,
+}
+
+// what the connection and the thread reading its answers both get at
+struct Shared {
+    // the queries waiting for an answer, by ID
+    pending: HashMap<u16, mpsc::Sender<Vec<u8>>>,
+    // why the connection closed, once it has
+    closed: Option<(io::ErrorKind, String)>,
+    idle_timeout: Duration,
+    last_used: Instant,
+}
+
+impl Connection {
+    fn open(
+        sock: TcpStream,
+        reader: Reader,
+        writer: Writer,
+        idle_timeout: Duration,
+    ) -> io::Result<Self> {
+        // the reading thread waits for as long as the connection lasts, it's the queries that
+        // time out
+        sock.set_read_timeout(None)?;
+        let shared = Arc::new(Mutex::new(Shared {
+            pending: HashMap::new(),
+            closed: None,
+            idle_timeout,
+            last_used: Instant::now(),
+        }));
+        let reading = shared.clone();
+        thread::spawn(move || read_answers(reader, &reading));
+        Ok(Connection {
+            local: sock.local_addr()?,
+            sock,
+            writer: Mutex::new(writer),
+            shared,
+        })
+    }
+
+    // still open, and either busy or not idle for too long
+    fn usable(&self, now: Instant) -> bool {
+        let shared = self.shared.lock().unwrap();
+        shared.closed.is_none()
+            && (!shared.pending.is_empty() || now < shared.last_used + shared.idle_timeout)
+    }
+
+    fn set_idle_timeout(&self, idle: Duration) {
+        self.shared.lock().unwrap().idle_timeout = idle;
+    }
+
+    fn exchange(&self, id: u16, query: &[u8], timeout: Duration) -> Result<Vec<u8>, DnsError> {
+        let (tx, rx) = mpsc::channel();
+        {
+            let mut shared = self.shared.lock().unwrap();
+            if let Some((kind, error)) = &shared.closed {
+                return Err(io::Error::new(*kind, error.clone()).into());
+            }
+            // the ID is all an answer is matched by
+            if shared.pending.contains_key(&id) {
+                return Err(io::Error::new(
+                    io::ErrorKind::AlreadyExists,
+                    "a query with this ID is already waiting on the connection",
+                )
+                .into());
+            }
+            shared.pending.insert(id, tx);
+            shared.last_used = Instant::now();
+        }
+
+        let written = tcp::write_message(&mut *self.writer.lock().unwrap(), query);
+        let res = match written {
+            Ok(()) => rx.recv_timeout(timeout),
+            Err(e) => {
+                self.shared.lock().unwrap().pending.remove(&id);
+                return Err(timeout_error(e));
+            }
+        };
+        match res {
+            Ok(response) => Ok(response),
+            Err(RecvTimeoutError::Timeout) => {
+                self.shared.lock().unwrap().pending.remove(&id);
+                Err(DnsError::Timeout)
+            }
+            // the connection closed under us
+            Err(RecvTimeoutError::Disconnected) => {
+                let shared = self.shared.lock().unwrap();
+                let (kind, error) = shared.closed.clone().unwrap_or((
+                    io::ErrorKind::ConnectionAborted,
+                    "connection closed".to_string(),
+                ));
+                Err(io::Error::new(kind, error).into())
+            }
+        }
+    }
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        let _ = self.sock.shutdown(Shutdown::Both);
+    }
+}
+
+// the thread behind each connection: every answer that comes in goes to the query waiting on
+// its ID. Answers nobody is waiting for any more (the query timed out) are dropped
+fn read_answers(reader: Reader, shared: &Mutex<Shared>) {
+    let mut framed = DnsFramed::new(reader);
+    let closed = loop {
+        match framed.read_message() {
+            Ok(Some(msg)) if msg.len() >= 2 => {
+                let id = u16::from_be_bytes([msg[0], msg[1]]);
+                let mut shared = shared.lock().unwrap();
+                shared.last_used = Instant::now();
+                if let Some(tx) = shared.pending.remove(&id) {
+                    let _ = tx.send(msg.to_vec());
+                }
+            }
+            Ok(Some(_)) => {}
+            Ok(None) => {
+                break (
+                    io::ErrorKind::ConnectionAborted,
+                    "server closed the connection".to_string(),
+                )
+            }
+            Err(e) => break (e.kind(), e.to_string()),
+        }
+    };
+    let mut shared = shared.lock().unwrap();
+    shared.closed = Some(closed);
+    // dropping the senders wakes up everyone still waiting
+    shared.pending.clear();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{mock_tcp_server_for, response_with_rdata};
+    use crate::Edns;
+    use std::net::{Ipv4Addr, TcpListener};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    // a TCP server that reads `count` queries off a single connection before answering any of
+    // them, then answers them last to first. With `keepalive`, answers that have EDNS get that
+    // idle timeout
+    fn mock_pipelining_server(count: usize, keepalive: Option<Duration>) -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut stream = DnsFramed::new(stream);
+            let mut queries = Vec::new();
+            for _ in 0..count {
+                queries.push(stream.read_message().unwrap().unwrap().to_vec());
+            }
+            for query in queries.iter().rev() {
+                let seen = DnsMessage::from_bytes(query).unwrap();
+                let last = seen.question().qname.to_string().len() as u8;
+                let res = response_with_rdata(query, 1, &[192, 0, 2, last]);
+                let mut res = DnsMessage::from_bytes(&res).unwrap();
+                if let (Some(edns), Some(idle)) = (&seen.edns, keepalive) {
+                    assert_eq!(edns.option(EDNS_TCP_KEEPALIVE), Some(&[][..]));
+                    let mut edns = Edns::new(1232);
+                    edns.options.push(EdnsOption::tcp_keepalive(Some(idle)));
+                    res.edns = Some(edns);
+                }
+                stream.write_message(&res.to_bytes().unwrap()).unwrap();
+            }
+            // hold the connection open until the client is done with it
+            let _ = stream.read_message();
+        });
+        addr
+    }
+
+    #[test]
+    fn test_pipelined_queries() {
+        let server = mock_pipelining_server(3, None);
+        let pool = ConnectionPool::tcp();
+        let answered = AtomicUsize::new(0);
+        thread::scope(|scope| {
+            for name in ["a.example", "bb.example", "ccc.example"] {
+                let (pool, answered) = (&pool, &answered);
+                scope.spawn(move || {
+                    let query = DnsMessage::query(name).build();
+                    let res = pool.exchange(server, &query).unwrap();
+                    assert_eq!(res.header.identification, query.header.identification);
+                    let last = name.len() as u8;
+                    assert_eq!(res.ipv4_addrs(), [Ipv4Addr::new(192, 0, 2, last)]);
+                    answered.fetch_add(1, Ordering::SeqCst);
+                });
+            }
+        });
+        assert_eq!(answered.load(Ordering::SeqCst), 3);
+        assert_eq!(pool.open_connections(), 1);
+    }
+
+    #[test]
+    fn test_keepalive_from_the_server() {
+        let server = mock_pipelining_server(1, Some(Duration::ZERO));
+        let pool = ConnectionPool::tcp();
+        let mut query = DnsMessage::query("example.com").build();
+        query.edns = Some(Edns::new(1232));
+        let done = pool.exchange_with(server, &query, DEFAULT_TIMEOUT).unwrap();
+        assert_eq!(done.res.edns.unwrap().tcp_keepalive(), Some(Duration::ZERO));
+        // an idle timeout of zero: the server wants the connection closed, so it isn't reused
+        assert_eq!(pool.open_connections(), 0);
+
+        // a server that hangs up after every answer: each query gets a connection of its own,
+        // whether or not we've noticed the last one closing by then
+        let server = mock_tcp_server_for(2, |query| vec![response_with_rdata(query, 1, &[1; 4])]);
+        for _ in 0..2 {
+            assert_eq!(pool.exchange(server, &query).unwrap().answers.len(), 1);
+        }
+    }
+}
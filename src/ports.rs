@@ -0,0 +1,50 @@
+// the ports the UDP sockets of a UdpMux (and so a Resolver's queries) go out from. An off-path
+// attacker forging an answer has to guess the query ID and the port it went out from: 16 bits
+// each. Kept on one socket forever, the port is there for the taking from the first answer they
+// see, so we can keep several sockets on random ports, send each query from one picked at
+// random, and swap them for new ones every so often
+use std::io;
+use std::net::{SocketAddr, UdpSocket};
+use std::time::Duration;
+
+use crate::random::random_u64;
+use crate::resolver::unspecified_addr;
+
+// where the random ports come from: the dynamic range (RFC 6335), which nothing is assigned in
+pub(crate) const FIRST_PORT: u16 = 49152;
+// the tries at a random port before leaving it to the OS, when they're all in use
+const BIND_TRIES: usize = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SourcePorts {
+    // how many sockets per address family. At least one
+    pub sockets: usize,
+    // how long a socket is used before it's replaced by one on a new port. None keeps them
+    pub rebind_after: Option<Duration>,
+}
+
+impl Default for SourcePorts {
+    // a single socket for good, on a random port
+    fn default() -> Self {
+        SourcePorts {
+            sockets: 1,
+            rebind_after: None,
+        }
+    }
+}
+
+// a socket on a random port of the dynamic range, or wherever the OS puts it if we keep running
+// into ports in use
+pub(crate) fn bind(server: &SocketAddr) -> io::Result<UdpSocket> {
+    let mut addr = unspecified_addr(server);
+    for _ in 0..BIND_TRIES {
+        addr.set_port(FIRST_PORT + (random_u64() % (u16::MAX - FIRST_PORT) as u64) as u16);
+        match UdpSocket::bind(addr) {
+            Ok(socket) => return Ok(socket),
+            Err(e) if e.kind() == io::ErrorKind::AddrInUse => {}
+            Err(e) => return Err(e),
+        }
+    }
+    addr.set_port(0);
+    UdpSocket::bind(addr)
+}
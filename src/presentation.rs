@@ -0,0 +1,362 @@
+// rdata in presentation format, the text form of zone files and dig output: "10 mail.example.com."
+// for an MX, quoted strings for TXT, base64 keys, and so on, with every name fully qualified.
+//
+// A type we don't know comes out in the generic form of RFC 3597 section 5, "\# 3 616263": the
+// rdata's length and then its bytes in hex. That form goes for every type, and reading it back
+// with from_generic decodes the bytes into the typed rdata when the type is one we know, so
+// nothing is lost either way
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::fmt::{self, Write};
+
+use crate::encoding::{from_hex, to_base32hex, to_base64, to_hex};
+use crate::rdata::{SvcParam, SvcParams};
+use crate::{DnsError, RData, RecordType};
+
+impl fmt::Display for RData {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RData::A(ip) => write!(f, "{}", ip),
+            RData::AAAA(ip) => write!(f, "{}", ip),
+            RData::NS(name) | RData::CNAME(name) | RData::PTR(name) | RData::DNAME(name) => {
+                f.write_str(&fqdn(name))
+            }
+            RData::MX {
+                preference,
+                exchange,
+            } => write!(f, "{} {}", preference, fqdn(exchange)),
+            RData::SOA(soa) => write!(
+                f,
+                "{} {} {} {} {} {} {}",
+                fqdn(&soa.mname),
+                fqdn(&soa.rname),
+                soa.serial,
+                soa.refresh,
+                soa.retry,
+                soa.expire,
+                soa.minimum
+            ),
+            RData::SRV(srv) => write!(
+                f,
+                "{} {} {} {}",
+                srv.priority,
+                srv.weight,
+                srv.port,
+                fqdn(&srv.target)
+            ),
+            RData::TXT(strings) => {
+                let quoted: Vec<String> = strings.iter().map(|string| quoted(string)).collect();
+                f.write_str(&quoted.join(" "))
+            }
+            RData::TSIG(tsig) => write!(
+                f,
+                "{} {} {} {} {} {} {} {} {}",
+                fqdn(&tsig.algorithm),
+                tsig.time_signed,
+                tsig.fudge,
+                tsig.mac.len(),
+                to_base64(&tsig.mac),
+                tsig.original_id,
+                tsig.error,
+                tsig.other.len(),
+                to_base64(&tsig.other)
+            ),
+            RData::DS(ds) => write!(
+                f,
+                "{} {} {} {}",
+                ds.key_tag,
+                ds.algorithm,
+                ds.digest_type,
+                to_hex(&ds.digest)
+            ),
+            RData::RRSIG(sig) => write!(
+                f,
+                "{} {} {} {} {} {} {} {} {}",
+                RecordType::from(sig.type_covered),
+                sig.algorithm,
+                sig.labels,
+                sig.original_ttl,
+                sig.expiration,
+                sig.inception,
+                sig.key_tag,
+                fqdn(&sig.signer),
+                to_base64(&sig.signature)
+            ),
+            RData::DNSKEY(key) => write!(
+                f,
+                "{} {} {} {}",
+                key.flags,
+                key.protocol,
+                key.algorithm,
+                to_base64(&key.public_key)
+            ),
+            RData::NSEC(nsec) => write!(f, "{}{}", fqdn(&nsec.next), types(&nsec.types)),
+            RData::NSEC3(nsec3) => write!(
+                f,
+                "{} {} {} {} {}{}",
+                nsec3.hash_algorithm,
+                nsec3.flags,
+                nsec3.iterations,
+                // no salt is a "-"
+                if nsec3.salt.is_empty() {
+                    "-".to_string()
+                } else {
+                    to_hex(&nsec3.salt)
+                },
+                to_base32hex(&nsec3.next_hashed),
+                types(&nsec3.types)
+            ),
+            RData::TLSA(tlsa) => write!(
+                f,
+                "{} {} {} {}",
+                tlsa.usage,
+                tlsa.selector,
+                tlsa.matching_type,
+                to_hex(&tlsa.data)
+            ),
+            RData::SVCB(svcb) | RData::HTTPS(svcb) => {
+                write!(f, "{} {}", svcb.priority, fqdn(&svcb.target))?;
+                write_params(f, &svcb.params)
+            }
+            RData::CAA(caa) => write!(f, "{} {} {}", caa.flags, caa.tag, quoted(&caa.value)),
+            RData::Unknown(_, raw) => f.write_str(&generic(raw)),
+        }
+    }
+}
+
+impl RData {
+    // the RFC 3597 generic form of this rdata, whatever its type
+    pub fn to_generic(&self) -> String {
+        let mut raw = Vec::new();
+        match self.encode(&mut raw) {
+            Ok(()) => generic(&raw),
+            // only a name too long for the wire gets here, and then there's no rdata to show
+            Err(_) => generic(&[]),
+        }
+    }
+
+    // reads the generic form back: "\#", the length, then hex that may be split up by spaces.
+    // The rdata is decoded as `rr_type`, into Unknown for a type we don't know
+    pub fn from_generic(rr_type: u16, text: &str) -> Result<RData, DnsError> {
+        let bad = DnsError::Malformed("bad RFC 3597 generic rdata");
+        let mut words = text.split_whitespace();
+        if words.next() != Some("\\#") {
+            return Err(bad);
+        }
+        let len: u16 = words.next().and_then(|len| len.parse().ok()).ok_or(bad)?;
+        let hex: String = words.collect();
+        let raw = from_hex(&hex).ok_or(DnsError::Malformed("bad RFC 3597 generic rdata"))?;
+        if raw.len() != len as usize {
+            return Err(DnsError::Malformed("RFC 3597 rdata length doesn't match"));
+        }
+        let data = RData::decode(rr_type, &raw, 0, len)?;
+        Ok(data)
+    }
+}
+
+fn generic(raw: &[u8]) -> String {
+    if raw.is_empty() {
+        "\\# 0".to_string()
+    } else {
+        format!("\\# {} {}", raw.len(), to_hex(raw))
+    }
+}
+
+// names are kept without the trailing dot, presentation format always has it
+fn fqdn(name: &str) -> String {
+    format!("{}.", name)
+}
+
+// a character-string in double quotes, with " and \ escaped and anything unprintable as \DDD
+fn quoted(bytes: &[u8]) -> String {
+    let mut out = String::from("\"");
+    for &byte in bytes {
+        match byte {
+            b'"' | b'\\' => {
+                out.push('\\');
+                out.push(byte as char);
+            }
+            0x20..=0x7e => out.push(byte as char),
+            _ => {
+                let _ = write!(out, "\\{:03}", byte);
+            }
+        }
+    }
+    out.push('"');
+    out
+}
+
+// the type list of NSEC and NSEC3, each with a space in front
+fn types(types: &[u16]) -> String {
+    types
+        .iter()
+        .map(|t| format!(" {}", RecordType::from(*t)))
+        .collect()
+}
+
+// RFC 9460 section 2.1: key=value for each param, by key, the values of lists comma-separated
+fn write_params(f: &mut fmt::Formatter<'_>, params: &SvcParams) -> fmt::Result {
+    for param in params.iter() {
+        f.write_char(' ')?;
+        match param {
+            SvcParam::Mandatory(keys) => {
+                let keys: Vec<String> = keys.iter().map(|key| key_name(*key)).collect();
+                write!(f, "mandatory={}", keys.join(","))?
+            }
+            SvcParam::Alpn(ids) => {
+                // a comma inside an id would read as the end of it
+                let ids: Vec<String> = ids.iter().map(|id| id.replace(',', "\\\\,")).collect();
+                write!(f, "alpn={}", ids.join(","))?
+            }
+            SvcParam::NoDefaultAlpn => f.write_str("no-default-alpn")?,
+            SvcParam::Port(port) => write!(f, "port={}", port)?,
+            SvcParam::Ipv4Hint(addrs) => {
+                let addrs: Vec<String> = addrs.iter().map(|addr| addr.to_string()).collect();
+                write!(f, "ipv4hint={}", addrs.join(","))?
+            }
+            SvcParam::Ipv6Hint(addrs) => {
+                let addrs: Vec<String> = addrs.iter().map(|addr| addr.to_string()).collect();
+                write!(f, "ipv6hint={}", addrs.join(","))?
+            }
+            SvcParam::Ech(config) => write!(f, "ech={}", to_base64(config))?,
+            SvcParam::Unknown(key, value) => write!(f, "key{}={}", key, quoted(value))?,
+        }
+    }
+    Ok(())
+}
+
+fn key_name(key: u16) -> String {
+    match key {
+        0 => "mandatory".to_string(),
+        1 => "alpn".to_string(),
+        2 => "no-default-alpn".to_string(),
+        3 => "port".to_string(),
+        4 => "ipv4hint".to_string(),
+        5 => "ech".to_string(),
+        6 => "ipv6hint".to_string(),
+        _ => format!("key{}", key),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rdata::{Svcb, TYPE_A, TYPE_MX};
+    use crate::{Caa, Nsec3, Soa};
+    use alloc::vec;
+
+    #[test]
+    fn test_presentation() {
+        let cases = [
+            (RData::A([192, 0, 2, 1].into()), "192.0.2.1"),
+            (
+                RData::CNAME("www.example.com".to_string()),
+                "www.example.com.",
+            ),
+            (RData::NS(String::new()), "."),
+            (
+                RData::MX {
+                    preference: 10,
+                    exchange: "mail.example.com".to_string(),
+                },
+                "10 mail.example.com.",
+            ),
+            (
+                RData::SOA(Soa {
+                    mname: "ns1.example.com".to_string(),
+                    rname: "hostmaster.example.com".to_string(),
+                    serial: 2024010101,
+                    refresh: 7200,
+                    retry: 900,
+                    expire: 1209600,
+                    minimum: 300,
+                }),
+                "ns1.example.com. hostmaster.example.com. 2024010101 7200 900 1209600 300",
+            ),
+            (
+                RData::TXT(vec![b"v=spf1 -all".to_vec(), b"say \"hi\"\n".to_vec()]),
+                "\"v=spf1 -all\" \"say \\\"hi\\\"\\010\"",
+            ),
+            (
+                RData::NSEC3(Nsec3 {
+                    hash_algorithm: 1,
+                    flags: 1,
+                    iterations: 0,
+                    salt: Vec::new(),
+                    next_hashed: b"foobar".to_vec(),
+                    types: vec![1, 46],
+                }),
+                "1 1 0 - CPNMUOJ1E8 A RRSIG",
+            ),
+            (
+                RData::CAA(Caa {
+                    flags: 0,
+                    tag: "issue".to_string(),
+                    value: b"letsencrypt.org".to_vec(),
+                }),
+                "0 issue \"letsencrypt.org\"",
+            ),
+            (RData::Unknown(0xFF00, b"abc".to_vec()), "\\# 3 616263"),
+            (RData::Unknown(0xFF00, Vec::new()), "\\# 0"),
+        ];
+        for (data, text) in cases {
+            assert_eq!(data.to_string(), text);
+        }
+
+        let mut params = SvcParams::new();
+        params.insert(SvcParam::Alpn(vec!["h2".to_string(), "h3".to_string()]));
+        params.insert(SvcParam::Mandatory(vec![1]));
+        params.insert(SvcParam::Ipv4Hint(vec![[192, 0, 2, 1].into()]));
+        let https = RData::HTTPS(Svcb {
+            priority: 1,
+            target: String::new(),
+            params,
+        });
+        assert_eq!(
+            https.to_string(),
+            "1 . mandatory=alpn alpn=h2,h3 ipv4hint=192.0.2.1"
+        );
+    }
+
+    #[test]
+    fn test_generic_round_trip() {
+        // unknown types come back as they were
+        let unknown = RData::Unknown(0xFF00, b"\x00\x01\xfe".to_vec());
+        assert_eq!(
+            RData::from_generic(0xFF00, &unknown.to_string()).unwrap(),
+            unknown
+        );
+        // and known ones turn into their typed rdata, whatever the spacing of the hex
+        assert_eq!(
+            RData::from_generic(TYPE_A, "\\# 4 C000 0201").unwrap(),
+            RData::A([192, 0, 2, 1].into())
+        );
+        let mx = RData::MX {
+            preference: 10,
+            exchange: "mail.example.com".to_string(),
+        };
+        assert_eq!(
+            mx.to_generic(),
+            "\\# 20 000A046D61696C076578616D706C6503636F6D00"
+        );
+        assert_eq!(RData::from_generic(TYPE_MX, &mx.to_generic()).unwrap(), mx);
+        assert_eq!(
+            RData::from_generic(0xFF00, "\\# 0").unwrap(),
+            RData::Unknown(0xFF00, Vec::new())
+        );
+
+        for bad in [
+            "",
+            "# 1 00",
+            "\\# 2 00",
+            "\\# 1 0g",
+            "\\# x 00",
+            "\\# 1 000",
+        ] {
+            assert!(RData::from_generic(0xFF00, bad).is_err(), "{:?}", bad);
+        }
+        // a generic A that isn't 4 bytes is no A
+        assert!(RData::from_generic(TYPE_A, "\\# 3 C00002").is_err());
+    }
+}
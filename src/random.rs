@@ -0,0 +1,31 @@
+// unpredictable numbers without pulling in a crate for them. RandomState is SipHash keyed with
+// randomness from the OS (and re-keyed for every new one), so hashing a counter with it gives
+// output nobody can guess without knowing the keys. Not meant for key material, but plenty for
+// query IDs an off-path attacker must not be able to predict
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+static COUNTER: AtomicU64 = AtomicU64::new(0);
+
+pub(crate) fn random_u64() -> u64 {
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u64(COUNTER.fetch_add(1, Ordering::Relaxed));
+    hasher.finish()
+}
+
+pub(crate) fn random_u16() -> u16 {
+    random_u64() as u16
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ids_differ() {
+        // 16 draws all the same would be a 1 in 2^240 fluke
+        let ids: Vec<u16> = (0..16).map(|_| random_u16()).collect();
+        assert!(ids.iter().any(|&id| id != ids[0]));
+    }
+}
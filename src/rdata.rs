@@ -0,0 +1,1352 @@
+// typed rdata. `ResourceRecord::rdata` keeps the raw bytes, this is the decoded version of them
+// for the types we understand
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::cmp::Ordering;
+use core::net::{Ipv4Addr, Ipv6Addr};
+
+use crate::message::{parse_qname, write_name, write_qname, NameCompressor};
+use crate::DnsError;
+
+pub const TYPE_A: u16 = 1;
+pub const TYPE_NS: u16 = 2;
+pub const TYPE_CNAME: u16 = 5;
+pub const TYPE_SOA: u16 = 6;
+pub const TYPE_PTR: u16 = 12;
+pub const TYPE_MX: u16 = 15;
+pub const TYPE_TXT: u16 = 16;
+pub const TYPE_AAAA: u16 = 28;
+pub const TYPE_SRV: u16 = 33;
+pub const TYPE_DNAME: u16 = 39;
+// EDNS pseudo-record, see edns.rs
+pub const TYPE_OPT: u16 = 41;
+// DNSSEC (RFC 4034)
+pub const TYPE_DS: u16 = 43;
+pub const TYPE_RRSIG: u16 = 46;
+pub const TYPE_NSEC: u16 = 47;
+pub const TYPE_DNSKEY: u16 = 48;
+pub const TYPE_NSEC3: u16 = 50;
+// DANE (RFC 6698)
+pub const TYPE_TLSA: u16 = 52;
+// service bindings (RFC 9460)
+pub const TYPE_SVCB: u16 = 64;
+pub const TYPE_HTTPS: u16 = 65;
+// which CAs may issue certificates for the owner (RFC 8659)
+pub const TYPE_CAA: u16 = 257;
+pub const TYPE_TSIG: u16 = 250;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum RData {
+    A(Ipv4Addr),
+    AAAA(Ipv6Addr),
+    NS(String),
+    CNAME(String),
+    PTR(String),
+    MX { preference: u16, exchange: String },
+    SOA(Soa),
+    SRV(SrvRecord),
+    // like CNAME but for a whole subtree: every name under the owner moves under the target
+    DNAME(String),
+    // one or more character-strings, each at most 255 bytes (long SPF/DKIM values get split up).
+    // Bytes, not text: nothing says they're UTF-8, and whatever was sent has to go back out as is
+    TXT(Vec<Vec<u8>>),
+    // transaction signature, always the last record of a signed message
+    TSIG(Tsig),
+    // DNSSEC: the hash of a child zone's key, kept in the parent; a signature over one RRset;
+    // and a zone's public key
+    DS(Ds),
+    RRSIG(Rrsig),
+    DNSKEY(Dnskey),
+    // DNSSEC's proof that a name (or a type at it) doesn't exist: the next name in the zone
+    // after the owner, or the hash of it for NSEC3
+    NSEC(Nsec),
+    NSEC3(Nsec3),
+    // the certificate (or key) a TLS server at _port._proto.name is supposed to present
+    TLSA(Tlsa),
+    // where and how to connect to a service (RFC 9460): another name to go to, or a server with
+    // the protocols, port and addresses to use. HTTPS is the same thing for https:// URLs
+    SVCB(Svcb),
+    HTTPS(Svcb),
+    CAA(Caa),
+    // a type we don't decode (yet), the raw rdata is kept as is and displayed in the generic
+    // form of RFC 3597 (see presentation.rs)
+    Unknown(u16, Vec<u8>),
+}
+
+// start of authority: who is in charge of a zone and how long its data (and the lack of it)
+// may be cached
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Soa {
+    pub mname: String, // primary nameserver
+    pub rname: String, // mailbox of whoever runs the zone, with the @ turned into a dot
+    pub serial: u32,
+    pub refresh: u32,
+    pub retry: u32,
+    pub expire: u32,
+    pub minimum: u32, // also the TTL for negative answers (RFC 2308)
+}
+
+impl Soa {
+    // which zone version is the newer one, in serial number arithmetic (RFC 1982): serials wrap
+    // around, so 1 comes after 4294967295. None for two serials exactly half the space apart,
+    // which the RFC leaves undefined
+    pub fn compare_serial(&self, other: &Soa) -> Option<Ordering> {
+        match self.serial.wrapping_sub(other.serial) {
+            0 => Some(Ordering::Equal),
+            0x8000_0000 => None,
+            diff if (diff as i32) > 0 => Some(Ordering::Greater),
+            _ => Some(Ordering::Less),
+        }
+    }
+}
+
+// where a service lives (RFC 2782), found under names like _imap._tcp.example.com
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SrvRecord {
+    pub priority: u16, // lowest first, the others are only for when those are down
+    pub weight: u16,   // among the same priority, how much of the load each one should get
+    pub port: u16,
+    pub target: String, // "." means the service isn't offered at all
+}
+
+// the rdata of a TSIG record (RFC 8945 4.2). The owner name is the key's name
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Tsig {
+    pub algorithm: String, // hmac-sha256 and friends, as a domain name
+    pub time_signed: u64,  // seconds since the epoch, only 48 bits of it go on the wire
+    pub fudge: u16,        // how many seconds either way of time_signed we accept
+    pub mac: Vec<u8>,
+    pub original_id: u16, // the message ID when it was signed, in case a forwarder changed it
+    pub error: u16,       // the TSIG error the other side hit (BADSIG, BADKEY, BADTIME...)
+    pub other: Vec<u8>,   // the server's time when the error is BADTIME, empty otherwise
+}
+
+// the parent's hash of one of a child zone's keys (RFC 4034 5), which is how trust passes down
+// a delegation
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Ds {
+    pub key_tag: u16,
+    pub algorithm: u8,
+    pub digest_type: u8, // 1 SHA-1, 2 SHA-256, 4 SHA-384
+    pub digest: Vec<u8>,
+}
+
+// a signature over every record of one type at one name (RFC 4034 3)
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Rrsig {
+    pub type_covered: u16,
+    pub algorithm: u8,
+    // how many labels the owner had when it was signed, fewer than it has now for a record
+    // made up from a wildcard
+    pub labels: u8,
+    pub original_ttl: u32,
+    // seconds since the epoch, modulo 2^32 (RFC 1982 arithmetic)
+    pub expiration: u32,
+    pub inception: u32,
+    pub key_tag: u16,
+    pub signer: String, // the zone whose key made it
+    pub signature: Vec<u8>,
+}
+
+// a zone's public key (RFC 4034 2)
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Dnskey {
+    pub flags: u16,
+    pub protocol: u8, // always 3
+    pub algorithm: u8,
+    pub public_key: Vec<u8>,
+}
+
+// the ZONE flag: the key signs the zone's data. Without it the key is for something else
+const DNSKEY_ZONE: u16 = 0x0100;
+// REVOKE (RFC 5011): the key's owner says it's not to be trusted any more
+const DNSKEY_REVOKE: u16 = 0x0080;
+// SEP, "secure entry point": by convention the key-signing key the parent's DS points at
+const DNSKEY_SEP: u16 = 0x0001;
+
+impl Dnskey {
+    pub fn is_zone_key(&self) -> bool {
+        self.flags & DNSKEY_ZONE != 0
+    }
+
+    pub fn is_sep(&self) -> bool {
+        self.flags & DNSKEY_SEP != 0
+    }
+
+    pub fn is_revoked(&self) -> bool {
+        self.flags & DNSKEY_REVOKE != 0
+    }
+
+    // whether `other` is this key, revoked or not. Setting the flag changes the key tag, but
+    // not the key
+    pub fn same_key(&self, other: &Dnskey) -> bool {
+        self.flags & !DNSKEY_REVOKE == other.flags & !DNSKEY_REVOKE
+            && self.protocol == other.protocol
+            && self.algorithm == other.algorithm
+            && self.public_key == other.public_key
+    }
+
+    // the 16 bit checksum of the key's rdata that RRSIG and DS records name it by (RFC 4034
+    // appendix B). Not unique, two keys can share one
+    pub fn key_tag(&self) -> u16 {
+        let mut rdata = Vec::new();
+        RData::DNSKEY(self.clone())
+            .encode(&mut rdata)
+            .expect("DNSKEY rdata has no names to fail on");
+        let mut sum: u32 = 0;
+        for (i, byte) in rdata.iter().enumerate() {
+            sum += if i % 2 == 0 {
+                (*byte as u32) << 8
+            } else {
+                *byte as u32
+            };
+        }
+        sum += (sum >> 16) & 0xFFFF;
+        (sum & 0xFFFF) as u16
+    }
+}
+
+// the next name in the zone, in canonical order, and the types there are at the owner (RFC 4034 4).
+// No name sorts between the two
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Nsec {
+    pub next: String,
+    pub types: Vec<u16>,
+}
+
+// NSEC with hashed names (RFC 5155 3), so the zone can't be walked: the owner's first label is
+// the base32hex of the hash of a name, `next_hashed` the next hash along
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Nsec3 {
+    pub hash_algorithm: u8, // 1 SHA-1, the only one there is
+    pub flags: u8,
+    pub iterations: u16,
+    pub salt: Vec<u8>,
+    pub next_hashed: Vec<u8>,
+    pub types: Vec<u16>,
+}
+
+// opt-out: the hashes this one covers may hide unsigned delegations
+const NSEC3_OPT_OUT: u8 = 0x01;
+
+impl Nsec3 {
+    pub fn opt_out(&self) -> bool {
+        self.flags & NSEC3_OPT_OUT != 0
+    }
+}
+
+// one certificate association for DANE (RFC 6698 section 2.1): which certificate of the chain
+// it's about, which part of it, and that part itself or its hash
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Tlsa {
+    // 0 a CA in the PKIX chain, 1 the server's own certificate (PKIX checks still apply), 2 a
+    // trust anchor of our own, 3 the server's certificate and no PKIX at all
+    pub usage: u8,
+    // 0 the whole certificate, 1 just its SubjectPublicKeyInfo
+    pub selector: u8,
+    // 0 `data` is the selected bytes themselves, 1 their SHA-256, 2 their SHA-512
+    pub matching_type: u8,
+    pub data: Vec<u8>,
+}
+
+// the rdata of SVCB and HTTPS records. Priority 0 is AliasMode: `target` is another name to
+// look the service up under and there are no params. Anything else is ServiceMode, lowest first,
+// with a target of "." meaning the owner itself
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Svcb {
+    pub priority: u16,
+    pub target: String,
+    pub params: SvcParams,
+}
+
+impl Svcb {
+    pub fn is_alias(&self) -> bool {
+        self.priority == 0
+    }
+}
+
+// the SvcParamKeys from the RFC 9460 registry we know by name
+pub const SVC_MANDATORY: u16 = 0;
+pub const SVC_ALPN: u16 = 1;
+pub const SVC_NO_DEFAULT_ALPN: u16 = 2;
+pub const SVC_PORT: u16 = 3;
+pub const SVC_IPV4HINT: u16 = 4;
+pub const SVC_ECH: u16 = 5;
+pub const SVC_IPV6HINT: u16 = 6;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum SvcParam {
+    // the keys a client has to understand to use the record at all
+    Mandatory(Vec<u16>),
+    // the protocols on offer (h2, h3...), on top of the scheme's default one
+    Alpn(Vec<String>),
+    // and the default one (http/1.1 for HTTPS) isn't
+    NoDefaultAlpn,
+    Port(u16),
+    // addresses to try before (or while) the target's A and AAAA come in
+    Ipv4Hint(Vec<Ipv4Addr>),
+    Ipv6Hint(Vec<Ipv6Addr>),
+    // an ECHConfigList for Encrypted Client Hello, opaque to us
+    Ech(Vec<u8>),
+    Unknown(u16, Vec<u8>),
+}
+
+impl SvcParam {
+    pub fn key(&self) -> u16 {
+        match self {
+            SvcParam::Mandatory(_) => SVC_MANDATORY,
+            SvcParam::Alpn(_) => SVC_ALPN,
+            SvcParam::NoDefaultAlpn => SVC_NO_DEFAULT_ALPN,
+            SvcParam::Port(_) => SVC_PORT,
+            SvcParam::Ipv4Hint(_) => SVC_IPV4HINT,
+            SvcParam::Ech(_) => SVC_ECH,
+            SvcParam::Ipv6Hint(_) => SVC_IPV6HINT,
+            SvcParam::Unknown(key, _) => *key,
+        }
+    }
+
+    fn encode_value(&self, out: &mut Vec<u8>) {
+        match self {
+            SvcParam::Mandatory(keys) => {
+                for key in keys {
+                    out.extend(key.to_be_bytes());
+                }
+            }
+            SvcParam::Alpn(ids) => {
+                for id in ids {
+                    out.push(id.len() as u8);
+                    out.extend(id.as_bytes());
+                }
+            }
+            SvcParam::NoDefaultAlpn => {}
+            SvcParam::Port(port) => out.extend(port.to_be_bytes()),
+            SvcParam::Ipv4Hint(addrs) => {
+                for addr in addrs {
+                    out.extend(addr.octets());
+                }
+            }
+            SvcParam::Ipv6Hint(addrs) => {
+                for addr in addrs {
+                    out.extend(addr.octets());
+                }
+            }
+            SvcParam::Ech(config) | SvcParam::Unknown(_, config) => out.extend(config),
+        }
+    }
+
+    // a value that doesn't have the shape its key calls for makes the whole record malformed
+    // (RFC 9460 section 2.2), rather than being skipped
+    fn decode(key: u16, value: &[u8]) -> Result<SvcParam, DnsError> {
+        let bad = DnsError::Malformed("bad SvcParam value");
+        let param = match key {
+            SVC_MANDATORY if !value.is_empty() && value.len().is_multiple_of(2) => {
+                SvcParam::Mandatory(
+                    value
+                        .chunks(2)
+                        .map(|key| u16::from_be_bytes([key[0], key[1]]))
+                        .collect(),
+                )
+            }
+            SVC_ALPN => {
+                let mut ids = Vec::new();
+                let mut rest = value;
+                while let Some((&len, tail)) = rest.split_first() {
+                    if len == 0 || tail.len() < len as usize {
+                        return Err(bad);
+                    }
+                    let (id, tail) = tail.split_at(len as usize);
+                    ids.push(String::from_utf8_lossy(id).into_owned());
+                    rest = tail;
+                }
+                if ids.is_empty() {
+                    return Err(bad);
+                }
+                SvcParam::Alpn(ids)
+            }
+            SVC_NO_DEFAULT_ALPN if value.is_empty() => SvcParam::NoDefaultAlpn,
+            SVC_PORT if value.len() == 2 => {
+                SvcParam::Port(u16::from_be_bytes([value[0], value[1]]))
+            }
+            SVC_IPV4HINT if !value.is_empty() && value.len().is_multiple_of(4) => {
+                SvcParam::Ipv4Hint(
+                    value
+                        .chunks(4)
+                        .map(|b| Ipv4Addr::new(b[0], b[1], b[2], b[3]))
+                        .collect(),
+                )
+            }
+            SVC_IPV6HINT if !value.is_empty() && value.len().is_multiple_of(16) => {
+                SvcParam::Ipv6Hint(
+                    value
+                        .chunks(16)
+                        .map(|b| {
+                            let mut octets = [0u8; 16];
+                            octets.copy_from_slice(b);
+                            Ipv6Addr::from(octets)
+                        })
+                        .collect(),
+                )
+            }
+            SVC_ECH => SvcParam::Ech(value.to_vec()),
+            SVC_MANDATORY | SVC_NO_DEFAULT_ALPN | SVC_PORT | SVC_IPV4HINT | SVC_IPV6HINT => {
+                return Err(bad)
+            }
+            _ => SvcParam::Unknown(key, value.to_vec()),
+        };
+        Ok(param)
+    }
+}
+
+// the params of one SVCB/HTTPS record by key, which is also the order they go on the wire in
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SvcParams(BTreeMap<u16, SvcParam>);
+
+impl SvcParams {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // replacing any param with the same key
+    pub fn insert(&mut self, param: SvcParam) {
+        self.0.insert(param.key(), param);
+    }
+
+    pub fn get(&self, key: u16) -> Option<&SvcParam> {
+        self.0.get(&key)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &SvcParam> {
+        self.0.values()
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn alpn(&self) -> Option<&[String]> {
+        match self.get(SVC_ALPN)? {
+            SvcParam::Alpn(ids) => Some(ids),
+            _ => None,
+        }
+    }
+
+    pub fn port(&self) -> Option<u16> {
+        match self.get(SVC_PORT)? {
+            SvcParam::Port(port) => Some(*port),
+            _ => None,
+        }
+    }
+
+    pub fn ipv4hint(&self) -> &[Ipv4Addr] {
+        match self.get(SVC_IPV4HINT) {
+            Some(SvcParam::Ipv4Hint(addrs)) => addrs,
+            _ => &[],
+        }
+    }
+
+    pub fn ipv6hint(&self) -> &[Ipv6Addr] {
+        match self.get(SVC_IPV6HINT) {
+            Some(SvcParam::Ipv6Hint(addrs)) => addrs,
+            _ => &[],
+        }
+    }
+
+    pub fn ech(&self) -> Option<&[u8]> {
+        match self.get(SVC_ECH)? {
+            SvcParam::Ech(config) => Some(config),
+            _ => None,
+        }
+    }
+
+    fn encode(&self, out: &mut Vec<u8>) {
+        for param in self.iter() {
+            let mut value = Vec::new();
+            param.encode_value(&mut value);
+            out.extend(param.key().to_be_bytes());
+            out.extend((value.len() as u16).to_be_bytes());
+            out.extend(value);
+        }
+    }
+
+    // keys have to come in strictly increasing order, so a repeated one is malformed too
+    fn decode(rd: &mut RdataReader) -> Result<SvcParams, DnsError> {
+        let mut params = SvcParams::new();
+        let mut last = None;
+        while rd.pos < rd.end {
+            let key = rd.u16()?;
+            if last.is_some_and(|last| key <= last) {
+                return Err(DnsError::Malformed("SvcParam keys out of order"));
+            }
+            last = Some(key);
+            let len = rd.u16()? as usize;
+            params.insert(SvcParam::decode(key, rd.take(len)?)?);
+        }
+        Ok(params)
+    }
+}
+
+// one property of a CAA record (RFC 8659 section 4.1): "issue" with the CA's domain, "iodef"
+// with where to report to, and so on. The value's syntax is up to the tag
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Caa {
+    pub flags: u8,
+    pub tag: String,
+    pub value: Vec<u8>,
+}
+
+// the Issuer Critical flag: a CA that doesn't know the tag mustn't issue at all
+const CAA_CRITICAL: u8 = 0x80;
+
+impl Caa {
+    pub fn is_critical(&self) -> bool {
+        self.flags & CAA_CRITICAL != 0
+    }
+
+    // the value as text, which is what every tag defined so far has
+    pub fn value_str(&self) -> Option<&str> {
+        core::str::from_utf8(&self.value).ok()
+    }
+}
+
+// the type bitmap of NSEC and NSEC3 (RFC 4034 4.1.2): per block of 256 types that has any, the
+// block number, how many bytes of bits follow and the bits, the highest bit of the first byte
+// for the lowest type
+fn write_types(out: &mut Vec<u8>, types: &[u16]) {
+    let mut types = types.to_vec();
+    types.sort_unstable();
+    types.dedup();
+    for window in types.chunk_by(|a, b| a >> 8 == b >> 8) {
+        let last = (window[window.len() - 1] & 0xFF) as usize;
+        let mut bits = vec![0u8; last / 8 + 1];
+        for t in window {
+            let low = (t & 0xFF) as usize;
+            bits[low / 8] |= 0x80 >> (low % 8);
+        }
+        out.extend([(window[0] >> 8) as u8, bits.len() as u8]);
+        out.extend(bits);
+    }
+}
+
+// only the one way write_types would have put it: blocks in order, no empty ones, no zero bytes
+// at the end of one. Anything else would come out different from what went in (and signed)
+fn read_types(rd: &mut RdataReader) -> Result<Vec<u16>, DnsError> {
+    let mut types = Vec::new();
+    let mut last = None;
+    while rd.pos < rd.end {
+        let window = rd.u8()? as u16;
+        let len = rd.u8()? as usize;
+        if len == 0 || len > 32 || last.is_some_and(|last| window <= last) {
+            return Err(DnsError::Malformed("bad NSEC type bitmap"));
+        }
+        last = Some(window);
+        let bits = rd.take(len)?;
+        if bits[len - 1] == 0 {
+            return Err(DnsError::Malformed("bad NSEC type bitmap"));
+        }
+        for (i, byte) in bits.iter().enumerate() {
+            for bit in 0..8 {
+                if byte & (0x80 >> bit) != 0 {
+                    types.push(window << 8 | (i * 8 + bit) as u16);
+                }
+            }
+        }
+    }
+    Ok(types)
+}
+
+impl RData {
+    pub fn rr_type(&self) -> u16 {
+        match self {
+            RData::A(_) => TYPE_A,
+            RData::AAAA(_) => TYPE_AAAA,
+            RData::NS(_) => TYPE_NS,
+            RData::CNAME(_) => TYPE_CNAME,
+            RData::PTR(_) => TYPE_PTR,
+            RData::MX { .. } => TYPE_MX,
+            RData::SOA(_) => TYPE_SOA,
+            RData::SRV(_) => TYPE_SRV,
+            RData::DNAME(_) => TYPE_DNAME,
+            RData::TXT(_) => TYPE_TXT,
+            RData::TSIG(_) => TYPE_TSIG,
+            RData::DS(_) => TYPE_DS,
+            RData::RRSIG(_) => TYPE_RRSIG,
+            RData::DNSKEY(_) => TYPE_DNSKEY,
+            RData::NSEC(_) => TYPE_NSEC,
+            RData::NSEC3(_) => TYPE_NSEC3,
+            RData::TLSA(_) => TYPE_TLSA,
+            RData::SVCB(_) => TYPE_SVCB,
+            RData::HTTPS(_) => TYPE_HTTPS,
+            RData::CAA(_) => TYPE_CAA,
+            RData::Unknown(rr_type, _) => *rr_type,
+        }
+    }
+
+    // the wire form of the rdata, names written out in full (no compression)
+    pub(crate) fn encode(&self, out: &mut Vec<u8>) -> Result<(), DnsError> {
+        self.encode_compressed(out, None)
+    }
+
+    // with `names`, `out` is the whole message so far and names in the rdata may be compressed.
+    // Only for the types from RFC 1035 though: RFC 3597 says names in anything newer (DNAME,
+    // SRV...) go out in full, whoever reads them might not know the type and couldn't follow
+    // the pointer
+    pub(crate) fn encode_compressed(
+        &self,
+        out: &mut Vec<u8>,
+        mut names: Option<&mut NameCompressor>,
+    ) -> Result<(), DnsError> {
+        let mut name = |out: &mut Vec<u8>, name: &str| match names.as_deref_mut() {
+            Some(names) => write_name(out, name, names),
+            None => write_qname(out, name),
+        };
+        match self {
+            RData::A(ip) => out.extend(ip.octets()),
+            RData::AAAA(ip) => out.extend(ip.octets()),
+            RData::NS(target) | RData::CNAME(target) | RData::PTR(target) => name(out, target)?,
+            RData::DNAME(target) => write_qname(out, target)?,
+            RData::MX {
+                preference,
+                exchange,
+            } => {
+                out.extend(preference.to_be_bytes());
+                name(out, exchange)?;
+            }
+            RData::SOA(soa) => {
+                name(out, &soa.mname)?;
+                name(out, &soa.rname)?;
+                for value in [soa.serial, soa.refresh, soa.retry, soa.expire, soa.minimum] {
+                    out.extend(value.to_be_bytes());
+                }
+            }
+            RData::SRV(srv) => {
+                for value in [srv.priority, srv.weight, srv.port] {
+                    out.extend(value.to_be_bytes());
+                }
+                write_qname(out, &srv.target)?;
+            }
+            RData::TXT(strings) => {
+                for string in strings {
+                    // a character-string can't be longer than 255 bytes, anything bigger is
+                    // split over several of them (which is what a server would have sent us)
+                    if string.is_empty() {
+                        out.push(0);
+                    }
+                    for chunk in string.chunks(255) {
+                        out.push(chunk.len() as u8);
+                        out.extend(chunk);
+                    }
+                }
+            }
+            RData::TSIG(tsig) => {
+                write_qname(out, &tsig.algorithm)?;
+                out.extend(&tsig.time_signed.to_be_bytes()[2..]);
+                out.extend(tsig.fudge.to_be_bytes());
+                out.extend((tsig.mac.len() as u16).to_be_bytes());
+                out.extend(&tsig.mac);
+                out.extend(tsig.original_id.to_be_bytes());
+                out.extend(tsig.error.to_be_bytes());
+                out.extend((tsig.other.len() as u16).to_be_bytes());
+                out.extend(&tsig.other);
+            }
+            RData::DS(ds) => {
+                out.extend(ds.key_tag.to_be_bytes());
+                out.extend([ds.algorithm, ds.digest_type]);
+                out.extend(&ds.digest);
+            }
+            RData::RRSIG(sig) => {
+                out.extend(sig.type_covered.to_be_bytes());
+                out.extend([sig.algorithm, sig.labels]);
+                for value in [sig.original_ttl, sig.expiration, sig.inception] {
+                    out.extend(value.to_be_bytes());
+                }
+                out.extend(sig.key_tag.to_be_bytes());
+                write_qname(out, &sig.signer)?;
+                out.extend(&sig.signature);
+            }
+            RData::DNSKEY(key) => {
+                out.extend(key.flags.to_be_bytes());
+                out.extend([key.protocol, key.algorithm]);
+                out.extend(&key.public_key);
+            }
+            RData::NSEC(nsec) => {
+                write_qname(out, &nsec.next)?;
+                write_types(out, &nsec.types);
+            }
+            RData::NSEC3(nsec3) => {
+                out.extend([nsec3.hash_algorithm, nsec3.flags]);
+                out.extend(nsec3.iterations.to_be_bytes());
+                out.push(nsec3.salt.len() as u8);
+                out.extend(&nsec3.salt);
+                out.push(nsec3.next_hashed.len() as u8);
+                out.extend(&nsec3.next_hashed);
+                write_types(out, &nsec3.types);
+            }
+            RData::TLSA(tlsa) => {
+                out.extend([tlsa.usage, tlsa.selector, tlsa.matching_type]);
+                out.extend(&tlsa.data);
+            }
+            RData::SVCB(svcb) | RData::HTTPS(svcb) => {
+                out.extend(svcb.priority.to_be_bytes());
+                write_qname(out, &svcb.target)?;
+                svcb.params.encode(out);
+            }
+            RData::CAA(caa) => {
+                out.extend([caa.flags, caa.tag.len() as u8]);
+                out.extend(caa.tag.as_bytes());
+                out.extend(&caa.value);
+            }
+            RData::Unknown(_, raw) => out.extend(raw),
+        }
+        Ok(())
+    }
+
+    // `buf` is the whole message, not just the rdata: names inside rdata can be compression
+    // pointers to anywhere before them. The rdata itself is buf[start..start + rdlength]
+    pub(crate) fn decode(
+        rr_type: u16,
+        buf: &[u8],
+        start: usize,
+        rdlength: u16,
+    ) -> Result<RData, DnsError> {
+        let end = start + rdlength as usize;
+        let mut rd = RdataReader {
+            buf,
+            pos: start,
+            end,
+            rr_type,
+        };
+        // no rdata at all is how a dynamic update (RFC 2136) means a whole RRset rather than one
+        // record of it, whatever the type
+        if rdlength == 0 {
+            return Ok(RData::Unknown(rr_type, Vec::new()));
+        }
+
+        let data = match rr_type {
+            TYPE_A => {
+                let b = rd.take(4)?;
+                RData::A(Ipv4Addr::new(b[0], b[1], b[2], b[3]))
+            }
+            TYPE_AAAA => {
+                let mut octets = [0u8; 16];
+                octets.copy_from_slice(rd.take(16)?);
+                RData::AAAA(Ipv6Addr::from(octets))
+            }
+            TYPE_NS => RData::NS(rd.name()?),
+            TYPE_CNAME => RData::CNAME(rd.name()?),
+            TYPE_PTR => RData::PTR(rd.name()?),
+            TYPE_DNAME => RData::DNAME(rd.name()?),
+            TYPE_MX => RData::MX {
+                preference: rd.u16()?,
+                exchange: rd.name()?,
+            },
+            TYPE_SOA => RData::SOA(Soa {
+                mname: rd.name()?,
+                rname: rd.name()?,
+                serial: rd.u32()?,
+                refresh: rd.u32()?,
+                retry: rd.u32()?,
+                expire: rd.u32()?,
+                minimum: rd.u32()?,
+            }),
+            TYPE_SRV => RData::SRV(SrvRecord {
+                priority: rd.u16()?,
+                weight: rd.u16()?,
+                port: rd.u16()?,
+                target: rd.name()?,
+            }),
+            TYPE_TXT => {
+                let mut strings = Vec::new();
+                while rd.pos < rd.end {
+                    let len = rd.take(1)?[0] as usize;
+                    strings.push(rd.take(len)?.to_vec());
+                }
+                RData::TXT(strings)
+            }
+            TYPE_TSIG => {
+                let algorithm = rd.name()?;
+                let mut time = [0u8; 8];
+                time[2..].copy_from_slice(rd.take(6)?);
+                let fudge = rd.u16()?;
+                let mac_len = rd.u16()? as usize;
+                let mac = rd.take(mac_len)?.to_vec();
+                let original_id = rd.u16()?;
+                let error = rd.u16()?;
+                let other_len = rd.u16()? as usize;
+                RData::TSIG(Tsig {
+                    algorithm,
+                    time_signed: u64::from_be_bytes(time),
+                    fudge,
+                    mac,
+                    original_id,
+                    error,
+                    other: rd.take(other_len)?.to_vec(),
+                })
+            }
+            TYPE_DS => RData::DS(Ds {
+                key_tag: rd.u16()?,
+                algorithm: rd.u8()?,
+                digest_type: rd.u8()?,
+                digest: rd.rest().to_vec(),
+            }),
+            TYPE_RRSIG => RData::RRSIG(Rrsig {
+                type_covered: rd.u16()?,
+                algorithm: rd.u8()?,
+                labels: rd.u8()?,
+                original_ttl: rd.u32()?,
+                expiration: rd.u32()?,
+                inception: rd.u32()?,
+                key_tag: rd.u16()?,
+                signer: rd.name()?,
+                signature: rd.rest().to_vec(),
+            }),
+            TYPE_DNSKEY => RData::DNSKEY(Dnskey {
+                flags: rd.u16()?,
+                protocol: rd.u8()?,
+                algorithm: rd.u8()?,
+                public_key: rd.rest().to_vec(),
+            }),
+            TYPE_NSEC => RData::NSEC(Nsec {
+                next: rd.name()?,
+                types: read_types(&mut rd)?,
+            }),
+            TYPE_NSEC3 => {
+                let hash_algorithm = rd.u8()?;
+                let flags = rd.u8()?;
+                let iterations = rd.u16()?;
+                let salt_len = rd.u8()? as usize;
+                let salt = rd.take(salt_len)?.to_vec();
+                let hash_len = rd.u8()? as usize;
+                RData::NSEC3(Nsec3 {
+                    hash_algorithm,
+                    flags,
+                    iterations,
+                    salt,
+                    next_hashed: rd.take(hash_len)?.to_vec(),
+                    types: read_types(&mut rd)?,
+                })
+            }
+            TYPE_TLSA => RData::TLSA(Tlsa {
+                usage: rd.u8()?,
+                selector: rd.u8()?,
+                matching_type: rd.u8()?,
+                data: rd.rest().to_vec(),
+            }),
+            TYPE_SVCB | TYPE_HTTPS => {
+                let svcb = Svcb {
+                    priority: rd.u16()?,
+                    target: rd.name()?,
+                    params: SvcParams::decode(&mut rd)?,
+                };
+                match rr_type {
+                    TYPE_SVCB => RData::SVCB(svcb),
+                    _ => RData::HTTPS(svcb),
+                }
+            }
+            TYPE_CAA => {
+                let flags = rd.u8()?;
+                // 1 to 15 letters and digits
+                let tag_len = rd.u8()? as usize;
+                let tag = rd.take(tag_len)?;
+                if tag.is_empty() || tag.len() > 15 || !tag.iter().all(u8::is_ascii_alphanumeric) {
+                    return Err(DnsError::Malformed("bad CAA tag"));
+                }
+                RData::CAA(Caa {
+                    flags,
+                    tag: String::from_utf8_lossy(tag).into_owned(),
+                    value: rd.rest().to_vec(),
+                })
+            }
+            _ => RData::Unknown(rr_type, buf[start..end].to_vec()),
+        };
+        Ok(data)
+    }
+}
+
+// walks the fields of one record's rdata and refuses to step past its rdlength. With pointer
+// compression a name's encoded size says nothing about the name itself, so a record can claim
+// fewer bytes than its rdata actually takes, and parsing it blindly would eat into the next
+// record
+struct RdataReader<'a> {
+    buf: &'a [u8],
+    pos: usize,
+    end: usize,
+    rr_type: u16,
+}
+
+impl RdataReader<'_> {
+    fn take(&mut self, len: usize) -> Result<&[u8], DnsError> {
+        if self.pos + len > self.end {
+            return Err(self.overrun());
+        }
+        let bytes = &self.buf[self.pos..self.pos + len];
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8, DnsError> {
+        Ok(self.take(1)?[0])
+    }
+
+    // whatever is left of the rdata, for a field that runs to the end of it
+    fn rest(&mut self) -> &[u8] {
+        let bytes = &self.buf[self.pos..self.end];
+        self.pos = self.end;
+        bytes
+    }
+
+    fn u16(&mut self) -> Result<u16, DnsError> {
+        let bytes = self.take(2)?;
+        Ok(u16::from_be_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn u32(&mut self) -> Result<u32, DnsError> {
+        let bytes = self.take(4)?;
+        Ok(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    fn name(&mut self) -> Result<String, DnsError> {
+        if self.pos >= self.end {
+            return Err(self.overrun());
+        }
+        let (name, next) = parse_qname(self.buf, self.pos)?;
+        if next > self.end {
+            return Err(self.overrun());
+        }
+        self.pos = next;
+        Ok(name)
+    }
+
+    fn overrun(&self) -> DnsError {
+        DnsError::RdataOverrun {
+            rr_type: self.rr_type,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::DnsMessage;
+    use alloc::string::ToString;
+    use alloc::vec;
+
+    // a response for example.com/A whose answers are (type, rdlength, rdata bytes). rdlength is
+    // given separately so tests can lie about it
+    fn response(answers: &[(u16, u16, &[u8])]) -> Vec<u8> {
+        let mut bytes = vec![
+            0x12,
+            0x34,
+            0x81,
+            0x80,
+            0,
+            1,
+            0,
+            answers.len() as u8,
+            0,
+            0,
+            0,
+            0,
+        ];
+        bytes.extend(b"\x07example\x03com\x00\x00\x01\x00\x01");
+        for (rr_type, rdlength, rdata) in answers {
+            bytes.extend(&[0xC0, 0x0C]);
+            bytes.extend(&rr_type.to_be_bytes());
+            bytes.extend(&[0, 1, 0, 0, 0x0E, 0x10]); // IN, TTL 3600
+            bytes.extend(&rdlength.to_be_bytes());
+            bytes.extend(*rdata);
+        }
+        bytes
+    }
+
+    #[test]
+    fn test_decode_compressed_names_in_rdata() {
+        let buf = response(&[
+            (TYPE_CNAME, 6, b"\x03www\xC0\x0C"),
+            (TYPE_MX, 9, b"\x00\x0A\x04mail\xC0\x0C"),
+        ]);
+        let msg = DnsMessage::from_bytes(&buf).unwrap();
+
+        assert_eq!(
+            msg.answers[0].data,
+            RData::CNAME("www.example.com".to_string())
+        );
+        assert_eq!(
+            msg.answers[1].data,
+            RData::MX {
+                preference: 10,
+                exchange: "mail.example.com".to_string()
+            }
+        );
+    }
+
+    #[test]
+    fn test_decode_soa_with_compressed_names() {
+        // both names point back into the question, then the five counters
+        let mut rdata = b"\x03ns1\xC0\x0C\x0Ahostmaster\xC0\x0C".to_vec();
+        for value in [2024010101u32, 7200, 900, 1209600, 300] {
+            rdata.extend(value.to_be_bytes());
+        }
+        let buf = response(&[(TYPE_SOA, rdata.len() as u16, &rdata)]);
+        let msg = DnsMessage::from_bytes(&buf).unwrap();
+        assert_eq!(
+            msg.answers[0].data,
+            RData::SOA(Soa {
+                mname: "ns1.example.com".to_string(),
+                rname: "hostmaster.example.com".to_string(),
+                serial: 2024010101,
+                refresh: 7200,
+                retry: 900,
+                expire: 1209600,
+                minimum: 300,
+            })
+        );
+
+        // one counter short
+        let buf = response(&[(TYPE_SOA, rdata.len() as u16 - 4, &rdata[..rdata.len() - 4])]);
+        let err = DnsMessage::from_bytes(&buf).unwrap_err();
+        assert!(matches!(err, DnsError::RdataOverrun { rr_type: TYPE_SOA }));
+    }
+
+    #[test]
+    fn test_compare_serial() {
+        let soa = |serial| Soa {
+            mname: "ns1.example.com".to_string(),
+            rname: "admin.example.com".to_string(),
+            serial,
+            refresh: 3600,
+            retry: 600,
+            expire: 86400,
+            minimum: 60,
+        };
+        assert_eq!(
+            soa(2024010102).compare_serial(&soa(2024010101)),
+            Some(Ordering::Greater)
+        );
+        assert_eq!(soa(7).compare_serial(&soa(7)), Some(Ordering::Equal));
+        // wrapped around: 1 is newer than the biggest serial there is
+        assert_eq!(
+            soa(1).compare_serial(&soa(u32::MAX)),
+            Some(Ordering::Greater)
+        );
+        assert_eq!(soa(u32::MAX).compare_serial(&soa(1)), Some(Ordering::Less));
+        assert_eq!(soa(0).compare_serial(&soa(0x8000_0000)), None);
+    }
+
+    #[test]
+    fn test_srv_round_trip() {
+        let buf = response(&[(TYPE_SRV, 12, b"\x00\x0A\x00\x05\x14\x6E\x03sip\xC0\x0C")]);
+        let msg = DnsMessage::from_bytes(&buf).unwrap();
+        let srv = RData::SRV(SrvRecord {
+            priority: 10,
+            weight: 5,
+            port: 5230,
+            target: "sip.example.com".to_string(),
+        });
+        assert_eq!(msg.answers[0].data, srv);
+
+        let reparsed = DnsMessage::from_bytes(&msg.to_bytes().unwrap()).unwrap();
+        assert_eq!(reparsed.answers[0].data, srv);
+    }
+
+    #[test]
+    fn test_rdlength_smaller_than_name_is_an_overrun() {
+        // the name is 5 bytes ([3]foo[0]) but the record only owns 3 of them
+        let buf = response(&[(TYPE_CNAME, 3, b"\x03foo\x00")]);
+        let err = DnsMessage::from_bytes(&buf).unwrap_err();
+        assert!(matches!(
+            err,
+            DnsError::RdataOverrun {
+                rr_type: TYPE_CNAME
+            }
+        ));
+
+        // same thing with the fixed-size part of an MX
+        let buf = response(&[(TYPE_MX, 1, b"\x00\x0A\x00")]);
+        let err = DnsMessage::from_bytes(&buf).unwrap_err();
+        assert!(matches!(err, DnsError::RdataOverrun { rr_type: TYPE_MX }));
+    }
+
+    #[test]
+    fn test_pointer_loops_in_rdata_are_malformed() {
+        let looping = |buf: Vec<u8>| {
+            let err = DnsMessage::from_bytes(&buf).unwrap_err();
+            assert!(matches!(
+                err,
+                DnsError::Malformed("compression pointer doesn't point backwards")
+            ));
+        };
+        // the first rdata starts at 41: a CNAME pointing at itself
+        looping(response(&[(TYPE_CNAME, 2, b"\xC0\x29")]));
+        // and two CNAMEs pointing at each other, the second rdata starting at 57
+        looping(response(&[
+            (TYPE_CNAME, 4, b"\x01a\xC0\x39"),
+            (TYPE_CNAME, 4, b"\x01b\xC0\x29"),
+        ]));
+    }
+
+    #[test]
+    fn test_next_record_starts_after_rdlength() {
+        // a trailing junk byte inside the first record's rdata must not shift the second record
+        let buf = response(&[
+            (TYPE_NS, 6, b"\x02ns\xC0\x0C\xFF"),
+            (TYPE_PTR, 6, b"\x03ptr\xC0\x0C"),
+        ]);
+        let msg = DnsMessage::from_bytes(&buf).unwrap();
+
+        assert_eq!(msg.answers[0].data, RData::NS("ns.example.com".to_string()));
+        assert_eq!(
+            msg.answers[1].data,
+            RData::PTR("ptr.example.com".to_string())
+        );
+    }
+
+    #[test]
+    fn test_any_response_decodes_every_type() {
+        let buf = response(&[
+            (TYPE_A, 4, b"\x5D\xB8\xD8\x22"),
+            (TYPE_MX, 9, b"\x00\x0A\x04mail\xC0\x0C"),
+            (TYPE_TXT, 13, b"\x06v=spf1\x05-all "),
+            (TYPE_A, 4, b"\x5D\xB8\xD8\x23"),
+            (
+                TYPE_AAAA,
+                16,
+                &[0x26, 0x06, 0x28, 0x00, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1],
+            ),
+        ]);
+        let msg = DnsMessage::from_bytes(&buf).unwrap();
+
+        assert_eq!(
+            msg.answers[0].data,
+            RData::A(Ipv4Addr::new(93, 184, 216, 34))
+        );
+        assert_eq!(
+            msg.answers[2].data,
+            RData::TXT(vec![b"v=spf1".to_vec(), b"-all ".to_vec()])
+        );
+        assert_eq!(
+            msg.answers[4].data,
+            RData::AAAA("2606:2800::1".parse().unwrap())
+        );
+
+        #[cfg(feature = "std")]
+        {
+            let by_type = msg.answers_by_type();
+            assert_eq!(by_type.len(), 4);
+            assert_eq!(by_type[&TYPE_A].len(), 2);
+            assert_eq!(by_type[&TYPE_MX][0].data, msg.answers[1].data);
+            assert_eq!(by_type[&TYPE_TXT].len(), 1);
+            assert_eq!(by_type[&TYPE_AAAA].len(), 1);
+        }
+    }
+
+    #[test]
+    fn test_binary_txt_round_trip() {
+        // not UTF-8, and it has to come back out the way it went in
+        let buf = response(&[(TYPE_TXT, 6, b"\x02\xff\xfe\x00\x01\x80")]);
+        let msg = DnsMessage::from_bytes(&buf).unwrap();
+        assert_eq!(
+            msg.answers[0].data,
+            RData::TXT(vec![vec![0xff, 0xfe], vec![], vec![0x80]])
+        );
+        let again = DnsMessage::from_bytes(&msg.to_bytes().unwrap()).unwrap();
+        assert_eq!(again.answers[0].rdata, b"\x02\xff\xfe\x00\x01\x80");
+        assert_eq!(again.answers[0].data, msg.answers[0].data);
+    }
+
+    #[test]
+    fn test_nsec_type_bitmap() {
+        // RFC 4034 4.3: A MX RRSIG NSEC TYPE1234, in two blocks
+        let nsec = RData::NSEC(Nsec {
+            next: "host.example.com".to_string(),
+            types: vec![TYPE_NSEC, TYPE_A, 1234, TYPE_MX, TYPE_RRSIG],
+        });
+        let mut rdata = Vec::new();
+        nsec.encode(&mut rdata).unwrap();
+        let bitmap = &rdata[b"\x04host\x07example\x03com\x00".len()..];
+        assert_eq!(&bitmap[..8], b"\x00\x06\x40\x01\x00\x00\x00\x03");
+        assert_eq!(&bitmap[8..10], b"\x04\x1B");
+        assert_eq!(bitmap.len(), 8 + 2 + 27);
+
+        let buf = response(&[(TYPE_NSEC, rdata.len() as u16, &rdata)]);
+        let msg = DnsMessage::from_bytes(&buf).unwrap();
+        let RData::NSEC(decoded) = &msg.answers[0].data else {
+            panic!("not an NSEC: {:?}", msg.answers[0].data)
+        };
+        assert_eq!(
+            decoded.types,
+            [TYPE_A, TYPE_MX, TYPE_RRSIG, TYPE_NSEC, 1234]
+        );
+
+        // a zero byte at the end, an empty block, blocks out of order: none of them can be sent
+        // back out as they came
+        for bitmap in [
+            &b"\x00\x02\x40\x00"[..],
+            b"\x00\x01\x00",
+            b"\x04\x01\x40\x00\x01\x40",
+        ] {
+            let rdata = [&b"\x00"[..], bitmap].concat();
+            let buf = response(&[(TYPE_NSEC, rdata.len() as u16, &rdata)]);
+            assert!(DnsMessage::from_bytes(&buf).is_err(), "{:02x?}", bitmap);
+        }
+    }
+
+    #[test]
+    fn test_unknown_types_keep_raw_rdata() {
+        let buf = response(&[(0xFF00, 3, b"abc")]);
+        let msg = DnsMessage::from_bytes(&buf).unwrap();
+        assert_eq!(msg.answers[0].data, RData::Unknown(0xFF00, b"abc".to_vec()));
+    }
+
+    #[test]
+    fn test_svcb_and_https() {
+        // the test vectors of RFC 9460 appendix D: AliasMode, then ServiceMode with a port,
+        // then with alpn, mandatory and ipv4hint, the last as SVCB
+        let alias = b"\x00\x00\x03foo\x07example\x03com\x00";
+        let port = b"\x00\x01\x00\x00\x03\x00\x02\x00\x35";
+        let mut full = b"\x00\x10\x03foo\x07example\x03org\x00".to_vec();
+        full.extend(b"\x00\x00\x00\x04\x00\x01\x00\x04");
+        full.extend(b"\x00\x01\x00\x09\x02h2\x05h3-19");
+        full.extend(b"\x00\x04\x00\x04\xc0\x00\x02\x01");
+        let buf = response(&[
+            (TYPE_HTTPS, alias.len() as u16, alias),
+            (TYPE_HTTPS, port.len() as u16, port),
+            (TYPE_SVCB, full.len() as u16, &full),
+        ]);
+        let msg = DnsMessage::from_bytes(&buf).unwrap();
+
+        let RData::HTTPS(alias) = &msg.answers[0].data else {
+            panic!("not HTTPS: {:?}", msg.answers[0].data)
+        };
+        assert!(alias.is_alias());
+        assert_eq!(alias.target, "foo.example.com");
+        assert!(alias.params.is_empty());
+        let RData::HTTPS(port) = &msg.answers[1].data else {
+            panic!("not HTTPS: {:?}", msg.answers[1].data)
+        };
+        assert_eq!(port.params.port(), Some(53));
+
+        let RData::SVCB(svcb) = &msg.answers[2].data else {
+            panic!("not SVCB: {:?}", msg.answers[2].data)
+        };
+        assert_eq!(svcb.priority, 16);
+        assert_eq!(
+            svcb.params.alpn().unwrap(),
+            ["h2".to_string(), "h3-19".to_string()]
+        );
+        assert_eq!(svcb.params.ipv4hint(), [Ipv4Addr::new(192, 0, 2, 1)]);
+        assert_eq!(
+            svcb.params.get(SVC_MANDATORY),
+            Some(&SvcParam::Mandatory(vec![SVC_ALPN, SVC_IPV4HINT]))
+        );
+        assert!(svcb.params.ech().is_none());
+        let mut rdata = Vec::new();
+        RData::SVCB(svcb.clone()).encode(&mut rdata).unwrap();
+        assert_eq!(rdata, full);
+
+        // params go out sorted whatever order they went in
+        let mut params = SvcParams::new();
+        params.insert(SvcParam::Ipv6Hint(vec!["2001:db8::1".parse().unwrap()]));
+        params.insert(SvcParam::Ech(vec![1, 2, 3]));
+        params.insert(SvcParam::NoDefaultAlpn);
+        let https = RData::HTTPS(Svcb {
+            priority: 1,
+            target: "".to_string(),
+            params,
+        });
+        let mut rdata = Vec::new();
+        https.encode(&mut rdata).unwrap();
+        let buf = response(&[(TYPE_HTTPS, rdata.len() as u16, &rdata)]);
+        assert_eq!(DnsMessage::from_bytes(&buf).unwrap().answers[0].data, https);
+    }
+
+    #[test]
+    fn test_bad_svc_params_are_malformed() {
+        for rdata in [
+            // keys out of order
+            &b"\x00\x01\x00\x00\x03\x00\x02\x00\x35\x00\x01\x00\x03\x02h2"[..],
+            // a port that isn't 2 bytes
+            b"\x00\x01\x00\x00\x03\x00\x01\x35",
+            // an empty alpn id
+            b"\x00\x01\x00\x00\x01\x00\x01\x00",
+        ] {
+            let buf = response(&[(TYPE_HTTPS, rdata.len() as u16, rdata)]);
+            assert!(
+                matches!(DnsMessage::from_bytes(&buf), Err(DnsError::Malformed(_))),
+                "{:?}",
+                rdata
+            );
+        }
+    }
+
+    #[test]
+    fn test_tlsa() {
+        // RFC 6698 section 2.3: 0 0 1 and a SHA-256, shortened here
+        let buf = response(&[(TYPE_TLSA, 7, b"\x00\x00\x01\xd2\xab\xde\x24")]);
+        let msg = DnsMessage::from_bytes(&buf).unwrap();
+        let tlsa = RData::TLSA(Tlsa {
+            usage: 0,
+            selector: 0,
+            matching_type: 1,
+            data: vec![0xd2, 0xab, 0xde, 0x24],
+        });
+        assert_eq!(msg.answers[0].data, tlsa);
+        let mut rdata = Vec::new();
+        tlsa.encode(&mut rdata).unwrap();
+        assert_eq!(rdata, msg.answers[0].rdata);
+
+        let buf = response(&[(TYPE_TLSA, 2, b"\x03\x01")]);
+        assert!(matches!(
+            DnsMessage::from_bytes(&buf),
+            Err(DnsError::RdataOverrun { rr_type: TYPE_TLSA })
+        ));
+    }
+
+    #[test]
+    fn test_caa() {
+        let rdata = b"\x80\x05issueletsencrypt.org";
+        let buf = response(&[(TYPE_CAA, rdata.len() as u16, rdata)]);
+        let msg = DnsMessage::from_bytes(&buf).unwrap();
+        let RData::CAA(caa) = &msg.answers[0].data else {
+            panic!("not CAA: {:?}", msg.answers[0].data)
+        };
+        assert!(caa.is_critical());
+        assert_eq!(caa.tag, "issue");
+        assert_eq!(caa.value_str(), Some("letsencrypt.org"));
+        let mut encoded = Vec::new();
+        msg.answers[0].data.encode(&mut encoded).unwrap();
+        assert_eq!(encoded, rdata);
+
+        for bad in [
+            &b"\x00\x00"[..],
+            b"\x00\x03is-",
+            b"\x00\x10issueissueissuei",
+        ] {
+            let buf = response(&[(TYPE_CAA, bad.len() as u16, bad)]);
+            assert!(
+                matches!(DnsMessage::from_bytes(&buf), Err(DnsError::Malformed(_))),
+                "{:?}",
+                bad
+            );
+        }
+    }
+}
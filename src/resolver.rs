@@ -0,0 +1,2727 @@
+use std::borrow::Cow;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::net::{IpAddr, SocketAddr};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::address_order::sort_addrs;
+use crate::blocklist::Blocklist;
+use crate::cache::DnsCache;
+use crate::cookie::{echoes_cookie, ClientCookies};
+use crate::dnssd::{self, ServiceInstance};
+use crate::hosts::HostsFile;
+use crate::iterative::redirected;
+use crate::local::LocalRecords;
+use crate::mdns;
+use crate::mux::UdpMux;
+use crate::mx::{self, MailExchanger};
+use crate::options::QueryOptions;
+use crate::pcap::PcapWriter;
+use crate::pool::ConnectionPool;
+use crate::ports::SourcePorts;
+use crate::random::random_u64;
+use crate::rdata::TYPE_TSIG;
+use crate::retry::{Deadline, RetryPolicy};
+use crate::rtt::{RttTable, ServerHealth};
+use crate::srv;
+use crate::stats::{Counters, Stats};
+use crate::system::SystemConfig;
+use crate::tcp;
+use crate::transport::{DnsTransport, TcpTransport, UdpTransport};
+use crate::txt::{self, Txt};
+#[cfg(feature = "dnssec")]
+use crate::{dnssec::Validator, TrustAnchor};
+use crate::{Caa, Soa, SrvRecord, Tlsa, RCODE_FORMERR, RCODE_NXDOMAIN};
+use crate::{DnsError, DnsMessage, Edns, EdnsOption, Name, RData, RecordType};
+
+// Google DNS, the placeholder upstream we have been using from the start
+pub const DEFAULT_SERVER: &str = "8.8.8.8:53";
+
+const EDNS_PADDING: u16 = 12;
+pub(crate) const DEFAULT_TIMEOUT: Duration = Duration::from_secs(5);
+// real chains are two or three long, this leaves plenty of room (and matches the iterative
+// resolver)
+const DEFAULT_MAX_CNAMES: usize = 8;
+
+// rcodes that say more about the server than about the name: worth asking the next one
+pub(crate) const RCODE_SERVFAIL: u16 = 2;
+pub(crate) const RCODE_REFUSED: u16 = 5;
+// the server wants its own cookie back before it answers (RFC 7873)
+const RCODE_BADCOOKIE: u16 = 23;
+
+// which upstream a query goes to first when there are several
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Strategy {
+    // always the first one, the others only when it doesn't come through
+    #[default]
+    Failover,
+    // each query starts at the next one in line, spreading the load over all of them
+    RoundRobin,
+    // the query goes to every upstream, `hedge` apart (all at once when it's zero), and the
+    // first good answer wins; the ones still waiting for their turn are called off. More queries
+    // for much less waiting on a flaky network, where one lost packet no longer costs a whole
+    // timeout. Each upstream gets a socket of its own for this, so raced queries don't show up
+    // in a capture. The RetryPolicy timeout applies, its attempts and backoff don't: a race is
+    // one try at every upstream
+    Race {
+        hedge: Duration,
+    },
+    // the upstream with the lowest smoothed RTT first, the others after it in the same order and
+    // the ones that keep failing last, with a probe of one of those every `probe_every` to see
+    // whether it's back (see rtt.rs). For upstreams that aren't all the same distance away
+    Fastest {
+        probe_every: Duration,
+    },
+}
+
+// what lookup found: the final response, and the names it took to get there
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Lookup {
+    // the response for the end of the chain, with the question put back to the name asked for
+    // and every CNAME/DNAME on the way ahead of its answers, so canonical_name, ipv4_addrs and
+    // friends work on it as if one server had answered the lot
+    pub response: DnsMessage,
+    // the name asked for first, then each name it led to, the one the records are for last
+    pub chain: Vec<Name>,
+    // the names from the search list we asked about, in order. The last one is where `chain`
+    // starts
+    pub tried: Vec<Name>,
+    // what DNSSEC validation made of the response, also in its AD bit
+    pub security: Security,
+}
+
+impl Lookup {
+    fn new(response: DnsMessage, security: Security, tried: Vec<Name>) -> Self {
+        Lookup {
+            chain: response.cname_chain(),
+            response,
+            tried,
+            security,
+        }
+    }
+}
+
+// how far DNSSEC vouches for an answer. There is no Bogus here: a bogus answer never gets as far
+// as a Lookup, it's a DnsError::Bogus
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Security {
+    // signed, and the signatures check out all the way up to a trust anchor
+    Secure,
+    // from a zone the chain of trust proves unsigned: nothing to check it against
+    Insecure,
+    // not validated at all, validation is off (or the name is under none of our trust anchors)
+    #[default]
+    Indeterminate,
+}
+
+// what resolve_traced found
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Resolved {
+    // the same as resolve gives back
+    pub addrs: Vec<IpAddr>,
+    // the names from the search list we asked about, in order, the addresses are for the last
+    pub tried: Vec<Name>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Upstream {
+    addr: SocketAddr,
+    timeout: Duration,
+}
+
+pub struct Resolver {
+    servers: Vec<Upstream>,
+    strategy: Strategy,
+    // where the next round-robin query starts
+    next_server: AtomicUsize,
+    options: QueryOptions,
+    // the sockets our UDP queries go out from, any number of them in flight at once (see
+    // mux.rs). Bound on the first queries and then kept, so late packets for older queries land
+    // there too and get told apart from the answers being waited for
+    mux: UdpMux,
+    // when set, every DNS payload we send or receive is written here as well
+    capture: Arc<Capture>,
+    // answers we already have, looked at before any upstream and filled with what they send back
+    cache: Option<Arc<DnsCache>>,
+    // names settled locally, looked at before the cache: our own records first, then the hosts
+    // file
+    local: Option<Arc<LocalRecords>>,
+    hosts: Option<Arc<HostsFile>>,
+    // names we don't look up at all, between the local records and the hosts file
+    blocklist: Option<Arc<Blocklist>>,
+    // where names under .local (and the link-local reverse zones) are asked instead of any
+    // upstream, the two mDNS groups unless set otherwise. Empty sends them upstream like any other
+    mdns_groups: Vec<SocketAddr>,
+    // when set, every query goes through it instead of our own UDP/TCP to `servers`
+    transport: Option<Arc<dyn DnsTransport>>,
+    // when set, TCP queries go over its connections, kept open between queries, instead of a
+    // new connection each
+    tcp_pool: Option<Arc<ConnectionPool>>,
+    // how many times lookup asks again for where a CNAME points
+    max_cnames: usize,
+    // how many tries a query gets, how long each one waits and what happens in between
+    retry: RetryPolicy,
+    // the domains from the system configuration for names that aren't fully qualified, and the
+    // number of dots that makes a name count as one
+    search: Vec<Name>,
+    ndots: usize,
+    // shared with the threads of a race
+    counters: Arc<Counters>,
+    // how fast each upstream answers, kept whatever the strategy but only Fastest goes by it
+    rtt: Arc<RttTable>,
+    // our DNS cookie for each upstream and the one it gave us back, when options.cookies is on
+    cookies: Arc<ClientCookies>,
+    // when set, what lookup and resolve give back has been checked with DNSSEC
+    #[cfg(feature = "dnssec")]
+    validator: Option<Arc<Validator>>,
+}
+
+// the capture is shared with the threads of the mux, which can hang on to it for a moment after
+// we're gone. It's closed (and flushed) with us, not whenever the last of them lets go
+impl Drop for Resolver {
+    fn drop(&mut self) {
+        if let Ok(mut capture) = self.capture.lock() {
+            capture.take();
+        }
+    }
+}
+
+impl Default for Resolver {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Resolver {
+    pub fn new() -> Self {
+        Self::with_server(DEFAULT_SERVER.parse().unwrap())
+    }
+
+    pub fn with_server(server: SocketAddr) -> Self {
+        Self::with_servers([server])
+    }
+
+    // several upstreams, tried in the order given (see set_strategy), each with the default
+    // timeout
+    pub fn with_servers(servers: impl IntoIterator<Item = SocketAddr>) -> Self {
+        let counters: Arc<Counters> = Arc::default();
+        let capture: Arc<Capture> = Arc::default();
+        Resolver {
+            servers: servers
+                .into_iter()
+                .map(|addr| Upstream {
+                    addr,
+                    timeout: DEFAULT_TIMEOUT,
+                })
+                .collect(),
+            strategy: Strategy::default(),
+            next_server: AtomicUsize::new(0),
+            options: QueryOptions::default(),
+            mux: UdpMux::attached(SourcePorts::default(), counters.clone(), capture.clone()),
+            capture,
+            cache: None,
+            local: None,
+            hosts: None,
+            blocklist: None,
+            mdns_groups: mdns::mdns_groups(),
+            transport: None,
+            tcp_pool: None,
+            max_cnames: DEFAULT_MAX_CNAMES,
+            retry: RetryPolicy::default(),
+            search: Vec::new(),
+            ndots: 1,
+            counters,
+            rtt: Arc::default(),
+            cookies: Arc::default(),
+            #[cfg(feature = "dnssec")]
+            validator: None,
+        }
+    }
+
+    // set up the way the machine is: the nameservers, search domains and options from
+    // /etc/resolv.conf. A machine without one gets what the C library would use, the local server
+    #[cfg(unix)]
+    pub fn from_system() -> Result<Self, DnsError> {
+        let config = match SystemConfig::from_resolv_conf(crate::RESOLV_CONF) {
+            Ok(config) => config,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => SystemConfig::parse_resolv_conf(""),
+            Err(e) => return Err(e.into()),
+        };
+        Ok(Self::from_config(&config))
+    }
+
+    // on Windows: the DNS servers and connection-specific suffixes of every network adapter
+    // that's up, with the defaults for everything resolv.conf would have said
+    #[cfg(windows)]
+    pub fn from_system() -> Result<Self, DnsError> {
+        Ok(Self::from_config(&SystemConfig::from_adapters()?))
+    }
+
+    pub fn from_config(config: &SystemConfig) -> Self {
+        let mut resolver = Self::with_servers(config.nameservers.iter().copied());
+        resolver.set_timeout(config.timeout);
+        resolver.set_retry_policy(RetryPolicy {
+            attempts: config.attempts,
+            ..RetryPolicy::default()
+        });
+        resolver.set_search(config.search.clone());
+        resolver.set_ndots(config.ndots);
+        if config.rotate {
+            resolver.set_strategy(Strategy::RoundRobin);
+        }
+        resolver
+    }
+
+    // queries go out through `transport` (TLS, HTTPS, a mock...) rather than over UDP/TCP to an
+    // upstream of ours. EDNS, the cache and the stats work the same, the servers and their
+    // strategy don't come into it
+    pub fn with_transport(transport: Arc<dyn DnsTransport>) -> Self {
+        let mut resolver = Self::with_servers([]);
+        resolver.transport = Some(transport);
+        resolver
+    }
+
+    // one more upstream, after the ones already there, with a timeout of its own (a server
+    // across the world needs longer than the one in the next rack)
+    pub fn add_server(&mut self, addr: SocketAddr, timeout: Duration) {
+        self.servers.push(Upstream { addr, timeout });
+    }
+
+    pub fn servers(&self) -> Vec<SocketAddr> {
+        self.servers.iter().map(|server| server.addr).collect()
+    }
+
+    // the same timeout for every upstream
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        for server in &mut self.servers {
+            server.timeout = timeout;
+        }
+    }
+
+    pub fn set_strategy(&mut self, strategy: Strategy) {
+        self.strategy = strategy;
+    }
+
+    // how each upstream has been doing, in the order they were added
+    pub fn server_health(&self) -> Vec<ServerHealth> {
+        self.servers
+            .iter()
+            .map(|server| self.rtt.health(server.addr))
+            .collect()
+    }
+
+    // how many extra queries one lookup may make down a CNAME chain before giving up on it
+    pub fn set_max_cnames(&mut self, max: usize) {
+        self.max_cnames = max;
+    }
+
+    pub fn set_retry_policy(&mut self, retry: RetryPolicy) {
+        self.retry = retry;
+    }
+
+    pub fn retry_policy(&self) -> &RetryPolicy {
+        &self.retry
+    }
+
+    pub fn set_search(&mut self, search: Vec<Name>) {
+        self.search = search;
+    }
+
+    pub fn search(&self) -> &[Name] {
+        &self.search
+    }
+
+    pub fn set_ndots(&mut self, ndots: usize) {
+        self.ndots = ndots;
+    }
+
+    pub fn set_options(&mut self, options: QueryOptions) {
+        self.options = options;
+    }
+
+    pub fn options(&self) -> &QueryOptions {
+        &self.options
+    }
+
+    // answer the names (and zones) in `local` from it, ahead of everything else, the hosts file
+    // included. A DnsServer forwarding to this resolver answers them the same way
+    pub fn set_local_records(&mut self, local: Arc<LocalRecords>) {
+        self.local = Some(local);
+    }
+
+    pub fn local_records(&self) -> Option<&LocalRecords> {
+        self.local.as_deref()
+    }
+
+    // answer the names on `blocklist` with what it says to rather than looking them up, counting
+    // them in Stats::blocked. Local records still win, which is how a name on a list gets let
+    // through
+    pub fn set_blocklist(&mut self, blocklist: Arc<Blocklist>) {
+        self.blocklist = Some(blocklist);
+    }
+
+    pub fn blocklist(&self) -> Option<&Blocklist> {
+        self.blocklist.as_deref()
+    }
+
+    // answer the names in `hosts` from it, before the cache or any upstream get a look in. A
+    // DnsServer forwarding to this resolver answers them the same way
+    pub fn set_hosts(&mut self, hosts: Arc<HostsFile>) {
+        self.hosts = Some(hosts);
+    }
+
+    pub fn hosts(&self) -> Option<&HostsFile> {
+        self.hosts.as_deref()
+    }
+
+    // ask these addresses with multicast DNS about .local names, rather than the mDNS groups.
+    // An empty list turns mDNS off, for networks whose unicast DNS serves a .local of its own
+    pub fn set_mdns_groups(&mut self, groups: Vec<SocketAddr>) {
+        self.mdns_groups = groups;
+    }
+
+    // answer from (and remember into) `cache` from now on. An Arc so several resolvers can share
+    // what they learn
+    pub fn set_cache(&mut self, cache: Arc<DnsCache>) {
+        self.cache = Some(cache);
+    }
+
+    pub fn cache(&self) -> Option<&DnsCache> {
+        self.cache.as_deref()
+    }
+
+    // keep TCP connections to the upstreams open in `pool` rather than opening one for every
+    // answer that doesn't fit UDP (or every query, with force_tcp). An Arc so several resolvers
+    // can share the connections
+    pub fn set_connection_pool(&mut self, pool: Arc<ConnectionPool>) {
+        self.tcp_pool = Some(pool);
+    }
+
+    // validate what lookup and resolve give back with DNSSEC, starting from the root zone's keys
+    // (see TrustAnchor::root). Bogus answers turn into DnsError::Bogus
+    #[cfg(feature = "dnssec")]
+    pub fn set_dnssec_validation(&mut self, on: bool) {
+        self.validator = on.then(|| Arc::new(Validator::new(TrustAnchor::root())));
+    }
+
+    // validate starting from these keys instead, for a private tree or a test one, or the ones
+    // an AnchorStore keeps in a file
+    #[cfg(feature = "dnssec")]
+    pub fn set_trust_anchors(&mut self, anchors: Vec<TrustAnchor>) {
+        self.validator = Some(Arc::new(Validator::new(anchors)));
+    }
+
+    // how many UDP sockets queries go out from, and how often they move to new ports
+    pub fn set_source_ports(&mut self, ports: SourcePorts) {
+        self.mux = UdpMux::attached(ports, self.counters.clone(), self.capture.clone());
+    }
+
+    pub fn source_ports(&self) -> SourcePorts {
+        self.mux.source_ports()
+    }
+
+    // dump everything this resolver sends and receives into a pcap file for Wireshark
+    pub fn capture_to(&mut self, path: impl AsRef<Path>) -> io::Result<()> {
+        let file: Box<dyn Write + Send> = Box::new(BufWriter::new(File::create(path)?));
+        *self.capture.lock().unwrap() = Some(PcapWriter::new(file)?);
+        Ok(())
+    }
+
+    pub fn stats(&self) -> Stats {
+        self.counters.snapshot()
+    }
+
+    // asks the upstreams in turn until one answers. A server that times out, fails or says
+    // SERVFAIL/REFUSED hands over to the next try (see RetryPolicy); if they all do, we give
+    // back the last thing that happened. With a cache, a fresh cached answer saves the trip
+    // altogether, and a name from our local records, the blocklist or the hosts file never
+    // makes one
+    pub fn query(&self, msg: &DnsMessage) -> Result<DnsMessage, DnsError> {
+        if let Some(res) = self.local.as_ref().and_then(|local| local.answer(msg)) {
+            return Ok(res);
+        }
+        if let Some(res) = self.blocklist.as_ref().and_then(|list| list.answer(msg)) {
+            Counters::bump(&self.counters.blocked);
+            return Ok(res);
+        }
+        if let Some(res) = self.hosts.as_ref().and_then(|hosts| hosts.answer(msg)) {
+            return Ok(res);
+        }
+        let Some(cache) = &self.cache else {
+            return self.query_upstream(msg);
+        };
+        // the subnet the query goes out for, whether the caller asked for one or we add it
+        let subnet = match &msg.edns {
+            Some(edns) => edns.client_subnet(),
+            None => self
+                .options
+                .client_subnet
+                .filter(|_| self.options.uses_edns()),
+        };
+        if let Some(res) = cache.get_response_for(msg, subnet.as_ref()) {
+            Counters::bump(&self.counters.cache_hits);
+            return Ok(res);
+        }
+        Counters::bump(&self.counters.cache_misses);
+
+        let res = self.query_upstream(msg)?;
+        if cacheable(&res) {
+            cache.insert_response(msg, &res);
+        }
+        Ok(res)
+    }
+
+    // the names a lookup of `name` goes through, in order, by the resolv.conf rules: one ending
+    // in a dot is fully qualified and only ever tried as is, one with at least ndots dots is
+    // tried as is and then with each search domain on the end, and one with fewer the other way
+    // round
+    pub fn search_names(&self, name: &str) -> Vec<Name> {
+        let as_is = Name::from(name);
+        if name.ends_with('.') || self.search.is_empty() {
+            return vec![as_is];
+        }
+        let mut names: Vec<Name> = self
+            .search
+            .iter()
+            .map(|domain| Name::from(format!("{}.{}", as_is, domain)))
+            .collect();
+        let dots = as_is.labels().len().saturating_sub(1);
+        if dots >= self.ndots {
+            names.insert(0, as_is);
+        } else {
+            names.push(as_is);
+        }
+        names
+    }
+
+    // asks for `name`, going down search_names: a name that doesn't exist or has nothing of
+    // `qtype` moves us on to the next one. When none of them has anything we give back the
+    // first NODATA answer, or the last NXDOMAIN if it's NXDOMAIN all the way. Any other rcode
+    // stops the search right there, as glibc does.
+    //
+    // Each name is also followed down its CNAME (or DNAME) chain: when the answer points at a
+    // name the server didn't include the records for, we ask again for that one, and so on.
+    // Upstreams usually chase the chain themselves, this is for the ones that stop early
+    // (forwarders, authoritative servers for just one end of it). A chain that comes back around
+    // to a name we have seen, or needs more than set_max_cnames extra queries, is an error
+    pub fn lookup(&self, name: &str, qtype: impl Into<u16>) -> Result<Lookup, DnsError> {
+        let qtype = qtype.into();
+        let mut tried = Vec::new();
+        let mut nodata = None;
+        let mut nxdomain = None;
+        for candidate in self.search_names(name) {
+            tried.push(candidate.clone());
+            let res = self.follow_chain(candidate, qtype)?;
+            match res.rcode() {
+                0 if !has_records(&res, qtype) => {
+                    nodata.get_or_insert(res);
+                }
+                RCODE_NXDOMAIN => nxdomain = Some(res),
+                _ => return self.validated(res, tried),
+            }
+        }
+        let res = nodata.or(nxdomain).expect("search_names is never empty");
+        self.validated(res, tried)
+    }
+
+    fn validated(&self, res: DnsMessage, tried: Vec<Name>) -> Result<Lookup, DnsError> {
+        let (res, security) = self.validate(res)?;
+        Ok(Lookup::new(res, security, tried))
+    }
+
+    #[cfg(not(feature = "dnssec"))]
+    fn validate(&self, res: DnsMessage) -> Result<(DnsMessage, Security), DnsError> {
+        Ok((res, Security::Indeterminate))
+    }
+
+    // checks `res` when validation is on, with its AD bit set to say whether it's Secure: it's
+    // our own verdict that counts, not the upstream's
+    #[cfg(feature = "dnssec")]
+    fn validate(&self, mut res: DnsMessage) -> Result<(DnsMessage, Security), DnsError> {
+        let Some(validator) = &self.validator else {
+            return Ok((res, Security::Indeterminate));
+        };
+        let security = validator.validate(self, &res)?;
+        let mut flags = res.flags();
+        flags.ad = security == Security::Secure;
+        res.set_flags(flags);
+        Ok((res, security))
+    }
+
+    // the query lookup sends for `name`: with DO and CD set when we validate, so the upstream
+    // sends the signatures along and leaves judging them to us
+    fn question(&self, name: Name, qtype: u16) -> DnsMessage {
+        let query = DnsMessage::query(name).qtype(qtype);
+        #[cfg(feature = "dnssec")]
+        let query = query
+            .dnssec_ok(self.validator.is_some())
+            .checking_disabled(self.validator.is_some());
+        query.build()
+    }
+
+    // one name and its CNAME chain, see lookup. The response for the end of the chain, with the
+    // question put back to `name` and the answers that led there ahead of its own
+    fn follow_chain(&self, name: Name, qtype: u16) -> Result<DnsMessage, DnsError> {
+        let mut res = self.query(&self.question(name.clone(), qtype))?;
+        let mut answers = Vec::new();
+        let mut seen = vec![name.clone()];
+        while let Some(target) = redirected(&res, qtype) {
+            if seen.contains(&target) || seen.len() > self.max_cnames {
+                return Err(DnsError::Malformed("CNAME chain loops or runs too long"));
+            }
+            seen.push(target.clone());
+            answers.append(&mut res.answers);
+            res = self.query(&self.question(target, qtype))?;
+        }
+
+        if !answers.is_empty() {
+            answers.append(&mut res.answers);
+            res.answers = answers;
+            res.header.no_of_answers_rr = res.answers.len() as u16;
+            if let Some(question) = res.questions.first_mut() {
+                question.qname = name;
+            }
+        }
+        Ok(res)
+    }
+
+    // the hostnames the PTR records for `addr` name. Empty when there are none (or the reverse
+    // name doesn't exist, which is how most addresses without a PTR come back)
+    pub fn lookup_addr(&self, addr: IpAddr) -> Result<Vec<String>, DnsError> {
+        // with the trailing dot, the search list has no business with these
+        let name = format!("{}.", Name::reverse(addr));
+        let res = self.lookup(&name, RecordType::PTR)?.response;
+        match res.rcode() {
+            0 | RCODE_NXDOMAIN => {}
+            _ => return Err(DnsError::from_response(&res)),
+        }
+        // RFC 2317 classless delegation points the PTR at a CNAME, lookup already went down it
+        let owner = res.canonical_name();
+        Ok(res
+            .answers
+            .iter()
+            .filter(|rr| rr.name == owner)
+            .filter_map(|rr| match &rr.data {
+                RData::PTR(host) => Some(host.clone()),
+                _ => None,
+            })
+            .collect())
+    }
+
+    // the targets of the SRV records for _service._proto.name, each with its addresses (from the
+    // additional section, or looked up), in the order RFC 2782 says to try them: by priority,
+    // then drawn at random in proportion to the weights, so the load spreads the way the zone
+    // asks. A name without the service gives an empty list, see resolve_srv
+    pub fn lookup_srv(
+        &self,
+        service: &str,
+        proto: &str,
+        name: &str,
+    ) -> Result<Vec<(SrvRecord, Vec<IpAddr>)>, DnsError> {
+        srv::lookup(self, service, proto, name)
+    }
+
+    // the mail exchangers for `domain`, lowest preference first, with whatever addresses the
+    // server sent along for them. Empty for a domain without MX records, or with a null MX (see
+    // mx.rs). Goes down the search list like lookup
+    pub fn lookup_mx(&self, domain: &str) -> Result<Vec<MailExchanger>, DnsError> {
+        mx::lookup(self, domain, false)
+    }
+
+    // lookup_mx, also looking up the addresses of the exchangers the server sent none for. One
+    // that doesn't resolve is left without, the others are still worth having
+    pub fn resolve_mx(&self, domain: &str) -> Result<Vec<MailExchanger>, DnsError> {
+        mx::lookup(self, domain, true)
+    }
+
+    // the TXT records of `domain`, each with its character-strings as sent. Txt::text glues
+    // them together, which is how SPF, DKIM and verification tokens are read. Empty for a name
+    // without any. Goes down the search list like lookup
+    pub fn lookup_txt(&self, domain: &str) -> Result<Vec<Txt>, DnsError> {
+        txt::lookup(self, domain)
+    }
+
+    // the TLSA records for a TLS service, asked for by their full name ("_443._tcp.example.com").
+    // DANE only has them count when DNSSEC vouches for them (RFC 6698 section 4.1), so they
+    // come with what validation made of the answer: anything short of Secure, and a TLS client
+    // has to carry on as if there were none
+    pub fn lookup_tlsa(&self, name: &str) -> Result<(Vec<Tlsa>, Security), DnsError> {
+        self.lookup_records(name, RecordType::TLSA, |data| match data {
+            RData::TLSA(tlsa) => Some(tlsa.clone()),
+            _ => None,
+        })
+    }
+
+    // the CAA records that decide who may issue certificates for `domain` (RFC 8659 section 3):
+    // its own, or when it has none those of its parent, and so on up to (but not including) the
+    // root. Empty when there are none all the way up, and then any CA may. An alias is followed,
+    // lookup goes down CNAME chains
+    pub fn lookup_caa(&self, domain: &str) -> Result<Vec<Caa>, DnsError> {
+        let mut name = Some(Name::from(domain));
+        while let Some(current) = name.filter(|name| !name.is_root()) {
+            let (records, _) = self.lookup_records(
+                &format!("{}.", current),
+                RecordType::CAA,
+                |data| match data {
+                    RData::CAA(caa) => Some(caa.clone()),
+                    _ => None,
+                },
+            )?;
+            if !records.is_empty() {
+                return Ok(records);
+            }
+            name = current.parent();
+        }
+        Ok(Vec::new())
+    }
+
+    // the SOA record of `zone`, None when the name isn't the apex of a zone (or doesn't exist).
+    // Asks whichever upstream this resolver would; to compare serials across a zone's
+    // nameservers, ask each through a resolver of its own and see Soa::compare_serial
+    pub fn query_soa(&self, zone: &str) -> Result<Option<Soa>, DnsError> {
+        let (records, _) =
+            self.lookup_records(&format!("{}.", Name::from(zone)), RecordType::SOA, |data| {
+                match data {
+                    RData::SOA(soa) => Some(soa.clone()),
+                    _ => None,
+                }
+            })?;
+        Ok(records.into_iter().next())
+    }
+
+    // the rdata of `name`'s records of `qtype` that `pick` takes, and how secure the answer is.
+    // No records (or no such name) is an empty list, any other rcode an error
+    fn lookup_records<T>(
+        &self,
+        name: &str,
+        qtype: RecordType,
+        pick: impl Fn(&RData) -> Option<T>,
+    ) -> Result<(Vec<T>, Security), DnsError> {
+        let lookup = self.lookup(name, qtype)?;
+        let res = &lookup.response;
+        match res.rcode() {
+            0 | RCODE_NXDOMAIN => {}
+            _ => return Err(DnsError::from_response(res)),
+        }
+        let owner = res.canonical_name();
+        let records = res
+            .answers
+            .iter()
+            .filter(|rr| rr.name == owner)
+            .filter_map(|rr| pick(&rr.data))
+            .collect();
+        Ok((records, lookup.security))
+    }
+
+    // DNS-SD (see dnssd.rs): every instance of `service` ("_http._tcp.local" on the local link
+    // over mDNS, "_http._tcp.example.com" from unicast DNS) with where it runs and its TXT
+    // pairs, in the order the PTR records came. A service type nobody offers gives an empty list
+    pub fn discover_services(&self, service: &str) -> Result<Vec<ServiceInstance>, DnsError> {
+        dnssd::discover(self, service)
+    }
+
+    // every address `name` resolves to, IPv4 and IPv6 together, with duplicates dropped and in
+    // the order they're best tried in (see sort_addrs). Goes down the search list like lookup,
+    // stopping at the first name with an address of either kind. Fails only when no name on the
+    // list got an answer, a name with just one kind of address (or none) is fine
+    pub fn resolve(&self, name: &str) -> Result<Vec<IpAddr>, DnsError> {
+        self.resolve_traced(name).map(|resolved| resolved.addrs)
+    }
+
+    // resolve, also saying which names it tried on the way there. For working out why a short
+    // name resolved to something unexpected, or to nothing.
+    //
+    // The A and AAAA queries for each name go out side by side
+    pub fn resolve_traced(&self, name: &str) -> Result<Resolved, DnsError> {
+        let mut tried = Vec::new();
+        let mut nodata = false;
+        for candidate in self.search_names(name) {
+            tried.push(candidate.clone());
+            let lookup = |qtype: RecordType| {
+                let (res, _) =
+                    self.validate(self.follow_chain(candidate.clone(), qtype.into())?)?;
+                match res.rcode() {
+                    0 => Ok(res),
+                    _ => Err(DnsError::from_response(&res)),
+                }
+            };
+            let (v4, v6) = thread::scope(|scope| {
+                let v6 = scope.spawn(|| lookup(RecordType::AAAA));
+                (lookup(RecordType::A), v6.join().unwrap())
+            });
+            let (v4, v6) = match (v4, v6) {
+                // one forged answer is enough to not trust either of them
+                (Err(e @ DnsError::Bogus(_)), _) | (_, Err(e @ DnsError::Bogus(_))) => {
+                    return Err(e)
+                }
+                (Err(e), Err(_)) if e.rcode() == Some(RCODE_NXDOMAIN) => continue,
+                (Err(e), Err(_)) => return Err(e),
+                answers => answers,
+            };
+
+            let mut addrs: Vec<IpAddr> = Vec::new();
+            let found = v4
+                .iter()
+                .flat_map(|res| res.ipv4_addrs().into_iter().map(IpAddr::V4))
+                .chain(
+                    v6.iter()
+                        .flat_map(|res| res.ipv6_addrs().into_iter().map(IpAddr::V6)),
+                );
+            for addr in found {
+                if !addrs.contains(&addr) {
+                    addrs.push(addr);
+                }
+            }
+            if !addrs.is_empty() {
+                sort_addrs(&mut addrs);
+                return Ok(Resolved { addrs, tried });
+            }
+            nodata = true;
+        }
+        if nodata {
+            Ok(Resolved {
+                addrs: Vec::new(),
+                tried,
+            })
+        } else {
+            Err(DnsError::Rcode(RCODE_NXDOMAIN))
+        }
+    }
+
+    fn query_upstream(&self, msg: &DnsMessage) -> Result<DnsMessage, DnsError> {
+        if !self.mdns_groups.is_empty() && mdns::is_mdns_name(&msg.question().qname) {
+            Counters::bump(&self.counters.queries_sent);
+            return mdns::query_groups(msg, &self.mdns_groups);
+        }
+        match with_mixed_case(msg, &self.options) {
+            Some(mixed) => case_restored(msg, &mixed, self.query_as_is(&mixed)?),
+            None => self.query_as_is(msg),
+        }
+    }
+
+    fn query_as_is(&self, msg: &DnsMessage) -> Result<DnsMessage, DnsError> {
+        let with_opt = with_edns(msg, &self.options);
+        let deadline = Deadline::after(self.retry.deadline);
+        if let Some(transport) = &self.transport {
+            Counters::bump(&self.counters.queries_sent);
+            let res = match (self.retry.timeout, self.retry.deadline) {
+                (None, None) => transport.exchange(&with_opt)?,
+                (timeout, _) => {
+                    let timeout = deadline.cap(timeout.unwrap_or(Duration::MAX));
+                    transport.exchange_timeout(&with_opt, timeout.ok_or(DnsError::Timeout)?)?
+                }
+            };
+            return Ok(self.received(res));
+        }
+        if let Strategy::Race { hedge } = self.strategy {
+            return self.race(msg, &with_opt, hedge, deadline);
+        }
+        let mut last = Err(no_upstreams());
+        let servers = self.in_order();
+        for (try_no, index) in self.retry.tries(servers.len()).into_iter().enumerate() {
+            if try_no > 0 {
+                thread::sleep(deadline.cap(self.retry.delay(try_no)).unwrap_or_default());
+            }
+            // out of time: what the last try got is the answer, or a timeout if there was none
+            let timeout = self.retry.timeout.unwrap_or(servers[index].timeout);
+            let Some(timeout) = deadline.cap(timeout) else {
+                return match last {
+                    Err(_) if try_no == 0 => Err(self.timed_out()),
+                    last => last,
+                };
+            };
+            if try_no > 0 {
+                Counters::bump(&self.counters.retries);
+            }
+            let server = Upstream {
+                timeout,
+                ..*servers[index]
+            };
+            let started = Instant::now();
+            let res = self.query_server(&server, msg, &with_opt);
+            self.rtt
+                .record(server.addr, started.elapsed(), is_answer(&res));
+            match res {
+                Ok(res) if matches!(res.rcode(), RCODE_SERVFAIL | RCODE_REFUSED) => last = Ok(res),
+                Ok(res) => return Ok(res),
+                Err(e) => last = Err(e),
+            }
+        }
+        last
+    }
+
+    // the upstreams in the order this query tries them
+    fn in_order(&self) -> Vec<&Upstream> {
+        if self.servers.is_empty() {
+            return Vec::new();
+        }
+        let start = match self.strategy {
+            Strategy::Failover | Strategy::Race { .. } => 0,
+            Strategy::Fastest { probe_every } => {
+                let addrs = self.servers();
+                let order = self.rtt.order(&addrs, probe_every);
+                return order.into_iter().map(|i| &self.servers[i]).collect();
+            }
+            Strategy::RoundRobin => {
+                self.next_server.fetch_add(1, Ordering::Relaxed) % self.servers.len()
+            }
+        };
+        self.servers
+            .iter()
+            .cycle()
+            .skip(start)
+            .take(self.servers.len())
+            .collect()
+    }
+
+    // Strategy::Race. Every upstream gets a thread, and each thread waits for its turn, skips
+    // the query if somebody has answered by then and otherwise goes through the usual UDP, TCP
+    // and FORMERR dance on its own sockets. We don't wait for the losers: they finish (or time
+    // out) in the background with nobody listening
+    fn race(
+        &self,
+        msg: &DnsMessage,
+        with_opt: &DnsMessage,
+        hedge: Duration,
+        deadline: Deadline,
+    ) -> Result<DnsMessage, DnsError> {
+        let (results, answers) = mpsc::channel();
+        let answered = Arc::new(AtomicBool::new(false));
+        for (turn, server) in self.servers.iter().enumerate() {
+            let timeout = self.retry.timeout.unwrap_or(server.timeout);
+            let Some(timeout) = deadline.cap(timeout) else {
+                return Err(self.timed_out());
+            };
+            let racer = Racer {
+                server: server.addr,
+                timeout,
+                force_tcp: self.options.force_tcp,
+                counters: self.counters.clone(),
+                rtt: self.rtt.clone(),
+                cookies: self.options.cookies.then(|| self.cookies.clone()),
+                padding_block: self.options.padding_block,
+            };
+            let (results, answered) = (results.clone(), answered.clone());
+            let (msg, with_opt) = (msg.clone(), with_opt.clone());
+            let wait = hedge.saturating_mul(turn as u32);
+            thread::spawn(move || {
+                thread::sleep(wait);
+                if !answered.load(Ordering::Relaxed) {
+                    // the receiving end is gone once somebody else has won
+                    let _ = results.send(racer.run(&msg, &with_opt));
+                }
+            });
+        }
+        drop(results);
+
+        let mut last = Err(no_upstreams());
+        loop {
+            let wait = deadline.cap(Duration::MAX).unwrap_or_default();
+            let res = match answers.recv_timeout(wait) {
+                Ok(res) => res,
+                // the deadline passed with nobody done yet
+                Err(RecvTimeoutError::Timeout) if last.is_err() => return Err(self.timed_out()),
+                Err(_) => break,
+            };
+            match res {
+                Ok(res) if matches!(res.rcode(), RCODE_SERVFAIL | RCODE_REFUSED) => last = Ok(res),
+                Ok(res) => {
+                    answered.store(true, Ordering::Relaxed);
+                    return Ok(res);
+                }
+                Err(e) => last = Err(e),
+            }
+        }
+        last
+    }
+
+    fn query_server(
+        &self,
+        server: &Upstream,
+        msg: &DnsMessage,
+        with_opt: &DnsMessage,
+    ) -> Result<DnsMessage, DnsError> {
+        let mut res = self.exchange(server, &self.with_cookie(with_opt, server))?;
+        // BADCOOKIE: the server answers once it sees a cookie of its own, and it just sent us
+        // one (RFC 7873 5.3). One more go with that
+        if res.rcode() == RCODE_BADCOOKIE && self.options.cookies {
+            Counters::bump(&self.counters.retries);
+            res = self.exchange(server, &self.with_cookie(with_opt, server))?;
+        }
+        // some older servers (and plenty of middleboxes) don't know what an OPT record is and
+        // answer FORMERR rather than ignoring it. If the OPT was ours, ask again the classic way;
+        // a big answer then comes back truncated and goes over TCP like any other. with_edns
+        // hands back `msg` itself when it added nothing
+        if res.rcode() == RCODE_FORMERR && !std::ptr::eq(with_opt, msg) {
+            Counters::bump(&self.counters.retries);
+            res = self.exchange(server, msg)?;
+        }
+        if recursed_anyway(msg, &res) {
+            Counters::bump(&self.counters.unrequested_recursion);
+        }
+        Ok(res)
+    }
+
+    fn with_cookie<'a>(&self, msg: &'a DnsMessage, server: &Upstream) -> Cow<'a, DnsMessage> {
+        if !self.options.cookies {
+            return Cow::Borrowed(msg);
+        }
+        let mut msg = self.cookies.attach(msg, server.addr.ip());
+        if let Cow::Owned(msg) = &mut msg {
+            if self.options.padding_block > 0 {
+                pad(msg, self.options.padding_block);
+            }
+        }
+        msg
+    }
+
+    // one query, over UDP first unless told otherwise, then TCP if the answer didn't fit
+    fn exchange(&self, server: &Upstream, msg: &DnsMessage) -> Result<DnsMessage, DnsError> {
+        if !self.options.force_tcp {
+            match self.query_udp(server, msg)? {
+                Some(res) if !res.truncated() => return Ok(res),
+                // TC set, or the datagram didn't even fit what we were willing to take: either
+                // way the full answer only comes over TCP
+                _ => {}
+            }
+        }
+        self.query_tcp(server, msg)
+    }
+
+    // None means the server sent more than max_udp_payload
+    fn query_udp(
+        &self,
+        server: &Upstream,
+        msg: &DnsMessage,
+    ) -> Result<Option<DnsMessage>, DnsError> {
+        let query = msg.to_bytes()?;
+        let waiting = self.mux.send(server.addr, msg, &query)?;
+        Counters::bump(&self.counters.queries_sent);
+
+        // the mux only hands over what came from the server we asked with our ID and question,
+        // and counts the rest as strays: duplicates, leftovers from earlier queries that timed
+        // out, or someone trying their luck with a forged reply. What it does hand over can
+        // still be one of those, getting the rest wrong
+        let payload = self.options.udp_payload();
+        let deadline = Instant::now() + server.timeout;
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            let Some(datagram) = waiting.recv(remaining) else {
+                return Err(self.timed_out());
+            };
+            // more than we said we'd take: a server that ignored our payload size, and whatever
+            // comes after it might have been cut along the way
+            if datagram.len() > payload {
+                return Ok(None);
+            }
+
+            // one that doesn't parse is a stray too: if it ended the query, a forger with the
+            // right ID could kill any lookup with a junk datagram
+            let Ok(res) = DnsMessage::from_bytes(&datagram) else {
+                waiting.stray();
+                continue;
+            };
+            if !echoes_cookie(msg, &res) || (self.options.randomize_case && !echoes_case(msg, &res))
+            {
+                waiting.stray();
+                continue;
+            }
+            self.cookies.store(server.addr.ip(), &res);
+            return Ok(Some(self.received(res)));
+        }
+    }
+
+    fn query_tcp(&self, server: &Upstream, msg: &DnsMessage) -> Result<DnsMessage, DnsError> {
+        let res = match &self.tcp_pool {
+            Some(pool) => {
+                let done = pool
+                    .exchange_with(server.addr, msg, server.timeout)
+                    .map_err(|e| match e {
+                        DnsError::Timeout => self.timed_out(),
+                        e => e,
+                    })?;
+                Counters::bump(&self.counters.queries_sent);
+                let capture = &self.capture;
+                record(
+                    capture,
+                    &self.counters,
+                    done.local,
+                    server.addr,
+                    &done.query,
+                );
+                record(
+                    capture,
+                    &self.counters,
+                    server.addr,
+                    done.local,
+                    &done.response,
+                );
+                done.res
+            }
+            None => {
+                let mut stream =
+                    tcp::connect(server.addr, server.timeout).map_err(|e| self.io_error(e))?;
+                let local = stream.local_addr()?;
+                let query = msg.to_bytes()?;
+                tcp::write_message(&mut stream, &query)?;
+                Counters::bump(&self.counters.queries_sent);
+                record(&self.capture, &self.counters, local, server.addr, &query);
+                let mut buf = Vec::new();
+                let size =
+                    tcp::read_message(&mut stream, &mut buf).map_err(|e| self.io_error(e))?;
+                record(
+                    &self.capture,
+                    &self.counters,
+                    server.addr,
+                    local,
+                    &buf[..size],
+                );
+                DnsMessage::from_bytes(&buf[..size])?
+            }
+        };
+
+        // nobody else's answers come over a connection of ours, but the answer still has to be
+        // for the question we asked
+        if res.header.identification != msg.header.identification
+            || !answers_question(msg, &res)
+            || !echoes_cookie(msg, &res)
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "TCP response doesn't match the query",
+            )
+            .into());
+        }
+        self.cookies.store(server.addr.ip(), &res);
+        Ok(self.received(res))
+    }
+
+    fn received(&self, res: DnsMessage) -> DnsMessage {
+        count_response(&self.counters, &res);
+        res
+    }
+
+    fn io_error(&self, e: io::Error) -> DnsError {
+        if is_timeout(&e) {
+            self.timed_out()
+        } else {
+            e.into()
+        }
+    }
+
+    fn timed_out(&self) -> DnsError {
+        Counters::bump(&self.counters.timeouts);
+        DnsError::Timeout
+    }
+}
+
+// one upstream's part in a race, everything it needs to run on a thread of its own
+struct Racer {
+    server: SocketAddr,
+    timeout: Duration,
+    force_tcp: bool,
+    counters: Arc<Counters>,
+    rtt: Arc<RttTable>,
+    // the resolver's, when it sends cookies
+    cookies: Option<Arc<ClientCookies>>,
+    padding_block: u16,
+}
+
+impl Racer {
+    fn run(&self, msg: &DnsMessage, with_opt: &DnsMessage) -> Result<DnsMessage, DnsError> {
+        let started = Instant::now();
+        let res = self.try_once(msg, with_opt);
+        self.rtt
+            .record(self.server, started.elapsed(), is_answer(&res));
+        res
+    }
+
+    fn try_once(&self, msg: &DnsMessage, with_opt: &DnsMessage) -> Result<DnsMessage, DnsError> {
+        let mut res = self.exchange(&self.with_cookie(with_opt))?;
+        if res.rcode() == RCODE_BADCOOKIE && self.cookies.is_some() {
+            Counters::bump(&self.counters.retries);
+            res = self.exchange(&self.with_cookie(with_opt))?;
+        }
+        // as in query_server, FORMERR to our OPT record gets a second go without it
+        if res.rcode() == RCODE_FORMERR && with_opt.edns != msg.edns {
+            Counters::bump(&self.counters.retries);
+            return self.exchange(msg);
+        }
+        Ok(res)
+    }
+
+    fn exchange(&self, msg: &DnsMessage) -> Result<DnsMessage, DnsError> {
+        let mut tcp = TcpTransport::new(self.server);
+        tcp.set_timeout(self.timeout);
+        let res = if self.force_tcp {
+            self.over(&tcp, msg)
+        } else {
+            let mut udp = UdpTransport::new(self.server);
+            udp.set_timeout(self.timeout);
+            match self.over(&udp, msg) {
+                Ok(res) if res.truncated() => self.over(&tcp, msg),
+                res => res,
+            }
+        };
+        let res = res.and_then(|res| {
+            if !echoes_cookie(msg, &res) {
+                return Err(DnsError::Malformed(
+                    "answer came back with somebody else's cookie",
+                ));
+            }
+            if let Some(cookies) = &self.cookies {
+                cookies.store(self.server.ip(), &res);
+            }
+            Ok(res)
+        });
+        match &res {
+            Ok(res) => count_response(&self.counters, res),
+            Err(DnsError::Timeout) => Counters::bump(&self.counters.timeouts),
+            Err(_) => {}
+        }
+        res
+    }
+
+    fn with_cookie<'a>(&self, msg: &'a DnsMessage) -> Cow<'a, DnsMessage> {
+        let Some(cookies) = &self.cookies else {
+            return Cow::Borrowed(msg);
+        };
+        let mut msg = cookies.attach(msg, self.server.ip());
+        if let Cow::Owned(msg) = &mut msg {
+            if self.padding_block > 0 {
+                pad(msg, self.padding_block);
+            }
+        }
+        msg
+    }
+
+    fn over(&self, transport: &dyn DnsTransport, msg: &DnsMessage) -> Result<DnsMessage, DnsError> {
+        Counters::bump(&self.counters.queries_sent);
+        transport.exchange(msg)
+    }
+}
+
+fn count_response(counters: &Counters, res: &DnsMessage) {
+    Counters::bump(&counters.responses_received);
+    if res.rcode() == RCODE_NXDOMAIN {
+        Counters::bump(&counters.nxdomains);
+    }
+}
+
+// whether a try got an answer worth having, for the RTT table. SERVFAIL and REFUSED come back
+// fast but don't make a server any use to us
+fn is_answer(res: &Result<DnsMessage, DnsError>) -> bool {
+    res.as_ref()
+        .is_ok_and(|res| !matches!(res.rcode(), RCODE_SERVFAIL | RCODE_REFUSED))
+}
+
+fn no_upstreams() -> DnsError {
+    io::Error::new(io::ErrorKind::InvalidInput, "no upstream servers").into()
+}
+
+// whether `res` has what a lookup for `qtype` was after, rather than just the CNAMEs to it
+fn has_records(res: &DnsMessage, qtype: u16) -> bool {
+    match RecordType::from(qtype) {
+        RecordType::Any => !res.answers.is_empty(),
+        _ => res.answers.iter().any(|rr| rr.rr_type == qtype),
+    }
+}
+
+// a Resolver with all its upstreams, failover and TCP fallback is a transport as well
+impl DnsTransport for Resolver {
+    fn exchange(&self, msg: &DnsMessage) -> Result<DnsMessage, DnsError> {
+        self.query(msg)
+    }
+}
+
+// a Resolver's pcap capture, written to by its queries and by the threads of its UdpMux
+pub(crate) type Capture = Mutex<Option<PcapWriter<Box<dyn Write + Send>>>>;
+
+// a capture is a debugging aid, failing to write to it shouldn't fail the query. It does end
+// the capture (a full disk isn't going to get better on the next packet), and shows up in the
+// stats as capture_errors
+pub(crate) fn record(
+    capture: &Capture,
+    counters: &Counters,
+    src: SocketAddr,
+    dst: SocketAddr,
+    payload: &[u8],
+) {
+    let mut capture = capture.lock().unwrap();
+    if let Some(pcap) = capture.as_mut() {
+        if pcap
+            .write_packet(SystemTime::now(), src, dst, payload)
+            .is_err()
+        {
+            Counters::bump(&counters.capture_errors);
+            *capture = None;
+        }
+    }
+}
+
+// a read timeout shows up as WouldBlock on unix and TimedOut on windows
+fn is_timeout(e: &io::Error) -> bool {
+    matches!(
+        e.kind(),
+        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+    )
+}
+
+fn is_signed(msg: &DnsMessage) -> bool {
+    msg.additional.iter().any(|rr| rr.rr_type == TYPE_TSIG)
+}
+
+// the query as it goes out with QueryOptions::randomize_case, None when it goes out as it is. A
+// TSIG signature covers the name as it was, we leave those alone
+pub(crate) fn with_mixed_case(msg: &DnsMessage, options: &QueryOptions) -> Option<DnsMessage> {
+    if !options.randomize_case || is_signed(msg) {
+        return None;
+    }
+    let mut mixed = msg.clone();
+    for question in &mut mixed.questions {
+        question.qname = mixed_case(&question.qname);
+    }
+    Some(mixed)
+}
+
+// the answer to `mixed` as if `msg` had been sent: the name back the way it was asked for, in
+// the question and the records for it. Over UDP a wrong case was already dropped like any other
+// stray, this is for the answers that came some other way
+pub(crate) fn case_restored(
+    msg: &DnsMessage,
+    mixed: &DnsMessage,
+    mut res: DnsMessage,
+) -> Result<DnsMessage, DnsError> {
+    if !echoes_case(mixed, &res) {
+        return Err(DnsError::Malformed(
+            "answer doesn't repeat the query name's case",
+        ));
+    }
+    for rr in res.answers.iter_mut().chain(&mut res.authority) {
+        if rr.name == msg.question().qname {
+            rr.name = msg.question().qname.clone();
+        }
+    }
+    for (question, asked) in res.questions.iter_mut().zip(&msg.questions) {
+        question.qname = asked.qname.clone();
+    }
+    Ok(res)
+}
+
+// `name` with each letter in upper or lower case at random
+fn mixed_case(name: &Name) -> Name {
+    let mut mixed = String::with_capacity(name.as_str().len());
+    let mut bits = 0;
+    for (i, c) in name.as_str().chars().enumerate() {
+        if i % 64 == 0 {
+            bits = random_u64();
+        }
+        mixed.push(match bits >> (i % 64) & 1 {
+            1 => c.to_ascii_uppercase(),
+            _ => c.to_ascii_lowercase(),
+        });
+    }
+    Name::from(mixed)
+}
+
+// whether `res` has the question name exactly as `query` sent it, case and all. Something that
+// doesn't echo the question at all (a FORMERR, say) can't be held to that. We only ever look
+// when the case was randomized, otherwise it's the same as answers_question
+pub(crate) fn echoes_case(query: &DnsMessage, res: &DnsMessage) -> bool {
+    res.questions.is_empty() || res.question().qname.as_str() == query.question().qname.as_str()
+}
+
+// the query as it goes out: with an OPT record telling the server how big a UDP answer we can
+// take, unless the options say classic DNS or the caller already put one in. A TSIG-signed query
+// is left alone too, anything added after the signature would break it
+pub(crate) fn with_edns<'a>(msg: &'a DnsMessage, options: &QueryOptions) -> Cow<'a, DnsMessage> {
+    if !options.uses_edns() || msg.edns.is_some() || is_signed(msg) {
+        return Cow::Borrowed(msg);
+    }
+    let mut msg = msg.clone();
+    let mut edns = Edns::new(options.max_udp_payload);
+    if let Some(subnet) = &options.client_subnet {
+        edns.options.push(subnet.to_option());
+    }
+    msg.edns = Some(edns);
+    if options.padding_block > 0 {
+        pad(&mut msg, options.padding_block);
+    }
+    Cow::Owned(msg)
+}
+
+// (re)pads a message with an OPT record. The padding goes last, after whatever options went on
+// since the last time (a cookie, say)
+fn pad(msg: &mut DnsMessage, block: u16) {
+    let Some(edns) = msg.edns.as_mut() else {
+        return;
+    };
+    edns.options.retain(|option| option.code != EDNS_PADDING);
+    // a message that doesn't encode fails as soon as it's sent, its padding doesn't matter
+    let msg_len = msg.to_bytes().map_or(0, |bytes| bytes.len());
+    let padding = padding(msg_len, block);
+    msg.edns.as_mut().unwrap().options.push(padding);
+}
+
+// the padding option (code 12, all zeros) for a message that is `msg_len` bytes with the rest of
+// its OPT record, sized so the final message lands exactly on a multiple of `block`
+fn padding(msg_len: usize, block: u16) -> EdnsOption {
+    // the option's code and length
+    const OVERHEAD: usize = 4;
+    let block = block as usize;
+    let len = (block - (msg_len + OVERHEAD) % block) % block;
+    EdnsOption {
+        code: EDNS_PADDING,
+        data: vec![0; len],
+    }
+}
+
+// with RD off, a server should answer from its own zones (AA set) or refer us further down.
+// A non-authoritative answer means it went and looked the name up for us anyway, which is no use
+// when we are walking the tree ourselves. Strictly a server may also answer RD=0 from its cache,
+// but for picking an upstream to iterate against that's just as bad
+pub(crate) fn recursed_anyway(query: &DnsMessage, res: &DnsMessage) -> bool {
+    let flags = res.flags();
+    !query.recursion_desired() && !flags.aa && res.rcode() == 0 && !res.answers.is_empty()
+}
+
+// answers and NXDOMAINs (the cache decides whether a negative one has what it takes to be kept):
+// other errors say nothing that lasts, and a truncated answer is only part of one. A signed
+// response's TSIG is for that one exchange and can't be handed out again
+fn cacheable(res: &DnsMessage) -> bool {
+    matches!(res.rcode(), 0 | RCODE_NXDOMAIN)
+        && !res.truncated()
+        && !res.additional.iter().any(|rr| rr.rr_type == TYPE_TSIG)
+}
+
+// the right ID isn't enough, the response has to be about the question we asked. Some servers
+// leave the question out of error responses, so there is nothing to compare in that case.
+// Servers are free to change the case of the name (and some do on purpose), so that's not a
+// mismatch
+pub(crate) fn answers_question(query: &DnsMessage, res: &DnsMessage) -> bool {
+    res.questions.is_empty()
+        || (res.question().qname == query.question().qname
+            && res.question().qtype == query.question().qtype
+            && res.question().qclass == query.question().qclass)
+}
+
+// bind to the same address family as the server we are talking to
+pub(crate) fn unspecified_addr(server: &SocketAddr) -> SocketAddr {
+    match server {
+        SocketAddr::V4(_) => "0.0.0.0:0".parse().unwrap(),
+        SocketAddr::V6(_) => "[::]:0".parse().unwrap(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{
+        free_loopback_port, mock_stalling_udp_server, mock_tcp_server_at, mock_udp_server,
+        mock_udp_server_replies, response_with_rdata, with_id,
+    };
+    use crate::{ClientSubnet, ExtendedError, RData, ResourceRecord};
+    use std::net::{Ipv4Addr, UdpSocket};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::time::Instant;
+
+    #[test]
+    fn test_repeated_large_responses() {
+        const QUERIES: usize = 50;
+
+        // one big record of a private-use type, sized so the whole packet is exactly 4KB
+        let rdata: Vec<u8> = (0..4055).map(|i| i as u8).collect();
+        let expected = rdata.clone();
+        let server = mock_udp_server(QUERIES, move |query| {
+            response_with_rdata(query, 0xFF00, &rdata)
+        });
+        let mut resolver = Resolver::with_server(server);
+        resolver.set_options(QueryOptions {
+            max_udp_payload: 4096,
+            ..QueryOptions::default()
+        });
+
+        // all read into the one buffer of the mux socket's thread, none of them cut short or
+        // mixed up with what the one before left in it
+        let msg = DnsMessage::new("example.com".to_string());
+        for _ in 0..QUERIES {
+            let res = resolver.query(&msg).unwrap();
+            assert_eq!(res.answers.len(), 1);
+            assert_eq!(res.answers[0].rdlength as usize, expected.len());
+            assert_eq!(res.answers[0].rdata, expected);
+        }
+        assert_eq!(resolver.stats().queries_sent, QUERIES as u64);
+    }
+
+    #[test]
+    fn test_stray_responses_are_dropped_and_counted() {
+        // first query: a reply with the wrong ID shows up before the real one, and the real one
+        // is then sent twice. The duplicate comes in once the answer has been taken, and must
+        // not be taken as the second query's answer
+        let server = mock_udp_server_replies(2, |query| {
+            let answer = response_with_rdata(query, 1, &[127, 0, 0, 1]);
+            if query[0..2] == 0x1111u16.to_be_bytes() {
+                vec![with_id(answer.clone(), 0xBEEF), answer.clone(), answer]
+            } else {
+                vec![answer]
+            }
+        });
+        let resolver = Resolver::with_server(server);
+        assert_eq!(resolver.stats().stray_responses, 0);
+
+        let mut first = DnsMessage::new("example.com".to_string());
+        first.header.identification = 0x1111;
+        let res = resolver.query(&first).unwrap();
+        assert_eq!(res.header.identification, 0x1111);
+        // the duplicate maybe still on its way
+        thread::sleep(Duration::from_millis(100));
+        assert_eq!(resolver.stats().stray_responses, 2);
+
+        let mut second = DnsMessage::new("example.com".to_string());
+        second.header.identification = 0x2222;
+        let res = resolver.query(&second).unwrap();
+        assert_eq!(res.header.identification, 0x2222);
+        assert_eq!(resolver.stats().stray_responses, 2);
+    }
+
+    #[test]
+    fn test_queries_dont_wait_on_each_other() {
+        let server = mock_stalling_udp_server(2, "slow.example", Duration::from_secs(1), |query| {
+            response_with_rdata(query, 1, &[192, 0, 2, 1])
+        });
+        let resolver = Resolver::with_server(server);
+        thread::scope(|scope| {
+            let slow = scope.spawn(|| resolver.query(&DnsMessage::query("slow.example").build()));
+            thread::sleep(Duration::from_millis(100));
+            let started = Instant::now();
+            resolver
+                .query(&DnsMessage::query("fast.example").build())
+                .unwrap();
+            assert!(started.elapsed() < Duration::from_millis(500));
+            slow.join().unwrap().unwrap();
+        });
+    }
+
+    #[test]
+    fn test_junk_with_the_right_id_is_a_stray() {
+        // the ID and then nothing DNS about it, ahead of the real answer
+        let server = mock_udp_server_replies(1, |query| {
+            let answer = response_with_rdata(query, 1, &[127, 0, 0, 1]);
+            vec![[&query[0..2], &[0xff; 7][..]].concat(), answer]
+        });
+        let resolver = Resolver::with_server(server);
+        let res = resolver
+            .query(&DnsMessage::query("example.com").build())
+            .unwrap();
+        assert_eq!(res.ipv4_addrs(), [Ipv4Addr::new(127, 0, 0, 1)]);
+        assert_eq!(resolver.stats().stray_responses, 1);
+    }
+
+    #[test]
+    fn test_randomized_case_has_to_come_back() {
+        // the name goes on the wire exactly as written
+        let query = DnsMessage::query(Name::from("WwW.eXample.COM")).build();
+        let parsed = DnsMessage::from_bytes(&query.to_bytes().unwrap()).unwrap();
+        assert_eq!(parsed.question().qname.as_str(), "WwW.eXample.COM");
+
+        // long enough that the odds of it coming out all lowercase don't matter
+        let name = "www.randomized-case-example.example.com";
+        let options = QueryOptions {
+            randomize_case: true,
+            ..QueryOptions::default()
+        };
+        let echoing = mock_udp_server(1, |query| response_with_rdata(query, 1, &[192, 0, 2, 1]));
+        let mut resolver = Resolver::with_server(echoing);
+        resolver.set_options(options.clone());
+        let res = resolver
+            .query(&DnsMessage::query(Name::from(name)).build())
+            .unwrap();
+        assert_eq!(res.question().qname.as_str(), name);
+        assert_eq!(res.answers[0].name.as_str(), name);
+
+        // a server (or a forger) that doesn't know the case we sent gets nowhere
+        let lowering = mock_udp_server(1, |query| {
+            let mut query = query.to_vec();
+            query[12..].make_ascii_lowercase();
+            response_with_rdata(&query, 1, &[192, 0, 2, 66])
+        });
+        let mut resolver = Resolver::with_server(lowering);
+        resolver.set_options(options);
+        resolver.set_timeout(Duration::from_millis(300));
+        let err = resolver
+            .query(&DnsMessage::query(Name::from(name)).build())
+            .unwrap_err();
+        assert!(matches!(err, DnsError::Timeout));
+        assert_eq!(resolver.stats().stray_responses, 1);
+    }
+
+    // answers everything with one A record, `octet` as its last byte so we can tell who answered
+    fn answering(count: usize, octet: u8) -> SocketAddr {
+        mock_udp_server(count, move |query| {
+            response_with_rdata(query, 1, &[192, 0, 2, octet])
+        })
+    }
+
+    #[test]
+    fn test_failover_to_the_next_upstream() {
+        // the first server never answers, the second refuses, the third has the answer
+        let silent = mock_udp_server_replies(2, |_| vec![]);
+        let refusing = mock_udp_server(2, |query| {
+            let mut res = response_with_rdata(query, 1, &[0, 0, 0, 0]);
+            res[3] |= RCODE_REFUSED as u8;
+            res
+        });
+        let mut resolver = Resolver::with_servers([silent]);
+        resolver.set_timeout(Duration::from_millis(200));
+        resolver.add_server(refusing, Duration::from_secs(1));
+        resolver.add_server(answering(2, 3), Duration::from_secs(1));
+        assert_eq!(resolver.servers().len(), 3);
+
+        for _ in 0..2 {
+            let res = resolver
+                .query(&DnsMessage::new("example.com".to_string()))
+                .unwrap();
+            assert_eq!(res.ipv4_addrs(), vec![Ipv4Addr::new(192, 0, 2, 3)]);
+        }
+        let stats = resolver.stats();
+        assert_eq!(stats.queries_sent, 6);
+        assert_eq!(stats.timeouts, 2);
+    }
+
+    #[test]
+    fn test_from_config_goes_round_the_servers_again() {
+        // the only server drops the first query and answers the second
+        let answered = Arc::new(AtomicUsize::new(0));
+        let count = answered.clone();
+        let server = mock_udp_server_replies(2, move |query| {
+            if count.fetch_add(1, Ordering::SeqCst) == 0 {
+                return vec![];
+            }
+            vec![response_with_rdata(query, 1, &[192, 0, 2, 1])]
+        });
+        let config = SystemConfig {
+            nameservers: vec![server],
+            search: vec![Name::from("example.com")],
+            ndots: 2,
+            timeout: Duration::from_millis(200),
+            attempts: 2,
+            rotate: true,
+        };
+        let resolver = Resolver::from_config(&config);
+        assert_eq!(resolver.search(), [Name::from("example.com")]);
+        assert_eq!(resolver.strategy, Strategy::RoundRobin);
+
+        let res = resolver
+            .query(&DnsMessage::new("example.com".to_string()))
+            .unwrap();
+        assert_eq!(res.ipv4_addrs(), vec![Ipv4Addr::new(192, 0, 2, 1)]);
+        assert_eq!(resolver.stats().timeouts, 1);
+        assert_eq!(answered.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn test_retry_policy_can_stay_on_one_server() {
+        // the first server drops the first query; the second would answer, but we don't switch
+        let first = mock_udp_server_replies(2, {
+            let dropped = AtomicUsize::new(0);
+            move |query| {
+                if dropped.fetch_add(1, Ordering::SeqCst) == 0 {
+                    return vec![];
+                }
+                vec![response_with_rdata(query, 1, &[192, 0, 2, 1])]
+            }
+        });
+        let second = mock_udp_server(1, |query| response_with_rdata(query, 1, &[192, 0, 2, 2]));
+        let mut resolver = Resolver::with_servers([first, second]);
+        resolver.set_retry_policy(RetryPolicy {
+            attempts: 2,
+            timeout: Some(Duration::from_millis(200)),
+            backoff: Duration::from_millis(50),
+            max_backoff: Duration::from_millis(50),
+            jitter: false,
+            switch_server: false,
+            deadline: None,
+        });
+
+        let started = Instant::now();
+        let res = resolver
+            .query(&DnsMessage::new("example.com".to_string()))
+            .unwrap();
+        assert_eq!(res.ipv4_addrs(), vec![Ipv4Addr::new(192, 0, 2, 1)]);
+        assert!(started.elapsed() >= Duration::from_millis(250));
+        let stats = resolver.stats();
+        assert_eq!((stats.timeouts, stats.retries), (1, 1));
+    }
+
+    #[test]
+    fn test_fastest_goes_by_rtt() {
+        let slow = mock_udp_server(1, |query| {
+            thread::sleep(Duration::from_millis(150));
+            response_with_rdata(query, 1, &[192, 0, 2, 1])
+        });
+        let mut resolver = Resolver::with_servers([slow, answering(3, 2)]);
+        resolver.set_strategy(Strategy::Fastest {
+            probe_every: Duration::from_secs(60),
+        });
+
+        // each upstream gets a go before the RTTs decide, the slow one first since it was first
+        for octet in [1, 2, 2, 2] {
+            let res = resolver
+                .query(&DnsMessage::new("example.com".to_string()))
+                .unwrap();
+            assert_eq!(res.ipv4_addrs(), vec![Ipv4Addr::new(192, 0, 2, octet)]);
+        }
+        let health = resolver.server_health();
+        assert_eq!(health[0].addr, slow);
+        assert!(health[0].srtt.unwrap() >= Duration::from_millis(150));
+        assert!(health[1].srtt.unwrap() < health[0].srtt.unwrap());
+        assert_eq!((health[1].failures, health[1].demoted), (0, false));
+    }
+
+    #[test]
+    fn test_deadline_cuts_the_retries_short() {
+        // three tries of 200ms would take 600ms and more, the deadline leaves time for one and
+        // a half
+        let silent = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let mut resolver = Resolver::with_server(silent.local_addr().unwrap());
+        resolver.set_retry_policy(RetryPolicy {
+            attempts: 3,
+            timeout: Some(Duration::from_millis(200)),
+            backoff: Duration::ZERO,
+            max_backoff: Duration::ZERO,
+            jitter: false,
+            switch_server: true,
+            deadline: Some(Duration::from_millis(300)),
+        });
+
+        let started = Instant::now();
+        let err = resolver
+            .query(&DnsMessage::new("example.com".to_string()))
+            .unwrap_err();
+        assert!(matches!(err, DnsError::Timeout));
+        let elapsed = started.elapsed();
+        assert!(elapsed >= Duration::from_millis(300) && elapsed < Duration::from_millis(500));
+        assert_eq!(resolver.stats().retries, 1);
+    }
+
+    #[test]
+    fn test_race_takes_the_first_answer() {
+        // the first upstream never answers, the second does straight away
+        let silent = mock_udp_server_replies(1, |_| vec![]);
+        let answering = mock_udp_server(1, |query| response_with_rdata(query, 1, &[192, 0, 2, 2]));
+        let mut resolver = Resolver::with_servers([silent, answering]);
+        resolver.set_timeout(Duration::from_secs(5));
+        resolver.set_strategy(Strategy::Race {
+            hedge: Duration::ZERO,
+        });
+
+        let started = Instant::now();
+        let res = resolver
+            .query(&DnsMessage::new("example.com".to_string()))
+            .unwrap();
+        assert_eq!(res.ipv4_addrs(), vec![Ipv4Addr::new(192, 0, 2, 2)]);
+        assert!(started.elapsed() < Duration::from_secs(1));
+        assert_eq!(resolver.stats().queries_sent, 2);
+    }
+
+    #[test]
+    fn test_hedged_race_only_asks_the_next_one_when_needed() {
+        let asked = Arc::new(AtomicUsize::new(0));
+        let first = mock_udp_server(1, |query| response_with_rdata(query, 1, &[192, 0, 2, 1]));
+        let second = mock_udp_server(1, {
+            let asked = asked.clone();
+            move |query| {
+                asked.fetch_add(1, Ordering::SeqCst);
+                response_with_rdata(query, 1, &[192, 0, 2, 2])
+            }
+        });
+        let mut resolver = Resolver::with_servers([first, second]);
+        resolver.set_strategy(Strategy::Race {
+            hedge: Duration::from_millis(200),
+        });
+
+        let res = resolver
+            .query(&DnsMessage::new("example.com".to_string()))
+            .unwrap();
+        assert_eq!(res.ipv4_addrs(), vec![Ipv4Addr::new(192, 0, 2, 1)]);
+        // well past the second one's turn, and it still hasn't been bothered
+        thread::sleep(Duration::from_millis(400));
+        assert_eq!(asked.load(Ordering::SeqCst), 0);
+        assert_eq!(resolver.stats().queries_sent, 1);
+    }
+
+    #[test]
+    fn test_round_robin_spreads_queries() {
+        let mut resolver = Resolver::with_servers([answering(2, 1), answering(2, 2)]);
+        resolver.set_strategy(Strategy::RoundRobin);
+
+        let answered_by: Vec<u8> = (0..4)
+            .map(|_| {
+                let res = resolver
+                    .query(&DnsMessage::new("example.com".to_string()))
+                    .unwrap();
+                res.ipv4_addrs()[0].octets()[3]
+            })
+            .collect();
+        assert_eq!(answered_by, vec![1, 2, 1, 2]);
+    }
+
+    #[test]
+    fn test_all_upstreams_failing_gives_the_last_result() {
+        let refusing = mock_udp_server(1, |query| {
+            let mut res = response_with_rdata(query, 1, &[0, 0, 0, 0]);
+            res[3] |= RCODE_SERVFAIL as u8;
+            res
+        });
+        let silent = mock_udp_server_replies(1, |_| vec![]);
+        let mut resolver = Resolver::with_servers([silent, refusing]);
+        resolver.set_timeout(Duration::from_millis(200));
+        let res = resolver
+            .query(&DnsMessage::new("example.com".to_string()))
+            .unwrap();
+        assert_eq!(res.rcode(), RCODE_SERVFAIL);
+
+        let err = Resolver::with_servers([])
+            .query(&DnsMessage::new("example.com".to_string()))
+            .unwrap_err();
+        assert!(matches!(err, DnsError::Io(_)));
+    }
+
+    #[test]
+    fn test_stats_count_answers_nxdomains_and_timeouts() {
+        // bigfoot.example doesn't exist, silent.example never gets an answer
+        let server = mock_udp_server_replies(5, |query| {
+            let mut answer = response_with_rdata(query, 1, &[127, 0, 0, 1]);
+            if query.windows(7).any(|w| w == b"bigfoot") {
+                answer[3] |= RCODE_NXDOMAIN as u8;
+            }
+            if query.windows(6).any(|w| w == b"silent") {
+                return vec![];
+            }
+            vec![answer]
+        });
+        let mut resolver = Resolver::with_server(server);
+        resolver.set_timeout(Duration::from_millis(200));
+
+        for name in [
+            "example.com",
+            "bigfoot.example",
+            "example.com",
+            "silent.example",
+        ] {
+            let _ = resolver.query(&DnsMessage::new(name.to_string()));
+        }
+        let err = resolver
+            .query(&DnsMessage::new("silent.example".to_string()))
+            .unwrap_err();
+        assert!(matches!(err, DnsError::Timeout));
+
+        assert_eq!(
+            resolver.stats(),
+            Stats {
+                queries_sent: 5,
+                responses_received: 3,
+                timeouts: 2,
+                nxdomains: 1,
+                ..Stats::default()
+            }
+        );
+    }
+
+    #[test]
+    fn test_cache_answers_repeated_queries() {
+        // two upstream queries at most: example.com once, and the NXDOMAIN every time it's asked
+        let server = mock_udp_server(3, |query| {
+            let mut answer = response_with_rdata(query, 1, &[192, 0, 2, 1]);
+            if query.windows(7).any(|w| w == b"bigfoot") {
+                answer[3] |= RCODE_NXDOMAIN as u8;
+            }
+            answer
+        });
+        let cache = Arc::new(DnsCache::new());
+        let mut resolver = Resolver::with_server(server);
+        resolver.set_cache(cache.clone());
+
+        let first = resolver
+            .query(&DnsMessage::new("example.com".to_string()))
+            .unwrap();
+        assert_eq!(first.answers[0].ttl, 300);
+        let mut query = DnsMessage::new("EXAMPLE.com".to_string());
+        query.header.identification = 0x4321;
+        let second = resolver.query(&query).unwrap();
+        assert_eq!(second.header.identification, 0x4321);
+        assert_eq!(second.question().qname.as_str(), "EXAMPLE.com");
+        assert_eq!(second.ipv4_addrs(), vec![Ipv4Addr::new(192, 0, 2, 1)]);
+        assert!(second.answers[0].ttl <= 300 && second.answers[0].ttl >= 298);
+
+        for _ in 0..2 {
+            let res = resolver
+                .query(&DnsMessage::new("bigfoot.example".to_string()))
+                .unwrap();
+            assert_eq!(res.rcode(), RCODE_NXDOMAIN);
+        }
+        assert_eq!(cache.len(), 1);
+
+        let stats = resolver.stats();
+        assert_eq!(stats.queries_sent, 3);
+        assert_eq!(stats.cache_hits, 1);
+        assert_eq!(stats.cache_misses, 3);
+    }
+
+    #[test]
+    fn test_stats_count_cache_hits_and_misses() {
+        let server = mock_udp_server(3, |query| response_with_rdata(query, 1, &[192, 0, 2, 1]));
+        let mut resolver = Resolver::with_server(server);
+        resolver.set_cache(Arc::new(DnsCache::new()));
+
+        for (name, hits, misses) in [
+            ("a.example", 0, 1),
+            ("a.example", 1, 1),
+            ("b.example", 1, 2),
+            ("B.EXAMPLE", 2, 2),
+            ("a.example", 3, 2),
+            ("c.example", 3, 3),
+        ] {
+            resolver.query(&DnsMessage::query(name).build()).unwrap();
+            let stats = resolver.stats();
+            assert_eq!(
+                (stats.cache_hits, stats.cache_misses),
+                (hits, misses),
+                "{}",
+                name
+            );
+        }
+        assert_eq!(resolver.stats().queries_sent, 3);
+
+        // no cache, nothing to hit or miss
+        let server = mock_udp_server(1, |query| response_with_rdata(query, 1, &[192, 0, 2, 1]));
+        let resolver = Resolver::with_server(server);
+        resolver
+            .query(&DnsMessage::query("a.example").build())
+            .unwrap();
+        assert_eq!(resolver.stats().cache_misses, 0);
+    }
+
+    #[test]
+    fn test_client_subnet_goes_out_and_scopes_the_cache() {
+        // answers with the first three octets of the subnet it was asked for, good for that /24
+        let server = mock_udp_server(2, |query| {
+            let query = DnsMessage::from_bytes(query).unwrap();
+            let subnet = query.edns.as_ref().unwrap().client_subnet().unwrap();
+            let IpAddr::V4(addr) = subnet.addr else {
+                panic!("expected an IPv4 subnet");
+            };
+            let mut res = DnsMessage::response_to(&query);
+            let [a, b, c, _] = addr.octets();
+            res.add_answer(ResourceRecord::new(
+                "cdn.example",
+                300,
+                RData::A(Ipv4Addr::new(a, b, c, 1)),
+            ));
+            let mut edns = Edns::new(1232);
+            edns.options.push(
+                ClientSubnet {
+                    scope_prefix: 24,
+                    ..subnet
+                }
+                .to_option(),
+            );
+            res.edns = Some(edns);
+            res.to_bytes().unwrap()
+        });
+        let mut resolver = Resolver::with_server(server);
+        resolver.set_cache(Arc::new(DnsCache::new()));
+        let query = DnsMessage::query("cdn.example").build();
+        let mut ask_for = |octets: [u8; 4]| {
+            resolver.set_options(QueryOptions {
+                client_subnet: Some(ClientSubnet::new(IpAddr::from(octets), 24)),
+                ..QueryOptions::default()
+            });
+            resolver.query(&query).unwrap().ipv4_addrs()
+        };
+        assert_eq!(ask_for([198, 51, 100, 7]), [Ipv4Addr::new(198, 51, 100, 1)]);
+        assert_eq!(ask_for([203, 0, 113, 7]), [Ipv4Addr::new(203, 0, 113, 1)]);
+        // from the cache, the server only answers twice
+        assert_eq!(
+            ask_for([198, 51, 100, 99]),
+            [Ipv4Addr::new(198, 51, 100, 1)]
+        );
+        assert_eq!(resolver.stats().cache_hits, 1);
+    }
+
+    #[test]
+    fn test_resolve_merges_both_families() {
+        // www is a CNAME for host, which has the same IPv4 address twice and one IPv6 address
+        let server = mock_udp_server(2, |query| {
+            let query = DnsMessage::from_bytes(query).unwrap();
+            let mut res = DnsMessage::response_to(&query);
+            let qname = query.question().qname.clone();
+            res.add_answer(ResourceRecord::new(
+                qname,
+                300,
+                RData::CNAME("host.example.com".to_string()),
+            ));
+            let data = match query.question().record_type() {
+                RecordType::A => [
+                    RData::A([192, 0, 2, 1].into()),
+                    RData::A([192, 0, 2, 1].into()),
+                ],
+                _ => [
+                    RData::AAAA("2001:db8::1".parse().unwrap()),
+                    RData::TXT(Vec::new()),
+                ],
+            };
+            for data in data {
+                res.add_answer(ResourceRecord::new("host.example.com", 300, data));
+            }
+            res.to_bytes().unwrap()
+        });
+        let resolver = Resolver::with_server(server);
+        let mut addrs = resolver.resolve("www.example.com").unwrap();
+        // which family comes first depends on the routes of the machine running this
+        addrs.sort();
+        assert_eq!(
+            addrs,
+            vec![
+                IpAddr::from([192, 0, 2, 1]),
+                "2001:db8::1".parse::<IpAddr>().unwrap()
+            ]
+        );
+        assert_eq!(resolver.stats().queries_sent, 2);
+
+        // a name that doesn't exist is an error, not an empty list
+        let server = mock_udp_server(2, |query| {
+            let mut res = response_with_rdata(query, 1, &[192, 0, 2, 1]);
+            res[3] |= RCODE_NXDOMAIN as u8;
+            res
+        });
+        let err = Resolver::with_server(server).resolve("nowhere.example");
+        assert!(matches!(err, Err(DnsError::Rcode(RCODE_NXDOMAIN))));
+    }
+
+    // a server that only knows the one step of a chain: www CNAME cdn, cdn CNAME edge (or back
+    // to www with `looping`), edge A 192.0.2.7
+    fn chain_server(count: usize, looping: bool) -> SocketAddr {
+        mock_udp_server(count, move |query| {
+            let query = DnsMessage::from_bytes(query).unwrap();
+            let mut res = DnsMessage::response_to(&query);
+            let qname = query.question().qname.clone();
+            let data = match qname.as_str() {
+                "www.example.com" => RData::CNAME("cdn.example.net".to_string()),
+                "cdn.example.net" if looping => RData::CNAME("www.example.com".to_string()),
+                "cdn.example.net" => RData::CNAME("edge.example.org".to_string()),
+                _ => RData::A([192, 0, 2, 7].into()),
+            };
+            res.add_answer(ResourceRecord::new(qname, 300, data));
+            res.to_bytes().unwrap()
+        })
+    }
+
+    #[test]
+    fn test_lookup_follows_cnames() {
+        let resolver = Resolver::with_server(chain_server(3, false));
+        let lookup = resolver.lookup("www.example.com", RecordType::A).unwrap();
+        assert_eq!(
+            lookup.chain,
+            ["www.example.com", "cdn.example.net", "edge.example.org"].map(Name::from)
+        );
+        assert_eq!(lookup.response.question().qname, "www.example.com");
+        assert_eq!(lookup.response.answers.len(), 3);
+        assert_eq!(
+            lookup.response.ipv4_addrs(),
+            vec![Ipv4Addr::new(192, 0, 2, 7)]
+        );
+        assert_eq!(resolver.stats().queries_sent, 3);
+
+        // resolve goes down the chain too
+        let resolver = Resolver::with_server(chain_server(6, false));
+        assert_eq!(
+            resolver.resolve("www.example.com").unwrap(),
+            vec![IpAddr::from([192, 0, 2, 7])]
+        );
+
+        // a chain longer than we allow
+        let mut resolver = Resolver::with_server(chain_server(2, false));
+        resolver.set_max_cnames(1);
+        let err = resolver.lookup("www.example.com", RecordType::A);
+        assert!(matches!(err, Err(DnsError::Malformed(_))));
+    }
+
+    #[test]
+    fn test_lookup_cname_loop_is_an_error() {
+        let resolver = Resolver::with_server(chain_server(2, true));
+        let err = resolver.lookup("www.example.com", RecordType::A);
+        assert!(matches!(err, Err(DnsError::Malformed(_))));
+        assert_eq!(resolver.stats().queries_sent, 2);
+    }
+
+    #[test]
+    fn test_search_list() {
+        // only db01.lab.example exists, with an IPv4 address and nothing else
+        let server = mock_udp_server(8, |query| {
+            let query = DnsMessage::from_bytes(query).unwrap();
+            let mut res = DnsMessage::response_to(&query);
+            let qname = query.question().qname.clone();
+            if qname != "db01.lab.example" {
+                let mut flags = res.flags();
+                flags.rcode = RCODE_NXDOMAIN as u8;
+                res.set_flags(flags);
+            } else if query.question().record_type() == RecordType::A {
+                res.add_answer(ResourceRecord::new(
+                    qname,
+                    300,
+                    RData::A([192, 0, 2, 8].into()),
+                ));
+            }
+            res.to_bytes().unwrap()
+        });
+        let mut resolver = Resolver::with_server(server);
+        resolver.set_search(vec![Name::from("corp.example"), Name::from("lab.example")]);
+        resolver.set_ndots(2);
+
+        assert_eq!(
+            resolver.search_names("db01.lab"),
+            ["db01.lab.corp.example", "db01.lab.lab.example", "db01.lab"].map(Name::from)
+        );
+        assert_eq!(
+            resolver.search_names("db01.lab.example"),
+            [
+                "db01.lab.example",
+                "db01.lab.example.corp.example",
+                "db01.lab.example.lab.example"
+            ]
+            .map(Name::from)
+        );
+        assert_eq!(resolver.search_names("db01."), [Name::from("db01")]);
+
+        // two names down the list, an A and an AAAA query for each
+        let resolved = resolver.resolve_traced("db01").unwrap();
+        assert_eq!(resolved.addrs, vec![IpAddr::from([192, 0, 2, 8])]);
+        assert_eq!(
+            resolved.tried,
+            ["db01.corp.example", "db01.lab.example"].map(Name::from)
+        );
+
+        // enough dots to go as is first, and no further
+        let lookup = resolver.lookup("db01.lab.example", RecordType::A).unwrap();
+        assert_eq!(lookup.tried, [Name::from("db01.lab.example")]);
+        assert_eq!(
+            lookup.response.ipv4_addrs(),
+            vec![Ipv4Addr::new(192, 0, 2, 8)]
+        );
+
+        // nowhere on the list: NXDOMAIN from the last name tried
+        let lookup = resolver.lookup("db02.", RecordType::A).unwrap();
+        assert_eq!(lookup.response.rcode(), RCODE_NXDOMAIN);
+        assert_eq!(resolver.stats().queries_sent, 6);
+        let err = resolver.resolve("db02.");
+        assert!(matches!(err, Err(DnsError::Rcode(RCODE_NXDOMAIN))));
+    }
+
+    #[test]
+    fn test_lookup_addr() {
+        let server = mock_udp_server(2, |query| {
+            let query = DnsMessage::from_bytes(query).unwrap();
+            let mut res = DnsMessage::response_to(&query);
+            let qname = query.question().qname.clone();
+            assert_eq!(query.question().record_type(), RecordType::PTR);
+            if qname == "1.2.0.192.in-addr.arpa" {
+                for host in ["one.example.com", "uno.example.com"] {
+                    res.add_answer(ResourceRecord::new(
+                        qname.clone(),
+                        300,
+                        RData::PTR(host.to_string()),
+                    ));
+                }
+            } else {
+                let mut flags = res.flags();
+                flags.rcode = RCODE_NXDOMAIN as u8;
+                res.set_flags(flags);
+            }
+            res.to_bytes().unwrap()
+        });
+        let resolver = Resolver::with_server(server);
+        assert_eq!(
+            resolver.lookup_addr(IpAddr::from([192, 0, 2, 1])).unwrap(),
+            vec!["one.example.com", "uno.example.com"]
+        );
+        assert!(resolver
+            .lookup_addr("2001:db8::1".parse().unwrap())
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn test_recursion_despite_rd_off_is_flagged() {
+        // the first query is answered like a recursive resolver would (RA, no AA), the second
+        // like an authoritative server, the third is a referral
+        let server = mock_udp_server(3, |query| {
+            let query = DnsMessage::from_bytes(query).unwrap();
+            let mut res = DnsMessage::response_to(&query);
+            let mut flags = res.flags();
+            match query.question().qname.as_str() {
+                "recursive.example" => flags.ra = true,
+                "auth.example" => flags.aa = true,
+                _ => {
+                    res.add_authority(ResourceRecord::new(
+                        "example",
+                        60,
+                        RData::NS("ns.example".to_string()),
+                    ));
+                    res.set_flags(flags);
+                    return res.to_bytes().unwrap();
+                }
+            }
+            res.set_flags(flags);
+            res.add_answer(ResourceRecord::new(
+                query.question().qname.clone(),
+                60,
+                RData::A([192, 0, 2, 1].into()),
+            ));
+            res.to_bytes().unwrap()
+        });
+        let resolver = Resolver::with_server(server);
+        let iterative = |name: &str| DnsMessage::query(name).recursion_desired(false).build();
+
+        resolver.query(&iterative("recursive.example")).unwrap();
+        assert_eq!(resolver.stats().unrequested_recursion, 1);
+
+        resolver.query(&iterative("auth.example")).unwrap();
+        resolver.query(&iterative("www.referral.example")).unwrap();
+        assert_eq!(resolver.stats().unrequested_recursion, 1);
+    }
+
+    #[test]
+    fn test_hosts_file_comes_first() {
+        let path = std::env::temp_dir().join(format!("resolver-hosts-{}", std::process::id()));
+        std::fs::write(&path, "192.168.1.50 printer.home\n").unwrap();
+        // only the name that isn't in the file goes upstream, its A and AAAA queries
+        let server = mock_udp_server(2, |query| response_with_rdata(query, 1, &[192, 0, 2, 1]));
+        let mut resolver = Resolver::with_server(server);
+        resolver.set_hosts(Arc::new(HostsFile::open(&path).unwrap()));
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(
+            resolver.resolve("printer.home.").unwrap(),
+            [IpAddr::from([192, 168, 1, 50])]
+        );
+        assert_eq!(
+            resolver
+                .lookup_addr(IpAddr::from([192, 168, 1, 50]))
+                .unwrap(),
+            ["printer.home"]
+        );
+        assert_eq!(
+            resolver.resolve("example.com.").unwrap(),
+            [IpAddr::from([192, 0, 2, 1])]
+        );
+        assert_eq!(resolver.stats().queries_sent, 2);
+    }
+
+    #[test]
+    fn test_blocked_names_are_counted_and_never_asked() {
+        let port = free_loopback_port();
+        let mut resolver = Resolver::with_server(SocketAddr::from(([127, 0, 0, 1], port)));
+        let mut blocklist = Blocklist::new();
+        blocklist.add("ads.example.com");
+        resolver.set_blocklist(Arc::new(blocklist));
+        // and let one name on the list through
+        let mut local = LocalRecords::new();
+        local.add_addr("ok.ads.example.com", IpAddr::from([192, 0, 2, 1]));
+        resolver.set_local_records(Arc::new(local));
+
+        let err = resolver.resolve("x.ads.example.com.").unwrap_err();
+        assert_eq!(err.rcode(), Some(RCODE_NXDOMAIN));
+        assert_eq!(
+            resolver.resolve("ok.ads.example.com.").unwrap(),
+            [IpAddr::from([192, 0, 2, 1])]
+        );
+        let stats = resolver.stats();
+        assert_eq!(stats.blocked, 2);
+        assert_eq!(stats.queries_sent, 0);
+    }
+
+    #[test]
+    fn test_local_names_go_to_mdns() {
+        let port = free_loopback_port();
+        let mut resolver = Resolver::with_server(SocketAddr::from(([127, 0, 0, 1], port)));
+        let responder = mock_udp_server(2, |query| {
+            let parsed = DnsMessage::from_bytes(query).unwrap();
+            assert!(!parsed.recursion_desired());
+            match RecordType::from(parsed.question().qtype) {
+                RecordType::A => response_with_rdata(query, 1, &[192, 168, 1, 10]),
+                _ => DnsMessage::response_to(&parsed).to_bytes().unwrap(),
+            }
+        });
+        resolver.set_mdns_groups(vec![responder]);
+
+        assert_eq!(
+            resolver.resolve("printer.local.").unwrap(),
+            [IpAddr::from([192, 168, 1, 10])]
+        );
+        assert_eq!(resolver.stats().queries_sent, 2);
+    }
+
+    #[test]
+    fn test_lookup_tlsa() {
+        let server = mock_udp_server(2, |query| {
+            let query = DnsMessage::from_bytes(query).unwrap();
+            let mut res = DnsMessage::response_to(&query);
+            if query.question().qname == "_443._tcp.example.com" {
+                res.add_answer(ResourceRecord::new(
+                    query.question().qname.clone(),
+                    300,
+                    RData::TLSA(Tlsa {
+                        usage: 3,
+                        selector: 1,
+                        matching_type: 1,
+                        data: vec![0xab; 32],
+                    }),
+                ));
+            } else {
+                let mut flags = res.flags();
+                flags.rcode = RCODE_NXDOMAIN as u8;
+                res.set_flags(flags);
+            }
+            res.to_bytes().unwrap()
+        });
+        let resolver = Resolver::with_server(server);
+
+        let (records, security) = resolver.lookup_tlsa("_443._tcp.example.com.").unwrap();
+        assert_eq!(records.len(), 1);
+        assert_eq!((records[0].usage, records[0].selector), (3, 1));
+        // nothing validated it, so no DANE on the strength of it
+        assert_eq!(security, Security::Indeterminate);
+        let (records, _) = resolver.lookup_tlsa("_25._tcp.example.com.").unwrap();
+        assert!(records.is_empty());
+    }
+
+    #[test]
+    fn test_lookup_caa_climbs_the_tree() {
+        // shop.example.com has none of its own, so example.com's apply; com is never asked
+        let server = mock_udp_server(2, |query| {
+            let query = DnsMessage::from_bytes(query).unwrap();
+            assert_ne!(query.question().qname, "com");
+            let mut res = DnsMessage::response_to(&query);
+            if query.question().qname == "example.com" {
+                for (tag, value) in [("issue", "letsencrypt.org"), ("iodef", "mailto:a@b.c")] {
+                    res.add_answer(ResourceRecord::new(
+                        "example.com",
+                        300,
+                        RData::CAA(Caa {
+                            flags: 0,
+                            tag: tag.to_string(),
+                            value: value.as_bytes().to_vec(),
+                        }),
+                    ));
+                }
+            }
+            res.to_bytes().unwrap()
+        });
+        let resolver = Resolver::with_server(server);
+
+        let records = resolver.lookup_caa("shop.example.com").unwrap();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].tag, "issue");
+        assert_eq!(records[0].value_str(), Some("letsencrypt.org"));
+        assert_eq!(resolver.stats().queries_sent, 2);
+    }
+
+    #[test]
+    fn test_query_soa_across_nameservers() {
+        // a secondary a serial behind its primary, and a name that's not a zone apex (only the
+        // zone's SOA in the authority section)
+        let nameserver = |serial| {
+            mock_udp_server(2, move |query| {
+                let query = DnsMessage::from_bytes(query).unwrap();
+                let mut res = DnsMessage::response_to(&query);
+                let soa = ResourceRecord::new(
+                    "example.com",
+                    3600,
+                    RData::SOA(Soa {
+                        mname: "ns1.example.com".to_string(),
+                        rname: "hostmaster.example.com".to_string(),
+                        serial,
+                        refresh: 7200,
+                        retry: 900,
+                        expire: 1209600,
+                        minimum: 300,
+                    }),
+                );
+                if query.question().qname == "example.com" {
+                    res.add_answer(soa);
+                } else {
+                    res.add_authority(soa);
+                }
+                res.to_bytes().unwrap()
+            })
+        };
+        let primary = Resolver::with_server(nameserver(2024060102));
+        let secondary = Resolver::with_server(nameserver(2024060101));
+
+        let newest = primary.query_soa("example.com").unwrap().unwrap();
+        let behind = secondary.query_soa("example.com.").unwrap().unwrap();
+        assert_eq!(newest.mname, "ns1.example.com");
+        assert_eq!(
+            newest.compare_serial(&behind),
+            Some(std::cmp::Ordering::Greater)
+        );
+        assert!(primary.query_soa("www.example.com").unwrap().is_none());
+    }
+
+    #[test]
+    fn test_servfail_reasons_come_with_the_error() {
+        let server = mock_udp_server(4, |query| {
+            let query = DnsMessage::from_bytes(query).unwrap();
+            let mut res = DnsMessage::response_to(&query);
+            let mut flags = res.flags();
+            flags.rcode = RCODE_SERVFAIL as u8;
+            res.set_flags(flags);
+            let mut edns = Edns::new(1232);
+            edns.options
+                .push(ExtendedError::new(6, "signature expired").to_option());
+            res.edns = Some(edns);
+            res.to_bytes().unwrap()
+        });
+        let mut resolver = Resolver::with_server(server);
+        resolver.set_retry_policy(RetryPolicy {
+            attempts: 1,
+            ..RetryPolicy::default()
+        });
+        let err = resolver.resolve("example.com.").unwrap_err();
+        assert_eq!(err.rcode(), Some(RCODE_SERVFAIL));
+        let DnsError::Extended { errors, .. } = &err else {
+            panic!("expected the extended errors, got {:?}", err);
+        };
+        assert_eq!(errors, &[ExtendedError::new(6, "signature expired")]);
+        assert_eq!(
+            err.to_string(),
+            "server answered with rcode 2 (DNSSEC Bogus (6): signature expired)"
+        );
+    }
+
+    #[test]
+    fn test_response_question_is_checked() {
+        // the first reply echoes the name with different case (fine), the second is for some
+        // other name entirely (dropped), the third is the real answer
+        let server = mock_udp_server_replies(2, |query| {
+            let mut shouty = response_with_rdata(query, 1, &[127, 0, 0, 1]);
+            shouty[13..20].copy_from_slice(b"EXAMPLE");
+            let mut other = shouty.clone();
+            other[13..20].copy_from_slice(b"example");
+            other[21..24].copy_from_slice(b"org");
+            if query[0..2] == 0x1111u16.to_be_bytes() {
+                vec![shouty]
+            } else {
+                vec![other, response_with_rdata(query, 1, &[127, 0, 0, 2])]
+            }
+        });
+        let resolver = Resolver::with_server(server);
+
+        let mut first = DnsMessage::new("example.com".to_string());
+        first.header.identification = 0x1111;
+        let res = resolver.query(&first).unwrap();
+        assert_eq!(res.question().qname.as_str(), "EXAMPLE.com");
+        assert_eq!(resolver.stats().stray_responses, 0);
+
+        let mut second = DnsMessage::new("example.com".to_string());
+        second.header.identification = 0x2222;
+        let res = resolver.query(&second).unwrap();
+        assert_eq!(res.answers[0].rdata, vec![127, 0, 0, 2]);
+        assert_eq!(resolver.stats().stray_responses, 1);
+    }
+
+    // 1000 bytes of TXT, over the classic limit but under what EDNS allows
+    fn big_answer(query: &[u8]) -> Vec<u8> {
+        let mut rdata = Vec::new();
+        for _ in 0..4 {
+            rdata.push(250);
+            rdata.extend([b'x'; 250]);
+        }
+        response_with_rdata(query, 16, &rdata)
+    }
+
+    #[test]
+    fn test_edns_advertises_max_udp_payload() {
+        let server = mock_udp_server(2, |query| {
+            let query = DnsMessage::from_bytes(query).unwrap();
+            let payload = query.edns.as_ref().map_or(0, |edns| edns.udp_payload);
+            let mut res = DnsMessage::response_to(&query);
+            res.add_answer(ResourceRecord::new(
+                "example.com",
+                60,
+                RData::TXT(vec![payload.to_string().into_bytes()]),
+            ));
+            res.to_bytes().unwrap()
+        });
+        let mut resolver = Resolver::with_server(server);
+        let msg = DnsMessage::new("example.com".to_string());
+
+        let res = resolver.query(&msg).unwrap();
+        assert_eq!(res.answers[0].data, RData::TXT(vec![b"1232".to_vec()]));
+
+        // at the classic size there is no OPT record at all
+        resolver.set_options(QueryOptions {
+            max_udp_payload: 512,
+            ..QueryOptions::default()
+        });
+        let res = resolver.query(&msg).unwrap();
+        assert_eq!(res.answers[0].data, RData::TXT(vec![b"0".to_vec()]));
+    }
+
+    #[test]
+    fn test_padding_rounds_queries_up_to_the_block() {
+        let options = QueryOptions {
+            padding_block: 128,
+            ..QueryOptions::default()
+        };
+        for name in [
+            "a.example",
+            "www.example.com",
+            &"x".repeat(63),
+            "a.b.c.d.e.f.g.h",
+        ] {
+            let query = DnsMessage::query(name).build();
+            let padded = with_edns(&query, &options).to_bytes().unwrap();
+            assert_eq!(padded.len() % 128, 0, "{} padded to {}", name, padded.len());
+
+            let parsed = DnsMessage::from_bytes(&padded).unwrap();
+            let edns = parsed.edns.unwrap();
+            assert_eq!(edns.udp_payload, 1232);
+            assert_eq!(edns.options.len(), 1);
+            assert!(edns.option(EDNS_PADDING).unwrap().iter().all(|&b| b == 0));
+        }
+
+        // a message that would already end on the boundary gets an empty padding option
+        assert_eq!(padding(128 - 4, 128).data, Vec::<u8>::new());
+        // and without the option nothing is padded
+        let query = DnsMessage::query("a.example").build();
+        let plain = with_edns(&query, &QueryOptions::default())
+            .to_bytes()
+            .unwrap();
+        assert_eq!(plain.len(), query.to_bytes().unwrap().len() + 11);
+    }
+
+    #[test]
+    fn test_force_tcp_skips_udp() {
+        let udp_queries = Arc::new(AtomicUsize::new(0));
+        let seen = udp_queries.clone();
+        let server = mock_udp_server(1, move |query| {
+            seen.fetch_add(1, Ordering::SeqCst);
+            big_answer(query)
+        });
+        mock_tcp_server_at(server, 1, big_answer);
+
+        let mut resolver = Resolver::with_server(server);
+        resolver.set_options(QueryOptions {
+            force_tcp: true,
+            ..QueryOptions::default()
+        });
+        let res = resolver
+            .query(&DnsMessage::new("example.com".to_string()))
+            .unwrap();
+
+        assert_eq!(res.answers[0].rdlength, 1004);
+        assert_eq!(udp_queries.load(Ordering::SeqCst), 0);
+        assert_eq!(resolver.stats().queries_sent, 1);
+    }
+
+    #[test]
+    fn test_small_payload_escalates_to_tcp() {
+        // the mock ignores EDNS and always sends the full 1000+ bytes. That fits the default
+        // payload size, but at 512 the datagram is cut and we have to go to TCP for it
+        let server = mock_udp_server(2, big_answer);
+        mock_tcp_server_at(server, 1, big_answer);
+        let msg = DnsMessage::new("example.com".to_string());
+
+        let mut resolver = Resolver::with_server(server);
+        let res = resolver.query(&msg).unwrap();
+        assert_eq!(res.answers[0].rdlength, 1004);
+        assert_eq!(resolver.stats().queries_sent, 1);
+
+        resolver.set_options(QueryOptions {
+            max_udp_payload: 512,
+            ..QueryOptions::default()
+        });
+        let res = resolver.query(&msg).unwrap();
+        assert_eq!(res.answers[0].rdlength, 1004);
+        assert_eq!(resolver.stats().queries_sent, 3); // UDP twice, then TCP
+    }
+
+    #[test]
+    fn test_truncated_response_is_retried_over_tcp() {
+        let server = mock_udp_server(1, |query| {
+            let mut res = response_with_rdata(query, 1, &[127, 0, 0, 1]);
+            res[2] |= 0x02; // TC
+            res
+        });
+        mock_tcp_server_at(server, 1, big_answer);
+
+        let resolver = Resolver::with_server(server);
+        let res = resolver
+            .query(&DnsMessage::new("example.com".to_string()))
+            .unwrap();
+        assert!(!res.truncated());
+        assert_eq!(res.answers[0].rdlength, 1004);
+    }
+
+    #[test]
+    fn test_tcp_retry_resends_the_same_query() {
+        // what each transport saw, to compare once the query is done
+        let seen = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let udp_seen = seen.clone();
+        let server = mock_udp_server(1, move |query| {
+            udp_seen.lock().unwrap().push(query.to_vec());
+            let mut res = response_with_rdata(query, 1, &[127, 0, 0, 1]);
+            res[2] |= 0x02; // TC
+            res
+        });
+        let tcp_seen = seen.clone();
+        mock_tcp_server_at(server, 1, move |query| {
+            tcp_seen.lock().unwrap().push(query.to_vec());
+            big_answer(query)
+        });
+
+        let resolver = Resolver::with_server(server);
+        let res = resolver
+            .query(&DnsMessage::new("example.com".to_string()))
+            .unwrap();
+        assert!(!res.truncated());
+
+        let seen = seen.lock().unwrap();
+        assert_eq!(seen.len(), 2);
+        assert_eq!(seen[0], seen[1]);
+        assert_eq!(resolver.stats().queries_sent, 2);
+    }
+
+    // a server from before EDNS: anything with an OPT record in it is a FORMERR
+    fn formerr_to_edns(query: &[u8], answer: fn(&[u8]) -> Vec<u8>) -> Vec<u8> {
+        let parsed = DnsMessage::from_bytes(query).unwrap();
+        if parsed.edns.is_some() {
+            let mut res = DnsMessage::response_to(&parsed);
+            res.header.flags |= RCODE_FORMERR;
+            return res.to_bytes().unwrap();
+        }
+        answer(query)
+    }
+
+    #[test]
+    fn test_formerr_to_edns_is_retried_without_opt() {
+        let opt_counts = Arc::new(Mutex::new(Vec::new()));
+        let seen = opt_counts.clone();
+        let server = mock_udp_server(2, move |query| {
+            seen.lock()
+                .unwrap()
+                .push(u16::from_be_bytes([query[10], query[11]]));
+            formerr_to_edns(query, |query| {
+                response_with_rdata(query, 1, &[127, 0, 0, 1])
+            })
+        });
+
+        let resolver = Resolver::with_server(server);
+        let res = resolver
+            .query(&DnsMessage::new("example.com".to_string()))
+            .unwrap();
+        assert_eq!(res.rcode(), 0);
+        assert_eq!(res.answers[0].data, RData::A([127, 0, 0, 1].into()));
+
+        // the first query carried our OPT record, the retry nothing at all
+        assert_eq!(*opt_counts.lock().unwrap(), vec![1, 0]);
+        let stats = resolver.stats();
+        assert_eq!(stats.queries_sent, 2);
+        assert_eq!(stats.retries, 1);
+    }
+
+    #[test]
+    fn test_formerr_retry_escalates_to_tcp() {
+        // without EDNS the answer doesn't fit in 512 bytes, so the retry comes back truncated
+        let server = mock_udp_server(2, |query| {
+            formerr_to_edns(query, |query| {
+                let mut res = response_with_rdata(query, 1, &[127, 0, 0, 1]);
+                res[2] |= 0x02; // TC
+                res
+            })
+        });
+        mock_tcp_server_at(server, 1, big_answer);
+
+        let resolver = Resolver::with_server(server);
+        let res = resolver
+            .query(&DnsMessage::new("example.com".to_string()))
+            .unwrap();
+        assert_eq!(res.answers[0].rdlength, 1004);
+        assert_eq!(resolver.stats().queries_sent, 3); // EDNS, classic UDP, TCP
+    }
+
+    #[test]
+    fn test_formerr_without_our_opt_is_final() {
+        let server = mock_udp_server(1, |query| formerr_to_edns(query, big_answer));
+
+        // classic DNS, so the FORMERR isn't about an OPT record we added
+        let mut resolver = Resolver::with_server(server);
+        resolver.set_options(QueryOptions {
+            max_udp_payload: 512,
+            ..QueryOptions::default()
+        });
+        let mut msg = DnsMessage::new("example.com".to_string());
+        msg.edns = Some(Edns::new(4096));
+
+        let res = resolver.query(&msg).unwrap();
+        assert_eq!(res.rcode(), RCODE_FORMERR);
+        assert_eq!(resolver.stats().retries, 0);
+    }
+
+    #[test]
+    fn test_capture_writes_query_and_response() {
+        let server = mock_udp_server(1, |query| response_with_rdata(query, 1, &[127, 0, 0, 1]));
+        let path = std::env::temp_dir().join(format!("dns-capture-{}.pcap", std::process::id()));
+
+        let mut resolver = Resolver::with_server(server);
+        resolver.capture_to(&path).unwrap();
+        let msg = DnsMessage::new("example.com".to_string());
+        resolver.query(&msg).unwrap();
+        drop(resolver); // flushes the file
+
+        let pcap = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(&pcap[0..4], &0xa1b2c3d4u32.to_le_bytes());
+
+        // walk the packet records after the 24 byte global header
+        let mut packets = Vec::new();
+        let mut pos = 24;
+        while pos < pcap.len() {
+            let len = u32::from_le_bytes(pcap[pos + 8..pos + 12].try_into().unwrap()) as usize;
+            packets.push(&pcap[pos + 16..pos + 16 + len]);
+            pos += 16 + len;
+        }
+        assert_eq!(packets.len(), 2);
+
+        // IPv4 + UDP headers are 28 bytes, the DNS payload follows. The query goes to the
+        // server's port, the response comes from it
+        let (query, response) = (packets[0], packets[1]);
+        assert_eq!(&query[28..30], &msg.header.identification.to_be_bytes());
+        assert_eq!(&query[22..24], &server.port().to_be_bytes());
+        assert_eq!(&response[20..22], &server.port().to_be_bytes());
+        assert_eq!(response[28 + 2] & 0x80, 0x80);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_capture_write_errors_end_the_capture() {
+        let server = mock_udp_server(2, |query| response_with_rdata(query, 1, &[127, 0, 0, 1]));
+        let mut resolver = Resolver::with_server(server);
+        // every write to /dev/full fails with ENOSPC
+        resolver.capture_to("/dev/full").unwrap();
+        for _ in 0..2 {
+            resolver
+                .query(&DnsMessage::query("example.com").build())
+                .unwrap();
+        }
+        assert_eq!(resolver.stats().capture_errors, 1);
+    }
+}
@@ -0,0 +1,140 @@
+// how hard a Resolver tries before giving up on a query: how many times, how long each try
+// waits, how long to pause in between, whether a failed try moves on to another upstream, and
+// how long all of that together can take
+use std::time::{Duration, Instant};
+
+use crate::random::random_u64;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RetryPolicy {
+    // how many times each upstream is tried, at least once
+    pub attempts: usize,
+    // how long one try waits for an answer. None leaves every upstream with its own timeout
+    // (see Resolver::add_server)
+    pub timeout: Option<Duration>,
+    // the pause before the second try, doubled for every try after that, up to max_backoff
+    pub backoff: Duration,
+    pub max_backoff: Duration,
+    // pause for a random amount between half and all of the backoff instead, so clients that
+    // lost their server at the same moment don't all come back at the same moment
+    pub jitter: bool,
+    // on: a failed try moves on to the next upstream and we go round the list `attempts` times.
+    // Off: each upstream gets all its attempts before the next one gets a go
+    pub switch_server: bool,
+    // the whole query, every try and pause included. A try that would run past it waits only
+    // for what's left, and none are started after it. None for no limit beyond the tries
+    // themselves
+    pub deadline: Option<Duration>,
+}
+
+// what a Resolver has always done: every upstream once, in turn, with no pause in between
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            attempts: 1,
+            timeout: None,
+            backoff: Duration::ZERO,
+            max_backoff: Duration::ZERO,
+            jitter: false,
+            switch_server: true,
+            deadline: None,
+        }
+    }
+}
+
+impl RetryPolicy {
+    // the order the tries go in, as indexes into the `servers` upstreams
+    pub(crate) fn tries(&self, servers: usize) -> Vec<usize> {
+        let attempts = self.attempts.max(1);
+        if self.switch_server {
+            (0..attempts).flat_map(|_| 0..servers).collect()
+        } else {
+            (0..servers)
+                .flat_map(|server| std::iter::repeat_n(server, attempts))
+                .collect()
+        }
+    }
+
+    // the pause before try number `try_no` (the first is 0, and gets none)
+    pub(crate) fn delay(&self, try_no: usize) -> Duration {
+        if try_no == 0 {
+            return Duration::ZERO;
+        }
+        let doublings = (try_no - 1).min(31) as u32;
+        let delay = self
+            .backoff
+            .saturating_mul(1 << doublings)
+            .min(self.max_backoff.max(self.backoff));
+        if !self.jitter || delay.is_zero() {
+            return delay;
+        }
+        let half = delay / 2;
+        let nanos = half.as_nanos().min(u64::MAX as u128) as u64;
+        half + Duration::from_nanos(random_u64() % (nanos + 1))
+    }
+}
+
+// when a query has to be done by, from RetryPolicy::deadline
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Deadline(Option<Instant>);
+
+impl Deadline {
+    pub(crate) fn after(total: Option<Duration>) -> Self {
+        Deadline(total.and_then(|total| Instant::now().checked_add(total)))
+    }
+
+    // `timeout`, or what's left before the deadline if that's less. None once it has passed
+    pub(crate) fn cap(&self, timeout: Duration) -> Option<Duration> {
+        let Some(at) = self.0 else {
+            return Some(timeout);
+        };
+        let left = at.saturating_duration_since(Instant::now());
+        (!left.is_zero()).then(|| timeout.min(left))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_try_order() {
+        let mut policy = RetryPolicy {
+            attempts: 2,
+            ..RetryPolicy::default()
+        };
+        assert_eq!(policy.tries(3), [0, 1, 2, 0, 1, 2]);
+        policy.switch_server = false;
+        assert_eq!(policy.tries(3), [0, 0, 1, 1, 2, 2]);
+        assert!(policy.tries(0).is_empty());
+    }
+
+    #[test]
+    fn test_backoff_doubles_up_to_the_max() {
+        let ms = Duration::from_millis;
+        let mut policy = RetryPolicy {
+            attempts: 5,
+            backoff: ms(100),
+            max_backoff: ms(350),
+            ..RetryPolicy::default()
+        };
+        let delays: Vec<Duration> = (0..5).map(|try_no| policy.delay(try_no)).collect();
+        assert_eq!(delays, [ms(0), ms(100), ms(200), ms(350), ms(350)]);
+
+        policy.jitter = true;
+        for (try_no, max) in delays.into_iter().enumerate() {
+            let delay = policy.delay(try_no);
+            assert!(delay >= max / 2 && delay <= max);
+        }
+    }
+
+    #[test]
+    fn test_deadline() {
+        let ms = Duration::from_millis;
+        assert_eq!(Deadline::after(None).cap(ms(500)), Some(ms(500)));
+        let deadline = Deadline::after(Some(ms(100)));
+        assert_eq!(deadline.cap(ms(20)), Some(ms(20)));
+        assert!(deadline.cap(ms(500)).unwrap() <= ms(100));
+        assert_eq!(Deadline::after(Some(Duration::ZERO)).cap(ms(500)), None);
+    }
+}
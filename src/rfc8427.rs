@@ -0,0 +1,458 @@
+// DNS messages in the JSON of RFC 8427, the one shared with DoH JSON tooling and test corpora,
+// as opposed to the serde derives, which mirror our own structs. The header is a member per
+// field (ID, QR, Opcode, RCODE...), the question QNAME/QTYPE/QCLASS, and each section an array
+// of records: NAME, TYPE, CLASS, TTL and the rdata, both as RDATAHEX and in presentation format
+// under rdata plus the type's name ("rdataA": "192.0.2.1").
+//
+// Reading takes what the RFC allows for each member: flags as booleans or 0/1 (section 2.1 says
+// boolean, the examples in section 5 use numbers), the type and class as numbers or their
+// names, and the rdata as RDATAHEX or, with std, in presentation format. A message that carries
+// messageOctetsHEX is just those bytes, parsed the usual way
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+
+use serde_json::{json, Map, Value};
+
+use crate::encoding::{from_hex, to_hex};
+use crate::rdata::TYPE_OPT;
+use crate::{
+    DnsClass, DnsError, DnsMessage, DnsQuestion, Edns, Name, RData, RecordType, ResourceRecord,
+};
+
+impl DnsMessage {
+    pub fn to_rfc8427(&self) -> Result<Value, DnsError> {
+        let flags = self.flags();
+        let mut json = json!({
+            "ID": self.header.identification,
+            "QR": flags.qr,
+            "Opcode": flags.opcode,
+            "AA": flags.aa,
+            "TC": flags.tc,
+            "RD": flags.rd,
+            "RA": flags.ra,
+            "AD": flags.ad,
+            "CD": flags.cd,
+            "RCODE": flags.rcode,
+            "QDCOUNT": self.questions.len(),
+            "ANCOUNT": self.answers.len(),
+            "NSCOUNT": self.authority.len(),
+            "ARCOUNT": self.additional.len() + self.edns.is_some() as usize,
+        });
+        let members = json.as_object_mut().unwrap();
+        // one question, the usual case, has members of its own. Anything else goes in an array
+        // like the other sections
+        match self.questions.as_slice() {
+            [question] => {
+                members.insert("QNAME".into(), json!(question.qname.to_string()));
+                members.insert("QTYPE".into(), json!(question.qtype));
+                members.insert(
+                    "QTYPEname".into(),
+                    json!(RecordType::from(question.qtype).to_string()),
+                );
+                members.insert("QCLASS".into(), json!(question.qclass));
+            }
+            questions => {
+                let questions: Vec<Value> = questions
+                    .iter()
+                    .map(|question| {
+                        json!({
+                            "NAME": question.qname.to_string(),
+                            "TYPE": question.qtype,
+                            "CLASS": question.qclass,
+                        })
+                    })
+                    .collect();
+                members.insert("questionRRs".into(), Value::Array(questions));
+            }
+        }
+
+        let mut additional = self.additional.clone();
+        if let Some(edns) = &self.edns {
+            additional.push(opt_record(edns)?);
+        }
+        for (key, records) in [
+            ("answerRRs", &self.answers),
+            ("authorityRRs", &self.authority),
+            ("additionalRRs", &additional),
+        ] {
+            if !records.is_empty() {
+                let records = records
+                    .iter()
+                    .map(record_to_json)
+                    .collect::<Result<_, _>>()?;
+                members.insert(key.into(), Value::Array(records));
+            }
+        }
+        Ok(json)
+    }
+
+    pub fn from_rfc8427(json: &Value) -> Result<Self, DnsError> {
+        let members = json
+            .as_object()
+            .ok_or(DnsError::Malformed("RFC 8427 message isn't a JSON object"))?;
+        if let Some(hex) = members.get("messageOctetsHEX") {
+            let octets = hex.as_str().and_then(from_hex);
+            return DnsMessage::from_bytes(&octets.ok_or(bad())?);
+        }
+
+        let mut msg = DnsMessage::query(Name::root()).build();
+        msg.header.identification = number(members, "ID")?.unwrap_or(0);
+        let mut flags = msg.flags();
+        flags.qr = flag(members, "QR")?;
+        flags.opcode = number(members, "Opcode")?.unwrap_or(0);
+        flags.aa = flag(members, "AA")?;
+        flags.tc = flag(members, "TC")?;
+        flags.rd = flag(members, "RD")?;
+        flags.ra = flag(members, "RA")?;
+        flags.ad = flag(members, "AD")?;
+        flags.cd = flag(members, "CD")?;
+        flags.rcode = number(members, "RCODE")?.unwrap_or(0);
+        msg.set_flags(flags);
+
+        msg.questions = match (members.get("QNAME"), members.get("questionRRs")) {
+            (Some(qname), _) => Vec::from([DnsQuestion {
+                qname: name(qname)?,
+                qtype: rr_type(members, "QTYPE")?,
+                qclass: class(members, "QCLASS")?,
+            }]),
+            (None, Some(questions)) => array(questions)?
+                .iter()
+                .map(|question| {
+                    let members = object(question)?;
+                    Ok(DnsQuestion {
+                        qname: name(members.get("NAME").unwrap_or(&Value::Null))?,
+                        qtype: rr_type(members, "TYPE")?,
+                        qclass: class(members, "CLASS")?,
+                    })
+                })
+                .collect::<Result<_, DnsError>>()?,
+            (None, None) => Vec::new(),
+        };
+        msg.answers = records(members, "answerRRs")?;
+        msg.authority = records(members, "authorityRRs")?;
+        msg.additional = Vec::new();
+        for rr in records(members, "additionalRRs")? {
+            if rr.rr_type == TYPE_OPT {
+                msg.edns = Some(Edns::from_record(&rr)?);
+            } else {
+                msg.additional.push(rr);
+            }
+        }
+        // what QDCOUNT and friends said doesn't matter, these are the records we have
+        msg.header.no_of_questions = msg.questions.len() as u16;
+        msg.header.no_of_answers_rr = msg.answers.len() as u16;
+        msg.header.no_of_authority_rr = msg.authority.len() as u16;
+        msg.header.no_of_additional_rr =
+            (msg.additional.len() + msg.edns.is_some() as usize) as u16;
+        Ok(msg)
+    }
+}
+
+fn record_to_json(rr: &ResourceRecord) -> Result<Value, DnsError> {
+    let mut rdata = Vec::new();
+    match &rr.data {
+        // the OPT record's is kept raw, and it's not for decoding as anything
+        RData::Unknown(_, raw) => rdata.extend(raw),
+        data => data.encode(&mut rdata)?,
+    }
+    let rr_type = RecordType::from(rr.rr_type);
+    let mut json = json!({
+        "NAME": rr.name.to_string(),
+        "TYPE": rr.rr_type,
+        "TYPEname": rr_type.to_string(),
+        "CLASS": rr.class,
+        "TTL": rr.ttl,
+        "RDLENGTH": rdata.len(),
+        "RDATAHEX": to_hex(&rdata),
+    });
+    if !matches!(rr.data, RData::Unknown(..)) {
+        let key = format!("rdata{}", rr_type);
+        json.as_object_mut()
+            .unwrap()
+            .insert(key, json!(rr.data.to_string()));
+    }
+    Ok(json)
+}
+
+fn record_from_json(json: &Value) -> Result<ResourceRecord, DnsError> {
+    let members = object(json)?;
+    let rr_type = rr_type(members, "TYPE")?;
+    let rr_class = class(members, "CLASS")?;
+    let rdata = match members.get("RDATAHEX") {
+        Some(hex) => hex.as_str().and_then(from_hex).ok_or(bad())?,
+        None => presentation_rdata(members, rr_type, rr_class)?,
+    };
+    let rdlength = u16::try_from(rdata.len()).map_err(|_| bad())?;
+    let data = match rr_type {
+        TYPE_OPT => RData::Unknown(TYPE_OPT, rdata.clone()),
+        _ => RData::decode(rr_type, &rdata, 0, rdlength)?,
+    };
+    Ok(ResourceRecord {
+        name: name(members.get("NAME").unwrap_or(&Value::Null))?,
+        rr_type,
+        class: rr_class,
+        ttl: number(members, "TTL")?.unwrap_or(0),
+        rdlength,
+        rdata,
+        data,
+    })
+}
+
+// the rdata from an rdataA/rdataMX/... member, read the way a zone file line would be
+#[cfg(feature = "std")]
+fn presentation_rdata(
+    members: &Map<String, Value>,
+    rr_type: u16,
+    rr_class: u16,
+) -> Result<Vec<u8>, DnsError> {
+    let key = format!("rdata{}", RecordType::from(rr_type));
+    let text = members
+        .get(&key)
+        .and_then(Value::as_str)
+        .ok_or(DnsError::Malformed(
+            "RFC 8427 record has no RDATAHEX and no rdata",
+        ))?;
+    let line = format!(
+        ". 0 {} {} {}",
+        DnsClass::from(rr_class),
+        RecordType::from(rr_type),
+        text
+    );
+    let records = crate::parse_zone(&line, Name::root()).map_err(|_| bad())?;
+    let mut rdata = Vec::new();
+    match records.as_slice() {
+        [rr] => rr.data.encode(&mut rdata)?,
+        _ => return Err(bad()),
+    }
+    Ok(rdata)
+}
+
+// zone file syntax is std only, so without it the rdata has to come as RDATAHEX
+#[cfg(not(feature = "std"))]
+fn presentation_rdata(_: &Map<String, Value>, _: u16, _: u16) -> Result<Vec<u8>, DnsError> {
+    Err(DnsError::Malformed("RFC 8427 record has no RDATAHEX"))
+}
+
+// the OPT record the way it goes on the wire: Edns writes it straight into a message, so we
+// take it apart again
+fn opt_record(edns: &Edns) -> Result<ResourceRecord, DnsError> {
+    let mut bytes = Vec::new();
+    edns.write(&mut bytes)?;
+    let field = |at: usize| u16::from_be_bytes([bytes[at], bytes[at + 1]]);
+    let rdata = bytes[11..].to_vec();
+    Ok(ResourceRecord {
+        name: Name::root(),
+        rr_type: TYPE_OPT,
+        class: field(3),
+        ttl: (field(5) as u32) << 16 | field(7) as u32,
+        rdlength: field(9),
+        data: RData::Unknown(TYPE_OPT, rdata.clone()),
+        rdata,
+    })
+}
+
+fn records(
+    members: &Map<String, Value>,
+    section: &'static str,
+) -> Result<Vec<ResourceRecord>, DnsError> {
+    match members.get(section) {
+        Some(records) => array(records)?.iter().map(record_from_json).collect(),
+        None => Ok(Vec::new()),
+    }
+}
+
+fn flag(members: &Map<String, Value>, key: &'static str) -> Result<bool, DnsError> {
+    match members.get(key) {
+        None => Ok(false),
+        Some(Value::Bool(on)) => Ok(*on),
+        Some(value) => match value.as_u64() {
+            Some(0) => Ok(false),
+            Some(1) => Ok(true),
+            _ => Err(bad()),
+        },
+    }
+}
+
+fn number<T: TryFrom<u64>>(
+    members: &Map<String, Value>,
+    key: &'static str,
+) -> Result<Option<T>, DnsError> {
+    match members.get(key) {
+        None => Ok(None),
+        Some(value) => value
+            .as_u64()
+            .and_then(|n| T::try_from(n).ok())
+            .map(Some)
+            .ok_or(bad()),
+    }
+}
+
+// TYPE, or failing that TYPEname (QTYPE and QTYPEname for the question)
+fn rr_type(members: &Map<String, Value>, key: &'static str) -> Result<u16, DnsError> {
+    if let Some(rr_type) = number(members, key)? {
+        return Ok(rr_type);
+    }
+    let name = members
+        .get(&format!("{}name", key))
+        .and_then(Value::as_str)
+        .ok_or(bad())?;
+    let rr_type: RecordType = name.parse().map_err(|_| bad())?;
+    Ok(rr_type.into())
+}
+
+// CLASS or CLASSname, IN when there's neither
+fn class(members: &Map<String, Value>, key: &'static str) -> Result<u16, DnsError> {
+    if let Some(class) = number(members, key)? {
+        return Ok(class);
+    }
+    match members.get(&format!("{}name", key)).and_then(Value::as_str) {
+        Some(name) => {
+            let class: DnsClass = name.parse().map_err(|_| bad())?;
+            Ok(class.into())
+        }
+        None => Ok(DnsClass::IN.into()),
+    }
+}
+
+// a name that's there but can't go on the wire is InvalidName, like anywhere else
+fn name(value: &Value) -> Result<Name, DnsError> {
+    value.as_str().ok_or(bad())?.parse()
+}
+
+fn object(value: &Value) -> Result<&Map<String, Value>, DnsError> {
+    value.as_object().ok_or(bad())
+}
+
+fn array(value: &Value) -> Result<&Vec<Value>, DnsError> {
+    value.as_array().ok_or(bad())
+}
+
+fn bad() -> DnsError {
+    DnsError::Malformed("bad member in RFC 8427 JSON")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{EdnsOption, Soa};
+    use alloc::vec;
+    use core::net::Ipv4Addr;
+
+    #[test]
+    fn test_round_trip() {
+        let query = DnsMessage::query("example.com")
+            .id(19678)
+            .dnssec_ok(true)
+            .build();
+        let mut res = DnsMessage::response_to(&query);
+        res.edns = query.edns.clone();
+        res.edns.as_mut().unwrap().options = vec![EdnsOption {
+            code: 10,
+            data: vec![1, 2, 3, 4, 5, 6, 7, 8],
+        }];
+        res.add_answer(ResourceRecord::new(
+            "example.com",
+            3600,
+            RData::A(Ipv4Addr::new(192, 0, 2, 1)),
+        ));
+        res.add_authority(ResourceRecord::new(
+            "example.com",
+            3600,
+            RData::SOA(Soa {
+                mname: "ns.example.com".into(),
+                rname: "hostmaster.example.com".into(),
+                serial: 1,
+                refresh: 7200,
+                retry: 3600,
+                expire: 1209600,
+                minimum: 300,
+            }),
+        ));
+        res.add_additional(ResourceRecord::new(
+            "example.com",
+            60,
+            RData::Unknown(65280, vec![0xab, 0xcd]),
+        ));
+        let res = DnsMessage::from_bytes(&res.to_bytes().unwrap()).unwrap();
+
+        let json = res.to_rfc8427().unwrap();
+        assert_eq!(json["ID"], 19678);
+        assert_eq!(
+            (&json["QR"], &json["RD"], &json["AA"]),
+            (&json!(true), &json!(true), &json!(false))
+        );
+        assert_eq!(json["QNAME"], "example.com");
+        assert_eq!(
+            (&json["QTYPE"], &json["QTYPEname"]),
+            (&json!(1), &json!("A"))
+        );
+        assert_eq!(json["ARCOUNT"], 2);
+        let a = &json["answerRRs"][0];
+        assert_eq!(a["RDATAHEX"], "C0000201");
+        assert_eq!(a["rdataA"], "192.0.2.1");
+        assert_eq!(json["additionalRRs"][0]["TYPEname"], "TYPE65280");
+        assert!(json["additionalRRs"][0].get("rdataTYPE65280").is_none());
+        let opt = &json["additionalRRs"][1];
+        assert_eq!(
+            (&opt["NAME"], &opt["TYPE"], &opt["CLASS"]),
+            (&json!("."), &json!(41), &json!(1232))
+        );
+        assert_eq!(opt["TTL"], 0x8000);
+
+        // the same message, give or take the compression in the parsed SOA's raw rdata
+        let back = DnsMessage::from_rfc8427(&json).unwrap();
+        assert_eq!(back.to_bytes().unwrap(), res.to_bytes().unwrap());
+        assert_eq!(back.header, res.header);
+    }
+
+    #[test]
+    fn test_from_rfc_example() {
+        // the query of RFC 8427 section 5.1, with the numbers for flags the way the RFC writes
+        // them, and a record with only its names and presentation rdata
+        let json: Value = serde_json::from_str(
+            r#"{ "ID": 19678, "QR": 0, "Opcode": 0, "AA": 0, "TC": 0, "RD": 0, "RA": 0,
+                 "AD": 0, "CD": 0, "RCODE": 0, "QDCOUNT": 1, "ANCOUNT": 0, "NSCOUNT": 0,
+                 "ARCOUNT": 0, "QNAME": "example.com", "QTYPE": 1, "QCLASS": 1 }"#,
+        )
+        .unwrap();
+        let msg = DnsMessage::from_rfc8427(&json).unwrap();
+        assert_eq!(msg.header.identification, 19678);
+        assert!(!msg.is_response() && !msg.recursion_desired());
+        assert_eq!(msg.question().qname, "example.com");
+        assert_eq!(msg.question().record_type(), RecordType::A);
+
+        let octets = to_hex(&msg.to_bytes().unwrap());
+        let same = DnsMessage::from_rfc8427(&json!({ "messageOctetsHEX": octets })).unwrap();
+        assert_eq!(same, msg);
+
+        let mx = json!({
+            "QR": true, "QNAME": "example.com", "QTYPEname": "MX",
+            "answerRRs": [{ "NAME": "example.com.", "TYPEname": "MX", "TTL": 300,
+                            "rdataMX": "10 mail.example.com." }],
+        });
+        #[cfg(feature = "std")]
+        {
+            let msg = DnsMessage::from_rfc8427(&mx).unwrap();
+            assert_eq!(msg.question().record_type(), RecordType::MX);
+            assert_eq!(
+                msg.answers[0].data,
+                RData::MX {
+                    preference: 10,
+                    exchange: "mail.example.com".into()
+                }
+            );
+        }
+        #[cfg(not(feature = "std"))]
+        assert!(DnsMessage::from_rfc8427(&mx).is_err());
+
+        for bad in [
+            json!([]),
+            json!({ "QR": 2 }),
+            json!({ "QNAME": "a..b" }),
+            json!({ "answerRRs": [{ "NAME": "a", "TYPE": 1, "RDATAHEX": "C00002" }] }),
+        ] {
+            assert!(DnsMessage::from_rfc8427(&bad).is_err(), "{}", bad);
+        }
+    }
+}
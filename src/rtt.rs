@@ -0,0 +1,159 @@
+// how each upstream has been doing: a smoothed round-trip time and a count of the tries in a row
+// that got nothing useful back. Strategy::Fastest orders the upstreams by it, the way BIND and
+// unbound pick among the servers of a zone, so a resolver given a near server and a far one ends
+// up asking the near one.
+//
+// A server that keeps failing is demoted behind all the others. It would never get a query to
+// show it's back if that were all, so once every `probe_every` a demoted server goes first again
+// for one query: a probe. One answer and it's back in the running
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+// failed tries in a row before a server is demoted
+pub const DEMOTE_AFTER: u32 = 3;
+
+// how an upstream looks from here, for Resolver::server_health
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ServerHealth {
+    pub addr: SocketAddr,
+    // None until it has been asked something
+    pub srtt: Option<Duration>,
+    // tries in a row with no answer, or SERVFAIL/REFUSED for one. An answer sets it back to 0
+    pub failures: u32,
+    // behind every server that isn't, DEMOTE_AFTER failures or more
+    pub demoted: bool,
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct RttTable {
+    servers: Mutex<HashMap<SocketAddr, Entry>>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Entry {
+    srtt: Duration,
+    failures: u32,
+    // when it was last put first as a probe, so concurrent queries don't all probe it at once
+    probed: Option<Instant>,
+}
+
+impl RttTable {
+    // how long a try at `server` took, and whether it got an answer. A failure counts for as
+    // long as we waited, usually the whole timeout, which is what pushes a server that's gone
+    // quiet down the list before it has failed often enough to be demoted
+    pub fn record(&self, server: SocketAddr, took: Duration, answered: bool) {
+        let mut servers = self.servers.lock().unwrap();
+        let entry = servers.entry(server).or_insert(Entry {
+            srtt: took,
+            failures: 0,
+            probed: None,
+        });
+        // the weights of TCP's SRTT (RFC 6298): 7/8 of what we had, 1/8 of the new sample
+        entry.srtt = (entry.srtt * 7 + took) / 8;
+        if answered {
+            entry.failures = 0;
+            entry.probed = None;
+        } else {
+            entry.failures = entry.failures.saturating_add(1);
+        }
+    }
+
+    // the indexes of `servers` best first: the ones never asked (so each gets a chance to show
+    // what it can do), then by smoothed RTT, demoted servers last. A demoted server that hasn't
+    // been probed for `probe_every` goes first instead
+    pub fn order(&self, servers: &[SocketAddr], probe_every: Duration) -> Vec<usize> {
+        let mut table = self.servers.lock().unwrap();
+        let mut order: Vec<usize> = (0..servers.len()).collect();
+        let key = |i: &usize| match table.get(&servers[*i]) {
+            None => (false, Duration::ZERO),
+            Some(entry) => (entry.failures >= DEMOTE_AFTER, entry.srtt),
+        };
+        order.sort_by_key(key);
+
+        let now = Instant::now();
+        let probe = order.iter().position(|i| {
+            table.get(&servers[*i]).is_some_and(|entry| {
+                entry.failures >= DEMOTE_AFTER
+                    && entry
+                        .probed
+                        .is_none_or(|at| now.duration_since(at) >= probe_every)
+            })
+        });
+        // nothing to probe when everything is demoted, they all get their tries anyway
+        if let Some(at) = probe.filter(|at| *at > 0) {
+            let index = order.remove(at);
+            table.get_mut(&servers[index]).unwrap().probed = Some(now);
+            order.insert(0, index);
+        }
+        order
+    }
+
+    pub fn health(&self, server: SocketAddr) -> ServerHealth {
+        let entry = self.servers.lock().unwrap().get(&server).copied();
+        ServerHealth {
+            addr: server,
+            srtt: entry.map(|entry| entry.srtt),
+            failures: entry.map_or(0, |entry| entry.failures),
+            demoted: entry.is_some_and(|entry| entry.failures >= DEMOTE_AFTER),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn servers() -> [SocketAddr; 3] {
+        ["192.0.2.1:53", "192.0.2.2:53", "192.0.2.3:53"].map(|addr| addr.parse().unwrap())
+    }
+
+    #[test]
+    fn test_fastest_first() {
+        let table = RttTable::default();
+        let servers = servers();
+        let ms = Duration::from_millis;
+        table.record(servers[0], ms(80), true);
+        table.record(servers[1], ms(10), true);
+        // the third hasn't been asked yet, so it goes first
+        assert_eq!(table.order(&servers, ms(1000)), [2, 1, 0]);
+        table.record(servers[2], ms(40), true);
+        assert_eq!(table.order(&servers, ms(1000)), [1, 2, 0]);
+
+        // one slow answer doesn't undo a good record, a run of them does
+        table.record(servers[1], ms(100), true);
+        assert_eq!(table.order(&servers, ms(1000)), [1, 2, 0]);
+        for _ in 0..16 {
+            table.record(servers[1], ms(100), true);
+        }
+        assert_eq!(table.order(&servers, ms(1000)), [2, 0, 1]);
+        assert!(table.health(servers[1]).srtt.unwrap() > ms(60));
+    }
+
+    #[test]
+    fn test_demote_and_probe() {
+        let table = RttTable::default();
+        let servers = servers();
+        let ms = Duration::from_millis;
+        for server in servers {
+            table.record(server, ms(20), true);
+        }
+        for _ in 0..DEMOTE_AFTER {
+            table.record(servers[0], ms(1), false);
+        }
+        let health = table.health(servers[0]);
+        assert_eq!((health.failures, health.demoted), (DEMOTE_AFTER, true));
+
+        // first in line for a probe, then at the back until the next one is due
+        assert_eq!(table.order(&servers, ms(50))[0], 0);
+        assert_eq!(table.order(&servers, ms(50))[2], 0);
+        std::thread::sleep(ms(60));
+        assert_eq!(table.order(&servers, ms(50))[0], 0);
+
+        // the probe got an answer
+        table.record(servers[0], ms(20), true);
+        assert!(!table.health(servers[0]).demoted);
+        assert_eq!(table.order(&servers, ms(50))[0], 0);
+    }
+}
@@ -0,0 +1,160 @@
+// secondary zones: copies of zones edited somewhere else, kept up to date from their primary
+// the way RFC 1034 section 4.3.5 has it. Every refresh seconds (from the zone's SOA) we ask a
+// primary for its SOA, and when its serial is ahead of ours we transfer what changed (IXFR, or
+// AXFR when that's all the primary does, see axfr.rs). If that fails we try again every retry
+// seconds, and once expire seconds have gone by without getting through the copy is too old to
+// answer from and we stop serving it.
+//
+// A primary doesn't have to wait for us to come round: a NOTIFY (RFC 1996) tells us the zone
+// changed, and we check right away. We only take them from the zone's primaries, and even then
+// all a NOTIFY can do is make us ask a bit sooner. DnsServer::add_secondary sets all this going
+use std::fs::File;
+use std::io;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::rdata::TYPE_SOA;
+use crate::transport::{DnsTransport, UdpTransport};
+use crate::{axfr, update_zone, DnsError, DnsMessage, Name, RData, RecordType};
+use crate::{TransferOptions, Zone};
+
+// how long we wait to try again before we have any copy of the zone, and so no SOA to say
+const DEFAULT_RETRY: Duration = Duration::from_secs(60);
+// the least time between two refreshes, whatever the SOA says
+const MIN_REFRESH: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone)]
+pub struct SecondaryZone {
+    pub origin: Name,
+    // the servers to transfer the zone from, tried in order. NOTIFYs from anywhere else are
+    // refused
+    pub primaries: Vec<SocketAddr>,
+    // the timeout and the TSIG key for talking to them
+    pub transfer: TransferOptions,
+    // where the copy is kept, so a restarted server has something to answer with before the
+    // first refresh. Nowhere if None. Its modification time is when the copy was last known to
+    // be current: every refresh that gets through touches it, and the expire counts from there
+    pub file: Option<PathBuf>,
+}
+
+impl SecondaryZone {
+    pub fn new(origin: impl Into<Name>, primaries: impl IntoIterator<Item = SocketAddr>) -> Self {
+        SecondaryZone {
+            origin: origin.into(),
+            primaries: primaries.into_iter().collect(),
+            transfer: TransferOptions::default(),
+            file: None,
+        }
+    }
+}
+
+// one secondary zone and where it is in its refresh cycle
+pub(crate) struct Secondary {
+    pub(crate) config: SecondaryZone,
+    pub(crate) refresh_at: Instant,
+    // when the copy we have is to be dropped, if we have one
+    pub(crate) expire_at: Option<Instant>,
+}
+
+impl Secondary {
+    // after a refresh: the next one if it went `ok`, or a retry if it didn't. `zone` is the copy
+    // we are left with either way. Gives back whether that copy has now expired
+    pub(crate) fn schedule(&mut self, ok: bool, zone: Option<&Zone>, now: Instant) -> bool {
+        let secs = |secs: u32| Duration::from_secs(secs as u64).max(MIN_REFRESH);
+        match (ok, zone.map(Zone::soa)) {
+            (true, Some(soa)) => {
+                self.refresh_at = now + secs(soa.refresh);
+                self.expire_at = Some(now + secs(soa.expire));
+                false
+            }
+            (_, soa) => {
+                self.refresh_at = now + soa.map_or(DEFAULT_RETRY, |soa| secs(soa.retry));
+                self.expire_at.is_some_and(|at| at <= now)
+            }
+        }
+    }
+}
+
+// the copy saved in the zone's file and how long it has left to be served, going by when it
+// was last refreshed. None if there's no copy that loads, or it has expired already
+pub(crate) fn saved_copy(config: &SecondaryZone) -> Option<(Zone, Duration)> {
+    let file = config.file.as_ref()?;
+    let zone = Zone::load(file, config.origin.clone()).ok()?;
+    let refreshed = file.metadata().and_then(|meta| meta.modified()).ok()?;
+    // a file from the future (the clock went back) is as fresh as it gets
+    let age = SystemTime::now()
+        .duration_since(refreshed)
+        .unwrap_or_default();
+    let left = Duration::from_secs(zone.soa().expire as u64).checked_sub(age)?;
+    (!left.is_zero()).then_some((zone, left))
+}
+
+// a refresh that found the saved copy still current: it's as good as new again
+pub(crate) fn touch(file: &Path) -> io::Result<()> {
+    File::options()
+        .write(true)
+        .open(file)?
+        .set_modified(SystemTime::now())
+}
+
+// a newer version of the zone than `current`, from the first of the primaries that gets back
+// to us. None when `current` is still the latest
+pub(crate) fn fetch(
+    config: &SecondaryZone,
+    current: Option<&Zone>,
+) -> Result<Option<Zone>, DnsError> {
+    let mut last = DnsError::Malformed("secondary zone without primaries");
+    for &primary in &config.primaries {
+        match fetch_from(config, current, primary) {
+            Ok(zone) => return Ok(zone),
+            Err(e) => last = e,
+        }
+    }
+    Err(last)
+}
+
+fn fetch_from(
+    config: &SecondaryZone,
+    current: Option<&Zone>,
+    primary: SocketAddr,
+) -> Result<Option<Zone>, DnsError> {
+    let serial = primary_serial(config, primary)?;
+    match current {
+        // RFC 1982 serial arithmetic: the serial wraps around, and "ahead" is less than half
+        // the way round
+        Some(zone) if (serial.wrapping_sub(zone.soa().serial) as i32) <= 0 => Ok(None),
+        Some(zone) => {
+            let mut zone = zone.clone();
+            Ok(update_zone(&mut zone, primary, &config.transfer)?.then_some(zone))
+        }
+        None => {
+            let records = axfr(&config.origin.to_string(), primary, &config.transfer)?;
+            let zone = Zone::new(config.origin.clone(), records)
+                .map_err(|_| DnsError::Malformed("transferred zone doesn't hold together"))?;
+            Ok(Some(zone))
+        }
+    }
+}
+
+// the serial in `primary`'s SOA for the zone, asked over UDP
+fn primary_serial(config: &SecondaryZone, primary: SocketAddr) -> Result<u32, DnsError> {
+    let query = DnsMessage::query(config.origin.clone())
+        .qtype(RecordType::SOA)
+        .recursion_desired(false)
+        .build();
+    let mut transport = UdpTransport::new(primary);
+    transport.set_timeout(config.transfer.timeout);
+    let res = transport.exchange(&query)?;
+    if res.rcode() != 0 {
+        return Err(DnsError::from_response(&res));
+    }
+    res.answers
+        .iter()
+        .filter(|rr| rr.rr_type == TYPE_SOA && rr.name == config.origin)
+        .find_map(|rr| match &rr.data {
+            RData::SOA(soa) => Some(soa.serial),
+            _ => None,
+        })
+        .ok_or(DnsError::Malformed("primary has no SOA for the zone"))
+}
@@ -0,0 +1,1254 @@
+// a stub DNS server: listens on UDP and TCP like any other DNS server, and answers each query
+// by passing it on to a Resolver and copying what comes back. Point the machines on a LAN at it
+// and they get whatever upstreams, cache and retry policy the Resolver was set up with. Or,
+// started with DnsServer::recursive, it forwards nowhere and resolves everything itself from
+// the root servers down.
+//
+// Every query goes upstream as a fresh one of our own (new ID, RD on), never as the client's
+// packet: the client's ID means nothing to the upstream, and what the client put in its OPT
+// record beyond DO is between it and us.
+//
+// The Resolver's cache does the caching, with the TTLs of what it hands out counted down to
+// what's left of them. What it can't do is notice that the same question is already on its way
+// upstream: a burst of clients asking for a name nobody has asked for in a while would all miss
+// the cache together. So the server keeps the questions in flight, and whoever asks while one is
+// waits for that answer instead of sending a query of their own.
+//
+// Clients that send a DNS cookie get one of ours back (see cookie.rs). With set_require_cookies
+// a UDP query is only answered once it comes with that cookie, which an off-path attacker
+// forging a victim's address never sees: they get a BADCOOKIE with a cookie to try again with,
+// or a truncated answer sending them to TCP if they don't do cookies at all. Neither is any
+// bigger than the query, so the server is useless for reflection.
+//
+// Zones added with add_zone are answered from memory with AA set (see authority.rs) and never
+// forwarded. DnsServer::authoritative is a server with nothing but those: it doesn't recurse,
+// says so by leaving RA clear, and refuses whatever isn't in one of its zones. Zones added with
+// add_secondary are transferred from their primary and kept up to date (see secondary.rs) by a
+// thread of their own while the server runs
+use std::collections::HashMap;
+use std::io::{self, ErrorKind};
+use std::net::{IpAddr, SocketAddr, TcpListener, TcpStream, UdpSocket};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Condvar, Mutex, PoisonError, RwLock};
+use std::thread::{self, Scope};
+use std::time::{Duration, Instant};
+
+use crate::cookie::{self, CookieCheck, ServerCookies};
+use crate::options::CLASSIC_UDP_PAYLOAD;
+use crate::rdata::{TYPE_SOA, TYPE_TSIG};
+use crate::secondary::{self, Secondary};
+use crate::tcp::DnsFramed;
+use crate::{
+    DnsCache, DnsMessage, Edns, EdnsOption, ExtendedError, IterativeResolver, Name, Opcode,
+    Resolver, ResponseCode, SecondaryZone, Zone, EDNS_TCP_KEEPALIVE,
+};
+
+pub const DEFAULT_LISTEN: &str = "0.0.0.0:53";
+
+// the UDP payload we tell clients we can take, the same DNS flag day number the Resolver uses
+const SERVER_UDP_PAYLOAD: u16 = 1232;
+// how long a TCP client can sit on an open connection without asking anything (RFC 7766 says
+// tens of seconds at most)
+const TCP_IDLE_TIMEOUT: Duration = Duration::from_secs(10);
+// how many UDP queries are handled at once unless set otherwise. Past that the receive loop
+// waits for one of them to finish, and new queries queue up in the socket meanwhile (past its
+// buffer they're dropped, which a client takes like any other lost packet and asks again)
+pub const DEFAULT_UDP_HANDLERS: usize = 256;
+
+pub struct DnsServer {
+    udp: UdpSocket,
+    tcp: TcpListener,
+    resolver: Resolver,
+    in_flight: Mutex<HashMap<FlightKey, Arc<Flight>>>,
+    cookies: ServerCookies,
+    // answer UDP queries only when they bring back a server cookie of ours
+    require_cookies: bool,
+    // the zones we're authoritative for, by origin
+    zones: RwLock<HashMap<Name, Arc<Zone>>>,
+    // false for an authoritative-only server
+    recursion: bool,
+    // the zones among them we are a secondary for, and the refresher waiting for one to be due
+    secondaries: Mutex<HashMap<Name, Secondary>>,
+    refresh_due: Condvar,
+    // set when run is on its way out, for the refresher to stop too
+    stopping: AtomicBool,
+    udp_handlers: Handlers,
+}
+
+// the UDP queries being handled right now, each on a thread of its own, and how many of them
+// there can be at most
+struct Handlers {
+    running: Mutex<usize>,
+    finished: Condvar,
+    max: usize,
+}
+
+// one of them, given back when it's dropped
+struct HandlerSlot<'a>(&'a Handlers);
+
+// the upstream query, as far as telling two apart goes: name, type, class, CD and DO
+type FlightKey = (Name, u16, u16, bool, bool);
+
+// the leader's end of a flight. However the leader gets out of forward, a panic included, this
+// lands it: the followers get the answer (None, a failure, unless one came in) and the next
+// client to ask starts a flight of its own
+struct Landing<'a> {
+    server: &'a DnsServer,
+    key: FlightKey,
+    flight: Arc<Flight>,
+    answer: Option<DnsMessage>,
+}
+
+// one upstream query and everyone waiting on it. None until it's back, then Some of the answer
+// (or of None if it failed)
+#[derive(Default)]
+struct Flight {
+    answer: Mutex<Option<Option<DnsMessage>>>,
+    landed: Condvar,
+}
+
+impl DnsServer {
+    // binds both sockets to `addr`. With port 0 the TCP listener gets the same port the UDP
+    // socket was given. A resolver without a cache gets one of its own, DEFAULT_CACHE_ENTRIES
+    // big; give it one first (see Resolver::set_cache) to size it or share it with something
+    // else
+    pub fn bind(addr: SocketAddr, mut resolver: Resolver) -> io::Result<Self> {
+        let udp = UdpSocket::bind(addr)?;
+        let tcp = TcpListener::bind(udp.local_addr()?)?;
+        if resolver.cache().is_none() {
+            resolver.set_cache(Arc::new(DnsCache::new()));
+        }
+        Ok(DnsServer {
+            udp,
+            tcp,
+            resolver,
+            in_flight: Mutex::new(HashMap::new()),
+            cookies: ServerCookies::new(),
+            require_cookies: false,
+            zones: RwLock::new(HashMap::new()),
+            recursion: true,
+            secondaries: Mutex::new(HashMap::new()),
+            refresh_due: Condvar::new(),
+            stopping: AtomicBool::new(false),
+            udp_handlers: Handlers {
+                running: Mutex::new(0),
+                finished: Condvar::new(),
+                max: DEFAULT_UDP_HANDLERS,
+            },
+        })
+    }
+
+    // a recursive server: the answers come from `iterative` walking the tree, which keeps the
+    // delegations it learns on the way, and land in the server's cache like forwarded ones do
+    pub fn recursive(addr: SocketAddr, iterative: IterativeResolver) -> io::Result<Self> {
+        Self::bind(addr, Resolver::with_transport(Arc::new(iterative)))
+    }
+
+    // a server for its zones and nothing else. Add them with add_zone
+    pub fn authoritative(addr: SocketAddr) -> io::Result<Self> {
+        let mut server = Self::bind(addr, Resolver::with_servers([]))?;
+        server.recursion = false;
+        Ok(server)
+    }
+
+    // answers for `zone` from now on, in place of any zone already there with the same origin.
+    // Works while the server is running, for a zone that has been reloaded
+    pub fn add_zone(&self, zone: Zone) {
+        let origin = zone.origin().clone();
+        self.zones.write().unwrap().insert(origin, Arc::new(zone));
+    }
+
+    pub fn remove_zone(&self, origin: &Name) -> Option<Arc<Zone>> {
+        self.zones.write().unwrap().remove(origin)
+    }
+
+    pub fn zone(&self, origin: &Name) -> Option<Arc<Zone>> {
+        self.zones.read().unwrap().get(origin).cloned()
+    }
+
+    // answers for `zone` as a secondary: transferred from its primaries as soon as the server
+    // runs, and refreshed from then on. The copy saved in its file, if there is one that loads,
+    // is served until then, or until it expires: counting from its last refresh, not from now
+    pub fn add_secondary(&self, zone: SecondaryZone) {
+        let now = Instant::now();
+        let mut expire_at = None;
+        if let Some((saved, left)) = secondary::saved_copy(&zone) {
+            expire_at = Some(now + left);
+            self.add_zone(saved);
+        }
+        let secondary = Secondary {
+            config: zone,
+            refresh_at: now,
+            expire_at,
+        };
+        let mut secondaries = self.secondaries.lock().unwrap();
+        secondaries.insert(secondary.config.origin.clone(), secondary);
+        self.refresh_due.notify_all();
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.udp.local_addr()
+    }
+
+    pub fn resolver(&self) -> &Resolver {
+        &self.resolver
+    }
+
+    // off by default: plenty of stub resolvers still don't send cookies, and with this on each
+    // of their queries costs them a second one over TCP
+    pub fn set_require_cookies(&mut self, on: bool) {
+        self.require_cookies = on;
+    }
+
+    // at most this many UDP queries handled at once, see DEFAULT_UDP_HANDLERS. At least one
+    pub fn set_max_udp_handlers(&mut self, max: usize) {
+        self.udp_handlers.max = max.max(1);
+    }
+
+    // serves until one of the sockets fails. Each query gets a thread of its own (up to
+    // set_max_udp_handlers of them for UDP), and the Resolver has any number of queries out at
+    // once, so a slow upstream holds up nobody but the client that asked
+    pub fn run(&self) -> io::Result<()> {
+        thread::scope(|scope| {
+            scope.spawn(|| self.refresh_secondaries());
+            let result = self.serve(scope);
+            // the refresher would keep the scope (and us) waiting forever otherwise
+            let _secondaries = self.secondaries.lock().unwrap();
+            self.stopping.store(true, Ordering::SeqCst);
+            self.refresh_due.notify_all();
+            result
+        })
+    }
+
+    // both sockets, until one of them fails
+    fn serve<'scope>(&'scope self, scope: &'scope Scope<'scope, '_>) -> io::Result<()> {
+        let tcp = scope.spawn(move || -> io::Result<()> {
+            for stream in self.tcp.incoming() {
+                match stream {
+                    Ok(stream) => {
+                        scope.spawn(move || self.serve_tcp(stream));
+                    }
+                    // the client gave up before we got to it
+                    Err(e) if e.kind() == ErrorKind::ConnectionAborted => {}
+                    Err(e) => return Err(e),
+                }
+            }
+            Ok(())
+        });
+
+        let mut buf = [0u8; 65535];
+        loop {
+            if tcp.is_finished() {
+                return tcp.join().unwrap();
+            }
+            let (len, peer) = match self.udp.recv_from(&mut buf) {
+                Ok(received) => received,
+                // Windows reports an ICMP port unreachable for an earlier reply this way
+                Err(e)
+                    if matches!(
+                        e.kind(),
+                        ErrorKind::ConnectionReset | ErrorKind::Interrupted
+                    ) =>
+                {
+                    continue
+                }
+                Err(e) => return Err(e),
+            };
+            let packet = buf[..len].to_vec();
+            let slot = self.udp_handlers.enter();
+            scope.spawn(move || {
+                let _slot = slot;
+                if let Some(reply) = self.reply_udp(&packet, peer.ip()) {
+                    // nothing to be done about a client we can't reach
+                    let _ = self.udp.send_to(&reply, peer);
+                }
+            });
+        }
+    }
+
+    // the response to one query, as the server sends it. Anything that isn't a query (a stray
+    // response, say) gets None and no reply at all
+    pub fn answer(&self, query: &DnsMessage) -> Option<DnsMessage> {
+        if query.is_response() {
+            return None;
+        }
+        if query.opcode() != Opcode::Query {
+            return Some(self.response(query, ResponseCode::NotImp));
+        }
+        if query.questions.len() != 1 {
+            return Some(self.response(query, ResponseCode::FormErr));
+        }
+        // we only speak EDNS version 0 (RFC 6891 6.1.3)
+        if query.edns.as_ref().is_some_and(|edns| edns.version > 0) {
+            return Some(self.response(query, ResponseCode::BadVers));
+        }
+        if let Some(zone) = self.zone_for(query) {
+            let answer = zone.answer(query);
+            let mut res = self.response(query, answer.response_code());
+            let mut flags = res.flags();
+            flags.aa = answer.flags().aa;
+            res.set_flags(flags);
+            for rr in answer.answers {
+                res.add_answer(rr);
+            }
+            for rr in answer.authority {
+                res.add_authority(rr);
+            }
+            for rr in answer.additional {
+                res.add_additional(rr);
+            }
+            return Some(res);
+        }
+        if !self.recursion {
+            return Some(self.response(query, ResponseCode::Refused));
+        }
+
+        let dnssec_ok = query.edns.as_ref().is_some_and(|edns| edns.dnssec_ok);
+        let upstream_query = DnsMessage::query(query.question().qname.clone())
+            .qtype(query.question().qtype)
+            .qclass(query.question().qclass)
+            .checking_disabled(query.checking_disabled())
+            .dnssec_ok(dnssec_ok)
+            .build();
+        let Some(upstream) = self.forward(&upstream_query) else {
+            let mut res = self.response(query, ResponseCode::ServFail);
+            if let Some(edns) = res.edns.as_mut() {
+                edns.options.push(ExtendedError::new(22, "").to_option());
+            }
+            return Some(res);
+        };
+
+        let mut res = self.response(query, upstream.response_code());
+        // an extended rcode needs an OPT record to carry it, and a client without one can't
+        // be told anything better than that it failed
+        if res.edns.is_none() && upstream.rcode() > 0xF {
+            return Some(self.response(query, ResponseCode::ServFail));
+        }
+        let mut flags = res.flags();
+        flags.ad = upstream.authentic_data();
+        res.set_flags(flags);
+        // the upstream's reasons are the client's reasons too
+        if let Some(edns) = res.edns.as_mut() {
+            edns.options.extend(
+                upstream
+                    .extended_errors()
+                    .iter()
+                    .map(ExtendedError::to_option),
+            );
+        }
+        for rr in upstream.answers {
+            res.add_answer(rr);
+        }
+        for rr in upstream.authority {
+            res.add_authority(rr);
+        }
+        // the upstream's signature was for us
+        for rr in upstream.additional {
+            if rr.rr_type != TYPE_TSIG {
+                res.add_additional(rr);
+            }
+        }
+        Some(res)
+    }
+
+    // `answer`, with the cookie checks on top for a query from `client`. Over TCP nobody can
+    // forge the address, so a missing or stale cookie only ever costs a UDP client
+    fn answer_from(&self, query: &DnsMessage, client: IpAddr, udp: bool) -> Option<DnsMessage> {
+        if query.is_response() {
+            return None;
+        }
+        if query.opcode() == Opcode::Notify {
+            return Some(self.notified(query, client));
+        }
+        let now = cookie::now();
+        let check = self.cookies.check(query, client, now);
+        let mut res = match check {
+            CookieCheck::Malformed => self.response(query, ResponseCode::FormErr),
+            CookieCheck::Missing if udp && self.require_cookies => {
+                let mut res = self.response(query, ResponseCode::NoError);
+                let mut flags = res.flags();
+                flags.tc = true;
+                res.set_flags(flags);
+                res
+            }
+            CookieCheck::ClientOnly(_) if udp && self.require_cookies => {
+                self.response(query, ResponseCode::BadCookie)
+            }
+            _ => self.answer(query)?,
+        };
+        if let (Some(edns), Some(option)) = (
+            res.edns.as_mut(),
+            self.cookies.option(query, check, client, now),
+        ) {
+            edns.options.push(option);
+        }
+        Some(res)
+    }
+
+    // the Resolver's answer to `query`, or the one to the same question already in flight.
+    // None when it failed
+    fn forward(&self, query: &DnsMessage) -> Option<DnsMessage> {
+        let dnssec_ok = query.edns.as_ref().is_some_and(|edns| edns.dnssec_ok);
+        let key = (
+            query.question().qname.clone(),
+            query.question().qtype,
+            query.question().qclass,
+            query.checking_disabled(),
+            dnssec_ok,
+        );
+        let (flight, leader) = {
+            let mut in_flight = self.in_flight.lock().unwrap();
+            match in_flight.get(&key) {
+                Some(flight) => (flight.clone(), false),
+                None => {
+                    let flight = Arc::new(Flight::default());
+                    in_flight.insert(key.clone(), flight.clone());
+                    (flight, true)
+                }
+            }
+        };
+
+        if !leader {
+            let mut answer = flight.answer.lock().unwrap();
+            while answer.is_none() {
+                answer = flight.landed.wait(answer).unwrap();
+            }
+            return answer.clone().unwrap();
+        }
+        let mut landing = Landing {
+            server: self,
+            key,
+            flight,
+            answer: None,
+        };
+        landing.answer = self.resolver.query(query).ok();
+        landing.answer.clone()
+    }
+
+    // a response that fits in the client's UDP buffer, or a truncated one sending it to TCP.
+    // A packet we can't parse gets a FORMERR if there's at least a header to answer
+    fn reply_udp(&self, packet: &[u8], client: IpAddr) -> Option<Vec<u8>> {
+        let query = match DnsMessage::from_bytes(packet) {
+            Ok(query) => query,
+            Err(_) => return formerr(packet),
+        };
+        let res = self.answer_from(&query, client, true)?;
+        let bytes = res.to_bytes().ok()?;
+        let limit = query
+            .edns
+            .as_ref()
+            .map_or(CLASSIC_UDP_PAYLOAD, |edns| edns.udp_payload)
+            .max(CLASSIC_UDP_PAYLOAD) as usize;
+        if bytes.len() <= limit {
+            return Some(bytes);
+        }
+        let mut truncated = self.response(&query, res.response_code());
+        let mut flags = truncated.flags();
+        flags.tc = true;
+        truncated.set_flags(flags);
+        truncated.edns = res.edns;
+        truncated.to_bytes().ok()
+    }
+
+    // the refresher: sleeps until the next secondary zone is due (or a NOTIFY makes one due
+    // now) and refreshes it, until run stops
+    fn refresh_secondaries(&self) {
+        let mut secondaries = self.secondaries.lock().unwrap();
+        while !self.stopping.load(Ordering::SeqCst) {
+            let now = Instant::now();
+            let due: Vec<Name> = secondaries
+                .iter()
+                .filter(|(_, secondary)| secondary.refresh_at <= now)
+                .map(|(origin, _)| origin.clone())
+                .collect();
+            if due.is_empty() {
+                let next = secondaries
+                    .values()
+                    .map(|secondary| secondary.refresh_at)
+                    .min();
+                secondaries = match next {
+                    Some(at) => {
+                        self.refresh_due
+                            .wait_timeout(secondaries, at - now)
+                            .unwrap()
+                            .0
+                    }
+                    None => self.refresh_due.wait(secondaries).unwrap(),
+                };
+                continue;
+            }
+            // the transfers take a while, and NOTIFYs have to get in meanwhile
+            drop(secondaries);
+            for origin in due {
+                self.refresh(&origin);
+            }
+            secondaries = self.secondaries.lock().unwrap();
+        }
+    }
+
+    // checks one secondary zone with its primaries and takes in the new version if there is
+    // one, saving it to the zone's file. A copy that's expired stops being served
+    fn refresh(&self, origin: &Name) {
+        let Some(config) = self
+            .secondaries
+            .lock()
+            .unwrap()
+            .get(origin)
+            .map(|secondary| secondary.config.clone())
+        else {
+            return;
+        };
+        let current = self.zone(origin);
+        let result = secondary::fetch(&config, current.as_deref());
+        let mut secondaries = self.secondaries.lock().unwrap();
+        let Some(secondary) = secondaries.get_mut(origin) else {
+            return;
+        };
+        let latest = match &result {
+            Ok(Some(zone)) => {
+                // the zone is served all the same if it can't be saved, just not after a restart
+                if let Some(file) = &config.file {
+                    let _ = zone.save(file);
+                }
+                self.add_zone(zone.clone());
+                Some(zone)
+            }
+            Ok(None) => {
+                if let Some(file) = &config.file {
+                    let _ = secondary::touch(file);
+                }
+                current.as_deref()
+            }
+            Err(_) => current.as_deref(),
+        };
+        if secondary.schedule(result.is_ok(), latest, Instant::now()) {
+            self.remove_zone(origin);
+            secondary.expire_at = None;
+        }
+    }
+
+    // a NOTIFY for one of our secondary zones, from one of its primaries, has it refreshed right
+    // away. Anyone else is refused, and a zone we aren't a secondary for is NOTAUTH
+    fn notified(&self, query: &DnsMessage, client: IpAddr) -> DnsMessage {
+        if query.questions.len() != 1 || query.question().qtype != TYPE_SOA {
+            return self.response(query, ResponseCode::FormErr);
+        }
+        let mut secondaries = self.secondaries.lock().unwrap();
+        let Some(secondary) = secondaries.get_mut(&query.question().qname) else {
+            return self.response(query, ResponseCode::NotAuth);
+        };
+        if !secondary
+            .config
+            .primaries
+            .iter()
+            .any(|primary| primary.ip() == client)
+        {
+            return self.response(query, ResponseCode::Refused);
+        }
+        secondary.refresh_at = Instant::now();
+        self.refresh_due.notify_all();
+        let mut res = self.response(query, ResponseCode::NoError);
+        let mut flags = res.flags();
+        flags.aa = true;
+        res.set_flags(flags);
+        res
+    }
+
+    // the zone of ours `query` is for: the closest one above its name, in its class
+    fn zone_for(&self, query: &DnsMessage) -> Option<Arc<Zone>> {
+        let question = query.question();
+        self.zones
+            .read()
+            .unwrap()
+            .values()
+            .filter(|zone| zone.contains(&question.qname))
+            .filter(|zone| question.qclass == zone.class() || question.qclass == 255)
+            .max_by_key(|zone| zone.origin().labels().len())
+            .cloned()
+    }
+
+    // error_response, with RA only when we do recurse
+    fn response(&self, query: &DnsMessage, rcode: ResponseCode) -> DnsMessage {
+        let mut res = error_response(query, rcode);
+        let mut flags = res.flags();
+        flags.ra = self.recursion;
+        res.set_flags(flags);
+        res
+    }
+
+    // one TCP client, which may send any number of queries down the connection (RFC 7766).
+    // They're answered in order
+    fn serve_tcp(&self, stream: TcpStream) {
+        if stream.set_read_timeout(Some(TCP_IDLE_TIMEOUT)).is_err() {
+            return;
+        }
+        let Ok(client) = stream.peer_addr() else {
+            return;
+        };
+        let mut stream = DnsFramed::new(stream);
+        while let Ok(Some(msg)) = stream.read_message() {
+            let reply = match DnsMessage::from_bytes(msg) {
+                Ok(query) => self
+                    .answer_from(&query, client.ip(), false)
+                    .map(|res| with_keepalive(&query, res))
+                    .and_then(|res| res.to_bytes().ok()),
+                Err(_) => formerr(msg),
+            };
+            if let Some(reply) = reply {
+                if stream.write_message(&reply).is_err() {
+                    return;
+                }
+            }
+        }
+    }
+}
+
+// a client asking over TCP with an edns-tcp-keepalive option (RFC 7828) gets told how long we
+// keep the connection open for it
+fn with_keepalive(query: &DnsMessage, mut res: DnsMessage) -> DnsMessage {
+    let asked = query
+        .edns
+        .as_ref()
+        .is_some_and(|edns| edns.option(EDNS_TCP_KEEPALIVE).is_some());
+    if let (true, Some(edns)) = (asked, &mut res.edns) {
+        edns.options
+            .retain(|option| option.code != EDNS_TCP_KEEPALIVE);
+        edns.options
+            .push(EdnsOption::tcp_keepalive(Some(TCP_IDLE_TIMEOUT)));
+    }
+    res
+}
+
+// a response to `query` with no records in it, just the rcode: same ID and question, QR, RD
+// copied, RA (DnsServer::response clears it when we don't recurse). A client that sent EDNS gets
+// an OPT record back
+fn error_response(query: &DnsMessage, rcode: ResponseCode) -> DnsMessage {
+    let mut res = DnsMessage::response_to(query);
+    res.questions.truncate(1);
+    res.header.no_of_questions = res.questions.len() as u16;
+    let rcode = u16::from(rcode);
+    let mut flags = res.flags();
+    flags.opcode = query.flags().opcode;
+    flags.ra = true;
+    flags.cd = query.checking_disabled();
+    flags.rcode = (rcode & 0xF) as u8;
+    res.set_flags(flags);
+    if let Some(client) = &query.edns {
+        let mut edns = Edns::new(SERVER_UDP_PAYLOAD);
+        edns.dnssec_ok = client.dnssec_ok;
+        edns.extended_rcode = (rcode >> 4) as u8;
+        res.edns = Some(edns);
+    }
+    res
+}
+
+impl Handlers {
+    // a slot for one more, once there's room
+    fn enter(&self) -> HandlerSlot<'_> {
+        let mut running = self.running.lock().unwrap();
+        while *running >= self.max {
+            running = self.finished.wait(running).unwrap();
+        }
+        *running += 1;
+        HandlerSlot(self)
+    }
+}
+
+impl Drop for HandlerSlot<'_> {
+    fn drop(&mut self) {
+        *self.0.running.lock().unwrap() -= 1;
+        self.0.finished.notify_one();
+    }
+}
+
+impl Drop for Landing<'_> {
+    fn drop(&mut self) {
+        // out of the map first, so nobody joins a flight that has already landed. A poisoned
+        // lock is taken all the same: panicking again on the way out of a panic would abort
+        let mut in_flight = self
+            .server
+            .in_flight
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        in_flight.remove(&self.key);
+        drop(in_flight);
+        let mut answer = self
+            .flight
+            .answer
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        *answer = Some(self.answer.take());
+        self.flight.landed.notify_all();
+    }
+}
+
+// the header of a packet we couldn't parse, sent back with QR and FORMERR. Too short for a
+// header, or a response itself, and it isn't worth a reply
+fn formerr(packet: &[u8]) -> Option<Vec<u8>> {
+    let header = packet.get(..12)?;
+    if header[2] & 0x80 != 0 {
+        return None;
+    }
+    let mut reply = vec![0u8; 12];
+    reply[0..2].copy_from_slice(&header[0..2]);
+    // QR, the opcode and RD copied, RA; then FORMERR
+    reply[2] = 0x80 | (header[2] & 0x79);
+    reply[3] = 0x80 | u16::from(ResponseCode::FormErr) as u8;
+    Some(reply)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{
+        free_loopback_port, mock_stalling_udp_server, mock_tcp_server_at, mock_udp_server,
+        mock_udp_server_replies_at, response_with_rdata,
+    };
+    use crate::{
+        parse_zone, DnsTransport, IterativeOptions, LocalRecords, RData, RecordType,
+        ResourceRecord, RootHints, Rrsig, RCODE_NXDOMAIN,
+    };
+    use std::net::Ipv4Addr;
+
+    const LOCALHOST: IpAddr = IpAddr::V4(Ipv4Addr::LOCALHOST);
+
+    fn start(resolver: Resolver) -> SocketAddr {
+        let server = DnsServer::bind("127.0.0.1:0".parse().unwrap(), resolver).unwrap();
+        let addr = server.local_addr().unwrap();
+        thread::spawn(move || server.run());
+        addr
+    }
+
+    fn ask_udp(server: SocketAddr, query: &DnsMessage) -> DnsMessage {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        socket
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        socket.send_to(&query.to_bytes().unwrap(), server).unwrap();
+        let mut buf = [0u8; 65535];
+        let (len, _) = socket.recv_from(&mut buf).unwrap();
+        DnsMessage::from_bytes(&buf[..len]).unwrap()
+    }
+
+    #[test]
+    fn test_forwards_over_udp_and_tcp() {
+        let upstream = mock_udp_server(2, |query| {
+            response_with_rdata(query, RecordType::A.into(), &[192, 0, 2, 7])
+        });
+        let server = start(Resolver::with_server(upstream));
+        let query = DnsMessage::query("example.com").id(0xBEEF).build();
+
+        let res = ask_udp(server, &query);
+        assert_eq!(res.header.identification, 0xBEEF);
+        assert!(res.is_response());
+        assert!(res.flags().ra && res.recursion_desired());
+        assert_eq!(res.question(), query.question());
+        assert_eq!(res.ipv4_addrs(), [Ipv4Addr::new(192, 0, 2, 7)]);
+
+        let mut stream = DnsFramed::new(TcpStream::connect(server).unwrap());
+        stream.write_message(&query.to_bytes().unwrap()).unwrap();
+        let res = DnsMessage::from_bytes(stream.read_message().unwrap().unwrap()).unwrap();
+        assert_eq!(res.header.identification, 0xBEEF);
+        assert_eq!(res.answers[0].data, RData::A(Ipv4Addr::new(192, 0, 2, 7)));
+    }
+
+    #[test]
+    fn test_error_replies() {
+        // nothing listens upstream, so whatever gets that far fails
+        let port = free_loopback_port();
+        let mut resolver = Resolver::with_server(SocketAddr::from(([127, 0, 0, 1], port)));
+        resolver.set_timeout(Duration::from_millis(100));
+        let server = DnsServer::bind("127.0.0.1:0".parse().unwrap(), resolver).unwrap();
+
+        let query = DnsMessage::query("example.com").id(7).build();
+        let res = server.answer(&query).unwrap();
+        assert_eq!(res.response_code(), ResponseCode::ServFail);
+        assert_eq!(res.header.identification, 7);
+        // a client with EDNS hears why
+        let res = server
+            .answer(
+                &DnsMessage::query("example.com")
+                    .edns(Edns::new(1232))
+                    .build(),
+            )
+            .unwrap();
+        assert_eq!(res.extended_errors(), [ExtendedError::new(22, "")]);
+
+        let mut notify = query.clone();
+        let mut flags = notify.flags();
+        flags.opcode = Opcode::Notify.into();
+        notify.set_flags(flags);
+        let res = server.answer(&notify).unwrap();
+        assert_eq!(res.response_code(), ResponseCode::NotImp);
+        assert_eq!(res.opcode(), Opcode::Notify);
+
+        let newer = DnsMessage::query("example.com")
+            .edns(Edns {
+                version: 1,
+                ..Edns::new(1232)
+            })
+            .build();
+        let res = server.answer(&newer).unwrap();
+        assert_eq!(res.response_code(), ResponseCode::BadVers);
+
+        let mut stray = query.clone();
+        stray.set_response(true);
+        assert!(server.answer(&stray).is_none());
+
+        // a header and garbage after it
+        let mut packet = query.to_bytes().unwrap();
+        packet.truncate(14);
+        let reply = DnsMessage::from_bytes(&server.reply_udp(&packet, LOCALHOST).unwrap()).unwrap();
+        assert_eq!(reply.header.identification, 7);
+        assert_eq!(reply.response_code(), ResponseCode::FormErr);
+        assert!(server.reply_udp(&packet[..5], LOCALHOST).is_none());
+    }
+
+    #[test]
+    fn test_required_cookies() {
+        let upstream = mock_udp_server(1, |query| {
+            response_with_rdata(query, RecordType::A.into(), &[192, 0, 2, 7])
+        });
+        let mut server = DnsServer::bind(
+            "127.0.0.1:0".parse().unwrap(),
+            Resolver::with_server(upstream),
+        )
+        .unwrap();
+        server.set_require_cookies(true);
+        let addr = server.local_addr().unwrap();
+        thread::spawn(move || server.run());
+
+        // no cookie: off to TCP
+        let plain = DnsMessage::query("example.com").build();
+        let res = ask_udp(addr, &plain);
+        assert!(res.truncated() && res.answers.is_empty());
+
+        // a client cookie alone gets BADCOOKIE and a server cookie to come back with, which the
+        // resolver does by itself
+        let resolver = Resolver::with_server(addr);
+        for _ in 0..2 {
+            let res = resolver.query(&plain).unwrap();
+            assert_eq!(res.ipv4_addrs(), [Ipv4Addr::new(192, 0, 2, 7)]);
+            let cookie = res
+                .edns
+                .unwrap()
+                .option(cookie::EDNS_COOKIE)
+                .unwrap()
+                .to_vec();
+            assert_eq!(cookie.len(), 24);
+        }
+        assert_eq!(resolver.stats().retries, 1);
+    }
+
+    #[test]
+    fn test_local_records_never_go_upstream() {
+        // nothing listens upstream
+        let port = free_loopback_port();
+        let mut resolver = Resolver::with_server(SocketAddr::from(([127, 0, 0, 1], port)));
+        let mut local = LocalRecords::new();
+        local.add_addr("printer.home", IpAddr::from([192, 168, 1, 50]));
+        local.add_zone("home");
+        resolver.set_local_records(Arc::new(local));
+        let server = start(resolver);
+
+        let res = ask_udp(server, &DnsMessage::query("printer.home").build());
+        assert_eq!(res.ipv4_addrs(), [Ipv4Addr::new(192, 168, 1, 50)]);
+        let res = ask_udp(server, &DnsMessage::query("nas.home").build());
+        assert_eq!(res.response_code(), ResponseCode::NXDomain);
+    }
+
+    #[test]
+    fn test_big_answers_are_truncated_over_udp() {
+        let upstream = mock_udp_server(1, |query| {
+            response_with_rdata(query, RecordType::TXT.into(), &[[255; 256]; 3].concat())
+        });
+        let server = start(Resolver::with_server(upstream));
+        let query = DnsMessage::query("example.com")
+            .qtype(RecordType::TXT)
+            .build();
+        let res = ask_udp(server, &query);
+        assert!(res.truncated());
+        assert!(res.answers.is_empty());
+        assert_eq!(res.question(), query.question());
+    }
+
+    #[test]
+    fn test_a_burst_of_the_same_question_goes_upstream_once() {
+        // a slow upstream, so the whole burst arrives while the first query is out
+        let upstream = mock_udp_server(5, |query| {
+            thread::sleep(Duration::from_millis(300));
+            response_with_rdata(query, RecordType::A.into(), &[192, 0, 2, 8])
+        });
+        let server = DnsServer::bind(
+            "127.0.0.1:0".parse().unwrap(),
+            Resolver::with_server(upstream),
+        )
+        .unwrap();
+        let barrier = std::sync::Barrier::new(5);
+        let answers: Vec<DnsMessage> = thread::scope(|scope| {
+            let clients: Vec<_> = (0..5)
+                .map(|id| {
+                    let (server, barrier) = (&server, &barrier);
+                    scope.spawn(move || {
+                        barrier.wait();
+                        let query = DnsMessage::query("example.com").id(id).build();
+                        server.answer(&query).unwrap()
+                    })
+                })
+                .collect();
+            clients.into_iter().map(|c| c.join().unwrap()).collect()
+        });
+
+        for (id, res) in answers.iter().enumerate() {
+            assert_eq!(res.header.identification, id as u16);
+            assert_eq!(res.ipv4_addrs(), [Ipv4Addr::new(192, 0, 2, 8)]);
+        }
+        assert_eq!(server.resolver().stats().queries_sent, 1);
+    }
+
+    // an upstream that sits on slow.example for a second, and how long a client asking
+    // fast.example waits while a client asking slow.example is waiting too
+    fn fast_behind_slow(server: DnsServer) -> Duration {
+        let addr = server.local_addr().unwrap();
+        thread::spawn(move || server.run());
+        thread::scope(|scope| {
+            let slow = scope.spawn(|| ask_udp(addr, &DnsMessage::query("slow.example").build()));
+            thread::sleep(Duration::from_millis(100));
+            let started = Instant::now();
+            let res = ask_udp(addr, &DnsMessage::query("fast.example").build());
+            let waited = started.elapsed();
+            assert_eq!(res.ipv4_addrs(), [Ipv4Addr::new(192, 0, 2, 6)]);
+            assert_eq!(
+                slow.join().unwrap().ipv4_addrs(),
+                [Ipv4Addr::new(192, 0, 2, 6)]
+            );
+            waited
+        })
+    }
+
+    fn stalling_upstream() -> Resolver {
+        let upstream =
+            mock_stalling_udp_server(2, "slow.example", Duration::from_secs(1), |query| {
+                response_with_rdata(query, RecordType::A.into(), &[192, 0, 2, 6])
+            });
+        Resolver::with_server(upstream)
+    }
+
+    #[test]
+    fn test_a_slow_upstream_holds_up_nobody_else() {
+        let server = DnsServer::bind("127.0.0.1:0".parse().unwrap(), stalling_upstream()).unwrap();
+        assert!(fast_behind_slow(server) < Duration::from_millis(500));
+    }
+
+    #[test]
+    fn test_udp_handlers_are_capped() {
+        // with room for one at a time, the second client waits its turn
+        let mut server =
+            DnsServer::bind("127.0.0.1:0".parse().unwrap(), stalling_upstream()).unwrap();
+        server.set_max_udp_handlers(1);
+        assert!(fast_behind_slow(server) > Duration::from_millis(700));
+    }
+
+    #[test]
+    fn test_the_cache_keeps_answers_with_do_apart() {
+        // signatures, and AD, only for a query that asks for them with DO
+        let upstream = mock_udp_server(2, |query| {
+            let query = DnsMessage::from_bytes(query).unwrap();
+            let mut res = DnsMessage::response_to(&query);
+            let a = RData::A(Ipv4Addr::new(192, 0, 2, 5));
+            res.add_answer(ResourceRecord::new("example.com", 300, a));
+            if query.edns.as_ref().is_some_and(|edns| edns.dnssec_ok) {
+                let sig = Rrsig {
+                    type_covered: RecordType::A.into(),
+                    algorithm: 13,
+                    labels: 2,
+                    original_ttl: 300,
+                    expiration: u32::MAX,
+                    inception: 0,
+                    key_tag: 1,
+                    signer: "example.com".to_string(),
+                    signature: vec![0; 64],
+                };
+                res.add_answer(ResourceRecord::new("example.com", 300, RData::RRSIG(sig)));
+                let mut flags = res.flags();
+                flags.ad = true;
+                res.set_flags(flags);
+            }
+            res.to_bytes().unwrap()
+        });
+        let server = start(Resolver::with_server(upstream));
+
+        // the answer without DO goes in the cache first
+        let plain = DnsMessage::query("example.com").build();
+        assert_eq!(ask_udp(server, &plain).answers.len(), 1);
+        // the second time from the cache, and the third would have nobody upstream to ask
+        let signed = DnsMessage::query("example.com").dnssec_ok(true).build();
+        for _ in 0..2 {
+            let res = ask_udp(server, &signed);
+            assert_eq!(res.answers.len(), 2);
+            assert!(res.authentic_data());
+        }
+    }
+
+    // panics on the first query it gets, a moment in, and answers the rest
+    struct PanicsOnce(AtomicBool);
+
+    impl DnsTransport for PanicsOnce {
+        fn exchange(&self, msg: &DnsMessage) -> Result<DnsMessage, crate::DnsError> {
+            if !self.0.swap(true, Ordering::SeqCst) {
+                thread::sleep(Duration::from_millis(300));
+                panic!("the upstream went wrong");
+            }
+            let mut res = DnsMessage::response_to(msg);
+            let a = RData::A(Ipv4Addr::new(192, 0, 2, 4));
+            res.add_answer(ResourceRecord::new("example.com", 300, a));
+            Ok(res)
+        }
+    }
+
+    #[test]
+    fn test_a_leader_that_panics_still_lands_its_flight() {
+        let transport = Arc::new(PanicsOnce(AtomicBool::new(false)));
+        let server = DnsServer::bind(
+            "127.0.0.1:0".parse().unwrap(),
+            Resolver::with_transport(transport),
+        )
+        .unwrap();
+        let query = DnsMessage::query("example.com").build();
+        thread::scope(|scope| {
+            let leader = scope.spawn(|| server.answer(&query));
+            thread::sleep(Duration::from_millis(100));
+            // the follower hears it failed instead of waiting forever
+            let res = server.answer(&query).unwrap();
+            assert_eq!(res.response_code(), ResponseCode::ServFail);
+            assert!(leader.join().is_err());
+        });
+        // and the next one to ask starts a flight of its own
+        let res = server.answer(&query).unwrap();
+        assert_eq!(res.ipv4_addrs(), [Ipv4Addr::new(192, 0, 2, 4)]);
+    }
+
+    #[test]
+    fn test_cached_answers_count_their_ttls_down() {
+        let upstream = mock_udp_server(1, |query| {
+            response_with_rdata(query, RecordType::A.into(), &[192, 0, 2, 9])
+        });
+        let server = DnsServer::bind(
+            "127.0.0.1:0".parse().unwrap(),
+            Resolver::with_server(upstream),
+        )
+        .unwrap();
+        let query = DnsMessage::query("example.com").build();
+
+        let first = server.answer(&query).unwrap();
+        assert_eq!(first.answers[0].ttl, 300);
+        let second = server.answer(&query).unwrap();
+        assert!(second.answers[0].ttl < 300);
+        assert_eq!(second.ipv4_addrs(), [Ipv4Addr::new(192, 0, 2, 9)]);
+        assert_eq!(server.resolver().stats().queries_sent, 1);
+        assert_eq!(server.resolver().stats().cache_hits, 1);
+    }
+
+    #[test]
+    fn test_recursive_mode_resolves_from_the_root() {
+        // the root refers example.com to its server, which has the answer
+        let port = free_loopback_port();
+        let root = SocketAddr::from(([127, 0, 0, 1], port));
+        mock_udp_server_replies_at(root, 1, |query| {
+            let query = DnsMessage::from_bytes(query).unwrap();
+            let mut res = DnsMessage::response_to(&query);
+            res.add_authority(ResourceRecord::new(
+                "example.com",
+                3600,
+                RData::NS("ns.example.com".to_string()),
+            ));
+            res.add_additional(ResourceRecord::new(
+                "ns.example.com",
+                3600,
+                RData::A([127, 0, 0, 8].into()),
+            ));
+            vec![res.to_bytes().unwrap()]
+        });
+        mock_udp_server_replies_at(([127, 0, 0, 8], port).into(), 1, |query| {
+            let query = DnsMessage::from_bytes(query).unwrap();
+            assert!(!query.recursion_desired());
+            let mut res = DnsMessage::response_to(&query);
+            let mut flags = res.flags();
+            flags.aa = true;
+            res.set_flags(flags);
+            res.add_answer(ResourceRecord::new(
+                query.question().qname.clone(),
+                300,
+                RData::A([192, 0, 2, 10].into()),
+            ));
+            vec![res.to_bytes().unwrap()]
+        });
+
+        let mut hints = RootHints::empty();
+        hints.add("root.lab", root);
+        let mut iterative = IterativeResolver::with_root_hints(hints);
+        iterative.set_options(IterativeOptions {
+            timeout: Duration::from_millis(500),
+            port,
+            qname_minimization: false,
+        });
+        let server = DnsServer::recursive("127.0.0.1:0".parse().unwrap(), iterative).unwrap();
+        let addr = server.local_addr().unwrap();
+        thread::spawn(move || server.run());
+
+        let query = DnsMessage::query("www.example.com").id(0x0529).build();
+        let res = ask_udp(addr, &query);
+        assert_eq!(res.header.identification, 0x0529);
+        assert!(res.flags().ra && !res.flags().aa);
+        assert_eq!(res.ipv4_addrs(), [Ipv4Addr::new(192, 0, 2, 10)]);
+    }
+
+    #[test]
+    fn test_authoritative_mode() {
+        let zone = "$TTL 300\n@ SOA ns hostmaster 1 2 3 4 60\nwww A 192.0.2.80\n";
+        let server = DnsServer::authoritative("127.0.0.1:0".parse().unwrap()).unwrap();
+        server
+            .add_zone(Zone::new("example.com", parse_zone(zone, "example.com").unwrap()).unwrap());
+        let addr = server.local_addr().unwrap();
+        thread::spawn(move || server.run());
+
+        let res = ask_udp(addr, &DnsMessage::query("www.example.com").build());
+        assert!(res.flags().aa && !res.flags().ra);
+        assert_eq!(res.ipv4_addrs(), [Ipv4Addr::new(192, 0, 2, 80)]);
+        let res = ask_udp(addr, &DnsMessage::query("ftp.example.com").build());
+        assert_eq!(res.rcode(), RCODE_NXDOMAIN);
+        assert_eq!(res.authority[0].ttl, 60);
+        // not ours, and we don't go looking
+        let res = ask_udp(addr, &DnsMessage::query("example.org").build());
+        assert_eq!(res.response_code(), ResponseCode::Refused);
+        assert!(!res.flags().ra);
+    }
+
+    #[test]
+    fn test_zones_come_before_forwarding() {
+        // nothing listens upstream
+        let port = free_loopback_port();
+        let server = DnsServer::bind(
+            "127.0.0.1:0".parse().unwrap(),
+            Resolver::with_server(SocketAddr::from(([127, 0, 0, 1], port))),
+        )
+        .unwrap();
+        let zone = "$TTL 300\n@ SOA ns hostmaster 1 2 3 4 60\n";
+        server.add_zone(Zone::new("lab", parse_zone(zone, "lab").unwrap()).unwrap());
+        let res = server
+            .answer(&DnsMessage::query("host.lab").build())
+            .unwrap();
+        assert!(res.flags().aa && res.flags().ra);
+        assert_eq!(res.rcode(), RCODE_NXDOMAIN);
+        assert_eq!(server.resolver().stats().queries_sent, 0);
+
+        assert!(server.remove_zone(&Name::from("lab")).is_some());
+        assert!(server.zone(&Name::from("lab")).is_none());
+    }
+
+    #[test]
+    fn test_saved_secondary_copies_expire_from_their_last_refresh() {
+        let text = "$TTL 60\n@ SOA ns hm 1 3600 60 86400 60\nwww A 192.0.2.1\n";
+        let saved = Zone::new("example.org", parse_zone(text, "example.org").unwrap()).unwrap();
+        let file = std::env::temp_dir().join(format!("saved-{}.zone", std::process::id()));
+        saved.save(&file).unwrap();
+        // nothing listens at the primary, the saved copy is all there is
+        let primary = SocketAddr::from(([127, 0, 0, 1], free_loopback_port()));
+        let mut zone = SecondaryZone::new("example.org", [primary]);
+        zone.file = Some(file.clone());
+        let origin = Name::from("example.org");
+        let saved_ago = |ago: u64| {
+            let when = std::time::SystemTime::now() - Duration::from_secs(ago);
+            let file = std::fs::File::options().write(true).open(&file).unwrap();
+            file.set_modified(when).unwrap();
+            let server = DnsServer::authoritative("127.0.0.1:0".parse().unwrap()).unwrap();
+            server.add_secondary(zone.clone());
+            let expire_at = server.secondaries.lock().unwrap()[&origin].expire_at;
+            (server.zone(&origin).is_some(), expire_at)
+        };
+
+        // refreshed half a day ago, with a day to expire: served for the half day left
+        let (served, expire_at) = saved_ago(43200);
+        assert!(served);
+        let left = expire_at.unwrap().saturating_duration_since(Instant::now());
+        assert!(left <= Duration::from_secs(43200) && left > Duration::from_secs(43000));
+
+        // two days ago it was already too old
+        assert_eq!(saved_ago(2 * 86400), (false, None));
+        std::fs::remove_file(&file).unwrap();
+    }
+
+    #[test]
+    fn test_secondary_zone() {
+        // the primary's zone is at serial 1, and goes to 2 before it sends a NOTIFY
+        let serial = Arc::new(std::sync::atomic::AtomicU32::new(1));
+        let zone_at = |serial: u32| {
+            let text = format!(
+                "$TTL 60\n@ SOA ns hm {} 3600 60 86400 60\nwww A 192.0.2.{}\n",
+                serial, serial
+            );
+            parse_zone(&text, "example.com").unwrap()
+        };
+        let primary = {
+            let serial = serial.clone();
+            mock_udp_server(2, move |query| {
+                let query = DnsMessage::from_bytes(query).unwrap();
+                assert_eq!(query.question().qtype, TYPE_SOA);
+                let mut res = DnsMessage::response_to(&query);
+                res.add_answer(zone_at(serial.load(Ordering::SeqCst)).remove(0));
+                res.to_bytes().unwrap()
+            })
+        };
+        let transfers = serial.clone();
+        mock_tcp_server_at(primary, 2, move |query| {
+            let query = DnsMessage::from_bytes(query).unwrap();
+            let mut res = DnsMessage::response_to(&query);
+            let records = zone_at(transfers.load(Ordering::SeqCst));
+            for rr in records.iter().chain(records.first()) {
+                res.add_answer(rr.clone());
+            }
+            res.to_bytes().unwrap()
+        });
+
+        let file = std::env::temp_dir().join(format!("secondary-{}.zone", std::process::id()));
+        let server = DnsServer::authoritative("127.0.0.1:0".parse().unwrap()).unwrap();
+        let mut zone = SecondaryZone::new("example.com", [primary]);
+        zone.file = Some(file.clone());
+        server.add_secondary(zone);
+        let addr = server.local_addr().unwrap();
+        thread::spawn(move || server.run());
+
+        let www = DnsMessage::query("www.example.com").build();
+        let serves = |last: u8| {
+            (0..50).any(|_| {
+                let res = ask_udp(addr, &www);
+                let found = res.ipv4_addrs() == [Ipv4Addr::new(192, 0, 2, last)];
+                if !found {
+                    thread::sleep(Duration::from_millis(100));
+                }
+                found
+            })
+        };
+        assert!(serves(1));
+        assert_eq!(Zone::load(&file, "example.com").unwrap().soa().serial, 1);
+
+        serial.store(2, Ordering::SeqCst);
+        let mut notify = DnsMessage::query("example.com")
+            .qtype(RecordType::SOA)
+            .build();
+        let mut flags = notify.flags();
+        flags.opcode = Opcode::Notify.into();
+        notify.set_flags(flags);
+        let res = ask_udp(addr, &notify);
+        assert_eq!(res.response_code(), ResponseCode::NoError);
+        assert_eq!(res.opcode(), Opcode::Notify);
+        assert!(res.flags().aa);
+        assert!(serves(2));
+        assert_eq!(Zone::load(&file, "example.com").unwrap().soa().serial, 2);
+        std::fs::remove_file(&file).unwrap();
+
+        notify.questions[0].qname = Name::from("example.net");
+        let res = ask_udp(addr, &notify);
+        assert_eq!(res.response_code(), ResponseCode::NotAuth);
+    }
+}
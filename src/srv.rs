@@ -0,0 +1,312 @@
+// SRV lookups (RFC 2782) for service discovery: ask for _service._proto.domain and get back which
+// hosts run the service, on which port, and in what order to try them. Servers usually send the
+// targets' addresses along in the additional section, we only go back and ask for the ones
+// they left out
+use std::net::{IpAddr, SocketAddr};
+
+use crate::random::random_u64;
+use crate::rdata::{TYPE_A, TYPE_AAAA};
+use crate::{DnsError, DnsMessage, Name, QueryOptions, RData, RecordType, Resolver};
+use crate::{ResourceRecord, SrvRecord, RCODE_NXDOMAIN};
+
+// every target of `service` with its addresses, lowest priority first and the heaviest first
+// among the same priority (always in that order, Resolver::lookup_srv makes the weighted draw).
+// A name without SRV records (or that doesn't exist) gives an empty list, and so does a single
+// "." target, which is how a domain says it doesn't offer the service
+pub fn resolve_srv(
+    service: &str,
+    server: SocketAddr,
+    opts: &QueryOptions,
+) -> Result<Vec<(SrvRecord, Vec<IpAddr>)>, DnsError> {
+    let mut resolver = Resolver::with_server(server);
+    resolver.set_options(opts.clone());
+    srv_targets(&resolver, service, |mut records| {
+        records.sort_by(|a, b| a.priority.cmp(&b.priority).then(b.weight.cmp(&a.weight)));
+        records
+    })
+}
+
+// see Resolver::lookup_srv. `service` and `proto` go in with or without their underscore
+pub(crate) fn lookup(
+    resolver: &Resolver,
+    service: &str,
+    proto: &str,
+    name: &str,
+) -> Result<Vec<(SrvRecord, Vec<IpAddr>)>, DnsError> {
+    let label = |label: &str| format!("_{}", label.trim_start_matches('_'));
+    let owner = format!("{}.{}.{}", label(service), label(proto), name);
+    srv_targets(resolver, &owner, |records| {
+        weighted_order(records, |total| (random_u64() % (total as u64 + 1)) as u32)
+    })
+}
+
+// the SRV records of `owner`, put in order by `order`, each with its target's addresses
+fn srv_targets(
+    resolver: &Resolver,
+    owner: &str,
+    order: impl FnOnce(Vec<SrvRecord>) -> Vec<SrvRecord>,
+) -> Result<Vec<(SrvRecord, Vec<IpAddr>)>, DnsError> {
+    let res = resolver.query(&DnsMessage::query(owner).qtype(RecordType::SRV).build())?;
+    match res.rcode() {
+        0 => {}
+        RCODE_NXDOMAIN => return Ok(Vec::new()),
+        _ => return Err(DnsError::from_response(&res)),
+    }
+
+    // the SRV records can sit behind a CNAME
+    let owner = res.canonical_name();
+    let records: Vec<SrvRecord> = res
+        .answers
+        .iter()
+        .filter(|rr| rr.name == owner)
+        .filter_map(|rr| match &rr.data {
+            RData::SRV(srv) => Some(srv.clone()),
+            _ => None,
+        })
+        .collect();
+    if let [only] = records.as_slice() {
+        if Name::from(only.target.as_str()).is_root() {
+            return Ok(Vec::new());
+        }
+    }
+
+    let mut targets = Vec::new();
+    for srv in order(records) {
+        let target = Name::from(srv.target.as_str());
+        let mut addrs = addresses(&res.additional, &target);
+        if addrs.is_empty() {
+            addrs = lookup_addrs(resolver, &target);
+        }
+        targets.push((srv, addrs));
+    }
+    Ok(targets)
+}
+
+// the selection RFC 2782 asks clients to make: lowest priority first and, among the same
+// priority, picked at random with odds in proportion to the weights. Weight 0 records go to the
+// front of their group before the draw, so they're only picked ahead of the others when the
+// draw comes up 0. `random(total)` gives a number in 0..=total
+fn weighted_order(
+    mut records: Vec<SrvRecord>,
+    mut random: impl FnMut(u32) -> u32,
+) -> Vec<SrvRecord> {
+    records.sort_by_key(|srv| (srv.priority, srv.weight != 0));
+    let mut ordered = Vec::with_capacity(records.len());
+    while !records.is_empty() {
+        let priority = records[0].priority;
+        let group = records
+            .iter()
+            .take_while(|srv| srv.priority == priority)
+            .count();
+        let mut group: Vec<SrvRecord> = records.drain(..group).collect();
+        while !group.is_empty() {
+            let total: u32 = group.iter().map(|srv| srv.weight as u32).sum();
+            let draw = random(total);
+            let mut sum = 0;
+            let picked = group
+                .iter()
+                .position(|srv| {
+                    sum += srv.weight as u32;
+                    sum >= draw
+                })
+                .unwrap_or(0);
+            ordered.push(group.remove(picked));
+        }
+    }
+    ordered
+}
+
+// the A and AAAA records for `name` among `records`
+pub(crate) fn addresses(records: &[ResourceRecord], name: &Name) -> Vec<IpAddr> {
+    records
+        .iter()
+        .filter(|rr| rr.name == *name)
+        .filter_map(|rr| match rr.data {
+            RData::A(ip) => Some(IpAddr::V4(ip)),
+            RData::AAAA(ip) => Some(IpAddr::V6(ip)),
+            _ => None,
+        })
+        .collect()
+}
+
+// a target that didn't come with glue. One that fails to resolve just ends up without addresses
+// instead of failing the whole lookup, the other targets are still worth having
+pub(crate) fn lookup_addrs(resolver: &Resolver, target: &Name) -> Vec<IpAddr> {
+    let mut addrs = Vec::new();
+    for qtype in [TYPE_A, TYPE_AAAA] {
+        let query = DnsMessage::query(target.clone()).qtype(qtype).build();
+        if let Ok(res) = resolver.query(&query) {
+            addrs.extend(res.ipv4_addrs().into_iter().map(IpAddr::V4));
+            addrs.extend(res.ipv6_addrs().into_iter().map(IpAddr::V6));
+        }
+    }
+    addrs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rdata::TYPE_SRV;
+    use crate::test_util::mock_udp_server;
+
+    fn srv(priority: u16, weight: u16, port: u16, target: &str) -> SrvRecord {
+        SrvRecord {
+            priority,
+            weight,
+            port,
+            target: target.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_resolve_srv_uses_glue_and_sorts() {
+        // three targets: two with glue in the additional section, backup.example.com without,
+        // which costs an A and an AAAA query of its own
+        let server = mock_udp_server(3, |query| {
+            let query = DnsMessage::from_bytes(query).unwrap();
+            let mut res = DnsMessage::response_to(&query);
+            let qname = query.question().qname.clone();
+            match query.question().qtype {
+                TYPE_SRV => {
+                    for record in [
+                        srv(20, 0, 5060, "backup.example.com"),
+                        srv(10, 10, 5060, "light.example.com"),
+                        srv(10, 60, 5061, "heavy.example.com"),
+                    ] {
+                        res.add_answer(ResourceRecord::new(qname.clone(), 300, RData::SRV(record)));
+                    }
+                    res.add_additional(ResourceRecord::new(
+                        "heavy.example.com",
+                        300,
+                        RData::A([192, 0, 2, 1].into()),
+                    ));
+                    res.add_additional(ResourceRecord::new(
+                        "heavy.example.com",
+                        300,
+                        RData::AAAA("2001:db8::1".parse().unwrap()),
+                    ));
+                    res.add_additional(ResourceRecord::new(
+                        "light.example.com",
+                        300,
+                        RData::A([192, 0, 2, 2].into()),
+                    ));
+                }
+                TYPE_A => {
+                    assert_eq!(qname, "backup.example.com");
+                    res.add_answer(ResourceRecord::new(
+                        qname,
+                        300,
+                        RData::A([192, 0, 2, 3].into()),
+                    ));
+                }
+                // no AAAA for the backup
+                _ => {}
+            }
+            res.to_bytes().unwrap()
+        });
+
+        let targets =
+            resolve_srv("_sip._udp.example.com", server, &QueryOptions::default()).unwrap();
+        let ip = |s: &str| s.parse::<IpAddr>().unwrap();
+        assert_eq!(
+            targets,
+            vec![
+                (
+                    srv(10, 60, 5061, "heavy.example.com"),
+                    vec![ip("192.0.2.1"), ip("2001:db8::1")]
+                ),
+                (
+                    srv(10, 10, 5060, "light.example.com"),
+                    vec![ip("192.0.2.2")]
+                ),
+                (
+                    srv(20, 0, 5060, "backup.example.com"),
+                    vec![ip("192.0.2.3")]
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_weighted_order() {
+        let records = vec![
+            srv(20, 0, 1, "backup"),
+            srv(10, 30, 2, "c"),
+            srv(10, 0, 3, "zero"),
+            srv(10, 70, 4, "d"),
+        ];
+        let targets = |ordered: Vec<SrvRecord>| {
+            ordered
+                .into_iter()
+                .map(|srv| srv.target)
+                .collect::<Vec<_>>()
+        };
+        // a draw of 0 always lands on the weight 0 record first, then on the first one left
+        assert_eq!(
+            targets(weighted_order(records.clone(), |_| 0)),
+            ["zero", "c", "d", "backup"]
+        );
+        // the top of the range lands on the last one
+        assert_eq!(
+            targets(weighted_order(records.clone(), |total| total)),
+            ["d", "c", "zero", "backup"]
+        );
+        // 31..=100 of 0..=100 goes to the weight 70 record
+        assert_eq!(
+            targets(weighted_order(records, |total| total.min(31))),
+            ["d", "c", "zero", "backup"]
+        );
+    }
+
+    #[test]
+    fn test_lookup_srv() {
+        let server = mock_udp_server(1, |query| {
+            let query = DnsMessage::from_bytes(query).unwrap();
+            assert_eq!(query.question().qname, "_ldap._tcp.example.com");
+            let mut res = DnsMessage::response_to(&query);
+            for (weight, last) in [(1, 1), (3, 2)] {
+                let target = format!("dc{}.example.com", last);
+                res.add_answer(ResourceRecord::new(
+                    query.question().qname.clone(),
+                    300,
+                    RData::SRV(srv(0, weight, 389, &target)),
+                ));
+                res.add_additional(ResourceRecord::new(
+                    target.as_str(),
+                    300,
+                    RData::A([192, 0, 2, last].into()),
+                ));
+            }
+            res.to_bytes().unwrap()
+        });
+        let mut resolver = Resolver::with_server(server);
+        resolver.set_cache(std::sync::Arc::new(crate::DnsCache::new()));
+
+        // the same answer every time (it's cached), but the draw picks either one first
+        let mut first = std::collections::HashSet::new();
+        for _ in 0..64 {
+            let targets = resolver.lookup_srv("ldap", "_tcp", "example.com").unwrap();
+            assert_eq!(targets.len(), 2);
+            assert_eq!(targets[0].1.len(), 1);
+            first.insert(targets[0].0.target.clone());
+        }
+        assert_eq!(first.len(), 2);
+    }
+
+    #[test]
+    fn test_resolve_srv_service_not_offered() {
+        let server = mock_udp_server(1, |query| {
+            let query = DnsMessage::from_bytes(query).unwrap();
+            let mut res = DnsMessage::response_to(&query);
+            res.add_answer(ResourceRecord::new(
+                query.question().qname.clone(),
+                300,
+                RData::SRV(srv(0, 0, 0, "")),
+            ));
+            res.to_bytes().unwrap()
+        });
+        let targets =
+            resolve_srv("_imap._tcp.example.com", server, &QueryOptions::default()).unwrap();
+        assert!(targets.is_empty());
+    }
+}
@@ -0,0 +1,66 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+// a snapshot of what a Resolver has been up to, handy for exporting to a metrics system or just
+// printing at shutdown
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Stats {
+    pub queries_sent: u64,
+    pub responses_received: u64,
+    pub timeouts: u64,
+    // extra attempts after the first one for the same query
+    pub retries: u64,
+    // queries answered from the cache given to set_cache, and the ones that had to go upstream.
+    // Both stay at 0 without a cache
+    pub cache_hits: u64,
+    pub cache_misses: u64,
+    pub nxdomains: u64,
+    // responses nobody was waiting for: wrong ID, wrong server, or a late duplicate of an answer
+    // we already took. A steady stream of these smells like spoofing or a broken server
+    pub stray_responses: u64,
+    // non-authoritative answers to queries sent with RD off: the server recursed for us when we
+    // asked it not to, so it's a poor choice to iterate against
+    pub unrequested_recursion: u64,
+    // queries the blocklist answered
+    pub blocked: u64,
+    // writes to the packet capture that failed; the capture stops at the first one
+    pub capture_errors: u64,
+}
+
+// the live counters behind Stats. Atomics so they can be bumped through a shared &Resolver
+#[derive(Debug, Default)]
+pub(crate) struct Counters {
+    pub queries_sent: AtomicU64,
+    pub responses_received: AtomicU64,
+    pub timeouts: AtomicU64,
+    pub retries: AtomicU64,
+    pub cache_hits: AtomicU64,
+    pub cache_misses: AtomicU64,
+    pub nxdomains: AtomicU64,
+    pub stray_responses: AtomicU64,
+    pub unrequested_recursion: AtomicU64,
+    pub blocked: AtomicU64,
+    pub capture_errors: AtomicU64,
+}
+
+impl Counters {
+    pub fn bump(counter: &AtomicU64) {
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn snapshot(&self) -> Stats {
+        let load = |counter: &AtomicU64| counter.load(Ordering::Relaxed);
+        Stats {
+            queries_sent: load(&self.queries_sent),
+            responses_received: load(&self.responses_received),
+            timeouts: load(&self.timeouts),
+            retries: load(&self.retries),
+            cache_hits: load(&self.cache_hits),
+            cache_misses: load(&self.cache_misses),
+            nxdomains: load(&self.nxdomains),
+            stray_responses: load(&self.stray_responses),
+            unrequested_recursion: load(&self.unrequested_recursion),
+            blocked: load(&self.blocked),
+            capture_errors: load(&self.capture_errors),
+        }
+    }
+}
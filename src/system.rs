@@ -0,0 +1,291 @@
+// the resolver configuration of the machine we run on, so Resolver::from_system asks the same
+// servers everything else on it does. On Unix that's /etc/resolv.conf, read the way glibc reads
+// it: unknown lines and options are skipped rather than refused, and the limits it puts on the
+// numbers apply here too. Windows keeps it per network adapter instead, we ask
+// GetAdaptersAddresses for the servers and suffixes of every adapter that's up
+use std::fs;
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::path::Path;
+use std::time::Duration;
+
+use crate::Name;
+
+pub const RESOLV_CONF: &str = "/etc/resolv.conf";
+
+// glibc only ever uses the first three nameservers
+const MAX_NAMESERVERS: usize = 3;
+// and its caps on the options
+const MAX_NDOTS: usize = 15;
+const MAX_TIMEOUT: u64 = 30;
+const MAX_ATTEMPTS: usize = 5;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SystemConfig {
+    pub nameservers: Vec<SocketAddr>,
+    // domains tried after (or before, see ndots) a name that isn't fully qualified
+    pub search: Vec<Name>,
+    // a name with at least this many dots is tried as is before the search list
+    pub ndots: usize,
+    // per server, per attempt
+    pub timeout: Duration,
+    // how many times the whole list of servers is gone through
+    pub attempts: usize,
+    // spread the queries over the servers instead of always starting at the first
+    pub rotate: bool,
+}
+
+// what resolv.conf(5) says you get from an empty file: the local server, a 5 second timeout and
+// two attempts
+impl Default for SystemConfig {
+    fn default() -> Self {
+        SystemConfig {
+            nameservers: Vec::new(),
+            search: Vec::new(),
+            ndots: 1,
+            timeout: Duration::from_secs(5),
+            attempts: 2,
+            rotate: false,
+        }
+    }
+}
+
+impl SystemConfig {
+    pub fn from_resolv_conf(path: impl AsRef<Path>) -> io::Result<Self> {
+        Ok(Self::parse_resolv_conf(&fs::read_to_string(path)?))
+    }
+
+    pub fn parse_resolv_conf(text: &str) -> Self {
+        let mut config = SystemConfig::default();
+        for line in text.lines() {
+            let mut words = line.split_whitespace();
+            let Some(keyword) = words.next() else {
+                continue;
+            };
+            match keyword {
+                "nameserver" => {
+                    if let Some(addr) = words.next().and_then(nameserver_addr) {
+                        if config.nameservers.len() < MAX_NAMESERVERS {
+                            config.nameservers.push(addr);
+                        }
+                    }
+                }
+                // `domain` and `search` both set the search list, whichever comes last wins
+                "domain" => config.search = words.next().map(Name::from).into_iter().collect(),
+                "search" => config.search = words.map(Name::from).collect(),
+                "options" => {
+                    for option in words {
+                        config.set_option(option);
+                    }
+                }
+                // comments (# or ;) and whatever we don't know
+                _ => {}
+            }
+        }
+        if config.nameservers.is_empty() {
+            config
+                .nameservers
+                .push(SocketAddr::from(([127, 0, 0, 1], 53)));
+        }
+        config
+    }
+
+    fn set_option(&mut self, option: &str) {
+        let (name, value) = match option.split_once(':') {
+            Some((name, value)) => (name, value.parse::<u64>().ok()),
+            None => (option, None),
+        };
+        match (name, value) {
+            ("ndots", Some(n)) => self.ndots = (n as usize).min(MAX_NDOTS),
+            ("timeout", Some(secs)) => {
+                self.timeout = Duration::from_secs(secs.clamp(1, MAX_TIMEOUT))
+            }
+            ("attempts", Some(n)) => self.attempts = (n as usize).clamp(1, MAX_ATTEMPTS),
+            ("rotate", _) => self.rotate = true,
+            _ => {}
+        }
+    }
+}
+
+#[cfg(windows)]
+impl SystemConfig {
+    pub fn from_adapters() -> io::Result<Self> {
+        use windows_sys::Win32::Foundation::{ERROR_BUFFER_OVERFLOW, NO_ERROR};
+        use windows_sys::Win32::NetworkManagement::IpHelper::{
+            GetAdaptersAddresses, GAA_FLAG_SKIP_ANYCAST, GAA_FLAG_SKIP_MULTICAST,
+            GAA_FLAG_SKIP_UNICAST, IP_ADAPTER_ADDRESSES_LH,
+        };
+        use windows_sys::Win32::NetworkManagement::Ndis::IfOperStatusUp;
+        use windows_sys::Win32::Networking::WinSock::AF_UNSPEC;
+
+        let flags = GAA_FLAG_SKIP_UNICAST | GAA_FLAG_SKIP_ANYCAST | GAA_FLAG_SKIP_MULTICAST;
+        // 15K is the size Microsoft says to start with. The call tells us how much it really
+        // needs when that's not enough, and an adapter can appear in between, hence the loop.
+        // u64s to get the list the 8 byte alignment its pointers want
+        let mut len: u32 = 15 * 1024;
+        let mut buf: Vec<u64>;
+        loop {
+            buf = vec![0; (len as usize).div_ceil(8)];
+            let ret = unsafe {
+                GetAdaptersAddresses(
+                    AF_UNSPEC as u32,
+                    flags,
+                    std::ptr::null(),
+                    buf.as_mut_ptr().cast(),
+                    &mut len,
+                )
+            };
+            match ret {
+                NO_ERROR => break,
+                ERROR_BUFFER_OVERFLOW => continue,
+                err => return Err(io::Error::from_raw_os_error(err as i32)),
+            }
+        }
+
+        let mut config = SystemConfig::default();
+        let mut adapter = buf.as_ptr() as *const IP_ADAPTER_ADDRESSES_LH;
+        // the list and everything it points to live in `buf`
+        while let Some(current) = unsafe { adapter.as_ref() } {
+            adapter = current.Next;
+            if current.OperStatus != IfOperStatusUp {
+                continue;
+            }
+            let mut server = current.FirstDnsServerAddress;
+            while let Some(entry) = unsafe { server.as_ref() } {
+                server = entry.Next;
+                let Some(addr) = (unsafe { windows::socket_addr(entry.Address.lpSockaddr) }) else {
+                    continue;
+                };
+                if !windows::is_placeholder(&addr) && !config.nameservers.contains(&addr) {
+                    config.nameservers.push(addr);
+                }
+            }
+
+            // the adapter's connection-specific suffix, then any extra ones set on it
+            let mut suffixes = vec![unsafe { windows::wide_string(current.DnsSuffix) }];
+            let mut extra = current.FirstDnsSuffix;
+            while let Some(entry) = unsafe { extra.as_ref() } {
+                extra = entry.Next;
+                let len = entry.String.iter().position(|&c| c == 0).unwrap_or(256);
+                suffixes.push(String::from_utf16_lossy(&entry.String[..len]));
+            }
+            for suffix in suffixes.into_iter().filter(|suffix| !suffix.is_empty()) {
+                let suffix = Name::from(suffix);
+                if !config.search.contains(&suffix) {
+                    config.search.push(suffix);
+                }
+            }
+        }
+        if config.nameservers.is_empty() {
+            config
+                .nameservers
+                .push(SocketAddr::from(([127, 0, 0, 1], 53)));
+        }
+        Ok(config)
+    }
+}
+
+#[cfg(windows)]
+mod windows {
+    use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV6};
+
+    use windows_sys::Win32::Networking::WinSock::{
+        AF_INET, AF_INET6, SOCKADDR, SOCKADDR_IN, SOCKADDR_IN6,
+    };
+
+    // the server address from one of the adapter's entries, on port 53 whatever it says
+    pub(super) unsafe fn socket_addr(addr: *const SOCKADDR) -> Option<SocketAddr> {
+        match addr.as_ref()?.sa_family {
+            AF_INET => {
+                let addr = &*(addr as *const SOCKADDR_IN);
+                let ip = Ipv4Addr::from(u32::from_be(addr.sin_addr.S_un.S_addr));
+                Some(SocketAddr::from((ip, 53)))
+            }
+            AF_INET6 => {
+                let addr = &*(addr as *const SOCKADDR_IN6);
+                let ip = Ipv6Addr::from(addr.sin6_addr.u.Byte);
+                let scope = addr.Anonymous.sin6_scope_id;
+                Some(SocketAddr::V6(SocketAddrV6::new(ip, 53, 0, scope)))
+            }
+            _ => None,
+        }
+    }
+
+    // an adapter with no IPv6 DNS servers configured still lists fec0:0:0:ffff::1 to ::3, the
+    // long dead site-local defaults. Nothing answers there
+    pub(super) fn is_placeholder(addr: &SocketAddr) -> bool {
+        match addr.ip() {
+            std::net::IpAddr::V6(ip) => {
+                let [a, b, c, d, ..] = ip.segments();
+                (a, b, c, d) == (0xFEC0, 0, 0, 0xFFFF)
+            }
+            _ => false,
+        }
+    }
+
+    // a NUL-terminated UTF-16 string, "" for a null pointer
+    pub(super) unsafe fn wide_string(mut ptr: *const u16) -> String {
+        let mut chars = Vec::new();
+        while let Some(&c) = ptr.as_ref() {
+            if c == 0 {
+                break;
+            }
+            chars.push(c);
+            ptr = ptr.add(1);
+        }
+        String::from_utf16_lossy(&chars)
+    }
+}
+
+// an address, on port 53. Link-local IPv6 servers come with a %scope, which has to be the
+// interface's number here, we have no way of looking up a name like eth0
+fn nameserver_addr(text: &str) -> Option<SocketAddr> {
+    if let Ok(ip) = text.parse::<IpAddr>() {
+        return Some(SocketAddr::new(ip, 53));
+    }
+    format!("[{}]:53", text).parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_resolv_conf() {
+        let config = SystemConfig::parse_resolv_conf(
+            "# generated by NetworkManager\n\
+             domain corp.example\n\
+             search corp.example lab.example\n\
+             nameserver 192.0.2.53\n\
+             nameserver 2001:db8::53\n\
+             nameserver fe80::1%2\n\
+             nameserver 192.0.2.54\n\
+             ; the fourth one is never used\n\
+             options ndots:2 timeout:3 attempts:9 rotate edns0\n",
+        );
+        assert_eq!(
+            config,
+            SystemConfig {
+                nameservers: vec![
+                    "192.0.2.53:53".parse().unwrap(),
+                    "[2001:db8::53]:53".parse().unwrap(),
+                    "[fe80::1%2]:53".parse().unwrap(),
+                ],
+                search: vec![Name::from("corp.example"), Name::from("lab.example")],
+                ndots: 2,
+                timeout: Duration::from_secs(3),
+                attempts: 5,
+                rotate: true,
+            }
+        );
+    }
+
+    #[test]
+    fn test_empty_resolv_conf() {
+        let config = SystemConfig::parse_resolv_conf("nameserver not-an-address\n");
+        assert_eq!(config.nameservers, vec!["127.0.0.1:53".parse().unwrap()]);
+        assert_eq!(config.ndots, 1);
+        assert_eq!(config.attempts, 2);
+        assert!(config.search.is_empty());
+    }
+}
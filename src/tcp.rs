@@ -0,0 +1,261 @@
+// DNS over TCP (RFC 1035 4.2.2, RFC 7766): the same messages as over UDP, each one prefixed with
+// its length as a big-endian u16. Used when a response is too big for UDP, and for anything
+// that takes more than one message, like zone transfers (axfr.rs) or a client sending several
+// queries down one connection
+use std::io::{self, Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::time::Duration;
+
+#[cfg(feature = "tokio")]
+use bytes::{Buf, BufMut, BytesMut};
+#[cfg(feature = "tokio")]
+use tokio_util::codec::{Decoder, Encoder};
+
+// what DnsFramed reads at a time. Most messages fit, and a bigger one gets the room it needs
+const READ_SIZE: usize = 4096;
+
+pub(crate) fn connect(server: SocketAddr, timeout: Duration) -> io::Result<TcpStream> {
+    let stream = TcpStream::connect_timeout(&server, timeout)?;
+    stream.set_read_timeout(Some(timeout))?;
+    stream.set_write_timeout(Some(timeout))?;
+    stream.set_nodelay(true)?;
+    Ok(stream)
+}
+
+pub(crate) fn write_message(stream: &mut impl Write, msg: &[u8]) -> io::Result<()> {
+    // one write for prefix and message, some servers don't like getting them in two segments
+    let mut framed = Vec::with_capacity(msg.len() + 2);
+    framed.extend(prefix(msg)?);
+    framed.extend(msg);
+    stream.write_all(&framed)
+}
+
+fn prefix(msg: &[u8]) -> io::Result<[u8; 2]> {
+    u16::try_from(msg.len())
+        .map(u16::to_be_bytes)
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "DNS message over 64K"))
+}
+
+// the length of the message framed at the front of `buf`, once all of it is there
+fn complete_frame(buf: &[u8]) -> Option<usize> {
+    let len = u16::from_be_bytes([*buf.first()?, *buf.get(1)?]) as usize;
+    (buf.len() >= len + 2).then_some(len)
+}
+
+// reads one framed message into the front of `buf` (growing it if needed) and returns its length
+pub(crate) fn read_message(stream: &mut impl Read, buf: &mut Vec<u8>) -> io::Result<usize> {
+    let mut len = [0u8; 2];
+    stream.read_exact(&mut len)?;
+    let len = u16::from_be_bytes(len) as usize;
+    if buf.len() < len {
+        buf.resize(len, 0);
+    }
+    stream.read_exact(&mut buf[..len])?;
+    Ok(len)
+}
+
+// a connection carrying DNS over TCP, one message after another in both directions. Reads are
+// buffered: a read can end halfway through a message, or take in the next few with it, and
+// read_message hands them back one at a time either way
+pub struct DnsFramed<S> {
+    stream: S,
+    buf: Vec<u8>,
+    // the bytes read but not handed out yet
+    start: usize,
+    end: usize,
+}
+
+impl<S> DnsFramed<S> {
+    pub fn new(stream: S) -> Self {
+        DnsFramed {
+            stream,
+            buf: vec![0; READ_SIZE],
+            start: 0,
+            end: 0,
+        }
+    }
+
+    pub fn get_ref(&self) -> &S {
+        &self.stream
+    }
+
+    pub fn get_mut(&mut self) -> &mut S {
+        &mut self.stream
+    }
+
+    // the stream back. Whatever was read past the last message handed out is lost
+    pub fn into_inner(self) -> S {
+        self.stream
+    }
+}
+
+impl<S: Read> DnsFramed<S> {
+    // the next message, without its length. None when the other end closed the connection
+    // between two messages, and UnexpectedEof when it did in the middle of one
+    pub fn read_message(&mut self) -> io::Result<Option<&[u8]>> {
+        loop {
+            if let Some(len) = complete_frame(&self.buf[self.start..self.end]) {
+                let msg = self.start + 2..self.start + 2 + len;
+                self.start = msg.end;
+                return Ok(Some(&self.buf[msg]));
+            }
+            // what's left of the buffer is the start of the next message: move it to the front,
+            // and make room for the rest of it
+            self.buf.copy_within(self.start..self.end, 0);
+            self.end -= self.start;
+            self.start = 0;
+            if self.end >= 2 {
+                let len = u16::from_be_bytes([self.buf[0], self.buf[1]]) as usize;
+                if self.buf.len() < len + 2 {
+                    self.buf.resize(len + 2, 0);
+                }
+            }
+            match self.stream.read(&mut self.buf[self.end..]) {
+                Ok(0) if self.end == 0 => return Ok(None),
+                Ok(0) => return Err(io::ErrorKind::UnexpectedEof.into()),
+                Ok(n) => self.end += n,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
+impl<S: Write> DnsFramed<S> {
+    pub fn write_message(&mut self, msg: &[u8]) -> io::Result<()> {
+        write_message(&mut self.stream, msg)
+    }
+}
+
+// the same framing for tokio: FramedRead / FramedWrite / Framed with this codec turn an
+// AsyncRead or AsyncWrite into a Stream of messages or a Sink for them. Messages are raw bytes
+// in both directions, parse them with DnsMessage::from_bytes (or DnsMessageRef::parse)
+#[cfg(feature = "tokio")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DnsCodec;
+
+#[cfg(feature = "tokio")]
+impl Decoder for DnsCodec {
+    type Item = BytesMut;
+    type Error = io::Error;
+
+    fn decode(&mut self, src: &mut BytesMut) -> io::Result<Option<BytesMut>> {
+        match complete_frame(src) {
+            Some(len) => {
+                src.advance(2);
+                Ok(Some(src.split_to(len)))
+            }
+            None => {
+                if src.len() >= 2 {
+                    let len = u16::from_be_bytes([src[0], src[1]]) as usize;
+                    src.reserve(len + 2 - src.len());
+                }
+                Ok(None)
+            }
+        }
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl Encoder<&[u8]> for DnsCodec {
+    type Error = io::Error;
+
+    fn encode(&mut self, msg: &[u8], dst: &mut BytesMut) -> io::Result<()> {
+        let len = prefix(msg)?;
+        dst.reserve(msg.len() + 2);
+        dst.put_slice(&len);
+        dst.put_slice(msg);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_framing_round_trip() {
+        let mut wire = Vec::new();
+        write_message(&mut wire, b"first").unwrap();
+        write_message(&mut wire, b"second message").unwrap();
+        assert_eq!(&wire[0..2], &[0, 5]);
+
+        let mut reader = &wire[..];
+        let mut buf = Vec::new();
+        let len = read_message(&mut reader, &mut buf).unwrap();
+        assert_eq!(&buf[..len], b"first");
+        let len = read_message(&mut reader, &mut buf).unwrap();
+        assert_eq!(&buf[..len], b"second message");
+        assert!(read_message(&mut reader, &mut buf).is_err());
+    }
+
+    // a reader that gives out at most `chunk` bytes a read
+    struct Trickle<'a> {
+        data: &'a [u8],
+        chunk: usize,
+    }
+
+    impl Read for Trickle<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let n = self.chunk.min(buf.len()).min(self.data.len());
+            buf[..n].copy_from_slice(&self.data[..n]);
+            self.data = &self.data[n..];
+            Ok(n)
+        }
+    }
+
+    #[test]
+    fn test_framed_reads() {
+        let big = vec![7; 5000];
+        let mut wire = Vec::new();
+        let mut framed = DnsFramed::new(&mut wire);
+        framed.write_message(b"first").unwrap();
+        framed.write_message(&big).unwrap();
+        framed.write_message(b"").unwrap();
+        framed.write_message(b"last").unwrap();
+
+        // a byte at a time, and everything in one read
+        for chunk in [1, wire.len()] {
+            let mut framed = DnsFramed::new(Trickle { data: &wire, chunk });
+            assert_eq!(framed.read_message().unwrap(), Some(&b"first"[..]));
+            assert_eq!(framed.read_message().unwrap(), Some(&big[..]));
+            assert_eq!(framed.read_message().unwrap(), Some(&b""[..]));
+            assert_eq!(framed.read_message().unwrap(), Some(&b"last"[..]));
+            assert_eq!(framed.read_message().unwrap(), None);
+        }
+
+        let cut = &wire[..wire.len() - 1];
+        let mut framed = DnsFramed::new(cut);
+        for _ in 0..3 {
+            framed.read_message().unwrap();
+        }
+        let err = framed.read_message().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+    }
+
+    #[cfg(feature = "tokio")]
+    #[test]
+    fn test_codec() {
+        let mut codec = DnsCodec;
+        let mut wire = BytesMut::new();
+        codec.encode(&b"first"[..], &mut wire).unwrap();
+        codec.encode(&b"second"[..], &mut wire).unwrap();
+        assert!(codec.encode(&vec![0; 65536][..], &mut wire).is_err());
+
+        let mut src = BytesMut::new();
+        let mut messages = Vec::new();
+        for &byte in wire.iter() {
+            src.put_u8(byte);
+            if let Some(msg) = codec.decode(&mut src).unwrap() {
+                messages.push(msg);
+            }
+        }
+        assert_eq!(messages, [&b"first"[..], &b"second"[..]]);
+        assert!(src.is_empty());
+
+        // the decoder asks for room for the whole message once it knows the length
+        let mut src = BytesMut::from(&[0x10, 0x00, 1][..]);
+        assert!(codec.decode(&mut src).unwrap().is_none());
+        assert!(src.capacity() >= 0x1002);
+    }
+}
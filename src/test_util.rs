@@ -0,0 +1,205 @@
+// helpers shared by the tests: a tiny mock DNS server and hand-rolled response packets
+use std::net::{SocketAddr, TcpListener, UdpSocket};
+use std::thread;
+use std::time::Duration;
+
+use crate::{tcp, DnsMessage};
+
+// answers `count` queries on a fresh localhost port using `handler` to build each reply
+pub fn mock_udp_server<F>(count: usize, handler: F) -> SocketAddr
+where
+    F: Fn(&[u8]) -> Vec<u8> + Send + 'static,
+{
+    mock_udp_server_replies(count, move |query| vec![handler(query)])
+}
+
+// same as mock_udp_server but every query can get any number of datagrams back (none, extra
+// stray ones, duplicates...)
+pub fn mock_udp_server_replies<F>(count: usize, handler: F) -> SocketAddr
+where
+    F: Fn(&[u8]) -> Vec<Vec<u8>> + Send + 'static,
+{
+    mock_udp_server_replies_at("127.0.0.1:0".parse().unwrap(), count, handler)
+}
+
+// the same on a given address. A whole mock hierarchy (root, TLD, zone) can share one port on
+// different loopback addresses, see free_loopback_port
+pub fn mock_udp_server_replies_at<F>(addr: SocketAddr, count: usize, handler: F) -> SocketAddr
+where
+    F: Fn(&[u8]) -> Vec<Vec<u8>> + Send + 'static,
+{
+    let socket = UdpSocket::bind(addr).unwrap();
+    let addr = socket.local_addr().unwrap();
+    thread::spawn(move || {
+        let mut buf = [0u8; 65535];
+        for _ in 0..count {
+            let (size, peer) = match socket.recv_from(&mut buf) {
+                Ok(received) => received,
+                Err(_) => return,
+            };
+            for reply in handler(&buf[..size]) {
+                socket.send_to(&reply, peer).unwrap();
+            }
+        }
+    });
+    addr
+}
+
+// like mock_udp_server, except that the answers to queries for `slow` are held back for `delay`,
+// without holding up the answers to anything else
+pub fn mock_stalling_udp_server<F>(
+    count: usize,
+    slow: &str,
+    delay: Duration,
+    handler: F,
+) -> SocketAddr
+where
+    F: Fn(&[u8]) -> Vec<u8> + Send + 'static,
+{
+    let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let addr = socket.local_addr().unwrap();
+    let slow = slow.to_string();
+    thread::spawn(move || {
+        let mut buf = [0u8; 65535];
+        for _ in 0..count {
+            let (size, peer) = socket.recv_from(&mut buf).unwrap();
+            let query = DnsMessage::from_bytes(&buf[..size]).unwrap();
+            let wait = query.question().qname.as_str() == slow;
+            let (reply, socket) = (handler(&buf[..size]), socket.try_clone().unwrap());
+            thread::spawn(move || {
+                if wait {
+                    thread::sleep(delay);
+                }
+                socket.send_to(&reply, peer).unwrap();
+            });
+        }
+    });
+    addr
+}
+
+// a response to `query` (same ID and question) carrying a single answer of the given type,
+// whose name is a compression pointer back to the question
+pub fn response_with_rdata(query: &[u8], rr_type: u16, rdata: &[u8]) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    bytes.extend(&query[0..2]); // same ID
+    bytes.extend(&0x8180u16.to_be_bytes()); // QR, RD, RA
+    bytes.extend(&1u16.to_be_bytes()); // 1 question
+    bytes.extend(&1u16.to_be_bytes()); // 1 answer
+    bytes.extend(&0u16.to_be_bytes());
+    bytes.extend(&0u16.to_be_bytes());
+    bytes.extend(question(query)); // question copied as is
+
+    bytes.extend(&[0xC0, 0x0C]); // pointer to the qname at offset 12
+    bytes.extend(&rr_type.to_be_bytes());
+    bytes.extend(&1u16.to_be_bytes()); // IN
+    bytes.extend(&300u32.to_be_bytes()); // TTL
+    bytes.extend(&(rdata.len() as u16).to_be_bytes());
+    bytes.extend(rdata);
+    bytes
+}
+
+// the same packet with a different transaction ID
+pub fn with_id(mut packet: Vec<u8>, id: u16) -> Vec<u8> {
+    packet[0..2].copy_from_slice(&id.to_be_bytes());
+    packet
+}
+
+// the raw question section of a query (just the one question, no EDNS record after it)
+pub fn question(query: &[u8]) -> &[u8] {
+    let mut end = 12;
+    while query[end] != 0 {
+        end += query[end] as usize + 1;
+    }
+    &query[12..end + 5]
+}
+
+// a TCP server on the same address as a UDP mock (servers take both on port 53), answering
+// `count` connections with one length-prefixed reply each
+pub fn mock_tcp_server_at<F>(addr: SocketAddr, count: usize, handler: F)
+where
+    F: Fn(&[u8]) -> Vec<u8> + Send + 'static,
+{
+    let listener = TcpListener::bind(addr).unwrap();
+    thread::spawn(move || {
+        for stream in listener.incoming().take(count) {
+            let mut stream = stream.unwrap();
+            let mut buf = Vec::new();
+            let len = tcp::read_message(&mut stream, &mut buf).unwrap();
+            tcp::write_message(&mut stream, &handler(&buf[..len])).unwrap();
+        }
+    });
+}
+
+// a TCP server on a fresh localhost port for a single connection, answering its one query with
+// any number of length-prefixed messages (a zone transfer streams several) and then hanging up
+pub fn mock_tcp_server<F>(handler: F) -> SocketAddr
+where
+    F: Fn(&[u8]) -> Vec<Vec<u8>> + Send + 'static,
+{
+    mock_tcp_server_for(1, handler)
+}
+
+// the same for `count` connections one after the other
+pub fn mock_tcp_server_for<F>(count: usize, handler: F) -> SocketAddr
+where
+    F: Fn(&[u8]) -> Vec<Vec<u8>> + Send + 'static,
+{
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    thread::spawn(move || {
+        for _ in 0..count {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut buf = Vec::new();
+            let len = tcp::read_message(&mut stream, &mut buf).unwrap();
+            for reply in handler(&buf[..len]) {
+                tcp::write_message(&mut stream, &reply).unwrap();
+            }
+        }
+    });
+    addr
+}
+
+// a port that was free on 127.0.0.1 a moment ago, for servers on 127.0.0.2, 127.0.0.3... that
+// all have to listen on the same one
+pub fn free_loopback_port() -> u16 {
+    UdpSocket::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port()
+}
+
+// a CA and a certificate for "dns.test" signed by it, made with openssl for the TLS tests
+#[cfg(feature = "tls")]
+const TEST_CA: &[u8] = include_bytes!("testdata/ca.der");
+#[cfg(feature = "tls")]
+const TEST_CERT: &[u8] = include_bytes!("testdata/server.der");
+#[cfg(feature = "tls")]
+const TEST_KEY: &[u8] = include_bytes!("testdata/server.key.der");
+
+// what a mock TLS server needs to pass as "dns.test"
+#[cfg(feature = "tls")]
+pub fn test_tls_config() -> std::sync::Arc<rustls::ServerConfig> {
+    use rustls::pki_types::{CertificateDer, PrivateKeyDer, PrivatePkcs8KeyDer};
+    let provider = std::sync::Arc::new(rustls::crypto::ring::default_provider());
+    let config = rustls::ServerConfig::builder_with_provider(provider)
+        .with_safe_default_protocol_versions()
+        .unwrap()
+        .with_no_client_auth()
+        .with_single_cert(
+            vec![CertificateDer::from(TEST_CERT.to_vec())],
+            PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(TEST_KEY.to_vec())),
+        )
+        .unwrap();
+    std::sync::Arc::new(config)
+}
+
+// the roots a client needs to trust test_tls_config's certificate
+#[cfg(feature = "tls")]
+pub fn test_roots() -> rustls::RootCertStore {
+    let mut roots = rustls::RootCertStore::empty();
+    roots
+        .add(rustls::pki_types::CertificateDer::from(TEST_CA))
+        .unwrap();
+    roots
+}
@@ -0,0 +1,236 @@
+// DNS over TLS (RFC 7858): the TCP framing, length prefix and all, inside a TLS session to port
+// 853. For networks that block or snoop on plain port 53. The server's certificate is checked
+// against the name we were given for it (it's what goes in SNI too), by default with the Mozilla
+// roots. The connection stays open between queries, setting up TLS costs more than the query,
+// and queries from several threads go over it together (see pool.rs)
+use std::io::{self, Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use rustls::pki_types::ServerName;
+use rustls::{ClientConfig, ClientConnection, RootCertStore, StreamOwned};
+
+use crate::pool::{Reader, Writer};
+use crate::tcp;
+use crate::transport::{timeout_error, DnsTransport};
+use crate::{ConnectionPool, DnsError, DnsMessage};
+
+pub(crate) type TlsStream = StreamOwned<ClientConnection, TcpStream>;
+
+pub struct TlsTransport {
+    server: SocketAddr,
+    pool: ConnectionPool,
+}
+
+impl TlsTransport {
+    // `hostname` is who the certificate has to be for, like "cloudflare-dns.com" for 1.1.1.1:853
+    // or "dns.quad9.net" for 9.9.9.9:853
+    pub fn new(server: SocketAddr, hostname: &str) -> Result<Self, DnsError> {
+        Self::with_roots(server, hostname, default_roots())
+    }
+
+    // the same, trusting `roots` instead of the public CAs: for a resolver on the local network
+    // with a certificate from a private CA
+    pub fn with_roots(
+        server: SocketAddr,
+        hostname: &str,
+        roots: RootCertStore,
+    ) -> Result<Self, DnsError> {
+        Ok(TlsTransport {
+            server,
+            pool: ConnectionPool::tls_with_roots(hostname, roots)?,
+        })
+    }
+
+    // for connecting, and then again for each write and each answer
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.pool.set_timeout(timeout);
+    }
+}
+
+pub(crate) fn server_name(hostname: &str) -> Result<ServerName<'static>, DnsError> {
+    ServerName::try_from(hostname.to_string())
+        .map_err(|_| DnsError::InvalidName(hostname.to_string()))
+}
+
+// TLS 1.2 and up with ring's crypto, trusting `roots`. `alpn` is the protocols we offer in the
+// handshake, if any
+pub(crate) fn client_config(
+    roots: RootCertStore,
+    alpn: Vec<Vec<u8>>,
+) -> Result<Arc<ClientConfig>, DnsError> {
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+    let mut config = ClientConfig::builder_with_provider(provider)
+        .with_safe_default_protocol_versions()
+        .map_err(io::Error::other)?
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+    config.alpn_protocols = alpn;
+    Ok(Arc::new(config))
+}
+
+// the TCP connection with the TLS session on top, not shaken hands yet: that happens with the
+// first read or write
+pub(crate) fn connect(
+    server: SocketAddr,
+    name: &ServerName<'static>,
+    config: &Arc<ClientConfig>,
+    timeout: Duration,
+) -> Result<TlsStream, DnsError> {
+    let tcp = tcp::connect(server, timeout).map_err(timeout_error)?;
+    let conn = ClientConnection::new(config.clone(), name.clone()).map_err(io::Error::other)?;
+    Ok(StreamOwned::new(conn, tcp))
+}
+
+// the public CAs, as Mozilla ships them
+pub(crate) fn default_roots() -> RootCertStore {
+    RootCertStore::from_iter(webpki_roots::TLS_SERVER_ROOTS.iter().cloned())
+}
+
+impl DnsTransport for TlsTransport {
+    fn exchange(&self, msg: &DnsMessage) -> Result<DnsMessage, DnsError> {
+        self.pool.exchange(self.server, msg)
+    }
+
+    fn exchange_timeout(
+        &self,
+        msg: &DnsMessage,
+        timeout: Duration,
+    ) -> Result<DnsMessage, DnsError> {
+        self.pool.exchange_timeout(self.server, msg, timeout)
+    }
+}
+
+// a TLS session on `sock` as two halves, one reading and one writing, for a pooled connection
+// that writes queries while a thread of its own waits on the answers. The halves share the
+// session but not the socket: reading blocks on it without holding up the writer
+pub(crate) fn split(
+    sock: TcpStream,
+    name: &ServerName<'static>,
+    config: &Arc<ClientConfig>,
+) -> Result<(Reader, Writer), DnsError> {
+    let conn = ClientConnection::new(config.clone(), name.clone()).map_err(io::Error::other)?;
+    let conn = Arc::new(Mutex::new(conn));
+    let writer = TlsHalf {
+        conn: conn.clone(),
+        sock: sock.try_clone()?,
+    };
+    Ok((Box::new(TlsHalf { conn, sock }), Box::new(writer)))
+}
+
+struct TlsHalf {
+    conn: Arc<Mutex<ClientConnection>>,
+    sock: TcpStream,
+}
+
+impl TlsHalf {
+    // whatever the session has for the server: the handshake, alerts, encrypted records
+    fn send_tls(&self, conn: &mut ClientConnection) -> io::Result<()> {
+        while conn.wants_write() {
+            conn.write_tls(&mut &self.sock)?;
+        }
+        Ok(())
+    }
+}
+
+impl Read for TlsHalf {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut raw = [0u8; 4096];
+        loop {
+            match self.conn.lock().unwrap().reader().read(buf) {
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => {}
+                done => return done,
+            }
+            // nothing decrypted yet: wait for more from the server, with the session unlocked
+            let n = (&self.sock).read(&mut raw)?;
+            if n == 0 {
+                return Ok(0);
+            }
+            let mut conn = self.conn.lock().unwrap();
+            let mut rest = &raw[..n];
+            while !rest.is_empty() {
+                conn.read_tls(&mut rest)?;
+                if let Err(e) = conn.process_new_packets() {
+                    // the alert saying why, if it gets there
+                    let _ = self.send_tls(&mut conn);
+                    return Err(io::Error::new(io::ErrorKind::InvalidData, e));
+                }
+            }
+            // the handshake goes on from here, and queries written before it was done go out
+            // now
+            self.send_tls(&mut conn)?;
+        }
+    }
+}
+
+impl Write for TlsHalf {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut conn = self.conn.lock().unwrap();
+        let n = conn.writer().write(buf)?;
+        self.send_tls(&mut conn)?;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{response_with_rdata, test_roots, test_tls_config};
+    use rustls::ServerConnection;
+    use std::net::{Ipv4Addr, TcpListener};
+    use std::thread;
+
+    // a DoT server on localhost: accepts one connection and answers `count` queries on it with
+    // an A record, then hangs up
+    fn mock_tls_server(count: usize) -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            let (tcp, _) = listener.accept().unwrap();
+            let conn = ServerConnection::new(test_tls_config()).unwrap();
+            let mut stream = StreamOwned::new(conn, tcp);
+            let mut buf = Vec::new();
+            for _ in 0..count {
+                let Ok(len) = tcp::read_message(&mut stream, &mut buf) else {
+                    return;
+                };
+                let res = response_with_rdata(&buf[..len], 1, &[192, 0, 2, 53]);
+                tcp::write_message(&mut stream, &res).unwrap();
+            }
+        });
+        addr
+    }
+
+    #[test]
+    fn test_queries_share_one_connection() {
+        // the server takes a single connection, so the second query only gets through on it
+        let server = mock_tls_server(2);
+        let transport = TlsTransport::with_roots(server, "dns.test", test_roots()).unwrap();
+        for name in ["example.com", "example.org"] {
+            let res = transport
+                .exchange(&DnsMessage::new(name.to_string()))
+                .unwrap();
+            assert_eq!(res.ipv4_addrs(), vec![Ipv4Addr::new(192, 0, 2, 53)]);
+        }
+    }
+
+    #[test]
+    fn test_certificate_for_another_name_is_refused() {
+        let server = mock_tls_server(1);
+        let transport = TlsTransport::with_roots(server, "dns.example", test_roots()).unwrap();
+        let err = transport
+            .exchange(&DnsMessage::new("example.com".to_string()))
+            .unwrap_err();
+        assert!(matches!(err, DnsError::Io(_)));
+
+        assert!(matches!(
+            TlsTransport::new(server, "not a hostname"),
+            Err(DnsError::InvalidName(_))
+        ));
+    }
+}
@@ -0,0 +1,220 @@
+// how a query gets to a server and its answer back, split out from everything that decides what
+// to ask. The Resolver has UDP-then-TCP built in; anything else (TLS, HTTPS, a canned answer in a
+// test) implements DnsTransport and goes in with Resolver::with_transport, keeping the cache,
+// the stats and whatever sits on top of the Resolver
+use std::io;
+use std::net::{SocketAddr, UdpSocket};
+use std::time::{Duration, Instant};
+
+use crate::resolver::{answers_question, unspecified_addr, DEFAULT_TIMEOUT};
+use crate::tcp;
+use crate::{DnsError, DnsMessage};
+
+// one query out, its answer back. Send + Sync so a Resolver holding one can still be shared
+// between threads
+pub trait DnsTransport: Send + Sync {
+    fn exchange(&self, msg: &DnsMessage) -> Result<DnsMessage, DnsError>;
+
+    // the same, waiting `timeout` instead of whatever the transport was set up with. It's how
+    // the Resolver's RetryPolicy timeout and deadline get to the transport; one that has no
+    // timeout of its own to change just does the exchange
+    fn exchange_timeout(
+        &self,
+        msg: &DnsMessage,
+        timeout: Duration,
+    ) -> Result<DnsMessage, DnsError> {
+        let _ = timeout;
+        self.exchange(msg)
+    }
+}
+
+// plain UDP and nothing else: a truncated answer comes back as it is, TC set, and it's up to the
+// caller to go again over TCP
+#[derive(Debug, Clone)]
+pub struct UdpTransport {
+    server: SocketAddr,
+    timeout: Duration,
+}
+
+impl UdpTransport {
+    pub fn new(server: SocketAddr) -> Self {
+        UdpTransport {
+            server,
+            timeout: DEFAULT_TIMEOUT,
+        }
+    }
+
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = timeout;
+    }
+}
+
+impl DnsTransport for UdpTransport {
+    // a socket of its own for every exchange, connected to the server so the kernel already
+    // drops anything from elsewhere. What's left can still be a late answer to something else
+    // or a forgery with a guessed port, so we keep reading until the ID and question match
+    fn exchange(&self, msg: &DnsMessage) -> Result<DnsMessage, DnsError> {
+        self.exchange_timeout(msg, self.timeout)
+    }
+
+    fn exchange_timeout(
+        &self,
+        msg: &DnsMessage,
+        timeout: Duration,
+    ) -> Result<DnsMessage, DnsError> {
+        let socket = UdpSocket::bind(unspecified_addr(&self.server))?;
+        socket.connect(self.server)?;
+        socket.send(&msg.to_bytes()?)?;
+
+        let deadline = Instant::now() + timeout;
+        let mut buf = vec![0; 65535];
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                return Err(DnsError::Timeout);
+            }
+            socket.set_read_timeout(Some(remaining))?;
+            let size = socket.recv(&mut buf).map_err(timeout_error)?;
+            if size < 2 || buf[0..2] != msg.header.identification.to_be_bytes() {
+                continue;
+            }
+            let res = DnsMessage::from_bytes(&buf[..size])?;
+            if answers_question(msg, &res) {
+                return Ok(res);
+            }
+        }
+    }
+}
+
+// a fresh TCP connection for every exchange, length-prefixed as RFC 7766 says
+#[derive(Debug, Clone)]
+pub struct TcpTransport {
+    server: SocketAddr,
+    timeout: Duration,
+}
+
+impl TcpTransport {
+    pub fn new(server: SocketAddr) -> Self {
+        TcpTransport {
+            server,
+            timeout: DEFAULT_TIMEOUT,
+        }
+    }
+
+    // for connecting, and then again for each read and write
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = timeout;
+    }
+}
+
+impl DnsTransport for TcpTransport {
+    fn exchange(&self, msg: &DnsMessage) -> Result<DnsMessage, DnsError> {
+        self.exchange_timeout(msg, self.timeout)
+    }
+
+    fn exchange_timeout(
+        &self,
+        msg: &DnsMessage,
+        timeout: Duration,
+    ) -> Result<DnsMessage, DnsError> {
+        let mut stream = tcp::connect(self.server, timeout).map_err(timeout_error)?;
+        tcp::write_message(&mut stream, &msg.to_bytes()?)?;
+        let mut buf = Vec::new();
+        let size = tcp::read_message(&mut stream, &mut buf).map_err(timeout_error)?;
+        let res = DnsMessage::from_bytes(&buf[..size])?;
+        if res.header.identification != msg.header.identification || !answers_question(msg, &res) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "TCP response doesn't match the query",
+            )
+            .into());
+        }
+        Ok(res)
+    }
+}
+
+// a read timeout shows up as WouldBlock on unix and TimedOut on windows
+pub(crate) fn timeout_error(e: io::Error) -> DnsError {
+    match e.kind() {
+        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut => DnsError::Timeout,
+        _ => e.into(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{
+        mock_tcp_server, mock_udp_server, mock_udp_server_replies, response_with_rdata, with_id,
+    };
+    use crate::{DnsCache, RData, Resolver, ResourceRecord};
+    use std::net::Ipv4Addr;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    #[test]
+    fn test_udp_transport_skips_mismatched_answers() {
+        let server = mock_udp_server_replies(1, |query| {
+            let answer = response_with_rdata(query, 1, &[192, 0, 2, 1]);
+            let id = u16::from_be_bytes([query[0], query[1]]);
+            vec![with_id(answer.clone(), id.wrapping_add(1)), answer]
+        });
+        let query = DnsMessage::new("example.com".to_string());
+        let res = UdpTransport::new(server).exchange(&query).unwrap();
+        assert_eq!(res.header.identification, query.header.identification);
+        assert_eq!(res.ipv4_addrs(), vec![Ipv4Addr::new(192, 0, 2, 1)]);
+
+        let silent = mock_udp_server_replies(1, |_| vec![]);
+        let mut transport = UdpTransport::new(silent);
+        transport.set_timeout(Duration::from_millis(200));
+        assert!(matches!(transport.exchange(&query), Err(DnsError::Timeout)));
+    }
+
+    #[test]
+    fn test_tcp_transport() {
+        let server = mock_tcp_server(|query| vec![response_with_rdata(query, 1, &[192, 0, 2, 2])]);
+        let res = TcpTransport::new(server)
+            .exchange(&DnsMessage::new("example.com".to_string()))
+            .unwrap();
+        assert_eq!(res.ipv4_addrs(), vec![Ipv4Addr::new(192, 0, 2, 2)]);
+    }
+
+    // answers everything itself, without any network, and counts how often it was asked
+    struct Canned(AtomicUsize);
+
+    impl DnsTransport for Canned {
+        fn exchange(&self, msg: &DnsMessage) -> Result<DnsMessage, DnsError> {
+            self.0.fetch_add(1, Ordering::Relaxed);
+            let mut res = DnsMessage::response_to(msg);
+            res.add_answer(ResourceRecord::new(
+                msg.question().qname.clone(),
+                300,
+                RData::A([192, 0, 2, 3].into()),
+            ));
+            Ok(res)
+        }
+    }
+
+    #[test]
+    fn test_resolver_with_custom_transport() {
+        let canned = Arc::new(Canned(AtomicUsize::new(0)));
+        let mut resolver = Resolver::with_transport(canned.clone());
+        resolver.set_cache(Arc::new(DnsCache::new()));
+        for _ in 0..2 {
+            let res = resolver
+                .query(&DnsMessage::new("example.com".to_string()))
+                .unwrap();
+            assert_eq!(res.ipv4_addrs(), vec![Ipv4Addr::new(192, 0, 2, 3)]);
+        }
+        assert_eq!(canned.0.load(Ordering::Relaxed), 1);
+        assert_eq!(resolver.stats().responses_received, 1);
+
+        // and the resolver is a transport too
+        let server = mock_udp_server(1, |query| response_with_rdata(query, 1, &[192, 0, 2, 4]));
+        let transport: &dyn DnsTransport = &Resolver::with_server(server);
+        let res = transport
+            .exchange(&DnsMessage::new("example.com".to_string()))
+            .unwrap();
+        assert_eq!(res.ipv4_addrs(), vec![Ipv4Addr::new(192, 0, 2, 4)]);
+    }
+}
@@ -0,0 +1,121 @@
+// TXT records (RFC 1035 section 3.3.14): one or more character-strings, each a length byte and
+// up to 255 bytes of whatever. RData::TXT has the strings; this is for reading what they say.
+//
+// Most of what's stored in TXT records these days (SPF, DKIM keys, site verification tokens) is
+// one long value, split only because a string can't hold more than 255 bytes, and readers are
+// supposed to glue the pieces back together with nothing in between (RFC 7208 section 3.3)
+use crate::rdata::TYPE_TXT;
+use crate::{DnsError, RData, RecordType, Resolver, RCODE_NXDOMAIN};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Txt {
+    pub strings: Vec<Vec<u8>>,
+}
+
+impl Txt {
+    // the strings of a TXT record, None for anything else
+    pub fn from_data(data: &RData) -> Option<Self> {
+        match data {
+            RData::TXT(strings) => Some(Txt {
+                strings: strings.clone(),
+            }),
+            // no rdata at all decodes as Unknown, whatever the type
+            RData::Unknown(TYPE_TXT, rdata) if rdata.is_empty() => Some(Txt {
+                strings: Vec::new(),
+            }),
+            _ => None,
+        }
+    }
+
+    // the character-strings in a TXT record's rdata, which can't run past its end
+    pub fn from_rdata(rdata: &[u8]) -> Result<Self, DnsError> {
+        let len =
+            u16::try_from(rdata.len()).map_err(|_| DnsError::RdataOverrun { rr_type: TYPE_TXT })?;
+        let data = RData::decode(TYPE_TXT, rdata, 0, len)?;
+        Self::from_data(&data).ok_or(DnsError::Malformed("TXT rdata that isn't TXT"))
+    }
+
+    // every string one after the other, which is what an SPF or DKIM record actually says
+    pub fn joined(&self) -> Vec<u8> {
+        self.strings.concat()
+    }
+
+    // joined, as text. Bytes that aren't UTF-8 come out as U+FFFD
+    pub fn text(&self) -> String {
+        String::from_utf8_lossy(&self.joined()).into_owned()
+    }
+}
+
+// see Resolver::lookup_txt
+pub(crate) fn lookup(resolver: &Resolver, domain: &str) -> Result<Vec<Txt>, DnsError> {
+    let res = resolver.lookup(domain, RecordType::TXT)?.response;
+    match res.rcode() {
+        0 | RCODE_NXDOMAIN => {}
+        _ => return Err(DnsError::from_response(&res)),
+    }
+    let owner = res.canonical_name();
+    Ok(res
+        .answers
+        .iter()
+        .filter(|rr| rr.name == owner)
+        .filter_map(|rr| Txt::from_data(&rr.data))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::mock_udp_server;
+    use crate::{DnsMessage, ResourceRecord};
+
+    #[test]
+    fn test_txt_strings() {
+        let txt = Txt::from_rdata(b"\x06v=spf1\x00\x05 -all\x02\xff\x01").unwrap();
+        assert_eq!(txt.strings, [&b"v=spf1"[..], b"", b" -all", b"\xff\x01"]);
+        assert_eq!(txt.joined(), b"v=spf1 -all\xff\x01");
+        assert_eq!(txt.text(), "v=spf1 -all\u{fffd}\u{1}");
+        assert!(Txt::from_rdata(b"").unwrap().strings.is_empty());
+        assert_eq!(
+            Txt::from_data(&RData::TXT(vec![vec![0xff]]))
+                .unwrap()
+                .joined(),
+            [0xff]
+        );
+        assert_eq!(Txt::from_data(&RData::NS("ns.example.com".into())), None);
+        assert!(matches!(
+            Txt::from_rdata(b"\x05abc"),
+            Err(DnsError::RdataOverrun { rr_type: TYPE_TXT })
+        ));
+    }
+
+    #[test]
+    fn test_lookup_txt() {
+        // a DKIM key is longer than a character-string can be, so it goes out split in two
+        let key = format!("v=DKIM1; k=rsa; p={}", "A".repeat(300));
+        let record = key.clone();
+        let server = mock_udp_server(1, move |query| {
+            let query = DnsMessage::from_bytes(query).unwrap();
+            let mut res = DnsMessage::response_to(&query);
+            let qname = query.question().qname.clone();
+            res.add_answer(ResourceRecord::new(
+                qname.clone(),
+                300,
+                RData::TXT(vec![record.clone().into_bytes()]),
+            ));
+            res.add_answer(ResourceRecord::new(
+                qname,
+                300,
+                RData::TXT(vec![b"token=".to_vec(), b"abc123".to_vec()]),
+            ));
+            res.to_bytes().unwrap()
+        });
+        let resolver = Resolver::with_server(server);
+
+        let records = resolver.lookup_txt("sel._domainkey.example.com.").unwrap();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].strings.len(), 2);
+        assert_eq!(records[0].text(), key);
+        assert_eq!(records[1].strings, [&b"token="[..], b"abc123"]);
+        assert_eq!(records[1].text(), "token=abc123");
+    }
+}
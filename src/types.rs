@@ -0,0 +1,252 @@
+// names for the numbers that show up in the type, class, opcode and rcode fields. The structs
+// still carry plain numbers, these convert both ways and anything we have no name for survives
+// as Unknown. They print (and parse) the way zone files and dig write them, with the RFC 3597
+// TYPE99 / CLASS99 forms for the ones without a name
+use alloc::string::{String, ToString};
+use core::fmt;
+use core::str::FromStr;
+
+// a mnemonic we don't know, as handed to from_str
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnknownMnemonic(pub String);
+
+impl fmt::Display for UnknownMnemonic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "unknown mnemonic {:?}", self.0)
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for UnknownMnemonic {}
+
+// writes out From both ways, Display and FromStr for an enum of named values plus Unknown.
+// `$prefix` is what goes in front of the number for an unknown one ("TYPE" for TYPE99)
+macro_rules! mnemonics {
+    ($ty:ident, $int:ty, $prefix:literal, { $($variant:ident = $value:literal, $text:literal,)* }) => {
+        impl From<$int> for $ty {
+            fn from(value: $int) -> Self {
+                match value {
+                    $($value => $ty::$variant,)*
+                    other => $ty::Unknown(other),
+                }
+            }
+        }
+
+        impl From<$ty> for $int {
+            fn from(value: $ty) -> Self {
+                match value {
+                    $($ty::$variant => $value,)*
+                    $ty::Unknown(other) => other,
+                }
+            }
+        }
+
+        impl fmt::Display for $ty {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                match self {
+                    $($ty::$variant => f.write_str($text),)*
+                    $ty::Unknown(other) => write!(f, "{}{}", $prefix, other),
+                }
+            }
+        }
+
+        // case doesn't matter, and the numeric form works for every value, named or not
+        impl FromStr for $ty {
+            type Err = UnknownMnemonic;
+
+            fn from_str(s: &str) -> Result<Self, Self::Err> {
+                $(if s.eq_ignore_ascii_case($text) {
+                    return Ok($ty::$variant);
+                })*
+                s.get(..$prefix.len())
+                    .filter(|head| head.eq_ignore_ascii_case($prefix))
+                    .and_then(|_| s[$prefix.len()..].parse::<$int>().ok())
+                    .map($ty::from)
+                    .ok_or_else(|| UnknownMnemonic(s.to_string()))
+            }
+        }
+    };
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum RecordType {
+    A,
+    NS,
+    CNAME,
+    SOA,
+    PTR,
+    MX,
+    TXT,
+    AAAA,
+    SRV,
+    DNAME,
+    DS,
+    RRSIG,
+    NSEC,
+    DNSKEY,
+    NSEC3,
+    TLSA,
+    SVCB,
+    HTTPS,
+    CAA,
+    TSIG,
+    // only valid in a question: what changed in a zone since a serial, or the whole zone
+    IXFR,
+    // only valid in a question: the whole zone, over TCP
+    AXFR,
+    // only valid in a question: "give me whatever you have for this name"
+    Any,
+    Unknown(u16),
+}
+
+// what the RFCs call the question's type field, it's the same numbering
+pub type QType = RecordType;
+
+mnemonics!(RecordType, u16, "TYPE", {
+    A = 1, "A",
+    NS = 2, "NS",
+    CNAME = 5, "CNAME",
+    SOA = 6, "SOA",
+    PTR = 12, "PTR",
+    MX = 15, "MX",
+    TXT = 16, "TXT",
+    AAAA = 28, "AAAA",
+    SRV = 33, "SRV",
+    DNAME = 39, "DNAME",
+    DS = 43, "DS",
+    RRSIG = 46, "RRSIG",
+    NSEC = 47, "NSEC",
+    DNSKEY = 48, "DNSKEY",
+    NSEC3 = 50, "NSEC3",
+    TLSA = 52, "TLSA",
+    SVCB = 64, "SVCB",
+    HTTPS = 65, "HTTPS",
+    CAA = 257, "CAA",
+    TSIG = 250, "TSIG",
+    IXFR = 251, "IXFR",
+    AXFR = 252, "AXFR",
+    Any = 255, "ANY",
+});
+
+// the class of a question or record. Everything real is IN, CH is still used for asking a server
+// about itself (version.bind) and NONE/ANY only show up in dynamic updates and TSIG
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum DnsClass {
+    IN,
+    CH,
+    HS,
+    None,
+    Any,
+    Unknown(u16),
+}
+
+mnemonics!(DnsClass, u16, "CLASS", {
+    IN = 1, "IN",
+    CH = 3, "CH",
+    HS = 4, "HS",
+    None = 254, "NONE",
+    Any = 255, "ANY",
+});
+
+// the 4 bit kind of message from the header. Nearly everything is Query
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Opcode {
+    Query,
+    // inverse query, long obsolete (RFC 3425)
+    IQuery,
+    Status,
+    // a primary telling its secondaries the zone changed (RFC 1996)
+    Notify,
+    // dynamic update (RFC 2136)
+    Update,
+    Unknown(u8),
+}
+
+mnemonics!(Opcode, u8, "OPCODE", {
+    Query = 0, "QUERY",
+    IQuery = 1, "IQUERY",
+    Status = 2, "STATUS",
+    Notify = 4, "NOTIFY",
+    Update = 5, "UPDATE",
+});
+
+// the full 12 bit rcode, the header's 4 bits with EDNS's extra 8 on top (DnsMessage::rcode)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ResponseCode {
+    NoError,
+    FormErr,
+    ServFail,
+    NXDomain,
+    NotImp,
+    Refused,
+    // the next five are for dynamic updates
+    YXDomain,
+    YXRRSet,
+    NXRRSet,
+    NotAuth,
+    NotZone,
+    // only possible with EDNS
+    BadVers,
+    BadCookie,
+    Unknown(u16),
+}
+
+mnemonics!(ResponseCode, u16, "RCODE", {
+    NoError = 0, "NOERROR",
+    FormErr = 1, "FORMERR",
+    ServFail = 2, "SERVFAIL",
+    NXDomain = 3, "NXDOMAIN",
+    NotImp = 4, "NOTIMP",
+    Refused = 5, "REFUSED",
+    YXDomain = 6, "YXDOMAIN",
+    YXRRSet = 7, "YXRRSET",
+    NXRRSet = 8, "NXRRSET",
+    NotAuth = 9, "NOTAUTH",
+    NotZone = 10, "NOTZONE",
+    BadVers = 16, "BADVERS",
+    BadCookie = 23, "BADCOOKIE",
+});
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_record_type_round_trip() {
+        for value in [
+            1u16, 2, 5, 6, 12, 15, 16, 28, 33, 39, 250, 252, 255, 99, 0xFF00,
+        ] {
+            assert_eq!(u16::from(RecordType::from(value)), value);
+        }
+        assert_eq!(QType::from(255), QType::Any);
+        assert_eq!(RecordType::from(99), RecordType::Unknown(99));
+    }
+
+    #[test]
+    fn test_mnemonics() {
+        use alloc::format;
+
+        assert_eq!(format!("{}", RecordType::AAAA), "AAAA");
+        assert_eq!(format!("{}", RecordType::Unknown(99)), "TYPE99");
+        assert_eq!("mx".parse(), Ok(RecordType::MX));
+        assert_eq!("TYPE15".parse(), Ok(RecordType::MX));
+        assert_eq!("type65".parse(), Ok(RecordType::HTTPS));
+        assert_eq!("type99".parse(), Ok(RecordType::Unknown(99)));
+        assert_eq!("any".parse(), Ok(RecordType::Any));
+        assert!("TYPE".parse::<RecordType>().is_err());
+        assert!("TYPE70000".parse::<RecordType>().is_err());
+        assert_eq!(
+            "bogus".parse::<RecordType>(),
+            Err(UnknownMnemonic("bogus".to_string()))
+        );
+
+        assert_eq!("ch".parse(), Ok(DnsClass::CH));
+        assert_eq!(format!("{}", DnsClass::from(1)), "IN");
+        assert_eq!(format!("{}", DnsClass::from(42)), "CLASS42");
+        assert_eq!(u8::from(Opcode::Notify), 4);
+        assert_eq!("update".parse(), Ok(Opcode::Update));
+        assert_eq!(format!("{}", ResponseCode::from(3)), "NXDOMAIN");
+        assert_eq!(u16::from(ResponseCode::BadVers), 16);
+        assert_eq!("RCODE12".parse(), Ok(ResponseCode::Unknown(12)));
+    }
+}
@@ -0,0 +1,322 @@
+// dynamic updates (RFC 2136): asking a zone's primary to change records in it, instead of
+// editing the zone file. An UPDATE message reuses the sections of a query with other meanings:
+// the question names the zone, the answer section holds prerequisites (this name has to exist,
+// that RRset must not...) and the authority section the changes. The server applies all of the
+// changes or none of them, and only if every prerequisite holds.
+//
+// What kind of prerequisite or change a record is comes from its class and TTL: the zone's own
+// class for "exactly these records", ANY for "whatever is there" and NONE for "not there" (or
+// "take this one out"), with empty rdata where no particular record is meant (section 2.4, 2.5)
+use std::net::{SocketAddr, UdpSocket};
+use std::time::{Duration, Instant};
+
+use crate::axfr::now;
+use crate::resolver::{unspecified_addr, DEFAULT_TIMEOUT};
+use crate::tcp;
+use crate::transport::timeout_error;
+use crate::TsigKey;
+use crate::{DnsClass, DnsError, DnsMessage, Name, Opcode, RData, RecordType, ResourceRecord};
+
+// builds an UPDATE for one zone, and sends it. For a home address that moved:
+//
+//     UpdateBuilder::new("example.com")
+//         .delete_rrset("home.example.com", RecordType::A)
+//         .add_record(ResourceRecord::new("home.example.com", 300, RData::A(addr)))
+//         .tsig(key)
+//         .send(primary)?;
+pub struct UpdateBuilder {
+    zone: Name,
+    prerequisites: Vec<ResourceRecord>,
+    updates: Vec<ResourceRecord>,
+    tsig: Option<TsigKey>,
+    timeout: Duration,
+}
+
+impl UpdateBuilder {
+    pub fn new(zone: impl Into<Name>) -> Self {
+        UpdateBuilder {
+            zone: zone.into(),
+            prerequisites: Vec::new(),
+            updates: Vec::new(),
+            tsig: None,
+            timeout: DEFAULT_TIMEOUT,
+        }
+    }
+
+    // the prerequisites. The update goes ahead only if all of them hold, otherwise the server
+    // answers YXDOMAIN, YXRRSET, NXDOMAIN or NXRRSET for the first one that doesn't
+
+    // `name` has at least one record of type `rr_type`
+    pub fn require_rrset(mut self, name: impl Into<Name>, rr_type: impl Into<u16>) -> Self {
+        self.prerequisites
+            .push(empty(name, rr_type.into(), DnsClass::Any));
+        self
+    }
+
+    // `name` has no records of type `rr_type`
+    pub fn require_no_rrset(mut self, name: impl Into<Name>, rr_type: impl Into<u16>) -> Self {
+        self.prerequisites
+            .push(empty(name, rr_type.into(), DnsClass::None));
+        self
+    }
+
+    // the RRset is exactly these records, no more and no fewer. Call it once per record of the
+    // set, the server puts all of a name and type's records together before comparing
+    pub fn require_record(mut self, rr: ResourceRecord) -> Self {
+        self.prerequisites.push(ResourceRecord { ttl: 0, ..rr });
+        self
+    }
+
+    // `name` has records of some type
+    pub fn require_name(mut self, name: impl Into<Name>) -> Self {
+        self.prerequisites
+            .push(empty(name, RecordType::Any.into(), DnsClass::Any));
+        self
+    }
+
+    // `name` has no records at all
+    pub fn require_no_name(mut self, name: impl Into<Name>) -> Self {
+        self.prerequisites
+            .push(empty(name, RecordType::Any.into(), DnsClass::None));
+        self
+    }
+
+    // the changes, applied in order
+
+    // adds a record, which has to be of the zone's class (a duplicate is ignored)
+    pub fn add_record(mut self, rr: ResourceRecord) -> Self {
+        self.updates.push(rr);
+        self
+    }
+
+    // takes out the one record matching `rr`'s name, type and data
+    pub fn delete_record(mut self, rr: ResourceRecord) -> Self {
+        self.updates.push(ResourceRecord {
+            class: DnsClass::None.into(),
+            ttl: 0,
+            ..rr
+        });
+        self
+    }
+
+    // takes out every record of type `rr_type` at `name`
+    pub fn delete_rrset(mut self, name: impl Into<Name>, rr_type: impl Into<u16>) -> Self {
+        self.updates
+            .push(empty(name, rr_type.into(), DnsClass::Any));
+        self
+    }
+
+    // takes out everything at `name`. The apex keeps its SOA and NS records whatever we ask
+    pub fn delete_name(mut self, name: impl Into<Name>) -> Self {
+        self.updates
+            .push(empty(name, RecordType::Any.into(), DnsClass::Any));
+        self
+    }
+
+    // signs the update (see tsig.rs), which is what primaries want before letting anyone change
+    // a zone. The answer then has to be signed with the same key
+    pub fn tsig(mut self, key: TsigKey) -> Self {
+        self.tsig = Some(key);
+        self
+    }
+
+    // for the UDP answer, and for connecting and each read and write over TCP
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    // the UPDATE message, unsigned. `send` signs it when there's a key
+    pub fn build(&self) -> DnsMessage {
+        let mut msg = DnsMessage::query(self.zone.clone())
+            .qtype(RecordType::SOA)
+            .recursion_desired(false)
+            .build();
+        let mut flags = msg.flags();
+        flags.opcode = Opcode::Update.into();
+        msg.set_flags(flags);
+        msg.answers = self.prerequisites.clone();
+        msg.authority = self.updates.clone();
+        msg
+    }
+
+    // sends the update to `server`, the zone's primary, over UDP and again over TCP if the
+    // answer was truncated. Ok means the server made the changes; a prerequisite that didn't
+    // hold, or a server that wouldn't, is the error for its rcode
+    pub fn send(&self, server: SocketAddr) -> Result<(), DnsError> {
+        let mut msg = self.build();
+        let request_mac = match &self.tsig {
+            Some(key) => Some(key.sign(&mut msg, None, now())?),
+            None => None,
+        };
+        let query = msg.to_bytes()?;
+
+        let mut buf = exchange_udp(&msg, &query, server, self.timeout)?;
+        if DnsMessage::from_bytes(&buf)?.truncated() {
+            let mut stream = tcp::connect(server, self.timeout).map_err(timeout_error)?;
+            tcp::write_message(&mut stream, &query)?;
+            let size = tcp::read_message(&mut stream, &mut buf).map_err(timeout_error)?;
+            buf.truncate(size);
+        }
+
+        if let (Some(key), Some(mac)) = (&self.tsig, &request_mac) {
+            key.verify(&buf, Some(mac), now())?;
+        }
+        let res = DnsMessage::from_bytes(&buf)?;
+        if res.header.identification != msg.header.identification
+            || !res.is_response()
+            || res.opcode() != Opcode::Update
+        {
+            return Err(DnsError::Malformed("answer doesn't match the update"));
+        }
+        if res.rcode() != 0 {
+            return Err(DnsError::from_response(&res));
+        }
+        Ok(())
+    }
+}
+
+// a record with no rdata, the way prerequisites and deletions name a whole RRset or name
+fn empty(name: impl Into<Name>, rr_type: u16, class: DnsClass) -> ResourceRecord {
+    ResourceRecord {
+        class: class.into(),
+        ttl: 0,
+        ..ResourceRecord::new(name, 0, RData::Unknown(rr_type, Vec::new()))
+    }
+}
+
+// the raw answer to `query` from `server`. Raw, because a TSIG can only be checked on the bytes
+// as they came in
+fn exchange_udp(
+    msg: &DnsMessage,
+    query: &[u8],
+    server: SocketAddr,
+    timeout: Duration,
+) -> Result<Vec<u8>, DnsError> {
+    let socket = UdpSocket::bind(unspecified_addr(&server))?;
+    socket.connect(server)?;
+    socket.send(query)?;
+
+    let deadline = Instant::now() + timeout;
+    let mut buf = vec![0; 65535];
+    loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            return Err(DnsError::Timeout);
+        }
+        socket.set_read_timeout(Some(remaining))?;
+        let size = socket.recv(&mut buf).map_err(timeout_error)?;
+        if size >= 12 && buf[0..2] == msg.header.identification.to_be_bytes() {
+            buf.truncate(size);
+            return Ok(buf);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::mock_udp_server;
+    use crate::{ResponseCode, TsigError};
+    use std::net::Ipv4Addr;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    fn home(last: u8) -> ResourceRecord {
+        ResourceRecord::new(
+            "home.example.com",
+            300,
+            RData::A(Ipv4Addr::new(192, 0, 2, last)),
+        )
+    }
+
+    #[test]
+    fn test_update_message() {
+        let update = UpdateBuilder::new("example.com")
+            .require_name("home.example.com")
+            .require_no_rrset("home.example.com", RecordType::CNAME)
+            .require_record(home(1))
+            .delete_rrset("home.example.com", RecordType::A)
+            .delete_record(home(1))
+            .add_record(home(2))
+            .delete_name("old.example.com")
+            .build();
+        let msg = DnsMessage::from_bytes(&update.to_bytes().unwrap()).unwrap();
+        assert_eq!(msg.opcode(), Opcode::Update);
+        assert!(!msg.recursion_desired());
+        assert_eq!(msg.question().qname, "example.com");
+        assert_eq!(msg.question().record_type(), RecordType::SOA);
+
+        let sections = |records: &[ResourceRecord]| -> Vec<(String, u16, DnsClass, u32, usize)> {
+            records
+                .iter()
+                .map(|rr| {
+                    let name = rr.name.to_string();
+                    (name, rr.rr_type, rr.class(), rr.ttl, rr.rdata.len())
+                })
+                .collect()
+        };
+        let home = "home.example.com".to_string();
+        assert_eq!(
+            sections(&msg.answers),
+            [
+                (home.clone(), 255, DnsClass::Any, 0, 0),
+                (home.clone(), 5, DnsClass::None, 0, 0),
+                (home.clone(), 1, DnsClass::IN, 0, 4),
+            ]
+        );
+        assert_eq!(
+            sections(&msg.authority),
+            [
+                (home.clone(), 1, DnsClass::Any, 0, 0),
+                (home.clone(), 1, DnsClass::None, 0, 4),
+                (home, 1, DnsClass::IN, 300, 4),
+                ("old.example.com".to_string(), 255, DnsClass::Any, 0, 0),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_send_signed_update() {
+        let key = TsigKey::new("update-key", &b"0123456789abcdef0123456789abcdef"[..]);
+        // the first update is made, the second fails its prerequisite, the third gets an
+        // answer signed with some other key
+        let count = AtomicUsize::new(0);
+        let server_key = key.clone();
+        let server = mock_udp_server(3, move |query| {
+            let request_mac = server_key.verify(query, None, now()).unwrap();
+            let query = DnsMessage::from_bytes(query).unwrap();
+            assert_eq!(query.opcode(), Opcode::Update);
+            assert_eq!(query.authority.len(), 2);
+            let mut res = DnsMessage::response_to(&query);
+            let mut flags = res.flags();
+            flags.opcode = Opcode::Update.into();
+            match count.fetch_add(1, Ordering::SeqCst) {
+                0 => {}
+                1 => flags.rcode = u16::from(ResponseCode::NXRRSet) as u8,
+                _ => {
+                    let other = TsigKey::new("update-key", &b"not the same secret"[..]);
+                    res.set_flags(flags);
+                    other.sign(&mut res, Some(&request_mac), now()).unwrap();
+                    return res.to_bytes().unwrap();
+                }
+            }
+            res.set_flags(flags);
+            server_key
+                .sign(&mut res, Some(&request_mac), now())
+                .unwrap();
+            res.to_bytes().unwrap()
+        });
+
+        let update = UpdateBuilder::new("example.com")
+            .require_rrset("home.example.com", RecordType::A)
+            .delete_rrset("home.example.com", RecordType::A)
+            .add_record(home(2))
+            .tsig(key);
+        update.send(server).unwrap();
+        assert!(matches!(update.send(server), Err(DnsError::Rcode(8))));
+        assert!(matches!(
+            update.send(server),
+            Err(DnsError::Tsig(TsigError::BadSig))
+        ));
+    }
+}
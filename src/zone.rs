@@ -0,0 +1,922 @@
+// zone files, the master file format of RFC 1035 section 5: one record per entry, written
+// "owner ttl class type rdata" with the rdata in presentation format (see presentation.rs).
+//
+// On top of that we take what BIND and the others read:
+// - $ORIGIN sets what relative names (the ones without a trailing dot) are relative to, and "@"
+//   is the origin itself
+// - $TTL is the TTL of records that don't have one (RFC 2308). Without it a record takes the TTL
+//   of the last one that gave one. TTLs can have units, 1h30m or 1w
+// - $INCLUDE reads another file in place, relative to the one it's in, with an origin of its
+//   own if one is given. The origin goes back to what it was afterwards
+// - an entry starting with a space or tab is for the previous entry's owner, and the TTL and
+//   class can come in either order or be left out (the class is then the last one given)
+// - ( and ) carry an entry over several lines, ; is a comment to the end of the line
+// - any type can be written in the generic form of RFC 3597, "\# 4 C0000201"
+use std::fmt;
+use std::fs;
+use std::mem;
+use std::net::{Ipv4Addr, Ipv6Addr};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use crate::encoding::{from_base32hex, from_base64, from_hex};
+use crate::name::{decimal_escape, strip_trailing_dot};
+use crate::rdata::{SvcParam, SvcParams, Svcb, SVC_ALPN, SVC_ECH, SVC_IPV4HINT, SVC_IPV6HINT};
+use crate::rdata::{SVC_MANDATORY, SVC_NO_DEFAULT_ALPN, SVC_PORT};
+use crate::{Caa, DnsClass, Dnskey, Ds, Name, Nsec, Nsec3, RData, RecordType, ResourceRecord};
+use crate::{Rrsig, Soa, SrvRecord, Tlsa};
+
+// how deep $INCLUDEs may nest, which is also what stops a file that includes itself
+const MAX_INCLUDE_DEPTH: usize = 16;
+
+// what went wrong, and where: the file (None for text given to parse_zone) and the line the
+// entry starts on, 0 when it's the file or the zone as a whole
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ZoneError {
+    pub file: Option<PathBuf>,
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ZoneError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (&self.file, self.line) {
+            (Some(file), 0) => write!(f, "{}: {}", file.display(), self.message),
+            (Some(file), line) => write!(f, "{}:{}: {}", file.display(), line, self.message),
+            (None, 0) => f.write_str(&self.message),
+            (None, line) => write!(f, "line {}: {}", line, self.message),
+        }
+    }
+}
+
+impl std::error::Error for ZoneError {}
+
+// the records of zone file `text`, in the order they're written. Relative names start out
+// relative to `origin`, and an $INCLUDE is relative to the current directory
+pub fn parse_zone(text: &str, origin: impl Into<Name>) -> Result<Vec<ResourceRecord>, ZoneError> {
+    let mut parser = Parser::new(origin.into());
+    parser.read(text, None)?;
+    Ok(parser.records)
+}
+
+// the same for the zone file at `path`
+pub fn load_zone(
+    path: impl AsRef<Path>,
+    origin: impl Into<Name>,
+) -> Result<Vec<ResourceRecord>, ZoneError> {
+    let path = path.as_ref();
+    let text = fs::read_to_string(path).map_err(|e| ZoneError {
+        file: Some(path.to_path_buf()),
+        line: 0,
+        message: e.to_string(),
+    })?;
+    let mut parser = Parser::new(origin.into());
+    parser.read(&text, Some(path))?;
+    Ok(parser.records)
+}
+
+// one word of an entry, with the quotes gone but the escapes still in: what they mean depends on
+// where the word goes (a name keeps "\." inside a label, a string wants the byte)
+#[derive(Debug, Default)]
+struct Token {
+    text: String,
+    quoted: bool,
+}
+
+// one entry, however many lines it took
+#[derive(Debug)]
+struct Entry {
+    line: usize,
+    // it starts with blank space, so it's another record for the previous owner
+    blank_owner: bool,
+    tokens: Vec<Token>,
+}
+
+// `text` split into entries, skipping the ones with nothing but comments. An error comes with
+// the line it's on
+fn entries(text: &str) -> Result<Vec<Entry>, (usize, String)> {
+    let mut entries = Vec::new();
+    let mut line = 1;
+    let mut depth = 0;
+    let mut entry = Entry {
+        line,
+        blank_owner: text.starts_with([' ', '\t']),
+        tokens: Vec::new(),
+    };
+    let mut token: Option<Token> = None;
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => {
+                let token = token.get_or_insert_with(Token::default);
+                token.quoted = true;
+                loop {
+                    match chars.next() {
+                        None => return Err((entry.line, "unterminated quoted string".to_string())),
+                        Some('"') => break,
+                        Some('\\') => {
+                            token.text.push('\\');
+                            if let Some(next) = chars.next() {
+                                line += (next == '\n') as usize;
+                                token.text.push(next);
+                            }
+                        }
+                        Some(c) => {
+                            line += (c == '\n') as usize;
+                            token.text.push(c);
+                        }
+                    }
+                }
+            }
+            '\\' => {
+                let token = token.get_or_insert_with(Token::default);
+                token.text.push('\\');
+                if let Some(next) = chars.next() {
+                    line += (next == '\n') as usize;
+                    token.text.push(next);
+                }
+            }
+            ';' => while chars.next_if(|c| *c != '\n').is_some() {},
+            '(' => {
+                entry.tokens.extend(token.take());
+                depth += 1;
+            }
+            ')' => {
+                entry.tokens.extend(token.take());
+                if depth == 0 {
+                    return Err((line, "unbalanced )".to_string()));
+                }
+                depth -= 1;
+            }
+            '\n' => {
+                entry.tokens.extend(token.take());
+                line += 1;
+                if depth == 0 {
+                    let next = Entry {
+                        line,
+                        blank_owner: matches!(chars.peek(), Some(' ' | '\t')),
+                        tokens: Vec::new(),
+                    };
+                    let done = mem::replace(&mut entry, next);
+                    if !done.tokens.is_empty() {
+                        entries.push(done);
+                    }
+                }
+            }
+            c if c.is_whitespace() => entry.tokens.extend(token.take()),
+            c => token.get_or_insert_with(Token::default).text.push(c),
+        }
+    }
+    if depth > 0 {
+        return Err((entry.line, "unbalanced (".to_string()));
+    }
+    entry.tokens.extend(token);
+    if !entry.tokens.is_empty() {
+        entries.push(entry);
+    }
+    Ok(entries)
+}
+
+struct Parser {
+    origin: Name,
+    // from $TTL
+    default_ttl: Option<u32>,
+    // the last TTL a record gave, for when there's no $TTL
+    last_ttl: Option<u32>,
+    last_owner: Option<Name>,
+    last_class: u16,
+    records: Vec<ResourceRecord>,
+    depth: usize,
+}
+
+impl Parser {
+    fn new(origin: Name) -> Self {
+        Parser {
+            origin,
+            default_ttl: None,
+            last_ttl: None,
+            last_owner: None,
+            last_class: 1,
+            records: Vec::new(),
+            depth: 0,
+        }
+    }
+
+    fn read(&mut self, text: &str, file: Option<&Path>) -> Result<(), ZoneError> {
+        let error = |line, message| ZoneError {
+            file: file.map(Path::to_path_buf),
+            line,
+            message,
+        };
+        for entry in entries(text).map_err(|(line, message)| error(line, message))? {
+            let first = &entry.tokens[0];
+            if !entry.blank_owner && first.text.eq_ignore_ascii_case("$INCLUDE") {
+                self.include(&entry, file)?;
+            } else {
+                self.entry(&entry)
+                    .map_err(|message| error(entry.line, message))?;
+            }
+        }
+        Ok(())
+    }
+
+    fn include(&mut self, entry: &Entry, file: Option<&Path>) -> Result<(), ZoneError> {
+        let error = |message| ZoneError {
+            file: file.map(Path::to_path_buf),
+            line: entry.line,
+            message,
+        };
+        let (path, origin) = match &entry.tokens[1..] {
+            [path] => (path, None),
+            [path, origin] => (path, Some(self.name(&origin.text).map_err(error)?)),
+            _ => {
+                return Err(error(
+                    "$INCLUDE takes a file and maybe an origin".to_string(),
+                ))
+            }
+        };
+        if self.depth == MAX_INCLUDE_DEPTH {
+            return Err(error("$INCLUDEs nested too deep".to_string()));
+        }
+        let path = match file.and_then(Path::parent) {
+            Some(dir) => dir.join(&path.text),
+            None => PathBuf::from(&path.text),
+        };
+        let text =
+            fs::read_to_string(&path).map_err(|e| error(format!("{}: {}", path.display(), e)))?;
+
+        let saved = (self.origin.clone(), self.last_owner.clone());
+        if let Some(origin) = origin {
+            self.origin = origin;
+        }
+        self.depth += 1;
+        let read = self.read(&text, Some(&path));
+        self.depth -= 1;
+        (self.origin, self.last_owner) = saved;
+        read
+    }
+
+    fn entry(&mut self, entry: &Entry) -> Result<(), String> {
+        let tokens = &entry.tokens[..];
+        if !entry.blank_owner && tokens[0].text.starts_with('$') {
+            return self.directive(tokens);
+        }
+        let (owner, mut rest) = if entry.blank_owner {
+            let owner = self.last_owner.clone();
+            (owner.ok_or("no previous owner to share")?, tokens)
+        } else {
+            (self.name(&tokens[0].text)?, &tokens[1..])
+        };
+
+        let (mut ttl, mut class) = (None, None);
+        while let [token, tail @ ..] = rest {
+            if ttl.is_none() && token.text.starts_with(|c: char| c.is_ascii_digit()) {
+                ttl = Some(parse_ttl(&token.text)?);
+            } else if let (None, Ok(given)) = (class, DnsClass::from_str(&token.text)) {
+                class = Some(u16::from(given));
+            } else {
+                break;
+            }
+            rest = tail;
+        }
+        let [rr_type, rdata @ ..] = rest else {
+            return Err("no record type".to_string());
+        };
+        let rr_type = RecordType::from_str(&rr_type.text)
+            .map_err(|_| format!("unknown type {}", rr_type.text))?;
+
+        let ttl = match ttl {
+            Some(ttl) => {
+                self.last_ttl = Some(ttl);
+                ttl
+            }
+            None => self
+                .default_ttl
+                .or(self.last_ttl)
+                .ok_or("no TTL, and no $TTL to fall back on")?,
+        };
+        let class = class.unwrap_or(self.last_class);
+        let data = self.rdata(rr_type, rdata)?;
+        let mut rr = ResourceRecord::new(owner.clone(), ttl, data);
+        rr.class = class;
+        self.records.push(rr);
+        self.last_owner = Some(owner);
+        self.last_class = class;
+        Ok(())
+    }
+
+    fn directive(&mut self, tokens: &[Token]) -> Result<(), String> {
+        let directive = tokens[0].text.to_ascii_uppercase();
+        match (directive.as_str(), &tokens[1..]) {
+            // a relative origin is relative to the one before it
+            ("$ORIGIN", [origin]) => self.origin = self.name(&origin.text)?,
+            ("$TTL", [ttl]) => self.default_ttl = Some(parse_ttl(&ttl.text)?),
+            ("$ORIGIN" | "$TTL", _) => return Err(format!("{} takes one argument", directive)),
+            _ => return Err(format!("unknown directive {}", tokens[0].text)),
+        }
+        Ok(())
+    }
+
+    // a name as written, made absolute
+    fn name(&self, text: &str) -> Result<Name, String> {
+        let full = if text == "@" {
+            return Ok(self.origin.clone());
+        } else if strip_trailing_dot(text).len() < text.len() {
+            text.to_string()
+        } else if self.origin.is_root() {
+            format!("{}.", text)
+        } else {
+            format!("{}.{}.", text, self.origin)
+        };
+        full.parse().map_err(|_| format!("bad name {}", text))
+    }
+
+    // the same, the way names are kept inside rdata
+    fn target(&self, token: &Token) -> Result<String, String> {
+        Ok(self.name(&token.text)?.as_str().to_string())
+    }
+
+    fn rdata(&self, rr_type: RecordType, tokens: &[Token]) -> Result<RData, String> {
+        if matches!(tokens.first(), Some(first) if first.text == "\\#" && !first.quoted) {
+            let words: Vec<&str> = tokens.iter().map(|token| token.text.as_str()).collect();
+            return RData::from_generic(rr_type.into(), &words.join(" "))
+                .map_err(|e| e.to_string());
+        }
+
+        let mut f = Fields(tokens);
+        let data = match rr_type {
+            RecordType::A => RData::A(f.parse()?),
+            RecordType::AAAA => RData::AAAA(f.parse()?),
+            RecordType::NS => RData::NS(self.target(f.next()?)?),
+            RecordType::CNAME => RData::CNAME(self.target(f.next()?)?),
+            RecordType::PTR => RData::PTR(self.target(f.next()?)?),
+            RecordType::DNAME => RData::DNAME(self.target(f.next()?)?),
+            RecordType::MX => RData::MX {
+                preference: f.parse()?,
+                exchange: self.target(f.next()?)?,
+            },
+            RecordType::SOA => RData::SOA(Soa {
+                mname: self.target(f.next()?)?,
+                rname: self.target(f.next()?)?,
+                serial: f.parse()?,
+                refresh: parse_ttl(&f.next()?.text)?,
+                retry: parse_ttl(&f.next()?.text)?,
+                expire: parse_ttl(&f.next()?.text)?,
+                minimum: parse_ttl(&f.next()?.text)?,
+            }),
+            RecordType::SRV => RData::SRV(SrvRecord {
+                priority: f.parse()?,
+                weight: f.parse()?,
+                port: f.parse()?,
+                target: self.target(f.next()?)?,
+            }),
+            RecordType::TXT => {
+                let mut strings = Vec::new();
+                for token in f.rest() {
+                    let string = char_string(&token.text);
+                    if string.len() > 255 {
+                        return Err("TXT string longer than 255 bytes".to_string());
+                    }
+                    strings.push(string);
+                }
+                if strings.is_empty() {
+                    return Err("TXT with no strings".to_string());
+                }
+                RData::TXT(strings)
+            }
+            RecordType::DS => RData::DS(Ds {
+                key_tag: f.parse()?,
+                algorithm: f.parse()?,
+                digest_type: f.parse()?,
+                digest: f.hex()?,
+            }),
+            RecordType::DNSKEY => RData::DNSKEY(Dnskey {
+                flags: f.parse()?,
+                protocol: f.parse()?,
+                algorithm: f.parse()?,
+                public_key: f.base64()?,
+            }),
+            RecordType::RRSIG => RData::RRSIG(Rrsig {
+                type_covered: f.record_type()?,
+                algorithm: f.parse()?,
+                labels: f.parse()?,
+                original_ttl: f.parse()?,
+                expiration: sig_time(&f.next()?.text)?,
+                inception: sig_time(&f.next()?.text)?,
+                key_tag: f.parse()?,
+                signer: self.target(f.next()?)?,
+                signature: f.base64()?,
+            }),
+            RecordType::NSEC => RData::NSEC(Nsec {
+                next: self.target(f.next()?)?,
+                types: f.types()?,
+            }),
+            RecordType::NSEC3 => RData::NSEC3(Nsec3 {
+                hash_algorithm: f.parse()?,
+                flags: f.parse()?,
+                iterations: f.parse()?,
+                salt: match f.next()?.text.as_str() {
+                    "-" => Vec::new(),
+                    salt => from_hex(salt).ok_or_else(|| format!("bad salt {}", salt))?,
+                },
+                next_hashed: {
+                    let next = &f.next()?.text;
+                    from_base32hex(next.as_bytes()).ok_or_else(|| format!("bad hash {}", next))?
+                },
+                types: f.types()?,
+            }),
+            RecordType::TLSA => RData::TLSA(Tlsa {
+                usage: f.parse()?,
+                selector: f.parse()?,
+                matching_type: f.parse()?,
+                data: f.hex()?,
+            }),
+            RecordType::CAA => {
+                let flags = f.parse()?;
+                let tag = f.next()?.text.clone();
+                if tag.is_empty()
+                    || tag.len() > 15
+                    || !tag.bytes().all(|b| b.is_ascii_alphanumeric())
+                {
+                    return Err(format!("bad CAA tag {}", tag));
+                }
+                RData::CAA(Caa {
+                    flags,
+                    tag,
+                    value: char_string(&f.next()?.text),
+                })
+            }
+            RecordType::SVCB | RecordType::HTTPS => {
+                let svcb = Svcb {
+                    priority: f.parse()?,
+                    target: self.target(f.next()?)?,
+                    params: svc_params(f.rest())?,
+                };
+                if rr_type == RecordType::SVCB {
+                    RData::SVCB(svcb)
+                } else {
+                    RData::HTTPS(svcb)
+                }
+            }
+            other => {
+                return Err(format!(
+                    "no presentation format for {}, it needs \\# generic rdata",
+                    other
+                ))
+            }
+        };
+        if !f.0.is_empty() {
+            return Err(format!("too much rdata for {}", rr_type));
+        }
+        Ok(data)
+    }
+}
+
+// the rdata tokens still to go
+struct Fields<'a>(&'a [Token]);
+
+impl<'a> Fields<'a> {
+    fn next(&mut self) -> Result<&'a Token, String> {
+        let (first, rest) = self.0.split_first().ok_or("missing rdata")?;
+        self.0 = rest;
+        Ok(first)
+    }
+
+    fn parse<T: FromStr>(&mut self) -> Result<T, String> {
+        let token = self.next()?;
+        token
+            .text
+            .parse()
+            .map_err(|_| format!("bad rdata field {}", token.text))
+    }
+
+    fn rest(&mut self) -> &'a [Token] {
+        mem::take(&mut self.0)
+    }
+
+    // everything left as one word: long hex and base64 can be split up by blank space
+    fn joined(&mut self) -> String {
+        self.rest()
+            .iter()
+            .map(|token| token.text.as_str())
+            .collect()
+    }
+
+    fn hex(&mut self) -> Result<Vec<u8>, String> {
+        let text = self.joined();
+        from_hex(&text).ok_or_else(|| format!("bad hex {}", text))
+    }
+
+    fn base64(&mut self) -> Result<Vec<u8>, String> {
+        let text = self.joined();
+        from_base64(&text).ok_or_else(|| format!("bad base64 {}", text))
+    }
+
+    fn record_type(&mut self) -> Result<u16, String> {
+        let token = self.next()?;
+        RecordType::from_str(&token.text)
+            .map(u16::from)
+            .map_err(|_| format!("unknown type {}", token.text))
+    }
+
+    // the type list that ends NSEC and NSEC3
+    fn types(&mut self) -> Result<Vec<u16>, String> {
+        let mut types = Vec::new();
+        while !self.0.is_empty() {
+            types.push(self.record_type()?);
+        }
+        Ok(types)
+    }
+}
+
+// a TTL in seconds, or with BIND's units: 1w2d, 1h30m, 90s. Case doesn't matter
+fn parse_ttl(text: &str) -> Result<u32, String> {
+    let bad = || format!("bad TTL {}", text);
+    if !text.starts_with(|c: char| c.is_ascii_digit()) {
+        return Err(bad());
+    }
+    let (mut total, mut number) = (0u32, 0u32);
+    for c in text.chars() {
+        if let Some(digit) = c.to_digit(10) {
+            number = number
+                .checked_mul(10)
+                .and_then(|n| n.checked_add(digit))
+                .ok_or_else(bad)?;
+            continue;
+        }
+        let unit = match c.to_ascii_lowercase() {
+            's' => 1,
+            'm' => 60,
+            'h' => 3600,
+            'd' => 86400,
+            'w' => 604800,
+            _ => return Err(bad()),
+        };
+        total = number
+            .checked_mul(unit)
+            .and_then(|n| total.checked_add(n))
+            .ok_or_else(bad)?;
+        number = 0;
+    }
+    // digits at the end without a unit are seconds
+    total.checked_add(number).ok_or_else(bad)
+}
+
+// an RRSIG time, YYYYMMDDHHmmSS in UTC or plain seconds since the epoch (RFC 4034 section 3.2)
+fn sig_time(text: &str) -> Result<u32, String> {
+    let bad = || format!("bad signature time {}", text);
+    if text.len() != 14 {
+        return text.parse().map_err(|_| bad());
+    }
+    if !text.bytes().all(|b| b.is_ascii_digit()) {
+        return Err(bad());
+    }
+    let field = |at: usize, len: usize| text[at..at + len].parse::<i64>().unwrap();
+    let (year, month, day) = (field(0, 4), field(4, 2), field(6, 2));
+    let (hour, minute, second) = (field(8, 2), field(10, 2), field(12, 2));
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) || hour > 23 || minute > 59 {
+        return Err(bad());
+    }
+    if second > 60 {
+        return Err(bad());
+    }
+    let seconds = days_from_civil(year, month, day) * 86400 + hour * 3600 + minute * 60 + second;
+    // the field is serial arithmetic on 32 bits, so a date past 2106 wraps around
+    Ok(seconds as u32)
+}
+
+// days since 1970-01-01 of a date in the proleptic Gregorian calendar
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+// the bytes of a character-string, with \DDD and \X unescaped
+fn char_string(text: &str) -> Vec<u8> {
+    let bytes = text.as_bytes();
+    let mut out = Vec::new();
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] != b'\\' {
+            out.push(bytes[i]);
+            i += 1;
+        } else if let Some(value) = decimal_escape(&bytes[i + 1..]) {
+            out.push(value);
+            i += 4;
+        } else {
+            out.extend(bytes.get(i + 1));
+            i += 2;
+        }
+    }
+    out
+}
+
+// RFC 9460 section 2.1: key=value, or just the key for one without a value. The value is a
+// character-string, and the list ones split on the commas that aren't escaped
+fn svc_params(tokens: &[Token]) -> Result<SvcParams, String> {
+    let mut params = SvcParams::new();
+    for token in tokens {
+        let (key, value) = match token.text.split_once('=') {
+            Some((key, value)) => (key, Some(char_string(value))),
+            None => (token.text.as_str(), None),
+        };
+        let bad = || format!("bad SvcParam {}", token.text);
+        let key = svc_key(key)?;
+        if params.get(key).is_some() {
+            return Err(format!("SvcParam {} given twice", token.text));
+        }
+        let param = match (key, value) {
+            (SVC_NO_DEFAULT_ALPN, None) => SvcParam::NoDefaultAlpn,
+            (SVC_NO_DEFAULT_ALPN, Some(_)) => return Err(bad()),
+            (SVC_MANDATORY | SVC_ALPN | SVC_PORT | SVC_IPV4HINT | SVC_IPV6HINT | SVC_ECH, None) => {
+                return Err(bad())
+            }
+            (SVC_MANDATORY, Some(value)) => SvcParam::Mandatory(
+                value_list(&value)
+                    .iter()
+                    .map(|key| svc_key(key))
+                    .collect::<Result<_, _>>()?,
+            ),
+            (SVC_ALPN, Some(value)) => SvcParam::Alpn(value_list(&value)),
+            (SVC_PORT, Some(value)) => {
+                let port = String::from_utf8_lossy(&value).parse().map_err(|_| bad())?;
+                SvcParam::Port(port)
+            }
+            (SVC_IPV4HINT, Some(value)) => SvcParam::Ipv4Hint(
+                value_list(&value)
+                    .iter()
+                    .map(|addr| addr.parse::<Ipv4Addr>())
+                    .collect::<Result<_, _>>()
+                    .map_err(|_| bad())?,
+            ),
+            (SVC_IPV6HINT, Some(value)) => SvcParam::Ipv6Hint(
+                value_list(&value)
+                    .iter()
+                    .map(|addr| addr.parse::<Ipv6Addr>())
+                    .collect::<Result<_, _>>()
+                    .map_err(|_| bad())?,
+            ),
+            (SVC_ECH, Some(value)) => {
+                let config = from_base64(&String::from_utf8_lossy(&value)).ok_or_else(bad)?;
+                SvcParam::Ech(config)
+            }
+            (key, value) => SvcParam::Unknown(key, value.unwrap_or_default()),
+        };
+        params.insert(param);
+    }
+    Ok(params)
+}
+
+fn svc_key(name: &str) -> Result<u16, String> {
+    let key = match name {
+        "mandatory" => SVC_MANDATORY,
+        "alpn" => SVC_ALPN,
+        "no-default-alpn" => SVC_NO_DEFAULT_ALPN,
+        "port" => SVC_PORT,
+        "ipv4hint" => SVC_IPV4HINT,
+        "ech" => SVC_ECH,
+        "ipv6hint" => SVC_IPV6HINT,
+        _ => name
+            .strip_prefix("key")
+            .and_then(|number| number.parse().ok())
+            .ok_or_else(|| format!("unknown SvcParam key {}", name))?,
+    };
+    Ok(key)
+}
+
+// "h2,h3" and the like, where "\," is a comma inside an item
+fn value_list(value: &[u8]) -> Vec<String> {
+    let mut items = Vec::new();
+    let mut item = Vec::new();
+    let mut bytes = value.iter();
+    while let Some(&byte) = bytes.next() {
+        match byte {
+            b'\\' => item.extend(bytes.next()),
+            b',' => items.push(String::from_utf8_lossy(&mem::take(&mut item)).into_owned()),
+            _ => item.push(byte),
+        }
+    }
+    items.push(String::from_utf8_lossy(&item).into_owned());
+    items
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rdata::{TYPE_A, TYPE_MX, TYPE_SOA, TYPE_TXT};
+
+    const ZONE: &str = r#"
+$ORIGIN example.com.
+$TTL 1h
+; the apex
+@   IN  SOA ns1 hostmaster (
+        2024010101 ; serial
+        2h         ; refresh
+        30m        ; retry
+        1w         ; expire
+        300 )      ; minimum
+    IN  NS  ns1
+    IN  NS  ns2.example.net.
+    IN  MX  10 mail
+    IN  TXT "v=spf1 -all" "a \"quoted\" ; not a comment" \065\066
+ns1 300 A   192.0.2.1
+    IN 600 AAAA 2001:db8::1
+mail    A   192.0.2.2
+www CNAME @
+$ORIGIN sub
+host    A   192.0.2.3
+weird   TYPE731 \# 3 ABCDEF
+"#;
+
+    fn record(zone: &str) -> RData {
+        parse_zone(zone, Name::root()).unwrap().remove(0).data
+    }
+
+    #[test]
+    fn test_zone_file() {
+        let records = parse_zone(ZONE, "ignored.example").unwrap();
+        assert_eq!(records.len(), 11);
+
+        let soa = &records[0];
+        assert_eq!(soa.name, Name::from("example.com"));
+        assert_eq!(soa.ttl, 3600);
+        assert_eq!(
+            soa.data,
+            RData::SOA(Soa {
+                mname: "ns1.example.com".to_string(),
+                rname: "hostmaster.example.com".to_string(),
+                serial: 2024010101,
+                refresh: 7200,
+                retry: 1800,
+                expire: 604800,
+                minimum: 300,
+            })
+        );
+        // blank owners are the apex's
+        assert!(records[1..5].iter().all(|rr| rr.name == "example.com"));
+        assert_eq!(records[2].data, RData::NS("ns2.example.net".to_string()));
+        assert_eq!(records[3].rr_type, TYPE_MX);
+        assert_eq!(
+            records[4].data,
+            RData::TXT(vec![
+                b"v=spf1 -all".to_vec(),
+                b"a \"quoted\" ; not a comment".to_vec(),
+                b"AB".to_vec(),
+            ])
+        );
+
+        assert_eq!(records[5].name, Name::from("ns1.example.com"));
+        assert_eq!(records[5].ttl, 300);
+        // TTL and class either way round
+        assert_eq!(records[6].name, Name::from("ns1.example.com"));
+        assert_eq!(records[6].ttl, 600);
+        assert_eq!(records[7].ttl, 3600);
+        assert_eq!(records[8].data, RData::CNAME("example.com".to_string()));
+
+        // a relative $ORIGIN is relative to the one before
+        assert_eq!(records[9].name, Name::from("host.sub.example.com"));
+        assert_eq!(records[10].rr_type, 731);
+        assert_eq!(
+            records[10].data,
+            RData::Unknown(731, vec![0xab, 0xcd, 0xef])
+        );
+    }
+
+    #[test]
+    fn test_presentation_round_trip() {
+        // whatever Display writes reads back as the same rdata
+        let zone = "\
+example.com. 3600 IN DNSKEY 257 3 13 ( mdsswUyr3DPW132mOi8V9xESWE8jTo0d
+                                       xCjjnopKl+GqJxpVXckHAeF+KkxLbxIL
+                                       fDLUT0rAK9iUzy1L53eKGQ== )
+example.com. 3600 IN RRSIG A 13 2 3600 20240301000000 20240201000000 2371 example.com. AAECAw==
+example.com. 3600 IN DS 2371 13 2 1F987CC6583E92DF0890718C42 91E9D7E4BB8C73B5B3C8B19E31ED9C23B0D5E0
+example.com. 3600 IN NSEC3 1 0 0 - 2T7B4G4VSA5SMI47K61MV5BV1A22BOJR A RRSIG
+example.com. 3600 IN NSEC host.example.com. A NS SOA RRSIG NSEC DNSKEY
+_443._tcp.example.com. 3600 IN TLSA 3 1 1 0C72AC70B745AC19998811B131D662C9AC69DBDBE7CB23E5B514B56664C5D3D6
+example.com. 3600 IN CAA 128 issue \"ca.example.net; policy=ev\"
+example.com. 3600 IN HTTPS 1 . alpn=h3,\"h2\" ipv4hint=192.0.2.1,192.0.2.2 key667=hello
+example.com. 3600 IN SRV 0 5 5060 sip.example.com.
+example.com. 3600 IN TXT \"\\255\\000\" \"caf\\195\\169\"
+";
+        let records = parse_zone(zone, Name::root()).unwrap();
+        assert_eq!(records.len(), 10);
+        let RData::RRSIG(sig) = &records[1].data else {
+            panic!("{:?}", records[1].data);
+        };
+        assert_eq!(sig.expiration, 1709251200);
+        assert_eq!(sig.inception, 1706745600);
+        let RData::HTTPS(https) = &records[7].data else {
+            panic!("{:?}", records[7].data);
+        };
+        assert_eq!(https.target, "");
+        assert_eq!(
+            https.params.alpn(),
+            Some(&["h3".to_string(), "h2".to_string()][..])
+        );
+        assert_eq!(
+            https.params.get(667),
+            Some(&SvcParam::Unknown(667, b"hello".to_vec()))
+        );
+        // escaped bytes go on the wire as they are, UTF-8 or not
+        let mut rdata = Vec::new();
+        records[9].data.encode(&mut rdata).unwrap();
+        assert_eq!(rdata, b"\x02\xff\x00\x05caf\xc3\xa9");
+
+        for rr in records {
+            let line = format!(
+                "{}. {} IN {} {}",
+                rr.name,
+                rr.ttl,
+                RecordType::from(rr.rr_type),
+                rr.data
+            );
+            assert_eq!(record(&line), rr.data, "{}", line);
+        }
+    }
+
+    #[test]
+    fn test_units_and_times() {
+        assert_eq!(parse_ttl("3600"), Ok(3600));
+        assert_eq!(parse_ttl("1h30m"), Ok(5400));
+        assert_eq!(parse_ttl("1W2D"), Ok(777600));
+        assert_eq!(parse_ttl("1m30"), Ok(90));
+        assert!(parse_ttl("1x").is_err());
+        assert!(parse_ttl("h").is_err());
+        assert!(parse_ttl("99999999999").is_err());
+        assert_eq!(sig_time("19700101000000"), Ok(0));
+        assert_eq!(sig_time("1234567"), Ok(1234567));
+        assert!(sig_time("20241301000000").is_err());
+    }
+
+    #[test]
+    fn test_errors() {
+        let error = |zone: &str| parse_zone(zone, "example.com").unwrap_err();
+        assert_eq!(error("a 300 A 192.0.2.1\nb 300 FOO x\n").line, 2);
+        assert_eq!(
+            error("a A 192.0.2.1\n").message,
+            "no TTL, and no $TTL to fall back on"
+        );
+        assert_eq!(error("\n\na 300 SOA ( x y\n 1 2 3 4\n").line, 3);
+        assert_eq!(error("a 300 TXT \"open\n").line, 1);
+        assert_eq!(
+            error("a 300 A 192.0.2.1 192.0.2.2").message,
+            "too much rdata for A"
+        );
+        assert_eq!(
+            error("  300 A 192.0.2.1").message,
+            "no previous owner to share"
+        );
+        assert!(error("a..b 300 A 192.0.2.1")
+            .message
+            .starts_with("bad name"));
+        assert!(error("$GENERATE 1-10 host$ A 192.0.2.$")
+            .message
+            .starts_with("unknown directive"));
+        assert!(error("a 300 TSIG hmac-sha256. 0 300 0 0 0 0")
+            .message
+            .starts_with("no presentation format"));
+    }
+
+    #[test]
+    fn test_include() {
+        let dir = std::env::temp_dir().join(format!("zone-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(
+            dir.join("example.com.zone"),
+            "$TTL 300\n@ SOA ns1 admin 1 2 3 4 5\n$INCLUDE hosts.inc lan\nafter A 192.0.2.9\n",
+        )
+        .unwrap();
+        fs::write(
+            dir.join("hosts.inc"),
+            "printer A 192.168.1.5\n\tTXT \"x\"\n",
+        )
+        .unwrap();
+        fs::write(dir.join("loop.zone"), "$INCLUDE loop.zone\n").unwrap();
+
+        let records = load_zone(dir.join("example.com.zone"), "example.com").unwrap();
+        let names: Vec<_> = records
+            .iter()
+            .map(|rr| (rr.name.to_string(), rr.rr_type))
+            .collect();
+        assert_eq!(
+            names,
+            [
+                ("example.com".to_string(), TYPE_SOA),
+                ("printer.lan.example.com".to_string(), TYPE_A),
+                ("printer.lan.example.com".to_string(), TYPE_TXT),
+                // the origin is back to what it was
+                ("after.example.com".to_string(), TYPE_A),
+            ]
+        );
+
+        let error = load_zone(dir.join("loop.zone"), "example.com").unwrap_err();
+        assert_eq!(error.message, "$INCLUDEs nested too deep");
+        assert!(load_zone(dir.join("missing.zone"), "example.com").is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}